@@ -0,0 +1,13 @@
+//! Embeds build metadata (git SHA, build timestamp, enabled cargo features) into the binary
+
+use vergen::EmitBuilder;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+  EmitBuilder::builder()
+    .build_timestamp()
+    .cargo_features()
+    .git_sha(false)
+    .emit()?;
+
+  Ok(())
+}
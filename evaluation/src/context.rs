@@ -0,0 +1,46 @@
+//! Data model for the context a Feature Flag is evaluated with
+
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Everything known about the subject a flag is being evaluated for
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct EvaluationContext {
+  /// Unique ID of the user the flag is evaluated for, if any
+  pub user: Option<String>,
+  /// Anonymous key of the subject (e.g. a device or session ID) used to bucket percentage releases when there's no
+  /// user. Unlike users, keys don't need to exist anywhere
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub key: Option<String>,
+  /// Account type of the user (e.g. `Developer` or `Client`), filled in by the service from the user's account
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub account_type: Option<String>,
+  /// Arbitrary attributes of the subject (e.g. `app_version`) used by targeting rules
+  #[serde(default)]
+  pub attributes: HashMap<String, String>,
+}
+
+impl EvaluationContext {
+  /// Creates a new `EvaluationContext` from a user and attributes
+  pub fn new(user: Option<&str>, attributes: HashMap<String, String>) -> EvaluationContext {
+    EvaluationContext {
+      user: user.map(|x| x.to_string()),
+      key: None,
+      account_type: None,
+      attributes,
+    }
+  }
+
+  /// Sets the anonymous key of the context
+  pub fn with_key(mut self, key: Option<&str>) -> EvaluationContext {
+    self.key = key.map(|x| x.to_string());
+    self
+  }
+
+  /// Returns the value of an attribute, if present
+  pub fn attribute(&self, name: &str) -> Option<&str> {
+    self.attributes.get(name).map(|x| x.as_str())
+  }
+}
@@ -0,0 +1,986 @@
+//! Sandboxed expression language for advanced targeting rules
+//!
+//! Expressions combine attributes of the evaluation context, e.g.
+//! `user.plan == "enterprise" && user.created_at < now() - 30d`
+//!
+//! * `user.id` is the user ID of the context, any other `user.<name>` (or a bare `<name>`) is the `<name>` attribute
+//! * Literals are strings (`"a"` or `'a'`), numbers, durations (`30s`, `15m`, `12h`, `30d` or `2w`), `true`, `false`,
+//!   `null` and lists (`["a", "b"]`)
+//! * Operators, loosest first: `||`, `&&`, `==` `!=` `<` `<=` `>` `>=` `in`, `+` `-`, and unary `!` `-`
+//! * Functions: `now()`, `lower(s)`, `contains(s, part)`, `starts_with(s, prefix)` and `ends_with(s, suffix)`
+//!
+//! Attributes are strings, they are read as numbers or times (RFC 3339 or unix seconds) when compared with one. Missing
+//! attributes are `null`. Times are unix seconds and durations are seconds, so `now() - 30d` is 30 days ago
+//!
+//! Expressions are size limited when parsed and step limited when evaluated. An expression that fails to evaluate (e.g.
+//! compares a list with a number) doesn't match
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::context::EvaluationContext;
+
+/// Longest accepted expression, in bytes
+const MAX_LENGTH: usize = 2048;
+/// Most nodes an expression can parse into
+const MAX_NODES: usize = 256;
+/// Deepest nesting of an expression
+const MAX_DEPTH: usize = 32;
+/// Most steps an evaluation can take
+const MAX_STEPS: usize = 4096;
+/// Compiled expressions kept in the cache before it's cleared
+const CACHE_CAPACITY: usize = 1024;
+
+/// Error parsing an expression
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpressionError {
+  /// Description of the problem
+  pub message: String,
+  /// Byte offset in the expression the problem was found at
+  pub position: usize,
+}
+
+impl fmt::Display for ExpressionError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} at position {}", self.message, self.position)
+  }
+}
+
+/// Compiled expression
+#[derive(Debug)]
+pub struct Expression {
+  root: Node,
+}
+
+impl Expression {
+  /// Parses an expression
+  pub fn parse(source: &str) -> Result<Expression, ExpressionError> {
+    if source.len() > MAX_LENGTH {
+      return Err(ExpressionError {
+        message: format!("Expression is longer than {} bytes", MAX_LENGTH),
+        position: MAX_LENGTH,
+      });
+    }
+
+    let mut parser = Parser {
+      tokens: tokenize(source)?,
+      index: 0,
+      nodes: 0,
+      depth: 0,
+      end: source.len(),
+    };
+
+    let root = parser.expression()?;
+    match parser.peek() {
+      Some((token, position)) => Err(ExpressionError {
+        message: format!("Unexpected {}", token.describe()),
+        position,
+      }),
+      None => Ok(Expression { root }),
+    }
+  }
+
+  /// Returns `true` if the expression evaluates to `true` with the context
+  pub fn matches(&self, context: &EvaluationContext) -> bool {
+    let mut evaluator = Evaluator { context, steps: 0 };
+    matches!(evaluator.evaluate(&self.root), Ok(Value::Bool(true)))
+  }
+}
+
+/// Compiles an expression, reusing the compiled expression if the same source was compiled before
+pub fn compile(source: &str) -> Result<Arc<Expression>, ExpressionError> {
+  static CACHE: OnceLock<Mutex<HashMap<String, Arc<Expression>>>> = OnceLock::new();
+  let cache = CACHE.get_or_init(Default::default);
+
+  if let Some(expression) = lock(cache).get(source) {
+    return Ok(expression.clone());
+  }
+
+  let expression = Arc::new(Expression::parse(source)?);
+
+  let mut cache = lock(cache);
+  if cache.len() >= CACHE_CAPACITY {
+    cache.clear();
+  }
+  cache.insert(source.to_string(), expression.clone());
+
+  Ok(expression)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+  match mutex.lock() {
+    Ok(value) => value,
+    Err(poisoned) => poisoned.into_inner(),
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+  Identifier(String),
+  String(String),
+  Number(f64),
+  True,
+  False,
+  Null,
+  In,
+  LeftParen,
+  RightParen,
+  LeftBracket,
+  RightBracket,
+  Comma,
+  Not,
+  And,
+  Or,
+  Equal,
+  NotEqual,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  Plus,
+  Minus,
+}
+
+impl Token {
+  fn describe(&self) -> String {
+    match self {
+      Token::Identifier(name) => format!("identifier '{}'", name),
+      Token::String(value) => format!("string \"{}\"", value),
+      Token::Number(value) => format!("number {}", value),
+      token => format!("'{}'", token.symbol()),
+    }
+  }
+
+  fn symbol(&self) -> &'static str {
+    match self {
+      Token::True => "true",
+      Token::False => "false",
+      Token::Null => "null",
+      Token::In => "in",
+      Token::LeftParen => "(",
+      Token::RightParen => ")",
+      Token::LeftBracket => "[",
+      Token::RightBracket => "]",
+      Token::Comma => ",",
+      Token::Not => "!",
+      Token::And => "&&",
+      Token::Or => "||",
+      Token::Equal => "==",
+      Token::NotEqual => "!=",
+      Token::Less => "<",
+      Token::LessEqual => "<=",
+      Token::Greater => ">",
+      Token::GreaterEqual => ">=",
+      Token::Plus => "+",
+      Token::Minus => "-",
+      Token::Identifier(_) | Token::String(_) | Token::Number(_) => "",
+    }
+  }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ExpressionError> {
+  let chars: Vec<(usize, char)> = source.char_indices().collect();
+  let mut tokens = vec![];
+  let mut i = 0;
+
+  let error = |message: String, position: usize| Err(ExpressionError { message, position });
+
+  while i < chars.len() {
+    let (position, c) = chars[i];
+    let next = chars.get(i + 1).map(|x| x.1);
+
+    let (token, length) = match (c, next) {
+      (c, _) if c.is_whitespace() => {
+        i += 1;
+        continue;
+      }
+      ('(', _) => (Token::LeftParen, 1),
+      (')', _) => (Token::RightParen, 1),
+      ('[', _) => (Token::LeftBracket, 1),
+      (']', _) => (Token::RightBracket, 1),
+      (',', _) => (Token::Comma, 1),
+      ('+', _) => (Token::Plus, 1),
+      ('-', _) => (Token::Minus, 1),
+      ('&', Some('&')) => (Token::And, 2),
+      ('|', Some('|')) => (Token::Or, 2),
+      ('=', Some('=')) => (Token::Equal, 2),
+      ('!', Some('=')) => (Token::NotEqual, 2),
+      ('!', _) => (Token::Not, 1),
+      ('<', Some('=')) => (Token::LessEqual, 2),
+      ('<', _) => (Token::Less, 1),
+      ('>', Some('=')) => (Token::GreaterEqual, 2),
+      ('>', _) => (Token::Greater, 1),
+      ('"', _) | ('\'', _) => {
+        let mut value = String::new();
+        let mut j = i + 1;
+        loop {
+          match chars.get(j).map(|x| x.1) {
+            Some('\\') => match chars.get(j + 1).map(|x| x.1) {
+              Some(escaped) => {
+                value.push(escaped);
+                j += 2;
+              }
+              None => return error("Unterminated string".to_string(), position),
+            },
+            Some(quote) if quote == c => break,
+            Some(other) => {
+              value.push(other);
+              j += 1;
+            }
+            None => return error("Unterminated string".to_string(), position),
+          }
+        }
+        (Token::String(value), j + 1 - i)
+      }
+      (c, _) if c.is_ascii_digit() => {
+        let mut j = i;
+        while chars.get(j).is_some_and(|x| x.1.is_ascii_digit() || x.1 == '.') {
+          j += 1;
+        }
+        let text: String = chars[i..j].iter().map(|x| x.1).collect();
+        let mut value: f64 = match text.parse() {
+          Ok(value) => value,
+          Err(_) => return error(format!("Invalid number '{}'", text), position),
+        };
+
+        // A unit directly after a number makes it a duration in seconds
+        let unit = chars.get(j).map(|x| x.1);
+        let after_unit = chars.get(j + 1).map(|x| x.1);
+        if !after_unit.is_some_and(|x| x.is_alphanumeric() || x == '_') {
+          let scale = match unit {
+            Some('s') => Some(1.0),
+            Some('m') => Some(60.0),
+            Some('h') => Some(3600.0),
+            Some('d') => Some(86400.0),
+            Some('w') => Some(604800.0),
+            _ => None,
+          };
+          if let Some(scale) = scale {
+            value *= scale;
+            j += 1;
+          }
+        }
+
+        if chars.get(j).is_some_and(|x| x.1.is_alphanumeric() || x.1 == '_') {
+          return error("Invalid number or duration".to_string(), position);
+        }
+        (Token::Number(value), j - i)
+      }
+      (c, _) if c.is_alphabetic() || c == '_' => {
+        let mut j = i;
+        while chars
+          .get(j)
+          .is_some_and(|x| x.1.is_alphanumeric() || x.1 == '_' || x.1 == '.')
+        {
+          j += 1;
+        }
+        let name: String = chars[i..j].iter().map(|x| x.1).collect();
+        let token = match name.as_str() {
+          "true" => Token::True,
+          "false" => Token::False,
+          "null" => Token::Null,
+          "in" => Token::In,
+          _ if name.ends_with('.') || name.contains("..") => {
+            return error(format!("Invalid identifier '{}'", name), position)
+          }
+          _ => Token::Identifier(name),
+        };
+        (token, j - i)
+      }
+      (c, _) => return error(format!("Unexpected character '{}'", c), position),
+    };
+
+    tokens.push((token, position));
+    i += length;
+  }
+
+  Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Function {
+  Now,
+  Lower,
+  Contains,
+  StartsWith,
+  EndsWith,
+}
+
+impl Function {
+  fn from_name(name: &str) -> Option<Function> {
+    match name {
+      "now" => Some(Function::Now),
+      "lower" => Some(Function::Lower),
+      "contains" => Some(Function::Contains),
+      "starts_with" => Some(Function::StartsWith),
+      "ends_with" => Some(Function::EndsWith),
+      _ => None,
+    }
+  }
+
+  fn arity(&self) -> usize {
+    match self {
+      Function::Now => 0,
+      Function::Lower => 1,
+      Function::Contains | Function::StartsWith | Function::EndsWith => 2,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Operator {
+  Equal,
+  NotEqual,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  In,
+  Plus,
+  Minus,
+}
+
+#[derive(Debug)]
+enum Node {
+  Literal(Value),
+  List(Vec<Node>),
+  UserId,
+  Attribute(String),
+  Call(Function, Vec<Node>),
+  Not(Box<Node>),
+  Negate(Box<Node>),
+  And(Box<Node>, Box<Node>),
+  Or(Box<Node>, Box<Node>),
+  Binary(Operator, Box<Node>, Box<Node>),
+}
+
+struct Parser {
+  tokens: Vec<(Token, usize)>,
+  index: usize,
+  nodes: usize,
+  depth: usize,
+  /// Position reported for errors at the end of the expression
+  end: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<(&Token, usize)> {
+    self.tokens.get(self.index).map(|(token, position)| (token, *position))
+  }
+
+  fn position(&self) -> usize {
+    self.peek().map_or(self.end, |x| x.1)
+  }
+
+  fn advance_if(&mut self, expected: &Token) -> bool {
+    match self.peek() {
+      Some((token, _)) if token == expected => {
+        self.index += 1;
+        true
+      }
+      _ => false,
+    }
+  }
+
+  fn expect(&mut self, expected: &Token) -> Result<(), ExpressionError> {
+    if self.advance_if(expected) {
+      return Ok(());
+    }
+
+    let found = match self.peek() {
+      Some((token, _)) => token.describe(),
+      None => "end of expression".to_string(),
+    };
+    Err(self.error(format!("Expected '{}' but found {}", expected.symbol(), found)))
+  }
+
+  fn error(&self, message: String) -> ExpressionError {
+    ExpressionError {
+      message,
+      position: self.position(),
+    }
+  }
+
+  fn node(&mut self, node: Node) -> Result<Node, ExpressionError> {
+    self.nodes += 1;
+    if self.nodes > MAX_NODES {
+      return Err(self.error(format!("Expression has more than {} terms", MAX_NODES)));
+    }
+    Ok(node)
+  }
+
+  fn nested<T>(&mut self, parse: impl FnOnce(&mut Parser) -> Result<T, ExpressionError>) -> Result<T, ExpressionError> {
+    self.depth += 1;
+    if self.depth > MAX_DEPTH {
+      return Err(self.error(format!("Expression is nested deeper than {} levels", MAX_DEPTH)));
+    }
+    let result = parse(self);
+    self.depth -= 1;
+    result
+  }
+
+  fn expression(&mut self) -> Result<Node, ExpressionError> {
+    self.nested(|parser| parser.or())
+  }
+
+  fn or(&mut self) -> Result<Node, ExpressionError> {
+    let mut left = self.and()?;
+    while self.advance_if(&Token::Or) {
+      let right = self.and()?;
+      left = self.node(Node::Or(Box::new(left), Box::new(right)))?;
+    }
+    Ok(left)
+  }
+
+  fn and(&mut self) -> Result<Node, ExpressionError> {
+    let mut left = self.comparison()?;
+    while self.advance_if(&Token::And) {
+      let right = self.comparison()?;
+      left = self.node(Node::And(Box::new(left), Box::new(right)))?;
+    }
+    Ok(left)
+  }
+
+  fn comparison(&mut self) -> Result<Node, ExpressionError> {
+    let left = self.sum()?;
+
+    let operator = match self.peek().map(|x| x.0) {
+      Some(Token::Equal) => Operator::Equal,
+      Some(Token::NotEqual) => Operator::NotEqual,
+      Some(Token::Less) => Operator::Less,
+      Some(Token::LessEqual) => Operator::LessEqual,
+      Some(Token::Greater) => Operator::Greater,
+      Some(Token::GreaterEqual) => Operator::GreaterEqual,
+      Some(Token::In) => Operator::In,
+      _ => return Ok(left),
+    };
+    self.index += 1;
+
+    let right = self.sum()?;
+    self.node(Node::Binary(operator, Box::new(left), Box::new(right)))
+  }
+
+  fn sum(&mut self) -> Result<Node, ExpressionError> {
+    let mut left = self.unary()?;
+    loop {
+      let operator = match self.peek().map(|x| x.0) {
+        Some(Token::Plus) => Operator::Plus,
+        Some(Token::Minus) => Operator::Minus,
+        _ => return Ok(left),
+      };
+      self.index += 1;
+
+      let right = self.unary()?;
+      left = self.node(Node::Binary(operator, Box::new(left), Box::new(right)))?;
+    }
+  }
+
+  fn unary(&mut self) -> Result<Node, ExpressionError> {
+    if self.advance_if(&Token::Not) {
+      let operand = self.nested(|parser| parser.unary())?;
+      return self.node(Node::Not(Box::new(operand)));
+    }
+    if self.advance_if(&Token::Minus) {
+      let operand = self.nested(|parser| parser.unary())?;
+      return self.node(Node::Negate(Box::new(operand)));
+    }
+    self.primary()
+  }
+
+  fn primary(&mut self) -> Result<Node, ExpressionError> {
+    let token = match self.peek() {
+      Some((token, _)) => token.clone(),
+      None => return Err(self.error("Unexpected end of expression".to_string())),
+    };
+    let position = self.position();
+    self.index += 1;
+
+    let node = match token {
+      Token::String(value) => Node::Literal(Value::String(value)),
+      Token::Number(value) => Node::Literal(Value::Number(value)),
+      Token::True => Node::Literal(Value::Bool(true)),
+      Token::False => Node::Literal(Value::Bool(false)),
+      Token::Null => Node::Literal(Value::Null),
+      Token::LeftParen => {
+        let inner = self.expression()?;
+        self.expect(&Token::RightParen)?;
+        return Ok(inner);
+      }
+      Token::LeftBracket => {
+        let items = self.nested(|parser| parser.arguments(&Token::RightBracket))?;
+        Node::List(items)
+      }
+      Token::Identifier(name) if self.advance_if(&Token::LeftParen) => {
+        let function = match Function::from_name(&name) {
+          Some(function) => function,
+          None => {
+            return Err(ExpressionError {
+              message: format!("Unknown function '{}'", name),
+              position,
+            })
+          }
+        };
+        let arguments = self.nested(|parser| parser.arguments(&Token::RightParen))?;
+        if arguments.len() != function.arity() {
+          return Err(ExpressionError {
+            message: format!(
+              "'{}' takes {} argument(s) but got {}",
+              name,
+              function.arity(),
+              arguments.len()
+            ),
+            position,
+          });
+        }
+        Node::Call(function, arguments)
+      }
+      Token::Identifier(name) => match name.as_str() {
+        "user.id" => Node::UserId,
+        _ => Node::Attribute(name.strip_prefix("user.").unwrap_or(&name).to_string()),
+      },
+      token => {
+        return Err(ExpressionError {
+          message: format!("Unexpected {}", token.describe()),
+          position,
+        })
+      }
+    };
+
+    self.node(node)
+  }
+
+  /// Parses a comma separated list of expressions up to and including the `close` token
+  fn arguments(&mut self, close: &Token) -> Result<Vec<Node>, ExpressionError> {
+    let mut arguments = vec![];
+    if self.advance_if(close) {
+      return Ok(arguments);
+    }
+
+    loop {
+      arguments.push(self.expression()?);
+      if self.advance_if(close) {
+        return Ok(arguments);
+      }
+      self.expect(&Token::Comma)?;
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  /// Unix time in seconds
+  Time(f64),
+  List(Vec<Value>),
+}
+
+impl Value {
+  /// Reads the value as a number of seconds, strings are read as numbers or RFC 3339 times
+  fn seconds(&self) -> Option<f64> {
+    match self {
+      Value::Number(value) | Value::Time(value) => Some(*value),
+      Value::String(value) => value.trim().parse().ok().or_else(|| parse_time(value)),
+      _ => None,
+    }
+  }
+}
+
+/// Error evaluating an expression, the expression doesn't match when one occurs
+struct EvaluationError;
+
+struct Evaluator<'a> {
+  context: &'a EvaluationContext,
+  steps: usize,
+}
+
+impl Evaluator<'_> {
+  fn evaluate(&mut self, node: &Node) -> Result<Value, EvaluationError> {
+    self.steps += 1;
+    if self.steps > MAX_STEPS {
+      return Err(EvaluationError);
+    }
+
+    match node {
+      Node::Literal(value) => Ok(value.clone()),
+      Node::List(items) => Ok(Value::List(
+        items.iter().map(|x| self.evaluate(x)).collect::<Result<_, _>>()?,
+      )),
+      Node::UserId => Ok(match &self.context.user {
+        Some(user) => Value::String(user.clone()),
+        None => Value::Null,
+      }),
+      Node::Attribute(name) => Ok(match self.context.attribute(name) {
+        Some(value) => Value::String(value.to_string()),
+        None => Value::Null,
+      }),
+      Node::Call(function, arguments) => {
+        let arguments = arguments
+          .iter()
+          .map(|x| self.evaluate(x))
+          .collect::<Result<Vec<_>, _>>()?;
+        call(*function, &arguments)
+      }
+      Node::Not(operand) => match self.evaluate(operand)? {
+        Value::Bool(value) => Ok(Value::Bool(!value)),
+        _ => Err(EvaluationError),
+      },
+      Node::Negate(operand) => match self.evaluate(operand)? {
+        Value::Number(value) => Ok(Value::Number(-value)),
+        value => value.seconds().map(|x| Value::Number(-x)).ok_or(EvaluationError),
+      },
+      Node::And(left, right) => match self.evaluate(left)? {
+        Value::Bool(false) => Ok(Value::Bool(false)),
+        Value::Bool(true) => match self.evaluate(right)? {
+          Value::Bool(value) => Ok(Value::Bool(value)),
+          _ => Err(EvaluationError),
+        },
+        _ => Err(EvaluationError),
+      },
+      Node::Or(left, right) => match self.evaluate(left)? {
+        Value::Bool(true) => Ok(Value::Bool(true)),
+        Value::Bool(false) => match self.evaluate(right)? {
+          Value::Bool(value) => Ok(Value::Bool(value)),
+          _ => Err(EvaluationError),
+        },
+        _ => Err(EvaluationError),
+      },
+      Node::Binary(operator, left, right) => {
+        let left = self.evaluate(left)?;
+        let right = self.evaluate(right)?;
+        binary(*operator, &left, &right)
+      }
+    }
+  }
+}
+
+fn call(function: Function, arguments: &[Value]) -> Result<Value, EvaluationError> {
+  let string = |index: usize| match arguments.get(index) {
+    Some(Value::String(value)) => Ok(value.as_str()),
+    _ => Err(EvaluationError),
+  };
+
+  match function {
+    Function::Now => Ok(Value::Time(now())),
+    Function::Lower => Ok(Value::String(string(0)?.to_lowercase())),
+    Function::Contains => Ok(Value::Bool(string(0)?.contains(string(1)?))),
+    Function::StartsWith => Ok(Value::Bool(string(0)?.starts_with(string(1)?))),
+    Function::EndsWith => Ok(Value::Bool(string(0)?.ends_with(string(1)?))),
+  }
+}
+
+fn binary(operator: Operator, left: &Value, right: &Value) -> Result<Value, EvaluationError> {
+  let ordering = |left: &Value, right: &Value| compare(left, right);
+
+  match operator {
+    Operator::Equal => Ok(Value::Bool(equals(left, right))),
+    Operator::NotEqual => Ok(Value::Bool(!equals(left, right))),
+    Operator::Less => Ok(Value::Bool(ordering(left, right) == Some(Ordering::Less))),
+    Operator::LessEqual => Ok(Value::Bool(matches!(
+      ordering(left, right),
+      Some(Ordering::Less | Ordering::Equal)
+    ))),
+    Operator::Greater => Ok(Value::Bool(ordering(left, right) == Some(Ordering::Greater))),
+    Operator::GreaterEqual => Ok(Value::Bool(matches!(
+      ordering(left, right),
+      Some(Ordering::Greater | Ordering::Equal)
+    ))),
+    Operator::In => match right {
+      Value::List(items) => Ok(Value::Bool(items.iter().any(|x| equals(left, x)))),
+      _ => Err(EvaluationError),
+    },
+    Operator::Plus | Operator::Minus => {
+      let sign = match operator {
+        Operator::Plus => 1.0,
+        _ => -1.0,
+      };
+
+      match (left, right) {
+        (Value::Time(_), Value::Time(_)) if sign < 0.0 => Ok(Value::Number(seconds(left)? - seconds(right)?)),
+        (Value::Time(_), _) | (Value::String(_), _) if is_time(left) => {
+          Ok(Value::Time(seconds(left)? + sign * seconds(right)?))
+        }
+        (_, Value::Time(_)) if sign > 0.0 => Ok(Value::Time(seconds(left)? + seconds(right)?)),
+        _ => Ok(Value::Number(seconds(left)? + sign * seconds(right)?)),
+      }
+    }
+  }
+}
+
+fn seconds(value: &Value) -> Result<f64, EvaluationError> {
+  value.seconds().ok_or(EvaluationError)
+}
+
+/// Returns true if the value is a time, or a string that is a time and not a plain number
+fn is_time(value: &Value) -> bool {
+  match value {
+    Value::Time(_) => true,
+    Value::String(value) => value.trim().parse::<f64>().is_err() && parse_time(value).is_some(),
+    _ => false,
+  }
+}
+
+fn equals(left: &Value, right: &Value) -> bool {
+  match (left, right) {
+    (Value::Null, Value::Null) => true,
+    (Value::Null, _) | (_, Value::Null) => false,
+    (Value::List(left), Value::List(right)) => {
+      left.len() == right.len() && left.iter().zip(right).all(|(left, right)| equals(left, right))
+    }
+    _ => compare(left, right) == Some(Ordering::Equal),
+  }
+}
+
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+  match (left, right) {
+    (Value::Null, _) | (_, Value::Null) | (Value::List(_), _) | (_, Value::List(_)) => None,
+    (Value::Bool(left), Value::Bool(right)) => Some(left.cmp(right)),
+    (Value::Bool(value), Value::String(text)) | (Value::String(text), Value::Bool(value)) => {
+      let parsed: bool = text.trim().parse().ok()?;
+      let ordering = if matches!(left, Value::Bool(_)) {
+        value.cmp(&parsed)
+      } else {
+        parsed.cmp(value)
+      };
+      Some(ordering)
+    }
+    (Value::Bool(_), _) | (_, Value::Bool(_)) => None,
+    (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+    _ => left.seconds()?.partial_cmp(&right.seconds()?),
+  }
+}
+
+/// Current unix time in seconds
+fn now() -> f64 {
+  #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+  {
+    js_sys::Date::now() / 1000.0
+  }
+  #[cfg(all(target_arch = "wasm32", not(feature = "wasm")))]
+  {
+    // No clock without a JavaScript host, comparisons with `now()` never match
+    f64::NAN
+  }
+  #[cfg(not(target_arch = "wasm32"))]
+  {
+    match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+      Ok(duration) => duration.as_secs_f64(),
+      Err(_) => f64::NAN,
+    }
+  }
+}
+
+/// Parses an RFC 3339 time (`2021-10-01T12:00:00Z`, `2021-10-01T12:00:00.5+02:00`) or date (`2021-10-01`) into unix
+/// seconds
+fn parse_time(value: &str) -> Option<f64> {
+  let value = value.trim();
+  let number = |range: std::ops::Range<usize>| -> Option<i64> {
+    let text = value.get(range)?;
+    if !text.bytes().all(|x| x.is_ascii_digit()) {
+      return None;
+    }
+    text.parse().ok()
+  };
+
+  if value.get(4..5)? != "-" || value.get(7..8)? != "-" {
+    return None;
+  }
+  let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+  if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+    return None;
+  }
+  let days = days_from_civil(year, month, day) as f64;
+
+  if value.len() == 10 {
+    return Some(days * 86400.0);
+  }
+
+  if !matches!(value.get(10..11)?, "T" | "t" | " ") || value.get(13..14)? != ":" || value.get(16..17)? != ":" {
+    return None;
+  }
+  let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+  if hour > 23 || minute > 59 || second > 60 {
+    return None;
+  }
+
+  let mut rest = &value[19..];
+  let mut fraction = 0.0;
+  if let Some(digits) = rest.strip_prefix('.') {
+    let length = digits.bytes().take_while(|x| x.is_ascii_digit()).count();
+    if length == 0 {
+      return None;
+    }
+    fraction = format!("0.{}", &digits[..length]).parse().ok()?;
+    rest = &digits[length..];
+  }
+
+  let offset = match rest {
+    "Z" | "z" => 0,
+    _ => {
+      let sign = match rest.get(0..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+      };
+      if rest.len() != 6 || rest.get(3..4)? != ":" {
+        return None;
+      }
+      let hours: i64 = rest.get(1..3)?.parse().ok()?;
+      let minutes: i64 = rest.get(4..6)?.parse().ok()?;
+      sign * (hours * 3600 + minutes * 60)
+    }
+  };
+
+  let seconds = hour * 3600 + minute * 60 + second - offset;
+  Some(days * 86400.0 + seconds as f64 + fraction)
+}
+
+/// Days since the unix epoch of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = year.div_euclid(400);
+  let year_of_era = year - era * 400;
+  let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn context(attributes: &[(&str, &str)]) -> EvaluationContext {
+    let attributes = attributes
+      .iter()
+      .map(|(name, value)| (name.to_string(), value.to_string()))
+      .collect();
+    EvaluationContext::new(Some("u1"), attributes)
+  }
+
+  fn matches(source: &str, attributes: &[(&str, &str)]) -> bool {
+    Expression::parse(source).unwrap().matches(&context(attributes))
+  }
+
+  #[test]
+  fn attributes_are_compared_as_strings_numbers_or_times() {
+    assert!(matches(
+      r#"user.plan == "enterprise" && user.id == 'u1'"#,
+      &[("plan", "enterprise")]
+    ));
+    assert!(matches("seats >= 10 && seats < 100", &[("seats", "25")]));
+    assert!(matches(r#"plan in ["pro", "enterprise"]"#, &[("plan", "pro")]));
+    assert!(matches("missing == null && !(missing == 1)", &[]));
+    assert!(matches(
+      r#"starts_with(lower(email), "admin@")"#,
+      &[("email", "Admin@example.com")]
+    ));
+  }
+
+  #[test]
+  fn durations_and_date_arithmetic_are_in_seconds() {
+    assert!(matches("1d == 86400 && 2w == 14d && 15m + 30s == 930", &[]));
+    assert!(matches(
+      "created_at + 1d == '2021-10-02T00:00:00Z'",
+      &[("created_at", "2021-10-01")]
+    ));
+    assert!(matches(
+      "'2021-10-08' - created_at == 1w",
+      &[("created_at", "2021-10-01T00:00:00Z")]
+    ));
+    assert!(matches(
+      "created_at < now() - 30d",
+      &[("created_at", "2020-01-01T00:00:00Z")]
+    ));
+    assert!(!matches("created_at < now() - 30d", &[("created_at", "2999-01-01")]));
+    // Plain numbers stay numbers, even though they are also unix times
+    assert!(matches("count + 1 == 3", &[("count", "2")]));
+  }
+
+  #[test]
+  fn times_are_parsed_from_rfc_3339_and_dates() {
+    assert_eq!(parse_time("1970-01-01"), Some(0.0));
+    assert_eq!(parse_time("2021-10-01T12:00:00Z"), Some(1_633_089_600.0));
+    assert_eq!(parse_time("2021-10-01T14:00:00+02:00"), Some(1_633_089_600.0));
+    assert_eq!(parse_time("2021-10-01t12:00:00.5z"), Some(1_633_089_600.5));
+    assert_eq!(parse_time("2000-02-29"), Some(951_782_400.0));
+    assert_eq!(parse_time("2021-13-01"), None);
+    assert_eq!(parse_time("2021-10-01T24:00:00Z"), None);
+    assert_eq!(parse_time("2021-10-01T12:00:00"), None);
+    assert_eq!(parse_time("2021-10-01T12:00:00.Z"), None);
+    assert_eq!(parse_time("1633089600"), None);
+  }
+
+  #[test]
+  fn invalid_expressions_are_refused_with_their_position() {
+    let error = |source: &str| Expression::parse(source).unwrap_err();
+
+    assert_eq!(error("a == ").position, 5);
+    assert_eq!(error("a == 'b").position, 5);
+    assert_eq!(error("a # b").position, 2);
+    assert_eq!(error("shout(a)").message, "Unknown function 'shout'");
+    assert_eq!(error("lower(a, b)").message, "'lower' takes 1 argument(s) but got 2");
+    assert_eq!(error("a b").message, "Unexpected identifier 'b'");
+    assert!(error("user.").message.starts_with("Invalid identifier"));
+    assert!(error("3x").message.starts_with("Invalid number"));
+  }
+
+  #[test]
+  fn oversized_expressions_are_refused() {
+    let long = format!("a == '{}'", "x".repeat(MAX_LENGTH));
+    assert!(Expression::parse(&long).unwrap_err().message.contains("longer than"));
+
+    let wide = vec!["a"; MAX_NODES].join("||");
+    assert!(Expression::parse(&wide).unwrap_err().message.contains("more than"));
+
+    let deep = format!("{}a{}", "(".repeat(MAX_DEPTH + 1), ")".repeat(MAX_DEPTH + 1));
+    assert!(Expression::parse(&deep).unwrap_err().message.contains("nested deeper"));
+    let negated = format!("{}true", "!".repeat(MAX_DEPTH + 1));
+    assert!(Expression::parse(&negated)
+      .unwrap_err()
+      .message
+      .contains("nested deeper"));
+
+    let shallow = format!("{}a{}", "(".repeat(MAX_DEPTH - 1), ")".repeat(MAX_DEPTH - 1));
+    assert!(Expression::parse(&shallow).is_ok());
+  }
+
+  #[test]
+  fn evaluations_are_step_limited() {
+    let context = context(&[]);
+    let list = |length| Node::List((0..length).map(|_| Node::Literal(Value::Null)).collect());
+
+    let mut evaluator = Evaluator {
+      context: &context,
+      steps: 0,
+    };
+    assert!(evaluator.evaluate(&list(MAX_STEPS - 1)).is_ok());
+
+    let mut evaluator = Evaluator {
+      context: &context,
+      steps: 0,
+    };
+    assert!(evaluator.evaluate(&list(MAX_STEPS)).is_err());
+  }
+
+  #[test]
+  fn type_errors_do_not_match() {
+    assert!(!matches("!a", &[("a", "true")]));
+    assert!(!matches("1 in 1", &[]));
+    assert!(!matches("[1] < 2", &[]));
+    assert!(!matches("a && true", &[("a", "true")]));
+    assert!(!matches("lower(1) == '1'", &[]));
+  }
+
+  #[test]
+  fn compiled_expressions_are_reused() {
+    let first = compile("plan == 'pro'").unwrap();
+    let second = compile("plan == 'pro'").unwrap();
+
+    assert!(Arc::ptr_eq(&first, &second));
+    assert!(compile("plan ==").is_err());
+  }
+}
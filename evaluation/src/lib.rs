@@ -0,0 +1,192 @@
+//! Core Feature Flag evaluation engine
+//!
+//! Free of any database or web framework so the exact same semantics can be shared between the service and
+//! `wasm32-unknown-unknown` builds running on edge runtimes (enable the `wasm` feature for JavaScript bindings)
+
+pub mod context;
+pub mod expression;
+pub mod release;
+pub mod result;
+pub mod rule;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub use context::EvaluationContext;
+pub use release::{BucketBy, ReleaseType};
+pub use result::{EvaluationResult, Reason};
+pub use rule::{Condition, Rule};
+
+/// Everything about a flag needed to evaluate it
+pub trait Evaluable {
+  /// Salt used when bucketing percentage releases, unique per flag
+  fn bucket_salt(&self) -> &str;
+  /// Global enabled status of the flag (false trumps other statuses)
+  fn enabled(&self) -> bool;
+  /// If the flag is archived, archived flags always evaluate as disabled
+  fn archived(&self) -> bool;
+  /// List of all users who've disabled the feature
+  fn disabled_for(&self) -> &[String];
+  /// Users always evaluating as enabled, overriding targeting rules and the release type
+  fn always_include(&self) -> &[String];
+  /// Users always evaluating as disabled, overriding targeting rules, the release type, and `always_include`
+  fn always_exclude(&self) -> &[String];
+  /// Type of release and relevant data
+  fn release_type(&self) -> &ReleaseType;
+  /// Targeting rules sorted by priority (see `rule::prioritize`), checked in order before the release type
+  fn rules(&self) -> &[Rule];
+}
+
+/// Custom evaluation logic (e.g. a product's WASM plugin) consulted before targeting rules
+pub trait Verdict {
+  /// Returns if the flag is enabled for the context, `None` to leave it to targeting rules and the release type
+  fn verdict(&self, context: &EvaluationContext) -> Option<bool>;
+}
+
+/// Evaluates a flag, returning if it is enabled and why
+///
+/// Users on the flag's always exclude or always include lists are decided first, then targeting rules are checked by
+/// priority before the release type, the first matching rule decides the result
+///
+/// # Parameters
+/// * **flag**    - Flag to evaluate
+/// * **context** - Context (optional user and attributes) used to evaluate the flag with
+pub fn evaluate<F: Evaluable + ?Sized>(flag: &F, context: &EvaluationContext) -> EvaluationResult {
+  evaluate_with(flag, context, None)
+}
+
+/// Evaluates a flag like `evaluate`, letting custom logic decide the result before targeting rules are checked
+///
+/// # Parameters
+/// * **flag**    - Flag to evaluate
+/// * **context** - Context (optional user and attributes) used to evaluate the flag with
+/// * **verdict** - *(optional)* custom logic deciding the result, it can't enable a disabled or archived flag
+pub fn evaluate_with<F: Evaluable + ?Sized>(
+  flag: &F,
+  context: &EvaluationContext,
+  verdict: Option<&dyn Verdict>,
+) -> EvaluationResult {
+  if !flag.enabled() {
+    return EvaluationResult::new(false, Reason::FlagDisabled);
+  }
+  if flag.archived() {
+    return EvaluationResult::new(false, Reason::Archived);
+  }
+
+  let user_id = context.user.as_deref();
+
+  if let Some(user_id) = user_id {
+    if flag.disabled_for().iter().any(|x| x == user_id) {
+      return EvaluationResult::new(false, Reason::UserDisabled);
+    }
+    if flag.always_exclude().iter().any(|x| x == user_id) {
+      return EvaluationResult::new(false, Reason::AlwaysExcluded);
+    }
+    if flag.always_include().iter().any(|x| x == user_id) {
+      return EvaluationResult::new(true, Reason::AlwaysIncluded);
+    }
+  }
+
+  if let Some(value) = verdict.and_then(|x| x.verdict(context)) {
+    return EvaluationResult::new(value, Reason::Plugin);
+  }
+
+  if let Some((index, rule)) = flag.rules().iter().enumerate().find(|(_, x)| x.matches(context)) {
+    return EvaluationResult::rule_match(rule.enabled, index);
+  }
+
+  let allowlisted = |allowlist: &[String]| match user_id {
+    Some(user_id) => allowlist.iter().any(|x| x == user_id),
+    None => false,
+  };
+
+  match flag.release_type() {
+    ReleaseType::Global => EvaluationResult::new(true, Reason::Global),
+    ReleaseType::Limited(allowlist) => {
+      if allowlisted(allowlist) {
+        EvaluationResult::new(true, Reason::Allowlisted)
+      } else {
+        EvaluationResult::new(false, Reason::NotAllowlisted)
+      }
+    }
+    ReleaseType::Percentage(percentage, allowlist, bucket_by) => {
+      if allowlisted(allowlist) {
+        return EvaluationResult::new(true, Reason::Allowlisted);
+      }
+
+      match bucket_by.key(context) {
+        Some(key) if release::bucket(flag.bucket_salt(), key) < *percentage => {
+          EvaluationResult::new(true, Reason::InBucket)
+        }
+        Some(_) => EvaluationResult::new(false, Reason::OutOfBucket),
+        None => EvaluationResult::new(false, Reason::MissingBucketKey),
+      }
+    }
+  }
+}
+
+/// Standalone, evaluation ready definition of a flag
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FlagDefinition {
+  /// Name of the feature flag
+  pub name: String,
+  /// Salt of the percentage release buckets, the name of the flag if not set
+  #[serde(default, alias = "bucketSalt", skip_serializing_if = "Option::is_none")]
+  pub bucket_salt: Option<String>,
+  /// Global enabled status of the flag (false trumps other statuses)
+  pub enabled: bool,
+  /// If the flag is archived, archived flags always evaluate as disabled
+  #[serde(default)]
+  pub archived: bool,
+  /// List of all users who've disabled the feature
+  #[serde(default, alias = "disabledFor")]
+  pub disabled_for: Vec<String>,
+  /// Users always evaluating as enabled, overriding targeting rules and the release type
+  #[serde(default, alias = "alwaysInclude")]
+  pub always_include: Vec<String>,
+  /// Users always evaluating as disabled, overriding everything but the flag being disabled or archived
+  #[serde(default, alias = "alwaysExclude")]
+  pub always_exclude: Vec<String>,
+  /// Type of release and relevant data
+  #[serde(alias = "releaseType")]
+  pub release_type: ReleaseType,
+  /// Targeting rules, checked by priority before the release type
+  #[serde(default)]
+  pub rules: Vec<Rule>,
+}
+
+impl Evaluable for FlagDefinition {
+  fn bucket_salt(&self) -> &str {
+    self.bucket_salt.as_deref().unwrap_or(&self.name)
+  }
+
+  fn enabled(&self) -> bool {
+    self.enabled
+  }
+
+  fn archived(&self) -> bool {
+    self.archived
+  }
+
+  fn disabled_for(&self) -> &[String] {
+    &self.disabled_for
+  }
+
+  fn always_include(&self) -> &[String] {
+    &self.always_include
+  }
+
+  fn always_exclude(&self) -> &[String] {
+    &self.always_exclude
+  }
+
+  fn release_type(&self) -> &ReleaseType {
+    &self.release_type
+  }
+
+  fn rules(&self) -> &[Rule] {
+    &self.rules
+  }
+}
@@ -0,0 +1,111 @@
+//! Data model for Feature Flag release types
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::context::EvaluationContext;
+
+/// Data object for a Feature Flag Release Type
+///
+/// Release types contain relevant information to the type of release
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub enum ReleaseType {
+  /// Release is global
+  Global,
+  /// Release is limited, contains an allowlist of users
+  Limited(Vec<String>),
+  /// Release is percentage, contains a percentage (0-100), allowlist, and what to bucket the percentage on
+  Percentage(f32, Vec<String>, #[serde(default)] BucketBy),
+}
+
+/// What a percentage release buckets on, so a rollout can be consistent per user, organization, session, ...
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub enum BucketBy {
+  /// Bucket on the user ID of the context, or its anonymous key when there's no user
+  #[default]
+  User,
+  /// Bucket on the named attribute of the context (e.g. `organization_id` or `session_id`)
+  Attribute(String),
+}
+
+impl BucketBy {
+  /// Returns the value of the context to bucket on, if present
+  pub fn key<'a>(&self, context: &'a EvaluationContext) -> Option<&'a str> {
+    match self {
+      BucketBy::User => context.user.as_deref().or(context.key.as_deref()),
+      BucketBy::Attribute(name) => context.attribute(name),
+    }
+  }
+}
+
+/// Deterministically places a key into a bucket in `[0, 100)`
+///
+/// Uses 64-bit FNV-1a over `salt:key` so every build of the engine (including WASM) buckets identically
+pub fn bucket(salt: &str, key: &str) -> f32 {
+  let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+  for byte in salt.bytes().chain(std::iter::once(b':')).chain(key.bytes()) {
+    hash ^= byte as u64;
+    hash = hash.wrapping_mul(0x0100_0000_01b3);
+  }
+
+  (hash % 10_000) as f32 / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn buckets_are_in_range_and_stable() {
+    for i in 0..10_000 {
+      let bucket = bucket("flag", &format!("user-{}", i));
+      assert!((0.0..100.0).contains(&bucket), "{}", bucket);
+    }
+
+    // Buckets are part of the rollout state of every flag, changing the hash would move users between them
+    assert_eq!(bucket("new-checkout", "u1"), 87.84);
+  }
+
+  #[test]
+  fn percentages_select_their_share_of_keys() {
+    let keys: Vec<String> = (0..10_000).map(|i| format!("user-{}", i)).collect();
+    let share = |percentage: f32| keys.iter().filter(|x| bucket("flag", x) < percentage).count();
+
+    assert_eq!(share(0.0), 0);
+    assert_eq!(share(100.0), keys.len());
+    assert!((2_300..2_700).contains(&share(25.0)), "{}", share(25.0));
+    // Raising a percentage only adds keys
+    assert!(keys
+      .iter()
+      .filter(|x| bucket("flag", x) < 10.0)
+      .all(|x| bucket("flag", x) < 20.0));
+  }
+
+  #[test]
+  fn salts_bucket_keys_independently() {
+    let moved = (0..1_000)
+      .map(|i| format!("user-{}", i))
+      .filter(|x| (bucket("a", x) < 50.0) != (bucket("b", x) < 50.0))
+      .count();
+
+    assert!((400..600).contains(&moved), "{}", moved);
+  }
+
+  #[test]
+  fn bucket_keys_fall_back_to_the_anonymous_key() {
+    let attributes = [("organization_id".to_string(), "o1".to_string())].into();
+    let context = EvaluationContext::new(None, attributes).with_key(Some("device-1"));
+
+    assert_eq!(BucketBy::User.key(&context), Some("device-1"));
+    assert_eq!(
+      BucketBy::User.key(&EvaluationContext::new(Some("u1"), Default::default()).with_key(Some("device-1"))),
+      Some("u1")
+    );
+    assert_eq!(
+      BucketBy::Attribute("organization_id".to_string()).key(&context),
+      Some("o1")
+    );
+    assert_eq!(BucketBy::Attribute("session_id".to_string()).key(&context), None);
+  }
+}
@@ -0,0 +1,90 @@
+//! Data model for the result of evaluating a Feature Flag
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Why a flag evaluated the way it did
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Reason {
+  /// The flag is globally disabled
+  FlagDisabled,
+  /// The flag is archived
+  Archived,
+  /// The user disabled the flag for themselves
+  UserDisabled,
+  /// The user is on the flag's always exclude list
+  AlwaysExcluded,
+  /// The user is on the flag's always include list
+  AlwaysIncluded,
+  /// The product's plugin decided the result
+  Plugin,
+  /// A targeting rule matched
+  RuleMatch,
+  /// The flag is released globally
+  Global,
+  /// The user is on the release's allowlist
+  Allowlisted,
+  /// The flag is a limited release and the user isn't on its allowlist
+  NotAllowlisted,
+  /// The subject's bucket is inside the release percentage
+  InBucket,
+  /// The subject's bucket is outside the release percentage
+  OutOfBucket,
+  /// The flag is a percentage release and the context has nothing to bucket on
+  MissingBucketKey,
+}
+
+impl Reason {
+  /// Serialized name of the reason (e.g. `RULE_MATCH`)
+  pub fn name(&self) -> &'static str {
+    match self {
+      Reason::FlagDisabled => "FLAG_DISABLED",
+      Reason::Archived => "ARCHIVED",
+      Reason::UserDisabled => "USER_DISABLED",
+      Reason::AlwaysExcluded => "ALWAYS_EXCLUDED",
+      Reason::AlwaysIncluded => "ALWAYS_INCLUDED",
+      Reason::Plugin => "PLUGIN",
+      Reason::RuleMatch => "RULE_MATCH",
+      Reason::Global => "GLOBAL",
+      Reason::Allowlisted => "ALLOWLISTED",
+      Reason::NotAllowlisted => "NOT_ALLOWLISTED",
+      Reason::InBucket => "IN_BUCKET",
+      Reason::OutOfBucket => "OUT_OF_BUCKET",
+      Reason::MissingBucketKey => "MISSING_BUCKET_KEY",
+    }
+  }
+}
+
+/// Result of evaluating a flag
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct EvaluationResult {
+  /// If the flag is enabled
+  pub value: bool,
+  /// Variant of the flag served, `None` until flags have variants
+  pub variant: Option<String>,
+  /// Why the flag evaluated to `value`
+  pub reason: Reason,
+  /// Index of the targeting rule that decided the result, if one did
+  pub rule_index: Option<usize>,
+}
+
+impl EvaluationResult {
+  /// Creates an `EvaluationResult` that wasn't decided by a targeting rule
+  pub fn new(value: bool, reason: Reason) -> EvaluationResult {
+    EvaluationResult {
+      value,
+      variant: None,
+      reason,
+      rule_index: None,
+    }
+  }
+
+  /// Creates an `EvaluationResult` decided by the targeting rule at `rule_index`
+  pub fn rule_match(value: bool, rule_index: usize) -> EvaluationResult {
+    EvaluationResult {
+      rule_index: Some(rule_index),
+      ..EvaluationResult::new(value, Reason::RuleMatch)
+    }
+  }
+}
@@ -0,0 +1,258 @@
+//! Data model for Feature Flag targeting rules
+
+use schemars::JsonSchema;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+
+use crate::context::EvaluationContext;
+use crate::expression;
+
+/// Targeting rule of a Feature Flag
+///
+/// Rules are evaluated by priority, the first rule whose condition matches decides the result of the flag
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Rule {
+  /// Condition the evaluation context must match
+  pub condition: Condition,
+  /// Result of the flag when the condition matches
+  pub enabled: bool,
+  /// Priority of the rule, rules with a lower priority are checked first
+  #[serde(default)]
+  pub priority: u32,
+}
+
+/// Sorts rules by priority and renumbers their priorities from 0, keeping the list order of rules with equal priorities
+///
+/// Rules are stored and evaluated in this order, so the priority of a stored rule is always its index
+pub fn prioritize(rules: &mut [Rule]) {
+  rules.sort_by_key(|x| x.priority);
+  for (priority, rule) in rules.iter_mut().enumerate() {
+    rule.priority = priority as u32;
+  }
+}
+
+/// Moves rules into a new order and renumbers their priorities
+///
+/// Returns an error if `order` isn't an arrangement of every current priority (e.g. `[2, 0, 1]` for three rules)
+///
+/// # Parameters
+/// * **rules** - Prioritized rules to reorder
+/// * **order** - Current priorities of the rules, in their new order
+pub fn reorder(rules: &[Rule], order: &[u32]) -> Result<Vec<Rule>, String> {
+  if order.len() != rules.len() {
+    return Err(format!(
+      "Expected {} rule priorities but got {}",
+      rules.len(),
+      order.len()
+    ));
+  }
+
+  let mut seen = vec![false; rules.len()];
+  let mut reordered = Vec::with_capacity(rules.len());
+  for priority in order {
+    match seen.get_mut(*priority as usize) {
+      Some(seen) if !*seen => *seen = true,
+      Some(_) => return Err(format!("Rule priority {} is listed more than once", priority)),
+      None => return Err(format!("No rule has priority {}", priority)),
+    }
+    reordered.push(Rule {
+      priority: reordered.len() as u32,
+      ..rules[*priority as usize].clone()
+    });
+  }
+
+  Ok(reordered)
+}
+
+/// Deepest nesting of `And`, `Or` and `Not` conditions
+const MAX_CONDITION_DEPTH: usize = 8;
+
+/// Condition of a targeting rule
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub enum Condition {
+  /// Compares a semantic version attribute (e.g. `app_version`) against a constraint
+  ///
+  /// Constraints use cargo syntax (`>=1.2.0`, `>=1.2.0, <2.0.0`, `~1.4`) or a hyphen range (`1.2.0 - 1.4.0`)
+  SemVer { attribute: String, constraint: String },
+  /// Evaluates an expression against the context, e.g. `user.plan == "enterprise" && user.created_at < now() - 30d`
+  ///
+  /// An advanced alternative to the structured conditions, see the `expression` module for the syntax
+  Expression { expression: String },
+  /// Matches users of an account type (e.g. `Developer`, to dogfood features before clients see them)
+  AccountType { account_type: String },
+  /// Matches when every one of the conditions matches
+  And(Vec<Condition>),
+  /// Matches when any of the conditions matches
+  Or(Vec<Condition>),
+  /// Matches when the condition doesn't match
+  Not(Box<Condition>),
+}
+
+impl Rule {
+  /// Returns `true` if the rule's condition matches the context
+  pub fn matches(&self, context: &EvaluationContext) -> bool {
+    self.condition.matches(context)
+  }
+}
+
+impl Condition {
+  /// Returns `true` if the condition matches the context
+  ///
+  /// A condition never matches when the attribute it uses is missing or malformed
+  pub fn matches(&self, context: &EvaluationContext) -> bool {
+    match self {
+      Condition::SemVer { attribute, constraint } => {
+        let version = match context.attribute(attribute).map(|x| Version::parse(x.trim())) {
+          Some(Ok(version)) => version,
+          _ => return false,
+        };
+
+        match parse_version_constraint(constraint) {
+          Ok(requirement) => requirement.matches(&version),
+          Err(_) => false,
+        }
+      }
+      Condition::Expression { expression } => match expression::compile(expression) {
+        Ok(expression) => expression.matches(context),
+        Err(_) => false,
+      },
+      Condition::AccountType { account_type } => match &context.account_type {
+        Some(x) => x.eq_ignore_ascii_case(account_type),
+        None => false,
+      },
+      Condition::And(conditions) => conditions.iter().all(|x| x.matches(context)),
+      Condition::Or(conditions) => conditions.iter().any(|x| x.matches(context)),
+      Condition::Not(condition) => !condition.matches(context),
+    }
+  }
+
+  /// Checks the condition is well formed, returning a description of the problem if not
+  ///
+  /// Besides malformed conditions, compositions that are always true, always false, or redundant are rejected:
+  /// empty `And`/`Or` lists, negated negations, and nesting deeper than 8 levels
+  pub fn validate(&self) -> Result<(), String> {
+    self.validate_nested(0)
+  }
+
+  /// Returns `true` if the condition (or any condition it's composed of) uses the account type of the context
+  pub fn uses_account_type(&self) -> bool {
+    match self {
+      Condition::AccountType { .. } => true,
+      Condition::And(conditions) | Condition::Or(conditions) => conditions.iter().any(|x| x.uses_account_type()),
+      Condition::Not(condition) => condition.uses_account_type(),
+      Condition::SemVer { .. } | Condition::Expression { .. } => false,
+    }
+  }
+
+  fn validate_nested(&self, depth: usize) -> Result<(), String> {
+    if depth > MAX_CONDITION_DEPTH {
+      return Err(format!(
+        "Conditions can't be nested deeper than {} levels",
+        MAX_CONDITION_DEPTH
+      ));
+    }
+
+    match self {
+      Condition::SemVer { attribute, constraint } => {
+        if attribute.is_empty() {
+          return Err("SemVer condition requires an attribute".to_string());
+        }
+
+        match parse_version_constraint(constraint) {
+          Ok(_) => Ok(()),
+          Err(e) => Err(format!("Invalid version constraint '{}': {}", constraint, e)),
+        }
+      }
+      Condition::Expression { expression } => match expression::compile(expression) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Invalid expression: {}", e)),
+      },
+      Condition::AccountType { account_type } => {
+        if account_type.trim().is_empty() {
+          return Err("AccountType condition requires an account type".to_string());
+        }
+        Ok(())
+      }
+      Condition::And(conditions) | Condition::Or(conditions) => {
+        let name = match self {
+          Condition::And(_) => "And",
+          _ => "Or",
+        };
+        if conditions.is_empty() {
+          return Err(format!("{} condition requires at least one condition", name));
+        }
+
+        conditions.iter().try_for_each(|x| x.validate_nested(depth + 1))
+      }
+      Condition::Not(condition) => match condition.as_ref() {
+        Condition::Not(_) => Err("Not condition can't negate another Not condition".to_string()),
+        condition => condition.validate_nested(depth + 1),
+      },
+    }
+  }
+}
+
+/// Parses a version constraint, translating hyphen ranges (`a - b`) into `>=a, <=b`
+fn parse_version_constraint(constraint: &str) -> Result<VersionReq, semver::Error> {
+  match constraint.split_once(" - ") {
+    Some((lower, upper)) => VersionReq::parse(&format!(">={}, <={}", lower.trim(), upper.trim())),
+    None => VersionReq::parse(constraint),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn semver(constraint: &str) -> Condition {
+    Condition::SemVer {
+      attribute: "app_version".to_string(),
+      constraint: constraint.to_string(),
+    }
+  }
+
+  fn matches(constraint: &str, version: &str) -> bool {
+    let attributes = [("app_version".to_string(), version.to_string())].into();
+    semver(constraint).matches(&EvaluationContext::new(None, attributes))
+  }
+
+  #[test]
+  fn versions_are_matched_against_cargo_constraints() {
+    assert!(matches(">=1.2.0", "1.2.0"));
+    assert!(!matches(">=1.2.0", "1.1.9"));
+    assert!(matches(">=1.2.0, <2.0.0", "1.9.9"));
+    assert!(!matches(">=1.2.0, <2.0.0", "2.0.0"));
+    assert!(matches("~1.4", "1.4.7"));
+    assert!(!matches("~1.4", "1.5.0"));
+    assert!(matches("^1.2", " 1.3.0 "));
+  }
+
+  #[test]
+  fn hyphen_ranges_include_both_ends() {
+    assert!(matches("1.2.0 - 1.4.0", "1.2.0"));
+    assert!(matches("1.2.0 - 1.4.0", "1.4.0"));
+    assert!(!matches("1.2.0 - 1.4.0", "1.4.1"));
+    assert!(!matches("1.2.0 - 1.4.0", "1.1.0"));
+  }
+
+  #[test]
+  fn missing_or_malformed_versions_do_not_match() {
+    assert!(!matches(">=1.0.0", "1.0"));
+    assert!(!matches(">=1.0.0", "latest"));
+    assert!(!semver(">=1.0.0").matches(&EvaluationContext::default()));
+    assert!(!matches("not a constraint", "1.0.0"));
+  }
+
+  #[test]
+  fn semver_conditions_are_validated() {
+    assert!(semver(">=1.2.0, <2.0.0").validate().is_ok());
+    assert!(semver("1.2.0 - 1.4.0").validate().is_ok());
+    assert!(semver(">=banana").validate().is_err());
+
+    let unnamed = Condition::SemVer {
+      attribute: String::new(),
+      constraint: ">=1.0.0".to_string(),
+    };
+    assert!(unnamed.validate().is_err());
+  }
+}
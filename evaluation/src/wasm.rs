@@ -0,0 +1,18 @@
+//! JavaScript bindings for edge runtimes
+
+use wasm_bindgen::prelude::*;
+
+use crate::{rule, EvaluationContext, FlagDefinition};
+
+/// Evaluates a JSON encoded `FlagDefinition` with a JSON encoded `EvaluationContext`
+///
+/// Throws if either document can't be parsed
+#[wasm_bindgen]
+pub fn evaluate(flag: &str, context: &str) -> Result<bool, JsValue> {
+  let mut flag: FlagDefinition = serde_json::from_str(flag).map_err(|e| JsValue::from_str(&e.to_string()))?;
+  let context: EvaluationContext = serde_json::from_str(context).map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+  rule::prioritize(&mut flag.rules);
+
+  Ok(crate::evaluate(&flag, &context).value)
+}
@@ -0,0 +1,150 @@
+//! IP allowlist of the administrative API, so changes can be limited to office or VPN ranges
+
+use std::net::IpAddr;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::Method;
+use rocket::{Data, Request};
+
+use crate::controller::versioning::API_V1;
+use crate::log;
+
+/// Path requests are rewritten to when they come from outside the allowed ranges
+pub const NETWORK_FORBIDDEN_PATH: &str = "/__network_forbidden";
+
+/// Writes open to every network: flag checks, and logging in and out
+const PUBLIC_WRITES: [&str; 4] = ["/check", "/login", "/logout", "/saml/acs"];
+
+/// Range of IP addresses in CIDR notation (e.g. `10.0.0.0/8`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+  network: IpAddr,
+  prefix: u8,
+}
+
+impl Cidr {
+  /// Parses a range in CIDR notation, a single address is a range of its own
+  pub fn parse(value: &str) -> Option<Cidr> {
+    let (address, prefix) = match value.trim().split_once('/') {
+      Some((address, prefix)) => (address, Some(prefix.trim().parse::<u8>().ok()?)),
+      None => (value.trim(), None),
+    };
+    let network: IpAddr = address.trim().parse().ok()?;
+    let max_prefix = match network {
+      IpAddr::V4(_) => 32,
+      IpAddr::V6(_) => 128,
+    };
+
+    match prefix.unwrap_or(max_prefix) {
+      prefix if prefix <= max_prefix => Some(Cidr { network, prefix }),
+      _ => None,
+    }
+  }
+
+  /// If `ip` is in the range. IPv4 addresses mapped to IPv6 are matched as IPv4
+  pub fn contains(&self, ip: IpAddr) -> bool {
+    let ip = match ip {
+      IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+      IpAddr::V4(_) => ip,
+    };
+
+    match (self.network, ip) {
+      (IpAddr::V4(network), IpAddr::V4(ip)) => {
+        let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+        u32::from(network) & mask == u32::from(ip) & mask
+      }
+      (IpAddr::V6(network), IpAddr::V6(ip)) => {
+        let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+        u128::from(network) & mask == u128::from(ip) & mask
+      }
+      _ => false,
+    }
+  }
+}
+
+/// Fairing limiting the administrative API to the client IPs of `ADMIN_ALLOWED_CIDRS`
+///
+/// `ADMIN_ALLOWED_CIDRS` is a comma separated list of ranges (e.g. `10.0.0.0/8,192.168.1.0/24`), the API is open to
+/// every network when it isn't set. Administrative requests are the ones that aren't `GET`, `HEAD` or `OPTIONS`, except
+/// flag checks (`/check/...`) and logging in and out, which stay public. Requests from other networks, or whose client
+/// IP is unknown, are answered 403. Invalid ranges are ignored, and if none is valid every administrative request is
+/// refused rather than the API left open
+pub struct AdminAllowlist {
+  /// Allowed ranges, `None` if the API is open to every network
+  cidrs: Option<Vec<Cidr>>,
+}
+
+impl AdminAllowlist {
+  /// Constructs an `AdminAllowlist` from `ADMIN_ALLOWED_CIDRS`
+  pub fn from_env() -> AdminAllowlist {
+    let value = match dotenv::var("ADMIN_ALLOWED_CIDRS") {
+      Ok(value) if !value.trim().is_empty() => value,
+      _ => return AdminAllowlist { cidrs: None },
+    };
+
+    let mut cidrs = vec![];
+    for range in value.split(',').filter(|x| !x.trim().is_empty()) {
+      match Cidr::parse(range) {
+        Some(cidr) => cidrs.push(cidr),
+        None => log!("Ignoring invalid 'ADMIN_ALLOWED_CIDRS' range '{}'", range.trim()),
+      }
+    }
+
+    if cidrs.is_empty() {
+      log!("No valid 'ADMIN_ALLOWED_CIDRS' range, administrative requests are refused from every network");
+    }
+
+    AdminAllowlist { cidrs: Some(cidrs) }
+  }
+
+  /// If the request changes something through the administrative API
+  fn is_administrative(request: &Request<'_>) -> bool {
+    if matches!(request.method(), Method::Get | Method::Head | Method::Options) {
+      return false;
+    }
+
+    let path = request.uri().path();
+    let path = path.as_str();
+    let path = path.strip_prefix(API_V1).unwrap_or(path);
+
+    !PUBLIC_WRITES
+      .iter()
+      .any(|x| path == *x || path.starts_with(&format!("{}/", x)))
+  }
+
+  /// If the request is allowed from its client IP
+  fn allows(&self, request: &Request<'_>) -> bool {
+    let cidrs = match &self.cidrs {
+      Some(cidrs) => cidrs,
+      None => return true,
+    };
+
+    if !AdminAllowlist::is_administrative(request) {
+      return true;
+    }
+
+    match request.client_ip() {
+      Some(ip) => cidrs.iter().any(|x| x.contains(ip)),
+      None => false,
+    }
+  }
+}
+
+#[rocket::async_trait]
+impl Fairing for AdminAllowlist {
+  fn info(&self) -> Info {
+    Info {
+      name: "Admin Allowlist",
+      kind: Kind::Request,
+    }
+  }
+
+  async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+    // Fairings can't respond directly, so refused requests are routed to the network forbidden route instead
+    if !self.allows(request) {
+      request.set_method(Method::Get);
+      request.set_uri(Origin::parse(NETWORK_FORBIDDEN_PATH).expect("valid network forbidden path"));
+    }
+  }
+}
@@ -0,0 +1,308 @@
+//! Per-product API keys, authorizing SDKs to check flags without a user session
+//!
+//! Keys are sent in the `X-Api-Key` header. Server keys can use every `/check` and `/sdk` route, client keys (shipped in
+//! apps, so assumed public) only the `/check` routes. Either kind only gives access to the flags of its own product,
+//! for what its scopes allow: `flags:read` checks flags, and `flags:write` lets the key change its product and its
+//! flags like a service account.
+//! The `/check` routes also take the tokens of service accounts with the `evaluate` scope, for their products
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::json;
+use rocket_okapi::okapi::openapi3::{Object, Responses, SecurityRequirement, SecurityScheme, SecuritySchemeData};
+use rocket_okapi::{
+  gen::OpenApiGenerator,
+  request::{OpenApiFromRequest, RequestHeaderInput},
+};
+use sha2::{Digest, Sha256};
+
+use crate::controller::database::ConnectionManager;
+use crate::controller::error::{self, ApiError};
+use crate::controller::service_accounts::{ServiceAccountAuth, ServiceAccountError};
+use crate::model::api_key::ApiKeyKind;
+use crate::model::service_account::ServiceScope;
+use crate::model::user::Permission;
+
+/// Header API keys are sent in
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Length of the start of a key stored alongside its hash, prefix included
+const KEY_PREFIX_LENGTH: usize = 16;
+
+/// Prefix of the actor of changes made with API keys in the audit log
+const ACTOR_PREFIX: &str = "api_key:";
+
+/// Generates a new random key of the given kind, returning the key and the start of it that's stored
+pub fn generate(kind: ApiKeyKind) -> (String, String) {
+  let secret: String = rand::random::<[u8; 32]>()
+    .iter()
+    .map(|x| format!("{:02x}", x))
+    .collect();
+  let key = format!("{}{}", kind.prefix(), secret);
+  let key_prefix = key[..KEY_PREFIX_LENGTH].to_string();
+
+  (key, key_prefix)
+}
+
+/// Returns the actor of changes made with the API key of ID `api_key_id`
+pub fn actor(api_key_id: &str) -> String {
+  format!("{}{}", ACTOR_PREFIX, api_key_id)
+}
+
+/// Hex SHA-256 hash of a key, the only form keys are stored in
+pub fn hash(key: &str) -> String {
+  Sha256::digest(key.as_bytes())
+    .iter()
+    .map(|x| format!("{:02x}", x))
+    .collect()
+}
+
+/// Returns true if `/check` routes require an API key, read from `REQUIRE_API_KEYS` (`false` by default)
+pub fn keys_required() -> bool {
+  matches!(
+    dotenv::var("REQUIRE_API_KEYS")
+      .map(|x| x.trim().to_lowercase())
+      .as_deref(),
+    Ok("true" | "1" | "yes")
+  )
+}
+
+#[derive(Debug)]
+pub enum ApiKeyError {
+  NoKey,
+  Invalid,
+  Unavailable,
+}
+
+/// Custom rocket request guard for routes authorized by an API key
+///
+/// Fails with 401 if the `X-Api-Key` header is missing, or the key is unknown or revoked. Routes must still check the
+/// key belongs to the product they access with `authorize`
+pub struct ApiKeyAuth {
+  /// Unique ID of the API key
+  pub api_key_id: String,
+  /// Unique ID of the product the key gives access to
+  pub product_id: String,
+  /// Where the key is used
+  pub kind: ApiKeyKind,
+  /// What the key can do with its product
+  pub scopes: Vec<Permission>,
+}
+
+impl ApiKeyAuth {
+  /// Checks the key can read the flags of the product of ID `product_id`, with 403 otherwise
+  pub fn authorize(&self, product_id: &str) -> Result<(), ApiError> {
+    self.require_scope(Permission::FlagsRead)?;
+    self.authorize_product(product_id)
+  }
+
+  /// Checks the key's scopes include `permission`, with 403 naming the missing scope otherwise
+  pub fn require_scope(&self, permission: Permission) -> Result<(), ApiError> {
+    match self.scopes.contains(&permission) {
+      true => Ok(()),
+      false => Err(
+        ApiError::new(
+          Status::Forbidden,
+          format!(
+            "Error. Missing permission '{}', which the scopes of the API key don't grant.",
+            permission.name()
+          ),
+        )
+        .with_details(json!({ "permission": permission.name() })),
+      ),
+    }
+  }
+
+  /// Checks the key gives access to the product of ID `product_id`, with 403 otherwise
+  pub fn authorize_product(&self, product_id: &str) -> Result<(), ApiError> {
+    match self.product_id == product_id {
+      true => Ok(()),
+      false => Err(ApiError::new(
+        Status::Forbidden,
+        format!("Error. The API key doesn't give access to product '{}'.", product_id),
+      )),
+    }
+  }
+
+  /// Checks the key is a server key, with 403 otherwise
+  pub fn require_server(&self) -> Result<(), ApiError> {
+    match self.kind {
+      ApiKeyKind::Server => Ok(()),
+      ApiKeyKind::Client => Err(ApiError::new(
+        Status::Forbidden,
+        "Error. Only server API keys can use this route.",
+      )),
+    }
+  }
+
+  /// Looks up the key of the request, if it sent one
+  pub async fn from_header(request: &Request<'_>) -> Option<Result<ApiKeyAuth, ApiKeyError>> {
+    let key = request.headers().get_one(API_KEY_HEADER)?;
+
+    let database_connection = match request.rocket().state::<ConnectionManager>() {
+      Some(value) => value,
+      None => return Some(Err(ApiKeyError::Unavailable)),
+    };
+
+    Some(ApiKeyAuth::lookup(database_connection, key).await)
+  }
+
+  /// Looks up `key`, failing if it's unknown or revoked
+  pub async fn lookup(database_connection: &ConnectionManager, key: &str) -> Result<ApiKeyAuth, ApiKeyError> {
+    match database_connection.get_api_key_by_hash(&hash(key.trim())).await {
+      Ok(Some(api_key)) if api_key.is_active() => Ok(ApiKeyAuth {
+        api_key_id: api_key.oid.map(|x| x.to_hex()).unwrap_or_default(),
+        product_id: api_key.product_id,
+        kind: api_key.kind,
+        scopes: api_key.scopes,
+      }),
+      Ok(_) => Err(ApiKeyError::Invalid),
+      Err(_) => Err(ApiKeyError::Unavailable),
+    }
+  }
+}
+
+/// Outcome of a failed API key lookup
+fn failure<T>(e: ApiKeyError) -> Outcome<T, ApiKeyError> {
+  let status = match e {
+    ApiKeyError::Unavailable => Status::InternalServerError,
+    _ => Status::Unauthorized,
+  };
+
+  Outcome::Failure((status, e))
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiKeyAuth {
+  type Error = ApiKeyError;
+
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    match ApiKeyAuth::from_header(request).await {
+      Some(Ok(auth)) => Outcome::Success(auth),
+      Some(Err(e)) => failure(e),
+      None => failure(ApiKeyError::NoKey),
+    }
+  }
+}
+
+/// Custom rocket request guard for `/check` routes, which take an API key or service account token unless
+/// `REQUIRE_API_KEYS` is set
+///
+/// Fails with 401 if a key or token is sent but unknown or revoked, or if neither is sent while keys are required
+pub struct CheckAuth {
+  /// Key of the request, `None` if it didn't send one
+  pub api_key: Option<ApiKeyAuth>,
+  /// Service account of the request, `None` if it didn't send a service account token
+  pub service_account: Option<ServiceAccountAuth>,
+}
+
+impl CheckAuth {
+  /// Checks the key or service account of the request, if any, gives access to the product of ID `product_id`, with
+  /// 403 otherwise. Service accounts also need the `evaluate` scope
+  pub fn authorize(&self, product_id: &str) -> Result<(), ApiError> {
+    if let Some(service_account) = &self.service_account {
+      service_account.require_scope(ServiceScope::Evaluate)?;
+      service_account.authorize(product_id)?;
+    }
+
+    match &self.api_key {
+      Some(api_key) => api_key.authorize(product_id),
+      None => Ok(()),
+    }
+  }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CheckAuth {
+  type Error = ApiKeyError;
+
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    match ApiKeyAuth::from_header(request).await {
+      Some(Ok(auth)) => Outcome::Success(CheckAuth {
+        api_key: Some(auth),
+        service_account: None,
+      }),
+      Some(Err(e)) => failure(e),
+      None => match ServiceAccountAuth::from_header(request).await {
+        Some(Ok(service_account)) => Outcome::Success(CheckAuth {
+          api_key: None,
+          service_account: Some(service_account),
+        }),
+        Some(Err(ServiceAccountError::Unavailable)) => failure(ApiKeyError::Unavailable),
+        Some(Err(ServiceAccountError::Invalid)) => failure(ApiKeyError::Invalid),
+        None if keys_required() => failure(ApiKeyError::NoKey),
+        None => Outcome::Success(CheckAuth {
+          api_key: None,
+          service_account: None,
+        }),
+      },
+    }
+  }
+}
+
+/// Security scheme of API keys in the OpenAPI spec
+fn security_input() -> RequestHeaderInput {
+  let security_scheme = SecurityScheme {
+    description: Some("Requires a per-product API key in the `X-Api-Key` header.".to_owned()),
+    data: SecuritySchemeData::ApiKey {
+      name: API_KEY_HEADER.to_owned(),
+      location: "header".to_owned(),
+    },
+    extensions: Object::default(),
+  };
+  let mut security_req = SecurityRequirement::new();
+  security_req.insert("ApiKeyAuth".to_owned(), Vec::new());
+
+  RequestHeaderInput::Security("ApiKeyAuth".to_owned(), security_scheme, security_req)
+}
+
+impl<'a> OpenApiFromRequest<'a> for ApiKeyAuth {
+  fn from_request_input(
+    _gen: &mut OpenApiGenerator,
+    _name: String,
+    _required: bool,
+  ) -> rocket_okapi::Result<RequestHeaderInput> {
+    Ok(security_input())
+  }
+
+  fn get_responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+    Ok(error::error_responses(
+      gen,
+      &[
+        ("401", "The API key is missing, unknown or revoked"),
+        (
+          "403",
+          "The API key doesn't give access to the product, isn't a server key, or its scopes don't include \
+           `flags:read`",
+        ),
+      ],
+    ))
+  }
+}
+
+impl<'a> OpenApiFromRequest<'a> for CheckAuth {
+  fn from_request_input(
+    _gen: &mut OpenApiGenerator,
+    _name: String,
+    _required: bool,
+  ) -> rocket_okapi::Result<RequestHeaderInput> {
+    Ok(security_input())
+  }
+
+  fn get_responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+    Ok(error::error_responses(
+      gen,
+      &[
+        (
+          "401",
+          "The API key or service account token is unknown or revoked, or missing while keys are required",
+        ),
+        (
+          "403",
+          "The API key or service account doesn't give access to the product, or the service account lacks the \
+           `evaluate` scope",
+        ),
+      ],
+    ))
+  }
+}
@@ -1,145 +1,273 @@
 //! User authentication utilities
-
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+//!
+//! Login sessions are stored in the database by the hash of their auth token, so they survive restarts and are shared
+//! by every instance of the service
 
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome, Request};
-use rocket_okapi::okapi::openapi3::{Object, SecurityRequirement, SecurityScheme, SecuritySchemeData};
+use rocket_okapi::okapi::openapi3::{Object, Responses, SecurityRequirement, SecurityScheme, SecuritySchemeData};
 use rocket_okapi::{
   gen::OpenApiGenerator,
   request::{OpenApiFromRequest, RequestHeaderInput},
 };
 
-const USER_ID: &str = "user_id";
-const AUTH_TOKEN: &str = "auth_token";
+use crate::controller::api_keys;
+use crate::controller::database::error::StorageResult;
+use crate::controller::database::ConnectionManager;
+use crate::controller::error;
+use crate::model::session::Session;
+use crate::model::user::Permission;
+
+/// Private cookie holding the ID of the logged in user
+pub const USER_ID: &str = "user_id";
+/// Private cookie holding the auth token of the logged in user
+pub const AUTH_TOKEN: &str = "auth_token";
+
+/// How long sessions last without activity when `SESSION_TTL_SECONDS` isn't set
+const DEFAULT_SESSION_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+/// Returns how long sessions last without activity, read from `SESSION_TTL_SECONDS`
+pub fn session_ttl_from_env() -> chrono::Duration {
+  let seconds = match dotenv::var("SESSION_TTL_SECONDS").map(|x| x.parse::<i64>()) {
+    Ok(Ok(seconds)) if seconds > 0 => seconds,
+    _ => DEFAULT_SESSION_TTL_SECONDS,
+  };
+
+  chrono::Duration::seconds(seconds)
+}
+
+/// Returns the token `POST /setup` requires to create the first user, read from `BOOTSTRAP_TOKEN`. `None` if it isn't
+/// set, which leaves setup open to whoever gets there first
+pub fn bootstrap_token_from_env() -> Option<String> {
+  dotenv::var("BOOTSTRAP_TOKEN").ok().filter(|x| !x.trim().is_empty())
+}
+
+/// Starts a session of the user of ID `user_id`, logged in from `user_agent` and limited to `scopes` if given
+///
+/// Returns the auth token of the session
+pub async fn start_session(
+  database_connection: &ConnectionManager,
+  user_id: &str,
+  user_agent: Option<&str>,
+  scopes: Option<Vec<Permission>>,
+) -> StorageResult<String> {
+  let auth_token: String = rand::random::<[u8; 32]>()
+    .iter()
+    .map(|x| format!("{:02x}", x))
+    .collect();
+
+  let session_builder = Session::builder()
+    .with_user_id(user_id)
+    .with_token_hash(&api_keys::hash(&auth_token))
+    .with_user_agent(user_agent)
+    .with_scopes(scopes)
+    .with_ttl(session_ttl_from_env());
+
+  database_connection
+    .create_session(session_builder)
+    .await
+    .map(|_| auth_token)
+}
+
+/// Returns the bearer token of a session, the user ID and auth token joined by `:`
+///
+/// Sessions are looked up by their user and the hash of their auth token, so the user ID is part of the bearer token
+pub fn bearer_token(user_id: &str, auth_token: &str) -> String {
+  format!("{}:{}", user_id, auth_token)
+}
 
 #[derive(Debug)]
 pub enum UserAuthError {
   NoUserId,
   NoAuthToken,
   Invalid,
+  Inactive,
+  Unavailable,
 }
 
-/// Custom rocket request guard for request where cookie based user authentication is required
-pub struct UserAuth;
-
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for UserAuth {
-  type Error = UserAuthError;
+/// User ID and auth token a request was sent with, from the login cookies or else an `Authorization: Bearer` header
+///
+/// Only the presence of the credentials is checked, use `UserAuth` to check they're of a logged in user
+pub struct Credentials {
+  pub user_id: String,
+  pub auth_token: String,
+}
 
-  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+impl Credentials {
+  fn from_cookies(request: &Request<'_>) -> Result<Credentials, UserAuthError> {
     // Get user id from cookie
     let user_id = match request.cookies().get_private(USER_ID) {
       Some(value) => value.value().to_owned(), // Get value found from cookies
-      None => return Outcome::Failure((Status::BadRequest, UserAuthError::NoUserId)),
+      None => return Err(UserAuthError::NoUserId),
     };
     // Get auth token from cookie
     let auth_token = match request.cookies().get_private(AUTH_TOKEN) {
       Some(value) => value.value().to_owned(), // Get value found from cookies
-      None => return Outcome::Failure((Status::BadRequest, UserAuthError::NoAuthToken)),
+      None => return Err(UserAuthError::NoAuthToken),
+    };
+
+    Ok(Credentials { user_id, auth_token })
+  }
+
+  fn from_authorization(request: &Request<'_>) -> Option<Credentials> {
+    Credentials::from_bearer(request.headers().get_one("Authorization")?)
+  }
+
+  /// Reads the credentials of an `Authorization: Bearer <user_id>:<auth_token>` header value, `None` if it isn't one
+  pub fn from_bearer(authorization: &str) -> Option<Credentials> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+      return None;
+    }
+
+    let (user_id, auth_token) = token.trim().split_once(':')?;
+    Some(Credentials {
+      user_id: user_id.to_string(),
+      auth_token: auth_token.to_string(),
+    })
+  }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Credentials {
+  type Error = UserAuthError;
+
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    match Credentials::from_cookies(request) {
+      Ok(credentials) => Outcome::Success(credentials),
+      Err(e) => match Credentials::from_authorization(request) {
+        Some(credentials) => Outcome::Success(credentials),
+        None => Outcome::Failure((Status::Unauthorized, e)),
+      },
+    }
+  }
+}
+
+/// Custom rocket request guard for request where user authentication is required, with the login cookies or an
+/// `Authorization: Bearer <token>` header
+///
+/// Fails with 401 if the credentials are missing or don't match a logged in user, or the session expired, and 403 if the
+/// user is suspended or deactivated. Succeeding extends the session, so only idle sessions expire
+pub struct UserAuth {
+  /// Unique ID of the authenticated user
+  pub user_id: String,
+  /// Permissions the token of the session is limited to, `None` if it's only limited by the user's role
+  pub scopes: Option<Vec<Permission>>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UserAuth {
+  type Error = UserAuthError;
+
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    let Credentials { user_id, auth_token } = match Credentials::from_request(request).await {
+      Outcome::Success(credentials) => credentials,
+      Outcome::Failure(failure) => return Outcome::Failure(failure),
+      Outcome::Forward(_) => return Outcome::Failure((Status::Unauthorized, UserAuthError::NoUserId)),
     };
-    // Get current auth tokens from state
-    let tokens_mut = match request.rocket().state::<Arc<Mutex<AuthTokens>>>() {
+    let database_connection = match request.rocket().state::<ConnectionManager>() {
       Some(value) => value,
-      None => return Outcome::Failure((Status::BadRequest, UserAuthError::Invalid)),
+      None => return Outcome::Failure((Status::InternalServerError, UserAuthError::Invalid)),
     };
-    // Lock current tokens for reading
-    let tokens = match tokens_mut.lock() {
-      Ok(value) => value,
-      Err(poisoned) => poisoned.into_inner(), // recover from poisoned mutex
+
+    let session = match database_connection
+      .refresh_session(&user_id, &api_keys::hash(&auth_token), session_ttl_from_env())
+      .await
+    {
+      Ok(Some(session)) => session,
+      Ok(None) => return Outcome::Failure((Status::Unauthorized, UserAuthError::Invalid)),
+      Err(e) => return error::fail_guard(request, e.into(), UserAuthError::Unavailable),
     };
 
-    if tokens.check_for(&user_id, &auth_token) {
-      return Outcome::Success(Self);
+    // Suspending a user ends their sessions, but a request racing the suspension mustn't get through either
+    match database_connection.get_user(None, Some(&user_id)).await {
+      Ok(Some(user)) if user.is_active() => Outcome::Success(Self {
+        user_id,
+        scopes: session.scopes,
+      }),
+      Ok(Some(_)) => Outcome::Failure((Status::Forbidden, UserAuthError::Inactive)),
+      Ok(None) => Outcome::Failure((Status::Unauthorized, UserAuthError::Invalid)),
+      Err(e) => error::fail_guard(request, e.into(), UserAuthError::Unavailable),
     }
-
-    Outcome::Failure((Status::BadRequest, UserAuthError::Invalid))
   }
 }
 
+/// Security scheme of user authentication in the OpenAPI spec
+pub fn security_input() -> RequestHeaderInput {
+  let security_scheme = SecurityScheme {
+    description: Some(
+      "Requires the `token` returned by `/login` as a Bearer token, or the login cookies. Routes list the permission \
+       they need as their scope, which the user's role and the scopes of the token must grant."
+        .to_owned(),
+    ),
+    // Setup data requirements.
+    // In this case the header `Authorization: mytoken` needs to be set.
+    data: SecuritySchemeData::Http {
+      scheme: "bearer".to_owned(), // `basic`, `digest`, ...
+      // Just gives use a hint to the format used
+      bearer_format: Some("bearer".to_owned()),
+    },
+    extensions: Object::default(),
+  };
+  // Add the requirement for this route/endpoint
+  // This can change between routes.
+  let mut security_req = SecurityRequirement::new();
+  // Each security requirement needs to be met before access is allowed.
+  security_req.insert("UserAuth".to_owned(), Vec::new());
+  // These vvvvvvv-----^^^^^^^^ values need to match exactly!
+  RequestHeaderInput::Security("UserAuth".to_owned(), security_scheme, security_req)
+}
+
 impl<'a> OpenApiFromRequest<'a> for UserAuth {
   fn from_request_input(
     _gen: &mut OpenApiGenerator,
     _name: String,
     _required: bool,
   ) -> rocket_okapi::Result<RequestHeaderInput> {
-    let security_scheme = SecurityScheme {
-      description: Some("Requires an Bearer token to access, token is: `auth_token`.".to_owned()),
-      // Setup data requirements.
-      // In this case the header `Authorization: mytoken` needs to be set.
-      data: SecuritySchemeData::Http {
-        scheme: "bearer".to_owned(), // `basic`, `digest`, ...
-        // Just gives use a hint to the format used
-        bearer_format: Some("bearer".to_owned()),
-      },
-      extensions: Object::default(),
-    };
-    // Add the requirement for this route/endpoint
-    // This can change between routes.
-    let mut security_req = SecurityRequirement::new();
-    // Each security requirement needs to be met before access is allowed.
-    security_req.insert("UserAuth".to_owned(), Vec::new());
-    // These vvvvvvv-----^^^^^^^^ values need to match exactly!
-    Ok(RequestHeaderInput::Security(
-      "UserAuth".to_owned(),
-      security_scheme,
-      security_req,
-    ))
+    Ok(security_input())
   }
-}
 
-/// Contains a hash map of user tokens to validate that a user is logged in
-pub struct AuthTokens {
-  /// `HashMap` relating a list of tokens to a user ID
-  user_tokens: HashMap<String, Vec<String>>,
+  fn get_responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+    Ok(error::error_responses(
+      gen,
+      &[
+        ("401", "Not logged in, or the login cookies or bearer token are invalid"),
+        ("403", "The role of the user doesn't grant a permission the route needs"),
+      ],
+    ))
+  }
 }
 
-// TODO implement FromRequest https://api.rocket.rs/v0.5-rc/rocket/request/trait.FromRequest.html
-
-impl AuthTokens {
-  /// Creates and returns a new `AuthTokens` struct
-  pub fn new() -> AuthTokens {
-    AuthTokens {
-      user_tokens: HashMap::new(),
-    }
+impl<'a> OpenApiFromRequest<'a> for Credentials {
+  fn from_request_input(
+    _gen: &mut OpenApiGenerator,
+    _name: String,
+    _required: bool,
+  ) -> rocket_okapi::Result<RequestHeaderInput> {
+    Ok(security_input())
   }
+}
 
-  /// Creates a new token for the specified user, adds it to the user tokens `HashMap` and returns the token
-  pub fn add_token(&mut self, user_id: &str) -> String {
-    // TODO generate real token
-    let token = "token";
-
-    let tokens_new = match self.user_tokens.get(user_id) {
-      Some(tokens_old) => {
-        tokens_old.to_owned().push(token.to_owned());
-        tokens_old.to_owned()
-      }
-      None => {
-        vec![token.to_string()]
-      }
-    };
-
-    self.user_tokens.insert(user_id.to_string(), tokens_new);
+/// `User-Agent` header of a request, if it sent one
+pub struct UserAgent(pub Option<String>);
 
-    token.to_string()
-  }
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UserAgent {
+  type Error = std::convert::Infallible;
 
-  /// Removes a user token.
-  ///
-  /// Returns `false` if the the user was not found
-  pub fn remove_token(&mut self, user_id: &str) -> bool {
-      match self.user_tokens.remove(user_id) {
-          Some(_) => true,
-          None => false,
-      }
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    Outcome::Success(UserAgent(
+      request.headers().get_one("User-Agent").map(|x| x.to_string()),
+    ))
   }
+}
 
-  /// Checks if a token is authenticated under a specific user
-  pub fn check_for(&self, user_id: &str, token: &str) -> bool {
-    match self.user_tokens.get(user_id) {
-      Some(tokens) => tokens.contains(&token.to_owned()),
-      None => false,
-    }
+impl<'a> OpenApiFromRequest<'a> for UserAgent {
+  fn from_request_input(
+    _gen: &mut OpenApiGenerator,
+    _name: String,
+    _required: bool,
+  ) -> rocket_okapi::Result<RequestHeaderInput> {
+    Ok(RequestHeaderInput::None)
   }
 }
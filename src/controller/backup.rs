@@ -0,0 +1,49 @@
+//! Backups of everything the service stores to a JSON file, and restoring them, run with `--export-backup <path>` and
+//! `--restore-backup <path>` instead of serving
+//!
+//! Backups are read and written through the storage driver, so they work the same on every database without its own
+//! tools (e.g. `mongodump`). They're meant for small installs, the whole backup is held in memory
+
+use std::fs;
+use std::path::Path;
+
+use rocket::serde::json::serde_json;
+
+use crate::controller::database::ConnectionManager;
+use crate::model::backup::{Backup, BACKUP_VERSION};
+
+/// Writes a backup of everything stored to the file at `path`, returning the number of documents backed up
+pub async fn export(database_connection: &ConnectionManager, path: &Path) -> Result<usize, String> {
+  let backup = database_connection
+    .export_backup()
+    .await
+    .map_err(|e| format!("Error reading the database: {}", e))?;
+
+  let contents = serde_json::to_vec_pretty(&backup).map_err(|e| format!("Error serializing the backup: {}", e))?;
+  fs::write(path, contents).map_err(|e| format!("Error writing '{}': {}", path.display(), e))?;
+
+  Ok(backup.document_count())
+}
+
+/// Restores the backup in the file at `path`, returning the number of documents restored
+///
+/// Stored documents with the same IDs as backed up ones are replaced, the others are kept
+pub async fn restore(database_connection: &ConnectionManager, path: &Path) -> Result<usize, String> {
+  let contents = fs::read(path).map_err(|e| format!("Error reading '{}': {}", path.display(), e))?;
+  let backup: Backup =
+    serde_json::from_slice(&contents).map_err(|e| format!("Error parsing '{}': {}", path.display(), e))?;
+
+  if backup.version > BACKUP_VERSION {
+    return Err(format!(
+      "The backup is of version {}, this service can only restore up to version {}",
+      backup.version, BACKUP_VERSION
+    ));
+  }
+
+  database_connection
+    .restore_backup(&backup)
+    .await
+    .map_err(|e| format!("Error writing to the database: {}", e))?;
+
+  Ok(backup.document_count())
+}
@@ -0,0 +1,138 @@
+//! HTTP caching of flag checks, so CDNs and the HTTP caches of client SDKs can serve very hot flags
+//!
+//! Checks are sent with a `Cache-Control` header built from the effective settings of the checked flags: results may
+//! be cached for `cache_ttl_seconds`, and served stale for `stale_while_revalidate_seconds` while they're revalidated.
+//! `CHECK_CACHE_CONTROL` sets who may cache them, `public` (shared caches like CDNs included), `private` (only the
+//! client) or `off` (no header is sent). It defaults to `public`, or `private` when `REQUIRE_API_KEYS` is set, so shared
+//! caches don't serve checks to callers without a key. Checks then also vary on `X-Api-Key`
+
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::response::OpenApiResponderInner;
+
+use crate::controller::api_keys::{self, API_KEY_HEADER};
+use crate::controller::case::ACCEPT_CASE;
+use crate::model::settings::EffectiveSettings;
+
+/// Caches allowed to store checks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheScope {
+  /// Any cache, shared caches like CDNs included
+  Public,
+  /// Only the client's own cache
+  Private,
+  /// No `Cache-Control` header is sent
+  Off,
+}
+
+impl CacheScope {
+  /// Parses a scope from its name (`public`, `private` or `off`), ignoring case
+  pub fn from_name(name: &str) -> Option<CacheScope> {
+    match name.trim().to_lowercase().as_str() {
+      "public" => Some(CacheScope::Public),
+      "private" => Some(CacheScope::Private),
+      "off" | "none" => Some(CacheScope::Off),
+      _ => None,
+    }
+  }
+
+  /// Returns the scope from `CHECK_CACHE_CONTROL`, public if it isn't set, or private when API keys are required
+  pub fn configured() -> CacheScope {
+    dotenv::var("CHECK_CACHE_CONTROL")
+      .ok()
+      .and_then(|x| CacheScope::from_name(&x))
+      .unwrap_or_else(|| {
+        if api_keys::keys_required() {
+          CacheScope::Private
+        } else {
+          CacheScope::Public
+        }
+      })
+  }
+}
+
+/// How long a response may be cached
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CachePolicy {
+  /// Seconds the response is fresh for
+  pub max_age: u64,
+  /// Seconds the response may be served stale for while it's revalidated
+  pub stale_while_revalidate: u64,
+}
+
+impl CachePolicy {
+  /// Returns the policy of a flag resolved to `settings`
+  pub fn from_settings(settings: &EffectiveSettings) -> CachePolicy {
+    CachePolicy {
+      max_age: settings.cache_ttl_seconds.value,
+      stale_while_revalidate: settings.stale_while_revalidate_seconds.value,
+    }
+  }
+
+  /// Returns the policy satisfying both policies, for responses covering several flags
+  pub fn shortest(self, other: CachePolicy) -> CachePolicy {
+    CachePolicy {
+      max_age: self.max_age.min(other.max_age),
+      stale_while_revalidate: self.stale_while_revalidate.min(other.stale_while_revalidate),
+    }
+  }
+
+  /// Value of the `Cache-Control` header for the policy, `None` if no header should be sent
+  pub fn header_value(&self, scope: CacheScope) -> Option<String> {
+    let scope = match scope {
+      CacheScope::Public => "public",
+      CacheScope::Private => "private",
+      CacheScope::Off => return None,
+    };
+
+    if self.max_age == 0 {
+      return Some("no-cache".to_string());
+    }
+
+    let mut value = format!("{}, max-age={}", scope, self.max_age);
+    if self.stale_while_revalidate > 0 {
+      value.push_str(&format!(", stale-while-revalidate={}", self.stale_while_revalidate));
+    }
+
+    Some(value)
+  }
+}
+
+/// Responder adding the `Cache-Control` header of `policy` to `response`
+///
+/// Responses also vary on `Accept-Case`, since it changes the body, and on `X-Api-Key` when API keys are required, since
+/// requests without a valid key are refused
+pub struct Cached<R> {
+  pub response: R,
+  pub policy: CachePolicy,
+}
+
+impl<R> Cached<R> {
+  pub fn new(response: R, policy: CachePolicy) -> Cached<R> {
+    Cached { response, policy }
+  }
+}
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for Cached<R> {
+  fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+    let mut response = self.response.respond_to(request)?;
+
+    if let Some(value) = self.policy.header_value(CacheScope::configured()) {
+      response.set_raw_header("Cache-Control", value);
+      response.adjoin_raw_header("Vary", ACCEPT_CASE);
+      if api_keys::keys_required() {
+        response.adjoin_raw_header("Vary", API_KEY_HEADER);
+      }
+    }
+
+    Ok(response)
+  }
+}
+
+impl<R: OpenApiResponderInner> OpenApiResponderInner for Cached<R> {
+  fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+    R::responses(gen)
+  }
+}
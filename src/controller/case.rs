@@ -0,0 +1,482 @@
+//! Naming policy of JSON fields in responses
+//!
+//! Fields are snake_case by default. Clients can ask for camelCase fields with the `Accept-Case: camel` header, and the
+//! default can be changed with the `JSON_CASE` environment variable (`snake` or `camel`). Only field names are renamed,
+//! keys of maps (e.g. flag names returned by `/check-all/...`) are returned as they are stored
+//!
+//! Request bodies accept both conventions, multi-word fields of request DTOs carry a camelCase `serde` alias
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::{serde_json, Json};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::response::OpenApiResponderInner;
+use serde::ser::{self, Serialize, Serializer};
+
+use crate::log;
+
+/// Header clients use to pick the case of response fields
+pub const ACCEPT_CASE: &str = "Accept-Case";
+
+/// Case of the fields of JSON responses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JsonCase {
+  /// `snake_case` fields, the names used in the code and the database
+  Snake,
+  /// `camelCase` fields
+  Camel,
+}
+
+impl JsonCase {
+  /// Parses a case from its name, accepting `snake`, `snake_case`, `camel` and `camelCase`
+  pub fn from_name(name: &str) -> Option<JsonCase> {
+    match name.trim().to_lowercase().as_str() {
+      "snake" | "snake_case" => Some(JsonCase::Snake),
+      "camel" | "camelcase" => Some(JsonCase::Camel),
+      _ => None,
+    }
+  }
+
+  /// Returns the case requested by the `Accept-Case` header, falling back to the configured default
+  pub fn from_request(request: &Request<'_>) -> JsonCase {
+    match request.headers().get_one(ACCEPT_CASE).and_then(JsonCase::from_name) {
+      Some(case) => case,
+      None => JsonCase::configured(),
+    }
+  }
+
+  /// Returns the default case from `JSON_CASE`, snake_case if it isn't set
+  pub fn configured() -> JsonCase {
+    dotenv::var("JSON_CASE")
+      .ok()
+      .and_then(|x| JsonCase::from_name(&x))
+      .unwrap_or(JsonCase::Snake)
+  }
+
+  /// Renames a snake_case field name to this case
+  fn rename(self, name: &'static str) -> &'static str {
+    if self == JsonCase::Snake || !name.trim_start_matches('_').contains('_') {
+      return name;
+    }
+
+    // Serializers need `'static` field names. Field names come from the code, so the set of renamed names is bounded
+    // and each is leaked once
+    static CAMEL_NAMES: OnceLock<Mutex<HashMap<&'static str, &'static str>>> = OnceLock::new();
+
+    let mut names = match CAMEL_NAMES.get_or_init(Default::default).lock() {
+      Ok(value) => value,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+
+    names
+      .entry(name)
+      .or_insert_with(|| Box::leak(to_camel_case(name).into_boxed_str()))
+  }
+}
+
+/// Converts a snake_case name to camelCase, keeping leading underscores (e.g. `_id`)
+pub fn to_camel_case(name: &str) -> String {
+  let trimmed = name.trim_start_matches('_');
+  let mut camel = name[..name.len() - trimmed.len()].to_string();
+  let mut upper = false;
+
+  for c in trimmed.chars() {
+    match c {
+      '_' => upper = true,
+      c if upper => {
+        camel.extend(c.to_uppercase());
+        upper = false;
+      }
+      c => camel.push(c),
+    }
+  }
+
+  camel
+}
+
+/// JSON responder following the field naming policy, use in place of `Json` for response bodies
+pub struct CasedJson<T>(pub T);
+
+impl<'r, T: Serialize> Responder<'r, 'static> for CasedJson<T> {
+  fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+    let case = JsonCase::from_request(request);
+    let mut body = Vec::new();
+
+    let cased = Cased { value: &self.0, case };
+    if let Err(e) = cased.serialize(&mut serde_json::Serializer::new(&mut body)) {
+      log!("Error serializing response. Error: {:?}", e);
+      return Err(Status::InternalServerError);
+    }
+
+    (ContentType::JSON, body).respond_to(request)
+  }
+}
+
+impl<T: Serialize + JsonSchema + Send> OpenApiResponderInner for CasedJson<T> {
+  fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+    Json::<T>::responses(gen)
+  }
+}
+
+/// Serializes `value` to JSON following the field naming policy, for bodies that aren't responses (e.g. WebSocket
+/// messages)
+pub fn to_json<T: Serialize + ?Sized>(value: &T, case: JsonCase) -> serde_json::Result<String> {
+  let mut body = Vec::new();
+  Cased { value, case }.serialize(&mut serde_json::Serializer::new(&mut body))?;
+
+  Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Wraps `value` so it's serialized with its struct fields renamed to `case`, for encodings other than JSON
+pub fn cased<T: Serialize + ?Sized>(value: &T, case: JsonCase) -> impl Serialize + '_ {
+  Cased { value, case }
+}
+
+/// Value serialized with its struct fields renamed to `case`
+struct Cased<'a, T: ?Sized> {
+  value: &'a T,
+  case: JsonCase,
+}
+
+impl<T: Serialize + ?Sized> Serialize for Cased<'_, T> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    self.value.serialize(CaseSerializer {
+      inner: serializer,
+      case: self.case,
+    })
+  }
+}
+
+/// Serializer renaming struct fields before handing them to the `inner` serializer
+///
+/// Everything nested is wrapped in `Cased` so the whole value is renamed, map keys are passed through untouched
+struct CaseSerializer<S> {
+  inner: S,
+  case: JsonCase,
+}
+
+impl<S> CaseSerializer<S> {
+  fn wrap<'a, T: ?Sized>(&self, value: &'a T) -> Cased<'a, T> {
+    Cased { value, case: self.case }
+  }
+}
+
+impl<S: Serializer> Serializer for CaseSerializer<S> {
+  type Ok = S::Ok;
+  type Error = S::Error;
+  type SerializeSeq = Compound<S::SerializeSeq>;
+  type SerializeTuple = Compound<S::SerializeTuple>;
+  type SerializeTupleStruct = Compound<S::SerializeTupleStruct>;
+  type SerializeTupleVariant = Compound<S::SerializeTupleVariant>;
+  type SerializeMap = Compound<S::SerializeMap>;
+  type SerializeStruct = Compound<S::SerializeStruct>;
+  type SerializeStructVariant = Compound<S::SerializeStructVariant>;
+
+  fn serialize_bool(self, v: bool) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_bool(v)
+  }
+
+  fn serialize_i8(self, v: i8) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_i8(v)
+  }
+
+  fn serialize_i16(self, v: i16) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_i16(v)
+  }
+
+  fn serialize_i32(self, v: i32) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_i32(v)
+  }
+
+  fn serialize_i64(self, v: i64) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_i64(v)
+  }
+
+  fn serialize_i128(self, v: i128) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_i128(v)
+  }
+
+  fn serialize_u8(self, v: u8) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_u8(v)
+  }
+
+  fn serialize_u16(self, v: u16) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_u16(v)
+  }
+
+  fn serialize_u32(self, v: u32) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_u32(v)
+  }
+
+  fn serialize_u64(self, v: u64) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_u64(v)
+  }
+
+  fn serialize_u128(self, v: u128) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_u128(v)
+  }
+
+  fn serialize_f32(self, v: f32) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_f32(v)
+  }
+
+  fn serialize_f64(self, v: f64) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_f64(v)
+  }
+
+  fn serialize_char(self, v: char) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_char(v)
+  }
+
+  fn serialize_str(self, v: &str) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_str(v)
+  }
+
+  fn serialize_bytes(self, v: &[u8]) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_bytes(v)
+  }
+
+  fn serialize_none(self) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_none()
+  }
+
+  fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<S::Ok, S::Error> {
+    let value = self.wrap(value);
+    self.inner.serialize_some(&value)
+  }
+
+  fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_unit()
+  }
+
+  fn serialize_unit_struct(self, name: &'static str) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_unit_struct(name)
+  }
+
+  fn serialize_unit_variant(
+    self,
+    name: &'static str,
+    variant_index: u32,
+    variant: &'static str,
+  ) -> Result<S::Ok, S::Error> {
+    self.inner.serialize_unit_variant(name, variant_index, variant)
+  }
+
+  fn serialize_newtype_struct<T: Serialize + ?Sized>(self, name: &'static str, value: &T) -> Result<S::Ok, S::Error> {
+    let value = self.wrap(value);
+    self.inner.serialize_newtype_struct(name, &value)
+  }
+
+  fn serialize_newtype_variant<T: Serialize + ?Sized>(
+    self,
+    name: &'static str,
+    variant_index: u32,
+    variant: &'static str,
+    value: &T,
+  ) -> Result<S::Ok, S::Error> {
+    let value = self.wrap(value);
+    self
+      .inner
+      .serialize_newtype_variant(name, variant_index, variant, &value)
+  }
+
+  fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+    let case = self.case;
+    Ok(Compound {
+      inner: self.inner.serialize_seq(len)?,
+      case,
+    })
+  }
+
+  fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+    let case = self.case;
+    Ok(Compound {
+      inner: self.inner.serialize_tuple(len)?,
+      case,
+    })
+  }
+
+  fn serialize_tuple_struct(self, name: &'static str, len: usize) -> Result<Self::SerializeTupleStruct, S::Error> {
+    let case = self.case;
+    Ok(Compound {
+      inner: self.inner.serialize_tuple_struct(name, len)?,
+      case,
+    })
+  }
+
+  fn serialize_tuple_variant(
+    self,
+    name: &'static str,
+    variant_index: u32,
+    variant: &'static str,
+    len: usize,
+  ) -> Result<Self::SerializeTupleVariant, S::Error> {
+    let case = self.case;
+    Ok(Compound {
+      inner: self.inner.serialize_tuple_variant(name, variant_index, variant, len)?,
+      case,
+    })
+  }
+
+  fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+    let case = self.case;
+    Ok(Compound {
+      inner: self.inner.serialize_map(len)?,
+      case,
+    })
+  }
+
+  fn serialize_struct(self, name: &'static str, len: usize) -> Result<Self::SerializeStruct, S::Error> {
+    let case = self.case;
+    Ok(Compound {
+      inner: self.inner.serialize_struct(name, len)?,
+      case,
+    })
+  }
+
+  fn serialize_struct_variant(
+    self,
+    name: &'static str,
+    variant_index: u32,
+    variant: &'static str,
+    len: usize,
+  ) -> Result<Self::SerializeStructVariant, S::Error> {
+    let case = self.case;
+    Ok(Compound {
+      inner: self.inner.serialize_struct_variant(name, variant_index, variant, len)?,
+      case,
+    })
+  }
+
+  fn is_human_readable(&self) -> bool {
+    self.inner.is_human_readable()
+  }
+}
+
+/// Compound serializer wrapping the elements of a sequence, map or struct in `Cased`
+struct Compound<C> {
+  inner: C,
+  case: JsonCase,
+}
+
+impl<C> Compound<C> {
+  fn wrap<'a, T: ?Sized>(&self, value: &'a T) -> Cased<'a, T> {
+    Cased { value, case: self.case }
+  }
+}
+
+impl<C: ser::SerializeSeq> ser::SerializeSeq for Compound<C> {
+  type Ok = C::Ok;
+  type Error = C::Error;
+
+  fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+    let value = self.wrap(value);
+    self.inner.serialize_element(&value)
+  }
+
+  fn end(self) -> Result<C::Ok, C::Error> {
+    self.inner.end()
+  }
+}
+
+impl<C: ser::SerializeTuple> ser::SerializeTuple for Compound<C> {
+  type Ok = C::Ok;
+  type Error = C::Error;
+
+  fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+    let value = self.wrap(value);
+    self.inner.serialize_element(&value)
+  }
+
+  fn end(self) -> Result<C::Ok, C::Error> {
+    self.inner.end()
+  }
+}
+
+impl<C: ser::SerializeTupleStruct> ser::SerializeTupleStruct for Compound<C> {
+  type Ok = C::Ok;
+  type Error = C::Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+    let value = self.wrap(value);
+    self.inner.serialize_field(&value)
+  }
+
+  fn end(self) -> Result<C::Ok, C::Error> {
+    self.inner.end()
+  }
+}
+
+impl<C: ser::SerializeTupleVariant> ser::SerializeTupleVariant for Compound<C> {
+  type Ok = C::Ok;
+  type Error = C::Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+    let value = self.wrap(value);
+    self.inner.serialize_field(&value)
+  }
+
+  fn end(self) -> Result<C::Ok, C::Error> {
+    self.inner.end()
+  }
+}
+
+impl<C: ser::SerializeMap> ser::SerializeMap for Compound<C> {
+  type Ok = C::Ok;
+  type Error = C::Error;
+
+  fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), C::Error> {
+    self.inner.serialize_key(key)
+  }
+
+  fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+    let value = self.wrap(value);
+    self.inner.serialize_value(&value)
+  }
+
+  fn end(self) -> Result<C::Ok, C::Error> {
+    self.inner.end()
+  }
+}
+
+impl<C: ser::SerializeStruct> ser::SerializeStruct for Compound<C> {
+  type Ok = C::Ok;
+  type Error = C::Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), C::Error> {
+    let value = self.wrap(value);
+    self.inner.serialize_field(self.case.rename(key), &value)
+  }
+
+  fn skip_field(&mut self, key: &'static str) -> Result<(), C::Error> {
+    self.inner.skip_field(self.case.rename(key))
+  }
+
+  fn end(self) -> Result<C::Ok, C::Error> {
+    self.inner.end()
+  }
+}
+
+impl<C: ser::SerializeStructVariant> ser::SerializeStructVariant for Compound<C> {
+  type Ok = C::Ok;
+  type Error = C::Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), C::Error> {
+    let value = self.wrap(value);
+    self.inner.serialize_field(self.case.rename(key), &value)
+  }
+
+  fn skip_field(&mut self, key: &'static str) -> Result<(), C::Error> {
+    self.inner.skip_field(self.case.rename(key))
+  }
+
+  fn end(self) -> Result<C::Ok, C::Error> {
+    self.inner.end()
+  }
+}
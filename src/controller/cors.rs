@@ -0,0 +1,108 @@
+//! CORS of the `/check` routes, so web apps can check flags straight from the browser with a client API key
+//!
+//! Only the `/check` routes are opened up, the rest of the API stays same-origin. Browsers send a preflight `OPTIONS`
+//! request before sending the `X-Api-Key` header, which is answered 204 with the allowed methods and headers
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Method, Status};
+use rocket::{Request, Response};
+
+use crate::controller::api_keys::API_KEY_HEADER;
+use crate::controller::request_id::REQUEST_ID;
+use crate::controller::versioning::API_V1;
+
+/// Prefixes of the paths of the `/check` routes
+const CHECK_PATHS: [&str; 2] = ["/check/", "/check-all/"];
+
+/// How long browsers can cache the answer of a preflight request, in seconds
+const PREFLIGHT_MAX_AGE: &str = "600";
+
+/// Fairing adding CORS headers to the responses of the `/check` routes
+///
+/// Origins are limited to `CHECK_ALLOWED_ORIGINS`, a comma separated list (e.g.
+/// `https://app.example.com,https://example.com`), and any origin is allowed when it isn't set since client keys are
+/// public anyway. Credentials (the login cookies) are never allowed, checks are authorized by their API key
+pub struct CheckCors {
+  /// Allowed origins, `None` if any origin is allowed
+  origins: Option<Vec<String>>,
+}
+
+impl CheckCors {
+  /// Constructs a `CheckCors` from `CHECK_ALLOWED_ORIGINS`
+  pub fn from_env() -> CheckCors {
+    let origins = match dotenv::var("CHECK_ALLOWED_ORIGINS") {
+      Ok(value) if !value.trim().is_empty() => Some(
+        value
+          .split(',')
+          .map(|x| x.trim().trim_end_matches('/').to_string())
+          .filter(|x| !x.is_empty())
+          .collect(),
+      ),
+      _ => None,
+    };
+
+    CheckCors { origins }
+  }
+
+  /// If the request is for a `/check` route
+  fn is_check(request: &Request<'_>) -> bool {
+    let path = request.uri().path();
+    let path = path.as_str();
+    let path = path.strip_prefix(API_V1).unwrap_or(path);
+
+    CHECK_PATHS.iter().any(|x| path.starts_with(x))
+  }
+
+  /// Returns the `Access-Control-Allow-Origin` of a request from `origin`, `None` if the origin isn't allowed
+  fn allowed_origin(&self, origin: &str) -> Option<String> {
+    match &self.origins {
+      None => Some("*".to_string()),
+      Some(origins) => origins.iter().find(|x| *x == origin).cloned(),
+    }
+  }
+}
+
+#[rocket::async_trait]
+impl Fairing for CheckCors {
+  fn info(&self) -> Info {
+    Info {
+      name: "Check CORS",
+      kind: Kind::Response,
+    }
+  }
+
+  async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+    if !CheckCors::is_check(request) {
+      return;
+    }
+
+    if self.origins.is_some() {
+      response.set_header(Header::new("Vary", "Origin"));
+    }
+
+    let allowed_origin = match request.headers().get_one("Origin") {
+      Some(origin) => self.allowed_origin(origin),
+      None => return,
+    };
+    let allowed_origin = match allowed_origin {
+      Some(value) => value,
+      None => return,
+    };
+
+    response.set_header(Header::new("Access-Control-Allow-Origin", allowed_origin));
+    response.set_header(Header::new("Access-Control-Expose-Headers", REQUEST_ID));
+
+    // Rocket has no routes for preflight requests, so they're answered here instead of the 404 they'd get
+    if request.method() == Method::Options {
+      response.set_status(Status::NoContent);
+      response.set_sized_body(0, std::io::Cursor::new(""));
+      response.remove_header("Content-Type");
+      response.set_header(Header::new("Access-Control-Allow-Methods", "GET, POST, OPTIONS"));
+      response.set_header(Header::new(
+        "Access-Control-Allow-Headers",
+        format!("{}, Content-Type, Accept", API_KEY_HEADER),
+      ));
+      response.set_header(Header::new("Access-Control-Max-Age", PREFLIGHT_MAX_AGE));
+    }
+  }
+}
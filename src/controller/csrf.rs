@@ -0,0 +1,111 @@
+//! CSRF protection of requests authenticated by the login cookies, with double-submit tokens
+//!
+//! Logging in sets the `csrf_token` cookie, readable by the frontend but never sent by other sites' scripts. Requests
+//! changing something with the login cookies must echo it in the `X-CSRF-Token` header, which a malicious site can't
+//! read and so can't forge. Requests sending a bearer token, service account token or API key aren't checked, browsers
+//! never attach those on their own
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Cookie, CookieJar, Method, SameSite};
+use rocket::{Data, Request};
+
+use crate::controller::api_keys;
+use crate::controller::authentication::{AUTH_TOKEN, USER_ID};
+use crate::controller::versioning::API_V1;
+
+/// Cookie holding the CSRF token of the login, readable by the frontend
+pub const CSRF_COOKIE: &str = "csrf_token";
+
+/// Header requests echo the CSRF token in
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Path requests are rewritten to when their CSRF token is missing or wrong
+pub const CSRF_FORBIDDEN_PATH: &str = "/__csrf_forbidden";
+
+/// Writes that don't need a token: flag checks, which don't use the login cookies, logging in, where there's no token
+/// yet, and the SAML IdP's cross-site POST
+const EXEMPT_WRITES: [&str; 4] = ["/check", "/login", "/setup", "/saml/acs"];
+
+/// Sets a new CSRF token cookie, called when a user logs in
+pub fn set_token(jar: &CookieJar<'_>) {
+  let token: String = rand::random::<[u8; 32]>()
+    .iter()
+    .map(|x| format!("{:02x}", x))
+    .collect();
+
+  jar.add(
+    Cookie::build(CSRF_COOKIE, token)
+      .path("/")
+      .same_site(SameSite::Strict)
+      .http_only(false)
+      .finish(),
+  );
+}
+
+/// Removes the CSRF token cookie, called when a user logs out
+pub fn remove_token(jar: &CookieJar<'_>) {
+  jar.remove(Cookie::build(CSRF_COOKIE, "").path("/").finish());
+}
+
+/// Fairing refusing requests that change something with the login cookies but without their CSRF token
+pub struct CsrfGuard;
+
+impl CsrfGuard {
+  /// If the request must send the CSRF token: it changes something, and is authenticated by the login cookies only
+  fn requires_token(request: &Request<'_>) -> bool {
+    if matches!(request.method(), Method::Get | Method::Head | Method::Options) {
+      return false;
+    }
+
+    let path = request.uri().path();
+    let path = path.as_str();
+    let path = path.strip_prefix(API_V1).unwrap_or(path);
+    if EXEMPT_WRITES
+      .iter()
+      .any(|x| path == *x || path.starts_with(&format!("{}/", x)))
+    {
+      return false;
+    }
+
+    let headers = request.headers();
+    if headers.contains("Authorization") || headers.contains(api_keys::API_KEY_HEADER) {
+      return false;
+    }
+
+    let cookies = request.cookies();
+    cookies.get_private(USER_ID).is_some() || cookies.get_private(AUTH_TOKEN).is_some()
+  }
+
+  /// If the request echoes the token of its CSRF cookie
+  fn has_token(request: &Request<'_>) -> bool {
+    let cookie = match request.cookies().get(CSRF_COOKIE) {
+      Some(cookie) if !cookie.value().is_empty() => cookie.value().to_string(),
+      _ => return false,
+    };
+
+    // Compares hashes so the time taken doesn't hint at how much of the token matched
+    match request.headers().get_one(CSRF_HEADER) {
+      Some(header) => api_keys::hash(header.trim()) == api_keys::hash(&cookie),
+      None => false,
+    }
+  }
+}
+
+#[rocket::async_trait]
+impl Fairing for CsrfGuard {
+  fn info(&self) -> Info {
+    Info {
+      name: "CSRF Guard",
+      kind: Kind::Request,
+    }
+  }
+
+  async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+    // Fairings can't respond directly, so refused requests are routed to the CSRF forbidden route instead
+    if CsrfGuard::requires_token(request) && !CsrfGuard::has_token(request) {
+      request.set_method(Method::Get);
+      request.set_uri(Origin::parse(CSRF_FORBIDDEN_PATH).expect("valid CSRF forbidden path"));
+    }
+  }
+}
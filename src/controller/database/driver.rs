@@ -0,0 +1,359 @@
+//! Interface every storage backend implements, so `ConnectionManager` doesn't depend on any one database
+//!
+//! IDs are passed as strings, each driver parses them into its own ID type. An ID the driver can't parse doesn't
+//! belong to anything it stored, so it's treated like an ID that isn't found rather than an error
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use tokio::sync::mpsc;
+
+use crate::controller::database::error::{StorageError, StorageResult};
+use crate::controller::database::{AuditFilter, FlagFilter, FlagProjection, Page};
+use crate::model::api_key::ApiKey;
+use crate::model::audit::AuditEntry;
+use crate::model::backup::Backup;
+use crate::model::comment::Comment;
+use crate::model::flag::{FeatureFlag, FeatureFlagBuilder};
+use crate::model::plugin::Plugin;
+use crate::model::product::{Product, ProductBuilder};
+use crate::model::schedule::ChangedField;
+use crate::model::service_account::ServiceAccount;
+use crate::model::session::Session;
+use crate::model::stats::Stats;
+use crate::model::user::{AccountType, User, UserBuilder};
+use crate::model::webhook::{DeliveryStatus, Webhook, WebhookDelivery};
+
+/// Change to a flag or product read back from the database, written by any instance
+#[derive(Debug)]
+pub enum StoredChange {
+  /// The flag was created or changed
+  FlagUpdated {
+    flag: Box<FeatureFlag>,
+    /// Name the flag had before the change, if it was renamed
+    previous_name: Option<String>,
+  },
+  /// The flag was deleted
+  FlagDeleted {
+    flag_id: String,
+    /// Unique ID of the product the flag belonged to
+    product_id: String,
+    /// Name the flag had
+    name: String,
+  },
+  /// The product was created or changed
+  ProductUpdated(Box<Product>),
+  /// The product was deleted
+  ProductDeleted { product_id: String },
+}
+
+/// Storage backend of the service
+///
+/// Drivers only read and write, converting the errors of their database into `StorageError`s. `ConnectionManager` logs
+/// the errors and publishes the changes drivers make
+#[rocket::async_trait]
+pub trait DatabaseDriver: Send + Sync {
+  /// Returns the product named `product_name`
+  async fn get_product(&self, product_name: &str) -> StorageResult<Option<Product>>;
+
+  /// Returns the product of ID `product_id`
+  async fn get_product_by_id(&self, product_id: &str) -> StorageResult<Option<Product>>;
+
+  /// Returns every product, or only those consumed by the user of ID `user_id`, in `page`
+  async fn get_products(&self, user_id: Option<String>, page: Page) -> StorageResult<Vec<Product>>;
+
+  /// Returns the flag named `flag_name` of the product of ID `product_id`
+  async fn get_feature_flag(
+    &self,
+    product_id: &str,
+    flag_name: &str,
+    projection: FlagProjection,
+  ) -> StorageResult<Option<FeatureFlag>>;
+
+  /// Returns the flag of ID `flag_id`
+  async fn get_feature_flag_by_id(
+    &self,
+    flag_id: &str,
+    projection: FlagProjection,
+  ) -> StorageResult<Option<FeatureFlag>>;
+
+  /// Returns the flags of the product of ID `product_id` matching `filter`
+  async fn get_feature_flags(
+    &self,
+    product_id: &str,
+    filter: FlagFilter<'_>,
+    projection: FlagProjection,
+  ) -> StorageResult<Vec<FeatureFlag>>;
+
+  /// Returns the flags, across every product, maintained by the user of ID `user_id` and matching `filter`
+  async fn get_feature_flags_by_maintainer(
+    &self,
+    user_id: &str,
+    filter: FlagFilter<'_>,
+  ) -> StorageResult<Vec<FeatureFlag>>;
+
+  /// Returns the flags of the given `(product_id, flag_name)` keys
+  async fn get_feature_flags_by_keys(
+    &self,
+    keys: &[(String, String)],
+    projection: FlagProjection,
+  ) -> StorageResult<Vec<FeatureFlag>>;
+
+  /// Replaces the flag of ID `feature_flag_id` with `updated`, `false` if the ID isn't valid
+  async fn update_feature_flag(&self, feature_flag_id: &str, updated: FeatureFlag) -> StorageResult<bool>;
+
+  /// Deletes the flag of ID `feature_flag_id` and its comments, returning the deleted flag
+  async fn delete_feature_flag(&self, feature_flag_id: &str) -> StorageResult<Option<FeatureFlag>>;
+
+  /// Hoists (`enabled`) or lowers the flag of ID `feature_flag_id` atomically, globally for no `user_id` and otherwise
+  /// for the user only (see `FeatureFlag::hoist`), setting its `updated_at`. Returns the updated flag
+  async fn set_feature_flag_enabled(
+    &self,
+    feature_flag_id: &str,
+    user_id: Option<&str>,
+    enabled: bool,
+    updated_at: DateTime<Utc>,
+  ) -> StorageResult<Option<FeatureFlag>>;
+
+  /// Replaces the product of ID `product_id` with `updated`, `false` if the ID isn't valid
+  async fn update_product(&self, product_id: &str, updated: Product) -> StorageResult<bool>;
+
+  /// Adds a user to the users of the product of ID `product_id`, returning the updated product
+  async fn add_product_user(&self, product_id: &str, user_id: &str) -> StorageResult<Option<Product>>;
+
+  /// Removes a user from the users of the product of ID `product_id`, returning the updated product
+  async fn remove_product_user(&self, product_id: &str, user_id: &str) -> StorageResult<Option<Product>>;
+
+  /// Deletes the product of ID `product_id`, its flags of IDs `flag_ids`, their comments, its plugins, and its API keys.
+  /// Returns if a product was deleted
+  async fn delete_product(&self, product_id: &str, flag_ids: &[String]) -> StorageResult<bool>;
+
+  /// Applies an import to the product of ID `product_id`, all of it or none of it: replaces the product with `product`
+  /// if given, replaces the flags of `updated` by their IDs, and stores the flags of `created`. Returns the created flags
+  /// with their generated IDs, in order
+  async fn import_product(
+    &self,
+    product_id: &str,
+    product: Option<Product>,
+    updated: Vec<FeatureFlag>,
+    created: Vec<FeatureFlagBuilder>,
+  ) -> StorageResult<Vec<FeatureFlag>>;
+
+  /// Replaces the user of ID `user_id` with `updated`, `false` if the ID isn't valid
+  async fn update_user(&self, user_id: &str, updated: User) -> StorageResult<bool>;
+
+  /// Returns the user of email `user_email` and/or ID `user_id`
+  async fn get_user(&self, user_email: Option<&str>, user_id: Option<&str>) -> StorageResult<Option<User>>;
+
+  /// Returns every user, or only those of `account_type`, in `page`, without their password hash
+  async fn get_users(&self, account_type: Option<AccountType>, page: Page) -> StorageResult<Vec<User>>;
+
+  /// Returns the users of IDs `user_ids`, without their password hash
+  async fn get_users_by_ids(&self, user_ids: &[String]) -> StorageResult<Vec<User>>;
+
+  /// Counts the users
+  async fn count_users(&self) -> StorageResult<u64>;
+
+  /// Stores a new product, returning it with its generated ID
+  async fn create_product(&self, product_builder: ProductBuilder) -> StorageResult<Product>;
+
+  /// Stores a new flag, returning it with its generated ID
+  async fn create_flag(&self, flag_builder: FeatureFlagBuilder) -> StorageResult<FeatureFlag>;
+
+  /// Stores new flags in a single write, returning each of them, or `None` if it failed to be stored, in order
+  async fn create_flags(&self, flag_builders: Vec<FeatureFlagBuilder>) -> StorageResult<Vec<Option<FeatureFlag>>>;
+
+  /// Stores a new user, returning it with its generated ID
+  async fn create_user(&self, user_builder: UserBuilder) -> StorageResult<User>;
+
+  /// Stores an entry of the audit log
+  async fn create_audit_entry(&self, entry: AuditEntry) -> StorageResult<()>;
+
+  /// Stores a new comment, returning it with its generated ID
+  async fn create_comment(&self, comment: Comment) -> StorageResult<Comment>;
+
+  /// Returns the comments on the flag of ID `flag_id`, oldest first
+  async fn get_comments(&self, flag_id: &str) -> StorageResult<Vec<Comment>>;
+
+  /// Stores a new plugin, returning it with its generated ID
+  async fn create_plugin(&self, plugin: Plugin) -> StorageResult<Plugin>;
+
+  /// Returns the plugin of ID `plugin_id`
+  async fn get_plugin(&self, plugin_id: &str) -> StorageResult<Option<Plugin>>;
+
+  /// Stores a new webhook, returning it with its generated ID
+  async fn create_webhook(&self, webhook: Webhook) -> StorageResult<Webhook>;
+
+  /// Returns the webhook of ID `webhook_id`
+  async fn get_webhook(&self, webhook_id: &str) -> StorageResult<Option<Webhook>>;
+
+  /// Returns every webhook, or only those of the product of ID `product_id`
+  async fn get_webhooks(&self, product_id: Option<&str>) -> StorageResult<Vec<Webhook>>;
+
+  /// Replaces the webhook of ID `webhook_id` with `updated`, `false` if the ID isn't valid
+  async fn update_webhook(&self, webhook_id: &str, updated: Webhook) -> StorageResult<bool>;
+
+  /// Deletes the webhook of ID `webhook_id` and its deliveries, returning if a webhook was deleted
+  async fn delete_webhook(&self, webhook_id: &str) -> StorageResult<bool>;
+
+  /// Stores a new API key, returning it with its generated ID
+  async fn create_api_key(&self, api_key: ApiKey) -> StorageResult<ApiKey>;
+
+  /// Returns the API key of ID `api_key_id`
+  async fn get_api_key(&self, api_key_id: &str) -> StorageResult<Option<ApiKey>>;
+
+  /// Returns the API key of hash `key_hash`, revoked or not
+  async fn get_api_key_by_hash(&self, key_hash: &str) -> StorageResult<Option<ApiKey>>;
+
+  /// Returns every API key of the product of ID `product_id`
+  async fn get_api_keys(&self, product_id: &str) -> StorageResult<Vec<ApiKey>>;
+
+  /// Revokes the API key of ID `api_key_id` at `revoked_at`, returning if it wasn't already revoked
+  async fn revoke_api_key(&self, api_key_id: &str, revoked_at: DateTime<Utc>) -> StorageResult<bool>;
+
+  /// Creates the indexes of API keys
+  async fn create_api_key_indexes(&self) -> StorageResult<()>;
+
+  /// Stores a new service account, returning it with its generated ID
+  async fn create_service_account(&self, service_account: ServiceAccount) -> StorageResult<ServiceAccount>;
+
+  /// Returns the service account of ID `service_account_id`
+  async fn get_service_account(&self, service_account_id: &str) -> StorageResult<Option<ServiceAccount>>;
+
+  /// Returns the service account of token hash `token_hash`, revoked or not
+  async fn get_service_account_by_hash(&self, token_hash: &str) -> StorageResult<Option<ServiceAccount>>;
+
+  /// Returns every service account
+  async fn get_service_accounts(&self) -> StorageResult<Vec<ServiceAccount>>;
+
+  /// Revokes the service account of ID `service_account_id` at `revoked_at`, returning if it wasn't already revoked
+  async fn revoke_service_account(&self, service_account_id: &str, revoked_at: DateTime<Utc>) -> StorageResult<bool>;
+
+  /// Creates the indexes of service accounts
+  async fn create_service_account_indexes(&self) -> StorageResult<()>;
+
+  /// Stores a new session, returning it with its generated ID
+  async fn create_session(&self, session: Session) -> StorageResult<Session>;
+
+  /// Returns the unexpired session of the user of ID `user_id` and token hash `token_hash`
+  async fn get_session(&self, user_id: &str, token_hash: &str) -> StorageResult<Option<Session>>;
+
+  /// Moves the expiry of the unexpired session of the user and token hash to `expires_at`, returning it
+  async fn refresh_session(
+    &self,
+    user_id: &str,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+  ) -> StorageResult<Option<Session>>;
+
+  /// Deletes the unexpired session of the user and token hash, returning if one was deleted
+  async fn delete_session(&self, user_id: &str, token_hash: &str) -> StorageResult<bool>;
+
+  /// Deletes every session of the user of ID `user_id`, returning how many unexpired ones were deleted
+  async fn delete_sessions(&self, user_id: &str) -> StorageResult<u64>;
+
+  /// Creates the indexes of sessions
+  async fn create_session_indexes(&self) -> StorageResult<()>;
+
+  /// Stores a new webhook delivery
+  async fn create_webhook_delivery(&self, delivery: &WebhookDelivery) -> StorageResult<()>;
+
+  /// Replaces the stored state of a webhook delivery
+  async fn update_webhook_delivery(&self, delivery: &WebhookDelivery) -> StorageResult<()>;
+
+  /// Returns the webhook delivery of ID `delivery_id`
+  async fn get_webhook_delivery(&self, delivery_id: &str) -> StorageResult<Option<WebhookDelivery>>;
+
+  /// Returns up to `limit` deliveries to the webhook of ID `webhook_id`, newest first, optionally only those in
+  /// `status`
+  async fn get_webhook_deliveries(
+    &self,
+    webhook_id: &str,
+    status: Option<DeliveryStatus>,
+    limit: i64,
+  ) -> StorageResult<Vec<WebhookDelivery>>;
+
+  /// Returns the flags with scheduled changes due at `now`
+  async fn get_feature_flags_with_due_changes(&self, now: DateTime<Utc>) -> StorageResult<Vec<FeatureFlag>>;
+
+  /// Sets `field` of the flag of ID `feature_flag_id` and removes its scheduled change of ID `change_id` atomically, only
+  /// if the change is still scheduled, setting its `updated_at`. Returns the updated flag, `None` if the flag isn't
+  /// found or the change was already applied (e.g. by another instance)
+  async fn apply_scheduled_change(
+    &self,
+    feature_flag_id: &str,
+    change_id: ObjectId,
+    field: &ChangedField,
+    updated_at: DateTime<Utc>,
+  ) -> StorageResult<Option<FeatureFlag>>;
+
+  /// Returns up to `limit` audit entries matching `filter`, newest first, starting after the entry of ID `after`.
+  /// `None` if `after` isn't a valid ID
+  async fn get_audit_entries(
+    &self,
+    filter: &AuditFilter,
+    after: Option<&str>,
+    limit: i64,
+  ) -> StorageResult<Option<Vec<AuditEntry>>>;
+
+  /// Returns up to `limit` flags whose name starts with `term`, ignoring case, with the fields of
+  /// `FlagProjection::Summary`
+  async fn search_feature_flags(&self, term: &str, limit: i64) -> StorageResult<Vec<FeatureFlag>>;
+
+  /// Returns up to `limit` products whose name starts with `term`, ignoring case
+  async fn search_products(&self, term: &str, limit: i64) -> StorageResult<Vec<Product>>;
+
+  /// Returns up to `limit` users whose email starts with `term`, ignoring case, without their password hash
+  async fn search_users(&self, term: &str, limit: i64) -> StorageResult<Vec<User>>;
+
+  /// Creates the indexes backing `/search`
+  async fn create_search_indexes(&self) -> StorageResult<()>;
+
+  /// Checks the database is reachable
+  async fn ping(&self) -> StorageResult<()>;
+
+  /// Creates the indexes backing the audit log filters
+  async fn create_audit_indexes(&self) -> StorageResult<()>;
+
+  /// Adds evaluations made during the hour starting at `hour` to the hourly counts of their products
+  async fn add_evaluation_counts(&self, hour: DateTime<Utc>, counts: &HashMap<String, u64>) -> StorageResult<()>;
+
+  /// Creates the indexes of the hourly evaluation counts, which expire after `retention`
+  async fn create_evaluation_count_indexes(&self, retention: Duration) -> StorageResult<()>;
+
+  /// Counts the products, flags, users, logged in sessions, and the evaluations made since `since`, without loading
+  /// every document when the database can count them itself
+  async fn aggregate_counts(&self, since: DateTime<Utc>) -> StorageResult<Stats>;
+
+  /// Reads every stored document for a backup, users with their password hash
+  async fn export_backup(&self) -> StorageResult<Backup>;
+
+  /// Writes every document of `backup`, replacing the stored documents with the same IDs and keeping the others
+  async fn restore_backup(&self, backup: &Backup) -> StorageResult<()>;
+
+  /// Applies the data migrations that haven't been yet, in order, returning the IDs of the ones applied
+  ///
+  /// Databases that never stored documents of older versions have nothing to migrate
+  async fn run_migrations(&self) -> StorageResult<Vec<String>> {
+    Ok(Vec::new())
+  }
+
+  /// Reverts the last data migration applied, returning its ID, `None` if no migration was applied
+  async fn revert_last_migration(&self) -> StorageResult<Option<String>> {
+    Ok(None)
+  }
+
+  /// If the database can tell about changes written by every instance, see `watch_changes`
+  fn watches_changes(&self) -> bool {
+    false
+  }
+
+  /// Sends the changes to flags and products written by every instance to `changes`, until it's closed
+  ///
+  /// Returns an error if changes can't be watched anymore, right away for databases that don't watch changes
+  async fn watch_changes(&self, _changes: mpsc::Sender<StoredChange>) -> StorageResult<()> {
+    Err(StorageError::Connection("changes aren't watched".to_string()))
+  }
+}
@@ -0,0 +1,47 @@
+//! Errors of storage operations, the same for every driver
+//!
+//! Drivers convert their database's errors into a `StorageError`, and routes respond to it with the status of its kind,
+//! see `From<StorageError> for ApiError`
+
+use std::fmt;
+
+/// Error of a storage operation
+#[derive(Clone, Debug)]
+pub enum StorageError {
+  /// The document the operation is on doesn't exist
+  NotFound,
+  /// The operation conflicts with a document already stored (e.g. a duplicate unique key)
+  Conflict(String),
+  /// The database couldn't be reached, or failed to run the operation
+  Connection(String),
+  /// A document couldn't be converted to or from its stored form
+  Serialization(String),
+}
+
+impl StorageError {
+  /// Machine readable kind of the error (e.g. `connection`)
+  pub fn kind(&self) -> &'static str {
+    match self {
+      StorageError::NotFound => "not_found",
+      StorageError::Conflict(_) => "conflict",
+      StorageError::Connection(_) => "connection",
+      StorageError::Serialization(_) => "serialization",
+    }
+  }
+}
+
+impl fmt::Display for StorageError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      StorageError::NotFound => write!(f, "not found"),
+      StorageError::Conflict(message) => write!(f, "conflict: {}", message),
+      StorageError::Connection(message) => write!(f, "connection: {}", message),
+      StorageError::Serialization(message) => write!(f, "serialization: {}", message),
+    }
+  }
+}
+
+impl std::error::Error for StorageError {}
+
+/// Result of a storage operation
+pub type StorageResult<T> = Result<T, StorageError>;
@@ -0,0 +1,1201 @@
+//! In-memory storage backend, for running the service and its tests without a database
+//!
+//! Selected with `DATABASE_CONNECTION_TYPE=memory`. Documents are kept in maps ordered by ID, which are generated in
+//! insertion order like MongoDB's, so lists come back in the same order. Everything is lost when the service stops and
+//! creating indexes does nothing
+//!
+//! `DATABASE_CONNECTION_TYPE=file` keeps the documents in memory too, but saves them to the JSON file at
+//! `DATABASE_FILE` (`feature-flags.json` by default) after every change and loads them from it on start, so local
+//! installs keep their data across restarts without a database. The file holds a backup (see `--export-backup`) with
+//! the logged in sessions, and is rewritten whole by every change, so it's only meant for a single instance with little
+//! data
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use evaluation::release::ReleaseType;
+use mongodb::bson::oid::ObjectId;
+use rocket::serde::json::serde_json;
+use serde::{Deserialize, Serialize};
+
+use crate::controller::database::driver::DatabaseDriver;
+use crate::controller::database::error::{StorageError, StorageResult};
+use crate::controller::database::{
+  AuditFilter, FlagFilter, FlagProjection, FlagSort, FlagSortField, Page, ReleaseKind,
+};
+use crate::log;
+use crate::model::api_key::ApiKey;
+use crate::model::audit::AuditEntry;
+use crate::model::backup::{Backup, BACKUP_VERSION};
+use crate::model::comment::Comment;
+use crate::model::flag::{FeatureFlag, FeatureFlagBuilder};
+use crate::model::plugin::Plugin;
+use crate::model::product::{Product, ProductBuilder};
+use crate::model::schedule::ChangedField;
+use crate::model::service_account::ServiceAccount;
+use crate::model::session::Session;
+use crate::model::stats::Stats;
+use crate::model::user::{AccountType, User, UserBuilder};
+use crate::model::webhook::{DeliveryStatus, Webhook, WebhookDelivery};
+
+/// Every collection of the in-memory database, keyed by ID
+#[derive(Default)]
+struct Collections {
+  products: BTreeMap<ObjectId, Product>,
+  flags: BTreeMap<ObjectId, FeatureFlag>,
+  users: BTreeMap<ObjectId, User>,
+  comments: BTreeMap<ObjectId, Comment>,
+  plugins: BTreeMap<ObjectId, Plugin>,
+  webhooks: BTreeMap<ObjectId, Webhook>,
+  deliveries: BTreeMap<ObjectId, WebhookDelivery>,
+  api_keys: BTreeMap<ObjectId, ApiKey>,
+  service_accounts: BTreeMap<ObjectId, ServiceAccount>,
+  sessions: BTreeMap<ObjectId, Session>,
+  audit: BTreeMap<ObjectId, AuditEntry>,
+  /// Evaluations by product ID and the hour they were made in
+  evaluation_counts: HashMap<(String, DateTime<Utc>), u64>,
+}
+
+impl Collections {
+  /// Returns a backup of every collection backups include
+  fn backup(&self) -> Backup {
+    Backup {
+      version: BACKUP_VERSION,
+      created_at: Utc::now(),
+      products: self.products.values().cloned().collect(),
+      flags: self.flags.values().cloned().collect(),
+      users: self.users.values().cloned().collect(),
+      comments: self.comments.values().cloned().collect(),
+      plugins: self.plugins.values().cloned().collect(),
+      webhooks: self.webhooks.values().cloned().collect(),
+      deliveries: self.deliveries.values().cloned().collect(),
+      api_keys: self.api_keys.values().cloned().collect(),
+      service_accounts: self.service_accounts.values().cloned().collect(),
+      audit: self.audit.values().cloned().collect(),
+    }
+  }
+
+  /// Inserts the documents of `backup`, replacing the ones with the same IDs
+  fn insert_backup(&mut self, backup: &Backup) {
+    restore(&mut self.products, &backup.products, |x| x.oid);
+    restore(&mut self.flags, &backup.flags, |x| x.oid);
+    restore(&mut self.users, &backup.users, |x| x.oid);
+    restore(&mut self.comments, &backup.comments, |x| x.oid);
+    restore(&mut self.plugins, &backup.plugins, |x| x.oid);
+    restore(&mut self.webhooks, &backup.webhooks, |x| x.oid);
+    restore(&mut self.deliveries, &backup.deliveries, |x| Some(x.oid));
+    restore(&mut self.api_keys, &backup.api_keys, |x| x.oid);
+    restore(&mut self.service_accounts, &backup.service_accounts, |x| x.oid);
+    restore(&mut self.audit, &backup.audit, |x| x.oid);
+  }
+}
+
+/// Contents of the file of `DATABASE_CONNECTION_TYPE=file`, a backup with the logged in sessions
+#[derive(Serialize, Deserialize)]
+struct SavedCollections {
+  #[serde(flatten)]
+  backup: Backup,
+  #[serde(default)]
+  sessions: Vec<Session>,
+}
+
+/// `DatabaseDriver` keeping everything in memory
+#[derive(Default)]
+pub struct MemoryDriver {
+  collections: RwLock<Collections>,
+  /// File every change is saved to, `None` to only keep everything in memory
+  file: Option<PathBuf>,
+}
+
+impl MemoryDriver {
+  /// Constructs an empty `MemoryDriver`
+  pub fn new() -> MemoryDriver {
+    MemoryDriver::default()
+  }
+
+  /// Constructs a `MemoryDriver` saving everything to the file at `DATABASE_FILE`, see `from_file`
+  pub fn from_env() -> Result<MemoryDriver, String> {
+    let path = match dotenv::var("DATABASE_FILE") {
+      Ok(value) if !value.trim().is_empty() => PathBuf::from(value.trim()),
+      _ => PathBuf::from("feature-flags.json"),
+    };
+
+    MemoryDriver::from_file(path)
+  }
+
+  /// Constructs a `MemoryDriver` saving everything to the file at `path`, starting from what it holds if it exists
+  pub fn from_file(path: PathBuf) -> Result<MemoryDriver, String> {
+    let mut collections = Collections::default();
+
+    match fs::read(&path) {
+      Ok(contents) => {
+        let saved: SavedCollections =
+          serde_json::from_slice(&contents).map_err(|e| format!("Error parsing '{}': {}", path.display(), e))?;
+        if saved.backup.version > BACKUP_VERSION {
+          return Err(format!(
+            "'{}' is of version {}, this service can only read up to version {}",
+            path.display(),
+            saved.backup.version,
+            BACKUP_VERSION
+          ));
+        }
+
+        collections.insert_backup(&saved.backup);
+        restore(&mut collections.sessions, &saved.sessions, |x| x.oid);
+      }
+      Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+      Err(e) => return Err(format!("Error reading '{}': {}", path.display(), e)),
+    }
+
+    Ok(MemoryDriver {
+      collections: RwLock::new(collections),
+      file: Some(path),
+    })
+  }
+
+  fn read(&self) -> RwLockReadGuard<'_, Collections> {
+    match self.collections.read() {
+      Ok(value) => value,
+      Err(poisoned) => poisoned.into_inner(), // recover from poisoned lock
+    }
+  }
+
+  fn write(&self) -> WriteGuard<'_> {
+    let collections = match self.collections.write() {
+      Ok(value) => value,
+      Err(poisoned) => poisoned.into_inner(), // recover from poisoned lock
+    };
+
+    WriteGuard {
+      collections,
+      file: self.file.as_deref(),
+    }
+  }
+}
+
+/// Write access to the collections, saving them to the driver's file (if it has one) when dropped
+struct WriteGuard<'a> {
+  collections: RwLockWriteGuard<'a, Collections>,
+  file: Option<&'a Path>,
+}
+
+impl Deref for WriteGuard<'_> {
+  type Target = Collections;
+
+  fn deref(&self) -> &Collections {
+    &self.collections
+  }
+}
+
+impl DerefMut for WriteGuard<'_> {
+  fn deref_mut(&mut self) -> &mut Collections {
+    &mut self.collections
+  }
+}
+
+impl Drop for WriteGuard<'_> {
+  fn drop(&mut self) {
+    // Saved while the lock is still held, so saves are in the order of the changes
+    if let Some(path) = self.file {
+      if let Err(e) = save(&self.collections, path) {
+        log!(
+          "Error saving the database to '{}', the last change will be lost on restart. Error: {}",
+          path.display(),
+          e
+        );
+      }
+    }
+  }
+}
+
+/// Writes the collections to the file at `path`, through a temporary file renamed over it so a crash mid-write leaves
+/// the previous contents
+fn save(collections: &Collections, path: &Path) -> io::Result<()> {
+  let saved = SavedCollections {
+    backup: collections.backup(),
+    sessions: collections.sessions.values().cloned().collect(),
+  };
+  let contents = serde_json::to_vec_pretty(&saved)?;
+
+  let mut temporary = OsString::from(path.as_os_str());
+  temporary.push(".tmp");
+  fs::write(&temporary, contents)?;
+  fs::rename(&temporary, path)
+}
+
+/// Inserts copies of `documents` keyed by their ID, replacing the ones with the same ID. Documents without an ID are
+/// skipped
+fn restore<T: Clone>(collection: &mut BTreeMap<ObjectId, T>, documents: &[T], oid: fn(&T) -> Option<ObjectId>) {
+  for document in documents {
+    if let Some(oid) = oid(document) {
+      collection.insert(oid, document.clone());
+    }
+  }
+}
+
+/// Parses an ID of a document, `None` if it isn't a valid `ObjectId`
+fn parse_id(id: &str) -> Option<ObjectId> {
+  ObjectId::parse_str(id).ok()
+}
+
+/// Number of documents a query with `limit` returns, 0 meaning no limit like in MongoDB
+fn limit_count(limit: i64) -> usize {
+  match limit {
+    0 => usize::MAX,
+    limit => limit.unsigned_abs() as usize,
+  }
+}
+
+/// Returns the items of `page`, in order
+fn paginate<T>(items: impl Iterator<Item = T>, page: Page) -> Vec<T> {
+  items
+    .skip(usize::try_from(page.skip).unwrap_or(usize::MAX))
+    .take(limit_count(page.limit))
+    .collect()
+}
+
+/// Returns `flag` with only the fields of `projection`, see `FlagProjection`
+fn project(mut flag: FeatureFlag, projection: FlagProjection) -> FeatureFlag {
+  if let FlagProjection::Evaluation | FlagProjection::Summary = projection {
+    flag.description = String::new();
+    flag.owner = String::new();
+    flag.maintainer_id = None;
+    flag.tags = vec![];
+    flag.created_at = None;
+    flag.updated_at = None;
+    flag.prerequisites = vec![];
+    flag.scheduled_changes = vec![];
+  }
+
+  if let FlagProjection::Summary = projection {
+    flag.always_include = vec![];
+    flag.always_exclude = vec![];
+    flag.rules = vec![];
+    flag.settings = Default::default();
+    flag.payload = None;
+  }
+
+  flag
+}
+
+/// Returns `user` without its password hash, like the users MongoDB returns for listings
+fn without_password_hash(mut user: User) -> User {
+  user.password_hash = String::new();
+  user
+}
+
+/// Kind of the release type of a flag
+fn release_kind(release_type: &ReleaseType) -> ReleaseKind {
+  match release_type {
+    ReleaseType::Global => ReleaseKind::Global,
+    ReleaseType::Limited(..) => ReleaseKind::Limited,
+    ReleaseType::Percentage(..) => ReleaseKind::Percentage,
+  }
+}
+
+/// If `flag` matches the conditions of `filter`
+fn matches_filter(flag: &FeatureFlag, filter: &FlagFilter<'_>) -> bool {
+  filter.tag.is_none_or(|tag| flag.tags.iter().any(|x| x == tag))
+    && (filter.include_archived || !flag.archived)
+    && filter.enabled.is_none_or(|enabled| flag.enabled == enabled)
+    && filter
+      .release_type
+      .is_none_or(|kind| release_kind(&flag.release_type).name() == kind.name())
+}
+
+/// Sorts flags in the order of `sort`, ties broken by ID. Flags keep their order when there's no `sort`
+fn sort_flags(flags: &mut [FeatureFlag], sort: Option<FlagSort>) {
+  let sort = match sort {
+    Some(sort) => sort,
+    None => return,
+  };
+
+  flags.sort_by(|a, b| {
+    let order = match sort.field {
+      FlagSortField::Name => a.name.cmp(&b.name),
+      FlagSortField::CreatedAt => a.created_at.cmp(&b.created_at),
+      FlagSortField::UpdatedAt => a.updated_at.cmp(&b.updated_at),
+    };
+    let order = if sort.descending { order.reverse() } else { order };
+
+    order.then(a.oid.cmp(&b.oid))
+  });
+}
+
+/// If `value` starts with `term`, ignoring case
+fn starts_with_ignore_case(value: &str, term: &str) -> bool {
+  value.to_lowercase().starts_with(&term.to_lowercase())
+}
+
+/// If the session hasn't expired
+fn is_unexpired(session: &Session) -> bool {
+  session.expires_at > mongodb::bson::DateTime::now()
+}
+
+#[rocket::async_trait]
+impl DatabaseDriver for MemoryDriver {
+  async fn get_product(&self, product_name: &str) -> StorageResult<Option<Product>> {
+    Ok(self.read().products.values().find(|x| x.name == product_name).cloned())
+  }
+
+  async fn get_product_by_id(&self, product_id: &str) -> StorageResult<Option<Product>> {
+    Ok(parse_id(product_id).and_then(|id| self.read().products.get(&id).cloned()))
+  }
+
+  async fn get_products(&self, user_id: Option<String>, page: Page) -> StorageResult<Vec<Product>> {
+    let collections = self.read();
+    let products = collections
+      .products
+      .values()
+      .filter(|x| user_id.as_ref().is_none_or(|user_id| x.users.contains(user_id)))
+      .cloned();
+
+    Ok(paginate(products, page))
+  }
+
+  async fn get_feature_flag(
+    &self,
+    product_id: &str,
+    flag_name: &str,
+    projection: FlagProjection,
+  ) -> StorageResult<Option<FeatureFlag>> {
+    Ok(
+      self
+        .read()
+        .flags
+        .values()
+        .find(|x| x.product_id == product_id && x.name == flag_name)
+        .map(|x| project(x.clone(), projection)),
+    )
+  }
+
+  async fn get_feature_flag_by_id(
+    &self,
+    flag_id: &str,
+    projection: FlagProjection,
+  ) -> StorageResult<Option<FeatureFlag>> {
+    Ok(
+      parse_id(flag_id)
+        .and_then(|id| self.read().flags.get(&id).cloned())
+        .map(|x| project(x, projection)),
+    )
+  }
+
+  async fn get_feature_flags(
+    &self,
+    product_id: &str,
+    filter: FlagFilter<'_>,
+    projection: FlagProjection,
+  ) -> StorageResult<Vec<FeatureFlag>> {
+    let mut flags: Vec<FeatureFlag> = self
+      .read()
+      .flags
+      .values()
+      .filter(|x| x.product_id == product_id && matches_filter(x, &filter))
+      .cloned()
+      .collect();
+    sort_flags(&mut flags, filter.sort);
+
+    Ok(paginate(flags.into_iter().map(|x| project(x, projection)), filter.page))
+  }
+
+  async fn get_feature_flags_by_maintainer(
+    &self,
+    user_id: &str,
+    filter: FlagFilter<'_>,
+  ) -> StorageResult<Vec<FeatureFlag>> {
+    let mut flags: Vec<FeatureFlag> = self
+      .read()
+      .flags
+      .values()
+      .filter(|x| x.maintainer_id.as_deref() == Some(user_id) && matches_filter(x, &filter))
+      .cloned()
+      .collect();
+    sort_flags(&mut flags, filter.sort);
+
+    Ok(paginate(flags.into_iter(), filter.page))
+  }
+
+  async fn get_feature_flags_by_keys(
+    &self,
+    keys: &[(String, String)],
+    projection: FlagProjection,
+  ) -> StorageResult<Vec<FeatureFlag>> {
+    Ok(
+      self
+        .read()
+        .flags
+        .values()
+        .filter(|x| {
+          keys
+            .iter()
+            .any(|(product_id, flag_name)| product_id == &x.product_id && flag_name == &x.name)
+        })
+        .map(|x| project(x.clone(), projection))
+        .collect(),
+    )
+  }
+
+  async fn update_feature_flag(&self, feature_flag_id: &str, mut updated: FeatureFlag) -> StorageResult<bool> {
+    let id = match parse_id(feature_flag_id) {
+      Some(id) => id,
+      None => return Ok(false),
+    };
+
+    // Like a MongoDB replace, updating a flag that doesn't exist does nothing but still succeeds
+    if let Some(flag) = self.write().flags.get_mut(&id) {
+      updated.oid = Some(id);
+      *flag = updated;
+    }
+
+    Ok(true)
+  }
+
+  async fn delete_feature_flag(&self, feature_flag_id: &str) -> StorageResult<Option<FeatureFlag>> {
+    let id = match parse_id(feature_flag_id) {
+      Some(id) => id,
+      None => return Ok(None),
+    };
+
+    let mut collections = self.write();
+    collections.comments.retain(|_, x| x.flag_id != feature_flag_id);
+
+    Ok(collections.flags.remove(&id))
+  }
+
+  async fn set_feature_flag_enabled(
+    &self,
+    feature_flag_id: &str,
+    user_id: Option<&str>,
+    enabled: bool,
+    updated_at: DateTime<Utc>,
+  ) -> StorageResult<Option<FeatureFlag>> {
+    let id = match parse_id(feature_flag_id) {
+      Some(id) => id,
+      None => return Ok(None),
+    };
+
+    Ok(self.write().flags.get_mut(&id).map(|flag| {
+      let user_id = user_id.map(|x| x.to_string());
+      match enabled {
+        true => flag.hoist(user_id),
+        false => flag.lower(user_id),
+      }
+      flag.updated_at = Some(updated_at);
+      flag.clone()
+    }))
+  }
+
+  async fn update_product(&self, product_id: &str, mut updated: Product) -> StorageResult<bool> {
+    let id = match parse_id(product_id) {
+      Some(id) => id,
+      None => return Ok(false),
+    };
+
+    if let Some(product) = self.write().products.get_mut(&id) {
+      updated.oid = Some(id);
+      *product = updated;
+    }
+
+    Ok(true)
+  }
+
+  async fn add_product_user(&self, product_id: &str, user_id: &str) -> StorageResult<Option<Product>> {
+    let id = match parse_id(product_id) {
+      Some(id) => id,
+      None => return Ok(None),
+    };
+
+    Ok(self.write().products.get_mut(&id).map(|product| {
+      if !product.users.iter().any(|x| x == user_id) {
+        product.users.push(user_id.to_string());
+      }
+      product.clone()
+    }))
+  }
+
+  async fn remove_product_user(&self, product_id: &str, user_id: &str) -> StorageResult<Option<Product>> {
+    let id = match parse_id(product_id) {
+      Some(id) => id,
+      None => return Ok(None),
+    };
+
+    Ok(self.write().products.get_mut(&id).map(|product| {
+      product.users.retain(|x| x != user_id);
+      product.clone()
+    }))
+  }
+
+  async fn delete_product(&self, product_id: &str, flag_ids: &[String]) -> StorageResult<bool> {
+    let id = match parse_id(product_id) {
+      Some(id) => id,
+      None => return Ok(false),
+    };
+
+    let mut collections = self.write();
+    collections.comments.retain(|_, x| !flag_ids.contains(&x.flag_id));
+    collections.flags.retain(|_, x| x.product_id != product_id);
+    collections.plugins.retain(|_, x| x.product_id != product_id);
+    collections.api_keys.retain(|_, x| x.product_id != product_id);
+
+    Ok(collections.products.remove(&id).is_some())
+  }
+
+  async fn import_product(
+    &self,
+    product_id: &str,
+    product: Option<Product>,
+    updated: Vec<FeatureFlag>,
+    created: Vec<FeatureFlagBuilder>,
+  ) -> StorageResult<Vec<FeatureFlag>> {
+    let id = match parse_id(product_id) {
+      Some(id) => id,
+      None => return Err(StorageError::NotFound),
+    };
+
+    // A single write lock, so no one sees part of the import
+    let mut collections = self.write();
+
+    if let Some(mut product) = product {
+      if let Some(stored) = collections.products.get_mut(&id) {
+        product.oid = Some(id);
+        *stored = product;
+      }
+    }
+    for flag in updated {
+      if let Some(stored) = flag.oid.and_then(|x| collections.flags.get_mut(&x)) {
+        *stored = flag;
+      }
+    }
+
+    Ok(
+      created
+        .into_iter()
+        .map(|x| {
+          let id = ObjectId::new();
+          let flag = x.with_oid(id).build();
+          collections.flags.insert(id, flag.clone());
+          flag
+        })
+        .collect(),
+    )
+  }
+
+  async fn update_user(&self, user_id: &str, mut updated: User) -> StorageResult<bool> {
+    let id = match parse_id(user_id) {
+      Some(id) => id,
+      None => return Ok(false),
+    };
+
+    if let Some(user) = self.write().users.get_mut(&id) {
+      updated.oid = Some(id);
+      *user = updated;
+    }
+
+    Ok(true)
+  }
+
+  async fn get_user(&self, user_email: Option<&str>, user_id: Option<&str>) -> StorageResult<Option<User>> {
+    // Like the MongoDB query, an invalid ID matches no user
+    let id = user_id.map(|x| parse_id(x).unwrap_or_default());
+
+    Ok(
+      self
+        .read()
+        .users
+        .values()
+        .find(|x| user_email.is_none_or(|email| x.email == email) && id.is_none_or(|id| x.oid == Some(id)))
+        .cloned(),
+    )
+  }
+
+  async fn get_users(&self, account_type: Option<AccountType>, page: Page) -> StorageResult<Vec<User>> {
+    let collections = self.read();
+    let users = collections
+      .users
+      .values()
+      .filter(|x| {
+        account_type
+          .as_ref()
+          .is_none_or(|account_type| x.account_type.name() == account_type.name())
+      })
+      .map(|x| without_password_hash(x.clone()));
+
+    Ok(paginate(users, page))
+  }
+
+  async fn get_users_by_ids(&self, user_ids: &[String]) -> StorageResult<Vec<User>> {
+    let collections = self.read();
+
+    Ok(
+      collections
+        .users
+        .values()
+        .filter(|x| x.oid.is_some_and(|id| user_ids.contains(&id.to_hex())))
+        .map(|x| without_password_hash(x.clone()))
+        .collect(),
+    )
+  }
+
+  async fn count_users(&self) -> StorageResult<u64> {
+    Ok(self.read().users.len() as u64)
+  }
+
+  async fn create_product(&self, product_builder: ProductBuilder) -> StorageResult<Product> {
+    let id = ObjectId::new();
+    let product = product_builder.with_oid(id).build();
+    self.write().products.insert(id, product.clone());
+
+    Ok(product)
+  }
+
+  async fn create_flag(&self, flag_builder: FeatureFlagBuilder) -> StorageResult<FeatureFlag> {
+    let id = ObjectId::new();
+    let flag = flag_builder.with_oid(id).build();
+    self.write().flags.insert(id, flag.clone());
+
+    Ok(flag)
+  }
+
+  async fn create_flags(&self, flag_builders: Vec<FeatureFlagBuilder>) -> StorageResult<Vec<Option<FeatureFlag>>> {
+    let mut collections = self.write();
+
+    Ok(
+      flag_builders
+        .into_iter()
+        .map(|x| {
+          let id = ObjectId::new();
+          let flag = x.with_oid(id).build();
+          collections.flags.insert(id, flag.clone());
+          Some(flag)
+        })
+        .collect(),
+    )
+  }
+
+  async fn create_user(&self, user_builder: UserBuilder) -> StorageResult<User> {
+    let id = ObjectId::new();
+    let user = user_builder.with_oid(id).build();
+    self.write().users.insert(id, user.clone());
+
+    Ok(user)
+  }
+
+  async fn create_audit_entry(&self, mut entry: AuditEntry) -> StorageResult<()> {
+    let id = ObjectId::new();
+    entry.oid = Some(id);
+    self.write().audit.insert(id, entry);
+
+    Ok(())
+  }
+
+  async fn create_comment(&self, mut comment: Comment) -> StorageResult<Comment> {
+    let id = ObjectId::new();
+    comment.oid = Some(id);
+    self.write().comments.insert(id, comment.clone());
+
+    Ok(comment)
+  }
+
+  async fn get_comments(&self, flag_id: &str) -> StorageResult<Vec<Comment>> {
+    Ok(
+      self
+        .read()
+        .comments
+        .values()
+        .filter(|x| x.flag_id == flag_id)
+        .cloned()
+        .collect(),
+    )
+  }
+
+  async fn create_plugin(&self, mut plugin: Plugin) -> StorageResult<Plugin> {
+    let id = ObjectId::new();
+    plugin.oid = Some(id);
+    self.write().plugins.insert(id, plugin.clone());
+
+    Ok(plugin)
+  }
+
+  async fn get_plugin(&self, plugin_id: &str) -> StorageResult<Option<Plugin>> {
+    Ok(parse_id(plugin_id).and_then(|id| self.read().plugins.get(&id).cloned()))
+  }
+
+  async fn create_webhook(&self, mut webhook: Webhook) -> StorageResult<Webhook> {
+    let id = ObjectId::new();
+    webhook.oid = Some(id);
+    self.write().webhooks.insert(id, webhook.clone());
+
+    Ok(webhook)
+  }
+
+  async fn get_webhook(&self, webhook_id: &str) -> StorageResult<Option<Webhook>> {
+    Ok(parse_id(webhook_id).and_then(|id| self.read().webhooks.get(&id).cloned()))
+  }
+
+  async fn get_webhooks(&self, product_id: Option<&str>) -> StorageResult<Vec<Webhook>> {
+    Ok(
+      self
+        .read()
+        .webhooks
+        .values()
+        .filter(|x| product_id.is_none_or(|product_id| x.product_id.as_deref() == Some(product_id)))
+        .cloned()
+        .collect(),
+    )
+  }
+
+  async fn update_webhook(&self, webhook_id: &str, mut updated: Webhook) -> StorageResult<bool> {
+    let id = match parse_id(webhook_id) {
+      Some(id) => id,
+      None => return Ok(false),
+    };
+
+    if let Some(webhook) = self.write().webhooks.get_mut(&id) {
+      updated.oid = Some(id);
+      *webhook = updated;
+    }
+
+    Ok(true)
+  }
+
+  async fn delete_webhook(&self, webhook_id: &str) -> StorageResult<bool> {
+    let id = match parse_id(webhook_id) {
+      Some(id) => id,
+      None => return Ok(false),
+    };
+
+    let mut collections = self.write();
+    collections.deliveries.retain(|_, x| x.webhook_id != webhook_id);
+
+    Ok(collections.webhooks.remove(&id).is_some())
+  }
+
+  async fn create_api_key(&self, mut api_key: ApiKey) -> StorageResult<ApiKey> {
+    let id = ObjectId::new();
+    api_key.oid = Some(id);
+    self.write().api_keys.insert(id, api_key.clone());
+
+    Ok(api_key)
+  }
+
+  async fn get_api_key(&self, api_key_id: &str) -> StorageResult<Option<ApiKey>> {
+    Ok(parse_id(api_key_id).and_then(|id| self.read().api_keys.get(&id).cloned()))
+  }
+
+  async fn get_api_key_by_hash(&self, key_hash: &str) -> StorageResult<Option<ApiKey>> {
+    Ok(self.read().api_keys.values().find(|x| x.key_hash == key_hash).cloned())
+  }
+
+  async fn get_api_keys(&self, product_id: &str) -> StorageResult<Vec<ApiKey>> {
+    Ok(
+      self
+        .read()
+        .api_keys
+        .values()
+        .filter(|x| x.product_id == product_id)
+        .cloned()
+        .collect(),
+    )
+  }
+
+  async fn revoke_api_key(&self, api_key_id: &str, revoked_at: DateTime<Utc>) -> StorageResult<bool> {
+    let id = match parse_id(api_key_id) {
+      Some(id) => id,
+      None => return Ok(false),
+    };
+
+    Ok(match self.write().api_keys.get_mut(&id) {
+      Some(api_key) if api_key.revoked_at.is_none() => {
+        api_key.revoked_at = Some(mongodb::bson::DateTime::from_millis(revoked_at.timestamp_millis()));
+        true
+      }
+      _ => false,
+    })
+  }
+
+  async fn create_api_key_indexes(&self) -> StorageResult<()> {
+    Ok(())
+  }
+
+  async fn create_service_account(&self, mut service_account: ServiceAccount) -> StorageResult<ServiceAccount> {
+    let id = ObjectId::new();
+    service_account.oid = Some(id);
+    self.write().service_accounts.insert(id, service_account.clone());
+
+    Ok(service_account)
+  }
+
+  async fn get_service_account(&self, service_account_id: &str) -> StorageResult<Option<ServiceAccount>> {
+    Ok(parse_id(service_account_id).and_then(|id| self.read().service_accounts.get(&id).cloned()))
+  }
+
+  async fn get_service_account_by_hash(&self, token_hash: &str) -> StorageResult<Option<ServiceAccount>> {
+    Ok(
+      self
+        .read()
+        .service_accounts
+        .values()
+        .find(|x| x.token_hash == token_hash)
+        .cloned(),
+    )
+  }
+
+  async fn get_service_accounts(&self) -> StorageResult<Vec<ServiceAccount>> {
+    Ok(self.read().service_accounts.values().cloned().collect())
+  }
+
+  async fn revoke_service_account(&self, service_account_id: &str, revoked_at: DateTime<Utc>) -> StorageResult<bool> {
+    let id = match parse_id(service_account_id) {
+      Some(id) => id,
+      None => return Ok(false),
+    };
+
+    Ok(match self.write().service_accounts.get_mut(&id) {
+      Some(service_account) if service_account.revoked_at.is_none() => {
+        service_account.revoked_at = Some(mongodb::bson::DateTime::from_millis(revoked_at.timestamp_millis()));
+        true
+      }
+      _ => false,
+    })
+  }
+
+  async fn create_service_account_indexes(&self) -> StorageResult<()> {
+    Ok(())
+  }
+
+  async fn create_session(&self, mut session: Session) -> StorageResult<Session> {
+    let id = ObjectId::new();
+    session.oid = Some(id);
+    self.write().sessions.insert(id, session.clone());
+
+    Ok(session)
+  }
+
+  async fn get_session(&self, user_id: &str, token_hash: &str) -> StorageResult<Option<Session>> {
+    Ok(
+      self
+        .read()
+        .sessions
+        .values()
+        .find(|x| x.user_id == user_id && x.token_hash == token_hash && is_unexpired(x))
+        .cloned(),
+    )
+  }
+
+  async fn refresh_session(
+    &self,
+    user_id: &str,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+  ) -> StorageResult<Option<Session>> {
+    Ok(
+      self
+        .write()
+        .sessions
+        .values_mut()
+        .find(|x| x.user_id == user_id && x.token_hash == token_hash && is_unexpired(x))
+        .map(|session| {
+          session.expires_at = mongodb::bson::DateTime::from_millis(expires_at.timestamp_millis());
+          session.clone()
+        }),
+    )
+  }
+
+  async fn delete_session(&self, user_id: &str, token_hash: &str) -> StorageResult<bool> {
+    let mut collections = self.write();
+    let id = collections
+      .sessions
+      .iter()
+      .find(|(_, x)| x.user_id == user_id && x.token_hash == token_hash && is_unexpired(x))
+      .map(|(id, _)| *id);
+
+    Ok(id.and_then(|id| collections.sessions.remove(&id)).is_some())
+  }
+
+  async fn delete_sessions(&self, user_id: &str) -> StorageResult<u64> {
+    let mut collections = self.write();
+    let active = collections
+      .sessions
+      .values()
+      .filter(|x| x.user_id == user_id && is_unexpired(x))
+      .count();
+    collections.sessions.retain(|_, x| x.user_id != user_id);
+
+    Ok(active as u64)
+  }
+
+  async fn create_session_indexes(&self) -> StorageResult<()> {
+    Ok(())
+  }
+
+  async fn create_webhook_delivery(&self, delivery: &WebhookDelivery) -> StorageResult<()> {
+    self.write().deliveries.insert(delivery.oid, delivery.clone());
+
+    Ok(())
+  }
+
+  async fn update_webhook_delivery(&self, delivery: &WebhookDelivery) -> StorageResult<()> {
+    if let Some(stored) = self.write().deliveries.get_mut(&delivery.oid) {
+      *stored = delivery.clone();
+    }
+
+    Ok(())
+  }
+
+  async fn get_webhook_delivery(&self, delivery_id: &str) -> StorageResult<Option<WebhookDelivery>> {
+    Ok(parse_id(delivery_id).and_then(|id| self.read().deliveries.get(&id).cloned()))
+  }
+
+  async fn get_webhook_deliveries(
+    &self,
+    webhook_id: &str,
+    status: Option<DeliveryStatus>,
+    limit: i64,
+  ) -> StorageResult<Vec<WebhookDelivery>> {
+    Ok(
+      self
+        .read()
+        .deliveries
+        .values()
+        .rev()
+        .filter(|x| x.webhook_id == webhook_id && status.is_none_or(|status| x.status == status))
+        .take(limit_count(limit))
+        .cloned()
+        .collect(),
+    )
+  }
+
+  async fn get_feature_flags_with_due_changes(&self, now: DateTime<Utc>) -> StorageResult<Vec<FeatureFlag>> {
+    let now = mongodb::bson::DateTime::from_millis(now.timestamp_millis());
+
+    Ok(
+      self
+        .read()
+        .flags
+        .values()
+        .filter(|x| x.scheduled_changes.iter().any(|change| change.at <= now))
+        .cloned()
+        .collect(),
+    )
+  }
+
+  async fn apply_scheduled_change(
+    &self,
+    feature_flag_id: &str,
+    change_id: ObjectId,
+    field: &ChangedField,
+    updated_at: DateTime<Utc>,
+  ) -> StorageResult<Option<FeatureFlag>> {
+    let id = match parse_id(feature_flag_id) {
+      Some(id) => id,
+      None => return Ok(None),
+    };
+
+    let mut collections = self.write();
+    let flag = match collections.flags.get_mut(&id) {
+      Some(flag) if flag.scheduled_changes.iter().any(|x| x.id == change_id) => flag,
+      _ => return Ok(None),
+    };
+
+    flag.scheduled_changes.retain(|x| x.id != change_id);
+    flag.set_field(field);
+    flag.updated_at = Some(updated_at);
+    Ok(Some(flag.clone()))
+  }
+
+  async fn get_audit_entries(
+    &self,
+    filter: &AuditFilter,
+    after: Option<&str>,
+    limit: i64,
+  ) -> StorageResult<Option<Vec<AuditEntry>>> {
+    let after = match after.map(parse_id) {
+      Some(Some(id)) => Some(id),
+      Some(None) => return Ok(None),
+      None => None,
+    };
+
+    let entries = self
+      .read()
+      .audit
+      .iter()
+      .rev()
+      .filter(|(id, _)| after.is_none_or(|after| **id < after))
+      .map(|(_, x)| x)
+      .filter(|x| {
+        let timestamp = x.timestamp();
+
+        filter.actor.as_ref().is_none_or(|actor| &x.actor == actor)
+          && filter
+            .entity_type
+            .is_none_or(|entity_type| x.entity_type == entity_type)
+          && filter.action.is_none_or(|action| x.action == action)
+          && filter.from.is_none_or(|from| timestamp >= from)
+          && filter.to.is_none_or(|to| timestamp <= to)
+      })
+      .take(limit_count(limit))
+      .cloned()
+      .collect();
+
+    Ok(Some(entries))
+  }
+
+  async fn search_feature_flags(&self, term: &str, limit: i64) -> StorageResult<Vec<FeatureFlag>> {
+    Ok(
+      self
+        .read()
+        .flags
+        .values()
+        .filter(|x| starts_with_ignore_case(&x.name, term))
+        .take(limit_count(limit))
+        .map(|x| project(x.clone(), FlagProjection::Summary))
+        .collect(),
+    )
+  }
+
+  async fn search_products(&self, term: &str, limit: i64) -> StorageResult<Vec<Product>> {
+    Ok(
+      self
+        .read()
+        .products
+        .values()
+        .filter(|x| starts_with_ignore_case(&x.name, term))
+        .take(limit_count(limit))
+        .cloned()
+        .collect(),
+    )
+  }
+
+  async fn search_users(&self, term: &str, limit: i64) -> StorageResult<Vec<User>> {
+    Ok(
+      self
+        .read()
+        .users
+        .values()
+        .filter(|x| starts_with_ignore_case(&x.email, term))
+        .take(limit_count(limit))
+        .map(|x| without_password_hash(x.clone()))
+        .collect(),
+    )
+  }
+
+  async fn create_search_indexes(&self) -> StorageResult<()> {
+    Ok(())
+  }
+
+  async fn ping(&self) -> StorageResult<()> {
+    Ok(())
+  }
+
+  async fn create_audit_indexes(&self) -> StorageResult<()> {
+    Ok(())
+  }
+
+  async fn add_evaluation_counts(&self, hour: DateTime<Utc>, counts: &HashMap<String, u64>) -> StorageResult<()> {
+    let mut collections = self.write();
+    for (product_id, count) in counts {
+      *collections
+        .evaluation_counts
+        .entry((product_id.clone(), hour))
+        .or_default() += count;
+    }
+
+    Ok(())
+  }
+
+  async fn create_evaluation_count_indexes(&self, _retention: Duration) -> StorageResult<()> {
+    Ok(())
+  }
+
+  async fn export_backup(&self) -> StorageResult<Backup> {
+    Ok(self.read().backup())
+  }
+
+  async fn restore_backup(&self, backup: &Backup) -> StorageResult<()> {
+    self.write().insert_backup(backup);
+    Ok(())
+  }
+
+  async fn aggregate_counts(&self, since: DateTime<Utc>) -> StorageResult<Stats> {
+    let collections = self.read();
+    let mut stats = Stats {
+      products: collections.products.len() as u64,
+      active_sessions: collections.sessions.values().filter(|x| is_unexpired(x)).count() as u64,
+      ..Default::default()
+    };
+
+    for flag in collections.flags.values() {
+      let flags = &mut stats.flags;
+      flags.total += 1;
+      match (flag.archived, flag.enabled) {
+        (true, _) => flags.archived += 1,
+        (false, true) => flags.enabled += 1,
+        (false, false) => flags.disabled += 1,
+      }
+      *flags
+        .by_release_type
+        .entry(release_kind(&flag.release_type).name().to_string())
+        .or_default() += 1;
+      *flags.by_product.entry(flag.product_id.clone()).or_default() += 1;
+    }
+
+    for user in collections.users.values() {
+      stats.users.total += 1;
+      *stats
+        .users
+        .by_account_type
+        .entry(user.account_type.name().to_string())
+        .or_default() += 1;
+    }
+
+    stats.evaluations_24h = collections
+      .evaluation_counts
+      .iter()
+      .filter(|((_, hour), _)| *hour >= since)
+      .map(|(_, count)| count)
+      .sum();
+
+    Ok(stats)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[rocket::async_test]
+  async fn file_driver_keeps_changes_across_restarts() {
+    let path = std::env::temp_dir().join(format!("feature-flags-{}.json", ObjectId::new()));
+
+    let driver = MemoryDriver::from_file(path.clone()).unwrap();
+    let product = driver
+      .create_product(Product::builder().with_name("checkout"))
+      .await
+      .unwrap();
+    let session = Session::builder()
+      .with_user_id("u1")
+      .with_token_hash("hash")
+      .with_ttl(chrono::Duration::hours(1))
+      .build();
+    driver.create_session(session).await.unwrap();
+    drop(driver);
+
+    let driver = MemoryDriver::from_file(path.clone()).unwrap();
+    let product_id = product.oid.unwrap().to_hex();
+    let restored = driver.get_product_by_id(&product_id).await.unwrap().unwrap();
+    assert_eq!(restored.name, "checkout");
+    assert!(driver.get_session("u1", "hash").await.unwrap().is_some());
+
+    fs::write(&path, "not json").unwrap();
+    assert!(MemoryDriver::from_file(path.clone()).is_err());
+    fs::remove_file(&path).unwrap();
+  }
+}
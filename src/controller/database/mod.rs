@@ -2,32 +2,211 @@
 
 use dotenv;
 
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
 use mongodb::bson::oid::ObjectId;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
 
+use crate::controller::events::{ChangeEvent, EVENT_CAPACITY};
+use crate::log;
+use crate::model::api_key::{ApiKey, ApiKeyBuilder};
+use crate::model::audit::{AuditAction, AuditEntry, AuditEntryBuilder, EntityType};
+use crate::model::backup::Backup;
+use crate::model::comment::{Comment, CommentBuilder};
 use crate::model::flag::{FeatureFlag, FeatureFlagBuilder};
+use crate::model::plugin::{Plugin, PluginBuilder};
 use crate::model::product::{Product, ProductBuilder};
+use crate::model::schedule::ChangedField;
+use crate::model::service_account::{ServiceAccount, ServiceAccountBuilder};
+use crate::model::session::{Session, SessionBuilder};
+use crate::model::stats::Stats;
 use crate::model::user::{AccountType, User, UserBuilder};
+use crate::model::webhook::{DeliveryStatus, Webhook, WebhookBuilder, WebhookDelivery};
 
+pub mod driver;
+pub mod error;
+pub mod memory;
 pub mod mongo;
+pub mod redis;
+
+use driver::{DatabaseDriver, StoredChange};
+use error::{StorageError, StorageResult};
+use memory::MemoryDriver;
+use mongo::MongoDriver;
+use redis::RedisCache;
+
+/// Longest the database may take to answer a health check before it's reported as down
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Result of a health check of the database, see `ConnectionManager::health`
+#[derive(Clone, Copy, Debug)]
+pub struct DatabaseHealth {
+  /// If the database answered in time
+  pub up: bool,
+  /// How long the check took
+  pub latency: Duration,
+}
+
+/// Which fields of a `FeatureFlag` to fetch from the database
+#[derive(Clone, Copy, Debug)]
+pub enum FlagProjection {
+  /// The full flag document, required for anything that writes the flag back
+  Full,
+  /// Only the fields needed by `FeatureFlag::evaluate` and to cache its results. Flags fetched this way must never be written back, since the
+  /// left out fields would be lost
+  Evaluation,
+  /// Only the name, product, and statuses of the flag, for listings that don't need its rules or details. Flags fetched
+  /// this way must never be written back either
+  Summary,
+}
+
+/// Which Feature Flags of a product to list
+#[derive(Clone, Debug, Default)]
+pub struct FlagFilter<'a> {
+  /// Only list flags carrying this tag
+  pub tag: Option<&'a str>,
+  /// List archived flags too, they're left out by default
+  pub include_archived: bool,
+  /// Only list flags with this global enabled status
+  pub enabled: Option<bool>,
+  /// Only list flags with this kind of release type
+  pub release_type: Option<ReleaseKind>,
+  /// Order to list the flags in, the database's natural order by default
+  pub sort: Option<FlagSort>,
+  /// Part of the flags to list, every one by default
+  pub page: Page,
+}
+
+/// Part of a listing to return, applied by the database so the documents outside of it are never read
+///
+/// Listings that are paged without a sort are ordered by ID, so pages of the same listing don't overlap
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Page {
+  /// Documents skipped before the first one returned
+  pub skip: u64,
+  /// Most documents returned, 0 for no limit
+  pub limit: i64,
+}
+
+impl Page {
+  /// If the page is the whole listing
+  pub fn is_everything(&self) -> bool {
+    self.skip == 0 && self.limit == 0
+  }
+}
+
+/// Kind of release type, without its data
+#[derive(Clone, Copy, Debug)]
+pub enum ReleaseKind {
+  Global,
+  Limited,
+  Percentage,
+}
+
+impl ReleaseKind {
+  /// Parses a kind of release type from its name (e.g. `Percentage`), ignoring case
+  pub fn from_name(name: &str) -> Option<ReleaseKind> {
+    match name.trim().to_lowercase().as_str() {
+      "global" => Some(ReleaseKind::Global),
+      "limited" => Some(ReleaseKind::Limited),
+      "percentage" => Some(ReleaseKind::Percentage),
+      _ => None,
+    }
+  }
+
+  /// Name of the kind of release type, as its variant of `ReleaseType` is stored
+  pub fn name(&self) -> &'static str {
+    match self {
+      ReleaseKind::Global => "Global",
+      ReleaseKind::Limited => "Limited",
+      ReleaseKind::Percentage => "Percentage",
+    }
+  }
+}
+
+/// Field Feature Flags can be sorted by
+#[derive(Clone, Copy, Debug)]
+pub enum FlagSortField {
+  Name,
+  CreatedAt,
+  UpdatedAt,
+}
+
+/// Order to list Feature Flags in
+#[derive(Clone, Copy, Debug)]
+pub struct FlagSort {
+  /// Field to sort by
+  pub field: FlagSortField,
+  /// If the flags are sorted from the largest value down
+  pub descending: bool,
+}
 
-enum ConnectionType {
-  MongoDB,
+impl FlagSort {
+  /// Parses a sort order from a field name, prefixed with `-` to sort in descending order (e.g. `-updated_at`)
+  pub fn parse(value: &str) -> Option<FlagSort> {
+    let value = value.trim();
+    let (name, descending) = match value.strip_prefix('-') {
+      Some(name) => (name, true),
+      None => (value, false),
+    };
+
+    let field = match name {
+      "name" => FlagSortField::Name,
+      "created_at" | "createdAt" => FlagSortField::CreatedAt,
+      "updated_at" | "updatedAt" => FlagSortField::UpdatedAt,
+      _ => return None,
+    };
+
+    Some(FlagSort { field, descending })
+  }
+}
+
+/// Which entries of the audit log to list, every set field must match
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+  /// Only list changes made by this actor
+  pub actor: Option<String>,
+  /// Only list changes to this type of entity
+  pub entity_type: Option<EntityType>,
+  /// Only list this action
+  pub action: Option<AuditAction>,
+  /// Only list changes made at or after this time
+  pub from: Option<DateTime<Utc>>,
+  /// Only list changes made at or before this time
+  pub to: Option<DateTime<Utc>>,
 }
 
 /// Manager for database connections
+#[derive(Clone)]
 pub struct ConnectionManager {
-  /// Type of the database driver
-  connection_type: ConnectionType,
+  /// Driver of the database, picked by `DATABASE_CONNECTION_TYPE`
+  driver: Arc<dyn DatabaseDriver>,
+  /// Changes to flags and products written through this manager, shared by its clones
+  events: broadcast::Sender<ChangeEvent>,
+  /// Changes to flags and products written by every instance when the driver watches changes, otherwise the same
+  /// channel as `events`
+  live_events: broadcast::Sender<ChangeEvent>,
+  /// Redis cache of flag lookups, `None` unless `REDIS_URL` is set
+  cache: Option<Arc<RedisCache>>,
 }
 
 impl ConnectionManager {
   /// Constructs and returns a new `ConnectionManager`
   pub fn new() -> ConnectionManager {
-    let connection_type = match dotenv::var("DATABASE_CONNECTION_TYPE") {
+    let driver: Arc<dyn DatabaseDriver> = match dotenv::var("DATABASE_CONNECTION_TYPE") {
       Ok(value) => match value.as_str() {
-        "mongodb" => ConnectionType::MongoDB,
+        "mongodb" => Arc::new(MongoDriver::from_env()),
+        "memory" => Arc::new(MemoryDriver::new()),
+        "file" => match MemoryDriver::from_env() {
+          Ok(driver) => Arc::new(driver),
+          Err(e) => panic!("Unrecoverable error. Unable to load 'DATABASE_FILE'. {}", e),
+        },
         _ => panic!(
-          "Unrecoverable error. Unrecognized 'DATABASE_CONNECTION_TYPE': {}",
+          "Unrecoverable error. Unrecognized 'DATABASE_CONNECTION_TYPE': {}, expected 'mongodb', 'memory' or 'file'",
           value
         ),
       },
@@ -39,186 +218,1168 @@ impl ConnectionManager {
       }
     };
 
-    ConnectionManager { connection_type }
+    let events = broadcast::channel(EVENT_CAPACITY).0;
+    let live_events = match driver.watches_changes() {
+      true => broadcast::channel(EVENT_CAPACITY).0,
+      false => events.clone(),
+    };
+
+    ConnectionManager {
+      driver,
+      events,
+      live_events,
+      cache: RedisCache::from_env().map(Arc::new),
+    }
+  }
+
+  /// Subscribes to changes to flags and products written through this manager from now on
+  pub fn subscribe(&self) -> broadcast::Receiver<ChangeEvent> {
+    self.events.subscribe()
   }
 
-  /// Given a product name, returns a fully constructed `Product` from the database
+  /// Subscribes to changes to flags and products written from now on, by every instance when the driver watches
+  /// changes (see `watch_changes`)
+  pub fn subscribe_live(&self) -> broadcast::Receiver<ChangeEvent> {
+    self.live_events.subscribe()
+  }
+
+  /// Publishes the changes written by every instance to live subscribers, deleting the cached copies of the flags
+  /// changed, for as long as the driver watches changes
   ///
-  /// Returns `Product` inside of an `Option<Product>`. If anything goes wrong, this function will return `None`
-  pub async fn get_product(&self, product_name: &str) -> Option<Product> {
-    match &self.connection_type {
-      ConnectionType::MongoDB => match mongo::get_product(product_name).await {
-        Ok(product) => product,
-        Err(e) => {
-          println!(
-            "Error getting product '{}'. Returning Option::None. Error {:?}",
-            product_name, e
-          );
-          None
+  /// Does nothing if the driver doesn't watch changes. If it stops watching them (e.g. the database doesn't support
+  /// it), the changes written through this manager are published instead
+  pub async fn watch_changes(&self) {
+    if !self.driver.watches_changes() {
+      return;
+    }
+
+    let (sender, mut changes) = mpsc::channel(EVENT_CAPACITY);
+    let driver = self.driver.clone();
+    let watcher = tokio::spawn(async move { driver.watch_changes(sender).await });
+
+    while let Some(change) = changes.recv().await {
+      self.publish_stored(change).await;
+    }
+
+    if let Ok(Err(e)) = watcher.await {
+      log!(
+        "Error watching changes, live streams only get changes written through this instance from now on. Error: {}",
+        e
+      );
+    }
+
+    let mut events = self.events.subscribe();
+    loop {
+      match events.recv().await {
+        Ok(event) => {
+          let _ = self.live_events.send(event);
         }
-      },
+        Err(RecvError::Lagged(missed)) => log!("Live streams missed {} changes", missed),
+        Err(RecvError::Closed) => return,
+      }
     }
   }
 
-  /// Given a user ID, returns a lit of products consumed by the user
-  ///
-  /// Will return an empty `Vec<Product>` if no results are found
-  pub async fn get_products(&self, user_id: Option<String>) -> Vec<Product> {
-    match &self.connection_type {
-      ConnectionType::MongoDB => match mongo::get_products(user_id).await {
-        Ok(products) => products,
-        Err(e) => {
-          println!(
-            "Error getting products. Returning empty Vec. Error {:?}", e);
-          return vec![];
+  /// Publishes a change read back from the database to live subscribers
+  async fn publish_stored(&self, change: StoredChange) {
+    let event = match change {
+      StoredChange::FlagUpdated { flag, previous_name } => {
+        let mut cached = vec![(flag.product_id.clone(), flag.name.clone())];
+        if let Some(previous_name) = previous_name {
+          cached.push((flag.product_id.clone(), previous_name));
         }
-      },
+        self.uncache_flags(&cached).await;
+
+        ChangeEvent::FlagUpdated(Arc::new(flag.get_spec_safe_feature_flag()))
+      }
+      StoredChange::FlagDeleted {
+        flag_id,
+        product_id,
+        name,
+      } => {
+        self.uncache_flags(&[(product_id.clone(), name)]).await;
+        ChangeEvent::FlagDeleted { product_id, flag_id }
+      }
+      StoredChange::ProductUpdated(product) => ChangeEvent::ProductUpdated(Arc::new(product.get_spec_safe_product())),
+      StoredChange::ProductDeleted { product_id } => ChangeEvent::ProductDeleted { product_id },
+    };
+
+    let _ = self.live_events.send(event);
+  }
+
+  /// Publishes a change to a flag or product to subscribers, if there are any
+  fn publish(&self, event: ChangeEvent) {
+    let _ = self.events.send(event);
+  }
+
+  /// Deletes the cached copies of the flags of the given `(product_id, flag_name)` keys, if flags are cached
+  async fn uncache_flags(&self, flags: &[(String, String)]) {
+    if let Some(cache) = &self.cache {
+      cache.invalidate_flags(flags).await;
     }
   }
 
+  /// Given a product name, returns a fully constructed `Product` from the database
+  ///
+  /// Returns `None` if no product has the name
+  pub async fn get_product(&self, product_name: &str) -> StorageResult<Option<Product>> {
+    self
+      .driver
+      .get_product(product_name)
+      .await
+      .map_err(|e| logged(e, &format!("getting product '{}'", product_name)))
+  }
+
+  /// Given a product ID, returns a fully constructed `Product` from the database
+  ///
+  /// Returns `None` if no product has the ID
+  pub async fn get_product_by_id(&self, product_id: &str) -> StorageResult<Option<Product>> {
+    self
+      .driver
+      .get_product_by_id(product_id)
+      .await
+      .map_err(|e| logged(e, &format!("getting product with id '{}'", product_id)))
+  }
+
+  /// Given a user ID, returns a lit of products consumed by the user
+  ///
+  /// Will return an empty `Vec<Product>` if no results are found, only the products of `page` are returned
+  pub async fn get_products(&self, user_id: Option<String>, page: Page) -> StorageResult<Vec<Product>> {
+    self
+      .driver
+      .get_products(user_id, page)
+      .await
+      .map_err(|e| logged(e, "getting products"))
+  }
+
   /// Given a product id, and flag name, returns a fully constructed `FeatureFlag`
   ///
-  /// Returns `FeatureFlag` inside of an `Option<FeatureFlag>`. If anything goes wrong, this function will return `None`
-  pub async fn get_feature_flag(&self, product_id: &str, flag_name: &str) -> Option<FeatureFlag> {
-    match &self.connection_type {
-      ConnectionType::MongoDB => match mongo::get_feature_flag(product_id, flag_name).await {
-        Ok(feature_flag) => feature_flag,
-        Err(e) => {
-          println!(
-            "Error getting feature '{}'. Returning Option::None. Error: {:?}",
-            flag_name, e
-          );
-          None
-        }
-      },
+  /// Returns `None` if the product has no flag with the name
+  pub async fn get_feature_flag(
+    &self,
+    product_id: &str,
+    flag_name: &str,
+    projection: FlagProjection,
+  ) -> StorageResult<Option<FeatureFlag>> {
+    if let Some(cache) = &self.cache {
+      if let Some(feature_flag) = cache.get_flag(product_id, flag_name, projection).await {
+        return Ok(Some(feature_flag));
+      }
+    }
+
+    let feature_flag = self
+      .driver
+      .get_feature_flag(product_id, flag_name, projection)
+      .await
+      .map_err(|e| logged(e, &format!("getting feature '{}'", flag_name)))?;
+
+    if let (Some(cache), Some(flag)) = (&self.cache, &feature_flag) {
+      cache.set_flag(product_id, flag_name, projection, flag).await;
     }
+
+    Ok(feature_flag)
+  }
+
+  /// Given a flag ID, returns a fully constructed `FeatureFlag`
+  ///
+  /// Returns `None` if no flag has the ID
+  pub async fn get_feature_flag_by_id(
+    &self,
+    flag_id: &str,
+    projection: FlagProjection,
+  ) -> StorageResult<Option<FeatureFlag>> {
+    self
+      .driver
+      .get_feature_flag_by_id(flag_id, projection)
+      .await
+      .map_err(|e| logged(e, &format!("getting feature with id '{}'", flag_id)))
   }
 
   /// Given a product_id returns a list of Feature Flags belonging to the product_id
   ///
+  /// Only flags matching the `filter` are returned, see `FlagFilter`
+  ///
   /// Returns an empty `Vec<FeatureFlag>` if no flags are found
-  pub async fn get_feature_flags(&self, product_id: &str) -> Vec<FeatureFlag> {
-    match &self.connection_type {
-      ConnectionType::MongoDB => match mongo::get_feature_flags(product_id).await {
-        Ok(feature_flags) => feature_flags,
-        Err(e) => {
-          println!(
-            "Error getting features for product_id '{}'. Returning empty Vec. Error: {:?}",
-            product_id, e
-          );
-          return vec![];
-        }
-      },
+  pub async fn get_feature_flags(
+    &self,
+    product_id: &str,
+    filter: FlagFilter<'_>,
+    projection: FlagProjection,
+  ) -> StorageResult<Vec<FeatureFlag>> {
+    self
+      .driver
+      .get_feature_flags(product_id, filter, projection)
+      .await
+      .map_err(|e| logged(e, &format!("getting features for product_id '{}'", product_id)))
+  }
+
+  /// Given a user_id returns the Feature Flags, across every product, maintained by the user
+  ///
+  /// Only flags matching the `filter` are returned, see `FlagFilter`
+  ///
+  /// Returns an empty `Vec<FeatureFlag>` if no flags are found
+  pub async fn get_feature_flags_by_maintainer(
+    &self,
+    user_id: &str,
+    filter: FlagFilter<'_>,
+  ) -> StorageResult<Vec<FeatureFlag>> {
+    self
+      .driver
+      .get_feature_flags_by_maintainer(user_id, filter)
+      .await
+      .map_err(|e| logged(e, &format!("getting features maintained by '{}'", user_id)))
+  }
+
+  /// Given a list of `(product_id, flag_name)` keys, returns every matching Feature Flag using a single query
+  ///
+  /// Returns an empty `Vec<FeatureFlag>` if no flags are found
+  pub async fn get_feature_flags_by_keys(
+    &self,
+    keys: &[(String, String)],
+    projection: FlagProjection,
+  ) -> StorageResult<Vec<FeatureFlag>> {
+    if keys.is_empty() {
+      return Ok(vec![]);
     }
+
+    self
+      .driver
+      .get_feature_flags_by_keys(keys, projection)
+      .await
+      .map_err(|e| logged(e, "getting features by keys"))
   }
 
   /// given a unique feature flag ID and a fully constructed FeatureFlag struct, will update said
   /// flag in the database
   ///
-  /// returns `bool` to indicate success
-  pub async fn update_feature_flag(&self, feature_flag_id: &str, updated: FeatureFlag) -> bool {
-    match &self.connection_type {
-      ConnectionType::MongoDB => {
-        let id: ObjectId = match ObjectId::parse_str(feature_flag_id) {
-          Ok(id) => id,
-          Err(_) => return false,
-        };
-
-        match mongo::update_feature_flag(id, updated).await {
-          Ok(_) => true,
-          Err(e) => {
-            println!("Error updating feature flag. Error: {:?}", e);
-            false
-          }
+  /// Sets `updated_at` on the flag to the current time
+  ///
+  /// Fails with `StorageError::NotFound` if no flag has the ID
+  pub async fn update_feature_flag(&self, feature_flag_id: &str, mut updated: FeatureFlag) -> StorageResult<()> {
+    updated.updated_at = Some(Utc::now());
+
+    let event = ChangeEvent::FlagUpdated(Arc::new(updated.get_spec_safe_feature_flag()));
+
+    // A renamed flag is also cached under its previous name
+    let mut cached = vec![(updated.product_id.clone(), updated.name.clone())];
+    if self.cache.is_some() {
+      if let Ok(Some(previous)) = self
+        .driver
+        .get_feature_flag_by_id(feature_flag_id, FlagProjection::Evaluation)
+        .await
+      {
+        if previous.name != updated.name {
+          cached.push((previous.product_id, previous.name));
         }
       }
     }
+
+    match self.driver.update_feature_flag(feature_flag_id, updated).await {
+      Ok(true) => {
+        self.uncache_flags(&cached).await;
+        self.publish(event);
+        Ok(())
+      }
+      Ok(false) => Err(StorageError::NotFound),
+      Err(e) => Err(logged(e, "updating feature flag")),
+    }
+  }
+
+  /// Given a unique feature flag ID, deletes the flag and its comments from the database, in one transaction
+  ///
+  /// Fails with `StorageError::NotFound` if no flag has the ID
+  pub async fn delete_feature_flag(&self, feature_flag_id: &str) -> StorageResult<()> {
+    match self.driver.delete_feature_flag(feature_flag_id).await {
+      Ok(Some(deleted)) => {
+        self.uncache_flags(&[(deleted.product_id.clone(), deleted.name)]).await;
+        self.publish(ChangeEvent::FlagDeleted {
+          product_id: deleted.product_id,
+          flag_id: feature_flag_id.to_string(),
+        });
+        Ok(())
+      }
+      Ok(None) => Err(StorageError::NotFound),
+      Err(e) => Err(logged(e, "deleting feature flag")),
+    }
+  }
+
+  /// Given a unique feature flag ID, enables the flag globally if `user_id` is `None`, otherwise re-enables it for the
+  /// user only, in a single atomic update
+  ///
+  /// Fails with `StorageError::NotFound` if no flag has the ID
+  pub async fn hoist_feature_flag(&self, feature_flag_id: &str, user_id: Option<&str>) -> StorageResult<()> {
+    self.set_feature_flag_enabled(feature_flag_id, user_id, true).await
+  }
+
+  /// Given a unique feature flag ID, disables the flag globally if `user_id` is `None`, otherwise disables it for the
+  /// user only, in a single atomic update
+  ///
+  /// Fails with `StorageError::NotFound` if no flag has the ID
+  pub async fn lower_feature_flag(&self, feature_flag_id: &str, user_id: Option<&str>) -> StorageResult<()> {
+    self.set_feature_flag_enabled(feature_flag_id, user_id, false).await
+  }
+
+  async fn set_feature_flag_enabled(
+    &self,
+    feature_flag_id: &str,
+    user_id: Option<&str>,
+    enabled: bool,
+  ) -> StorageResult<()> {
+    match self
+      .driver
+      .set_feature_flag_enabled(feature_flag_id, user_id, enabled, Utc::now())
+      .await
+    {
+      Ok(Some(flag)) => {
+        self
+          .uncache_flags(&[(flag.product_id.clone(), flag.name.clone())])
+          .await;
+        self.publish(ChangeEvent::FlagUpdated(Arc::new(flag.get_spec_safe_feature_flag())));
+        Ok(())
+      }
+      Ok(None) => Err(StorageError::NotFound),
+      Err(e) => Err(logged(
+        e,
+        match enabled {
+          true => "hoisting feature flag",
+          false => "lowering feature flag",
+        },
+      )),
+    }
+  }
+
+  /// given a unique product ID and a fully constructed Product struct, will update said product in the database
+  ///
+  /// Fails with `StorageError::NotFound` if no product has the ID
+  pub async fn update_product(&self, product_id: &str, updated: Product) -> StorageResult<()> {
+    let event = ChangeEvent::ProductUpdated(Arc::new(updated.get_spec_safe_product()));
+    match self.driver.update_product(product_id, updated).await {
+      Ok(true) => {
+        self.publish(event);
+        Ok(())
+      }
+      Ok(false) => Err(StorageError::NotFound),
+      Err(e) => Err(logged(e, "updating product")),
+    }
+  }
+
+  /// Given a unique product ID, adds a user to the product's users unless it's already one
+  ///
+  /// Fails with `StorageError::NotFound` if no product has the ID
+  pub async fn add_product_user(&self, product_id: &str, user_id: &str) -> StorageResult<()> {
+    match self.driver.add_product_user(product_id, user_id).await {
+      Ok(Some(product)) => {
+        self.publish(ChangeEvent::ProductUpdated(Arc::new(product.get_spec_safe_product())));
+        Ok(())
+      }
+      Ok(None) => Err(StorageError::NotFound),
+      Err(e) => Err(logged(e, "adding user to product")),
+    }
   }
 
-  /// Given a product id, and flag name, returns a fully constructed `User`
+  /// Given a unique product ID, removes a user from the product's users
+  ///
+  /// Fails with `StorageError::NotFound` if no product has the ID
+  pub async fn remove_product_user(&self, product_id: &str, user_id: &str) -> StorageResult<()> {
+    match self.driver.remove_product_user(product_id, user_id).await {
+      Ok(Some(product)) => {
+        self.publish(ChangeEvent::ProductUpdated(Arc::new(product.get_spec_safe_product())));
+        Ok(())
+      }
+      Ok(None) => Err(StorageError::NotFound),
+      Err(e) => Err(logged(e, "removing user from product")),
+    }
+  }
+
+  /// Given a unique product ID and the IDs of its flags, deletes the product, its flags, their comments, its plugins,
+  /// and its API keys from the database, in one transaction
+  ///
+  /// Fails with `StorageError::NotFound` if no product has the ID
+  pub async fn delete_product(&self, product_id: &str, flag_ids: &[String]) -> StorageResult<()> {
+    let mut cached = vec![];
+    if self.cache.is_some() {
+      let filter = FlagFilter {
+        include_archived: true,
+        ..FlagFilter::default()
+      };
+      if let Ok(flags) = self
+        .driver
+        .get_feature_flags(product_id, filter, FlagProjection::Summary)
+        .await
+      {
+        cached = flags.into_iter().map(|x| (x.product_id, x.name)).collect();
+      }
+    }
+
+    let deleted = self
+      .driver
+      .delete_product(product_id, flag_ids)
+      .await
+      .map_err(|e| logged(e, "deleting product"))?;
+
+    self.uncache_flags(&cached).await;
+    for flag_id in flag_ids {
+      self.publish(ChangeEvent::FlagDeleted {
+        product_id: product_id.to_string(),
+        flag_id: flag_id.clone(),
+      });
+    }
+
+    match deleted {
+      true => {
+        self.publish(ChangeEvent::ProductDeleted {
+          product_id: product_id.to_string(),
+        });
+        Ok(())
+      }
+      false => Err(StorageError::NotFound),
+    }
+  }
+
+  /// Given a unique product ID, applies an import to the product in one transaction: replaces the product with
+  /// `product` if given, replaces the flags of `updated` by their IDs, and creates the flags of `created`
+  ///
+  /// Sets `updated_at` on the updated flags, and `created_at` and `updated_at` on the created ones, to the current time.
+  /// Nothing is written if a write fails (unless the database doesn't support transactions)
   ///
-  /// Returns `User` inside of an `Option<User>`. If anything goes wrong, this function will return `None`
-  pub async fn get_user(&self, user_email: Option<&str>, user_id: Option<&str>) -> Option<User> {
+  /// Returns the created flags, in the order of the builders
+  pub async fn import_product(
+    &self,
+    product_id: &str,
+    product: Option<Product>,
+    mut updated: Vec<FeatureFlag>,
+    created: Vec<FeatureFlagBuilder>,
+  ) -> StorageResult<Vec<FeatureFlag>> {
+    let now = Utc::now();
+    for flag in updated.iter_mut() {
+      flag.updated_at = Some(now);
+    }
+    let created = created
+      .into_iter()
+      .map(|x| x.with_created_at(now).with_updated_at(now))
+      .collect();
+
+    let mut events = vec![];
+    if let Some(product) = &product {
+      events.push(ChangeEvent::ProductUpdated(Arc::new(product.get_spec_safe_product())));
+    }
+    for flag in &updated {
+      events.push(ChangeEvent::FlagUpdated(Arc::new(flag.get_spec_safe_feature_flag())));
+    }
+    let cached: Vec<(String, String)> = updated.iter().map(|x| (x.product_id.clone(), x.name.clone())).collect();
+
+    let created = self
+      .driver
+      .import_product(product_id, product, updated, created)
+      .await
+      .map_err(|e| logged(e, "importing product"))?;
+
+    self.uncache_flags(&cached).await;
+    for event in events {
+      self.publish(event);
+    }
+    for flag in &created {
+      self.publish(ChangeEvent::FlagUpdated(Arc::new(flag.get_spec_safe_feature_flag())));
+    }
+    Ok(created)
+  }
+
+  /// given a unique user ID and a fully constructed User struct, will update said user in the database
+  ///
+  /// Fails with `StorageError::NotFound` if no user has the ID
+  pub async fn update_user(&self, user_id: &str, updated: User) -> StorageResult<()> {
+    match self.driver.update_user(user_id, updated).await {
+      Ok(true) => Ok(()),
+      Ok(false) => Err(StorageError::NotFound),
+      Err(e) => Err(logged(e, "updating user")),
+    }
+  }
+
+  /// Given a user email and/or ID, returns a fully constructed `User`
+  ///
+  /// Returns `None` if no user matches, or neither `user_email` nor `user_id` are given
+  pub async fn get_user(&self, user_email: Option<&str>, user_id: Option<&str>) -> StorageResult<Option<User>> {
     if user_email.is_none() && user_id.is_none() {
-      println!("Error getting user, must provide at least one `user_email` or `user_id`");
-      return None;
-    }
-
-    match &self.connection_type {
-      ConnectionType::MongoDB => match mongo::get_user(user_email, user_id).await {
-        Ok(user) => user,
-        Err(e) => {
-          println!(
-            "Error getting user from email '{}' and/or id '{}'. Returning Option::None. Error: {:?}",
-            user_email.unwrap_or("[Not Provided]"),
-            user_id.unwrap_or("[Not Provided]"),
-            e
-          );
-          None
-        }
-      },
+      log!("Error getting user, must provide at least one `user_email` or `user_id`");
+      return Ok(None);
     }
+
+    self.driver.get_user(user_email, user_id).await.map_err(|e| {
+      logged(
+        e,
+        &format!(
+          "getting user from email '{}' and/or id '{}'",
+          user_email.unwrap_or("[Not Provided]"),
+          user_id.unwrap_or("[Not Provided]")
+        ),
+      )
+    })
   }
 
-  /// Returns all users of a given acount type
-  pub async fn get_users(&self, account_type: Option<AccountType>) -> Vec<User> {
-    match &self.connection_type {
-      ConnectionType::MongoDB => match mongo::get_users(account_type).await {
-        Ok(users) => users,
-        Err(e) => {
-          println!("Error getting users. Returning empty list: Error: {:?}", e);
-          return vec![];
-        }
-      },
+  /// Returns the users of a given acount type in `page`
+  ///
+  /// The users are fetched without their password hash, so they must never be written back
+  pub async fn get_users(&self, account_type: Option<AccountType>, page: Page) -> StorageResult<Vec<User>> {
+    self
+      .driver
+      .get_users(account_type, page)
+      .await
+      .map_err(|e| logged(e, "getting users"))
+  }
+
+  /// Given a list of user IDs, returns every matching user using a single query
+  ///
+  /// Returns an empty `Vec<User>` if no users are found. The users are fetched without their password hash, so they
+  /// must never be written back
+  pub async fn get_users_by_ids(&self, user_ids: &[String]) -> StorageResult<Vec<User>> {
+    if user_ids.is_empty() {
+      return Ok(vec![]);
     }
+
+    self
+      .driver
+      .get_users_by_ids(user_ids)
+      .await
+      .map_err(|e| logged(e, "getting users by IDs"))
   }
 
   /// creates a product given a partially compleate `ProductBuilder`
   ///
   /// This expects that the only missing element in the `ProductBuilder` is the `oid`
   ///
-  /// Returns fully constructed product inside an `Option`
-  pub async fn create_product(&self, product_builder: ProductBuilder) -> Option<Product> {
-    match &self.connection_type {
-      ConnectionType::MongoDB => match mongo::create_product(product_builder).await {
-        Ok(value) => Some(value),
-        Err(e) => {
-          println!("Error creating product. Returning Option::None. Error {:?}", e);
-          None
-        }
-      },
-    }
+  /// Returns the fully constructed product
+  pub async fn create_product(&self, product_builder: ProductBuilder) -> StorageResult<Product> {
+    let product = self
+      .driver
+      .create_product(product_builder)
+      .await
+      .map_err(|e| logged(e, "creating product"))?;
+
+    self.publish(ChangeEvent::ProductUpdated(Arc::new(product.get_spec_safe_product())));
+    Ok(product)
   }
 
   /// Creates a feature flag given a partially constructed `FeatureFlagBuilder`
   ///
-  /// This expects that the only missing element in the `FeatureFlagBuilder` is the `oid`
+  /// This expects that the only missing element in the `FeatureFlagBuilder` is the `oid`. The `created_at` and
+  /// `updated_at` timestamps are set here
   ///
-  /// Returns a fully constructed product inside of an `Option`
-  pub async fn create_flag(&self, flag_builder: FeatureFlagBuilder) -> Option<FeatureFlag> {
-    match &self.connection_type {
-      ConnectionType::MongoDB => match mongo::create_flag(flag_builder).await {
-        Ok(value) => Some(value),
-        Err(e) => {
-          println!("Error creating flag. Returning Option::None. Error {:?}", e);
-          None
-        }
-      },
+  /// Returns the fully constructed flag
+  pub async fn create_flag(&self, flag_builder: FeatureFlagBuilder) -> StorageResult<FeatureFlag> {
+    let now = Utc::now();
+    let flag_builder = flag_builder.with_created_at(now).with_updated_at(now);
+
+    let flag = self
+      .driver
+      .create_flag(flag_builder)
+      .await
+      .map_err(|e| logged(e, "creating flag"))?;
+
+    self.publish(ChangeEvent::FlagUpdated(Arc::new(flag.get_spec_safe_feature_flag())));
+    Ok(flag)
+  }
+
+  /// Creates feature flags given partially constructed `FeatureFlagBuilder`s, in a single write
+  ///
+  /// The `created_at` and `updated_at` timestamps are set here
+  ///
+  /// Returns each flag, or `None` if it failed to be created, in the order of the builders
+  pub async fn create_flags(&self, flag_builders: Vec<FeatureFlagBuilder>) -> StorageResult<Vec<Option<FeatureFlag>>> {
+    let now = Utc::now();
+    let flag_builders = flag_builders
+      .into_iter()
+      .map(|x| x.with_created_at(now).with_updated_at(now))
+      .collect();
+
+    let flags = self
+      .driver
+      .create_flags(flag_builders)
+      .await
+      .map_err(|e| logged(e, "creating flags"))?;
+
+    for flag in flags.iter().flatten() {
+      self.publish(ChangeEvent::FlagUpdated(Arc::new(flag.get_spec_safe_feature_flag())));
     }
+    Ok(flags)
+  }
+
+  /// Counts the users, 0 for a fresh deployment
+  pub async fn count_users(&self) -> StorageResult<u64> {
+    self.driver.count_users().await.map_err(|e| logged(e, "counting users"))
   }
 
   /// Creates a user from a given `UserBuilder`
   ///
   /// It's expected that all values besides `UserBuilder.oid` are set. `UserBuilder.oid` will be set by the database
-  pub async fn create_user(&self, user_builder: UserBuilder) -> Option<User> {
-    match &self.connection_type {
-      ConnectionType::MongoDB => match mongo::create_user(user_builder).await {
-        Ok(value) => Some(value),
-        Err(e) => {
-          println!("Error creating user. Returning Option::None. Error {:?}", e);
-          None
-        }
-      },
+  pub async fn create_user(&self, user_builder: UserBuilder) -> StorageResult<User> {
+    self
+      .driver
+      .create_user(user_builder)
+      .await
+      .map_err(|e| logged(e, "creating user"))
+  }
+
+  /// Records a change in the audit log
+  ///
+  /// The error is logged here, so callers that don't fail the change itself on it can ignore it
+  pub async fn create_audit_entry(&self, entry_builder: AuditEntryBuilder) -> StorageResult<()> {
+    self
+      .driver
+      .create_audit_entry(entry_builder.build())
+      .await
+      .map_err(|e| logged(e, "recording audit entry"))
+  }
+
+  /// Creates a comment from a given `CommentBuilder`
+  ///
+  /// The returned `Comment` contains the ID generated by the database
+  pub async fn create_comment(&self, comment_builder: CommentBuilder) -> StorageResult<Comment> {
+    self
+      .driver
+      .create_comment(comment_builder.build())
+      .await
+      .map_err(|e| logged(e, "creating comment"))
+  }
+
+  /// Given a flag ID returns the comments on the flag, oldest first
+  ///
+  /// Returns an empty `Vec<Comment>` if no comments are found
+  pub async fn get_comments(&self, flag_id: &str) -> StorageResult<Vec<Comment>> {
+    self
+      .driver
+      .get_comments(flag_id)
+      .await
+      .map_err(|e| logged(e, &format!("getting comments for flag '{}'", flag_id)))
+  }
+
+  /// Creates a plugin from a given `PluginBuilder`
+  ///
+  /// The returned `Plugin` contains the ID generated by the database
+  pub async fn create_plugin(&self, plugin_builder: PluginBuilder) -> StorageResult<Plugin> {
+    self
+      .driver
+      .create_plugin(plugin_builder.build())
+      .await
+      .map_err(|e| logged(e, "creating plugin"))
+  }
+
+  /// Given a plugin ID, returns the `Plugin` from the database
+  ///
+  /// Returns `None` if no plugin has the ID
+  pub async fn get_plugin(&self, plugin_id: &str) -> StorageResult<Option<Plugin>> {
+    self
+      .driver
+      .get_plugin(plugin_id)
+      .await
+      .map_err(|e| logged(e, &format!("getting plugin with id '{}'", plugin_id)))
+  }
+
+  /// Creates a webhook from a given `WebhookBuilder`
+  ///
+  /// Returns the webhook with its generated ID
+  pub async fn create_webhook(&self, webhook_builder: WebhookBuilder) -> StorageResult<Webhook> {
+    self
+      .driver
+      .create_webhook(webhook_builder.build())
+      .await
+      .map_err(|e| logged(e, "creating webhook"))
+  }
+
+  /// Given a webhook ID, returns the `Webhook` from the database
+  ///
+  /// Returns `None` if no webhook has the ID
+  pub async fn get_webhook(&self, webhook_id: &str) -> StorageResult<Option<Webhook>> {
+    self
+      .driver
+      .get_webhook(webhook_id)
+      .await
+      .map_err(|e| logged(e, &format!("getting webhook with id '{}'", webhook_id)))
+  }
+
+  /// Returns every webhook, or only those of the product of ID `product_id`
+  ///
+  /// Returns an empty `Vec<Webhook>` if no webhooks are found
+  pub async fn get_webhooks(&self, product_id: Option<&str>) -> StorageResult<Vec<Webhook>> {
+    self
+      .driver
+      .get_webhooks(product_id)
+      .await
+      .map_err(|e| logged(e, "getting webhooks"))
+  }
+
+  /// given a unique webhook ID and a fully constructed Webhook struct, will update said webhook in the database
+  ///
+  /// Fails with `StorageError::NotFound` if no webhook has the ID
+  pub async fn update_webhook(&self, webhook_id: &str, updated: Webhook) -> StorageResult<()> {
+    match self.driver.update_webhook(webhook_id, updated).await {
+      Ok(true) => Ok(()),
+      Ok(false) => Err(StorageError::NotFound),
+      Err(e) => Err(logged(e, "updating webhook")),
     }
   }
+
+  /// Given a unique webhook ID, deletes the webhook and its deliveries from the database
+  ///
+  /// Fails with `StorageError::NotFound` if no webhook has the ID
+  pub async fn delete_webhook(&self, webhook_id: &str) -> StorageResult<()> {
+    match self.driver.delete_webhook(webhook_id).await {
+      Ok(true) => Ok(()),
+      Ok(false) => Err(StorageError::NotFound),
+      Err(e) => Err(logged(e, "deleting webhook")),
+    }
+  }
+
+  /// Creates an API key from a given `ApiKeyBuilder`
+  ///
+  /// Returns the API key with its generated ID
+  pub async fn create_api_key(&self, api_key_builder: ApiKeyBuilder) -> StorageResult<ApiKey> {
+    self
+      .driver
+      .create_api_key(api_key_builder.build())
+      .await
+      .map_err(|e| logged(e, "creating API key"))
+  }
+
+  /// Given an API key ID, returns the `ApiKey` from the database
+  ///
+  /// Returns `None` if no key has the ID
+  pub async fn get_api_key(&self, api_key_id: &str) -> StorageResult<Option<ApiKey>> {
+    self
+      .driver
+      .get_api_key(api_key_id)
+      .await
+      .map_err(|e| logged(e, &format!("getting API key with id '{}'", api_key_id)))
+  }
+
+  /// Given the hash of a key, returns its `ApiKey` from the database, revoked or not
+  pub async fn get_api_key_by_hash(&self, key_hash: &str) -> StorageResult<Option<ApiKey>> {
+    self
+      .driver
+      .get_api_key_by_hash(key_hash)
+      .await
+      .map_err(|e| logged(e, "getting API key by hash"))
+  }
+
+  /// Returns every API key of the product of ID `product_id`, revoked ones included
+  ///
+  /// Returns an empty `Vec<ApiKey>` if no keys are found
+  pub async fn get_api_keys(&self, product_id: &str) -> StorageResult<Vec<ApiKey>> {
+    self
+      .driver
+      .get_api_keys(product_id)
+      .await
+      .map_err(|e| logged(e, "getting API keys"))
+  }
+
+  /// Given a unique API key ID, revokes the key so it no longer authorizes anything
+  ///
+  /// Returns if a key was revoked, `false` if it already was
+  pub async fn revoke_api_key(&self, api_key_id: &str) -> StorageResult<bool> {
+    self
+      .driver
+      .revoke_api_key(api_key_id, Utc::now())
+      .await
+      .map_err(|e| logged(e, "revoking API key"))
+  }
+
+  /// Creates the indexes of API keys. Does nothing for indexes that already exist
+  pub async fn create_api_key_indexes(&self) -> StorageResult<()> {
+    self
+      .driver
+      .create_api_key_indexes()
+      .await
+      .map_err(|e| logged(e, "creating API key indexes"))
+  }
+
+  /// Creates a service account from a given `ServiceAccountBuilder`
+  ///
+  /// Returns the service account with its generated ID
+  pub async fn create_service_account(
+    &self,
+    service_account_builder: ServiceAccountBuilder,
+  ) -> StorageResult<ServiceAccount> {
+    self
+      .driver
+      .create_service_account(service_account_builder.build())
+      .await
+      .map_err(|e| logged(e, "creating service account"))
+  }
+
+  /// Given a service account ID, returns the `ServiceAccount` from the database
+  ///
+  /// Returns `None` if no service account has the ID
+  pub async fn get_service_account(&self, service_account_id: &str) -> StorageResult<Option<ServiceAccount>> {
+    self
+      .driver
+      .get_service_account(service_account_id)
+      .await
+      .map_err(|e| logged(e, &format!("getting service account with id '{}'", service_account_id)))
+  }
+
+  /// Given the hash of a token, returns its `ServiceAccount` from the database, revoked or not
+  pub async fn get_service_account_by_hash(&self, token_hash: &str) -> StorageResult<Option<ServiceAccount>> {
+    self
+      .driver
+      .get_service_account_by_hash(token_hash)
+      .await
+      .map_err(|e| logged(e, "getting service account by hash"))
+  }
+
+  /// Returns every service account, revoked ones included
+  ///
+  /// Returns an empty `Vec<ServiceAccount>` if no accounts are found
+  pub async fn get_service_accounts(&self) -> StorageResult<Vec<ServiceAccount>> {
+    self
+      .driver
+      .get_service_accounts()
+      .await
+      .map_err(|e| logged(e, "getting service accounts"))
+  }
+
+  /// Given a unique service account ID, revokes its token so it no longer authorizes anything
+  ///
+  /// Returns if a token was revoked, `false` if it already was
+  pub async fn revoke_service_account(&self, service_account_id: &str) -> StorageResult<bool> {
+    self
+      .driver
+      .revoke_service_account(service_account_id, Utc::now())
+      .await
+      .map_err(|e| logged(e, "revoking service account"))
+  }
+
+  /// Creates the indexes of service accounts. Does nothing for indexes that already exist
+  pub async fn create_service_account_indexes(&self) -> StorageResult<()> {
+    self
+      .driver
+      .create_service_account_indexes()
+      .await
+      .map_err(|e| logged(e, "creating service account indexes"))
+  }
+
+  /// Creates a session from a given `SessionBuilder`
+  ///
+  /// Returns the session with its generated ID
+  pub async fn create_session(&self, session_builder: SessionBuilder) -> StorageResult<Session> {
+    self
+      .driver
+      .create_session(session_builder.build())
+      .await
+      .map_err(|e| logged(e, "creating session"))
+  }
+
+  /// Given a user ID and the hash of an auth token, returns their unexpired `Session` from the database
+  pub async fn get_session(&self, user_id: &str, token_hash: &str) -> StorageResult<Option<Session>> {
+    self
+      .driver
+      .get_session(user_id, token_hash)
+      .await
+      .map_err(|e| logged(e, "getting session"))
+  }
+
+  /// Extends the unexpired session of the given user and auth token hash by `ttl` from now
+  ///
+  /// Returns the refreshed `Session`, `None` if it wasn't found
+  pub async fn refresh_session(
+    &self,
+    user_id: &str,
+    token_hash: &str,
+    ttl: chrono::Duration,
+  ) -> StorageResult<Option<Session>> {
+    self
+      .driver
+      .refresh_session(user_id, token_hash, Utc::now() + ttl)
+      .await
+      .map_err(|e| logged(e, "refreshing session"))
+  }
+
+  /// Deletes the unexpired session of the given user and auth token hash, logging out of it alone
+  ///
+  /// Returns if a session was deleted
+  pub async fn delete_session(&self, user_id: &str, token_hash: &str) -> StorageResult<bool> {
+    self
+      .driver
+      .delete_session(user_id, token_hash)
+      .await
+      .map_err(|e| logged(e, "deleting session"))
+  }
+
+  /// Deletes every session of a user, logging them out everywhere
+  ///
+  /// Returns the number of unexpired sessions deleted
+  pub async fn delete_sessions(&self, user_id: &str) -> StorageResult<u64> {
+    self
+      .driver
+      .delete_sessions(user_id)
+      .await
+      .map_err(|e| logged(e, "deleting sessions"))
+  }
+
+  /// Creates the indexes of sessions. Does nothing for indexes that already exist
+  pub async fn create_session_indexes(&self) -> StorageResult<()> {
+    self
+      .driver
+      .create_session_indexes()
+      .await
+      .map_err(|e| logged(e, "creating session indexes"))
+  }
+
+  /// Records a new delivery
+  pub async fn create_webhook_delivery(&self, delivery: &WebhookDelivery) -> StorageResult<()> {
+    self
+      .driver
+      .create_webhook_delivery(delivery)
+      .await
+      .map_err(|e| logged(e, "creating webhook delivery"))
+  }
+
+  /// Records the latest state of a delivery
+  pub async fn update_webhook_delivery(&self, delivery: &WebhookDelivery) -> StorageResult<()> {
+    self
+      .driver
+      .update_webhook_delivery(delivery)
+      .await
+      .map_err(|e| logged(e, "updating webhook delivery"))
+  }
+
+  /// Given a delivery ID, returns the `WebhookDelivery` from the database
+  ///
+  /// Returns `None` if no delivery has the ID
+  pub async fn get_webhook_delivery(&self, delivery_id: &str) -> StorageResult<Option<WebhookDelivery>> {
+    self
+      .driver
+      .get_webhook_delivery(delivery_id)
+      .await
+      .map_err(|e| logged(e, &format!("getting webhook delivery with id '{}'", delivery_id)))
+  }
+
+  /// Returns up to `limit` deliveries to the webhook of ID `webhook_id`, newest first, optionally only those in
+  /// `status` (e.g. the dead letters)
+  ///
+  /// Returns an empty `Vec<WebhookDelivery>` if no deliveries are found
+  pub async fn get_webhook_deliveries(
+    &self,
+    webhook_id: &str,
+    status: Option<DeliveryStatus>,
+    limit: i64,
+  ) -> StorageResult<Vec<WebhookDelivery>> {
+    self
+      .driver
+      .get_webhook_deliveries(webhook_id, status, limit)
+      .await
+      .map_err(|e| logged(e, "getting webhook deliveries"))
+  }
+
+  /// Returns the flags with scheduled changes due at `now`
+  ///
+  /// Returns an empty `Vec<FeatureFlag>` if no flags are found
+  pub async fn get_feature_flags_with_due_changes(&self, now: DateTime<Utc>) -> StorageResult<Vec<FeatureFlag>> {
+    self
+      .driver
+      .get_feature_flags_with_due_changes(now)
+      .await
+      .map_err(|e| logged(e, "getting feature flags with due changes"))
+  }
+
+  /// Sets `field` of a flag and removes its scheduled change of ID `change_id`, in a single atomic update made only if
+  /// the change is still scheduled, so it's applied once even when several instances run the scheduler. Sets
+  /// `updated_at` on the flag to the current time
+  ///
+  /// Returns the updated flag, `None` if the flag isn't found or the change was already applied
+  pub async fn apply_scheduled_change(
+    &self,
+    feature_flag_id: &str,
+    change_id: ObjectId,
+    field: &ChangedField,
+  ) -> StorageResult<Option<FeatureFlag>> {
+    match self
+      .driver
+      .apply_scheduled_change(feature_flag_id, change_id, field, Utc::now())
+      .await
+    {
+      Ok(Some(flag)) => {
+        self
+          .uncache_flags(&[(flag.product_id.clone(), flag.name.clone())])
+          .await;
+        self.publish(ChangeEvent::FlagUpdated(Arc::new(flag.get_spec_safe_feature_flag())));
+        Ok(Some(flag))
+      }
+      Ok(None) => Ok(None),
+      Err(e) => Err(logged(e, "applying scheduled change")),
+    }
+  }
+
+  /// Lists entries of the audit log matching the `filter`, newest first
+  ///
+  /// Entries are paginated by cursor, pass the ID of the last entry of a page as `after` to get the next page
+  ///
+  /// Returns `None` if `after` isn't a valid ID
+  pub async fn get_audit_entries(
+    &self,
+    filter: &AuditFilter,
+    after: Option<&str>,
+    limit: i64,
+  ) -> StorageResult<Option<Vec<AuditEntry>>> {
+    self
+      .driver
+      .get_audit_entries(filter, after, limit)
+      .await
+      .map_err(|e| logged(e, "getting audit entries"))
+  }
+
+  /// Gets up to `limit` flags whose name starts with `term`, ignoring case, with the fields of `FlagProjection::Summary`
+  ///
+  /// Returns an empty `Vec<FeatureFlag>` if nothing matches
+  pub async fn search_feature_flags(&self, term: &str, limit: i64) -> StorageResult<Vec<FeatureFlag>> {
+    self
+      .driver
+      .search_feature_flags(term, limit)
+      .await
+      .map_err(|e| logged(e, "searching flags"))
+  }
+
+  /// Gets up to `limit` products whose name starts with `term`, ignoring case
+  ///
+  /// Returns an empty `Vec<Product>` if nothing matches
+  pub async fn search_products(&self, term: &str, limit: i64) -> StorageResult<Vec<Product>> {
+    self
+      .driver
+      .search_products(term, limit)
+      .await
+      .map_err(|e| logged(e, "searching products"))
+  }
+
+  /// Gets up to `limit` users whose email starts with `term`, ignoring case, without their password hash
+  ///
+  /// Returns an empty `Vec<User>` if nothing matches
+  pub async fn search_users(&self, term: &str, limit: i64) -> StorageResult<Vec<User>> {
+    self
+      .driver
+      .search_users(term, limit)
+      .await
+      .map_err(|e| logged(e, "searching users"))
+  }
+
+  /// Creates the indexes backing `/search`, does nothing for indexes that already exist
+  pub async fn create_search_indexes(&self) -> StorageResult<()> {
+    self
+      .driver
+      .create_search_indexes()
+      .await
+      .map_err(|e| logged(e, "creating search indexes"))
+  }
+
+  /// Pings the database, failing if it isn't reachable
+  pub async fn ping(&self) -> StorageResult<()> {
+    self.driver.ping().await.map_err(|e| logged(e, "pinging database"))
+  }
+
+  /// Checks the database is up with a ping, which is cheap for every driver, reporting it down if it fails or takes
+  /// longer than 2 seconds
+  pub async fn health(&self) -> DatabaseHealth {
+    let start = Instant::now();
+    let up = match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, self.ping()).await {
+      Ok(result) => result.is_ok(),
+      Err(_) => {
+        log!(
+          "Error pinging database: no answer in {}ms",
+          HEALTH_CHECK_TIMEOUT.as_millis()
+        );
+        false
+      }
+    };
+
+    DatabaseHealth {
+      up,
+      latency: start.elapsed(),
+    }
+  }
+
+  /// Creates the indexes backing the audit log filters, does nothing for indexes that already exist
+  pub async fn create_audit_indexes(&self) -> StorageResult<()> {
+    self
+      .driver
+      .create_audit_indexes()
+      .await
+      .map_err(|e| logged(e, "creating audit indexes"))
+  }
+
+  /// Adds evaluations made during the hour starting at `hour` to the hourly counts of their products
+  pub async fn add_evaluation_counts(&self, hour: DateTime<Utc>, counts: &HashMap<String, u64>) -> StorageResult<()> {
+    self
+      .driver
+      .add_evaluation_counts(hour, counts)
+      .await
+      .map_err(|e| logged(e, "adding evaluation counts"))
+  }
+
+  /// Creates the indexes of the hourly evaluation counts, which expire after `retention`. Does nothing for indexes that
+  /// already exist
+  pub async fn create_evaluation_count_indexes(&self, retention: Duration) -> StorageResult<()> {
+    self
+      .driver
+      .create_evaluation_count_indexes(retention)
+      .await
+      .map_err(|e| logged(e, "creating evaluation count indexes"))
+  }
+
+  /// Reads every stored document for a backup, users with their password hash
+  pub async fn export_backup(&self) -> StorageResult<Backup> {
+    self
+      .driver
+      .export_backup()
+      .await
+      .map_err(|e| logged(e, "exporting a backup"))
+  }
+
+  /// Writes every document of `backup`, replacing the stored documents with the same IDs and keeping the others
+  pub async fn restore_backup(&self, backup: &Backup) -> StorageResult<()> {
+    self
+      .driver
+      .restore_backup(backup)
+      .await
+      .map_err(|e| logged(e, "restoring a backup"))?;
+
+    let cached: Vec<(String, String)> = backup
+      .flags
+      .iter()
+      .map(|x| (x.product_id.clone(), x.name.clone()))
+      .collect();
+    self.uncache_flags(&cached).await;
+
+    Ok(())
+  }
+
+  /// Applies the data migrations that haven't been yet, in order, returning the IDs of the ones applied
+  pub async fn run_migrations(&self) -> StorageResult<Vec<String>> {
+    self
+      .driver
+      .run_migrations()
+      .await
+      .map_err(|e| logged(e, "running migrations"))
+  }
+
+  /// Reverts the last data migration applied, returning its ID, `None` if no migration was applied
+  pub async fn revert_last_migration(&self) -> StorageResult<Option<String>> {
+    self
+      .driver
+      .revert_last_migration()
+      .await
+      .map_err(|e| logged(e, "reverting the last migration"))
+  }
+
+  /// Counts the products, flags (by state, release type and product), users (by account type), logged in sessions, and
+  /// the evaluations made since `since`, aggregated by the database
+  pub async fn aggregate_counts(&self, since: DateTime<Utc>) -> StorageResult<Stats> {
+    self
+      .driver
+      .aggregate_counts(since)
+      .await
+      .map_err(|e| logged(e, "getting stats"))
+  }
+}
+
+/// Logs the error of a failed operation, described by `action` (e.g. `getting products`), and returns it
+fn logged(e: StorageError, action: &str) -> StorageError {
+  log!("Error {}. Error: {}", action, e);
+  e
 }
@@ -0,0 +1,213 @@
+//! Change streams of the flags and products collections, so changes written through any instance reach the live
+//! streams of every instance
+//!
+//! Change streams need a replica set (or a sharded cluster). They're read with a `$changeStream` aggregation on the
+//! `data` database, resumed after the last change read when the connection drops. Deletes only carry the ID of the
+//! deleted document, so the product and name of every flag are kept in memory to tell which product a deleted flag
+//! belonged to
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use futures::stream::TryStreamExt;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{self, doc, Bson, Document};
+use mongodb::error::{self, ErrorKind};
+use mongodb::options::{AggregateOptions, FindOptions};
+use mongodb::Client;
+use tokio::sync::mpsc;
+
+use crate::controller::database::driver::StoredChange;
+use crate::log;
+use crate::model::flag::FeatureFlag;
+
+/// Longest the server waits for a change before answering an empty batch
+const MAX_AWAIT_TIME: Duration = Duration::from_secs(1);
+
+/// First delay before reopening a stream that failed, doubled on each failure in a row
+const BASE_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest delay before reopening a stream that failed
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Codes of errors that retrying won't fix: change streams not supported by the deployment (standalone servers and
+/// storage engines without them), and not being allowed to read them
+const UNSUPPORTED_CODES: [i32; 3] = [13, 40573, 136];
+
+/// Code of the error raised when a stream can't be resumed, its resume token fell out of the oplog
+const HISTORY_LOST_CODE: i32 = 286;
+
+/// Product and name of a flag
+type FlagKey = (String, String);
+
+/// Sends the changes to flags and products to `changes`, reopening the stream when it fails, until `changes` is closed
+///
+/// Returns an error if the deployment doesn't support change streams
+pub async fn watch(client: &Client, changes: mpsc::Sender<StoredChange>) -> error::Result<()> {
+  let mut resume_token: Option<Document> = None;
+  let mut flags: HashMap<ObjectId, FlagKey> = HashMap::new();
+  let mut delay = BASE_RETRY_DELAY;
+
+  loop {
+    let resumed_from = resume_token.clone();
+
+    match read(client, &changes, &mut resume_token, &mut flags).await {
+      // `changes` was closed, nothing is listening anymore
+      Ok(()) => return Ok(()),
+      Err(e) if code(&e).is_some_and(|x| UNSUPPORTED_CODES.contains(&x)) => return Err(e),
+      Err(e) => {
+        // The changes made while the stream was down are lost, flags are read again when it's reopened
+        if code(&e) == Some(HISTORY_LOST_CODE) {
+          resume_token = None;
+        }
+        // Streams that read changes before failing aren't failing in a row
+        if resume_token.is_some() && resume_token != resumed_from {
+          delay = BASE_RETRY_DELAY;
+        }
+
+        log!(
+          "Error reading the change stream, reopening it in {}ms. Error: {:?}",
+          delay.as_millis(),
+          e
+        );
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+      }
+    }
+  }
+}
+
+/// Opens a stream, resumed after `resume_token` if there's one, and sends its changes until it fails or `changes` is
+/// closed
+async fn read(
+  client: &Client,
+  changes: &mpsc::Sender<StoredChange>,
+  resume_token: &mut Option<Document>,
+  flags: &mut HashMap<ObjectId, FlagKey>,
+) -> error::Result<()> {
+  let db = client.database("data");
+
+  let mut stage = doc! {"fullDocument": "updateLookup"};
+  if let Some(token) = resume_token.clone() {
+    stage.insert("resumeAfter", token);
+  }
+  let pipeline = vec![
+    doc! {"$changeStream": stage},
+    doc! {"$match": {"ns.coll": {"$in": ["features", "products"]}}},
+  ];
+  let options = AggregateOptions::builder().max_await_time(MAX_AWAIT_TIME).build();
+
+  // Opened before the flags are read, so flags created in between aren't missed
+  let mut stream = db.aggregate(pipeline, options).await?;
+
+  if resume_token.is_none() {
+    *flags = read_flag_keys(&db).await?;
+  }
+
+  while let Some(event) = stream.try_next().await? {
+    if let Ok(token) = event.get_document("_id") {
+      *resume_token = Some(token.clone());
+    }
+
+    let change = match to_change(&event, flags) {
+      Some(change) => change,
+      None => continue,
+    };
+
+    if changes.send(change).await.is_err() {
+      return Ok(());
+    }
+  }
+
+  // Streams end when their collection or database is dropped or renamed, and can't be resumed after that
+  *resume_token = None;
+  Err(error::Error::from(std::io::Error::other(
+    "the change stream was invalidated",
+  )))
+}
+
+/// Reads the product and name of every flag
+async fn read_flag_keys(db: &mongodb::Database) -> error::Result<HashMap<ObjectId, FlagKey>> {
+  let options = FindOptions::builder()
+    .projection(doc! {"product_id": 1, "name": 1})
+    .build();
+
+  let mut keys = HashMap::new();
+  let mut cursor = db.collection::<Document>("features").find(None, options).await?;
+  while let Some(flag) = cursor.try_next().await? {
+    if let (Ok(oid), Ok(product_id), Ok(name)) = (
+      flag.get_object_id("_id"),
+      flag.get_str("product_id"),
+      flag.get_str("name"),
+    ) {
+      keys.insert(oid, (product_id.to_string(), name.to_string()));
+    }
+  }
+
+  Ok(keys)
+}
+
+/// Returns the change an event of the stream is, `None` for events that don't change flags or products (or whose
+/// document was deleted before it could be looked up)
+fn to_change(event: &Document, flags: &mut HashMap<ObjectId, FlagKey>) -> Option<StoredChange> {
+  let collection = event.get_document("ns").ok()?.get_str("coll").ok()?;
+  let oid = event.get_document("documentKey").ok()?.get_object_id("_id").ok()?;
+
+  match (collection, event.get_str("operationType").ok()?) {
+    ("features", "insert" | "update" | "replace") => {
+      let flag: FeatureFlag = from_full_document(event)?;
+      let previous = flags.insert(oid, (flag.product_id.clone(), flag.name.clone()));
+
+      Some(StoredChange::FlagUpdated {
+        previous_name: previous.map(|(_, name)| name).filter(|x| *x != flag.name),
+        flag: Box::new(flag),
+      })
+    }
+    ("features", "delete") => match flags.remove(&oid) {
+      Some((product_id, name)) => Some(StoredChange::FlagDeleted {
+        flag_id: oid.to_hex(),
+        product_id,
+        name,
+      }),
+      None => {
+        log!(
+          "Flag '{}' was deleted, but its product isn't known. Ignoring it",
+          oid.to_hex()
+        );
+        None
+      }
+    },
+    ("products", "insert" | "update" | "replace") => {
+      Some(StoredChange::ProductUpdated(Box::new(from_full_document(event)?)))
+    }
+    ("products", "delete") => Some(StoredChange::ProductDeleted {
+      product_id: oid.to_hex(),
+    }),
+    _ => None,
+  }
+}
+
+/// Deserializes the document an event changed, as looked up when the event was read
+fn from_full_document<T: serde::de::DeserializeOwned>(event: &Document) -> Option<T> {
+  match event.get("fullDocument")? {
+    Bson::Document(document) => match bson::from_document(document.clone()) {
+      Ok(value) => Some(value),
+      Err(e) => {
+        log!(
+          "Error reading a document of the change stream, ignoring it. Error: {:?}",
+          e
+        );
+        None
+      }
+    },
+    _ => None,
+  }
+}
+
+/// Code of a server error, `None` for errors the server didn't send
+fn code(e: &error::Error) -> Option<i32> {
+  match e.kind.as_ref() {
+    ErrorKind::Command(e) => Some(e.code),
+    _ => None,
+  }
+}
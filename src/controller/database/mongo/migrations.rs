@@ -0,0 +1,257 @@
+//! Data migrations of the documents stored in MongoDB, bringing documents written by older versions of the service up
+//! to date
+//!
+//! Migrations are applied in order at startup, before the service serves requests, each one recorded in the
+//! `_migrations` collection of the `data` database so it's only applied once. A migration is claimed in the collection
+//! as `running`, with a lease the instance applying it renews, and recorded as `applied` once it's done. Instances
+//! starting together wait for a migration another one is running rather than skip past it, and take it over once its
+//! lease expires (e.g. the instance stopped halfway). Migrations are written so applying one again changes nothing, and
+//! so documents they haven't reached yet still read the same, since instances already running serve requests while
+//! they run. Cached flags aren't invalidated, so migrations must not change what flags evaluate to
+//!
+//! The last migration applied can be reverted with `--revert-migration`, see `revert_last`
+
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::stream::TryStreamExt;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{doc, Document};
+use mongodb::error;
+use mongodb::options::FindOptions;
+use mongodb::{Client, Collection, Database};
+
+use crate::controller::database::mongo::is_duplicate_key;
+use crate::log;
+
+/// Name of the collection the applied migrations are recorded in
+const COLLECTION: &str = "_migrations";
+
+/// Status of a migration being applied
+const RUNNING: &str = "running";
+/// Status of a migration that was applied
+const APPLIED: &str = "applied";
+
+/// How long a claim on a running migration lasts without being renewed, before other instances take it over
+const LEASE: Duration = Duration::from_secs(120);
+/// How often instances check on a migration another one is running
+const WAIT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Step of a migration, run on the `data` database
+type Step = fn(Database) -> BoxFuture<'static, error::Result<()>>;
+
+/// Change to the stored documents, applied by `up` and reverted by `down`
+struct Migration {
+  /// Unique ID of the migration, migrations are applied in the order of their IDs
+  id: &'static str,
+  /// Human readable description of what the migration changes
+  description: &'static str,
+  up: Step,
+  down: Step,
+}
+
+/// Every migration, in the order they're applied
+const MIGRATIONS: &[Migration] = &[Migration {
+  id: "0001_flag_defaults",
+  description: "Stores the fields older flags were written without, with their default values",
+  up: |db| Box::pin(flag_defaults_up(db)),
+  down: |db| Box::pin(flag_defaults_down(db)),
+}];
+
+/// Fields added to flags after their first version, with the value they read as when they're missing
+fn flag_defaults() -> Document {
+  doc! {
+    "always_include": [],
+    "always_exclude": [],
+    "tags": [],
+    "prerequisites": [],
+    "rules": [],
+    "archived": false,
+    "permanent": false,
+    "scheduled_changes": [],
+  }
+}
+
+/// Sets each default field on the flags missing it
+async fn flag_defaults_up(db: Database) -> error::Result<()> {
+  let collection = db.collection::<Document>("features");
+
+  for (field, value) in flag_defaults() {
+    collection
+      .update_many(doc! {&field: {"$exists": false}}, doc! {"$set": {&field: value}}, None)
+      .await?;
+  }
+
+  Ok(())
+}
+
+/// Unsets each default field on the flags where it still has its default value, which reads the same
+async fn flag_defaults_down(db: Database) -> error::Result<()> {
+  let collection = db.collection::<Document>("features");
+
+  for (field, value) in flag_defaults() {
+    collection
+      .update_many(doc! {&field: value}, doc! {"$unset": {&field: ""}}, None)
+      .await?;
+  }
+
+  Ok(())
+}
+
+/// Outcome of claiming a migration
+enum Claim {
+  /// The migration is claimed by this instance, to apply it
+  Claimed,
+  /// The migration was applied
+  Applied,
+  /// Another instance is applying the migration
+  Running,
+}
+
+/// End of a lease taken now
+fn lease_until() -> mongodb::bson::DateTime {
+  mongodb::bson::DateTime::from_millis(mongodb::bson::DateTime::now().timestamp_millis() + LEASE.as_millis() as i64)
+}
+
+/// Claims `migration` for the instance `owner`, unless it's applied or another instance holds an unexpired claim on it
+async fn claim(collection: &Collection<Document>, migration: &Migration, owner: ObjectId) -> error::Result<Claim> {
+  let now = mongodb::bson::DateTime::now();
+  let record = doc! {
+    "_id": migration.id,
+    "description": migration.description,
+    "status": RUNNING,
+    "owner": owner,
+    "claimed_at": now,
+    "lease_until": lease_until(),
+  };
+  match collection.insert_one(record, None).await {
+    Ok(_) => return Ok(Claim::Claimed),
+    Err(e) if is_duplicate_key(&e) => (),
+    Err(e) => return Err(e),
+  }
+
+  // Left running by an instance that stopped before finishing it
+  let expired = doc! {"_id": migration.id, "status": RUNNING, "lease_until": {"$lt": now}};
+  let takeover = doc! {"$set": {"owner": owner, "claimed_at": now, "lease_until": lease_until()}};
+  if collection.find_one_and_update(expired, takeover, None).await?.is_some() {
+    log!("Taking over migration '{}', its claim expired", migration.id);
+    return Ok(Claim::Claimed);
+  }
+
+  match collection.find_one(doc! {"_id": migration.id}, None).await? {
+    Some(record) if record.get_str("status") == Ok(RUNNING) => Ok(Claim::Running),
+    // Records written before statuses were recorded are of applied migrations
+    Some(_) => Ok(Claim::Applied),
+    // Released by an instance it failed on, claimed again on the next attempt
+    None => Ok(Claim::Running),
+  }
+}
+
+/// Runs the `up` step of `migration`, renewing the claim of `owner` on it until it's done
+async fn apply(
+  db: &Database,
+  collection: &Collection<Document>,
+  migration: &Migration,
+  owner: ObjectId,
+) -> error::Result<()> {
+  let up = (migration.up)(db.clone());
+  tokio::pin!(up);
+
+  let mut renewal = tokio::time::interval(LEASE / 4);
+  loop {
+    tokio::select! {
+      result = &mut up => return result,
+      _ = renewal.tick() => {
+        // Failing to renew is only a risk another instance takes over, which applies the migration again harmlessly
+        let _ = collection
+          .update_one(
+            doc! {"_id": migration.id, "owner": owner},
+            doc! {"$set": {"lease_until": lease_until()}},
+            None,
+          )
+          .await;
+      }
+    }
+  }
+}
+
+/// Applies the migrations that haven't been yet, in order, returning the IDs of the ones applied
+///
+/// Waits for migrations other instances are running, so later migrations are never applied before earlier ones. Stops
+/// at the first migration that fails, which is applied again on the next start
+pub async fn run_pending(client: &Client) -> error::Result<Vec<String>> {
+  let db = client.database("data");
+  let collection = db.collection::<Document>(COLLECTION);
+  let owner = ObjectId::new();
+
+  let mut applied = Vec::new();
+  for migration in MIGRATIONS {
+    let mut waiting = false;
+    let claimed = loop {
+      match claim(&collection, migration, owner).await? {
+        Claim::Claimed => break true,
+        Claim::Applied => break false,
+        Claim::Running => {
+          if !waiting {
+            log!(
+              "Waiting for migration '{}', another instance is applying it",
+              migration.id
+            );
+            waiting = true;
+          }
+          tokio::time::sleep(WAIT_INTERVAL).await;
+        }
+      }
+    };
+    if !claimed {
+      continue;
+    }
+
+    log!("Applying migration '{}': {}", migration.id, migration.description);
+    let ours = doc! {"_id": migration.id, "owner": owner};
+    if let Err(e) = apply(&db, &collection, migration, owner).await {
+      // Released so the next start applies it again
+      let _ = collection.delete_one(ours, None).await;
+      return Err(e);
+    }
+
+    let done = doc! {
+      "$set": {"status": APPLIED, "applied_at": mongodb::bson::DateTime::now()},
+      "$unset": {"owner": "", "lease_until": ""},
+    };
+    collection.update_one(ours, done, None).await?;
+
+    applied.push(migration.id.to_string());
+  }
+
+  Ok(applied)
+}
+
+/// Reverts the last migration applied, returning its ID, `None` if no migration was applied
+pub async fn revert_last(client: &Client) -> error::Result<Option<String>> {
+  let db = client.database("data");
+  let collection = db.collection::<Document>(COLLECTION);
+
+  let ids: Vec<&str> = MIGRATIONS.iter().map(|x| x.id).collect();
+  let options = FindOptions::builder().sort(doc! {"_id": -1}).limit(1).build();
+  let last = collection
+    .find(doc! {"_id": {"$in": ids}, "status": {"$ne": RUNNING}}, options)
+    .await?
+    .try_next()
+    .await?;
+
+  let migration = match last
+    .as_ref()
+    .and_then(|x| x.get_str("_id").ok())
+    .and_then(|id| MIGRATIONS.iter().find(|x| x.id == id))
+  {
+    Some(migration) => migration,
+    None => return Ok(None),
+  };
+
+  log!("Reverting migration '{}': {}", migration.id, migration.description);
+  (migration.down)(db).await?;
+  collection.delete_one(doc! {"_id": migration.id}, None).await?;
+
+  Ok(Some(migration.id.to_string()))
+}
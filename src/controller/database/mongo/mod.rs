@@ -1,16 +1,48 @@
 //! MongoDB connection management
 
+mod changes;
+mod migrations;
+mod options;
+mod retry;
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
 use dotenv;
 use futures::stream::TryStreamExt;
-use mongodb::bson::doc;
 use mongodb::bson::oid::ObjectId;
-use mongodb::error;
-use mongodb::options::ClientOptions;
-use mongodb::Client;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::error::{self, BulkWriteFailure, ErrorKind, WriteFailure};
+use mongodb::options::{
+  ClientOptions, Collation, CollationStrength, FindOneAndUpdateOptions, FindOneOptions, FindOptions, IndexOptions,
+  InsertManyOptions, ReplaceOptions, ReturnDocument, UpdateOptions,
+};
+use mongodb::{Client, ClientSession, Database, IndexModel};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::{mpsc, Mutex};
 
+use crate::controller::database::driver::{DatabaseDriver, StoredChange};
+use crate::controller::database::error::{StorageError, StorageResult};
+use crate::controller::database::mongo::retry::{Retry, RetryPolicy};
+use crate::controller::database::{
+  AuditFilter, FlagFilter, FlagProjection, FlagSort, FlagSortField, Page, ReleaseKind,
+};
+use crate::controller::secrets;
+use crate::model::api_key::ApiKey;
+use crate::model::audit::AuditEntry;
+use crate::model::backup::{Backup, BACKUP_VERSION};
+use crate::model::comment::Comment;
 use crate::model::flag::{FeatureFlag, FeatureFlagBuilder};
+use crate::model::plugin::Plugin;
 use crate::model::product::{Product, ProductBuilder};
+use crate::model::schedule::ChangedField;
+use crate::model::service_account::ServiceAccount;
+use crate::model::session::Session;
+use crate::model::stats::Stats;
 use crate::model::user::{AccountType, User, UserBuilder};
+use crate::model::webhook::{DeliveryStatus, Webhook, WebhookDelivery};
 
 /// Given a product name, this will search for and return a fully constructed `Product` from MongoDB wrapped inside of a
 /// `Result`.
@@ -19,9 +51,7 @@ use crate::model::user::{AccountType, User, UserBuilder};
 ///
 /// ## Result Error
 /// `Result` can contain a MongoDB specific error
-pub async fn get_product(product_name: &str) -> error::Result<Option<Product>> {
-  let client = get_client().await?;
-
+pub async fn get_product(client: &Client, product_name: &str) -> error::Result<Option<Product>> {
   let db = client.database("data");
   let product_collection = db.collection::<Product>("products");
 
@@ -30,11 +60,24 @@ pub async fn get_product(product_name: &str) -> error::Result<Option<Product>> {
   product_collection.find_one(filter, None).await
 }
 
+/// Given a product ID, this will search for and return a fully constructed `Product` from MongoDB wrapped inside of a
+/// `Result`.
+///
+/// ## Result Error
+/// `Result` can contain a MongoDB specific error
+pub async fn get_product_by_id(client: &Client, product_id: ObjectId) -> error::Result<Option<Product>> {
+  let db = client.database("data");
+  let product_collection = db.collection::<Product>("products");
+
+  let filter = doc! { "_id": product_id };
+
+  product_collection.find_one(filter, None).await
+}
+
 /// Gets a `Vec<Product>` given a user_id
 ///
-/// Returns all products consumed by the user
-pub async fn get_products(user_id: Option<String>) -> error::Result<Vec<Product>> {
-  let client = get_client().await?;
+/// Returns the products consumed by the user in `page`
+pub async fn get_products(client: &Client, user_id: Option<String>, page: Page) -> error::Result<Vec<Product>> {
   let mut products: Vec<Product> = vec![];
 
   let db = client.database("data");
@@ -46,7 +89,13 @@ pub async fn get_products(user_id: Option<String>) -> error::Result<Vec<Product>
     filter.insert("users", user_id);
   }
 
-  let mut cursor = product_collection.find(filter, None).await?;
+  let options = FindOptions::builder()
+    .sort(page_sort_document(page))
+    .skip(page.skip)
+    .limit(page.limit)
+    .build();
+
+  let mut cursor = product_collection.find(filter, options).await?;
 
   while let Some(product) = cursor.try_next().await? {
     products.push(product);
@@ -62,28 +111,85 @@ pub async fn get_products(user_id: Option<String>) -> error::Result<Vec<Product>
 ///
 /// ## Result Error
 /// `Result` can contain a MongoDB specific error
-pub async fn get_feature_flag(product_id: &str, flag_name: &str) -> error::Result<Option<FeatureFlag>> {
-  let client = get_client().await?;
-
+pub async fn get_feature_flag(
+  client: &Client,
+  product_id: &str,
+  flag_name: &str,
+  projection: FlagProjection,
+) -> error::Result<Option<FeatureFlag>> {
   let db = client.database("data");
   let features_collection = db.collection::<FeatureFlag>("features");
 
   let filter = doc! { "name": flag_name, "product_id": product_id };
+  let options = FindOneOptions::builder()
+    .projection(flag_projection_document(projection))
+    .build();
 
-  features_collection.find_one(filter, None).await
+  features_collection.find_one(filter, options).await
 }
 
-/// Gets a `Vec<FeatureFlag>` given a product_id
+/// Given a flag ID, this will search for and return a fully constructed `FeatureFlag` from MongoDB wrapped inside of a
+/// `Result`.
+///
+/// ## Result Error
+/// `Result` can contain a MongoDB specific error
+pub async fn get_feature_flag_by_id(
+  client: &Client,
+  flag_id: ObjectId,
+  projection: FlagProjection,
+) -> error::Result<Option<FeatureFlag>> {
+  let db = client.database("data");
+  let features_collection = db.collection::<FeatureFlag>("features");
+
+  let filter = doc! { "_id": flag_id };
+  let options = FindOneOptions::builder()
+    .projection(flag_projection_document(projection))
+    .build();
+
+  features_collection.find_one(filter, options).await
+}
+
+/// Gets a `Vec<FeatureFlag>` given the user_id of their maintainer
 ///
-/// Returns all feature flags belonging to the product
-pub async fn get_feature_flags(product_id: &str) -> error::Result<Vec<FeatureFlag>> {
-  let client = get_client().await?;
+/// Returns the feature flags, across every product, maintained by the user that match the `filter`
+pub async fn get_feature_flags_by_maintainer(
+  client: &Client,
+  user_id: &str,
+  filter: FlagFilter<'_>,
+) -> error::Result<Vec<FeatureFlag>> {
+  let mut feature_flags: Vec<FeatureFlag> = vec![];
+
+  let db = client.database("data");
+  let features_collection = db.collection::<FeatureFlag>("features");
+
+  let query = flag_filter_document(doc! {"maintainer_id": user_id}, &filter);
+  let options = FindOptions::builder()
+    .sort(flag_sort_document(filter.sort, filter.page))
+    .skip(filter.page.skip)
+    .limit(filter.page.limit)
+    .build();
+
+  let mut cursor = features_collection.find(query, options).await?;
+
+  while let Some(feature_flag) = cursor.try_next().await? {
+    feature_flags.push(feature_flag);
+  }
+
+  Ok(feature_flags)
+}
+
+/// Gets a `Vec<FeatureFlag>` of the flags with at least one scheduled change due at `now`
+pub async fn get_feature_flags_with_due_changes(
+  client: &Client,
+  now: DateTime<Utc>,
+) -> error::Result<Vec<FeatureFlag>> {
   let mut feature_flags: Vec<FeatureFlag> = vec![];
 
   let db = client.database("data");
   let features_collection = db.collection::<FeatureFlag>("features");
 
-  let filter = doc! {"product_id": product_id};
+  let filter =
+    doc! { "scheduled_changes.at": { "$lte": mongodb::bson::DateTime::from_millis(now.timestamp_millis()) } };
 
   let mut cursor = features_collection.find(filter, None).await?;
 
@@ -94,12 +200,81 @@ pub async fn get_feature_flags(product_id: &str) -> error::Result<Vec<FeatureFla
   Ok(feature_flags)
 }
 
+/// Gets a `Vec<FeatureFlag>` given a product_id
+///
+/// Returns the feature flags belonging to the product that match the `filter`
+pub async fn get_feature_flags(
+  client: &Client,
+  product_id: &str,
+  filter: FlagFilter<'_>,
+  projection: FlagProjection,
+) -> error::Result<Vec<FeatureFlag>> {
+  let mut feature_flags: Vec<FeatureFlag> = vec![];
+
+  let db = client.database("data");
+  let features_collection = db.collection::<FeatureFlag>("features");
+
+  let query = flag_filter_document(doc! {"product_id": product_id}, &filter);
+  let options = FindOptions::builder()
+    .projection(flag_projection_document(projection))
+    .sort(flag_sort_document(filter.sort, filter.page))
+    .skip(filter.page.skip)
+    .limit(filter.page.limit)
+    .build();
+
+  let mut cursor = features_collection.find(query, options).await?;
+
+  while let Some(feature_flag) = cursor.try_next().await? {
+    feature_flags.push(feature_flag);
+  }
+
+  Ok(feature_flags)
+}
+
+/// Gets every `FeatureFlag` matching one of the given `(product_id, flag_name)` keys in a single query
+///
+/// Keys that don't match a flag are left out of the result
+pub async fn get_feature_flags_by_keys(
+  client: &Client,
+  keys: &[(String, String)],
+  projection: FlagProjection,
+) -> error::Result<Vec<FeatureFlag>> {
+  let mut feature_flags: Vec<FeatureFlag> = vec![];
+
+  let db = client.database("data");
+  let features_collection = db.collection::<FeatureFlag>("features");
+
+  let product_ids: Vec<&String> = keys.iter().map(|(product_id, _)| product_id).collect();
+  let flag_names: Vec<&String> = keys.iter().map(|(_, flag_name)| flag_name).collect();
+
+  let filter = doc! { "product_id": { "$in": product_ids }, "name": { "$in": flag_names } };
+  let options = FindOptions::builder()
+    .projection(flag_projection_document(projection))
+    .build();
+
+  let mut cursor = features_collection.find(filter, options).await?;
+
+  // `$in` on both fields can over-match across products, so only keep exact pairs
+  while let Some(feature_flag) = cursor.try_next().await? {
+    if keys
+      .iter()
+      .any(|(product_id, flag_name)| product_id == &feature_flag.product_id && flag_name == &feature_flag.name)
+    {
+      feature_flags.push(feature_flag);
+    }
+  }
+
+  Ok(feature_flags)
+}
+
 /// Updates a feature_flag of the given ID with the `updated` `FeatureFlag` struct
 ///
 /// Returns a result indicating success
-pub async fn update_feature_flag(feature_flag_id: ObjectId, updated: FeatureFlag) -> error::Result<()> {
-  let client = get_client().await?;
-
+pub async fn update_feature_flag(
+  client: &Client,
+  feature_flag_id: ObjectId,
+  updated: FeatureFlag,
+) -> error::Result<()> {
   let db = client.database("data");
   let features_collection = db.collection::<FeatureFlag>("features");
 
@@ -110,6 +285,256 @@ pub async fn update_feature_flag(feature_flag_id: ObjectId, updated: FeatureFlag
   Ok(())
 }
 
+/// Deletes the feature flag of the given ID along with its comments, in a transaction
+///
+/// Returns a result containing the deleted flag, `None` if there was no flag with the ID
+pub async fn delete_feature_flag(client: &Client, feature_flag_id: ObjectId) -> error::Result<Option<FeatureFlag>> {
+  let db = client.database("data");
+  let features_collection = db.collection::<FeatureFlag>("features");
+  let comment_collection = db.collection::<Comment>("comments");
+
+  let mut session = client.start_session(None).await?;
+  let transaction = start_transaction(&mut session).await?;
+
+  let deleted = features_collection
+    .find_one_and_delete_with_session(doc! {"_id": feature_flag_id}, None, &mut session)
+    .await?;
+  comment_collection
+    .delete_many_with_session(doc! {"flag_id": feature_flag_id.to_hex()}, None, &mut session)
+    .await?;
+
+  if transaction {
+    session.commit_transaction().await?;
+  }
+
+  Ok(deleted)
+}
+
+/// Hoists (`enabled`) or lowers the feature flag of the given ID in a single update: globally for no `user_id`, otherwise
+/// for the user only, by removing them from or adding them to the flag's `disabled_for`
+///
+/// Returns the updated flag, `None` if it wasn't found
+pub async fn set_feature_flag_enabled(
+  client: &Client,
+  feature_flag_id: ObjectId,
+  user_id: Option<&str>,
+  enabled: bool,
+  updated_at: DateTime<Utc>,
+) -> error::Result<Option<FeatureFlag>> {
+  let db = client.database("data");
+  let features_collection = db.collection::<FeatureFlag>("features");
+
+  // Stored like the rest of the flag, as serde serializes it
+  let updated_at = mongodb::bson::to_bson(&updated_at)?;
+  let update = match user_id {
+    None => doc! {"$set": {"enabled": enabled, "updated_at": updated_at}},
+    Some(user_id) if enabled => doc! {
+      "$pull": {"disabled_for": user_id},
+      "$set": {"updated_at": updated_at},
+    },
+    Some(user_id) => doc! {
+      "$addToSet": {"disabled_for": user_id},
+      "$set": {"updated_at": updated_at},
+    },
+  };
+
+  let options = FindOneAndUpdateOptions::builder()
+    .return_document(ReturnDocument::After)
+    .build();
+
+  features_collection
+    .find_one_and_update(doc! {"_id": feature_flag_id}, update, options)
+    .await
+}
+
+/// Sets `field` of the feature flag of the given ID and pulls its scheduled change of ID `change_id`, in a single update
+/// made only if the change is still scheduled. Writes made to the flag's other fields meanwhile are kept
+///
+/// Returns the updated flag, `None` if it wasn't found or the change was already applied
+pub async fn apply_scheduled_change(
+  client: &Client,
+  feature_flag_id: ObjectId,
+  change_id: ObjectId,
+  field: &ChangedField,
+  updated_at: DateTime<Utc>,
+) -> error::Result<Option<FeatureFlag>> {
+  let db = client.database("data");
+  let features_collection = db.collection::<FeatureFlag>("features");
+
+  // Stored like the rest of the flag, as serde serializes it
+  let updated_at = mongodb::bson::to_bson(&updated_at)?;
+  let set = match field {
+    ChangedField::Enabled(enabled) => doc! {"enabled": enabled, "updated_at": updated_at},
+    ChangedField::ReleaseType(release_type) => {
+      doc! {"release_type": mongodb::bson::to_bson(release_type)?, "updated_at": updated_at}
+    }
+  };
+
+  let query = doc! {"_id": feature_flag_id, "scheduled_changes.id": change_id};
+  let update = doc! {
+    "$set": set,
+    "$pull": {"scheduled_changes": {"id": change_id}},
+  };
+  let options = FindOneAndUpdateOptions::builder()
+    .return_document(ReturnDocument::After)
+    .build();
+
+  features_collection.find_one_and_update(query, update, options).await
+}
+
+/// Updates a product of the given ID with the `updated` `Product` struct
+///
+/// Returns a result indicating success
+pub async fn update_product(client: &Client, product_id: ObjectId, updated: Product) -> error::Result<()> {
+  let db = client.database("data");
+  let products_collection = db.collection::<Product>("products");
+
+  let query = doc! {"_id": product_id};
+
+  products_collection.replace_one(query, updated, None).await?;
+
+  Ok(())
+}
+
+/// Adds a user to the users of the product of the given ID in a single update, doing nothing if it's already one
+///
+/// Returns the updated product, `None` if it wasn't found
+pub async fn add_product_user(client: &Client, product_id: ObjectId, user_id: &str) -> error::Result<Option<Product>> {
+  let db = client.database("data");
+  let products_collection = db.collection::<Product>("products");
+
+  let options = FindOneAndUpdateOptions::builder()
+    .return_document(ReturnDocument::After)
+    .build();
+
+  products_collection
+    .find_one_and_update(
+      doc! {"_id": product_id},
+      doc! {"$addToSet": {"users": user_id}},
+      options,
+    )
+    .await
+}
+
+/// Removes a user from the users of the product of the given ID in a single update
+///
+/// Returns the updated product, `None` if it wasn't found
+pub async fn remove_product_user(
+  client: &Client,
+  product_id: ObjectId,
+  user_id: &str,
+) -> error::Result<Option<Product>> {
+  let db = client.database("data");
+  let products_collection = db.collection::<Product>("products");
+
+  let options = FindOneAndUpdateOptions::builder()
+    .return_document(ReturnDocument::After)
+    .build();
+
+  products_collection
+    .find_one_and_update(doc! {"_id": product_id}, doc! {"$pull": {"users": user_id}}, options)
+    .await
+}
+
+/// Deletes the product of the given ID along with its flags, their comments, its plugins, and its API keys, in a
+/// transaction
+///
+/// Without transactions flags are deleted first, so a product that fails to be deleted can be deleted again
+///
+/// Returns a result indicating if a product was deleted
+pub async fn delete_product(client: &Client, product_id: ObjectId, flag_ids: &[String]) -> error::Result<bool> {
+  let db = client.database("data");
+  let products_collection = db.collection::<Product>("products");
+  let features_collection = db.collection::<FeatureFlag>("features");
+  let comment_collection = db.collection::<Comment>("comments");
+  let plugin_collection = db.collection::<Plugin>("plugins");
+  let api_key_collection = db.collection::<ApiKey>("api_keys");
+
+  let mut session = client.start_session(None).await?;
+  let transaction = start_transaction(&mut session).await?;
+
+  comment_collection
+    .delete_many_with_session(doc! {"flag_id": {"$in": flag_ids}}, None, &mut session)
+    .await?;
+  features_collection
+    .delete_many_with_session(doc! {"product_id": product_id.to_hex()}, None, &mut session)
+    .await?;
+  plugin_collection
+    .delete_many_with_session(doc! {"product_id": product_id.to_hex()}, None, &mut session)
+    .await?;
+  api_key_collection
+    .delete_many_with_session(doc! {"product_id": product_id.to_hex()}, None, &mut session)
+    .await?;
+
+  let deleted = products_collection
+    .delete_one_with_session(doc! {"_id": product_id}, None, &mut session)
+    .await?
+    .deleted_count;
+
+  if transaction {
+    session.commit_transaction().await?;
+  }
+
+  Ok(deleted > 0)
+}
+
+/// Applies an import to the product of the given ID in a transaction: replaces the product with `product` if given,
+/// replaces each of `updated` (by their IDs), and inserts `created`
+///
+/// Without transactions the writes are applied in that order, and the ones made before a failure are kept
+pub async fn import_product(
+  client: &Client,
+  product_id: ObjectId,
+  product: Option<Product>,
+  updated: Vec<FeatureFlag>,
+  created: Vec<FeatureFlag>,
+) -> error::Result<()> {
+  let db = client.database("data");
+  let products_collection = db.collection::<Product>("products");
+  let features_collection = db.collection::<FeatureFlag>("features");
+
+  let mut session = client.start_session(None).await?;
+  let transaction = start_transaction(&mut session).await?;
+
+  if let Some(product) = product {
+    products_collection
+      .replace_one_with_session(doc! {"_id": product_id}, product, None, &mut session)
+      .await?;
+  }
+  for flag in updated {
+    if let Some(oid) = flag.oid {
+      features_collection
+        .replace_one_with_session(doc! {"_id": oid}, flag, None, &mut session)
+        .await?;
+    }
+  }
+  if !created.is_empty() {
+    features_collection
+      .insert_many_with_session(created, None, &mut session)
+      .await?;
+  }
+
+  if transaction {
+    session.commit_transaction().await?;
+  }
+
+  Ok(())
+}
+
+/// Updates a user of the given ID with the `updated` `User` struct
+///
+/// Returns a result indicating success
+pub async fn update_user(client: &Client, user_id: ObjectId, updated: User) -> error::Result<()> {
+  let db = client.database("data");
+  let users_collection = db.collection::<User>("users");
+
+  let query = doc! {"_id": user_id};
+
+  users_collection.replace_one(query, updated, None).await?;
+
+  Ok(())
+}
+
 /// Given a user email, this will search for and return a fully constructed `User` from MongoDB wrapped inside of a
 /// `Result`.
 ///
@@ -117,9 +542,7 @@ pub async fn update_feature_flag(feature_flag_id: ObjectId, updated: FeatureFlag
 ///
 /// ## Result Error
 /// `Result` can contain a MongoDB specific error
-pub async fn get_user(user_email: Option<&str>, user_id: Option<&str>) -> error::Result<Option<User>> {
-  let client = get_client().await?;
-
+pub async fn get_user(client: &Client, user_email: Option<&str>, user_id: Option<&str>) -> error::Result<Option<User>> {
   let db = client.database("data");
   let user_collection = db.collection::<User>("users");
 
@@ -136,9 +559,8 @@ pub async fn get_user(user_email: Option<&str>, user_id: Option<&str>) -> error:
   user_collection.find_one(filter, None).await
 }
 
-/// Gets a `Vec<User>` optinally given an account_type
-pub async fn get_users(account_type: Option<AccountType>) -> error::Result<Vec<User>> {
-  let client = get_client().await?;
+/// Gets a `Vec<User>` optinally given an account_type, only the users in `page` and without their password hash
+pub async fn get_users(client: &Client, account_type: Option<AccountType>, page: Page) -> error::Result<Vec<User>> {
   let mut users: Vec<User> = vec![];
 
   let db = client.database("data");
@@ -150,7 +572,14 @@ pub async fn get_users(account_type: Option<AccountType>) -> error::Result<Vec<U
     filter.insert("account_type", account_type);
   }
 
-  let mut cursor = user_collection.find(filter, None).await?;
+  let options = FindOptions::builder()
+    .projection(public_user_projection())
+    .sort(page_sort_document(page))
+    .skip(page.skip)
+    .limit(page.limit)
+    .build();
+
+  let mut cursor = user_collection.find(filter, options).await?;
 
   while let Some(user) = cursor.try_next().await? {
     users.push(user);
@@ -159,10 +588,38 @@ pub async fn get_users(account_type: Option<AccountType>) -> error::Result<Vec<U
   Ok(users)
 }
 
-/// Creates a product given a builder and returns a fully constructed product
-pub async fn create_product(product_builder: ProductBuilder) -> error::Result<Product> {
-  let client = get_client().await?;
+/// Counts the users, a fresh deployment has none
+pub async fn count_users(client: &Client) -> error::Result<u64> {
+  let db = client.database("data");
+  let user_collection = db.collection::<User>("users");
+
+  user_collection.count_documents(None, None).await
+}
+
+/// Gets every `User` with one of the given IDs in a single query
+///
+/// IDs that aren't valid or don't match a user are left out of the result, the users come without their password hash
+pub async fn get_users_by_ids(client: &Client, user_ids: &[String]) -> error::Result<Vec<User>> {
+  let mut users: Vec<User> = vec![];
+
+  let db = client.database("data");
+  let user_collection = db.collection::<User>("users");
+
+  let ids: Vec<ObjectId> = user_ids.iter().filter_map(|x| ObjectId::parse_str(x).ok()).collect();
+
+  let options = FindOptions::builder().projection(public_user_projection()).build();
+
+  let mut cursor = user_collection.find(doc! { "_id": { "$in": ids } }, options).await?;
+
+  while let Some(user) = cursor.try_next().await? {
+    users.push(user);
+  }
+
+  Ok(users)
+}
 
+/// Creates a product given a builder and returns a fully constructed product
+pub async fn create_product(client: &Client, product_builder: ProductBuilder) -> error::Result<Product> {
   let db = client.database("data");
   let products_collection = db.collection::<Product>("products");
 
@@ -179,9 +636,7 @@ pub async fn create_product(product_builder: ProductBuilder) -> error::Result<Pr
 }
 
 /// Creates a new feature flag given a builder and returns a fully constructed flag
-pub async fn create_flag(flag_builder: FeatureFlagBuilder) -> error::Result<FeatureFlag> {
-  let client = get_client().await?;
-
+pub async fn create_flag(client: &Client, flag_builder: FeatureFlagBuilder) -> error::Result<FeatureFlag> {
   let db = client.database("data");
   let features_collection = db.collection::<FeatureFlag>("features");
 
@@ -197,15 +652,55 @@ pub async fn create_flag(flag_builder: FeatureFlagBuilder) -> error::Result<Feat
   Ok(flag)
 }
 
+/// Creates new feature flags given their builders, with a single `insert_many`
+///
+/// Flags are inserted unordered so one failing doesn't stop the others. Returns each flag, or `None` if it failed to be
+/// inserted, in the order of the builders
+pub async fn create_flags(
+  client: &Client,
+  flag_builders: Vec<FeatureFlagBuilder>,
+) -> error::Result<Vec<Option<FeatureFlag>>> {
+  let db = client.database("data");
+  let features_collection = db.collection::<FeatureFlag>("features");
+
+  // IDs are generated here rather than by the database so the flags inserted next to failed ones are still known
+  let flags: Vec<FeatureFlag> = flag_builders
+    .into_iter()
+    .map(|x| x.with_oid(ObjectId::new()).build())
+    .collect();
+
+  let options = InsertManyOptions::builder().ordered(false).build();
+
+  let failed: HashSet<usize> = match features_collection.insert_many(&flags, options).await {
+    Ok(_) => HashSet::new(),
+    Err(e) => match e.kind.as_ref() {
+      ErrorKind::BulkWrite(BulkWriteFailure {
+        write_errors: Some(write_errors),
+        ..
+      }) => write_errors.iter().map(|x| x.index).collect(),
+      _ => return Err(e),
+    },
+  };
+
+  Ok(
+    flags
+      .into_iter()
+      .enumerate()
+      .map(|(index, flag)| match failed.contains(&index) {
+        true => None,
+        false => Some(flag),
+      })
+      .collect(),
+  )
+}
+
 /// Given a `UserBuilder`, this will attempt to create a new `User` and insert them into the database.
 ///
 /// The `User` returned inside of the `Result` will contain the ObjectId generated by MongoDB
 ///
 /// ## Result Error
 /// `Result` can contain a MongoDB specific error
-pub async fn create_user(user_builder: UserBuilder) -> error::Result<User> {
-  let client = get_client().await?;
-
+pub async fn create_user(client: &Client, user_builder: UserBuilder) -> error::Result<User> {
   let db = client.database("data");
   let user_collection = db.collection::<User>("users");
 
@@ -221,19 +716,1984 @@ pub async fn create_user(user_builder: UserBuilder) -> error::Result<User> {
   Ok(user)
 }
 
-async fn get_client() -> error::Result<Client> {
-  dotenv::dotenv().ok();
+/// Maps a `FlagProjection` to the MongoDB projection document used to fetch it
+///
+/// The evaluation projection excludes metadata rather than including evaluation fields so new evaluation fields are
+/// picked up without touching this function
+/// Inserts an `AuditEntry` into the audit log
+///
+/// ## Result Error
+/// `Result` can contain a MongoDB specific error
+pub async fn create_audit_entry(client: &Client, entry: AuditEntry) -> error::Result<()> {
+  let db = client.database("data");
+  let audit_collection = db.collection::<AuditEntry>("audit");
 
-  let connection_string = match dotenv::var("MONGO_STR") {
-    Ok(value) => value,
-    Err(e) => {
-      panic!("Error getting MongoDB connection string (MONGO_STR): {:?}", e);
-    }
-  };
+  audit_collection.insert_one(entry, None).await?;
+
+  Ok(())
+}
+
+/// Inserts a comment into the database
+///
+/// The `Comment` returned inside of the `Result` will contain the ObjectId generated by MongoDB
+pub async fn create_comment(client: &Client, mut comment: Comment) -> error::Result<Comment> {
+  let db = client.database("data");
+  let comment_collection = db.collection::<Comment>("comments");
+
+  comment.oid = comment_collection
+    .insert_one(&comment, None)
+    .await?
+    .inserted_id
+    .as_object_id();
+
+  Ok(comment)
+}
+
+/// Gets the comments on the flag with ID `flag_id`, oldest first
+pub async fn get_comments(client: &Client, flag_id: &str) -> error::Result<Vec<Comment>> {
+  let mut comments: Vec<Comment> = vec![];
+
+  let db = client.database("data");
+  let comment_collection = db.collection::<Comment>("comments");
+
+  let filter = doc! { "flag_id": flag_id };
+  let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+
+  let mut cursor = comment_collection.find(filter, options).await?;
+
+  while let Some(comment) = cursor.try_next().await? {
+    comments.push(comment);
+  }
+
+  Ok(comments)
+}
+
+/// Inserts a plugin into the database
+///
+/// The `Plugin` returned inside of the `Result` will contain the ObjectId generated by MongoDB
+pub async fn create_plugin(client: &Client, mut plugin: Plugin) -> error::Result<Plugin> {
+  let db = client.database("data");
+  let plugin_collection = db.collection::<Plugin>("plugins");
+
+  plugin.oid = plugin_collection
+    .insert_one(&plugin, None)
+    .await?
+    .inserted_id
+    .as_object_id();
+
+  Ok(plugin)
+}
+
+/// Given a plugin ID, this will search for and return the `Plugin` from MongoDB wrapped inside of a `Result`
+pub async fn get_plugin(client: &Client, plugin_id: ObjectId) -> error::Result<Option<Plugin>> {
+  let db = client.database("data");
+  let plugin_collection = db.collection::<Plugin>("plugins");
+
+  let filter = doc! { "_id": plugin_id };
+
+  plugin_collection.find_one(filter, None).await
+}
+
+/// Gets at most `limit` audit entries matching the `filter`, newest first, starting after the entry with ID `after`
+///
+/// Entries are ordered by their ID, which is generated in insertion order, so pages stay stable as entries are added
+pub async fn get_audit_entries(
+  client: &Client,
+  filter: &AuditFilter,
+  after: Option<ObjectId>,
+  limit: i64,
+) -> error::Result<Vec<AuditEntry>> {
+  let mut entries: Vec<AuditEntry> = vec![];
+
+  let db = client.database("data");
+  let audit_collection = db.collection::<AuditEntry>("audit");
 
-  let client_options = ClientOptions::parse(connection_string).await?;
+  let mut query = doc! {};
 
-  let client = Client::with_options(client_options)?;
+  if let Some(actor) = &filter.actor {
+    query.insert("actor", actor);
+  }
+
+  if let Some(entity_type) = filter.entity_type {
+    query.insert("entity_type", entity_type.name());
+  }
+
+  if let Some(action) = filter.action {
+    query.insert("action", action.name());
+  }
 
-  Ok(client)
+  let mut timestamp = doc! {};
+  if let Some(from) = filter.from {
+    timestamp.insert("$gte", mongodb::bson::DateTime::from_millis(from.timestamp_millis()));
+  }
+  if let Some(to) = filter.to {
+    timestamp.insert("$lte", mongodb::bson::DateTime::from_millis(to.timestamp_millis()));
+  }
+  if !timestamp.is_empty() {
+    query.insert("timestamp", timestamp);
+  }
+
+  if let Some(after) = after {
+    query.insert("_id", doc! {"$lt": after});
+  }
+
+  let options = FindOptions::builder().sort(doc! {"_id": -1}).limit(limit).build();
+
+  let mut cursor = audit_collection.find(query, options).await?;
+
+  while let Some(entry) = cursor.try_next().await? {
+    entries.push(entry);
+  }
+
+  Ok(entries)
+}
+
+/// Creates the compound indexes used by the audit log filters
+///
+/// Every index ends on `_id` so each filter can also be paginated and sorted from the index
+pub async fn create_audit_indexes(client: &Client) -> error::Result<()> {
+  let db = client.database("data");
+  let audit_collection = db.collection::<AuditEntry>("audit");
+
+  let indexes = vec![
+    IndexModel::builder().keys(doc! {"actor": 1, "_id": -1}).build(),
+    IndexModel::builder()
+      .keys(doc! {"entity_type": 1, "entity_id": 1, "_id": -1})
+      .build(),
+    IndexModel::builder().keys(doc! {"action": 1, "_id": -1}).build(),
+    IndexModel::builder().keys(doc! {"timestamp": -1, "_id": -1}).build(),
+  ];
+
+  audit_collection.create_indexes(indexes, None).await?;
+
+  Ok(())
+}
+
+/// Creates the indexes backing `/search` on the names of flags and products and the emails of users
+///
+/// Searches are case-insensitive range queries, using these indexes since they share the same collation
+pub async fn create_search_indexes(client: &Client) -> error::Result<()> {
+  let db = client.database("data");
+  let options = IndexOptions::builder().collation(search_collation()).build();
+
+  db.collection::<FeatureFlag>("features")
+    .create_index(
+      IndexModel::builder()
+        .keys(doc! {"name": 1})
+        .options(options.clone())
+        .build(),
+      None,
+    )
+    .await?;
+  db.collection::<Product>("products")
+    .create_index(
+      IndexModel::builder()
+        .keys(doc! {"name": 1})
+        .options(options.clone())
+        .build(),
+      None,
+    )
+    .await?;
+  db.collection::<User>("users")
+    .create_index(
+      IndexModel::builder().keys(doc! {"email": 1}).options(options).build(),
+      None,
+    )
+    .await?;
+
+  Ok(())
+}
+
+/// Gets up to `limit` flags, across every product, whose name starts with `term` ignoring case
+pub async fn search_feature_flags(client: &Client, term: &str, limit: i64) -> error::Result<Vec<FeatureFlag>> {
+  let mut feature_flags: Vec<FeatureFlag> = vec![];
+
+  let db = client.database("data");
+  let features_collection = db.collection::<FeatureFlag>("features");
+
+  let options = FindOptions::builder()
+    .collation(search_collation())
+    .projection(flag_projection_document(FlagProjection::Summary))
+    .limit(limit)
+    .build();
+
+  let mut cursor = features_collection.find(prefix_query("name", term), options).await?;
+
+  while let Some(feature_flag) = cursor.try_next().await? {
+    feature_flags.push(feature_flag);
+  }
+
+  Ok(feature_flags)
+}
+
+/// Gets up to `limit` products whose name starts with `term` ignoring case
+pub async fn search_products(client: &Client, term: &str, limit: i64) -> error::Result<Vec<Product>> {
+  let mut products: Vec<Product> = vec![];
+
+  let db = client.database("data");
+  let product_collection = db.collection::<Product>("products");
+
+  let options = FindOptions::builder()
+    .collation(search_collation())
+    .limit(limit)
+    .build();
+
+  let mut cursor = product_collection.find(prefix_query("name", term), options).await?;
+
+  while let Some(product) = cursor.try_next().await? {
+    products.push(product);
+  }
+
+  Ok(products)
+}
+
+/// Gets up to `limit` users whose email starts with `term` ignoring case, without their password hash
+pub async fn search_users(client: &Client, term: &str, limit: i64) -> error::Result<Vec<User>> {
+  let mut users: Vec<User> = vec![];
+
+  let db = client.database("data");
+  let users_collection = db.collection::<User>("users");
+
+  let options = FindOptions::builder()
+    .collation(search_collation())
+    .projection(public_user_projection())
+    .limit(limit)
+    .build();
+
+  let mut cursor = users_collection.find(prefix_query("email", term), options).await?;
+
+  while let Some(user) = cursor.try_next().await? {
+    users.push(user);
+  }
+
+  Ok(users)
+}
+
+/// Inserts a webhook into the database
+///
+/// The `Webhook` returned inside of the `Result` will contain the ObjectId generated by MongoDB
+pub async fn create_webhook(client: &Client, mut webhook: Webhook) -> error::Result<Webhook> {
+  let db = client.database("data");
+  let webhook_collection = db.collection::<Webhook>("webhooks");
+
+  webhook.oid = webhook_collection
+    .insert_one(&webhook, None)
+    .await?
+    .inserted_id
+    .as_object_id();
+
+  Ok(webhook)
+}
+
+/// Given a webhook ID, this will search for and return the `Webhook` from MongoDB wrapped inside of a `Result`
+pub async fn get_webhook(client: &Client, webhook_id: ObjectId) -> error::Result<Option<Webhook>> {
+  let db = client.database("data");
+  let webhook_collection = db.collection::<Webhook>("webhooks");
+
+  webhook_collection.find_one(doc! {"_id": webhook_id}, None).await
+}
+
+/// Gets every webhook, or only those delivering changes to the product of ID `product_id`
+pub async fn get_webhooks(client: &Client, product_id: Option<&str>) -> error::Result<Vec<Webhook>> {
+  let mut webhooks: Vec<Webhook> = vec![];
+
+  let db = client.database("data");
+  let webhook_collection = db.collection::<Webhook>("webhooks");
+
+  let mut filter = doc! {};
+
+  if let Some(product_id) = product_id {
+    filter.insert("product_id", product_id);
+  }
+
+  let mut cursor = webhook_collection.find(filter, None).await?;
+
+  while let Some(webhook) = cursor.try_next().await? {
+    webhooks.push(webhook);
+  }
+
+  Ok(webhooks)
+}
+
+pub async fn update_webhook(client: &Client, webhook_id: ObjectId, updated: Webhook) -> error::Result<()> {
+  let db = client.database("data");
+  let webhook_collection = db.collection::<Webhook>("webhooks");
+
+  webhook_collection
+    .replace_one(doc! {"_id": webhook_id}, updated, None)
+    .await?;
+
+  Ok(())
+}
+
+/// Deletes the webhook of the given ID along with its deliveries
+///
+/// Returns a result indicating if a webhook was deleted
+pub async fn delete_webhook(client: &Client, webhook_id: ObjectId) -> error::Result<bool> {
+  let db = client.database("data");
+  let webhook_collection = db.collection::<Webhook>("webhooks");
+  let delivery_collection = db.collection::<WebhookDelivery>("webhook_deliveries");
+
+  let result = webhook_collection.delete_one(doc! {"_id": webhook_id}, None).await?;
+
+  delivery_collection
+    .delete_many(doc! {"webhook_id": webhook_id.to_hex()}, None)
+    .await?;
+
+  Ok(result.deleted_count > 0)
+}
+
+/// Inserts an API key into the database
+///
+/// The `ApiKey` returned inside of the `Result` will contain the ObjectId generated by MongoDB
+pub async fn create_api_key(client: &Client, mut api_key: ApiKey) -> error::Result<ApiKey> {
+  let db = client.database("data");
+  let api_key_collection = db.collection::<ApiKey>("api_keys");
+
+  api_key.oid = api_key_collection
+    .insert_one(&api_key, None)
+    .await?
+    .inserted_id
+    .as_object_id();
+
+  Ok(api_key)
+}
+
+/// Given an API key ID, this will search for and return the `ApiKey` from MongoDB wrapped inside of a `Result`
+pub async fn get_api_key(client: &Client, api_key_id: ObjectId) -> error::Result<Option<ApiKey>> {
+  let db = client.database("data");
+  let api_key_collection = db.collection::<ApiKey>("api_keys");
+
+  api_key_collection.find_one(doc! {"_id": api_key_id}, None).await
+}
+
+/// Given the hash of a key, this will search for and return its `ApiKey`, revoked or not
+pub async fn get_api_key_by_hash(client: &Client, key_hash: &str) -> error::Result<Option<ApiKey>> {
+  let db = client.database("data");
+  let api_key_collection = db.collection::<ApiKey>("api_keys");
+
+  api_key_collection.find_one(doc! {"key_hash": key_hash}, None).await
+}
+
+/// Gets every API key of the product of ID `product_id`, revoked ones included
+pub async fn get_api_keys(client: &Client, product_id: &str) -> error::Result<Vec<ApiKey>> {
+  let mut api_keys: Vec<ApiKey> = vec![];
+
+  let db = client.database("data");
+  let api_key_collection = db.collection::<ApiKey>("api_keys");
+
+  let options = FindOptions::builder().sort(doc! {"_id": 1}).build();
+  let mut cursor = api_key_collection
+    .find(doc! {"product_id": product_id}, options)
+    .await?;
+
+  while let Some(api_key) = cursor.try_next().await? {
+    api_keys.push(api_key);
+  }
+
+  Ok(api_keys)
+}
+
+/// Marks the API key of the given ID as revoked at `revoked_at`, unless it already was
+///
+/// Returns a result indicating if a key was revoked
+pub async fn revoke_api_key(client: &Client, api_key_id: ObjectId, revoked_at: DateTime<Utc>) -> error::Result<bool> {
+  let db = client.database("data");
+  let api_key_collection = db.collection::<ApiKey>("api_keys");
+
+  let result = api_key_collection
+    .update_one(
+      doc! {"_id": api_key_id, "revoked_at": Bson::Null},
+      doc! {"$set": {"revoked_at": mongodb::bson::DateTime::from_millis(revoked_at.timestamp_millis())}},
+      None,
+    )
+    .await?;
+
+  Ok(result.modified_count > 0)
+}
+
+/// Creates the indexes of API keys, looked up by hash on every authorized check
+pub async fn create_api_key_indexes(client: &Client) -> error::Result<()> {
+  let db = client.database("data");
+  let api_key_collection = db.collection::<ApiKey>("api_keys");
+
+  let indexes = vec![
+    IndexModel::builder()
+      .keys(doc! {"key_hash": 1})
+      .options(IndexOptions::builder().unique(true).build())
+      .build(),
+    IndexModel::builder().keys(doc! {"product_id": 1, "_id": 1}).build(),
+  ];
+
+  api_key_collection.create_indexes(indexes, None).await?;
+
+  Ok(())
+}
+
+/// Inserts a service account into the database
+///
+/// The `ServiceAccount` returned inside of the `Result` will contain the ObjectId generated by MongoDB
+pub async fn create_service_account(
+  client: &Client,
+  mut service_account: ServiceAccount,
+) -> error::Result<ServiceAccount> {
+  let db = client.database("data");
+  let service_account_collection = db.collection::<ServiceAccount>("service_accounts");
+
+  service_account.oid = service_account_collection
+    .insert_one(&service_account, None)
+    .await?
+    .inserted_id
+    .as_object_id();
+
+  Ok(service_account)
+}
+
+/// Given a service account ID, this will search for and return the `ServiceAccount` from MongoDB wrapped inside of a
+/// `Result`
+pub async fn get_service_account(
+  client: &Client,
+  service_account_id: ObjectId,
+) -> error::Result<Option<ServiceAccount>> {
+  let db = client.database("data");
+  let service_account_collection = db.collection::<ServiceAccount>("service_accounts");
+
+  service_account_collection
+    .find_one(doc! {"_id": service_account_id}, None)
+    .await
+}
+
+/// Given the hash of a token, this will search for and return its `ServiceAccount`, revoked or not
+pub async fn get_service_account_by_hash(client: &Client, token_hash: &str) -> error::Result<Option<ServiceAccount>> {
+  let db = client.database("data");
+  let service_account_collection = db.collection::<ServiceAccount>("service_accounts");
+
+  service_account_collection
+    .find_one(doc! {"token_hash": token_hash}, None)
+    .await
+}
+
+/// Gets every service account, revoked ones included
+pub async fn get_service_accounts(client: &Client) -> error::Result<Vec<ServiceAccount>> {
+  let mut service_accounts: Vec<ServiceAccount> = vec![];
+
+  let db = client.database("data");
+  let service_account_collection = db.collection::<ServiceAccount>("service_accounts");
+
+  let options = FindOptions::builder().sort(doc! {"_id": 1}).build();
+  let mut cursor = service_account_collection.find(None, options).await?;
+
+  while let Some(service_account) = cursor.try_next().await? {
+    service_accounts.push(service_account);
+  }
+
+  Ok(service_accounts)
+}
+
+/// Marks the token of the service account of the given ID as revoked at `revoked_at`, unless it already was
+///
+/// Returns a result indicating if a token was revoked
+pub async fn revoke_service_account(
+  client: &Client,
+  service_account_id: ObjectId,
+  revoked_at: DateTime<Utc>,
+) -> error::Result<bool> {
+  let db = client.database("data");
+  let service_account_collection = db.collection::<ServiceAccount>("service_accounts");
+
+  let result = service_account_collection
+    .update_one(
+      doc! {"_id": service_account_id, "revoked_at": Bson::Null},
+      doc! {"$set": {"revoked_at": mongodb::bson::DateTime::from_millis(revoked_at.timestamp_millis())}},
+      None,
+    )
+    .await?;
+
+  Ok(result.modified_count > 0)
+}
+
+/// Creates the indexes of service accounts, looked up by token hash on every request they authorize
+pub async fn create_service_account_indexes(client: &Client) -> error::Result<()> {
+  let db = client.database("data");
+  let service_account_collection = db.collection::<ServiceAccount>("service_accounts");
+
+  let indexes = vec![IndexModel::builder()
+    .keys(doc! {"token_hash": 1})
+    .options(IndexOptions::builder().unique(true).build())
+    .build()];
+
+  service_account_collection.create_indexes(indexes, None).await?;
+
+  Ok(())
+}
+
+/// Inserts a session into the database
+///
+/// The `Session` returned inside of the `Result` will contain the ObjectId generated by MongoDB
+pub async fn create_session(client: &Client, mut session: Session) -> error::Result<Session> {
+  let db = client.database("data");
+  let session_collection = db.collection::<Session>("sessions");
+
+  session.oid = session_collection
+    .insert_one(&session, None)
+    .await?
+    .inserted_id
+    .as_object_id();
+
+  Ok(session)
+}
+
+/// Given a user ID and the hash of an auth token, this will search for and return their unexpired `Session`
+pub async fn get_session(client: &Client, user_id: &str, token_hash: &str) -> error::Result<Option<Session>> {
+  let db = client.database("data");
+  let session_collection = db.collection::<Session>("sessions");
+
+  session_collection
+    .find_one(
+      doc! {"user_id": user_id, "token_hash": token_hash, "expires_at": {"$gt": mongodb::bson::DateTime::now()}},
+      None,
+    )
+    .await
+}
+
+/// Pushes back the expiry of the unexpired session of the given user and auth token hash to `expires_at`
+///
+/// Returns the refreshed `Session` inside of an `Option`, `None` if it wasn't found
+pub async fn refresh_session(
+  client: &Client,
+  user_id: &str,
+  token_hash: &str,
+  expires_at: DateTime<Utc>,
+) -> error::Result<Option<Session>> {
+  let db = client.database("data");
+  let session_collection = db.collection::<Session>("sessions");
+
+  let options = FindOneAndUpdateOptions::builder()
+    .return_document(ReturnDocument::After)
+    .build();
+
+  session_collection
+    .find_one_and_update(
+      doc! {"user_id": user_id, "token_hash": token_hash, "expires_at": {"$gt": mongodb::bson::DateTime::now()}},
+      doc! {"$set": {"expires_at": mongodb::bson::DateTime::from_millis(expires_at.timestamp_millis())}},
+      options,
+    )
+    .await
+}
+
+/// Deletes the unexpired session of the given user and auth token hash
+///
+/// Returns a result indicating if a session was deleted
+pub async fn delete_session(client: &Client, user_id: &str, token_hash: &str) -> error::Result<bool> {
+  let db = client.database("data");
+  let session_collection = db.collection::<Session>("sessions");
+
+  let result = session_collection
+    .delete_one(
+      doc! {"user_id": user_id, "token_hash": token_hash, "expires_at": {"$gt": mongodb::bson::DateTime::now()}},
+      None,
+    )
+    .await?;
+
+  Ok(result.deleted_count > 0)
+}
+
+/// Deletes every session of the user of the given ID, expired ones included
+///
+/// Returns a result with the number of unexpired sessions deleted
+pub async fn delete_sessions(client: &Client, user_id: &str) -> error::Result<u64> {
+  let db = client.database("data");
+  let session_collection = db.collection::<Session>("sessions");
+
+  let active = session_collection
+    .count_documents(
+      doc! {"user_id": user_id, "expires_at": {"$gt": mongodb::bson::DateTime::now()}},
+      None,
+    )
+    .await?;
+  session_collection.delete_many(doc! {"user_id": user_id}, None).await?;
+
+  Ok(active)
+}
+
+/// Creates the indexes of sessions, looked up by token hash on every authenticated request. Expired sessions are
+/// removed by MongoDB
+pub async fn create_session_indexes(client: &Client) -> error::Result<()> {
+  let db = client.database("data");
+  let session_collection = db.collection::<Session>("sessions");
+
+  let indexes = vec![
+    IndexModel::builder()
+      .keys(doc! {"token_hash": 1})
+      .options(IndexOptions::builder().unique(true).build())
+      .build(),
+    IndexModel::builder().keys(doc! {"user_id": 1}).build(),
+    IndexModel::builder()
+      .keys(doc! {"expires_at": 1})
+      .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+      .build(),
+  ];
+
+  session_collection.create_indexes(indexes, None).await?;
+
+  Ok(())
+}
+
+/// Inserts a delivery into the database, with the ID it was constructed with
+pub async fn create_webhook_delivery(client: &Client, delivery: &WebhookDelivery) -> error::Result<()> {
+  let db = client.database("data");
+  let delivery_collection = db.collection::<WebhookDelivery>("webhook_deliveries");
+
+  delivery_collection.insert_one(delivery, None).await?;
+
+  Ok(())
+}
+
+/// Replaces a delivery with its latest state, doing nothing if it was deleted along with its webhook
+pub async fn update_webhook_delivery(client: &Client, delivery: &WebhookDelivery) -> error::Result<()> {
+  let db = client.database("data");
+  let delivery_collection = db.collection::<WebhookDelivery>("webhook_deliveries");
+
+  delivery_collection
+    .replace_one(doc! {"_id": delivery.oid}, delivery, None)
+    .await?;
+
+  Ok(())
+}
+
+/// Given a delivery ID, this will search for and return the `WebhookDelivery` from MongoDB wrapped inside of a `Result`
+pub async fn get_webhook_delivery(client: &Client, delivery_id: ObjectId) -> error::Result<Option<WebhookDelivery>> {
+  let db = client.database("data");
+  let delivery_collection = db.collection::<WebhookDelivery>("webhook_deliveries");
+
+  delivery_collection.find_one(doc! {"_id": delivery_id}, None).await
+}
+
+/// Gets at most `limit` deliveries to the webhook of ID `webhook_id`, newest first, optionally only those in `status`
+pub async fn get_webhook_deliveries(
+  client: &Client,
+  webhook_id: &str,
+  status: Option<DeliveryStatus>,
+  limit: i64,
+) -> error::Result<Vec<WebhookDelivery>> {
+  let mut deliveries: Vec<WebhookDelivery> = vec![];
+
+  let db = client.database("data");
+  let delivery_collection = db.collection::<WebhookDelivery>("webhook_deliveries");
+
+  let mut filter = doc! {"webhook_id": webhook_id};
+
+  if let Some(status) = status {
+    filter.insert("status", status.name());
+  }
+
+  let options = FindOptions::builder().sort(doc! {"_id": -1}).limit(limit).build();
+
+  let mut cursor = delivery_collection.find(filter, options).await?;
+
+  while let Some(delivery) = cursor.try_next().await? {
+    deliveries.push(delivery);
+  }
+
+  Ok(deliveries)
+}
+
+/// Adds evaluations made during the hour starting at `hour` to the hourly counts of their products
+///
+/// # Parameters
+/// * **hour**   - Start of the hour the evaluations were made in
+/// * **counts** - Number of evaluations by product ID
+pub async fn add_evaluation_counts(
+  client: &Client,
+  hour: DateTime<Utc>,
+  counts: &HashMap<String, u64>,
+) -> error::Result<()> {
+  let db = client.database("data");
+  let count_collection = db.collection::<Document>("evaluation_counts");
+
+  let hour = mongodb::bson::DateTime::from_millis(hour.timestamp_millis());
+  let options = UpdateOptions::builder().upsert(true).build();
+
+  for (product_id, count) in counts {
+    count_collection
+      .update_one(
+        doc! {"product_id": product_id, "hour": hour},
+        doc! {"$inc": {"count": *count as i64}},
+        options.clone(),
+      )
+      .await?;
+  }
+
+  Ok(())
+}
+
+/// Creates the indexes of the hourly evaluation counts
+///
+/// Counts are unique by product and hour, and expire after `retention`
+pub async fn create_evaluation_count_indexes(client: &Client, retention: Duration) -> error::Result<()> {
+  let db = client.database("data");
+  let count_collection = db.collection::<Document>("evaluation_counts");
+
+  let indexes = vec![
+    IndexModel::builder()
+      .keys(doc! {"product_id": 1, "hour": 1})
+      .options(IndexOptions::builder().unique(true).build())
+      .build(),
+    IndexModel::builder()
+      .keys(doc! {"hour": 1})
+      .options(IndexOptions::builder().expire_after(retention).build())
+      .build(),
+  ];
+
+  count_collection.create_indexes(indexes, None).await?;
+
+  Ok(())
+}
+
+/// Counts the products, flags, users, logged in sessions, and evaluations since `since`, aggregated by the database
+///
+/// Flags and users are grouped by `$group` stages, so none of their documents are loaded
+pub async fn aggregate_counts(client: &Client, since: DateTime<Utc>) -> error::Result<Stats> {
+  let db = client.database("data");
+  let now = mongodb::bson::DateTime::now();
+  let mut stats = Stats {
+    products: db.collection::<Product>("products").count_documents(None, None).await?,
+    active_sessions: db
+      .collection::<Session>("sessions")
+      .count_documents(doc! {"expires_at": {"$gt": now}}, None)
+      .await?,
+    ..Default::default()
+  };
+
+  // Global releases are stored as a string, the others as a document keyed by the name of their kind
+  let release_kind = doc! {
+    "$cond": [
+      {"$eq": [{"$type": "$release_type"}, "string"]},
+      "$release_type",
+      {"$arrayElemAt": [{"$map": {"input": {"$objectToArray": "$release_type"}, "in": "$$this.k"}}, 0]},
+    ]
+  };
+  let pipeline = vec![doc! {
+    "$group": {
+      "_id": {
+        "product_id": "$product_id",
+        "enabled": "$enabled",
+        "archived": {"$ifNull": ["$archived", false]},
+        "release_type": release_kind,
+      },
+      "count": {"$sum": 1},
+    }
+  }];
+
+  let mut cursor = db
+    .collection::<FeatureFlag>("features")
+    .aggregate(pipeline, None)
+    .await?;
+  while let Some(group) = cursor.try_next().await? {
+    let count = group_count(&group);
+    let key = group.get_document("_id").ok();
+    let enabled = key.and_then(|x| x.get_bool("enabled").ok()).unwrap_or_default();
+    let archived = key.and_then(|x| x.get_bool("archived").ok()).unwrap_or_default();
+    let release_type = key.and_then(|x| x.get_str("release_type").ok()).unwrap_or("Unknown");
+    let product_id = key.and_then(|x| x.get_str("product_id").ok()).unwrap_or_default();
+
+    let flags = &mut stats.flags;
+    flags.total += count;
+    match (archived, enabled) {
+      (true, _) => flags.archived += count,
+      (false, true) => flags.enabled += count,
+      (false, false) => flags.disabled += count,
+    }
+    *flags.by_release_type.entry(release_type.to_string()).or_default() += count;
+    *flags.by_product.entry(product_id.to_string()).or_default() += count;
+  }
+
+  let pipeline = vec![doc! {"$group": {"_id": "$account_type", "count": {"$sum": 1}}}];
+
+  let mut cursor = db.collection::<User>("users").aggregate(pipeline, None).await?;
+  while let Some(group) = cursor.try_next().await? {
+    let count = group_count(&group);
+    let account_type = group.get_str("_id").unwrap_or("Unknown");
+
+    stats.users.total += count;
+    *stats.users.by_account_type.entry(account_type.to_string()).or_default() += count;
+  }
+
+  let since = mongodb::bson::DateTime::from_millis(since.timestamp_millis());
+  let pipeline = vec![
+    doc! {"$match": {"hour": {"$gte": since}}},
+    doc! {"$group": {"_id": null, "count": {"$sum": "$count"}}},
+  ];
+
+  let mut cursor = db
+    .collection::<Document>("evaluation_counts")
+    .aggregate(pipeline, None)
+    .await?;
+  while let Some(group) = cursor.try_next().await? {
+    stats.evaluations_24h += group_count(&group);
+  }
+
+  Ok(stats)
+}
+
+/// Reads every document of every backed up collection, users with their password hash
+pub async fn export_backup(client: &Client) -> error::Result<Backup> {
+  let db = client.database("data");
+
+  Ok(Backup {
+    version: BACKUP_VERSION,
+    created_at: Utc::now(),
+    products: dump(&db, "products").await?,
+    flags: dump(&db, "features").await?,
+    users: dump(&db, "users").await?,
+    comments: dump(&db, "comments").await?,
+    plugins: dump(&db, "plugins").await?,
+    webhooks: dump(&db, "webhooks").await?,
+    deliveries: dump(&db, "webhook_deliveries").await?,
+    api_keys: dump(&db, "api_keys").await?,
+    service_accounts: dump(&db, "service_accounts").await?,
+    audit: dump(&db, "audit").await?,
+  })
+}
+
+/// Writes every document of `backup`, replacing the documents with the same IDs
+///
+/// Not written in a transaction, backups may be larger than one can hold. Restoring again after a failure finishes it
+pub async fn restore_backup(client: &Client, backup: &Backup) -> error::Result<()> {
+  let db = client.database("data");
+
+  load(&db, "products", &backup.products, |x| x.oid).await?;
+  load(&db, "features", &backup.flags, |x| x.oid).await?;
+  load(&db, "users", &backup.users, |x| x.oid).await?;
+  load(&db, "comments", &backup.comments, |x| x.oid).await?;
+  load(&db, "plugins", &backup.plugins, |x| x.oid).await?;
+  load(&db, "webhooks", &backup.webhooks, |x| x.oid).await?;
+  load(&db, "webhook_deliveries", &backup.deliveries, |x| Some(x.oid)).await?;
+  load(&db, "api_keys", &backup.api_keys, |x| x.oid).await?;
+  load(&db, "service_accounts", &backup.service_accounts, |x| x.oid).await?;
+  load(&db, "audit", &backup.audit, |x| x.oid).await?;
+
+  Ok(())
+}
+
+/// Reads every document of the collection `name`
+async fn dump<T>(db: &Database, name: &str) -> error::Result<Vec<T>>
+where
+  T: DeserializeOwned + Unpin + Send + Sync,
+{
+  db.collection::<T>(name).find(None, None).await?.try_collect().await
+}
+
+/// Writes `documents` to the collection `name`, replacing the documents with the same IDs. Documents without an ID are
+/// skipped
+async fn load<T>(db: &Database, name: &str, documents: &[T], oid: fn(&T) -> Option<ObjectId>) -> error::Result<()>
+where
+  T: Serialize,
+{
+  let collection = db.collection::<T>(name);
+  let options = ReplaceOptions::builder().upsert(true).build();
+
+  for document in documents {
+    if let Some(oid) = oid(document) {
+      collection
+        .replace_one(doc! {"_id": oid}, document, options.clone())
+        .await?;
+    }
+  }
+
+  Ok(())
+}
+
+/// Count of a `$group` stage result, summed into its `count` field
+fn group_count(group: &Document) -> u64 {
+  match group.get("count") {
+    Some(Bson::Int32(count)) => *count as u64,
+    Some(Bson::Int64(count)) => *count as u64,
+    Some(Bson::Double(count)) => *count as u64,
+    _ => 0,
+  }
+}
+
+/// Collation of the search indexes, comparing strings without regard to case
+fn search_collation() -> Collation {
+  Collation::builder()
+    .locale("en")
+    .strength(CollationStrength::Secondary)
+    .build()
+}
+
+/// Query matching documents whose `field` starts with `term`
+///
+/// A range rather than a regex so the index is used, U+FFFF sorts after every character in the collation
+fn prefix_query(field: &str, term: &str) -> Document {
+  doc! {field: {"$gte": term, "$lt": format!("{}{}", term, '\u{FFFF}')}}
+}
+
+/// Pings the database, returning a result indicating if it's reachable
+pub async fn ping(client: &Client) -> error::Result<()> {
+  client.database("data").run_command(doc! {"ping": 1}, None).await?;
+
+  Ok(())
+}
+
+/// Adds the conditions of `filter` to a query of flags
+fn flag_filter_document(mut query: Document, filter: &FlagFilter<'_>) -> Document {
+  if let Some(tag) = filter.tag {
+    query.insert("tags", tag);
+  }
+
+  if !filter.include_archived {
+    query.insert("archived", doc! {"$ne": true});
+  }
+
+  if let Some(enabled) = filter.enabled {
+    query.insert("enabled", enabled);
+  }
+
+  // Unit variants are stored as their name, variants with data as a document keyed by their name
+  match filter.release_type {
+    Some(ReleaseKind::Global) => {
+      query.insert("release_type", ReleaseKind::Global.name());
+    }
+    Some(kind) => {
+      query.insert(format!("release_type.{}", kind.name()), doc! {"$exists": true});
+    }
+    None => (),
+  }
+
+  query
+}
+
+fn flag_sort_document(sort: Option<FlagSort>, page: Page) -> Option<Document> {
+  let sort = match sort {
+    Some(sort) => sort,
+    None => return page_sort_document(page),
+  };
+  let field = match sort.field {
+    FlagSortField::Name => "name",
+    FlagSortField::CreatedAt => "created_at",
+    FlagSortField::UpdatedAt => "updated_at",
+  };
+
+  // Ties are broken by ID so pages of the same query are stable
+  Some(doc! {field: if sort.descending { -1 } else { 1 }, "_id": 1})
+}
+
+/// Sorts a paged listing by ID so its pages don't overlap, unsorted listings are left in natural order
+fn page_sort_document(page: Page) -> Option<Document> {
+  match page.is_everything() {
+    true => None,
+    false => Some(doc! {"_id": 1}),
+  }
+}
+
+fn flag_projection_document(projection: FlagProjection) -> Option<Document> {
+  match projection {
+    FlagProjection::Full => None,
+    FlagProjection::Evaluation => Some(doc! {
+      "description": 0,
+      "owner": 0,
+      "maintainer_id": 0,
+      "tags": 0,
+      "created_at": 0,
+      "updated_at": 0,
+      "prerequisites": 0,
+      "scheduled_changes": 0,
+    }),
+    FlagProjection::Summary => Some(doc! {
+      "always_include": 0,
+      "always_exclude": 0,
+      "tags": 0,
+      "description": 0,
+      "owner": 0,
+      "maintainer_id": 0,
+      "created_at": 0,
+      "updated_at": 0,
+      "prerequisites": 0,
+      "rules": 0,
+      "settings": 0,
+      "payload": 0,
+      "scheduled_changes": 0,
+    }),
+  }
+}
+
+/// Leaves the password hash out of users fetched for listings, they must never be written back
+fn public_user_projection() -> Document {
+  doc! {"password_hash": 0}
+}
+
+/// Returns `MONGO_STR`, read on every operation so a rotated connection string is picked up
+fn connection_string() -> String {
+  dotenv::dotenv().ok();
+
+  match secrets::var("MONGO_STR") {
+    Some(value) => value,
+    None => {
+      panic!("Error getting MongoDB connection string (MONGO_STR): not set");
+    }
+  }
+}
+
+/// Constructs a client of the deployment at `connection_string`, with its own connection pool
+async fn connect(connection_string: &str) -> error::Result<Client> {
+  let mut client_options = ClientOptions::parse(connection_string).await?;
+  options::apply_env(&mut client_options);
+
+  Client::with_options(client_options)
+}
+
+/// Starts a transaction on `session`, returning if one was started
+///
+/// Standalone servers don't support transactions, the writes of a session without one are applied as they're made.
+/// Transactions that aren't committed are aborted when their session is dropped, so returning early on an error rolls
+/// back the writes made before it
+async fn start_transaction(session: &mut ClientSession) -> error::Result<bool> {
+  match session.start_transaction(None).await {
+    Ok(()) => Ok(true),
+    Err(e) if matches!(e.kind.as_ref(), ErrorKind::Transaction { .. }) => Ok(false),
+    Err(e) => Err(e),
+  }
+}
+
+/// Parses an ID of a document, `None` if it isn't a valid `ObjectId`
+fn parse_id(id: &str) -> Option<ObjectId> {
+  ObjectId::parse_str(id).ok()
+}
+
+/// Code of the error MongoDB raises on a duplicate key of a unique index
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+/// If `e` was raised on a duplicate key of a unique index
+fn is_duplicate_key(e: &error::Error) -> bool {
+  match e.kind.as_ref() {
+    ErrorKind::Command(command_error) => command_error.code == DUPLICATE_KEY_CODE,
+    ErrorKind::Write(WriteFailure::WriteError(write_error)) => write_error.code == DUPLICATE_KEY_CODE,
+    ErrorKind::BulkWrite(BulkWriteFailure {
+      write_errors: Some(write_errors),
+      ..
+    }) => write_errors.iter().any(|x| x.code == DUPLICATE_KEY_CODE),
+    _ => false,
+  }
+}
+
+impl From<error::Error> for StorageError {
+  fn from(e: error::Error) -> StorageError {
+    match e.kind.as_ref() {
+      _ if is_duplicate_key(&e) => StorageError::Conflict(e.to_string()),
+      ErrorKind::BsonSerialization(_) | ErrorKind::BsonDeserialization(_) | ErrorKind::InvalidResponse { .. } => {
+        StorageError::Serialization(e.to_string())
+      }
+      _ => StorageError::Connection(e.to_string()),
+    }
+  }
+}
+
+/// `DatabaseDriver` storing everything in MongoDB, connecting to `MONGO_STR`
+pub struct MongoDriver {
+  /// How operations failing on transient errors are retried
+  retry: RetryPolicy,
+  /// If changes are read from change streams, set by `MONGO_CHANGE_STREAMS` (`false` by default)
+  change_streams: bool,
+  /// Client shared by every operation, with the connection string it was made with. `None` until the first operation
+  client: Mutex<Option<(String, Client)>>,
+}
+
+impl MongoDriver {
+  /// Constructs a `MongoDriver` retrying operations and watching changes as configured by the environment, see
+  /// `RetryPolicy::from_env`
+  pub fn from_env() -> MongoDriver {
+    MongoDriver {
+      retry: RetryPolicy::from_env(),
+      change_streams: dotenv::var("MONGO_CHANGE_STREAMS")
+        .map(|x| x.trim().eq_ignore_ascii_case("true"))
+        .unwrap_or(false),
+      client: Mutex::new(None),
+    }
+  }
+
+  /// Returns the client shared by every operation, so they reuse the connections of its pool
+  ///
+  /// The client is made on the first operation, and made again when `MONGO_STR` was rotated since
+  async fn client(&self) -> error::Result<Client> {
+    let connection_string = connection_string();
+    let mut client = self.client.lock().await;
+
+    match &*client {
+      Some((current, shared)) if *current == connection_string => Ok(shared.clone()),
+      _ => {
+        let shared = connect(&connection_string).await?;
+        *client = Some((connection_string, shared.clone()));
+        Ok(shared)
+      }
+    }
+  }
+}
+
+#[rocket::async_trait]
+impl DatabaseDriver for MongoDriver {
+  async fn get_product(&self, product_name: &str) -> StorageResult<Option<Product>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_product(&client, product_name))
+        .await?,
+    )
+  }
+
+  async fn get_product_by_id(&self, product_id: &str) -> StorageResult<Option<Product>> {
+    let client = self.client().await?;
+    match parse_id(product_id) {
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Idempotent, || get_product_by_id(&client, id))
+          .await?,
+      ),
+      None => Ok(None),
+    }
+  }
+
+  async fn get_products(&self, user_id: Option<String>, page: Page) -> StorageResult<Vec<Product>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_products(&client, user_id.clone(), page))
+        .await?,
+    )
+  }
+
+  async fn get_feature_flag(
+    &self,
+    product_id: &str,
+    flag_name: &str,
+    projection: FlagProjection,
+  ) -> StorageResult<Option<FeatureFlag>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || {
+          get_feature_flag(&client, product_id, flag_name, projection)
+        })
+        .await?,
+    )
+  }
+
+  async fn get_feature_flag_by_id(
+    &self,
+    flag_id: &str,
+    projection: FlagProjection,
+  ) -> StorageResult<Option<FeatureFlag>> {
+    let client = self.client().await?;
+    match parse_id(flag_id) {
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Idempotent, || get_feature_flag_by_id(&client, id, projection))
+          .await?,
+      ),
+      None => Ok(None),
+    }
+  }
+
+  async fn get_feature_flags(
+    &self,
+    product_id: &str,
+    filter: FlagFilter<'_>,
+    projection: FlagProjection,
+  ) -> StorageResult<Vec<FeatureFlag>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || {
+          get_feature_flags(&client, product_id, filter.clone(), projection)
+        })
+        .await?,
+    )
+  }
+
+  async fn get_feature_flags_by_maintainer(
+    &self,
+    user_id: &str,
+    filter: FlagFilter<'_>,
+  ) -> StorageResult<Vec<FeatureFlag>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || {
+          get_feature_flags_by_maintainer(&client, user_id, filter.clone())
+        })
+        .await?,
+    )
+  }
+
+  async fn get_feature_flags_by_keys(
+    &self,
+    keys: &[(String, String)],
+    projection: FlagProjection,
+  ) -> StorageResult<Vec<FeatureFlag>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || {
+          get_feature_flags_by_keys(&client, keys, projection)
+        })
+        .await?,
+    )
+  }
+
+  async fn update_feature_flag(&self, feature_flag_id: &str, updated: FeatureFlag) -> StorageResult<bool> {
+    let client = self.client().await?;
+    match parse_id(feature_flag_id) {
+      Some(id) => {
+        self
+          .retry
+          .run(Retry::Idempotent, || update_feature_flag(&client, id, updated.clone()))
+          .await?;
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+
+  async fn delete_feature_flag(&self, feature_flag_id: &str) -> StorageResult<Option<FeatureFlag>> {
+    let client = self.client().await?;
+    match parse_id(feature_flag_id) {
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Idempotent, || delete_feature_flag(&client, id))
+          .await?,
+      ),
+      None => Ok(None),
+    }
+  }
+
+  async fn set_feature_flag_enabled(
+    &self,
+    feature_flag_id: &str,
+    user_id: Option<&str>,
+    enabled: bool,
+    updated_at: DateTime<Utc>,
+  ) -> StorageResult<Option<FeatureFlag>> {
+    let client = self.client().await?;
+    match parse_id(feature_flag_id) {
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Idempotent, || {
+            set_feature_flag_enabled(&client, id, user_id, enabled, updated_at)
+          })
+          .await?,
+      ),
+      None => Ok(None),
+    }
+  }
+
+  async fn update_product(&self, product_id: &str, updated: Product) -> StorageResult<bool> {
+    let client = self.client().await?;
+    match parse_id(product_id) {
+      Some(id) => {
+        self
+          .retry
+          .run(Retry::Idempotent, || update_product(&client, id, updated.clone()))
+          .await?;
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+
+  async fn add_product_user(&self, product_id: &str, user_id: &str) -> StorageResult<Option<Product>> {
+    let client = self.client().await?;
+    match parse_id(product_id) {
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Idempotent, || add_product_user(&client, id, user_id))
+          .await?,
+      ),
+      None => Ok(None),
+    }
+  }
+
+  async fn remove_product_user(&self, product_id: &str, user_id: &str) -> StorageResult<Option<Product>> {
+    let client = self.client().await?;
+    match parse_id(product_id) {
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Idempotent, || remove_product_user(&client, id, user_id))
+          .await?,
+      ),
+      None => Ok(None),
+    }
+  }
+
+  async fn delete_product(&self, product_id: &str, flag_ids: &[String]) -> StorageResult<bool> {
+    let client = self.client().await?;
+    match parse_id(product_id) {
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Idempotent, || delete_product(&client, id, flag_ids))
+          .await?,
+      ),
+      None => Ok(false),
+    }
+  }
+
+  async fn import_product(
+    &self,
+    product_id: &str,
+    product: Option<Product>,
+    updated: Vec<FeatureFlag>,
+    created: Vec<FeatureFlagBuilder>,
+  ) -> StorageResult<Vec<FeatureFlag>> {
+    let client = self.client().await?;
+    let id = match parse_id(product_id) {
+      Some(id) => id,
+      None => return Err(StorageError::NotFound),
+    };
+
+    // IDs are generated here so a retry inserts the same flags, failing instead of inserting them twice
+    let created: Vec<FeatureFlag> = created
+      .into_iter()
+      .map(|x| x.with_oid(ObjectId::new()).build())
+      .collect();
+
+    self
+      .retry
+      .run(Retry::Unapplied, || {
+        import_product(&client, id, product.clone(), updated.clone(), created.clone())
+      })
+      .await?;
+
+    Ok(created)
+  }
+
+  async fn update_user(&self, user_id: &str, updated: User) -> StorageResult<bool> {
+    let client = self.client().await?;
+    match parse_id(user_id) {
+      Some(id) => {
+        self
+          .retry
+          .run(Retry::Idempotent, || update_user(&client, id, updated.clone()))
+          .await?;
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+
+  async fn get_user(&self, user_email: Option<&str>, user_id: Option<&str>) -> StorageResult<Option<User>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_user(&client, user_email, user_id))
+        .await?,
+    )
+  }
+
+  async fn get_users(&self, account_type: Option<AccountType>, page: Page) -> StorageResult<Vec<User>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_users(&client, account_type.clone(), page))
+        .await?,
+    )
+  }
+
+  async fn get_users_by_ids(&self, user_ids: &[String]) -> StorageResult<Vec<User>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_users_by_ids(&client, user_ids))
+        .await?,
+    )
+  }
+
+  async fn count_users(&self) -> StorageResult<u64> {
+    let client = self.client().await?;
+    Ok(self.retry.run(Retry::Idempotent, || count_users(&client)).await?)
+  }
+
+  async fn create_product(&self, product_builder: ProductBuilder) -> StorageResult<Product> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || create_product(&client, product_builder.clone()))
+        .await?,
+    )
+  }
+
+  async fn create_flag(&self, flag_builder: FeatureFlagBuilder) -> StorageResult<FeatureFlag> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || create_flag(&client, flag_builder.clone()))
+        .await?,
+    )
+  }
+
+  async fn create_flags(&self, flag_builders: Vec<FeatureFlagBuilder>) -> StorageResult<Vec<Option<FeatureFlag>>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || create_flags(&client, flag_builders.clone()))
+        .await?,
+    )
+  }
+
+  async fn create_user(&self, user_builder: UserBuilder) -> StorageResult<User> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || create_user(&client, user_builder.clone()))
+        .await?,
+    )
+  }
+
+  async fn create_audit_entry(&self, entry: AuditEntry) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || create_audit_entry(&client, entry.clone()))
+        .await?,
+    )
+  }
+
+  async fn create_comment(&self, comment: Comment) -> StorageResult<Comment> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || create_comment(&client, comment.clone()))
+        .await?,
+    )
+  }
+
+  async fn get_comments(&self, flag_id: &str) -> StorageResult<Vec<Comment>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_comments(&client, flag_id))
+        .await?,
+    )
+  }
+
+  async fn create_plugin(&self, plugin: Plugin) -> StorageResult<Plugin> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || create_plugin(&client, plugin.clone()))
+        .await?,
+    )
+  }
+
+  async fn get_plugin(&self, plugin_id: &str) -> StorageResult<Option<Plugin>> {
+    let client = self.client().await?;
+    match parse_id(plugin_id) {
+      Some(id) => Ok(self.retry.run(Retry::Idempotent, || get_plugin(&client, id)).await?),
+      None => Ok(None),
+    }
+  }
+
+  async fn create_webhook(&self, webhook: Webhook) -> StorageResult<Webhook> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || create_webhook(&client, webhook.clone()))
+        .await?,
+    )
+  }
+
+  async fn get_webhook(&self, webhook_id: &str) -> StorageResult<Option<Webhook>> {
+    let client = self.client().await?;
+    match parse_id(webhook_id) {
+      Some(id) => Ok(self.retry.run(Retry::Idempotent, || get_webhook(&client, id)).await?),
+      None => Ok(None),
+    }
+  }
+
+  async fn get_webhooks(&self, product_id: Option<&str>) -> StorageResult<Vec<Webhook>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_webhooks(&client, product_id))
+        .await?,
+    )
+  }
+
+  async fn update_webhook(&self, webhook_id: &str, updated: Webhook) -> StorageResult<bool> {
+    let client = self.client().await?;
+    match parse_id(webhook_id) {
+      Some(id) => {
+        self
+          .retry
+          .run(Retry::Idempotent, || update_webhook(&client, id, updated.clone()))
+          .await?;
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+
+  async fn delete_webhook(&self, webhook_id: &str) -> StorageResult<bool> {
+    let client = self.client().await?;
+    match parse_id(webhook_id) {
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Idempotent, || delete_webhook(&client, id))
+          .await?,
+      ),
+      None => Ok(false),
+    }
+  }
+
+  async fn create_api_key(&self, api_key: ApiKey) -> StorageResult<ApiKey> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || create_api_key(&client, api_key.clone()))
+        .await?,
+    )
+  }
+
+  async fn get_api_key(&self, api_key_id: &str) -> StorageResult<Option<ApiKey>> {
+    let client = self.client().await?;
+    match parse_id(api_key_id) {
+      Some(id) => Ok(self.retry.run(Retry::Idempotent, || get_api_key(&client, id)).await?),
+      None => Ok(None),
+    }
+  }
+
+  async fn get_api_key_by_hash(&self, key_hash: &str) -> StorageResult<Option<ApiKey>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_api_key_by_hash(&client, key_hash))
+        .await?,
+    )
+  }
+
+  async fn get_api_keys(&self, product_id: &str) -> StorageResult<Vec<ApiKey>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_api_keys(&client, product_id))
+        .await?,
+    )
+  }
+
+  async fn revoke_api_key(&self, api_key_id: &str, revoked_at: DateTime<Utc>) -> StorageResult<bool> {
+    let client = self.client().await?;
+    match parse_id(api_key_id) {
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Idempotent, || revoke_api_key(&client, id, revoked_at))
+          .await?,
+      ),
+      None => Ok(false),
+    }
+  }
+
+  async fn create_api_key_indexes(&self) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || create_api_key_indexes(&client))
+        .await?,
+    )
+  }
+
+  async fn create_service_account(&self, service_account: ServiceAccount) -> StorageResult<ServiceAccount> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || {
+          create_service_account(&client, service_account.clone())
+        })
+        .await?,
+    )
+  }
+
+  async fn get_service_account(&self, service_account_id: &str) -> StorageResult<Option<ServiceAccount>> {
+    let client = self.client().await?;
+    match parse_id(service_account_id) {
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Idempotent, || get_service_account(&client, id))
+          .await?,
+      ),
+      None => Ok(None),
+    }
+  }
+
+  async fn get_service_account_by_hash(&self, token_hash: &str) -> StorageResult<Option<ServiceAccount>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_service_account_by_hash(&client, token_hash))
+        .await?,
+    )
+  }
+
+  async fn get_service_accounts(&self) -> StorageResult<Vec<ServiceAccount>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_service_accounts(&client))
+        .await?,
+    )
+  }
+
+  async fn revoke_service_account(&self, service_account_id: &str, revoked_at: DateTime<Utc>) -> StorageResult<bool> {
+    let client = self.client().await?;
+    match parse_id(service_account_id) {
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Idempotent, || revoke_service_account(&client, id, revoked_at))
+          .await?,
+      ),
+      None => Ok(false),
+    }
+  }
+
+  async fn create_service_account_indexes(&self) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || create_service_account_indexes(&client))
+        .await?,
+    )
+  }
+
+  async fn create_session(&self, session: Session) -> StorageResult<Session> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || create_session(&client, session.clone()))
+        .await?,
+    )
+  }
+
+  async fn get_session(&self, user_id: &str, token_hash: &str) -> StorageResult<Option<Session>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_session(&client, user_id, token_hash))
+        .await?,
+    )
+  }
+
+  async fn refresh_session(
+    &self,
+    user_id: &str,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+  ) -> StorageResult<Option<Session>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || {
+          refresh_session(&client, user_id, token_hash, expires_at)
+        })
+        .await?,
+    )
+  }
+
+  async fn delete_session(&self, user_id: &str, token_hash: &str) -> StorageResult<bool> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || delete_session(&client, user_id, token_hash))
+        .await?,
+    )
+  }
+
+  async fn delete_sessions(&self, user_id: &str) -> StorageResult<u64> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || delete_sessions(&client, user_id))
+        .await?,
+    )
+  }
+
+  async fn create_session_indexes(&self) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || create_session_indexes(&client))
+        .await?,
+    )
+  }
+
+  async fn create_webhook_delivery(&self, delivery: &WebhookDelivery) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || create_webhook_delivery(&client, delivery))
+        .await?,
+    )
+  }
+
+  async fn update_webhook_delivery(&self, delivery: &WebhookDelivery) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || update_webhook_delivery(&client, delivery))
+        .await?,
+    )
+  }
+
+  async fn get_webhook_delivery(&self, delivery_id: &str) -> StorageResult<Option<WebhookDelivery>> {
+    let client = self.client().await?;
+    match parse_id(delivery_id) {
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Idempotent, || get_webhook_delivery(&client, id))
+          .await?,
+      ),
+      None => Ok(None),
+    }
+  }
+
+  async fn get_webhook_deliveries(
+    &self,
+    webhook_id: &str,
+    status: Option<DeliveryStatus>,
+    limit: i64,
+  ) -> StorageResult<Vec<WebhookDelivery>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || {
+          get_webhook_deliveries(&client, webhook_id, status, limit)
+        })
+        .await?,
+    )
+  }
+
+  async fn get_feature_flags_with_due_changes(&self, now: DateTime<Utc>) -> StorageResult<Vec<FeatureFlag>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_feature_flags_with_due_changes(&client, now))
+        .await?,
+    )
+  }
+
+  async fn apply_scheduled_change(
+    &self,
+    feature_flag_id: &str,
+    change_id: ObjectId,
+    field: &ChangedField,
+    updated_at: DateTime<Utc>,
+  ) -> StorageResult<Option<FeatureFlag>> {
+    let client = self.client().await?;
+    match parse_id(feature_flag_id) {
+      // Applied once, a retry after the change was pulled would find nothing and skip recording it
+      Some(id) => Ok(
+        self
+          .retry
+          .run(Retry::Unapplied, || {
+            apply_scheduled_change(&client, id, change_id, field, updated_at)
+          })
+          .await?,
+      ),
+      None => Ok(None),
+    }
+  }
+
+  async fn get_audit_entries(
+    &self,
+    filter: &AuditFilter,
+    after: Option<&str>,
+    limit: i64,
+  ) -> StorageResult<Option<Vec<AuditEntry>>> {
+    let client = self.client().await?;
+    let after = match after.map(parse_id) {
+      Some(Some(id)) => Some(id),
+      Some(None) => return Ok(None),
+      None => None,
+    };
+
+    Ok(Some(
+      self
+        .retry
+        .run(Retry::Idempotent, || get_audit_entries(&client, filter, after, limit))
+        .await?,
+    ))
+  }
+
+  async fn search_feature_flags(&self, term: &str, limit: i64) -> StorageResult<Vec<FeatureFlag>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || search_feature_flags(&client, term, limit))
+        .await?,
+    )
+  }
+
+  async fn search_products(&self, term: &str, limit: i64) -> StorageResult<Vec<Product>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || search_products(&client, term, limit))
+        .await?,
+    )
+  }
+
+  async fn search_users(&self, term: &str, limit: i64) -> StorageResult<Vec<User>> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || search_users(&client, term, limit))
+        .await?,
+    )
+  }
+
+  async fn create_search_indexes(&self) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || create_search_indexes(&client))
+        .await?,
+    )
+  }
+
+  async fn ping(&self) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(self.retry.run(Retry::Idempotent, || ping(&client)).await?)
+  }
+
+  async fn create_audit_indexes(&self) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || create_audit_indexes(&client))
+        .await?,
+    )
+  }
+
+  async fn add_evaluation_counts(&self, hour: DateTime<Utc>, counts: &HashMap<String, u64>) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || add_evaluation_counts(&client, hour, counts))
+        .await?,
+    )
+  }
+
+  async fn create_evaluation_count_indexes(&self, retention: Duration) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || {
+          create_evaluation_count_indexes(&client, retention)
+        })
+        .await?,
+    )
+  }
+
+  async fn aggregate_counts(&self, since: DateTime<Utc>) -> StorageResult<Stats> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || aggregate_counts(&client, since))
+        .await?,
+    )
+  }
+
+  async fn export_backup(&self) -> StorageResult<Backup> {
+    let client = self.client().await?;
+    Ok(self.retry.run(Retry::Idempotent, || export_backup(&client)).await?)
+  }
+
+  async fn restore_backup(&self, backup: &Backup) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(
+      self
+        .retry
+        .run(Retry::Idempotent, || restore_backup(&client, backup))
+        .await?,
+    )
+  }
+
+  async fn run_migrations(&self) -> StorageResult<Vec<String>> {
+    let client = self.client().await?;
+    // Migrations whose claim is held wait for it to expire when retried, so only errors raised before the claim are
+    // retried
+    Ok(
+      self
+        .retry
+        .run(Retry::Unapplied, || migrations::run_pending(&client))
+        .await?,
+    )
+  }
+
+  async fn revert_last_migration(&self) -> StorageResult<Option<String>> {
+    let client = self.client().await?;
+    Ok(migrations::revert_last(&client).await?)
+  }
+
+  fn watches_changes(&self) -> bool {
+    self.change_streams
+  }
+
+  async fn watch_changes(&self, changes: mpsc::Sender<StoredChange>) -> StorageResult<()> {
+    let client = self.client().await?;
+    Ok(changes::watch(&client, changes).await?)
+  }
 }
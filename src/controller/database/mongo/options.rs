@@ -0,0 +1,102 @@
+//! Options of the MongoDB client set by the environment, overriding the ones of the connection string
+//!
+//! - `MONGO_APP_NAME`: name the connections are made under, shown in the server's logs
+//! - `MONGO_AUTH_SOURCE`: database the credentials of the connection string are checked against
+//! - `MONGO_TLS`: `true` or `false` to turn TLS on or off
+//! - `MONGO_TLS_CA_FILE`: path of the CA certificates servers are checked against (the Mozilla roots by default)
+//! - `MONGO_TLS_CERT_KEY_FILE`: path of the certificate and key presented to servers requiring client certificates
+//! - `MONGO_TLS_ALLOW_INVALID_CERTIFICATES`: `true` to accept servers with invalid certificates, for testing only
+//! - `MONGO_MIN_POOL_SIZE` and `MONGO_MAX_POOL_SIZE`: least and most connections kept to each server
+//! - `MONGO_CONNECT_TIMEOUT_MS`: longest wait for a connection to a server
+//! - `MONGO_SERVER_SELECTION_TIMEOUT_MS`: longest wait for a server an operation can be sent to
+//!
+//! Setting a `MONGO_TLS_*` file, or allowing invalid certificates, turns TLS on unless `MONGO_TLS` is `false`
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use mongodb::options::{ClientOptions, Tls, TlsOptions};
+
+/// Reads a variable, `None` if it isn't set or is empty
+fn var(name: &str) -> Option<String> {
+  dotenv::var(name)
+    .ok()
+    .map(|x| x.trim().to_string())
+    .filter(|x| !x.is_empty())
+}
+
+/// Reads a boolean variable, `None` if it isn't set
+fn flag(name: &str) -> Option<bool> {
+  var(name).map(|x| x.eq_ignore_ascii_case("true"))
+}
+
+/// Reads a number of milliseconds, `None` if it isn't set or isn't a number
+fn millis(name: &str) -> Option<Duration> {
+  var(name).and_then(|x| x.parse().ok()).map(Duration::from_millis)
+}
+
+/// Overrides the options of `options` set by the environment
+pub fn apply_env(options: &mut ClientOptions) {
+  if let Some(app_name) = var("MONGO_APP_NAME") {
+    options.app_name = Some(app_name);
+  }
+
+  // Only credentials of the connection string are checked against the auth source
+  if let (Some(source), Some(credential)) = (var("MONGO_AUTH_SOURCE"), options.credential.as_mut()) {
+    credential.source = Some(source);
+  }
+
+  if let Some(size) = var("MONGO_MIN_POOL_SIZE").and_then(|x| x.parse().ok()) {
+    options.min_pool_size = Some(size);
+  }
+  if let Some(size) = var("MONGO_MAX_POOL_SIZE")
+    .and_then(|x| x.parse().ok())
+    .filter(|x| *x > 0)
+  {
+    options.max_pool_size = Some(size);
+  }
+
+  if let Some(timeout) = millis("MONGO_CONNECT_TIMEOUT_MS") {
+    options.connect_timeout = Some(timeout);
+  }
+  if let Some(timeout) = millis("MONGO_SERVER_SELECTION_TIMEOUT_MS") {
+    options.server_selection_timeout = Some(timeout);
+  }
+
+  apply_tls(options);
+}
+
+/// Overrides the TLS options of `options` set by the environment
+fn apply_tls(options: &mut ClientOptions) {
+  let ca_file = var("MONGO_TLS_CA_FILE").map(PathBuf::from);
+  let cert_key_file = var("MONGO_TLS_CERT_KEY_FILE").map(PathBuf::from);
+  let allow_invalid_certificates = flag("MONGO_TLS_ALLOW_INVALID_CERTIFICATES");
+
+  let configured = ca_file.is_some() || cert_key_file.is_some() || allow_invalid_certificates == Some(true);
+  match flag("MONGO_TLS") {
+    Some(false) => {
+      options.tls = Some(Tls::Disabled);
+      return;
+    }
+    Some(true) => (),
+    None if configured => (),
+    None => return,
+  }
+
+  // Options of the connection string are kept unless overridden
+  let mut tls = match options.tls.take() {
+    Some(Tls::Enabled(tls)) => tls,
+    _ => TlsOptions::default(),
+  };
+  if ca_file.is_some() {
+    tls.ca_file_path = ca_file;
+  }
+  if cert_key_file.is_some() {
+    tls.cert_key_file_path = cert_key_file;
+  }
+  if allow_invalid_certificates.is_some() {
+    tls.allow_invalid_certificates = allow_invalid_certificates;
+  }
+
+  options.tls = Some(Tls::Enabled(tls));
+}
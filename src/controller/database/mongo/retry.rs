@@ -0,0 +1,151 @@
+//! Retries of MongoDB operations failing on transient errors, so brief failovers don't fail requests
+//!
+//! Operations are retried up to `MONGO_RETRY_ATTEMPTS` times in total (3 by default, 1 to never retry) on network
+//! errors, no server being selectable, "not master" or "node is recovering" errors, and transactions aborted by a
+//! transient error, after a delay starting at `MONGO_RETRY_BASE_DELAY_MS` (50 by default) and doubling up to
+//! `MONGO_RETRY_MAX_DELAY_MS` (1000 by default). Each delay is shortened by a random part of up to `MONGO_RETRY_JITTER`
+//! of it (0.5 by default), so instances failing together don't retry together
+
+use std::future::Future;
+use std::time::Duration;
+
+use mongodb::error::{self, ErrorKind, WriteFailure, RETRYABLE_WRITE_ERROR, TRANSIENT_TRANSACTION_ERROR};
+
+use crate::log;
+
+/// Attempts made when `MONGO_RETRY_ATTEMPTS` isn't set
+const DEFAULT_ATTEMPTS: u32 = 3;
+
+/// First delay when `MONGO_RETRY_BASE_DELAY_MS` isn't set
+const DEFAULT_BASE_DELAY_MS: u64 = 50;
+
+/// Longest delay when `MONGO_RETRY_MAX_DELAY_MS` isn't set
+const DEFAULT_MAX_DELAY_MS: u64 = 1000;
+
+/// Jitter when `MONGO_RETRY_JITTER` isn't set
+const DEFAULT_JITTER: f64 = 0.5;
+
+/// Error codes of a primary stepping down, a node recovering or shutting down, or the network failing between servers
+const TRANSIENT_CODES: [i32; 13] = [6, 7, 89, 91, 189, 262, 9001, 10058, 10107, 11600, 11602, 13435, 13436];
+
+/// Error codes of a write refused by a server that isn't the primary, which is never applied
+const NOT_PRIMARY_CODES: [i32; 3] = [10058, 10107, 13435];
+
+/// Which errors an operation can be retried on
+#[derive(Clone, Copy, Debug)]
+pub enum Retry {
+  /// Reads and writes that can be applied twice, retried on every transient error
+  Idempotent,
+  /// Inserts and increments, retried only on errors raised before anything was written, since a network error may come
+  /// after the server applied them
+  Unapplied,
+}
+
+/// How MongoDB operations failing on transient errors are retried
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+  /// Attempts made in total, the first one included
+  attempts: u32,
+  base_delay: Duration,
+  max_delay: Duration,
+  /// Part of each delay, from 0 to 1, that's randomly taken off it
+  jitter: f64,
+}
+
+impl RetryPolicy {
+  /// Reads the policy from `MONGO_RETRY_ATTEMPTS`, `MONGO_RETRY_BASE_DELAY_MS`, `MONGO_RETRY_MAX_DELAY_MS` and
+  /// `MONGO_RETRY_JITTER`
+  pub fn from_env() -> RetryPolicy {
+    let var = |name: &str| dotenv::var(name).ok().map(|x| x.trim().to_string());
+
+    let base_delay = var("MONGO_RETRY_BASE_DELAY_MS")
+      .and_then(|x| x.parse().ok())
+      .unwrap_or(DEFAULT_BASE_DELAY_MS);
+    let max_delay = var("MONGO_RETRY_MAX_DELAY_MS")
+      .and_then(|x| x.parse().ok())
+      .unwrap_or(DEFAULT_MAX_DELAY_MS);
+
+    RetryPolicy {
+      attempts: var("MONGO_RETRY_ATTEMPTS")
+        .and_then(|x| x.parse().ok())
+        .filter(|x| *x > 0)
+        .unwrap_or(DEFAULT_ATTEMPTS),
+      base_delay: Duration::from_millis(base_delay),
+      max_delay: Duration::from_millis(max_delay.max(base_delay)),
+      jitter: var("MONGO_RETRY_JITTER")
+        .and_then(|x| x.parse::<f64>().ok())
+        .filter(|x| x.is_finite())
+        .unwrap_or(DEFAULT_JITTER)
+        .clamp(0.0, 1.0),
+    }
+  }
+
+  /// Runs `operation`, running it again after a delay when it fails on an error `retry` allows, until it has been
+  /// attempted as many times as the policy allows
+  pub async fn run<T, F, Fut>(&self, retry: Retry, mut operation: F) -> error::Result<T>
+  where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = error::Result<T>>,
+  {
+    let mut attempt = 1;
+
+    loop {
+      match operation().await {
+        Err(e) if attempt < self.attempts && is_retryable(&e, retry) => {
+          let delay = self.delay(attempt);
+          log!(
+            "Transient MongoDB error, retrying in {}ms (attempt {} of {}). Error: {:?}",
+            delay.as_millis(),
+            attempt + 1,
+            self.attempts,
+            e
+          );
+
+          tokio::time::sleep(delay).await;
+          attempt += 1;
+        }
+        result => return result,
+      }
+    }
+  }
+
+  /// Delay before the attempt following attempt number `attempt`, starting at 1
+  fn delay(&self, attempt: u32) -> Duration {
+    let delay = self
+      .base_delay
+      .saturating_mul(2u32.saturating_pow(attempt - 1))
+      .min(self.max_delay);
+
+    delay.mul_f64(1.0 - self.jitter * rand::random::<f64>())
+  }
+}
+
+/// Code of a server error, `None` for errors the server didn't send
+fn code(e: &error::Error) -> Option<i32> {
+  match e.kind.as_ref() {
+    ErrorKind::Command(e) => Some(e.code),
+    ErrorKind::Write(WriteFailure::WriteConcernError(e)) => Some(e.code),
+    ErrorKind::BulkWrite(e) => e.write_concern_error.as_ref().map(|x| x.code),
+    _ => None,
+  }
+}
+
+/// If `e` is transient, and `retry` allows retrying on it
+fn is_retryable(e: &error::Error, retry: Retry) -> bool {
+  let not_applied = matches!(
+    e.kind.as_ref(),
+    ErrorKind::ServerSelection { .. } | ErrorKind::ConnectionPoolCleared { .. }
+  ) || code(e).is_some_and(|x| NOT_PRIMARY_CODES.contains(&x))
+    // Aborted transactions roll back everything they wrote
+    || e.contains_label(TRANSIENT_TRANSACTION_ERROR);
+
+  match retry {
+    Retry::Unapplied => not_applied,
+    Retry::Idempotent => {
+      not_applied
+        || matches!(e.kind.as_ref(), ErrorKind::Io(_))
+        || e.contains_label(RETRYABLE_WRITE_ERROR)
+        || code(e).is_some_and(|x| TRANSIENT_CODES.contains(&x))
+    }
+  }
+}
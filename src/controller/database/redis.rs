@@ -0,0 +1,316 @@
+//! Redis read-through cache of flag lookups, absorbing the read load of `/check`
+//!
+//! Set `REDIS_URL` (e.g. `redis://:password@localhost:6379/0`, or `rediss://` for TLS) to cache flags looked up by
+//! product and name for `REDIS_FLAG_TTL_SECONDS` (30 by default). Reserved characters of the username and password
+//! must be percent-encoded. Writes through `ConnectionManager` delete the cached copies of the flags
+//! they change, so every instance sharing the Redis sees them right away, and the TTL bounds how long a lookup racing a
+//! write can keep an old copy. The cache fails open: when Redis can't be reached flags are read from the database
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use mongodb::bson;
+use reqwest::Url;
+use rocket::http::RawStr;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_rustls::rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+use crate::controller::database::FlagProjection;
+use crate::log;
+use crate::model::flag::FeatureFlag;
+
+/// How long flags are cached when `REDIS_FLAG_TTL_SECONDS` isn't set
+const DEFAULT_TTL_SECONDS: u64 = 30;
+
+/// How long connecting to Redis or running a command can take before the database is read instead
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Most connections kept open between commands
+const MAX_IDLE_CONNECTIONS: usize = 16;
+
+/// Stream to Redis, plain TCP or TLS
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Connection to Redis
+type Connection = BufStream<Box<dyn Stream>>;
+
+/// Reply of a Redis command
+enum Reply {
+  /// Simple string, `OK` for most commands
+  Status,
+  Integer,
+  /// Bulk string, `None` for a missing key
+  Bulk(Option<Vec<u8>>),
+}
+
+/// Cache of flags in Redis, speaking its protocol over TCP or TLS
+pub struct RedisCache {
+  /// Host name of the server, checked against its certificate over TLS
+  host: String,
+  /// `host:port` of the server
+  address: String,
+  /// Connector of `rediss://` URLs, `None` for plain TCP
+  tls: Option<TlsConnector>,
+  /// Username of Redis 6 ACLs, `None` for the default user
+  username: Option<String>,
+  password: Option<String>,
+  /// Logical database flags are cached in
+  database: u32,
+  /// Seconds flags are cached for
+  ttl: u64,
+  /// Connections open and not running a command
+  idle: Mutex<Vec<Connection>>,
+}
+
+impl RedisCache {
+  /// Constructs a `RedisCache` from `REDIS_URL` and `REDIS_FLAG_TTL_SECONDS`, `None` if `REDIS_URL` isn't set or valid
+  pub fn from_env() -> Option<RedisCache> {
+    let url = match dotenv::var("REDIS_URL") {
+      Ok(value) if !value.trim().is_empty() => value,
+      _ => return None,
+    };
+    let url = match Url::parse(url.trim()) {
+      Ok(url) if matches!(url.scheme(), "redis" | "rediss") && url.host_str().is_some() => url,
+      _ => {
+        log!("Error. 'REDIS_URL' isn't a valid redis:// or rediss:// URL, flags won't be cached in Redis");
+        return None;
+      }
+    };
+    let host = url.host_str().unwrap_or_default().to_string();
+    let tls = match url.scheme() {
+      "rediss" => Some(tls_connector()),
+      _ => None,
+    };
+    // The URL keeps the credentials encoded
+    let decode = |x: &str| RawStr::new(x).percent_decode_lossy().into_owned();
+
+    let ttl = match dotenv::var("REDIS_FLAG_TTL_SECONDS").map(|x| x.trim().parse::<u64>()) {
+      Ok(Ok(value)) if value > 0 => value,
+      _ => DEFAULT_TTL_SECONDS,
+    };
+
+    Some(RedisCache {
+      address: format!("{}:{}", host, url.port().unwrap_or(6379)),
+      host,
+      tls,
+      username: Some(decode(url.username())).filter(|x| !x.is_empty()),
+      password: url.password().map(decode),
+      database: url.path().trim_matches('/').parse().unwrap_or(0),
+      ttl,
+      idle: Mutex::new(vec![]),
+    })
+  }
+
+  /// Returns the cached copy of the flag named `flag_name` of the product of ID `product_id`, `None` if it isn't cached
+  /// or Redis can't be reached
+  pub async fn get_flag(&self, product_id: &str, flag_name: &str, projection: FlagProjection) -> Option<FeatureFlag> {
+    let key = flag_key(product_id, flag_name, projection);
+
+    match self.command(&[b"GET", key.as_bytes()]).await {
+      Ok(Reply::Bulk(Some(value))) => match bson::from_slice(&value) {
+        Ok(flag) => Some(flag),
+        Err(e) => {
+          log!("Error reading a cached flag, ignoring it. Error: {:?}", e);
+          None
+        }
+      },
+      Ok(_) => None,
+      Err(e) => {
+        log!("Error getting a flag from Redis, reading the database. Error: {}", e);
+        None
+      }
+    }
+  }
+
+  /// Caches the flag named `flag_name` of the product of ID `product_id`, as fetched with `projection`
+  pub async fn set_flag(&self, product_id: &str, flag_name: &str, projection: FlagProjection, flag: &FeatureFlag) {
+    let value = match bson::to_vec(flag) {
+      Ok(value) => value,
+      Err(e) => {
+        log!("Error serializing a flag to cache it. Error: {:?}", e);
+        return;
+      }
+    };
+    let key = flag_key(product_id, flag_name, projection);
+    let ttl = self.ttl.to_string();
+
+    if let Err(e) = self
+      .command(&[b"SET", key.as_bytes(), &value, b"EX", ttl.as_bytes()])
+      .await
+    {
+      log!("Error caching a flag in Redis. Error: {}", e);
+    }
+  }
+
+  /// Deletes the cached copies of the flags of the given `(product_id, flag_name)` keys
+  pub async fn invalidate_flags(&self, flags: &[(String, String)]) {
+    if flags.is_empty() {
+      return;
+    }
+
+    let keys: Vec<String> = flags
+      .iter()
+      .flat_map(|(product_id, flag_name)| {
+        [
+          FlagProjection::Full,
+          FlagProjection::Evaluation,
+          FlagProjection::Summary,
+        ]
+        .into_iter()
+        .map(move |projection| flag_key(product_id, flag_name, projection))
+      })
+      .collect();
+    let mut args: Vec<&[u8]> = vec![b"DEL"];
+    args.extend(keys.iter().map(|x| x.as_bytes()));
+
+    if let Err(e) = self.command(&args).await {
+      log!(
+        "Error deleting cached flags from Redis, they may be served stale for up to {} seconds. Error: {}",
+        self.ttl,
+        e
+      );
+    }
+  }
+
+  /// Runs a command on an idle connection, or a new one if none are idle
+  async fn command(&self, args: &[&[u8]]) -> Result<Reply, String> {
+    let idle = self.idle.lock().await.pop();
+    let mut connection = match idle {
+      Some(connection) => connection,
+      None => self.connect().await?,
+    };
+
+    // Connections that fail are dropped, their state is unknown
+    let reply = run(&mut connection, args).await?;
+
+    let mut idle = self.idle.lock().await;
+    if idle.len() < MAX_IDLE_CONNECTIONS {
+      idle.push(connection);
+    }
+
+    Ok(reply)
+  }
+
+  /// Opens a connection, authenticated and on the database of `REDIS_URL`
+  async fn connect(&self) -> Result<Connection, String> {
+    let stream = match tokio::time::timeout(TIMEOUT, self.open()).await {
+      Ok(stream) => stream?,
+      Err(_) => return Err("timed out connecting".to_string()),
+    };
+    let mut connection = BufStream::new(stream);
+
+    if let Some(password) = &self.password {
+      match &self.username {
+        Some(username) => run(&mut connection, &[b"AUTH", username.as_bytes(), password.as_bytes()]).await?,
+        None => run(&mut connection, &[b"AUTH", password.as_bytes()]).await?,
+      };
+    }
+    if self.database != 0 {
+      run(&mut connection, &[b"SELECT", self.database.to_string().as_bytes()]).await?;
+    }
+
+    Ok(connection)
+  }
+
+  /// Opens a TCP connection to the server, over TLS for `rediss://` URLs
+  async fn open(&self) -> Result<Box<dyn Stream>, String> {
+    let stream = TcpStream::connect(&self.address)
+      .await
+      .map_err(|e| format!("{:?}", e))?;
+
+    match &self.tls {
+      Some(connector) => {
+        let server_name = ServerName::try_from(self.host.as_str()).map_err(|e| format!("{:?}", e))?;
+        let stream = connector
+          .connect(server_name, stream)
+          .await
+          .map_err(|e| format!("{:?}", e))?;
+        Ok(Box::new(stream))
+      }
+      None => Ok(Box::new(stream)),
+    }
+  }
+}
+
+/// TLS connector trusting the Mozilla root certificates
+fn tls_connector() -> TlsConnector {
+  let mut roots = RootCertStore::empty();
+  roots.add_server_trust_anchors(
+    webpki_roots::TLS_SERVER_ROOTS
+      .0
+      .iter()
+      .map(|x| OwnedTrustAnchor::from_subject_spki_name_constraints(x.subject, x.spki, x.name_constraints)),
+  );
+  let config = ClientConfig::builder()
+    .with_safe_defaults()
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+
+  TlsConnector::from(Arc::new(config))
+}
+
+/// Key a flag is cached under. The name goes last, since it can contain anything
+fn flag_key(product_id: &str, flag_name: &str, projection: FlagProjection) -> String {
+  let projection = match projection {
+    FlagProjection::Full => "full",
+    FlagProjection::Evaluation => "evaluation",
+    FlagProjection::Summary => "summary",
+  };
+
+  format!("flag:{}:{}:{}", product_id, projection, flag_name)
+}
+
+/// Sends a command and reads its reply, failing if it takes longer than `TIMEOUT`
+async fn run(connection: &mut Connection, args: &[&[u8]]) -> Result<Reply, String> {
+  match tokio::time::timeout(TIMEOUT, exchange(connection, args)).await {
+    Ok(reply) => reply,
+    Err(_) => Err("timed out running a command".to_string()),
+  }
+}
+
+/// Sends a command as an array of bulk strings and reads its reply
+async fn exchange(connection: &mut Connection, args: &[&[u8]]) -> Result<Reply, String> {
+  let mut request = format!("*{}\r\n", args.len()).into_bytes();
+  for arg in args {
+    request.extend(format!("${}\r\n", arg.len()).into_bytes());
+    request.extend_from_slice(arg);
+    request.extend_from_slice(b"\r\n");
+  }
+  connection.write_all(&request).await.map_err(|e| format!("{:?}", e))?;
+  connection.flush().await.map_err(|e| format!("{:?}", e))?;
+
+  let mut line = vec![];
+  connection
+    .read_until(b'\n', &mut line)
+    .await
+    .map_err(|e| format!("{:?}", e))?;
+  let line = String::from_utf8_lossy(&line).trim_end().to_string();
+  if line.is_empty() {
+    return Err("connection closed".to_string());
+  }
+  let (kind, value) = line.split_at(1);
+
+  match kind {
+    "+" => Ok(Reply::Status),
+    "-" => Err(value.to_string()),
+    ":" => Ok(Reply::Integer),
+    "$" => {
+      let length: i64 = value.parse().map_err(|_| format!("invalid bulk length '{}'", value))?;
+      if length < 0 {
+        return Ok(Reply::Bulk(None));
+      }
+
+      // The value is followed by `\r\n`
+      let mut bulk = vec![0; length as usize + 2];
+      connection.read_exact(&mut bulk).await.map_err(|e| format!("{:?}", e))?;
+      bulk.truncate(length as usize);
+      Ok(Reply::Bulk(Some(bulk)))
+    }
+    _ => Err(format!("unexpected reply '{}'", line)),
+  }
+}
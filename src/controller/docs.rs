@@ -0,0 +1,105 @@
+//! Access control of the API docs, the Swagger UI and the OpenAPI spec describe the full admin surface
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::Method;
+use rocket::outcome::Outcome;
+use rocket::{Data, Request};
+
+use crate::controller::authentication::UserAuth;
+use crate::controller::database::ConnectionManager;
+use crate::log;
+use crate::model::user::AccountType;
+
+/// Path requests for the docs are rewritten to when they aren't logged in
+pub const DOCS_UNAUTHORIZED_PATH: &str = "/__docs_unauthorized";
+/// Path requests for the docs are rewritten to when they aren't logged in as a developer
+pub const DOCS_FORBIDDEN_PATH: &str = "/__docs_forbidden";
+
+/// Who can view the API docs, read from `API_DOCS`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DocsAccess {
+  /// Anyone, the default
+  Public,
+  /// Only logged in developers (`developer`)
+  Developers,
+  /// No one, the docs aren't served at all (`off`)
+  Disabled,
+}
+
+impl DocsAccess {
+  /// Reads who can view the API docs from `API_DOCS`, `public` by default
+  pub fn from_env() -> DocsAccess {
+    match dotenv::var("API_DOCS") {
+      Ok(value) => match value.trim().to_lowercase().as_str() {
+        "public" => DocsAccess::Public,
+        "developer" | "developers" => DocsAccess::Developers,
+        "off" => DocsAccess::Disabled,
+        _ => {
+          log!("Ignoring unknown 'API_DOCS' value '{}', the docs are public", value);
+          DocsAccess::Public
+        }
+      },
+      Err(_) => DocsAccess::Public,
+    }
+  }
+
+  /// If the docs are served at all
+  pub fn is_enabled(&self) -> bool {
+    *self != DocsAccess::Disabled
+  }
+}
+
+/// Fairing restricting the API docs to logged in developers when `access` is `DocsAccess::Developers`
+///
+/// Requests for the docs without login cookies are answered 401, and requests of users who aren't developers 403
+pub struct DocsGuard {
+  pub access: DocsAccess,
+}
+
+/// Returns true if the request is for the Swagger UI or an OpenAPI spec
+fn is_docs_request(request: &Request<'_>) -> bool {
+  let path = request.uri().path();
+  let path = path.as_str();
+  path.starts_with("/swagger-ui") || path.ends_with("/openapi.json")
+}
+
+#[rocket::async_trait]
+impl Fairing for DocsGuard {
+  fn info(&self) -> Info {
+    Info {
+      name: "Docs Guard",
+      kind: Kind::Request,
+    }
+  }
+
+  async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+    if self.access != DocsAccess::Developers || !is_docs_request(request) {
+      return;
+    }
+
+    let denied = match request.guard::<UserAuth>().await {
+      Outcome::Success(token_auth) => {
+        let is_developer = match request.rocket().state::<ConnectionManager>() {
+          Some(database_connection) => matches!(
+            database_connection.get_user(None, Some(&token_auth.user_id)).await,
+            Ok(Some(user)) if matches!(user.account_type, AccountType::Developer)
+          ),
+          None => false,
+        };
+
+        match is_developer {
+          true => None,
+          false => Some(DOCS_FORBIDDEN_PATH),
+        }
+      }
+      _ => Some(DOCS_UNAUTHORIZED_PATH),
+    };
+
+    // Fairings can't respond directly, so denied requests are routed to the denied routes instead
+    if let Some(path) = denied {
+      request.set_method(Method::Get);
+      request.set_uri(Origin::parse(path).expect("valid docs denied path"));
+    }
+  }
+}
@@ -0,0 +1,257 @@
+//! Binary encodings of the responses SDKs poll most, `/check` and `/sdk/snapshot`
+//!
+//! Clients pick the encoding with the `Accept` header: `application/msgpack` for MessagePack, `application/protobuf`
+//! for Protobuf (messages described by `proto/flags.proto`), JSON otherwise. MessagePack bodies have the same shape as
+//! the JSON ones, field naming policy included. Protobuf bodies carry payloads, release types and rules as nested JSON
+//! documents, since they're free form
+
+use std::collections::HashMap;
+
+use rocket::http::{ContentType, MediaType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::{serde_json, Json, Value};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::response::OpenApiResponderInner;
+use serde::Serialize;
+
+use crate::controller::case::{self, CasedJson, JsonCase};
+use crate::controller::response::{FlagCheck, SdkSnapshot, SnapshotFlag};
+use crate::log;
+
+/// Encoding of a response body
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+  Json,
+  MessagePack,
+  Protobuf,
+}
+
+impl Encoding {
+  /// Returns the encoding of a media type, `None` if it isn't supported
+  pub fn from_media_type(media_type: &MediaType) -> Option<Encoding> {
+    match (media_type.top().as_str(), media_type.sub().as_str()) {
+      ("application", "json") => Some(Encoding::Json),
+      ("application", "msgpack" | "x-msgpack" | "vnd.msgpack") => Some(Encoding::MessagePack),
+      ("application", "protobuf" | "x-protobuf" | "vnd.google.protobuf") => Some(Encoding::Protobuf),
+      _ => None,
+    }
+  }
+
+  /// Returns the supported encoding the `Accept` header of the request prefers, JSON if it names none
+  pub fn from_request(request: &Request<'_>) -> Encoding {
+    let accept = match request.accept() {
+      Some(accept) => accept,
+      None => return Encoding::Json,
+    };
+
+    let mut media_types: Vec<_> = accept.iter().collect();
+    media_types.sort_by(|a, b| b.weight_or(1.0).total_cmp(&a.weight_or(1.0)));
+
+    media_types
+      .into_iter()
+      .filter(|x| x.weight_or(1.0) > 0.0)
+      .find_map(|x| Encoding::from_media_type(x.media_type()))
+      .unwrap_or(Encoding::Json)
+  }
+
+  /// Content type bodies of the encoding are served with
+  pub fn content_type(&self) -> ContentType {
+    match self {
+      Encoding::Json => ContentType::JSON,
+      Encoding::MessagePack => ContentType::new("application", "msgpack"),
+      Encoding::Protobuf => ContentType::new("application", "protobuf"),
+    }
+  }
+}
+
+/// Response body that can be encoded as a Protobuf message of `proto/flags.proto`
+pub trait ToProtobuf {
+  type Message: prost::Message;
+
+  /// Converts the body to its Protobuf message
+  fn to_protobuf(&self) -> Self::Message;
+}
+
+/// Responder encoding the body as JSON, MessagePack or Protobuf, following the `Accept` header of the request
+///
+/// Responses vary on `Accept`, since it changes the body
+pub struct Negotiated<T>(pub T);
+
+impl<'r, T: Serialize + ToProtobuf> Responder<'r, 'static> for Negotiated<T> {
+  fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+    let encoding = Encoding::from_request(request);
+    let body = match encoding {
+      Encoding::Json => {
+        let mut response = CasedJson(self.0).respond_to(request)?;
+        response.adjoin_raw_header("Vary", "Accept");
+        return Ok(response);
+      }
+      Encoding::MessagePack => {
+        let mut body = Vec::new();
+        let mut serializer = rmp_serde::Serializer::new(&mut body).with_struct_map();
+        let cased = case::cased(&self.0, JsonCase::from_request(request));
+        if let Err(e) = cased.serialize(&mut serializer) {
+          log!("Error serializing response to MessagePack. Error: {:?}", e);
+          return Err(Status::InternalServerError);
+        }
+        body
+      }
+      Encoding::Protobuf => prost::Message::encode_to_vec(&self.0.to_protobuf()),
+    };
+
+    let mut response = (encoding.content_type(), body).respond_to(request)?;
+    response.adjoin_raw_header("Vary", "Accept");
+    Ok(response)
+  }
+}
+
+impl<T: Serialize + JsonSchema + Send> OpenApiResponderInner for Negotiated<T> {
+  fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+    Json::<T>::responses(gen)
+  }
+}
+
+/// Protobuf messages of `proto/flags.proto`
+pub mod proto {
+  use std::collections::HashMap;
+
+  /// Result of a flag check
+  #[derive(Clone, PartialEq, prost::Message)]
+  pub struct FlagCheck {
+    #[prost(bool, tag = "1")]
+    pub enabled: bool,
+    #[prost(string, optional, tag = "2")]
+    pub reason: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub variant: Option<String>,
+    #[prost(uint64, optional, tag = "4")]
+    pub rule_index: Option<u64>,
+    /// JSON document
+    #[prost(string, optional, tag = "5")]
+    pub payload: Option<String>,
+  }
+
+  /// Results of checking every flag of a product, by flag name
+  #[derive(Clone, PartialEq, prost::Message)]
+  pub struct FlagChecks {
+    #[prost(map = "string, message", tag = "1")]
+    pub checks: HashMap<String, FlagCheck>,
+  }
+
+  /// Evaluation ready definition of a flag
+  #[derive(Clone, PartialEq, prost::Message)]
+  pub struct SnapshotFlag {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(bool, tag = "2")]
+    pub enabled: bool,
+    #[prost(bool, tag = "3")]
+    pub archived: bool,
+    #[prost(string, repeated, tag = "4")]
+    pub disabled_for: Vec<String>,
+    #[prost(string, repeated, tag = "5")]
+    pub always_include: Vec<String>,
+    #[prost(string, repeated, tag = "6")]
+    pub always_exclude: Vec<String>,
+    /// JSON document
+    #[prost(string, tag = "7")]
+    pub release_type: String,
+    /// JSON document
+    #[prost(string, tag = "8")]
+    pub rules: String,
+    /// JSON document
+    #[prost(string, optional, tag = "9")]
+    pub payload: Option<String>,
+    /// RFC 3339 timestamp
+    #[prost(string, optional, tag = "10")]
+    pub updated_at: Option<String>,
+    #[prost(string, optional, tag = "11")]
+    pub bucket_salt: Option<String>,
+  }
+
+  /// Snapshot of a product's flags for SDKs evaluating flags locally
+  #[derive(Clone, PartialEq, prost::Message)]
+  pub struct SdkSnapshot {
+    #[prost(string, tag = "1")]
+    pub product_id: String,
+    /// RFC 3339 timestamp
+    #[prost(string, tag = "2")]
+    pub generated_at: String,
+    #[prost(bool, tag = "3")]
+    pub plugin: bool,
+    #[prost(message, repeated, tag = "4")]
+    pub flags: Vec<SnapshotFlag>,
+    #[prost(string, repeated, tag = "5")]
+    pub flag_names: Vec<String>,
+  }
+}
+
+/// Encodes a free form value as a JSON document for a Protobuf string field
+fn to_json_document<T: Serialize + ?Sized>(value: &T) -> String {
+  serde_json::to_string(value).unwrap_or_else(|_| Value::Null.to_string())
+}
+
+impl ToProtobuf for FlagCheck {
+  type Message = proto::FlagCheck;
+
+  fn to_protobuf(&self) -> proto::FlagCheck {
+    proto::FlagCheck {
+      enabled: self.enabled,
+      reason: self.reason.clone(),
+      variant: self.variant.clone(),
+      rule_index: self.rule_index.map(|x| x as u64),
+      payload: self.payload.as_ref().map(to_json_document),
+    }
+  }
+}
+
+impl ToProtobuf for HashMap<String, FlagCheck> {
+  type Message = proto::FlagChecks;
+
+  fn to_protobuf(&self) -> proto::FlagChecks {
+    proto::FlagChecks {
+      checks: self
+        .iter()
+        .map(|(name, check)| (name.clone(), check.to_protobuf()))
+        .collect(),
+    }
+  }
+}
+
+impl ToProtobuf for SnapshotFlag {
+  type Message = proto::SnapshotFlag;
+
+  fn to_protobuf(&self) -> proto::SnapshotFlag {
+    let definition = &self.definition;
+    proto::SnapshotFlag {
+      name: definition.name.clone(),
+      enabled: definition.enabled,
+      archived: definition.archived,
+      disabled_for: definition.disabled_for.clone(),
+      always_include: definition.always_include.clone(),
+      always_exclude: definition.always_exclude.clone(),
+      release_type: to_json_document(&definition.release_type),
+      rules: to_json_document(&definition.rules),
+      payload: self.payload.as_ref().map(to_json_document),
+      updated_at: self.updated_at.map(|x| x.to_rfc3339()),
+      bucket_salt: definition.bucket_salt.clone(),
+    }
+  }
+}
+
+impl ToProtobuf for SdkSnapshot {
+  type Message = proto::SdkSnapshot;
+
+  fn to_protobuf(&self) -> proto::SdkSnapshot {
+    proto::SdkSnapshot {
+      product_id: self.product_id.clone(),
+      generated_at: self.generated_at.to_rfc3339(),
+      plugin: self.plugin,
+      flags: self.flags.iter().map(ToProtobuf::to_protobuf).collect(),
+      flag_names: self.flag_names.clone(),
+    }
+  }
+}
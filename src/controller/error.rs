@@ -0,0 +1,200 @@
+//! Structured JSON error responses
+//!
+//! Every error the service responds with, from routes and from Rocket's catchers alike, has the same body:
+//!
+//! ```json
+//! {"code": "not_found", "message": "Error. Product not found.", "details": null, "request_id": "..."}
+//! ```
+
+use rocket::http::Status;
+use rocket::request::{Outcome, Request};
+use rocket::response::{self, status, Responder};
+use rocket::serde::json::{json, Value};
+use rocket::serde::Serialize;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{MediaType, RefOr, Response, Responses};
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use rocket_okapi::response::OpenApiResponderInner;
+
+use crate::controller::case::CasedJson;
+use crate::controller::database::error::StorageError;
+use crate::controller::request_id::RequestId;
+use crate::controller::validation::{self, Invalid};
+
+/// Body of error responses
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ErrorBody {
+  /// Machine readable kind of the error, the snake_case reason of its status (e.g. `not_found`)
+  pub code: String,
+  /// Human readable description of the error
+  pub message: String,
+  /// More about the error, if there is more (e.g. the path that wasn't found)
+  pub details: Option<Value>,
+  /// ID of the request, also returned in the `X-Request-Id` header, to find its log lines
+  pub request_id: String,
+}
+
+/// Error response of a route
+#[derive(Clone, Debug)]
+pub struct ApiError {
+  /// Status of the response
+  pub status: Status,
+  /// Human readable description of the error
+  pub message: String,
+  /// More about the error, if there is more
+  pub details: Option<Value>,
+}
+
+impl ApiError {
+  /// Constructs an error responding with `status`
+  pub fn new(status: Status, message: impl Into<String>) -> ApiError {
+    ApiError {
+      status,
+      message: message.into(),
+      details: None,
+    }
+  }
+
+  /// Constructs a 400 Bad Request error
+  pub fn bad_request(message: impl Into<String>) -> ApiError {
+    ApiError::new(Status::BadRequest, message)
+  }
+
+  /// Constructs a 404 Not Found error
+  pub fn not_found(message: impl Into<String>) -> ApiError {
+    ApiError::new(Status::NotFound, message)
+  }
+
+  /// Adds more about the error to its body
+  pub fn with_details(mut self, details: Value) -> ApiError {
+    self.details = Some(details);
+    self
+  }
+
+  /// Machine readable kind of the error, the snake_case reason of its status (e.g. `unprocessable_entity`)
+  pub fn code(&self) -> String {
+    self
+      .status
+      .reason()
+      .unwrap_or("error")
+      .to_lowercase()
+      .replace(|x: char| !x.is_ascii_alphanumeric(), "_")
+  }
+}
+
+impl From<StorageError> for ApiError {
+  /// Responds to a failed storage operation with the status of its kind, its details naming the kind (e.g.
+  /// `{"storage_error": "connection"}`)
+  fn from(e: StorageError) -> ApiError {
+    let error = match &e {
+      StorageError::NotFound => ApiError::not_found("Error. Not found."),
+      StorageError::Conflict(_) => ApiError::new(Status::Conflict, "Error. It conflicts with what's already stored."),
+      StorageError::Connection(_) => ApiError::new(Status::ServiceUnavailable, "Error. The database is unavailable."),
+      StorageError::Serialization(_) => ApiError::new(
+        Status::InternalServerError,
+        "Error. Stored data couldn't be read or written.",
+      ),
+    };
+
+    error.with_details(json!({ "storage_error": e.kind() }))
+  }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+  fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+    let body = ErrorBody {
+      code: self.code(),
+      message: self.message,
+      details: self.details,
+      request_id: RequestId::of(request).0.clone(),
+    };
+
+    status::Custom(self.status, CasedJson(body)).respond_to(request)
+  }
+}
+
+impl OpenApiResponderInner for ApiError {
+  fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+    Ok(error_responses(
+      gen,
+      &[("4XX", "The request was invalid"), ("5XX", "The service failed")],
+    ))
+  }
+}
+
+/// Documents error responses with the `ErrorBody` schema, for each status (e.g. `401` or `4XX`) and its description
+pub fn error_responses(gen: &mut OpenApiGenerator, statuses: &[(&str, &str)]) -> Responses {
+  let media = MediaType {
+    schema: Some(gen.json_schema::<ErrorBody>()),
+    ..Default::default()
+  };
+
+  let mut responses = Responses::default();
+  for (status, description) in statuses {
+    let mut response = Response {
+      description: description.to_string(),
+      ..Default::default()
+    };
+    response.content.insert("application/json".to_string(), media.clone());
+    responses.responses.insert(status.to_string(), RefOr::Object(response));
+  }
+
+  responses
+}
+
+/// Error of a failed request guard, stored in the request local cache for the catchers to respond with
+pub struct GuardError(pub Option<ApiError>);
+
+/// Fails a request guard with `error`, which the catchers respond with
+pub fn fail_guard<S, E>(request: &Request<'_>, error: ApiError, guard_error: E) -> Outcome<S, E> {
+  let status = error.status;
+  request.local_cache(|| GuardError(Some(error)));
+  Outcome::Failure((status, guard_error))
+}
+
+/// Returns the error a request guard failed with, if one was stored with `fail_guard`
+fn guard_error(request: &Request<'_>) -> Option<ApiError> {
+  request.local_cache(|| GuardError(None)).0.clone()
+}
+
+/// Responds to requests that no route matched
+#[catch(404)]
+pub fn not_found(request: &Request<'_>) -> ApiError {
+  if let Some(error) = guard_error(request) {
+    return error;
+  }
+
+  ApiError::not_found("Error. Not found.").with_details(json!({ "path": request.uri().path().as_str() }))
+}
+
+/// Responds to requests whose body couldn't be parsed, or was parsed but failed validation
+#[catch(422)]
+pub fn unprocessable_entity(request: &Request<'_>) -> ApiError {
+  let violations = &request.local_cache(|| Invalid(vec![])).0;
+  if !violations.is_empty() {
+    return validation::invalid(violations.clone());
+  }
+
+  ApiError::new(
+    Status::UnprocessableEntity,
+    "Error. The request body couldn't be parsed.",
+  )
+  .with_details(json!({ "path": request.uri().path().as_str() }))
+}
+
+/// Responds to requests that failed unexpectedly
+#[catch(500)]
+pub fn internal_server_error(_request: &Request<'_>) -> ApiError {
+  ApiError::new(Status::InternalServerError, "Error. Something went wrong.")
+}
+
+/// Responds to every other error status (e.g. failed request guards)
+#[catch(default)]
+pub fn default(status: Status, request: &Request<'_>) -> ApiError {
+  if let Some(error) = guard_error(request) {
+    return error;
+  }
+
+  let message = format!("Error. {}.", status.reason().unwrap_or("Unknown error"));
+  ApiError::new(status, message)
+}
@@ -0,0 +1,88 @@
+//! Counts of flag evaluations, written to the database in hourly buckets per product for `/stats`
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, DurationRound, Utc};
+
+use crate::controller::database::ConnectionManager;
+
+/// How often counted evaluations are written to the database
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long hourly counts are kept in the database, expired by a TTL index
+pub const RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Evaluations counted since the last flush, by the start of the hour they were made in and product ID
+type Pending = HashMap<DateTime<Utc>, HashMap<String, u64>>;
+
+/// Counter of flag evaluations, shared by its clones
+///
+/// Evaluations are counted in memory and written to the database every `FLUSH_INTERVAL` by `run`, so checks never wait
+/// on the database to be counted
+#[derive(Clone, Default)]
+pub struct EvaluationCounter {
+  pending: Arc<Mutex<Pending>>,
+}
+
+impl EvaluationCounter {
+  /// Creates and returns a new `EvaluationCounter` with nothing counted
+  pub fn new() -> EvaluationCounter {
+    EvaluationCounter::default()
+  }
+
+  /// Counts `count` evaluations of flags of the product of ID `product_id`, made now
+  pub fn record(&self, product_id: &str, count: u64) {
+    if count == 0 {
+      return;
+    }
+
+    let mut pending = self.lock();
+    *pending
+      .entry(current_hour())
+      .or_default()
+      .entry(product_id.to_string())
+      .or_default() += count;
+  }
+
+  /// Writes the evaluations counted since the last flush to the database
+  ///
+  /// Counts that couldn't be written are kept for the next flush
+  pub async fn flush(&self, database_connection: &ConnectionManager) {
+    let pending = std::mem::take(&mut *self.lock());
+
+    for (hour, counts) in pending {
+      if database_connection.add_evaluation_counts(hour, &counts).await.is_err() {
+        let mut pending = self.lock();
+        let retry = pending.entry(hour).or_default();
+        for (product_id, count) in counts {
+          *retry.entry(product_id).or_default() += count;
+        }
+      }
+    }
+  }
+
+  /// Flushes the counted evaluations every `FLUSH_INTERVAL`, forever
+  pub async fn run(self, database_connection: ConnectionManager) {
+    let mut ticker = rocket::tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+      ticker.tick().await;
+      self.flush(&database_connection).await;
+    }
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, Pending> {
+    match self.pending.lock() {
+      Ok(pending) => pending,
+      Err(poisoned) => poisoned.into_inner(), // recover from poisoned mutex
+    }
+  }
+}
+
+/// Start of the current hour
+fn current_hour() -> DateTime<Utc> {
+  let now = Utc::now();
+  now.duration_trunc(chrono::Duration::hours(1)).unwrap_or(now)
+}
@@ -0,0 +1,188 @@
+//! Broadcast of changes to flags and products, feeding live streams of flag state and webhooks, and how changed
+//! entities are shown in event payloads
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use evaluation::ReleaseType;
+use hmac::{Hmac, Mac};
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::controller::secrets;
+use crate::model::flag::SpecSafeFeatureFlag;
+use crate::model::product::SpecSafeProduct;
+
+/// Changes kept for subscribers that fall behind, slower subscribers miss changes and have to catch up
+pub const EVENT_CAPACITY: usize = 1024;
+
+/// Change to a flag or product, published by the `ConnectionManager` once it's written to the database
+#[derive(Clone, Debug)]
+pub enum ChangeEvent {
+  /// The flag was created or changed, contains its new state
+  FlagUpdated(Arc<SpecSafeFeatureFlag>),
+  /// The flag was deleted
+  FlagDeleted {
+    /// Unique ID of the product the flag belonged to
+    product_id: String,
+    /// Unique ID of the deleted flag
+    flag_id: String,
+  },
+  /// The product was created or changed, contains its new state
+  ProductUpdated(Arc<SpecSafeProduct>),
+  /// The product was deleted, along with its flags
+  ProductDeleted {
+    /// Unique ID of the deleted product
+    product_id: String,
+  },
+}
+
+impl ChangeEvent {
+  /// Unique ID of the changed product, or of the product of the changed flag
+  pub fn product_id(&self) -> &str {
+    match self {
+      ChangeEvent::FlagUpdated(flag) => &flag.product_id,
+      ChangeEvent::FlagDeleted { product_id, .. } => product_id,
+      ChangeEvent::ProductUpdated(product) => &product.oid,
+      ChangeEvent::ProductDeleted { product_id } => product_id,
+    }
+  }
+
+  /// Kind of the change
+  pub fn kind(&self) -> EventKind {
+    match self {
+      ChangeEvent::FlagUpdated(_) => EventKind::FlagUpdated,
+      ChangeEvent::FlagDeleted { .. } => EventKind::FlagDeleted,
+      ChangeEvent::ProductUpdated(_) => EventKind::ProductUpdated,
+      ChangeEvent::ProductDeleted { .. } => EventKind::ProductDeleted,
+    }
+  }
+}
+
+/// Kind of a `ChangeEvent`, without its data
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum EventKind {
+  #[serde(rename = "flag.updated")]
+  FlagUpdated,
+  #[serde(rename = "flag.deleted")]
+  FlagDeleted,
+  #[serde(rename = "product.updated")]
+  ProductUpdated,
+  #[serde(rename = "product.deleted")]
+  ProductDeleted,
+}
+
+impl EventKind {
+  /// Serialized name of the kind of change (e.g. `flag.updated`)
+  pub fn name(&self) -> &'static str {
+    match self {
+      EventKind::FlagUpdated => "flag.updated",
+      EventKind::FlagDeleted => "flag.deleted",
+      EventKind::ProductUpdated => "product.updated",
+      EventKind::ProductDeleted => "product.deleted",
+    }
+  }
+}
+
+/// How user identifiers (user IDs and emails) appear in event payloads
+///
+/// Payloads sent to third-party tools that must not receive personal data should use `Hash`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UserIdentifiers {
+  /// Identifiers are sent as they are stored
+  #[default]
+  Include,
+  /// Identifiers are replaced with opaque hashes, the same identifier always gets the same hash
+  Hash,
+}
+
+impl UserIdentifiers {
+  /// Parses a setting from its name (`include` or `hash`)
+  pub fn from_name(name: &str) -> Option<UserIdentifiers> {
+    match name.trim().to_lowercase().as_str() {
+      "include" => Some(UserIdentifiers::Include),
+      "hash" => Some(UserIdentifiers::Hash),
+      _ => None,
+    }
+  }
+
+  /// If the setting can be used, hashing needs `ANONYMIZATION_SECRET` to be set
+  pub fn is_available(&self) -> bool {
+    *self == UserIdentifiers::Include || anonymization_secret().is_some()
+  }
+
+  /// Returns `flag` as it appears in payloads with this setting
+  ///
+  /// Hashes the users the flag targets (overrides and allowlists), its maintainer, and email notification targets
+  pub fn apply<'a>(&self, flag: &'a SpecSafeFeatureFlag) -> Cow<'a, SpecSafeFeatureFlag> {
+    if *self == UserIdentifiers::Include {
+      return Cow::Borrowed(flag);
+    }
+
+    let hash_all = |users: &mut Vec<String>| users.iter_mut().for_each(|x| *x = hash_identifier(x));
+
+    let mut flag = flag.clone();
+    hash_all(&mut flag.always_include);
+    hash_all(&mut flag.always_exclude);
+    match &mut flag.release_type {
+      ReleaseType::Limited(users) | ReleaseType::Percentage(_, users, _) => hash_all(users),
+      ReleaseType::Global => (),
+    }
+    if let Some(maintainer_id) = &mut flag.maintainer_id {
+      *maintainer_id = hash_identifier(maintainer_id);
+    }
+    if let Some(targets) = &mut flag.settings.notification_targets {
+      targets
+        .iter_mut()
+        .filter(|x| x.contains('@') && !x.contains("://"))
+        .for_each(|x| *x = hash_identifier(x));
+    }
+
+    Cow::Owned(flag)
+  }
+
+  /// Returns `product` as it appears in payloads with this setting
+  ///
+  /// Hashes the product's users
+  pub fn apply_to_product<'a>(&self, product: &'a SpecSafeProduct) -> Cow<'a, SpecSafeProduct> {
+    if *self == UserIdentifiers::Include {
+      return Cow::Borrowed(product);
+    }
+
+    let mut product = product.clone();
+    product.users.iter_mut().for_each(|x| *x = hash_identifier(x));
+
+    Cow::Owned(product)
+  }
+}
+
+/// Replaces a user identifier with an opaque hash, keyed by `ANONYMIZATION_SECRET` so hashes of known identifiers
+/// (e.g. emails) can't be computed by whoever receives them
+///
+/// Without the secret every identifier becomes `anon_`, since hashes keyed with an empty secret could be computed by
+/// anyone
+pub fn hash_identifier(identifier: &str) -> String {
+  let secret = match anonymization_secret() {
+    Some(secret) => secret,
+    None => return "anon_".to_string(),
+  };
+  let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+    Ok(mac) => mac,
+    Err(_) => return "anon_".to_string(),
+  };
+  mac.update(identifier.as_bytes());
+
+  let hash: String = mac.finalize().into_bytes()[..8]
+    .iter()
+    .map(|x| format!("{:02x}", x))
+    .collect();
+
+  format!("anon_{}", hash)
+}
+
+/// Returns `ANONYMIZATION_SECRET`, `None` if it isn't set or is blank
+fn anonymization_secret() -> Option<String> {
+  secrets::var("ANONYMIZATION_SECRET").filter(|x| !x.trim().is_empty())
+}
@@ -0,0 +1,269 @@
+//! Portable documents of a product's flags and settings, for backups, code review, and moving flags between
+//! environments, and importing them back
+
+use std::collections::{BTreeMap, HashSet};
+
+use rocket::http::ContentType;
+use rocket::serde::json::{serde_json, Value};
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+
+use crate::controller::request::FlagUpdateRequest;
+use crate::controller::validation::Validate;
+use crate::model::flag::FeatureFlag;
+use crate::model::freeze::FreezeWindow;
+use crate::model::product::Product;
+use crate::model::settings::SettingsOverrides;
+
+/// Version of the export format, bumped on changes older services couldn't read
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Largest document that can be imported, in bytes
+pub const MAX_IMPORT_SIZE: usize = 5 * 1024 * 1024;
+
+/// Document format of an export
+#[derive(Clone, Copy, Debug)]
+pub enum ExportFormat {
+  Json,
+  Yaml,
+}
+
+impl ExportFormat {
+  /// Parses a format from its name (`json`, `yaml` or `yml`), ignoring case
+  pub fn from_name(name: &str) -> Option<ExportFormat> {
+    match name.trim().to_lowercase().as_str() {
+      "json" => Some(ExportFormat::Json),
+      "yaml" | "yml" => Some(ExportFormat::Yaml),
+      _ => None,
+    }
+  }
+
+  /// Content type documents of the format are served with
+  pub fn content_type(&self) -> ContentType {
+    match self {
+      ExportFormat::Json => ContentType::JSON,
+      ExportFormat::Yaml => ContentType::new("application", "yaml"),
+    }
+  }
+
+  /// Serializes an export, returning a description of the problem on error
+  ///
+  /// YAML documents are written from the JSON document so both have the same shape, rather than YAML tags for enums
+  pub fn render(&self, export: &ProductExport) -> Result<String, String> {
+    match self {
+      ExportFormat::Json => serde_json::to_string_pretty(export).map_err(|e| e.to_string()),
+      ExportFormat::Yaml => {
+        let document = serde_json::to_value(export).map_err(|e| e.to_string())?;
+        serde_yaml::to_string(&document).map_err(|e| e.to_string())
+      }
+    }
+  }
+
+  /// Parses an export, returning a description of the problem on error
+  ///
+  /// YAML documents are read as JSON documents, the same way they're written
+  pub fn parse(&self, document: &str) -> Result<ProductExport, String> {
+    let document = match self {
+      ExportFormat::Json => serde_json::from_str::<Value>(document).map_err(|e| e.to_string())?,
+      ExportFormat::Yaml => serde_yaml::from_str::<Value>(document).map_err(|e| e.to_string())?,
+    };
+
+    serde_json::from_value(document).map_err(|e| e.to_string())
+  }
+}
+
+/// Portable document of a product's flags and settings
+///
+/// IDs are left out and flags are ordered by name, so exports of the same product in different environments can be
+/// diffed
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ProductExport {
+  /// Version of the export format
+  pub version: u32,
+  /// Settings of the product
+  pub product: ExportedProduct,
+  /// Every flag of the product, archived flags included, ordered by name
+  pub flags: Vec<ExportedFlag>,
+}
+
+impl ProductExport {
+  /// Exports `product` and its `flags`
+  pub fn new(product: &Product, flags: &[FeatureFlag]) -> ProductExport {
+    let mut flags: Vec<ExportedFlag> = flags
+      .iter()
+      .map(|x| ExportedFlag {
+        definition: FlagUpdateRequest::from_flag(x),
+        archived: x.archived,
+      })
+      .collect();
+    flags.sort_by(|a, b| a.definition.name.cmp(&b.definition.name));
+
+    ProductExport {
+      version: EXPORT_VERSION,
+      product: ExportedProduct {
+        name: product.name.clone(),
+        strict_mode: product.strict_mode,
+        settings: product.settings.clone(),
+        environments: product
+          .environments
+          .iter()
+          .map(|(name, settings)| (name.clone(), settings.clone()))
+          .collect(),
+        freeze_windows: product.freeze_windows.clone(),
+      },
+      flags,
+    }
+  }
+}
+
+/// Settings of an exported product, its users and plugin are specific to where it's deployed and left out
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ExportedProduct {
+  /// Product Name
+  pub name: String,
+  /// If evaluations with users unknown to the product should be rejected
+  #[serde(default, alias = "strictMode")]
+  pub strict_mode: bool,
+  /// Settings overriding the organization defaults for the product's flags
+  #[serde(default)]
+  pub settings: SettingsOverrides,
+  /// Settings overriding the product settings, by environment name
+  #[serde(default)]
+  pub environments: BTreeMap<String, SettingsOverrides>,
+  /// Weekly windows during which the product's flags can't be changed
+  #[serde(default, alias = "freezeWindows")]
+  pub freeze_windows: Vec<FreezeWindow>,
+}
+
+/// Definition of an exported flag
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ExportedFlag {
+  #[serde(flatten)]
+  pub definition: FlagUpdateRequest,
+  /// If the flag is archived
+  #[serde(default)]
+  pub archived: bool,
+}
+
+impl ProductExport {
+  /// Checks the document can be imported into a product with the given `flags`, returning a description of the
+  /// first problem if not
+  pub fn validate(&self, flags: &[FeatureFlag]) -> Result<(), String> {
+    if self.version > EXPORT_VERSION {
+      return Err(format!(
+        "Error. Export format version {} is newer than the supported version {}.",
+        self.version, EXPORT_VERSION
+      ));
+    }
+
+    for window in &self.product.freeze_windows {
+      window.validate().map_err(|e| format!("Error. {}.", e))?;
+    }
+
+    let mut names = HashSet::new();
+    for flag in &self.flags {
+      if !names.insert(flag.definition.name.as_str()) {
+        return Err(format!(
+          "Error. Flag '{}' is defined more than once.",
+          flag.definition.name
+        ));
+      }
+    }
+
+    for flag in &self.flags {
+      let definition = &flag.definition;
+      let violations = definition.violations();
+      if !violations.is_empty() {
+        return Err(format!("Error. {} (flag '{}')", violations.summary(), definition.name));
+      }
+
+      let unknown = definition
+        .prerequisites
+        .iter()
+        .find(|x| !names.contains(x.as_str()) && !flags.iter().any(|y| y.name == **x));
+      if let Some(prerequisite) = unknown {
+        return Err(format!(
+          "Error. Prerequisite '{}' of flag '{}' doesn't exist.",
+          prerequisite, definition.name
+        ));
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Compares the document to the current state of `product` and its `flags`
+  pub fn diff(&self, product: &Product, flags: &[FeatureFlag]) -> ImportReport {
+    let current = ProductExport::new(product, flags);
+    let mut report = ImportReport {
+      product_changes: changed_fields(&current.product, &self.product)
+        .into_iter()
+        .filter(|x| x != "name")
+        .collect(),
+      ..Default::default()
+    };
+
+    for flag in &self.flags {
+      match current.flags.iter().find(|x| x.definition.name == flag.definition.name) {
+        Some(existing) => match changed_fields(existing, flag) {
+          fields if fields.is_empty() => report.unchanged.push(flag.definition.name.clone()),
+          fields => report.updated.push(FlagChanges {
+            name: flag.definition.name.clone(),
+            fields,
+          }),
+        },
+        None => report.created.push(flag.definition.name.clone()),
+      }
+    }
+
+    report.untouched = current
+      .flags
+      .iter()
+      .filter(|x| !self.flags.iter().any(|y| y.definition.name == x.definition.name))
+      .map(|x| x.definition.name.clone())
+      .collect();
+
+    report
+  }
+}
+
+/// Names of the top level fields whose values differ between `current` and `imported`
+fn changed_fields<T: Serialize>(current: &T, imported: &T) -> Vec<String> {
+  match (serde_json::to_value(current), serde_json::to_value(imported)) {
+    (Ok(Value::Object(current)), Ok(Value::Object(imported))) => imported
+      .iter()
+      .filter(|(key, value)| current.get(*key) != Some(*value))
+      .map(|(key, _)| key.clone())
+      .collect(),
+    _ => vec![],
+  }
+}
+
+/// Differences between an imported document and the current state of the product, and what was applied
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct ImportReport {
+  /// If the import was only checked, nothing was changed
+  pub dry_run: bool,
+  /// Product settings that differ from the document (e.g. `freeze_windows`), the product is never renamed
+  pub product_changes: Vec<String>,
+  /// Flags of the document the product doesn't have
+  pub created: Vec<String>,
+  /// Flags that differ from the document, with the fields that differ
+  pub updated: Vec<FlagChanges>,
+  /// Flags already matching the document
+  pub unchanged: Vec<String>,
+  /// Flags of the product missing from the document, left as they are
+  pub untouched: Vec<String>,
+  /// Flags (or `product` for its settings) that failed to be written. Always empty, imports are written in one
+  /// transaction that fails the import if any write fails
+  pub failed: Vec<String>,
+}
+
+/// Flag that differs from an imported document
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FlagChanges {
+  /// Name of the flag
+  pub name: String,
+  /// Fields whose values differ (e.g. `release_type`)
+  pub fields: Vec<String>,
+}
@@ -0,0 +1,44 @@
+//! Build and version information about the running service
+
+use rocket::serde::Serialize;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+
+/// Metadata describing the build of the running service, embedded at compile time
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct BuildInfo {
+  /// Crate version from `Cargo.toml`
+  pub version: String,
+  /// Git commit SHA the binary was built from
+  pub git_sha: String,
+  /// Timestamp of when the binary was built
+  pub build_timestamp: String,
+  /// Cargo features enabled for the build
+  pub cargo_features: Vec<String>,
+}
+
+impl BuildInfo {
+  /// Returns the `BuildInfo` of the running binary
+  pub fn current() -> BuildInfo {
+    BuildInfo {
+      version: env!("CARGO_PKG_VERSION").to_string(),
+      git_sha: env!("VERGEN_GIT_SHA").to_string(),
+      build_timestamp: env!("VERGEN_BUILD_TIMESTAMP").to_string(),
+      cargo_features: env!("VERGEN_CARGO_FEATURES")
+        .split(',')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect(),
+    }
+  }
+
+  /// Single line startup banner summarizing the build
+  pub fn banner(&self) -> String {
+    format!(
+      "feature-flagging-service v{} (git {}, built {}, features [{}])",
+      self.version,
+      self.git_sha,
+      self.build_timestamp,
+      self.cargo_features.join(", ")
+    )
+  }
+}
@@ -0,0 +1,408 @@
+//! WebSocket stream of the live state of a product's flags
+//!
+//! Rocket can't upgrade connections, so the stream is served by its own listener on the Rocket address and
+//! `WEBSOCKET_PORT` (the Rocket port plus one by default). Clients connect to
+//! `/api/v1/flags/<product_id>` with the `token` returned by `/login` in an `Authorization: Bearer <token>` header, or
+//! a server API key of the product with the `flags:read` scope in an `X-Api-Key` header (optionally with
+//! `?case=camel`, and `user_identifiers=hash` to replace user IDs and emails with opaque hashes) and receive:
+//! * `{"type": "snapshot", "flags": [...]}` - every flag of the product, on connect and whenever the client fell too far
+//!   behind to catch up from changes
+//! * `{"type": "update", "flag": {...}}`     - new state of a flag that was created or changed
+//! * `{"type": "delete", "flag_id": "..."}`  - ID of a flag that was deleted
+//!
+//! The server pings every 30 seconds and disconnects clients it hasn't heard from (pongs included) in two intervals.
+//! Credentials are checked again before every ping, so streams of users who logged out, were suspended or deactivated,
+//! or stopped being users of the product, and of revoked API keys, are closed within an interval. Connections that
+//! don't finish the handshake within 10 seconds are dropped
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use futures::{SinkExt, StreamExt};
+use rocket::http::RawStr;
+use serde::Serialize;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::WebSocketStream;
+
+use crate::controller::api_keys::{self, ApiKeyAuth, ApiKeyError, API_KEY_HEADER};
+use crate::controller::authentication::Credentials;
+use crate::controller::case::{self, JsonCase};
+use crate::controller::database::{ConnectionManager, FlagFilter, FlagProjection};
+use crate::controller::events::{ChangeEvent, UserIdentifiers};
+use crate::controller::versioning::API_V1;
+use crate::log;
+use crate::model::flag::SpecSafeFeatureFlag;
+use crate::model::user::AccountType;
+
+/// Interval between pings sent to clients
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Time clients have to finish the handshake, so connections that never send a request don't stay open
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Returns the address to serve the stream on, the Rocket address with `WEBSOCKET_PORT` or the Rocket port plus one
+pub fn address_from_env(rocket_address: IpAddr, rocket_port: u16) -> SocketAddr {
+  let port = match dotenv::var("WEBSOCKET_PORT").map(|x| x.parse::<u16>()) {
+    Ok(Ok(port)) => port,
+    _ => rocket_port.wrapping_add(1),
+  };
+
+  SocketAddr::new(rocket_address, port)
+}
+
+/// Accepts connections on `address`, forever
+pub async fn serve(address: SocketAddr, database_connection: ConnectionManager) {
+  let listener = match TcpListener::bind(address).await {
+    Ok(listener) => listener,
+    Err(e) => {
+      log!("Error binding live flag stream to {}. Error: {:?}", address, e);
+      return;
+    }
+  };
+
+  log!("Streaming live flag state on ws://{}", address);
+
+  loop {
+    match listener.accept().await {
+      Ok((stream, _)) => {
+        tokio::spawn(handle(stream, database_connection.clone()));
+      }
+      Err(e) => log!("Error accepting live flag stream connection. Error: {:?}", e),
+    }
+  }
+}
+
+/// Product whose flags a connection streams, and who to
+struct Subscription {
+  product_id: String,
+  access: Access,
+  case: JsonCase,
+  user_identifiers: UserIdentifiers,
+}
+
+/// What a connection authenticates with
+enum Access {
+  /// Session of a user logged in with `/login`
+  Session(Credentials),
+  /// API key of the product
+  ApiKey(String),
+}
+
+/// Message sent to clients
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LiveMessage<'a> {
+  Snapshot { flags: Vec<SpecSafeFeatureFlag> },
+  Update { flag: &'a SpecSafeFeatureFlag },
+  Delete { flag_id: &'a str },
+}
+
+async fn handle(stream: TcpStream, database_connection: ConnectionManager) {
+  let mut subscription = None;
+  let handshake = Handshake {
+    subscription: &mut subscription,
+  };
+
+  let mut socket = match tokio::time::timeout(
+    HANDSHAKE_TIMEOUT,
+    tokio_tungstenite::accept_hdr_async(stream, handshake),
+  )
+  .await
+  {
+    Ok(Ok(socket)) => socket,
+    // Rejected by `subscribe`, the client was sent why
+    Ok(Err(tungstenite::Error::Http(_))) => return,
+    Ok(Err(e)) => {
+      log!("Error accepting live flag stream handshake. Error: {:?}", e);
+      return;
+    }
+    Err(_) => return,
+  };
+
+  let subscription = match subscription {
+    Some(subscription) => subscription,
+    None => return,
+  };
+
+  if let Err(reason) = authorize(&subscription, &database_connection).await {
+    let _ = refuse(&mut socket, reason).await;
+    return;
+  }
+
+  stream_flags(socket, &subscription, &database_connection).await;
+}
+
+/// Handshake callback storing the subscription of accepted requests
+struct Handshake<'a> {
+  subscription: &'a mut Option<Subscription>,
+}
+
+impl Callback for Handshake<'_> {
+  fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+    match subscribe(request) {
+      Ok(subscription) => {
+        *self.subscription = Some(subscription);
+        Ok(response)
+      }
+      Err((status, message)) => {
+        let mut response = ErrorResponse::new(Some(message.to_string()));
+        *response.status_mut() = status;
+        Err(response)
+      }
+    }
+  }
+}
+
+/// Reads the product and credentials from a handshake request, rejecting unknown paths and missing credentials
+fn subscribe(request: &Request) -> Result<Subscription, (StatusCode, &'static str)> {
+  let path = request.uri().path();
+  let path = path.strip_prefix(API_V1).unwrap_or(path);
+  let product_id = match path.strip_prefix("/flags/") {
+    Some(product_id) if !product_id.is_empty() && !product_id.contains('/') => product_id.to_string(),
+    _ => return Err((StatusCode::NOT_FOUND, "Error. Unknown path.")),
+  };
+
+  // Read from headers rather than the query, which ends up in the logs of proxies
+  let header = |name| request.headers().get(name).and_then(|x| x.to_str().ok());
+  let access = match (
+    header(API_KEY_HEADER),
+    header("Authorization").and_then(Credentials::from_bearer),
+  ) {
+    (Some(key), _) => Access::ApiKey(key.to_string()),
+    (None, Some(credentials)) => Access::Session(credentials),
+    (None, None) => {
+      return Err((
+        StatusCode::UNAUTHORIZED,
+        "Error. Missing Authorization bearer token or X-Api-Key header.",
+      ))
+    }
+  };
+
+  let mut case = JsonCase::configured();
+  let mut user_identifiers = UserIdentifiers::Include;
+
+  for pair in request.uri().query().unwrap_or_default().split('&') {
+    let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+    let value = RawStr::new(value).url_decode_lossy().into_owned();
+
+    match key {
+      "case" => case = JsonCase::from_name(&value).unwrap_or(case),
+      "user_identifiers" => match UserIdentifiers::from_name(&value) {
+        Some(value) if !value.is_available() => {
+          return Err((
+            StatusCode::BAD_REQUEST,
+            "Error. user_identifiers=hash needs ANONYMIZATION_SECRET to be set.",
+          ))
+        }
+        Some(value) => user_identifiers = value,
+        None => {
+          return Err((
+            StatusCode::BAD_REQUEST,
+            "Error. user_identifiers must be include or hash.",
+          ))
+        }
+      },
+      _ => (),
+    }
+  }
+
+  Ok(Subscription {
+    product_id,
+    access,
+    case,
+    user_identifiers,
+  })
+}
+
+/// Checks the connection may stream the product's flags, returning why not otherwise
+async fn authorize(subscription: &Subscription, database_connection: &ConnectionManager) -> Result<(), String> {
+  match &subscription.access {
+    Access::Session(credentials) => authorize_session(&subscription.product_id, credentials, database_connection).await,
+    Access::ApiKey(key) => authorize_api_key(&subscription.product_id, key, database_connection).await,
+  }
+}
+
+/// Checks the credentials are of a logged in session of an active user, who is a developer or a user of the product
+async fn authorize_session(
+  product_id: &str,
+  credentials: &Credentials,
+  database_connection: &ConnectionManager,
+) -> Result<(), String> {
+  let token_hash = api_keys::hash(&credentials.auth_token);
+  if database_connection
+    .get_session(&credentials.user_id, &token_hash)
+    .await
+    .map_err(unavailable)?
+    .is_none()
+  {
+    return Err("Invalid credentials".to_string());
+  }
+
+  let product = match database_connection
+    .get_product_by_id(product_id)
+    .await
+    .map_err(unavailable)?
+  {
+    Some(product) => product,
+    None => return Err(format!("Unknown product '{}'", product_id)),
+  };
+
+  match database_connection
+    .get_user(None, Some(&credentials.user_id))
+    .await
+    .map_err(unavailable)?
+  {
+    Some(user) if !user.is_active() => Err("Account is not active".to_string()),
+    Some(user) => match user.account_type {
+      AccountType::Developer => Ok(()),
+      AccountType::Client if product.users.contains(&credentials.user_id) => Ok(()),
+      AccountType::Client => Err("Not a user of the product".to_string()),
+    },
+    None => Err(format!("Unknown user '{}'", credentials.user_id)),
+  }
+}
+
+/// Checks the key is an active server key of the product with the `flags:read` scope, like `/sdk/snapshot` requires
+async fn authorize_api_key(product_id: &str, key: &str, database_connection: &ConnectionManager) -> Result<(), String> {
+  let api_key = match ApiKeyAuth::lookup(database_connection, key).await {
+    Ok(api_key) => api_key,
+    Err(ApiKeyError::Unavailable) => return Err(unavailable(())),
+    Err(_) => return Err("Invalid API key".to_string()),
+  };
+
+  api_key
+    .require_server()
+    .and_then(|_| api_key.authorize(product_id))
+    .map_err(|e| e.message)
+}
+
+/// Reason given to clients whose credentials couldn't be checked
+fn unavailable<E>(_: E) -> String {
+  "Unable to check the credentials, try again later".to_string()
+}
+
+/// Sends a snapshot of the product's flags, then changes to them until the client leaves or stops answering
+async fn stream_flags(
+  mut socket: WebSocketStream<TcpStream>,
+  subscription: &Subscription,
+  database_connection: &ConnectionManager,
+) {
+  // Subscribed before the snapshot is read so changes made in between aren't missed
+  let mut events = database_connection.subscribe_live();
+  if send_snapshot(&mut socket, subscription, database_connection)
+    .await
+    .is_err()
+  {
+    return;
+  }
+
+  let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+  heartbeat.tick().await;
+  let mut last_seen = Instant::now();
+
+  loop {
+    let sent = tokio::select! {
+      message = socket.next() => match message {
+        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+        // Pings are answered by the socket itself, anything received shows the client is still there
+        Some(Ok(_)) => {
+          last_seen = Instant::now();
+          Ok(())
+        }
+      },
+      event = events.recv() => match event {
+        Ok(event) if event.product_id() == subscription.product_id => {
+          match &event {
+            ChangeEvent::FlagUpdated(flag) => {
+              let flag = subscription.user_identifiers.apply(flag);
+              send(&mut socket, &LiveMessage::Update { flag: &flag }, subscription.case).await
+            }
+            ChangeEvent::FlagDeleted { flag_id, .. } => {
+              send(&mut socket, &LiveMessage::Delete { flag_id }, subscription.case).await
+            }
+            // Deleting a product deletes its flags, their deletes were already sent
+            ChangeEvent::ProductDeleted { .. } => break,
+            ChangeEvent::ProductUpdated(_) => Ok(()),
+          }
+        }
+        Ok(_) => Ok(()),
+        Err(RecvError::Lagged(_)) => send_snapshot(&mut socket, subscription, database_connection).await,
+        Err(RecvError::Closed) => break,
+      },
+      _ = heartbeat.tick() => {
+        if last_seen.elapsed() > 2 * HEARTBEAT_INTERVAL {
+          break;
+        }
+        if let Err(reason) = authorize(subscription, database_connection).await {
+          let _ = refuse(&mut socket, reason).await;
+          break;
+        }
+        socket.send(Message::Ping(vec![])).await
+      }
+    };
+
+    if sent.is_err() {
+      break;
+    }
+  }
+}
+
+/// Closes the connection of a client whose credentials were refused, telling it why
+async fn refuse(socket: &mut WebSocketStream<TcpStream>, reason: String) -> tungstenite::Result<()> {
+  let frame = CloseFrame {
+    code: CloseCode::Policy,
+    reason: reason.into(),
+  };
+  socket.close(Some(frame)).await
+}
+
+async fn send_snapshot(
+  socket: &mut WebSocketStream<TcpStream>,
+  subscription: &Subscription,
+  database_connection: &ConnectionManager,
+) -> tungstenite::Result<()> {
+  let flags = match database_connection
+    .get_feature_flags(&subscription.product_id, FlagFilter::default(), FlagProjection::Full)
+    .await
+  {
+    Ok(flags) => flags,
+    Err(_) => {
+      let frame = CloseFrame {
+        code: CloseCode::Again,
+        reason: "Unable to read the flags, try again later".into(),
+      };
+      socket.close(Some(frame)).await?;
+      return Err(tungstenite::Error::ConnectionClosed);
+    }
+  };
+
+  let flags = flags
+    .iter()
+    .map(|x| {
+      let flag = x.get_spec_safe_feature_flag();
+      subscription.user_identifiers.apply(&flag).into_owned()
+    })
+    .collect();
+
+  send(socket, &LiveMessage::Snapshot { flags }, subscription.case).await
+}
+
+async fn send(
+  socket: &mut WebSocketStream<TcpStream>,
+  message: &LiveMessage<'_>,
+  case: JsonCase,
+) -> tungstenite::Result<()> {
+  match case::to_json(message, case) {
+    Ok(text) => socket.send(Message::Text(text)).await,
+    Err(e) => {
+      log!("Error serializing live flag message. Error: {:?}", e);
+      Ok(())
+    }
+  }
+}
@@ -0,0 +1,205 @@
+//! Admission control that sheds low priority traffic while the service is overloaded
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Method};
+use rocket::{Data, Request, Response};
+
+use crate::controller::versioning::API_V1;
+use crate::log;
+
+/// Path requests are rewritten to when they are shed
+pub const SHED_PATH: &str = "/__load_shed";
+
+/// Weight given to the newest latency sample in the moving average
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// Priority of a group of routes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+  /// Shed first, as soon as the service is overloaded
+  Low,
+  /// Shed once the service is severely overloaded
+  Normal,
+  /// Never shed
+  Critical,
+}
+
+impl std::convert::From<&str> for Priority {
+  fn from(other: &str) -> Self {
+    match other.trim().to_lowercase().as_str() {
+      "low" => Self::Low,
+      "critical" => Self::Critical,
+      &_ => Self::Normal,
+    }
+  }
+}
+
+/// Groups of routes that share a priority
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RouteGroup {
+  /// Flag evaluation (`/check...`)
+  Check,
+  /// Kill switch mutations (`/hoist/...`, `/lower/...`)
+  KillSwitch,
+  /// List endpoints (`/get/flags/...`, `/get/products/...`, `/get/users/...`)
+  Lists,
+  /// Everything else
+  Default,
+}
+
+impl RouteGroup {
+  /// Classifies a request path, versioned or not, into its route group
+  pub fn from_path(path: &str) -> RouteGroup {
+    let path = path.strip_prefix(API_V1).unwrap_or(path);
+
+    if path.starts_with("/check") {
+      RouteGroup::Check
+    } else if path.starts_with("/hoist/") || path.starts_with("/lower/") {
+      RouteGroup::KillSwitch
+    } else if path.starts_with("/get/flags/") || path.starts_with("/get/products/") || path.starts_with("/get/users/") {
+      RouteGroup::Lists
+    } else {
+      RouteGroup::Default
+    }
+  }
+
+  fn from_name(name: &str) -> Option<RouteGroup> {
+    match name.trim() {
+      "check" => Some(RouteGroup::Check),
+      "kill_switch" => Some(RouteGroup::KillSwitch),
+      "lists" => Some(RouteGroup::Lists),
+      "default" => Some(RouteGroup::Default),
+      _ => None,
+    }
+  }
+}
+
+/// Fairing tracking in-flight requests and latency, shedding requests by priority under overload
+///
+/// Configured from the environment:
+/// * **LOAD_SHED_MAX_IN_FLIGHT**  - in-flight requests above which the service is overloaded
+/// * **LOAD_SHED_MAX_LATENCY_MS** - average latency above which the service is overloaded
+/// * **LOAD_SHED_RETRY_AFTER**    - seconds sent in the `Retry-After` header of shed requests (default `1`)
+/// * **LOAD_SHED_PRIORITIES**     - overrides of route group priorities, e.g. `lists:low,default:normal`
+///
+/// Shedding is disabled when neither threshold is set. `Low` priority routes are shed past the thresholds and `Normal`
+/// routes past twice the thresholds, `Critical` routes are never shed
+pub struct LoadShedder {
+  max_in_flight: Option<usize>,
+  max_latency_ms: Option<f64>,
+  retry_after: u64,
+  priorities: HashMap<RouteGroup, Priority>,
+  in_flight: AtomicUsize,
+  /// Moving average of request latency in microseconds
+  latency_micros: AtomicU64,
+}
+
+/// Time a request started, stored in the request local cache
+struct RequestStart(Instant);
+
+/// If a request was shed, stored in the request local cache
+struct Shed(bool);
+
+impl LoadShedder {
+  /// Constructs a `LoadShedder` from the environment
+  pub fn from_env() -> LoadShedder {
+    let mut priorities = HashMap::new();
+    priorities.insert(RouteGroup::Check, Priority::Critical);
+    priorities.insert(RouteGroup::KillSwitch, Priority::Critical);
+    priorities.insert(RouteGroup::Lists, Priority::Low);
+    priorities.insert(RouteGroup::Default, Priority::Normal);
+
+    if let Ok(value) = dotenv::var("LOAD_SHED_PRIORITIES") {
+      for entry in value.split(',') {
+        match entry.split_once(':') {
+          Some((group, priority)) => match RouteGroup::from_name(group) {
+            Some(group) => {
+              priorities.insert(group, Priority::from(priority));
+            }
+            None => log!("Ignoring unknown route group '{}' in 'LOAD_SHED_PRIORITIES'", group),
+          },
+          None => log!("Ignoring malformed entry '{}' in 'LOAD_SHED_PRIORITIES'", entry),
+        }
+      }
+    }
+
+    LoadShedder {
+      max_in_flight: dotenv::var("LOAD_SHED_MAX_IN_FLIGHT").ok().and_then(|x| x.parse().ok()),
+      max_latency_ms: dotenv::var("LOAD_SHED_MAX_LATENCY_MS").ok().and_then(|x| x.parse().ok()),
+      retry_after: dotenv::var("LOAD_SHED_RETRY_AFTER")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(1),
+      priorities,
+      in_flight: AtomicUsize::new(0),
+      latency_micros: AtomicU64::new(0),
+    }
+  }
+
+  /// Returns how overloaded the service is as a multiple of the most exceeded threshold (`< 1.0` is not overloaded)
+  fn load(&self, in_flight: usize) -> f64 {
+    let in_flight_load = match self.max_in_flight {
+      Some(max) if max > 0 => in_flight as f64 / max as f64,
+      _ => 0.0,
+    };
+    let latency_load = match self.max_latency_ms {
+      Some(max) if max > 0.0 => self.latency_micros.load(Ordering::Relaxed) as f64 / 1000.0 / max,
+      _ => 0.0,
+    };
+
+    in_flight_load.max(latency_load)
+  }
+
+  fn should_shed(&self, group: RouteGroup, in_flight: usize) -> bool {
+    let priority = self.priorities.get(&group).copied().unwrap_or(Priority::Normal);
+    let load = self.load(in_flight);
+
+    match priority {
+      Priority::Critical => false,
+      Priority::Normal => load > 2.0,
+      Priority::Low => load > 1.0,
+    }
+  }
+}
+
+#[rocket::async_trait]
+impl Fairing for LoadShedder {
+  fn info(&self) -> Info {
+    Info {
+      name: "Load Shedder",
+      kind: Kind::Request | Kind::Response,
+    }
+  }
+
+  async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+    request.local_cache(|| RequestStart(Instant::now()));
+    let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+    let shed = self.should_shed(RouteGroup::from_path(request.uri().path().as_str()), in_flight);
+    request.local_cache(|| Shed(shed));
+
+    // Fairings can't respond directly, so shed requests are routed to the load shed route instead
+    if shed {
+      request.set_method(Method::Get);
+      request.set_uri(Origin::parse(SHED_PATH).expect("valid load shed path"));
+    }
+  }
+
+  async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+    self.in_flight.fetch_sub(1, Ordering::Relaxed);
+
+    if request.local_cache(|| Shed(false)).0 {
+      response.set_header(Header::new("Retry-After", self.retry_after.to_string()));
+    }
+
+    // Shed requests are sampled too, so the average recovers even when only low priority traffic arrives
+    let elapsed = request.local_cache(|| RequestStart(Instant::now())).0.elapsed().as_micros() as f64;
+    let average = self.latency_micros.load(Ordering::Relaxed) as f64;
+    let updated = average + LATENCY_SMOOTHING * (elapsed - average);
+    self.latency_micros.store(updated as u64, Ordering::Relaxed);
+  }
+}
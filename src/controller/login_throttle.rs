@@ -0,0 +1,162 @@
+//! Throttling of failed logins, resisting credential stuffing
+//!
+//! Failed logins are counted per email and per client IP. Every failure makes the next attempt wait twice as long as
+//! the previous one, and after `LOGIN_MAX_FAILURES` failures the email or IP is locked out for `LOGIN_LOCKOUT_SECONDS`.
+//! Failures are forgotten once an email or IP has been quiet for the lockout duration, a successful login only forgets
+//! those of its email. Developers can lift the lockout of a user early with `POST /users/<user_id>/unlock`
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use crate::controller::error::ApiError;
+
+/// Failures before an email or IP is locked out when `LOGIN_MAX_FAILURES` isn't set
+const DEFAULT_MAX_FAILURES: u32 = 5;
+/// Seconds lockouts last when `LOGIN_LOCKOUT_SECONDS` isn't set
+const DEFAULT_LOCKOUT_SECONDS: u64 = 15 * 60;
+/// Seconds waited after a first failure when `LOGIN_BACKOFF_SECONDS` isn't set, doubling with each failure after it (up to
+/// the lockout duration)
+const DEFAULT_BACKOFF_SECONDS: u64 = 1;
+
+/// Emails and IPs tracked before the ones whose failures would be forgotten are pruned
+const MAX_TRACKED: usize = 10_000;
+
+/// What failed logins are counted against
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum ThrottleKey {
+  Email(String),
+  Ip(IpAddr),
+}
+
+/// Failed logins of an email or IP
+struct Failures {
+  count: u32,
+  /// When the next attempt is allowed
+  blocked_until: Instant,
+}
+
+/// Failed logins tracked per email and per client IP
+pub struct LoginThrottle {
+  max_failures: u32,
+  lockout: Duration,
+  backoff: Duration,
+  failures: Mutex<HashMap<ThrottleKey, Failures>>,
+}
+
+/// Reads a positive number from the environment variable `name`, `default` if it isn't set or invalid
+fn positive_from_env(name: &str, default: u64) -> u64 {
+  match dotenv::var(name).map(|x| x.trim().parse::<u64>()) {
+    Ok(Ok(value)) if value > 0 => value,
+    _ => default,
+  }
+}
+
+impl LoginThrottle {
+  /// Constructs a `LoginThrottle` configured from `LOGIN_MAX_FAILURES`, `LOGIN_LOCKOUT_SECONDS` and
+  /// `LOGIN_BACKOFF_SECONDS`
+  pub fn from_env() -> LoginThrottle {
+    LoginThrottle {
+      max_failures: positive_from_env("LOGIN_MAX_FAILURES", DEFAULT_MAX_FAILURES as u64) as u32,
+      lockout: Duration::from_secs(positive_from_env("LOGIN_LOCKOUT_SECONDS", DEFAULT_LOCKOUT_SECONDS)),
+      backoff: Duration::from_secs(positive_from_env("LOGIN_BACKOFF_SECONDS", DEFAULT_BACKOFF_SECONDS)),
+      failures: Mutex::new(HashMap::new()),
+    }
+  }
+
+  fn keys(email: &str, ip: Option<IpAddr>) -> Vec<ThrottleKey> {
+    let mut keys = vec![ThrottleKey::Email(email.trim().to_lowercase())];
+    keys.extend(ip.map(ThrottleKey::Ip));
+    keys
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<ThrottleKey, Failures>> {
+    match self.failures.lock() {
+      Ok(value) => value,
+      Err(poisoned) => poisoned.into_inner(), // recover from poisoned mutex
+    }
+  }
+
+  /// If the failures are old enough to be forgotten at `now`
+  fn is_stale(&self, failures: &Failures, now: Instant) -> bool {
+    now >= failures.blocked_until + self.lockout
+  }
+
+  /// Checks a login of `email` from `ip` can be attempted now
+  ///
+  /// Returns 423 if the email or IP is locked out, and 429 if it has to wait after a recent failure, with the seconds
+  /// to wait in `details.retry_after`
+  pub fn check(&self, email: &str, ip: Option<IpAddr>) -> Result<(), ApiError> {
+    let failures = self.lock();
+    let now = Instant::now();
+
+    let blocked = LoginThrottle::keys(email, ip)
+      .iter()
+      .filter_map(|key| failures.get(key))
+      .filter(|x| x.blocked_until > now)
+      .max_by_key(|x| x.blocked_until);
+
+    let failures = match blocked {
+      Some(failures) => failures,
+      None => return Ok(()),
+    };
+
+    let retry_after = (failures.blocked_until - now).as_secs_f64().ceil() as u64;
+    let error = match failures.count >= self.max_failures {
+      true => ApiError::new(
+        Status::Locked,
+        format!("Error. Too many failed logins, locked out for {} seconds.", retry_after),
+      ),
+      false => ApiError::new(
+        Status::TooManyRequests,
+        format!("Error. Login failed recently, retry in {} seconds.", retry_after),
+      ),
+    };
+
+    Err(error.with_details(json!({ "retry_after": retry_after })))
+  }
+
+  /// Records a failed login of `email` from `ip`
+  pub fn record_failure(&self, email: &str, ip: Option<IpAddr>) {
+    let mut failures = self.lock();
+    let now = Instant::now();
+
+    for key in LoginThrottle::keys(email, ip) {
+      let entry = failures.entry(key).or_insert(Failures {
+        count: 0,
+        blocked_until: now,
+      });
+      if self.is_stale(entry, now) {
+        entry.count = 0;
+      }
+
+      entry.count += 1;
+      entry.blocked_until = match entry.count >= self.max_failures {
+        true => now + self.lockout,
+        false => {
+          let backoff = self.backoff.checked_mul(2u32.saturating_pow(entry.count - 1));
+          now + backoff.unwrap_or(self.lockout).min(self.lockout)
+        }
+      };
+    }
+
+    if failures.len() > MAX_TRACKED {
+      failures.retain(|_, x| !self.is_stale(x, now));
+    }
+  }
+
+  /// Forgets the failed logins of `email`, after it logged in or was unlocked
+  ///
+  /// Returns true if it was locked out or waiting after a failure
+  pub fn clear(&self, email: &str) -> bool {
+    let now = Instant::now();
+    match self.lock().remove(&ThrottleKey::Email(email.trim().to_lowercase())) {
+      Some(failures) => failures.blocked_until > now,
+      None => false,
+    }
+  }
+}
@@ -0,0 +1,181 @@
+//! Per-product membership, routes changing a product or its flags are limited to the users of the product,
+//! developers, and the service accounts and API keys given access to the product
+
+use rocket::http::Status;
+use rocket::outcome::Outcome as GuardOutcome;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::{
+  gen::OpenApiGenerator,
+  request::{OpenApiFromRequest, RequestHeaderInput},
+};
+
+use crate::controller::authentication;
+use crate::controller::database::ConnectionManager;
+use crate::controller::error::{self, ApiError};
+use crate::controller::scopes::ActorAuth;
+use crate::log;
+use crate::model::product::Product;
+use crate::model::user::AccountType;
+
+/// Name of the route parameter holding the ID of the product
+const PRODUCT_ID_PARAM: &str = "<product_id>";
+
+#[derive(Debug)]
+pub enum MembershipError {
+  NotLoggedIn,
+  UnknownProduct,
+  NotMember,
+  Unavailable,
+}
+
+/// Custom rocket request guard for routes changing the product of their `<product_id>` parameter
+///
+/// Fails with 401 if the request isn't logged in, 404 if the product isn't found, and 403 if the user is neither a
+/// developer nor listed in the users of the product, or the service account or API key doesn't give access to the
+/// product
+pub struct ProductMember {
+  /// Product of the route's `<product_id>` parameter
+  pub product: Product,
+}
+
+/// Returns the `<product_id>` parameter of the route the request matched, if it has one
+pub fn product_id_param<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+  let route = request.route()?;
+  let index = route
+    .uri
+    .unmounted_origin
+    .path()
+    .as_str()
+    .split('/')
+    .skip(1)
+    .position(|x| x == PRODUCT_ID_PARAM)?;
+
+  request.routed_segment(index)
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ProductMember {
+  type Error = MembershipError;
+
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    let actor = match request.guard::<ActorAuth>().await {
+      GuardOutcome::Success(actor) => actor,
+      _ => {
+        return error::fail_guard(
+          request,
+          ApiError::new(Status::Unauthorized, "Error. Not logged in."),
+          MembershipError::NotLoggedIn,
+        )
+      }
+    };
+
+    let unavailable = || ApiError::new(Status::InternalServerError, "Error. Something went wrong.");
+    let database_connection = match request.rocket().state::<ConnectionManager>() {
+      Some(value) => value,
+      None => return error::fail_guard(request, unavailable(), MembershipError::Unavailable),
+    };
+    let product_id = match product_id_param(request) {
+      Some(value) => value,
+      None => {
+        log!(
+          "Route guarded by product membership has no '{}' parameter",
+          PRODUCT_ID_PARAM
+        );
+        return error::fail_guard(request, unavailable(), MembershipError::Unavailable);
+      }
+    };
+
+    let product = match database_connection.get_product_by_id(product_id).await {
+      Ok(Some(product)) => product,
+      Err(e) => return error::fail_guard(request, e.into(), MembershipError::Unavailable),
+      Ok(None) => {
+        return error::fail_guard(
+          request,
+          ApiError::not_found(format!("Error. Unable to get product: '{}'.", product_id)),
+          MembershipError::UnknownProduct,
+        )
+      }
+    };
+
+    match require_member(database_connection, &actor, &product).await {
+      Ok(()) => Outcome::Success(ProductMember { product }),
+      Err(e) if e.status == Status::Forbidden => error::fail_guard(request, e, MembershipError::NotMember),
+      Err(e) => error::fail_guard(request, e, MembershipError::Unavailable),
+    }
+  }
+}
+
+/// Checks `actor` can change `product`, like `ProductMember` does for routes with a `<product_id>` parameter
+///
+/// For routes finding the product some other way (e.g. from a flag's ID). Returns 403 if the user is neither a
+/// developer nor listed in the users of the product, or the service account or API key doesn't give access to it
+pub async fn require_member(
+  database_connection: &ConnectionManager,
+  actor: &ActorAuth,
+  product: &Product,
+) -> Result<(), ApiError> {
+  let product_id = product.oid.map(|x| x.to_hex()).unwrap_or_default();
+  if !actor.is_user() {
+    return actor.authorize(&product_id);
+  }
+
+  let is_member = product.users.contains(&actor.user_id)
+    || matches!(
+      database_connection.get_user(None, Some(&actor.user_id)).await?,
+      Some(user) if matches!(user.account_type, AccountType::Developer)
+    );
+
+  match is_member {
+    true => Ok(()),
+    false => Err(ApiError::new(
+      Status::Forbidden,
+      format!(
+        "Error. Only users of product '{}' and developers can change it.",
+        product.name
+      ),
+    )),
+  }
+}
+
+/// Checks `actor` can change the product of ID `product_id`, see `require_member`
+///
+/// Returns 404 if the product isn't found
+pub async fn require_product_member(
+  database_connection: &ConnectionManager,
+  actor: &ActorAuth,
+  product_id: &str,
+) -> Result<(), ApiError> {
+  match database_connection.get_product_by_id(product_id).await? {
+    Some(product) => require_member(database_connection, actor, &product).await,
+    None => Err(ApiError::not_found(format!(
+      "Error. Unable to get product: '{}'.",
+      product_id
+    ))),
+  }
+}
+
+impl<'a> OpenApiFromRequest<'a> for ProductMember {
+  fn from_request_input(
+    _gen: &mut OpenApiGenerator,
+    _name: String,
+    _required: bool,
+  ) -> rocket_okapi::Result<RequestHeaderInput> {
+    Ok(authentication::security_input())
+  }
+
+  fn get_responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+    Ok(error::error_responses(
+      gen,
+      &[
+        ("401", "Not logged in, or the login cookies or bearer token are invalid"),
+        (
+          "403",
+          "The user isn't a user of the product nor a developer, the service account or API key doesn't give access to \
+           the product, or a permission the route needs isn't granted",
+        ),
+        ("404", "The product isn't found"),
+      ],
+    ))
+  }
+}
@@ -1,3 +1,35 @@
+pub mod allowlist;
+pub mod api_keys;
 pub mod authentication;
+pub mod backup;
+pub mod cache;
+pub mod case;
+pub mod cors;
+pub mod csrf;
 pub mod database;
+pub mod docs;
+pub mod encoding;
+pub mod error;
+pub mod evaluations;
+pub mod events;
+pub mod export;
+pub mod info;
+pub mod live;
+pub mod load_shedding;
+pub mod login_throttle;
+pub mod membership;
+pub mod oidc;
+pub mod password_policy;
+pub mod plugin;
+pub mod rate_limit;
+pub mod request;
+pub mod request_id;
 pub mod response;
+pub mod saml;
+pub mod scheduler;
+pub mod scopes;
+pub mod secrets;
+pub mod service_accounts;
+pub mod validation;
+pub mod versioning;
+pub mod webhooks;
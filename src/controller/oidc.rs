@@ -0,0 +1,215 @@
+//! Login through an OpenID Connect provider (e.g. Google, Okta), with the authorization code flow
+//!
+//! `GET /oidc/login` redirects to the provider, which redirects back to `GET /oidc/callback` with a code. The code is
+//! exchanged for an access token at the provider's token endpoint, and the user's email read from its userinfo
+//! endpoint. Both requests are made directly to the provider over TLS and authenticated with the client secret, so the
+//! ID token doesn't have to be verified. Users are looked up by their email, which the provider must have verified, and
+//! created if they don't exist yet
+//!
+//! Configured from the environment, OIDC login is disabled unless the first four are set (and not blank):
+//! * **OIDC_ISSUER**              - issuer URL of the provider (e.g. `https://accounts.google.com`)
+//! * **OIDC_CLIENT_ID**           - client ID of the service at the provider
+//! * **OIDC_CLIENT_SECRET**       - client secret of the service at the provider
+//! * **OIDC_REDIRECT_URI**        - URL of `/api/v1/oidc/callback`, as registered at the provider
+//! * **OIDC_SCOPES**              - scopes requested, `openid email profile` by default
+//! * **OIDC_DEFAULT_ROLE**        - role of users created on their first login, `viewer` by default
+//! * **OIDC_POST_LOGIN_REDIRECT** - where users are redirected once logged in, `/` by default
+
+use std::time::Duration;
+
+use reqwest::Url;
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+use serde::Deserialize;
+
+use crate::controller::error::ApiError;
+use crate::controller::secrets;
+use crate::log;
+use crate::model::user::Role;
+
+/// Private cookie holding the `state` of a login in progress, checked when the provider redirects back
+pub const OIDC_STATE: &str = "oidc_state";
+
+/// How long requests to the provider can take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Configuration of the OpenID Connect provider
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+  pub issuer: String,
+  pub client_id: String,
+  pub client_secret: String,
+  pub redirect_uri: String,
+  pub scopes: String,
+  /// Role of users created on their first login
+  pub default_role: Role,
+  /// Where users are redirected once logged in
+  pub post_login_redirect: String,
+}
+
+/// Reads the environment variable `name`, `None` if it isn't set or is blank
+fn var(name: &str) -> Option<String> {
+  secrets::var(name).filter(|x| !x.trim().is_empty())
+}
+
+impl OidcConfig {
+  /// Reads the configuration from the environment, `None` if OIDC login isn't configured
+  pub fn from_env() -> Option<OidcConfig> {
+    let default_role = match var("OIDC_DEFAULT_ROLE") {
+      Some(role) => Role::from_name(role.trim()).unwrap_or_else(|| {
+        log!(
+          "Ignoring unknown 'OIDC_DEFAULT_ROLE' value '{}', new users are viewers",
+          role
+        );
+        Role::Viewer
+      }),
+      None => Role::Viewer,
+    };
+
+    Some(OidcConfig {
+      issuer: var("OIDC_ISSUER")?.trim_end_matches('/').to_string(),
+      client_id: var("OIDC_CLIENT_ID")?,
+      client_secret: var("OIDC_CLIENT_SECRET")?,
+      redirect_uri: var("OIDC_REDIRECT_URI")?,
+      scopes: var("OIDC_SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+      default_role,
+      post_login_redirect: var("OIDC_POST_LOGIN_REDIRECT").unwrap_or_else(|| "/".to_string()),
+    })
+  }
+
+  /// Returns the configuration, or 404 if OIDC login isn't configured
+  pub fn required() -> Result<OidcConfig, ApiError> {
+    OidcConfig::from_env().ok_or_else(|| ApiError::not_found("Error. OIDC login isn't configured."))
+  }
+}
+
+/// Endpoints of the provider, from its discovery document
+#[derive(Debug, Deserialize)]
+struct Discovery {
+  authorization_endpoint: String,
+  token_endpoint: String,
+  userinfo_endpoint: String,
+}
+
+/// Response of the token endpoint
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+  access_token: String,
+}
+
+/// Claims about the user from the userinfo endpoint
+#[derive(Debug, Deserialize)]
+pub struct UserInfo {
+  /// Unique ID of the user at the provider
+  pub sub: String,
+  pub email: Option<String>,
+  #[serde(default)]
+  pub email_verified: bool,
+  pub name: Option<String>,
+}
+
+impl UserInfo {
+  /// Returns the email of the user if the provider verified it, with 403 otherwise
+  pub fn verified_email(&self) -> Result<&str, ApiError> {
+    match (&self.email, self.email_verified) {
+      (Some(email), true) => Ok(email),
+      _ => Err(ApiError::new(
+        Status::Forbidden,
+        "Error. The provider didn't verify the email of the user.",
+      )),
+    }
+  }
+}
+
+/// Returns a random value for the `state` parameter
+pub fn new_state() -> String {
+  rand::random::<[u8; 16]>()
+    .iter()
+    .map(|x| format!("{:02x}", x))
+    .collect()
+}
+
+fn client() -> reqwest::Client {
+  reqwest::Client::builder()
+    .timeout(REQUEST_TIMEOUT)
+    .build()
+    .unwrap_or_default()
+}
+
+/// Returns the 502 error of a failed request to the provider
+fn provider_error(what: &str, e: impl std::fmt::Debug) -> ApiError {
+  log!("Error trying to {} of the OIDC provider. Error: {:?}", what, e);
+  ApiError::new(
+    Status::BadGateway,
+    format!("Error. Unable to {} of the OIDC provider.", what),
+  )
+}
+
+/// GETs a JSON document from the provider, authorized with `access_token` if given
+async fn get_json<T: for<'de> Deserialize<'de>>(
+  url: &str,
+  access_token: Option<&str>,
+  what: &str,
+) -> Result<T, ApiError> {
+  let mut request = client().get(url);
+  if let Some(access_token) = access_token {
+    request = request.bearer_auth(access_token);
+  }
+
+  let response = request.send().await.map_err(|e| provider_error(what, e))?;
+  let response = response.error_for_status().map_err(|e| provider_error(what, e))?;
+  let body = response.text().await.map_err(|e| provider_error(what, e))?;
+
+  serde_json::from_str(&body).map_err(|e| provider_error(what, e))
+}
+
+async fn discover(config: &OidcConfig) -> Result<Discovery, ApiError> {
+  let url = format!("{}/.well-known/openid-configuration", config.issuer);
+  get_json(&url, None, "get the discovery document").await
+}
+
+/// Returns the URL of the provider users are redirected to for logging in, carrying `state`
+pub async fn authorization_url(config: &OidcConfig, state: &str) -> Result<String, ApiError> {
+  let discovery = discover(config).await?;
+  let mut url =
+    Url::parse(&discovery.authorization_endpoint).map_err(|e| provider_error("parse the authorization endpoint", e))?;
+
+  url
+    .query_pairs_mut()
+    .append_pair("response_type", "code")
+    .append_pair("client_id", &config.client_id)
+    .append_pair("redirect_uri", &config.redirect_uri)
+    .append_pair("scope", &config.scopes)
+    .append_pair("state", state);
+
+  Ok(url.to_string())
+}
+
+/// Exchanges the code the provider redirected back with for the claims about the user
+pub async fn user_info(config: &OidcConfig, code: &str) -> Result<UserInfo, ApiError> {
+  let discovery = discover(config).await?;
+  let what = "exchange the code at the token endpoint";
+
+  let response = client()
+    .post(&discovery.token_endpoint)
+    .form(&[
+      ("grant_type", "authorization_code"),
+      ("code", code),
+      ("redirect_uri", &config.redirect_uri),
+      ("client_id", &config.client_id),
+      ("client_secret", &config.client_secret),
+    ])
+    .send()
+    .await
+    .map_err(|e| provider_error(what, e))?;
+  let response = response.error_for_status().map_err(|e| provider_error(what, e))?;
+  let body = response.text().await.map_err(|e| provider_error(what, e))?;
+  let token: TokenResponse = serde_json::from_str(&body).map_err(|e| provider_error(what, e))?;
+
+  get_json(
+    &discovery.userinfo_endpoint,
+    Some(&token.access_token),
+    "get the user from the userinfo endpoint",
+  )
+  .await
+}
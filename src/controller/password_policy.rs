@@ -0,0 +1,143 @@
+//! Password policy, rejecting weak and breached passwords when users are created or change their password
+//!
+//! The rules apply to the `hash` clients send, which is the password itself for clients that don't hash it first, so
+//! they're all off by default except a minimum length that hashes always pass. Breached passwords are looked up in
+//! Have I Been Pwned with its k-anonymity range API: only the first 5 characters of the password's SHA-1 hash are sent
+
+use std::time::Duration;
+
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use sha1::{Digest, Sha1};
+
+use crate::controller::error::ApiError;
+use crate::log;
+
+/// Shortest password allowed when `PASSWORD_MIN_LENGTH` isn't set
+const DEFAULT_MIN_LENGTH: usize = 8;
+
+/// Range API of Have I Been Pwned, followed by the first 5 characters of a SHA-1 hash
+const BREACH_RANGE_URL: &str = "https://api.pwnedpasswords.com/range/";
+
+/// How long breach lookups can take before the password is accepted unchecked
+const BREACH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Rules passwords must follow
+pub struct PasswordPolicy {
+  /// Shortest password allowed, in characters
+  min_length: usize,
+  /// Character classes (lowercase, uppercase, digits and symbols) a password must mix
+  min_classes: usize,
+  /// If passwords found in breaches are rejected
+  breach_check: bool,
+  client: reqwest::Client,
+}
+
+impl PasswordPolicy {
+  /// Constructs a `PasswordPolicy` configured from `PASSWORD_MIN_LENGTH`, `PASSWORD_MIN_CHARACTER_CLASSES` (0 to 4)
+  /// and `PASSWORD_BREACH_CHECK`
+  pub fn from_env() -> PasswordPolicy {
+    let min_length = match dotenv::var("PASSWORD_MIN_LENGTH").map(|x| x.trim().parse::<usize>()) {
+      Ok(Ok(value)) => value,
+      _ => DEFAULT_MIN_LENGTH,
+    };
+    let min_classes = match dotenv::var("PASSWORD_MIN_CHARACTER_CLASSES").map(|x| x.trim().parse::<usize>()) {
+      Ok(Ok(value)) => value.min(4),
+      _ => 0,
+    };
+    let breach_check = dotenv::var("PASSWORD_BREACH_CHECK")
+      .map(|x| x.trim().eq_ignore_ascii_case("true"))
+      .unwrap_or(false);
+
+    PasswordPolicy {
+      min_length,
+      min_classes,
+      breach_check,
+      client: reqwest::Client::builder()
+        .timeout(BREACH_TIMEOUT)
+        .build()
+        .unwrap_or_default(),
+    }
+  }
+
+  /// Checks `password` follows the policy, with 422 naming the rule it broke in `details.rule` otherwise
+  pub async fn check(&self, password: &str) -> Result<(), ApiError> {
+    let length = password.chars().count();
+    if length < self.min_length {
+      return Err(violation(
+        json!({ "rule": "min_length", "min_length": self.min_length }),
+        format!("Error. Passwords must be at least {} characters long.", self.min_length),
+      ));
+    }
+
+    let classes = [
+      password.chars().any(|x| x.is_lowercase()),
+      password.chars().any(|x| x.is_uppercase()),
+      password.chars().any(|x| x.is_ascii_digit()),
+      password.chars().any(|x| !x.is_alphanumeric()),
+    ]
+    .iter()
+    .filter(|x| **x)
+    .count();
+    if classes < self.min_classes {
+      return Err(violation(
+        json!({ "rule": "character_classes", "min_classes": self.min_classes }),
+        format!(
+          "Error. Passwords must mix at least {} of lowercase letters, uppercase letters, digits and symbols.",
+          self.min_classes
+        ),
+      ));
+    }
+
+    if self.breach_check && self.is_breached(password).await {
+      return Err(violation(
+        json!({ "rule": "breached" }),
+        "Error. The password was found in a data breach, choose another one.",
+      ));
+    }
+
+    Ok(())
+  }
+
+  /// If `password` is in Have I Been Pwned. Lookups that fail are logged and count as not breached, so an outage of
+  /// the API doesn't stop users from being created
+  async fn is_breached(&self, password: &str) -> bool {
+    let hash: String = Sha1::digest(password.as_bytes())
+      .iter()
+      .map(|x| format!("{:02X}", x))
+      .collect();
+    let (prefix, suffix) = hash.split_at(5);
+
+    let response = self
+      .client
+      .get(format!("{}{}", BREACH_RANGE_URL, prefix))
+      .header("Add-Padding", "true")
+      .send()
+      .await
+      .and_then(|x| x.error_for_status());
+    let body = match response {
+      Ok(response) => response.text().await,
+      Err(e) => Err(e),
+    };
+
+    match body {
+      // Every line is the suffix of a breached hash and how often it was seen, padding lines are seen 0 times
+      Ok(body) => body.lines().any(|line| match line.trim().split_once(':') {
+        Some((x, count)) => x.eq_ignore_ascii_case(suffix) && count.trim() != "0",
+        None => false,
+      }),
+      Err(e) => {
+        log!(
+          "Error looking up a password in Have I Been Pwned, accepting it. Error: {:?}",
+          e
+        );
+        false
+      }
+    }
+  }
+}
+
+/// Returns the 422 error of a password breaking a rule, named in `details.rule`
+fn violation(details: Value, message: impl Into<String>) -> ApiError {
+  ApiError::new(Status::UnprocessableEntity, message).with_details(details)
+}
@@ -0,0 +1,221 @@
+//! Sandboxed execution of product WASM plugins implementing custom evaluation logic
+//!
+//! A plugin is a WASM module without any imports exporting:
+//! * `memory`                             - its linear memory
+//! * `alloc(len: i32) -> i32`             - reserves `len` bytes of `memory`, returning their offset
+//! * `evaluate(ptr: i32, len: i32) -> i32` - evaluates the UTF-8 JSON `{"flag": ..., "context": ...}` written at `ptr`,
+//!   returning `1` to enable the flag, `0` to disable it, and anything else to leave it to targeting rules
+//!
+//! Every evaluation runs in a fresh instance with limited fuel and memory, on the blocking thread pool so slow plugins
+//! don't hold up other requests. A plugin that traps, runs out of fuel, or otherwise misbehaves has no verdict, so the
+//! flag is evaluated as if the product had no plugin
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use evaluation::{EvaluationContext, EvaluationResult, Verdict};
+use rocket::serde::json::serde_json;
+use serde::Serialize;
+use wasmi::{Config, Engine, ExternType, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, ValType};
+
+use crate::controller::database::ConnectionManager;
+use crate::log;
+use crate::model::flag::{FeatureFlag, SpecSafeFeatureFlag};
+use crate::model::product::Product;
+
+/// Largest accepted WASM module, in bytes
+pub const MAX_PLUGIN_SIZE: usize = 1024 * 1024;
+
+/// Fuel (roughly, instructions) a plugin may use per evaluation
+const PLUGIN_FUEL: u64 = 10_000_000;
+
+/// Largest linear memory a plugin may grow to, in bytes
+const PLUGIN_MEMORY: usize = 16 * 1024 * 1024;
+
+/// Compiled plugins kept around before the cache is cleared
+const MAX_CACHED_PLUGINS: usize = 64;
+
+/// Compiles and caches plugins, managed by Rocket
+pub struct PluginHost {
+  engine: Engine,
+  /// Compiled modules by plugin ID, plugins never change once uploaded so entries never go stale
+  modules: Mutex<HashMap<String, Module>>,
+}
+
+impl PluginHost {
+  pub fn new() -> PluginHost {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+
+    PluginHost {
+      engine: Engine::new(&config),
+      modules: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Compiles a WASM module, checking it has no imports and exports what plugins must export
+  pub fn compile(&self, bytes: &[u8]) -> Result<Module, String> {
+    let module = Module::new(&self.engine, bytes).map_err(|e| format!("Invalid WASM module: {}", e))?;
+
+    if let Some(import) = module.imports().next() {
+      return Err(format!(
+        "Plugins can't import anything, found import '{}.{}'",
+        import.module(),
+        import.name()
+      ));
+    }
+
+    let exports: HashMap<&str, ExternType> = module.exports().map(|x| (x.name(), x.ty().clone())).collect();
+    let is_function = |name: &str, params: &[ValType], results: &[ValType]| match exports.get(name) {
+      Some(ExternType::Func(ty)) => ty.params() == params && ty.results() == results,
+      _ => false,
+    };
+
+    if !matches!(exports.get("memory"), Some(ExternType::Memory(_))) {
+      return Err("Plugins must export their 'memory'".to_string());
+    }
+    if !is_function("alloc", &[ValType::I32], &[ValType::I32]) {
+      return Err("Plugins must export 'alloc(len: i32) -> i32'".to_string());
+    }
+    if !is_function("evaluate", &[ValType::I32, ValType::I32], &[ValType::I32]) {
+      return Err("Plugins must export 'evaluate(ptr: i32, len: i32) -> i32'".to_string());
+    }
+
+    Ok(module)
+  }
+
+  /// Returns the compiled plugin of a product, `None` if it doesn't have one or it can't be loaded
+  pub async fn load(&self, product: &Product, database_connection: &ConnectionManager) -> Option<LoadedPlugin> {
+    let plugin_id = product.plugin_id.as_deref()?;
+
+    if let Some(module) = self.modules.lock().unwrap_or_else(|e| e.into_inner()).get(plugin_id) {
+      return Some(self.loaded(module.clone()));
+    }
+
+    let plugin = database_connection.get_plugin(plugin_id).await.ok().flatten()?;
+    let module = match self.compile(&plugin.module.bytes) {
+      Ok(module) => module,
+      Err(e) => {
+        log!("Error compiling plugin '{}'. Error {}", plugin_id, e);
+        return None;
+      }
+    };
+
+    let mut modules = self.modules.lock().unwrap_or_else(|e| e.into_inner());
+    if modules.len() >= MAX_CACHED_PLUGINS {
+      modules.clear();
+    }
+    modules.insert(plugin_id.to_string(), module.clone());
+
+    Some(self.loaded(module))
+  }
+
+  fn loaded(&self, module: Module) -> LoadedPlugin {
+    LoadedPlugin {
+      engine: self.engine.clone(),
+      module,
+    }
+  }
+}
+
+/// Compiled plugin ready to evaluate flags
+#[derive(Clone)]
+pub struct LoadedPlugin {
+  engine: Engine,
+  module: Module,
+}
+
+impl LoadedPlugin {
+  /// Evaluates a flag with the plugin's verdict, on the blocking thread pool since the plugin may run for up to
+  /// `PLUGIN_FUEL` instructions
+  pub async fn evaluate(&self, flag: &FeatureFlag, context: &EvaluationContext) -> EvaluationResult {
+    let plugin = self.clone();
+    let (bound_flag, bound_context) = (flag.clone(), context.clone());
+    let evaluation =
+      tokio::task::spawn_blocking(move || bound_flag.evaluate_with(&bound_context, &plugin.bind(&bound_flag)));
+
+    match evaluation.await {
+      Ok(result) => result,
+      Err(e) => {
+        log!("Error running plugin for flag '{}'. Error {}", flag.name, e);
+        flag.evaluate(context)
+      }
+    }
+  }
+
+  /// Returns the verdict of the plugin for a flag, to pass to `FeatureFlag::evaluate_with`
+  pub fn bind(&self, flag: &FeatureFlag) -> BoundPlugin<'_> {
+    BoundPlugin {
+      plugin: self,
+      flag: flag.get_spec_safe_feature_flag(),
+    }
+  }
+
+  /// Runs `evaluate` on `input` in a fresh instance, returning what it returned
+  fn call(&self, input: &[u8]) -> Result<i32, String> {
+    let limits = StoreLimitsBuilder::new().memory_size(PLUGIN_MEMORY).build();
+    let mut store: Store<StoreLimits> = Store::new(&self.engine, limits);
+    store.limiter(|limits| limits);
+    store.set_fuel(PLUGIN_FUEL).map_err(|e| e.to_string())?;
+
+    let instance = Linker::<StoreLimits>::new(&self.engine)
+      .instantiate_and_start(&mut store, &self.module)
+      .map_err(|e| e.to_string())?;
+
+    let memory = instance
+      .get_memory(&store, "memory")
+      .ok_or_else(|| "Missing 'memory' export".to_string())?;
+    let alloc = instance
+      .get_typed_func::<i32, i32>(&store, "alloc")
+      .map_err(|e| e.to_string())?;
+    let evaluate = instance
+      .get_typed_func::<(i32, i32), i32>(&store, "evaluate")
+      .map_err(|e| e.to_string())?;
+
+    let len = i32::try_from(input.len()).map_err(|e| e.to_string())?;
+    let ptr = alloc.call(&mut store, len).map_err(|e| e.to_string())?;
+    let offset = usize::try_from(ptr).map_err(|e| e.to_string())?;
+    memory.write(&mut store, offset, input).map_err(|e| e.to_string())?;
+
+    evaluate.call(&mut store, (ptr, len)).map_err(|e| e.to_string())
+  }
+}
+
+/// Plugin bound to the flag it evaluates
+pub struct BoundPlugin<'a> {
+  plugin: &'a LoadedPlugin,
+  flag: SpecSafeFeatureFlag,
+}
+
+/// Input handed to the plugin's `evaluate`
+#[derive(Serialize)]
+struct PluginInput<'a> {
+  flag: &'a SpecSafeFeatureFlag,
+  context: &'a EvaluationContext,
+}
+
+impl Verdict for BoundPlugin<'_> {
+  fn verdict(&self, context: &EvaluationContext) -> Option<bool> {
+    let input = PluginInput {
+      flag: &self.flag,
+      context,
+    };
+    let input = match serde_json::to_vec(&input) {
+      Ok(input) => input,
+      Err(e) => {
+        log!("Error serializing plugin input. Error {:?}", e);
+        return None;
+      }
+    };
+
+    match self.plugin.call(&input) {
+      Ok(1) => Some(true),
+      Ok(0) => Some(false),
+      Ok(_) => None,
+      Err(e) => {
+        log!("Error running plugin for flag '{}'. Error {}", self.flag.oid, e);
+        None
+      }
+    }
+  }
+}
@@ -0,0 +1,226 @@
+//! Token bucket rate limiting of logins and writes, so a misbehaving client can't hammer the database
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Method};
+use rocket::{Data, Request, Response};
+
+use crate::controller::authentication::{AUTH_TOKEN, USER_ID};
+use crate::controller::versioning::API_V1;
+use crate::log;
+
+/// Path requests are rewritten to when they are rate limited
+pub const RATE_LIMITED_PATH: &str = "/__rate_limited";
+
+/// Buckets tracked before full buckets are forgotten, bounding the memory taken by clients that went away
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+/// Size and refill rate of a token bucket
+#[derive(Clone, Copy, Debug)]
+pub struct Limit {
+  /// Requests that can be made in a burst
+  pub burst: f64,
+  /// Requests added back to the bucket every second
+  pub per_second: f64,
+}
+
+impl Limit {
+  /// Reads a limit from `<prefix>_BURST` and `<prefix>_PER_SECOND`, `None` if either isn't set
+  ///
+  /// The burst is raised to a single request, a bucket that can never be taken from is a misconfiguration
+  fn from_env(prefix: &str) -> Option<Limit> {
+    let burst: f64 = dotenv::var(format!("{}_BURST", prefix)).ok()?.parse().ok()?;
+    let per_second: f64 = dotenv::var(format!("{}_PER_SECOND", prefix)).ok()?.parse().ok()?;
+
+    if per_second <= 0.0 {
+      log!("Ignoring '{}_PER_SECOND', it must be positive", prefix);
+      return None;
+    }
+
+    Some(Limit {
+      burst: burst.max(1.0),
+      per_second,
+    })
+  }
+}
+
+/// What a bucket limits
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum BucketKey {
+  /// Logins from a client IP
+  Login(IpAddr),
+  /// Writes from a client IP
+  Ip(IpAddr),
+  /// Writes made with a user's auth token
+  Token(String),
+}
+
+/// Requests left in a bucket as of `updated`
+struct Bucket {
+  tokens: f64,
+  updated: Instant,
+}
+
+/// Seconds a rate limited request should wait before retrying, stored in the request local cache
+struct RateLimited(Option<u64>);
+
+/// Fairing limiting logins per client IP, and writes per client IP and per auth token
+///
+/// Configured from the environment, each limit is disabled unless both of its variables are set:
+/// * **RATE_LIMIT_LOGIN_BURST** / **RATE_LIMIT_LOGIN_PER_SECOND** - logins (`/login`) from an IP
+/// * **RATE_LIMIT_IP_BURST** / **RATE_LIMIT_IP_PER_SECOND**       - writes from an IP
+/// * **RATE_LIMIT_TOKEN_BURST** / **RATE_LIMIT_TOKEN_PER_SECOND** - writes made with an auth token
+///
+/// Writes are requests that aren't `GET`, `HEAD` or `OPTIONS`, except flag checks (`/check/batch`). Limited requests
+/// are answered 429 with a `Retry-After` header of the seconds until the bucket has a request again
+pub struct RateLimiter {
+  login: Option<Limit>,
+  ip: Option<Limit>,
+  token: Option<Limit>,
+  buckets: Mutex<HashMap<BucketKey, Bucket>>,
+}
+
+impl RateLimiter {
+  /// Constructs a `RateLimiter` from the environment
+  pub fn from_env() -> RateLimiter {
+    RateLimiter {
+      login: Limit::from_env("RATE_LIMIT_LOGIN"),
+      ip: Limit::from_env("RATE_LIMIT_IP"),
+      token: Limit::from_env("RATE_LIMIT_TOKEN"),
+      buckets: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Limit of the buckets of `key`'s kind
+  fn limit_of(&self, key: &BucketKey) -> Option<Limit> {
+    match key {
+      BucketKey::Login(_) => self.login,
+      BucketKey::Ip(_) => self.ip,
+      BucketKey::Token(_) => self.token,
+    }
+  }
+
+  /// Returns the buckets a request takes from
+  fn buckets_for(&self, request: &Request<'_>) -> Vec<BucketKey> {
+    let path = request.uri().path();
+    let path = path.as_str();
+    let path = path.strip_prefix(API_V1).unwrap_or(path);
+    let ip = request.client_ip();
+
+    if path == "/login" || path.starts_with("/login/") {
+      return ip.map(BucketKey::Login).into_iter().collect();
+    }
+
+    let write = !matches!(request.method(), Method::Get | Method::Head | Method::Options);
+    if !write || path.starts_with("/check") {
+      return vec![];
+    }
+
+    let mut buckets: Vec<BucketKey> = ip.map(BucketKey::Ip).into_iter().collect();
+
+    // Tokens aren't unique between users yet, so buckets are kept per user and token
+    let cookies = request.cookies();
+    if let (Some(user_id), Some(auth_token)) = (cookies.get_private(USER_ID), cookies.get_private(AUTH_TOKEN)) {
+      buckets.push(BucketKey::Token(format!("{}:{}", user_id.value(), auth_token.value())));
+    }
+
+    buckets
+  }
+
+  /// Takes a request from every bucket of `keys` that has a limit
+  ///
+  /// Returns `None` if the request is allowed, otherwise the seconds until every bucket has a request again. Nothing
+  /// is taken from any bucket when one of them is empty
+  fn take(&self, keys: Vec<BucketKey>) -> Option<u64> {
+    let keys: Vec<(BucketKey, Limit)> = keys
+      .into_iter()
+      .filter_map(|key| self.limit_of(&key).map(|limit| (key, limit)))
+      .collect();
+    if keys.is_empty() {
+      return None;
+    }
+
+    let mut buckets = match self.buckets.lock() {
+      Ok(value) => value,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+
+    let now = Instant::now();
+    let mut wait: f64 = 0.0;
+
+    for (key, limit) in &keys {
+      let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+        tokens: limit.burst,
+        updated: now,
+      });
+      bucket.tokens = bucket.refilled(*limit, now);
+      bucket.updated = now;
+
+      if bucket.tokens < 1.0 {
+        wait = wait.max((1.0 - bucket.tokens) / limit.per_second);
+      }
+    }
+
+    let limited = wait > 0.0;
+    if !limited {
+      for (key, _) in &keys {
+        if let Some(bucket) = buckets.get_mut(key) {
+          bucket.tokens -= 1.0;
+        }
+      }
+    }
+
+    // Full buckets behave the same as missing ones, so they can be forgotten
+    if buckets.len() > MAX_TRACKED_BUCKETS {
+      buckets.retain(|key, bucket| match self.limit_of(key) {
+        Some(limit) => bucket.refilled(limit, now) < limit.burst,
+        None => false,
+      });
+    }
+
+    match limited {
+      true => Some(wait.ceil() as u64),
+      false => None,
+    }
+  }
+}
+
+impl Bucket {
+  /// Requests in the bucket at `now`, after refilling it at the rate of `limit`
+  fn refilled(&self, limit: Limit, now: Instant) -> f64 {
+    let elapsed = now.duration_since(self.updated).as_secs_f64();
+    (self.tokens + elapsed * limit.per_second).min(limit.burst)
+  }
+}
+
+#[rocket::async_trait]
+impl Fairing for RateLimiter {
+  fn info(&self) -> Info {
+    Info {
+      name: "Rate Limiter",
+      kind: Kind::Request | Kind::Response,
+    }
+  }
+
+  async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+    let retry_after = self.take(self.buckets_for(request));
+    request.local_cache(|| RateLimited(retry_after));
+
+    // Fairings can't respond directly, so limited requests are routed to the rate limited route instead
+    if retry_after.is_some() {
+      request.set_method(Method::Get);
+      request.set_uri(Origin::parse(RATE_LIMITED_PATH).expect("valid rate limited path"));
+    }
+  }
+
+  async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+    if let Some(retry_after) = request.local_cache(|| RateLimited(None)).0 {
+      response.set_header(Header::new("Retry-After", retry_after.to_string()));
+    }
+  }
+}
@@ -0,0 +1,573 @@
+//! Request body data structures for endpoints
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use evaluation::{rule, EvaluationContext, FlagDefinition, Rule};
+use rocket::serde::json::serde_json::{self, Map};
+use rocket::serde::json::Value;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+
+use crate::controller::case::to_camel_case;
+use crate::controller::events::{EventKind, UserIdentifiers};
+use crate::controller::validation::{self, Validate, Violations};
+use crate::model::api_key::ApiKeyKind;
+use crate::model::comment::MAX_COMMENT_LENGTH;
+use crate::model::flag::{FeatureFlag, FeatureFlagBuilder, ReleaseType};
+use crate::model::schedule::FlagChange;
+use crate::model::service_account::ServiceScope;
+use crate::model::settings::SettingsOverrides;
+use crate::model::user::{AccountType, Permission, Role, UserStatus};
+
+/// Single flag check inside of a `/check/batch` request
+#[derive(Deserialize, JsonSchema)]
+pub struct CheckRequest {
+  /// Unique ID of the product that the feature flag belongs to
+  #[serde(alias = "productId")]
+  pub product_id: String,
+  /// Name of the feature flag
+  pub feature: String,
+  /// *(optional)* unique ID of the user to evaluate the flag with
+  pub user: Option<String>,
+  /// *(optional)* anonymous key (e.g. a device or session ID) to bucket percentage releases with when there's no user
+  #[serde(default)]
+  pub key: Option<String>,
+  /// *(optional)* attributes used by targeting rules
+  #[serde(default)]
+  pub attributes: HashMap<String, String>,
+}
+
+impl Validate for CheckRequest {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check(
+      !self.product_id.trim().is_empty(),
+      "product_id",
+      "Product IDs can't be empty.",
+    );
+    violations.check(!self.feature.trim().is_empty(), "feature", "Flag names can't be empty.");
+  }
+}
+
+/// Body of a `/flags/<flag_id>/preview` request
+#[derive(Deserialize, JsonSchema)]
+pub struct PreviewRequest {
+  /// Proposed definition of the flag, its name and bucket salt are ignored so percentage buckets match the stored flag
+  pub flag: FlagDefinition,
+  /// Sample contexts (optional user and attributes) to evaluate the flag with
+  pub contexts: Vec<EvaluationContext>,
+}
+
+impl Validate for PreviewRequest {
+  fn validate(&self, violations: &mut Violations) {
+    violations.nested("flag.release_type", &self.flag.release_type);
+    violations.nested("flag.rules", &self.flag.rules);
+    violations.nested("contexts", &self.contexts);
+  }
+}
+
+/// Body of a `/flags/<flag_id>/scheduled-changes` request
+#[derive(Deserialize, JsonSchema)]
+pub struct ScheduleChangeRequest {
+  /// Change to apply to the flag
+  pub change: FlagChange,
+  /// When to apply the change (e.g. `2024-06-01T09:00:00Z`)
+  pub at: DateTime<Utc>,
+}
+
+impl Validate for ScheduleChangeRequest {
+  fn validate(&self, violations: &mut Violations) {
+    violations.nested("change", &self.change);
+    violations.check(self.at > Utc::now(), "at", "Changes must be scheduled in the future.");
+  }
+}
+
+/// Body of a `/flags/<flag_id>/comments` request
+#[derive(Deserialize, JsonSchema)]
+pub struct CommentRequest {
+  /// Text of the comment
+  pub body: String,
+}
+
+impl Validate for CommentRequest {
+  fn validate(&self, violations: &mut Violations) {
+    let body = self.body.trim();
+    violations.check(!body.is_empty(), "body", "Comments can't be empty.");
+    violations.check(
+      body.chars().count() <= MAX_COMMENT_LENGTH,
+      "body",
+      format!("Comments can't be longer than {} characters.", MAX_COMMENT_LENGTH),
+    );
+  }
+}
+
+/// Body of a `/update/overrides/<product_id>/<feature>` request
+#[derive(Deserialize, JsonSchema)]
+pub struct OverridesRequest {
+  /// Users (e.g. QA accounts) always evaluating as enabled, regardless of targeting rules and the release type
+  #[serde(default, alias = "alwaysInclude")]
+  pub always_include: Vec<String>,
+  /// Users always evaluating as disabled, regardless of targeting rules and the release type
+  #[serde(default, alias = "alwaysExclude")]
+  pub always_exclude: Vec<String>,
+}
+
+impl Validate for OverridesRequest {
+  fn validate(&self, violations: &mut Violations) {
+    violations.nested("always_include", &self.always_include);
+    violations.nested("always_exclude", &self.always_exclude);
+    check_overrides(violations, &self.always_include, &self.always_exclude);
+  }
+}
+
+/// Checks no user is both always included and always excluded
+fn check_overrides(violations: &mut Violations, always_include: &[String], always_exclude: &[String]) {
+  for user in always_include.iter().filter(|x| always_exclude.contains(x)) {
+    violations.add(
+      "always_exclude",
+      format!("User '{}' can't be both always included and excluded.", user),
+    );
+  }
+}
+
+/// Body of a `PUT /products/<product_id>` request
+#[derive(Deserialize, JsonSchema)]
+pub struct ProductUpdateRequest {
+  /// New name of the product
+  pub name: String,
+  /// If evaluations with users unknown to the product should be rejected
+  #[serde(default, alias = "strictMode")]
+  pub strict_mode: bool,
+}
+
+impl Validate for ProductUpdateRequest {
+  fn validate(&self, violations: &mut Violations) {
+    validation::check_product_name(violations, "name", &self.name);
+  }
+}
+
+/// Body of a `PUT /users/<user_id>` request
+#[derive(Deserialize, JsonSchema)]
+pub struct UserUpdateRequest {
+  /// New name of the user
+  pub name: String,
+  /// New email of the user
+  pub email: String,
+  /// *(optional)* new type of account, only developers can change it
+  #[serde(default, alias = "accountType")]
+  pub account_type: Option<AccountType>,
+  /// *(optional)* new role of the user, changing it requires the `users:admin` permission
+  #[serde(default)]
+  pub role: Option<Role>,
+}
+
+impl Validate for UserUpdateRequest {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check(!self.name.trim().is_empty(), "name", "User names can't be empty.");
+    violations.check(self.email.contains('@'), "email", "Emails must contain an '@'.");
+  }
+}
+
+/// Body of a `POST /setup` request
+#[derive(Deserialize, JsonSchema)]
+pub struct SetupRequest {
+  /// Name of the first user
+  pub name: String,
+  /// Email of the first user
+  pub email: String,
+  /// Hashed password of the first user
+  pub hash: String,
+  /// Value of `BOOTSTRAP_TOKEN`, needed when it's set
+  #[serde(default, alias = "bootstrapToken")]
+  pub bootstrap_token: Option<String>,
+}
+
+impl Validate for SetupRequest {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check(!self.name.trim().is_empty(), "name", "User names can't be empty.");
+    violations.check(self.email.contains('@'), "email", "Emails must contain an '@'.");
+    violations.check(!self.hash.is_empty(), "hash", "Passwords can't be empty.");
+  }
+}
+
+/// Body of a `PUT /users/<user_id>/status` request
+#[derive(Deserialize, JsonSchema)]
+pub struct UserStatusRequest {
+  /// New status of the user: `active`, `suspended` or `deactivated`
+  pub status: UserStatus,
+}
+
+impl Validate for UserStatusRequest {
+  // Unknown statuses are refused when the body is parsed, there's nothing more to check
+  fn validate(&self, _violations: &mut Violations) {}
+}
+
+/// Body of a `POST /login` request
+#[derive(Deserialize, JsonSchema)]
+pub struct LoginRequest {
+  /// Email of the user being logged in
+  pub email: String,
+  /// Hashed password of the user being logged in
+  pub hash: String,
+  /// Permissions the token of the session is limited to, on top of the user's role. Left out to not limit it
+  #[serde(default)]
+  pub scopes: Option<Vec<Permission>>,
+}
+
+impl Validate for LoginRequest {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check(!self.email.trim().is_empty(), "email", "Emails can't be empty.");
+    violations.check(!self.hash.is_empty(), "hash", "Passwords can't be empty.");
+    violations.check(
+      self.scopes.as_ref().is_none_or(|x| !x.is_empty()),
+      "scopes",
+      "Scopes can't be empty, leave them out to not limit the token.",
+    );
+  }
+}
+
+/// Form the SAML IdP POSTs to `/saml/acs`
+#[derive(FromForm, JsonSchema)]
+pub struct SamlPost {
+  /// Base64 encoded SAML response
+  #[field(name = "SAMLResponse")]
+  #[schemars(rename = "SAMLResponse")]
+  pub saml_response: String,
+  /// State given with the authentication request, the path to redirect to once logged in
+  #[field(name = "RelayState")]
+  #[schemars(rename = "RelayState")]
+  pub relay_state: Option<String>,
+}
+
+/// Body of a `POST /users/password` request
+#[derive(Deserialize, JsonSchema)]
+pub struct PasswordChangeRequest {
+  /// Current hashed password of the logged in user
+  #[serde(alias = "currentHash")]
+  pub current_hash: String,
+  /// New hashed password of the logged in user
+  #[serde(alias = "newHash")]
+  pub new_hash: String,
+}
+
+impl Validate for PasswordChangeRequest {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check(
+      !self.new_hash.trim().is_empty(),
+      "new_hash",
+      "Passwords can't be empty.",
+    );
+  }
+}
+
+/// Shortest secret webhooks can be signed with
+pub const MIN_WEBHOOK_SECRET_LENGTH: usize = 16;
+
+/// Body of `POST /webhooks` and `PUT /webhooks/<webhook_id>` requests
+#[derive(Deserialize, JsonSchema)]
+pub struct WebhookRequest {
+  /// URL changes are POSTed to
+  pub url: String,
+  /// Secret deliveries are signed with, at least 16 characters. Required for new webhooks, kept as is if left out of
+  /// updates
+  #[serde(default)]
+  pub secret: Option<String>,
+  /// *(optional)* unique ID of the product whose changes are delivered, every product's if left out
+  #[serde(default, alias = "productId")]
+  pub product_id: Option<String>,
+  /// *(optional)* kinds of changes delivered, every kind if left out
+  #[serde(default)]
+  pub events: Vec<EventKind>,
+  /// *(optional)* how user identifiers appear in deliveries, `hash` for destinations that must not receive personal
+  /// data
+  #[serde(default, alias = "userIdentifiers")]
+  pub user_identifiers: UserIdentifiers,
+  /// *(optional)* if changes are delivered, `true` by default
+  #[serde(default)]
+  pub enabled: Option<bool>,
+}
+
+impl Validate for WebhookRequest {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check(
+      matches!(reqwest::Url::parse(&self.url), Ok(url) if url.scheme() == "http" || url.scheme() == "https"),
+      "url",
+      format!("'{}' isn't an http(s) URL.", self.url),
+    );
+    if let Some(secret) = &self.secret {
+      violations.check(
+        secret.chars().count() >= MIN_WEBHOOK_SECRET_LENGTH,
+        "secret",
+        format!(
+          "Webhook secrets must be at least {} characters.",
+          MIN_WEBHOOK_SECRET_LENGTH
+        ),
+      );
+    }
+    violations.check(
+      self.user_identifiers.is_available(),
+      "user_identifiers",
+      "Hashing user identifiers needs ANONYMIZATION_SECRET to be set.",
+    );
+  }
+}
+
+/// Body of a `POST /products/<product_id>/api-keys` request
+#[derive(Deserialize, JsonSchema)]
+pub struct ApiKeyRequest {
+  /// Human readable name of the key (e.g. `ios-app`)
+  pub name: String,
+  /// Where the key is used, `server` keys can also get flag definitions from `/sdk` routes
+  pub kind: ApiKeyKind,
+  /// What the key can do with its product: `flags:read` (the default if left out) and/or `flags:write`
+  #[serde(default)]
+  pub scopes: Vec<Permission>,
+}
+
+impl Validate for ApiKeyRequest {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check(!self.name.trim().is_empty(), "name", "API key names can't be empty.");
+    violations.check(
+      self.name.chars().count() <= validation::MAX_NAME_LENGTH,
+      "name",
+      format!(
+        "API key names can't be longer than {} characters.",
+        validation::MAX_NAME_LENGTH
+      ),
+    );
+    violations.check(
+      !self.scopes.contains(&Permission::UsersAdmin),
+      "scopes",
+      "API keys can't be given the 'users:admin' scope.",
+    );
+    violations.check(
+      self.kind == ApiKeyKind::Server || !self.scopes.contains(&Permission::FlagsWrite),
+      "scopes",
+      "Only server keys can be given the 'flags:write' scope, client keys are assumed public.",
+    );
+  }
+}
+
+/// Body of a `POST /service-accounts` request
+#[derive(Deserialize, JsonSchema)]
+pub struct ServiceAccountRequest {
+  /// Human readable name of the account (e.g. `release-pipeline`)
+  pub name: String,
+  /// Unique IDs of the products the token gives access to
+  #[serde(alias = "productIds")]
+  pub product_ids: Vec<String>,
+  /// What the token can do with the products: `read`, `evaluate` and/or `write`
+  pub scopes: Vec<ServiceScope>,
+}
+
+impl Validate for ServiceAccountRequest {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check(
+      !self.name.trim().is_empty(),
+      "name",
+      "Service account names can't be empty.",
+    );
+    violations.check(
+      self.name.chars().count() <= validation::MAX_NAME_LENGTH,
+      "name",
+      format!(
+        "Service account names can't be longer than {} characters.",
+        validation::MAX_NAME_LENGTH
+      ),
+    );
+    violations.check(
+      !self.product_ids.is_empty(),
+      "product_ids",
+      "Service accounts need access to at least one product.",
+    );
+    violations.check(
+      !self.scopes.is_empty(),
+      "scopes",
+      "Service accounts need at least one scope.",
+    );
+  }
+}
+
+/// Body of a `PUT /flags/<product_id>/<name>` request, the full definition of a flag
+///
+/// Fields left out are reset to their defaults, except the ones the service manages (e.g. `disabled_for`)
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct FlagUpdateRequest {
+  /// Name of the feature flag
+  pub name: String,
+  /// Global enabled status of the flag (false trumps other statuses)
+  pub enabled: bool,
+  /// If client toggles are enabled
+  #[serde(alias = "clientToggle")]
+  pub client_toggle: bool,
+  /// Type of release and relevant data
+  #[serde(alias = "releaseType")]
+  pub release_type: ReleaseType,
+  /// Tags used to group flags (e.g. by squad or initiative)
+  #[serde(default)]
+  pub tags: Vec<String>,
+  /// Human readable description of what the flag gates
+  #[serde(default)]
+  pub description: String,
+  /// Owner of the flag (team or person responsible for it)
+  #[serde(default)]
+  pub owner: String,
+  /// Unique ID of the user responsible for maintaining (and eventually retiring) the flag
+  #[serde(default, alias = "maintainerId")]
+  pub maintainer_id: Option<String>,
+  /// Names of flags (in the same product) that must be enabled before this flag
+  #[serde(default)]
+  pub prerequisites: Vec<String>,
+  /// Targeting rules, checked by priority before the release type
+  #[serde(default)]
+  pub rules: Vec<Rule>,
+  /// Settings overriding the product and environment settings for the flag
+  #[serde(default)]
+  pub settings: SettingsOverrides,
+  /// If the flag is meant to stay (e.g. an operational kill switch) rather than be removed once rolled out
+  #[serde(default)]
+  pub permanent: bool,
+  /// JSON configuration (e.g. a button color or limits) returned by checks alongside an enabled status
+  #[serde(default)]
+  pub payload: Option<Value>,
+  /// Users (e.g. QA accounts) always evaluating as enabled, regardless of targeting rules and the release type
+  #[serde(default, alias = "alwaysInclude")]
+  pub always_include: Vec<String>,
+  /// Users always evaluating as disabled, regardless of targeting rules and the release type
+  #[serde(default, alias = "alwaysExclude")]
+  pub always_exclude: Vec<String>,
+}
+
+impl FlagUpdateRequest {
+  /// Returns the current definition of a flag
+  pub fn from_flag(flag: &FeatureFlag) -> FlagUpdateRequest {
+    FlagUpdateRequest {
+      name: flag.name.clone(),
+      enabled: flag.enabled,
+      client_toggle: flag.client_toggle,
+      release_type: flag.release_type.clone(),
+      tags: flag.tags.clone(),
+      description: flag.description.clone(),
+      owner: flag.owner.clone(),
+      maintainer_id: flag.maintainer_id.clone(),
+      prerequisites: flag.prerequisites.clone(),
+      rules: flag.rules.clone(),
+      settings: flag.settings.clone(),
+      permanent: flag.permanent,
+      payload: flag.payload.clone(),
+      always_include: flag.always_include.clone(),
+      always_exclude: flag.always_exclude.clone(),
+    }
+  }
+
+  /// Returns a builder of a new flag of the product of ID `product_id` with this definition
+  pub fn builder(self, product_id: &str) -> FeatureFlagBuilder {
+    let mut rules = self.rules;
+    rule::prioritize(&mut rules);
+
+    FeatureFlag::builder()
+      .with_name(&self.name)
+      .with_product_id(product_id)
+      .with_enabled(self.enabled)
+      .with_client_toggle(self.client_toggle)
+      .with_release_type(self.release_type)
+      .with_tags(self.tags)
+      .with_description(&self.description)
+      .with_owner(&self.owner)
+      .with_maintainer_id(self.maintainer_id.as_deref())
+      .with_prerequisites(self.prerequisites)
+      .with_rules(rules)
+      .with_settings(self.settings)
+      .with_permanent(self.permanent)
+      .with_payload(self.payload)
+      .with_always_include(self.always_include)
+      .with_always_exclude(self.always_exclude)
+  }
+
+  /// Applies a JSON Merge Patch (RFC 7396) to the current definition of a flag
+  ///
+  /// Top level fields may be snake_case or camelCase, unknown fields are rejected and fields set to `null` are reset to
+  /// their defaults
+  pub fn merge_patch(flag: &FeatureFlag, patch: Value) -> Result<FlagUpdateRequest, String> {
+    let patch = match patch {
+      Value::Object(patch) => patch,
+      _ => return Err("Error. Flag patches must be JSON objects.".to_string()),
+    };
+
+    let mut definition = match serde_json::to_value(FlagUpdateRequest::from_flag(flag)) {
+      Ok(Value::Object(definition)) => definition,
+      _ => return Err("Error. Unable to serialize the flag.".to_string()),
+    };
+
+    let mut normalized = Map::new();
+    for (key, value) in patch {
+      let field = match definition.keys().find(|x| **x == key || to_camel_case(x) == key) {
+        Some(field) => field.clone(),
+        None => return Err(format!("Error. Unknown field '{}'.", key)),
+      };
+      normalized.insert(field, value);
+    }
+
+    merge(&mut definition, normalized);
+
+    serde_json::from_value(Value::Object(definition)).map_err(|e| format!("Error. Invalid flag definition: {}.", e))
+  }
+
+  /// Replaces the definition of `flag`, leaving what the service manages (e.g. `disabled_for`) as is
+  pub fn apply(self, flag: &mut FeatureFlag) {
+    flag.name = self.name;
+    flag.enabled = self.enabled;
+    flag.client_toggle = self.client_toggle;
+    flag.release_type = self.release_type;
+    flag.tags = self.tags;
+    flag.description = self.description;
+    flag.owner = self.owner;
+    flag.maintainer_id = self.maintainer_id;
+    flag.prerequisites = self.prerequisites;
+    flag.rules = self.rules;
+    flag.settings = self.settings;
+    flag.permanent = self.permanent;
+    flag.payload = self.payload;
+    flag.always_include = self.always_include;
+    flag.always_exclude = self.always_exclude;
+  }
+}
+
+impl Validate for FlagUpdateRequest {
+  fn validate(&self, violations: &mut Violations) {
+    validation::check_flag_name(violations, "name", &self.name);
+    violations.nested("release_type", &self.release_type);
+    violations.nested("rules", &self.rules);
+    violations.nested("settings", &self.settings);
+    violations.nested("prerequisites", &self.prerequisites);
+    violations.check(
+      !self.prerequisites.contains(&self.name),
+      "prerequisites",
+      "A flag can't be its own prerequisite.",
+    );
+    check_overrides(violations, &self.always_include, &self.always_exclude);
+  }
+}
+
+/// Merges `patch` into `target` following JSON Merge Patch (RFC 7396)
+fn merge(target: &mut Map<String, Value>, patch: Map<String, Value>) {
+  for (key, value) in patch {
+    match value {
+      Value::Null => {
+        target.remove(&key);
+      }
+      Value::Object(value) => {
+        let entry = target.entry(key).or_insert(Value::Null);
+        if !entry.is_object() {
+          *entry = Value::Object(Map::new());
+        }
+        if let Value::Object(entry) = entry {
+          merge(entry, value);
+        }
+      }
+      value => {
+        target.insert(key, value);
+      }
+    }
+  }
+}
@@ -0,0 +1,128 @@
+//! Request IDs, tying together the log lines and response of a request for end-to-end tracing
+//!
+//! Every request gets an ID, forwarded from its `X-Request-Id` header when it has a valid one (e.g. set by a load
+//! balancer) or generated otherwise. The ID is returned in the `X-Request-Id` header of the response, and prefixes the
+//! log lines written with `log!` while the request is handled
+
+use std::fmt;
+
+use mongodb::bson::oid::ObjectId;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::route::{Handler, Outcome};
+use rocket::{Data, Request, Response, Route};
+
+/// Header request IDs are read from and returned in
+pub const REQUEST_ID: &str = "X-Request-Id";
+
+/// Longest forwarded request ID accepted, longer ones are replaced with a generated ID
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+rocket::tokio::task_local! {
+  /// ID of the request being handled by the current task
+  static CURRENT: String;
+}
+
+/// Prints a log line, prefixed with the ID of the request being handled if there is one
+#[macro_export]
+macro_rules! log {
+  ($($arg:tt)*) => {
+    $crate::controller::request_id::print_log($crate::controller::request_id::current().as_deref(), format_args!($($arg)*))
+  };
+}
+
+/// ID of a request, stored in the request local cache
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+  /// Returns the ID of `request`
+  pub fn of<'r>(request: &'r Request<'_>) -> &'r RequestId {
+    request.local_cache(|| RequestId(ObjectId::new().to_hex()))
+  }
+
+  /// Parses a forwarded request ID, `None` if it's empty, too long, or has characters other than ASCII letters,
+  /// digits, `-`, `_`, `.` and `:`
+  fn forwarded(value: &str) -> Option<RequestId> {
+    let valid = !value.is_empty()
+      && value.len() <= MAX_REQUEST_ID_LENGTH
+      && value
+        .chars()
+        .all(|x| x.is_ascii_alphanumeric() || matches!(x, '-' | '_' | '.' | ':'));
+
+    match valid {
+      true => Some(RequestId(value.to_string())),
+      false => None,
+    }
+  }
+}
+
+/// Returns the ID of the request being handled by the current task, `None` outside of route handlers
+pub fn current() -> Option<String> {
+  CURRENT.try_with(|x| x.clone()).ok()
+}
+
+/// Prints a log line, prefixed with `request_id` if there is one, use `log!` instead
+pub fn print_log(request_id: Option<&str>, args: fmt::Arguments<'_>) {
+  match request_id {
+    Some(request_id) => println!("[{}] {}", request_id, args),
+    None => println!("{}", args),
+  }
+}
+
+/// Fairing assigning every request an ID and returning it in the `X-Request-Id` header of the response
+///
+/// Error responses (4xx and 5xx) are logged with the ID, so they can be found from what the client received
+pub struct RequestIds;
+
+#[rocket::async_trait]
+impl Fairing for RequestIds {
+  fn info(&self) -> Info {
+    Info {
+      name: "Request IDs",
+      kind: Kind::Request | Kind::Response,
+    }
+  }
+
+  async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+    if let Some(request_id) = request.headers().get_one(REQUEST_ID).and_then(RequestId::forwarded) {
+      request.local_cache(|| request_id);
+    }
+  }
+
+  async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+    let request_id = RequestId::of(request);
+    response.set_header(Header::new(REQUEST_ID, request_id.0.clone()));
+
+    let status = response.status();
+    if status.code >= 400 {
+      print_log(
+        Some(&request_id.0),
+        format_args!("{} {} responded {}", request.method(), request.uri().path(), status),
+      );
+    }
+  }
+}
+
+/// Route handler running the handler it wraps with the ID of the request as the current request ID
+#[derive(Clone)]
+struct Traced(Box<dyn Handler>);
+
+#[rocket::async_trait]
+impl Handler for Traced {
+  async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+    let request_id = RequestId::of(request).0.clone();
+    CURRENT.scope(request_id, self.0.handle(request, data)).await
+  }
+}
+
+/// Wraps the handlers of `routes` so the log lines written while they handle a request carry its ID
+pub fn traced(routes: Vec<Route>) -> Vec<Route> {
+  routes
+    .into_iter()
+    .map(|mut route| {
+      route.handler = Box::new(Traced(route.handler));
+      route
+    })
+    .collect()
+}
@@ -1,27 +1,124 @@
 //! Response data structures for endpoints
 
-use rocket::serde::{json::Json, Serialize};
+use chrono::{DateTime, Utc};
+use evaluation::{EvaluationContext, EvaluationResult, FlagDefinition};
+use rocket::serde::json::Value;
+use rocket::serde::Serialize;
 use rocket_okapi::okapi::schemars::{self, JsonSchema};
 
+use crate::controller::plugin::LoadedPlugin;
+use crate::model::api_key::SpecSafeApiKey;
+use crate::model::audit::SpecSafeAuditEntry;
+use crate::model::flag::FeatureFlag;
+use crate::model::service_account::SpecSafeServiceAccount;
+use crate::model::user::{Permission, SpecSafeUser};
+
+/// Reason given when a strict mode product is evaluated with a user it doesn't know
+pub const UNKNOWN_USER: &str = "UNKNOWN_USER";
+
 /// Response from `/check/...` routes that will state if a flag is enabled or not
 #[derive(Serialize, JsonSchema)]
 pub struct FlagCheck {
   /// Status of the flag
   pub enabled: bool,
+  /// Why the flag has its status (e.g. `RULE_MATCH`, `OUT_OF_BUCKET`, or `UNKNOWN_USER` if it wasn't evaluated)
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub reason: Option<String>,
+  /// Variant of the flag served, if any
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub variant: Option<String>,
+  /// Index of the targeting rule that decided the status, if one did
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub rule_index: Option<usize>,
+  /// Configuration payload of the flag, only returned when the flag is enabled
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub payload: Option<Value>,
 }
 
 impl FlagCheck {
-  /// Creates a `FlagCheck` with an enabled status
-  pub async fn get_enabled() -> Option<Json<FlagCheck>> {
-    Some(Json(FlagCheck { enabled: true }))
+  /// Evaluates a flag with the context, attaching the flag's payload if it's enabled
+  ///
+  /// # Parameters
+  /// * **flag**    - Flag to evaluate
+  /// * **context** - Context (optional user and attributes) used to evaluate the flag with
+  /// * **plugin**  - *(optional)* plugin of the flag's product, consulted before targeting rules
+  pub async fn evaluate(flag: &FeatureFlag, context: &EvaluationContext, plugin: Option<&LoadedPlugin>) -> FlagCheck {
+    let result = match plugin {
+      Some(plugin) => plugin.evaluate(flag, context).await,
+      None => flag.evaluate(context),
+    };
+
+    FlagCheck::from_result(result, flag.payload.as_ref())
+  }
+
+  /// Creates a `FlagCheck` from the result of evaluating a flag
+  ///
+  /// # Parameters
+  /// * **result**  - Result of evaluating the flag
+  /// * **payload** - *(optional)* payload of the flag, only attached if the flag is enabled
+  pub fn from_result(result: EvaluationResult, payload: Option<&Value>) -> FlagCheck {
+    FlagCheck {
+      enabled: result.value,
+      reason: Some(result.reason.name().to_string()),
+      variant: result.variant,
+      rule_index: result.rule_index,
+      payload: if result.value { payload.cloned() } else { None },
+    }
   }
 
-  /// Creates a `FlagCheck` with an disabled status
-  pub async fn get_disabled() -> Option<Json<FlagCheck>> {
-    Some(Json(FlagCheck { enabled: false }))
+  /// Creates a disabled `FlagCheck` with the `UNKNOWN_USER` reason
+  pub fn unknown_user() -> FlagCheck {
+    FlagCheck {
+      enabled: false,
+      reason: Some(UNKNOWN_USER.to_string()),
+      variant: None,
+      rule_index: None,
+      payload: None,
+    }
   }
 }
 
+/// Evaluation ready definition of a flag in an `/sdk/snapshot/<product_id>` response
+#[derive(Serialize, JsonSchema)]
+pub struct SnapshotFlag {
+  /// Everything needed to evaluate the flag, percentage releases are bucketed with the flag's name as the salt
+  #[serde(flatten)]
+  pub definition: FlagDefinition,
+  /// Configuration payload returned alongside an enabled status
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub payload: Option<Value>,
+  /// When the flag was last changed, `None` if it predates timestamps being recorded
+  pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Response from `/sdk/snapshot/<product_id>`
+#[derive(Serialize, JsonSchema)]
+pub struct SdkSnapshot {
+  /// Unique ID of the product
+  pub product_id: String,
+  /// When the snapshot was taken, pass it as `since` to only get the flags changed after it
+  pub generated_at: DateTime<Utc>,
+  /// If the product has a plugin, which SDKs can't run. Its flags should be checked with `/check` instead
+  pub plugin: bool,
+  /// Definitions of the flags, only those changed after `since` when it's given
+  pub flags: Vec<SnapshotFlag>,
+  /// Names of every flag of the product, so SDKs can drop flags that were deleted
+  pub flag_names: Vec<String>,
+}
+
+/// Single result of a `/check/batch` request
+#[derive(Serialize, JsonSchema)]
+pub struct BatchFlagCheck {
+  /// Unique ID of the product that was checked
+  pub product_id: String,
+  /// Name of the feature flag that was checked
+  pub feature: String,
+  /// User the flag was evaluated with, if any
+  pub user: Option<String>,
+  /// Result of the check, `None` if the flag wasn't found
+  pub check: Option<FlagCheck>,
+}
+
 /// Response from `/create/...` routes containing the unique ID generated for the object/record
 #[derive(Serialize, JsonSchema)]
 pub struct Created {
@@ -35,3 +132,171 @@ impl Created {
     Created { id: id.to_string() }
   }
 }
+
+/// Response from `POST /products/<product_id>/api-keys`, the only time the key itself is shown
+#[derive(Serialize, JsonSchema)]
+pub struct CreatedApiKey {
+  /// The key, to send in the `X-Api-Key` header. Only its hash is stored, so it can't be shown again
+  pub key: String,
+  /// The stored key
+  pub api_key: SpecSafeApiKey,
+}
+
+/// Response from `POST /service-accounts`, the only time the token of the account is shown
+#[derive(Serialize, JsonSchema)]
+pub struct CreatedServiceAccount {
+  /// The token, to send as `Authorization: Bearer <token>`. Only its hash is stored, so it can't be shown again
+  pub token: String,
+  /// The stored service account
+  pub service_account: SpecSafeServiceAccount,
+}
+
+/// Outcome of one flag of a `/create/flags/<product_id>` request
+#[derive(Serialize, JsonSchema)]
+pub struct BulkCreatedFlag {
+  /// Name of the flag
+  pub name: String,
+  /// Unique ID generated for the flag, if it was created
+  pub id: Option<String>,
+  /// Why the flag wasn't created, if it wasn't
+  pub error: Option<String>,
+}
+
+/// What a `DELETE /products/<product_id>` request deleted, or would delete with `dry_run`
+#[derive(Serialize, JsonSchema)]
+pub struct ProductDeletion {
+  /// Unique ID of the product
+  pub product_id: String,
+  /// Name of the product
+  pub name: String,
+  /// Names of the product's flags, archived ones included
+  pub flags: Vec<String>,
+  /// Unique ID of the product's plugin, if any
+  pub plugin_id: Option<String>,
+  /// If nothing was deleted because the request was a dry run
+  pub dry_run: bool,
+}
+
+/// Response from `/login`
+#[derive(Serialize, JsonSchema)]
+pub struct LoggedIn {
+  /// The logged in user
+  #[serde(flatten)]
+  pub user: SpecSafeUser,
+  /// Token of the session, for API clients to send as `Authorization: Bearer <token>` instead of the login cookies
+  pub token: String,
+  /// Permissions the token is limited to, left out if it's only limited by the user's role
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub scopes: Option<Vec<Permission>>,
+}
+
+/// Response from `/logout/all` and `/users/password`
+#[derive(Serialize, JsonSchema)]
+pub struct SessionsRevoked {
+  /// Number of sessions that were logged out, the current one included
+  pub revoked: usize,
+}
+
+/// Kind of entity matched by a `/search` request
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchKind {
+  Flag,
+  Product,
+  User,
+}
+
+/// Entity matched by a `/search` request
+#[derive(Serialize, JsonSchema)]
+pub struct SearchResult {
+  /// Kind of the entity
+  pub kind: SearchKind,
+  /// Unique ID of the entity
+  pub id: String,
+  /// Name of the flag or product, or email of the user
+  pub name: String,
+  /// Unique ID of the product a flag belongs to
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub product_id: Option<String>,
+}
+
+/// Status of the service or one of its dependencies
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+  Up,
+  Down,
+}
+
+/// Health of a dependency of the service (e.g. the database), from `/healthz` and `/readyz`
+#[derive(Serialize, JsonSchema)]
+pub struct DependencyHealth {
+  /// Name of the dependency
+  pub name: String,
+  /// If the dependency could be reached
+  pub status: HealthStatus,
+  /// How long checking the dependency took, in milliseconds
+  pub latency_ms: u64,
+}
+
+/// Response from `/healthz` and `/readyz`
+#[derive(Serialize, JsonSchema)]
+pub struct Health {
+  /// `up` if every dependency is up
+  pub status: HealthStatus,
+  /// Health of each dependency
+  pub dependencies: Vec<DependencyHealth>,
+}
+
+/// Page of audit log entries
+#[derive(Serialize, JsonSchema)]
+pub struct AuditPage {
+  /// Entries of the page, newest first
+  pub entries: Vec<SpecSafeAuditEntry>,
+  /// Cursor to pass as `after` to get the next page, `None` on the last page
+  pub next_cursor: Option<String>,
+}
+
+/// Temporary flag listed by `/report/stale/...` as ready to be cleaned up
+#[derive(Serialize, JsonSchema)]
+pub struct StaleFlag {
+  /// Name of the feature flag
+  pub name: String,
+  /// Owner of the flag (team or person responsible for it)
+  pub owner: String,
+  /// Unique ID of the user maintaining the flag, if any
+  pub maintainer_id: Option<String>,
+  /// When the flag was last changed, `None` if it predates timestamps being recorded
+  pub updated_at: Option<DateTime<Utc>>,
+  /// Days since the flag was last changed, `None` if it predates timestamps being recorded
+  pub days_unchanged: Option<i64>,
+}
+
+/// Where a user falls in a percentage release, from a `/flags/<flag_id>/bucket-preview` request
+#[derive(Serialize, JsonSchema)]
+pub struct BucketPreview {
+  /// User ID (or value of the bucketed attribute) that was checked
+  pub user: String,
+  /// Bucket of the user in `[0, 100)`, users with a bucket below the release percentage are inside the rollout
+  pub bucket: f32,
+  /// If the bucket is inside the current release percentage
+  pub in_bucket: bool,
+  /// If the user is on the release's allowlist, allowlisted users are enabled regardless of their bucket
+  pub allowlisted: bool,
+  /// `true` if the user is on the flag's always include list, `false` if on its always exclude list
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub forced: Option<bool>,
+}
+
+/// Result of evaluating a sample context in a `/flags/<flag_id>/preview` request
+#[derive(Serialize, JsonSchema)]
+pub struct PreviewResult {
+  /// Context the flag was evaluated with
+  pub context: EvaluationContext,
+  /// Status with the stored flag
+  pub current: bool,
+  /// Status with the proposed flag
+  pub proposed: bool,
+  /// If the proposed flag changes the status
+  pub changed: bool,
+}
@@ -0,0 +1,565 @@
+//! Enterprise login through a SAML 2.0 identity provider (IdP)
+//!
+//! The service is a SAML service provider (SP), its metadata is served at `/saml/metadata` for registering it at the
+//! IdP. `GET /saml/login` redirects to the IdP with an authentication request (HTTP-Redirect binding), and the IdP
+//! POSTs its response back to `/saml/acs` (HTTP-POST binding), IdP initiated logins are accepted too. Responses must
+//! carry a single unencrypted assertion, signed (or inside a signed response) with the IdP's certificate. Signatures are
+//! checked with the `xmlsec1` command line tool, which must be installed where the service runs
+//!
+//! The user's account type and role follow the IdP groups they're in, listed in the `SAML_GROUP_ATTRIBUTE` attribute of
+//! the assertion and mapped by `SAML_GROUP_MAPPING` (e.g. `ff-admins=Developer:admin,ff-editors=Client:editor`). Users
+//! in several mapped groups get the highest role, and are developers if any of their groups maps to `Developer`. Users in
+//! no mapped group are clients with the `viewer` role
+//!
+//! Configured from the environment, SAML login is disabled unless the first four are set (and not blank):
+//! * **SAML_SP_ENTITY_ID**        - entity ID of the service (e.g. `https://flags.example.com/saml`)
+//! * **SAML_ACS_URL**             - URL of `/api/v1/saml/acs`, where the IdP POSTs its responses
+//! * **SAML_IDP_SSO_URL**         - single sign-on URL of the IdP, for the HTTP-Redirect binding
+//! * **SAML_IDP_CERT**            - path of the PEM certificate the IdP signs with
+//! * **SAML_IDP_ENTITY_ID**       - *(optional)* entity ID of the IdP, the issuer of its assertions
+//! * **SAML_GROUP_ATTRIBUTE**     - attribute listing the groups of the user, `groups` by default
+//! * **SAML_GROUP_MAPPING**       - account type and role of the users of each group
+//! * **SAML_POST_LOGIN_REDIRECT** - where users are redirected once logged in, `/` by default
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use reqwest::Url;
+use rocket::http::Status;
+use roxmltree::{Document, Node};
+
+use crate::controller::error::ApiError;
+use crate::log;
+use crate::model::user::{AccountType, Role};
+
+const PROTOCOL_NS: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const ASSERTION_NS: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+const HTTP_POST_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+
+/// Attributes the email of the user is read from, before falling back to the `NameID`
+const EMAIL_ATTRIBUTES: [&str; 3] = ["email", "mail", "urn:oid:0.9.2342.19200300.100.1.3"];
+/// Attributes the name of the user is read from
+const NAME_ATTRIBUTES: [&str; 3] = ["name", "displayName", "urn:oid:2.16.840.1.113730.3.1.241"];
+
+/// Clock difference with the IdP tolerated when checking the validity period of assertions
+const CLOCK_SKEW_SECONDS: i64 = 60;
+
+/// Account type and role of the users of an IdP group
+#[derive(Clone, Debug)]
+pub struct GroupMapping {
+  pub group: String,
+  pub account_type: AccountType,
+  pub role: Role,
+}
+
+impl GroupMapping {
+  /// Parses mappings of the form `group=AccountType:role`, separated by commas, skipping (and logging) invalid ones
+  pub fn parse_list(value: &str) -> Vec<GroupMapping> {
+    value
+      .split(',')
+      .map(str::trim)
+      .filter(|x| !x.is_empty())
+      .filter_map(|mapping| {
+        let parsed = mapping.rsplit_once('=').and_then(|(group, access)| {
+          let (account_type, role) = access.split_once(':')?;
+          Some(GroupMapping {
+            group: group.trim().to_string(),
+            account_type: AccountType::from(account_type.trim().to_string()),
+            role: Role::from_name(role.trim())?,
+          })
+        });
+
+        if parsed.is_none() {
+          log!("Ignoring invalid 'SAML_GROUP_MAPPING' entry '{}'", mapping);
+        }
+        parsed
+      })
+      .collect()
+  }
+}
+
+/// Configuration of the SAML service provider and of its IdP
+#[derive(Clone, Debug)]
+pub struct SamlConfig {
+  pub entity_id: String,
+  pub acs_url: String,
+  pub idp_sso_url: String,
+  pub idp_cert: PathBuf,
+  pub idp_entity_id: Option<String>,
+  pub group_attribute: String,
+  pub group_mappings: Vec<GroupMapping>,
+  /// Where users are redirected once logged in
+  pub post_login_redirect: String,
+}
+
+/// Reads the environment variable `name`, `None` if it isn't set or is blank
+fn var(name: &str) -> Option<String> {
+  dotenv::var(name).ok().filter(|x| !x.trim().is_empty())
+}
+
+impl SamlConfig {
+  /// Reads the configuration from the environment, `None` if SAML login isn't configured
+  pub fn from_env() -> Option<SamlConfig> {
+    Some(SamlConfig {
+      entity_id: var("SAML_SP_ENTITY_ID")?,
+      acs_url: var("SAML_ACS_URL")?,
+      idp_sso_url: var("SAML_IDP_SSO_URL")?,
+      idp_cert: PathBuf::from(var("SAML_IDP_CERT")?),
+      idp_entity_id: var("SAML_IDP_ENTITY_ID"),
+      group_attribute: var("SAML_GROUP_ATTRIBUTE").unwrap_or_else(|| "groups".to_string()),
+      group_mappings: var("SAML_GROUP_MAPPING")
+        .map(|x| GroupMapping::parse_list(&x))
+        .unwrap_or_default(),
+      post_login_redirect: var("SAML_POST_LOGIN_REDIRECT").unwrap_or_else(|| "/".to_string()),
+    })
+  }
+
+  /// Returns the configuration, or 404 if SAML login isn't configured
+  pub fn required() -> Result<SamlConfig, ApiError> {
+    SamlConfig::from_env().ok_or_else(|| ApiError::not_found("Error. SAML login isn't configured."))
+  }
+
+  /// Account type and role of a user in `groups`, see the module docs
+  pub fn access(&self, groups: &[String]) -> (AccountType, Role) {
+    let mappings: Vec<&GroupMapping> = self
+      .group_mappings
+      .iter()
+      .filter(|x| groups.contains(&x.group))
+      .collect();
+
+    let account_type = match mappings
+      .iter()
+      .any(|x| matches!(x.account_type, AccountType::Developer))
+    {
+      true => AccountType::Developer,
+      false => AccountType::Client,
+    };
+    let role = mappings
+      .iter()
+      .map(|x| x.role)
+      .max_by_key(|x| x.permissions().len())
+      .unwrap_or(Role::Viewer);
+
+    (account_type, role)
+  }
+
+  /// Metadata of the service provider, for registering it at the IdP
+  pub fn metadata(&self) -> String {
+    format!(
+      r#"<?xml version="1.0" encoding="UTF-8"?>
+<md:EntityDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata" entityID="{entity_id}">
+  <md:SPSSODescriptor AuthnRequestsSigned="false" WantAssertionsSigned="true" protocolSupportEnumeration="{protocol}">
+    <md:NameIDFormat>urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress</md:NameIDFormat>
+    <md:AssertionConsumerService Binding="{binding}" Location="{acs_url}" index="0" isDefault="true"/>
+  </md:SPSSODescriptor>
+</md:EntityDescriptor>
+"#,
+      entity_id = escape(&self.entity_id),
+      protocol = PROTOCOL_NS,
+      binding = HTTP_POST_BINDING,
+      acs_url = escape(&self.acs_url),
+    )
+  }
+
+  /// Returns the URL of the IdP users are redirected to for logging in, carrying an authentication request
+  pub fn login_url(&self, relay_state: Option<&str>) -> Result<String, ApiError> {
+    let request_id: String = rand::random::<[u8; 16]>()
+      .iter()
+      .map(|x| format!("{:02x}", x))
+      .collect();
+    let request = format!(
+      r#"<samlp:AuthnRequest xmlns:samlp="{protocol}" xmlns:saml="{assertion}" ID="_{id}" Version="2.0" IssueInstant="{now}" Destination="{destination}" ProtocolBinding="{binding}" AssertionConsumerServiceURL="{acs_url}"><saml:Issuer>{entity_id}</saml:Issuer></samlp:AuthnRequest>"#,
+      protocol = PROTOCOL_NS,
+      assertion = ASSERTION_NS,
+      id = request_id,
+      now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+      destination = escape(&self.idp_sso_url),
+      binding = HTTP_POST_BINDING,
+      acs_url = escape(&self.acs_url),
+      entity_id = escape(&self.entity_id),
+    );
+
+    // The HTTP-Redirect binding sends requests raw DEFLATE compressed then base64 encoded
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    let deflated = encoder
+      .write_all(request.as_bytes())
+      .and_then(|_| encoder.finish())
+      .map_err(|e| {
+        log!("Error compressing SAML authentication request. Error: {:?}", e);
+        ApiError::new(Status::InternalServerError, "Error. Something went wrong.")
+      })?;
+
+    let mut url = Url::parse(&self.idp_sso_url).map_err(|e| {
+      log!("Error parsing 'SAML_IDP_SSO_URL'. Error: {:?}", e);
+      ApiError::new(Status::InternalServerError, "Error. Something went wrong.")
+    })?;
+    url
+      .query_pairs_mut()
+      .append_pair("SAMLRequest", &BASE64.encode(deflated));
+    if let Some(relay_state) = relay_state {
+      url.query_pairs_mut().append_pair("RelayState", relay_state);
+    }
+
+    Ok(url.to_string())
+  }
+}
+
+/// Returns `relay_state` if it's a path of the service, which is safe to redirect to once logged in
+///
+/// The path must start with a single `/`. Backslashes and control characters are refused, since browsers read `/\` and
+/// `/<tab>/` the same as `//`, which would redirect to another host
+pub fn local_path(relay_state: &str) -> Option<&str> {
+  let mut chars = relay_state.chars();
+  if chars.next() != Some('/') || chars.next() == Some('/') {
+    return None;
+  }
+
+  if relay_state.chars().any(|x| x == '\\' || x.is_control()) {
+    return None;
+  }
+
+  Some(relay_state)
+}
+
+/// Escapes a value for an XML attribute or text
+fn escape(value: &str) -> String {
+  value
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// User an IdP vouched for
+#[derive(Debug)]
+pub struct SamlUser {
+  /// Issuer of the assertion, the IdP
+  pub issuer: String,
+  /// ID of the user at the IdP
+  pub name_id: String,
+  pub email: String,
+  pub name: Option<String>,
+  pub groups: Vec<String>,
+}
+
+fn unauthorized(message: &str) -> ApiError {
+  ApiError::new(Status::Unauthorized, format!("Error. {}", message))
+}
+
+fn is(node: &Node, namespace: &str, name: &str) -> bool {
+  node.is_element() && node.tag_name().namespace() == Some(namespace) && node.tag_name().name() == name
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, namespace: &str, name: &str) -> Option<Node<'a, 'input>> {
+  node.children().find(|x| is(x, namespace, name))
+}
+
+/// Text of an element, trimmed, with every text node under it joined
+///
+/// Comments are dropped by the canonicalization signatures are computed on, so text split by one (e.g.
+/// `victim@example.com<!---->.evil.com`) must be read whole, as it was signed, and not only up to the comment
+fn text(node: Node) -> String {
+  let text: String = node
+    .descendants()
+    .filter(|x| x.is_text())
+    .filter_map(|x| x.text())
+    .collect();
+
+  text.trim().to_string()
+}
+
+/// Parses an optional timestamp attribute, `Err` if it's present but invalid
+fn time_attribute(node: Node, name: &str) -> Result<Option<DateTime<Utc>>, ApiError> {
+  match node.attribute(name) {
+    Some(value) => DateTime::parse_from_rfc3339(value)
+      .map(|x| Some(x.with_timezone(&Utc)))
+      .map_err(|_| ApiError::bad_request(format!("Error. Invalid SAML timestamp '{}'.", value))),
+    None => Ok(None),
+  }
+}
+
+/// Checks `now` is within the `NotBefore` and `NotOnOrAfter` attributes of `node`, allowing for clock skew
+fn check_validity(node: Node, now: DateTime<Utc>) -> Result<(), ApiError> {
+  let skew = Duration::seconds(CLOCK_SKEW_SECONDS);
+  if matches!(time_attribute(node, "NotBefore")?, Some(x) if now + skew < x) {
+    return Err(unauthorized("The SAML assertion isn't valid yet."));
+  }
+  if matches!(time_attribute(node, "NotOnOrAfter")?, Some(x) if now - skew >= x) {
+    return Err(unauthorized("The SAML assertion expired."));
+  }
+
+  Ok(())
+}
+
+/// Checks the signature of a SAML response with `xmlsec1`, the signature is found by `signature_xpath`
+async fn verify_signature(config: &SamlConfig, xml: &str, signature_xpath: &str) -> Result<(), ApiError> {
+  let file_name: String = rand::random::<[u8; 16]>()
+    .iter()
+    .map(|x| format!("{:02x}", x))
+    .collect();
+  let path = std::env::temp_dir().join(format!("saml-{}.xml", file_name));
+
+  if let Err(e) = rocket::tokio::fs::write(&path, xml).await {
+    log!("Error writing SAML response for verification. Error: {:?}", e);
+    return Err(ApiError::new(
+      Status::InternalServerError,
+      "Error. Something went wrong.",
+    ));
+  }
+
+  let output = rocket::tokio::process::Command::new("xmlsec1")
+    .arg("--verify")
+    .arg("--pubkey-cert-pem")
+    .arg(&config.idp_cert)
+    .arg("--id-attr:ID")
+    .arg(format!("{}:Response", PROTOCOL_NS))
+    .arg("--id-attr:ID")
+    .arg(format!("{}:Assertion", ASSERTION_NS))
+    .arg("--node-xpath")
+    .arg(signature_xpath)
+    .arg(&path)
+    .output()
+    .await;
+  let _ = rocket::tokio::fs::remove_file(&path).await;
+
+  match output {
+    Ok(output) if output.status.success() => Ok(()),
+    Ok(output) => {
+      log!(
+        "SAML response signature verification failed. Output: {}",
+        String::from_utf8_lossy(&output.stderr)
+      );
+      Err(unauthorized("Invalid SAML response signature."))
+    }
+    Err(e) => {
+      log!("Error running 'xmlsec1', it's needed for SAML login. Error: {:?}", e);
+      Err(ApiError::new(
+        Status::InternalServerError,
+        "Error. Something went wrong.",
+      ))
+    }
+  }
+}
+
+/// Verifies a base64 encoded SAML response POSTed by the IdP, returning the user it vouches for
+///
+/// Returns 400 if the response can't be parsed, and 401 if it's unsuccessful, unsigned, badly signed, expired, or for
+/// another service provider
+pub async fn verify_response(config: &SamlConfig, saml_response: &str) -> Result<SamlUser, ApiError> {
+  let encoded: String = saml_response.chars().filter(|x| !x.is_whitespace()).collect();
+  let xml = BASE64
+    .decode(encoded)
+    .ok()
+    .and_then(|x| String::from_utf8(x).ok())
+    .ok_or_else(|| ApiError::bad_request("Error. The SAML response isn't base64 encoded XML."))?;
+  let document =
+    Document::parse(&xml).map_err(|_| ApiError::bad_request("Error. The SAML response isn't valid XML."))?;
+
+  let response = document.root_element();
+  if !is(&response, PROTOCOL_NS, "Response") {
+    return Err(ApiError::bad_request("Error. The document isn't a SAML response."));
+  }
+
+  let status = child(response, PROTOCOL_NS, "Status")
+    .and_then(|x| child(x, PROTOCOL_NS, "StatusCode"))
+    .and_then(|x| x.attribute("Value"));
+  if status != Some("urn:oasis:names:tc:SAML:2.0:status:Success") {
+    return Err(unauthorized("The IdP didn't authenticate the user."));
+  }
+
+  // A single assertion, right under the response, so the one read is the one whose signature is checked
+  let assertions: Vec<Node> = document
+    .descendants()
+    .filter(|x| is(x, ASSERTION_NS, "Assertion") || is(x, ASSERTION_NS, "EncryptedAssertion"))
+    .collect();
+  let assertion = match assertions.as_slice() {
+    [assertion] if is(assertion, ASSERTION_NS, "Assertion") && assertion.parent() == Some(response) => *assertion,
+    _ => {
+      return Err(ApiError::bad_request(
+        "Error. SAML responses must have a single unencrypted assertion.",
+      ))
+    }
+  };
+
+  let (signed, signature_xpath) = match child(assertion, DSIG_NS, "Signature") {
+    Some(signature) => (
+      (assertion, signature),
+      "/*[local-name()='Response']/*[local-name()='Assertion']/*[local-name()='Signature']",
+    ),
+    None => match child(response, DSIG_NS, "Signature") {
+      Some(signature) => (
+        (response, signature),
+        "/*[local-name()='Response']/*[local-name()='Signature']",
+      ),
+      None => return Err(unauthorized("The SAML assertion isn't signed.")),
+    },
+  };
+
+  // The signature must cover the element it's in, not another one elsewhere in the document
+  let (signed_element, signature) = signed;
+  let reference = signature
+    .descendants()
+    .find(|x| is(x, DSIG_NS, "Reference"))
+    .and_then(|x| x.attribute("URI"));
+  match (reference, signed_element.attribute("ID")) {
+    (Some(reference), Some(id)) if reference.strip_prefix('#') == Some(id) => (),
+    _ => return Err(unauthorized("The SAML signature doesn't cover the assertion.")),
+  }
+
+  verify_signature(config, &xml, signature_xpath).await?;
+
+  read_assertion(config, assertion)
+}
+
+/// Reads the user out of an assertion whose signature was verified, checking it's valid for this service provider
+fn read_assertion(config: &SamlConfig, assertion: Node) -> Result<SamlUser, ApiError> {
+  let issuer = child(assertion, ASSERTION_NS, "Issuer").map(text).unwrap_or_default();
+  if matches!(&config.idp_entity_id, Some(idp_entity_id) if *idp_entity_id != issuer) {
+    return Err(unauthorized("The SAML assertion was issued by an unknown IdP."));
+  }
+
+  let now = Utc::now();
+  if let Some(conditions) = child(assertion, ASSERTION_NS, "Conditions") {
+    check_validity(conditions, now)?;
+
+    let audiences: Vec<String> = conditions
+      .descendants()
+      .filter(|x| is(x, ASSERTION_NS, "Audience"))
+      .map(text)
+      .collect();
+    if !audiences.is_empty() && !audiences.contains(&config.entity_id) {
+      return Err(unauthorized(
+        "The SAML assertion is meant for another service provider.",
+      ));
+    }
+  }
+
+  let subject = child(assertion, ASSERTION_NS, "Subject");
+  for confirmation_data in subject
+    .into_iter()
+    .flat_map(|x| x.descendants())
+    .filter(|x| is(x, ASSERTION_NS, "SubjectConfirmationData"))
+  {
+    check_validity(confirmation_data, now)?;
+    if matches!(confirmation_data.attribute("Recipient"), Some(recipient) if recipient != config.acs_url) {
+      return Err(unauthorized(
+        "The SAML assertion is meant for another service provider.",
+      ));
+    }
+  }
+
+  let name_id = subject
+    .and_then(|x| child(x, ASSERTION_NS, "NameID"))
+    .map(text)
+    .filter(|x| !x.is_empty())
+    .ok_or_else(|| unauthorized("The SAML assertion has no subject."))?;
+
+  let attribute = |names: &[&str]| -> Vec<String> {
+    assertion
+      .descendants()
+      .filter(|x| is(x, ASSERTION_NS, "Attribute"))
+      .filter(|x| matches!(x.attribute("Name"), Some(name) if names.contains(&name)))
+      .flat_map(|x| x.children().filter(|x| is(x, ASSERTION_NS, "AttributeValue")))
+      .map(text)
+      .filter(|x| !x.is_empty())
+      .collect()
+  };
+
+  let email = match attribute(&EMAIL_ATTRIBUTES).into_iter().next() {
+    Some(email) => email,
+    None if name_id.contains('@') => name_id.clone(),
+    None => return Err(unauthorized("The SAML assertion has no email for the user.")),
+  };
+
+  Ok(SamlUser {
+    issuer,
+    name_id,
+    email,
+    name: attribute(&NAME_ATTRIBUTES).into_iter().next(),
+    groups: attribute(&[config.group_attribute.as_str()]),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config() -> SamlConfig {
+    SamlConfig {
+      entity_id: "https://flags.example.com/saml".to_string(),
+      acs_url: "https://flags.example.com/api/v1/saml/acs".to_string(),
+      idp_sso_url: "https://idp.example.com/sso".to_string(),
+      idp_cert: PathBuf::from("idp.pem"),
+      idp_entity_id: None,
+      group_attribute: "groups".to_string(),
+      group_mappings: vec![],
+      post_login_redirect: "/".to_string(),
+    }
+  }
+
+  fn read(subject: &str, attributes: &str) -> Result<SamlUser, ApiError> {
+    let xml = format!(
+      r#"<saml:Assertion xmlns:saml="{}" ID="a1"><saml:Issuer>https://idp.example.com</saml:Issuer><saml:Subject>{}</saml:Subject><saml:AttributeStatement>{}</saml:AttributeStatement></saml:Assertion>"#,
+      ASSERTION_NS, subject, attributes
+    );
+    let document = Document::parse(&xml).unwrap();
+
+    read_assertion(&config(), document.root_element())
+  }
+
+  #[test]
+  fn name_id_split_by_a_comment_is_read_whole() {
+    let user = read("<saml:NameID>victim@example.com<!---->.evil.com</saml:NameID>", "").unwrap();
+
+    assert_eq!(user.name_id, "victim@example.com.evil.com");
+    assert_eq!(user.email, "victim@example.com.evil.com");
+  }
+
+  #[test]
+  fn attribute_values_split_by_a_comment_are_read_whole() {
+    let user = read(
+      "<saml:NameID>u1</saml:NameID>",
+      r#"<saml:Attribute Name="email"><saml:AttributeValue> victim@example.com<!-- x -->.evil.com </saml:AttributeValue></saml:Attribute>
+      <saml:Attribute Name="groups"><saml:AttributeValue>ff-<!---->admins</saml:AttributeValue></saml:Attribute>"#,
+    )
+    .unwrap();
+
+    assert_eq!(user.email, "victim@example.com.evil.com");
+    assert_eq!(user.groups, vec!["ff-admins"]);
+  }
+
+  #[test]
+  fn assertions_without_subject_or_email_are_refused() {
+    assert!(read("<saml:NameID><!-- nothing --></saml:NameID>", "").is_err());
+    assert!(read("<saml:NameID>u1</saml:NameID>", "").is_err());
+  }
+
+  #[test]
+  fn group_mappings_are_parsed_skipping_invalid_ones() {
+    let mappings = GroupMapping::parse_list(" ff-admins = Developer:admin ,, a=b=Client:viewer,bad,x=Client:root");
+
+    assert_eq!(mappings.len(), 2);
+    assert_eq!(mappings[0].group, "ff-admins");
+    assert!(matches!(mappings[0].account_type, AccountType::Developer));
+    assert_eq!(mappings[0].role, Role::Admin);
+    assert_eq!(mappings[1].group, "a=b");
+    assert!(matches!(mappings[1].account_type, AccountType::Client));
+    assert_eq!(mappings[1].role, Role::Viewer);
+  }
+
+  #[test]
+  fn relay_states_are_local_paths_only() {
+    assert_eq!(local_path("/flags?x=1"), Some("/flags?x=1"));
+    assert_eq!(local_path("//evil.com"), None);
+    assert_eq!(local_path("/\\evil.com"), None);
+    assert_eq!(local_path("/\t/evil.com"), None);
+    assert_eq!(local_path("https://evil.com"), None);
+    assert_eq!(local_path(""), None);
+  }
+}
@@ -0,0 +1,111 @@
+//! Background task applying scheduled flag changes once they're due
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::controller::database::ConnectionManager;
+use crate::log;
+use crate::model::audit::{AuditAction, AuditEntry, EntityType};
+
+/// How often due changes are checked for when `SCHEDULER_INTERVAL_SECONDS` isn't set
+const DEFAULT_INTERVAL_SECONDS: u64 = 60;
+
+/// Returns how often due changes are checked for, read from `SCHEDULER_INTERVAL_SECONDS`
+pub fn interval_from_env() -> Duration {
+  let seconds = match dotenv::var("SCHEDULER_INTERVAL_SECONDS").map(|x| x.parse::<u64>()) {
+    Ok(Ok(seconds)) if seconds > 0 => seconds,
+    _ => DEFAULT_INTERVAL_SECONDS,
+  };
+
+  Duration::from_secs(seconds)
+}
+
+/// Applies due changes every `interval`, forever
+pub async fn run(database_connection: ConnectionManager, interval: Duration) {
+  let mut ticker = rocket::tokio::time::interval(interval);
+
+  loop {
+    ticker.tick().await;
+    apply_due_changes(&database_connection).await;
+  }
+}
+
+/// Applies every scheduled change that's due, in the order they were due, and records them in the audit log
+///
+/// Changes of flags whose product is in a freeze window are left for once the window ends
+///
+/// Returns the number of changes applied
+pub async fn apply_due_changes(database_connection: &ConnectionManager) -> usize {
+  let now = Utc::now();
+  let mut applied = 0;
+  let mut frozen: HashMap<String, bool> = HashMap::new();
+
+  // Failing to read the flags is logged, they're read again on the next run
+  let flags = database_connection
+    .get_feature_flags_with_due_changes(now)
+    .await
+    .unwrap_or_default();
+
+  for mut flag in flags {
+    let flag_id = match flag.oid {
+      Some(oid) => oid.to_hex(),
+      None => continue,
+    };
+
+    let is_frozen = match frozen.get(&flag.product_id) {
+      Some(is_frozen) => *is_frozen,
+      None => {
+        let is_frozen = match database_connection.get_product_by_id(&flag.product_id).await {
+          Ok(Some(product)) => product.is_frozen(now),
+          Ok(None) => false,
+          // Left for the next run rather than risk applying them during a freeze
+          Err(_) => continue,
+        };
+        frozen.insert(flag.product_id.clone(), is_frozen);
+        is_frozen
+      }
+    };
+    if is_frozen {
+      continue;
+    }
+
+    // Each change is applied on its own, only if it's still scheduled, so concurrent writes to the flag are kept and
+    // instances running the scheduler together apply it once
+    let details = format!("{}/{}", flag.product_id, flag.name);
+    for scheduled in flag.take_due_changes(now) {
+      let field = flag.resolve_change(&scheduled.change);
+      match database_connection
+        .apply_scheduled_change(&flag_id, scheduled.id, &field)
+        .await
+      {
+        Ok(Some(updated)) => flag = updated,
+        Ok(None) => continue,
+        Err(_) => {
+          log!("Error applying scheduled changes to flag '{}'", flag_id);
+          break;
+        }
+      }
+
+      let _ = database_connection
+        .create_audit_entry(
+          AuditEntry::builder()
+            .with_actor(&scheduled.created_by)
+            .with_entity(EntityType::Flag, &flag_id)
+            .with_action(AuditAction::ApplyScheduledChange)
+            .with_details(&format!(
+              "{} {:?} (scheduled {})",
+              details,
+              scheduled.change,
+              scheduled.id.to_hex()
+            )),
+        )
+        .await;
+
+      applied += 1;
+    }
+  }
+
+  applied
+}
@@ -0,0 +1,345 @@
+//! Scopes of tokens and API keys, and the request guards routes declare the permission they need with
+//!
+//! Routes take `Scoped<FlagsRead>`, `Scoped<FlagsWrite>` or `Scoped<UsersAdmin>`, which only lets the request through if:
+//! - a user's role grants the permission, and the scopes of their token include it when the token was limited at login
+//! - a service account's scopes grant it, for a product the account gives access to
+//! - an API key's scopes include it, for the key's own product
+//!
+//! Service accounts and API keys can only use routes of a product (with a `<product_id>` parameter). The permission is
+//! listed as the scope of the route's security requirement in the OpenAPI spec
+
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use rocket::http::Status;
+use rocket::outcome::Outcome as GuardOutcome;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::json;
+use rocket_okapi::okapi::openapi3::{Responses, SecurityRequirement};
+use rocket_okapi::{
+  gen::OpenApiGenerator,
+  request::{OpenApiFromRequest, RequestHeaderInput},
+};
+
+use crate::controller::api_keys::{self, ApiKeyAuth, ApiKeyError};
+use crate::controller::authentication::{self, UserAuth, UserAuthError};
+use crate::controller::database::ConnectionManager;
+use crate::controller::error::{self, ApiError};
+use crate::controller::membership;
+use crate::controller::service_accounts::{self, ServiceAccountAuth, ServiceAccountError};
+use crate::model::user::{Permission, Role};
+
+/// Custom rocket request guard for routes open to users, service accounts and API keys
+///
+/// Authorizes a service account if the request sent a service account token, an API key if it sent an `X-Api-Key`
+/// header, and a user like `UserAuth` otherwise. Fails with 401 if the token, key or the user's credentials are invalid.
+/// Routes should use `Scoped` instead, which also checks what the caller can do
+pub struct ActorAuth {
+  /// Unique ID of the authenticated user, `service_account:<id>` for service accounts and `api_key:<id>` for API keys
+  pub user_id: String,
+  /// Permissions the user's token is limited to, `None` if it isn't limited or no user sent the request
+  pub scopes: Option<Vec<Permission>>,
+  /// Service account of the request, `None` if it didn't send a service account token
+  pub service_account: Option<ServiceAccountAuth>,
+  /// API key of the request, `None` if it didn't send one
+  pub api_key: Option<ApiKeyAuth>,
+}
+
+impl ActorAuth {
+  /// If a user sent the request, rather than a service account or an API key
+  pub fn is_user(&self) -> bool {
+    self.service_account.is_none() && self.api_key.is_none()
+  }
+
+  /// Checks a service account or API key gives access to the product of ID `product_id`, with 403 otherwise. Users
+  /// aren't checked
+  pub fn authorize(&self, product_id: &str) -> Result<(), ApiError> {
+    if let Some(service_account) = &self.service_account {
+      return service_account.authorize(product_id);
+    }
+
+    match &self.api_key {
+      Some(api_key) => api_key.authorize_product(product_id),
+      None => Ok(()),
+    }
+  }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ActorAuth {
+  type Error = UserAuthError;
+
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    match ServiceAccountAuth::from_header(request).await {
+      Some(Ok(service_account)) => {
+        return Outcome::Success(ActorAuth {
+          user_id: service_accounts::actor(&service_account.service_account_id),
+          scopes: None,
+          service_account: Some(service_account),
+          api_key: None,
+        })
+      }
+      Some(Err(ServiceAccountError::Unavailable)) => {
+        return Outcome::Failure((Status::InternalServerError, UserAuthError::Invalid))
+      }
+      Some(Err(ServiceAccountError::Invalid)) => {
+        return Outcome::Failure((Status::Unauthorized, UserAuthError::Invalid))
+      }
+      None => {}
+    }
+
+    match ApiKeyAuth::from_header(request).await {
+      Some(Ok(api_key)) => {
+        return Outcome::Success(ActorAuth {
+          user_id: api_keys::actor(&api_key.api_key_id),
+          scopes: None,
+          service_account: None,
+          api_key: Some(api_key),
+        })
+      }
+      Some(Err(ApiKeyError::Unavailable)) => {
+        return Outcome::Failure((Status::InternalServerError, UserAuthError::Invalid))
+      }
+      Some(Err(_)) => return Outcome::Failure((Status::Unauthorized, UserAuthError::Invalid)),
+      None => {}
+    }
+
+    match request.guard::<UserAuth>().await {
+      GuardOutcome::Success(token_auth) => Outcome::Success(ActorAuth {
+        user_id: token_auth.user_id,
+        scopes: token_auth.scopes,
+        service_account: None,
+        api_key: None,
+      }),
+      GuardOutcome::Failure(failure) => Outcome::Failure(failure),
+      GuardOutcome::Forward(_) => Outcome::Failure((Status::Unauthorized, UserAuthError::NoUserId)),
+    }
+  }
+}
+
+impl<'a> OpenApiFromRequest<'a> for ActorAuth {
+  fn from_request_input(
+    _gen: &mut OpenApiGenerator,
+    _name: String,
+    _required: bool,
+  ) -> rocket_okapi::Result<RequestHeaderInput> {
+    Ok(authentication::security_input())
+  }
+
+  fn get_responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+    Ok(error::error_responses(
+      gen,
+      &[(
+        "401",
+        "Not logged in, or the login cookies, bearer token, service account token or API key are invalid",
+      )],
+    ))
+  }
+}
+
+/// Returns the 403 error of a user of role `role` missing `permission`
+pub fn permission_denied(role: Role, permission: Permission) -> ApiError {
+  ApiError::new(
+    Status::Forbidden,
+    format!(
+      "Error. Missing permission '{}', which the '{}' role doesn't grant.",
+      permission.name(),
+      role.name()
+    ),
+  )
+  .with_details(json!({ "permission": permission.name(), "role": role.name() }))
+}
+
+/// Rejects the user of ID `user_id` if their role doesn't grant `permission`, or the `scopes` of their token don't
+/// include it, with 403 naming the missing permission
+pub async fn require_user(
+  database_connection: &ConnectionManager,
+  user_id: &str,
+  scopes: Option<&[Permission]>,
+  permission: Permission,
+) -> Result<(), ApiError> {
+  let role = match database_connection.get_user(None, Some(user_id)).await? {
+    Some(user) => user.role(),
+    None => return Err(ApiError::new(Status::Unauthorized, "Error. User not found.")),
+  };
+
+  if !role.has(permission) {
+    return Err(permission_denied(role, permission));
+  }
+
+  match scopes {
+    Some(scopes) if !scopes.contains(&permission) => Err(
+      ApiError::new(
+        Status::Forbidden,
+        format!(
+          "Error. Missing permission '{}', which the scopes of the token don't grant.",
+          permission.name()
+        ),
+      )
+      .with_details(json!({ "permission": permission.name() })),
+    ),
+    _ => Ok(()),
+  }
+}
+
+/// Rejects actors that can't do what `permission` allows, with 403 naming the missing permission
+pub async fn require(
+  database_connection: &ConnectionManager,
+  actor: &ActorAuth,
+  permission: Permission,
+) -> Result<(), ApiError> {
+  if let Some(service_account) = &actor.service_account {
+    return service_account.require_permission(permission);
+  }
+
+  match &actor.api_key {
+    Some(api_key) => api_key.require_scope(permission),
+    None => require_user(database_connection, &actor.user_id, actor.scopes.as_deref(), permission).await,
+  }
+}
+
+/// Permission a route declares it needs through `Scoped`
+pub trait Scope: Send + Sync + 'static {
+  const PERMISSION: Permission;
+}
+
+/// Scope of routes reading products and flags
+pub struct FlagsRead;
+
+/// Scope of routes changing products and flags
+pub struct FlagsWrite;
+
+/// Scope of routes managing users and their access to products
+pub struct UsersAdmin;
+
+impl Scope for FlagsRead {
+  const PERMISSION: Permission = Permission::FlagsRead;
+}
+
+impl Scope for FlagsWrite {
+  const PERMISSION: Permission = Permission::FlagsWrite;
+}
+
+impl Scope for UsersAdmin {
+  const PERMISSION: Permission = Permission::UsersAdmin;
+}
+
+#[derive(Debug)]
+pub enum ScopeError {
+  NotLoggedIn,
+  Denied,
+}
+
+/// Custom rocket request guard for routes needing the permission of scope `S`
+///
+/// Fails with 401 like `ActorAuth`, and 403 if the caller can't do what the permission allows, or is a service account
+/// or API key and the route isn't of a product it gives access to. Dereferences to the `ActorAuth` of the request
+pub struct Scoped<S: Scope> {
+  actor: ActorAuth,
+  scope: PhantomData<S>,
+}
+
+impl<S: Scope> Deref for Scoped<S> {
+  type Target = ActorAuth;
+
+  fn deref(&self) -> &ActorAuth {
+    &self.actor
+  }
+}
+
+#[rocket::async_trait]
+impl<'r, S: Scope> FromRequest<'r> for Scoped<S> {
+  type Error = ScopeError;
+
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    let actor = match request.guard::<ActorAuth>().await {
+      GuardOutcome::Success(actor) => actor,
+      GuardOutcome::Failure((_, UserAuthError::Inactive)) => {
+        return error::fail_guard(
+          request,
+          ApiError::new(Status::Forbidden, "Error. The account is suspended or deactivated."),
+          ScopeError::Denied,
+        )
+      }
+      GuardOutcome::Failure((status, _)) if status == Status::InternalServerError => {
+        return error::fail_guard(
+          request,
+          ApiError::new(Status::InternalServerError, "Error. Something went wrong."),
+          ScopeError::NotLoggedIn,
+        )
+      }
+      _ => {
+        return error::fail_guard(
+          request,
+          ApiError::new(Status::Unauthorized, "Error. Not logged in."),
+          ScopeError::NotLoggedIn,
+        )
+      }
+    };
+
+    if !actor.is_user() {
+      let authorized = match membership::product_id_param(request) {
+        Some(product_id) => actor.authorize(product_id),
+        None => Err(ApiError::new(
+          Status::Forbidden,
+          "Error. Service accounts and API keys can only use the routes of a product.",
+        )),
+      };
+      if let Err(e) = authorized {
+        return error::fail_guard(request, e, ScopeError::Denied);
+      }
+    }
+
+    let database_connection = match request.rocket().state::<ConnectionManager>() {
+      Some(value) => value,
+      None => {
+        return error::fail_guard(
+          request,
+          ApiError::new(Status::InternalServerError, "Error. Something went wrong."),
+          ScopeError::Denied,
+        )
+      }
+    };
+
+    match require(database_connection, &actor, S::PERMISSION).await {
+      Ok(()) => Outcome::Success(Scoped {
+        actor,
+        scope: PhantomData,
+      }),
+      Err(e) => error::fail_guard(request, e, ScopeError::Denied),
+    }
+  }
+}
+
+impl<'a, S: Scope> OpenApiFromRequest<'a> for Scoped<S> {
+  fn from_request_input(
+    _gen: &mut OpenApiGenerator,
+    _name: String,
+    _required: bool,
+  ) -> rocket_okapi::Result<RequestHeaderInput> {
+    let (name, security_scheme) = match authentication::security_input() {
+      RequestHeaderInput::Security(name, security_scheme, _) => (name, security_scheme),
+      other => return Ok(other),
+    };
+    let mut security_req = SecurityRequirement::new();
+    security_req.insert(name.clone(), vec![S::PERMISSION.name().to_owned()]);
+    Ok(RequestHeaderInput::Security(name, security_scheme, security_req))
+  }
+
+  fn get_responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+    Ok(error::error_responses(
+      gen,
+      &[
+        (
+          "401",
+          "Not logged in, or the login cookies, bearer token, service account token or API key are invalid",
+        ),
+        (
+          "403",
+          "The user's role or the scopes of the token, service account or API key don't grant the permission of the \
+           route, or the service account or API key doesn't give access to the product",
+        ),
+      ],
+    ))
+  }
+}
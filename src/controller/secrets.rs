@@ -0,0 +1,284 @@
+//! Secrets loaded from HashiCorp Vault or AWS Secrets Manager instead of the environment
+//!
+//! `SECRETS_BACKEND` picks where secrets come from: `env` (the default) only reads the environment and `.env`, `vault`
+//! reads a KV secret of Vault, and `aws` a JSON secret of AWS Secrets Manager. Either holds a JSON object of names to
+//! values (e.g. `{"MONGO_STR": "...", "ROCKET_SECRET_KEY": "..."}`), loaded at startup and refreshed every
+//! `SECRETS_REFRESH_SECONDS`. Names it doesn't have fall back to the environment
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rocket::serde::json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::log;
+
+/// How often secrets are refreshed when `SECRETS_REFRESH_SECONDS` isn't set
+const DEFAULT_REFRESH_SECONDS: u64 = 300;
+
+/// How long fetching the secrets can take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Secrets of the last successful load, empty until then or when they come from the environment
+static LOADED: RwLock<Option<HashMap<String, String>>> = RwLock::new(None);
+
+/// Where secrets are loaded from
+enum SecretsBackend {
+  Env,
+  Vault {
+    /// Address of the Vault server (e.g. `https://vault.example.com:8200`)
+    address: String,
+    token: String,
+    /// API path of the secret, mount included (e.g. `secret/data/feature-flags` for KV v2)
+    path: String,
+  },
+  Aws {
+    region: String,
+    /// Name or ARN of the secret
+    secret_id: String,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+  },
+}
+
+impl SecretsBackend {
+  /// Reads the backend from `SECRETS_BACKEND` and its own settings, `Err` naming what's missing if it's incomplete
+  fn from_env() -> Result<SecretsBackend, String> {
+    let required = |name: &str| match dotenv::var(name) {
+      Ok(value) if !value.trim().is_empty() => Ok(value.trim().to_string()),
+      _ => Err(format!("'{}' isn't set", name)),
+    };
+
+    match dotenv::var("SECRETS_BACKEND")
+      .unwrap_or_default()
+      .trim()
+      .to_lowercase()
+      .as_str()
+    {
+      "" | "env" => Ok(SecretsBackend::Env),
+      "vault" => Ok(SecretsBackend::Vault {
+        address: required("VAULT_ADDR")?.trim_end_matches('/').to_string(),
+        token: required("VAULT_TOKEN")?,
+        path: required("VAULT_SECRET_PATH")?.trim_matches('/').to_string(),
+      }),
+      "aws" => Ok(SecretsBackend::Aws {
+        region: required("AWS_REGION")?,
+        secret_id: required("AWS_SECRET_ID")?,
+        access_key_id: required("AWS_ACCESS_KEY_ID")?,
+        secret_access_key: required("AWS_SECRET_ACCESS_KEY")?,
+        session_token: required("AWS_SESSION_TOKEN").ok(),
+      }),
+      other => Err(format!("unknown 'SECRETS_BACKEND' '{}'", other)),
+    }
+  }
+
+  /// Fetches the secrets, `None` for the environment
+  async fn fetch(&self) -> Result<Option<HashMap<String, String>>, String> {
+    let client = reqwest::Client::builder()
+      .timeout(REQUEST_TIMEOUT)
+      .build()
+      .map_err(|e| format!("{:?}", e))?;
+
+    let secrets = match self {
+      SecretsBackend::Env => return Ok(None),
+      SecretsBackend::Vault { address, token, path } => {
+        let body = send(
+          client
+            .get(format!("{}/v1/{}", address, path))
+            .header("X-Vault-Token", token),
+        )
+        .await?;
+
+        // KV v2 nests the secret in `data.data`, KV v1 has it in `data`
+        match &body["data"]["data"] {
+          Value::Object(_) => body["data"]["data"].clone(),
+          _ => body["data"].clone(),
+        }
+      }
+      SecretsBackend::Aws {
+        region,
+        secret_id,
+        access_key_id,
+        secret_access_key,
+        session_token,
+      } => {
+        let host = format!("secretsmanager.{}.amazonaws.com", region);
+        let payload = json!({ "SecretId": secret_id }).to_string();
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+
+        let mut request = client
+          .post(format!("https://{}/", host))
+          .header("Content-Type", "application/x-amz-json-1.1")
+          .header("X-Amz-Target", "secretsmanager.GetSecretValue")
+          .header("X-Amz-Date", &amz_date)
+          .header(
+            "Authorization",
+            aws_authorization(region, access_key_id, secret_access_key, &host, &amz_date, &payload),
+          );
+        if let Some(session_token) = session_token {
+          request = request.header("X-Amz-Security-Token", session_token);
+        }
+
+        let body = send(request.body(payload)).await?;
+
+        match body["SecretString"].as_str() {
+          Some(secret) => rocket::serde::json::from_str::<Value>(secret).map_err(|e| format!("{:?}", e))?,
+          None => return Err("the secret has no 'SecretString'".to_string()),
+        }
+      }
+    };
+
+    match secrets {
+      Value::Object(map) => Ok(Some(
+        map
+          .into_iter()
+          .filter_map(|(name, value)| match value {
+            Value::String(value) => Some((name, value)),
+            Value::Null => None,
+            value => Some((name, value.to_string())),
+          })
+          .collect(),
+      )),
+      _ => Err("the secret isn't a JSON object".to_string()),
+    }
+  }
+}
+
+/// Sends `request`, returning its JSON response
+async fn send(request: reqwest::RequestBuilder) -> Result<Value, String> {
+  let body = request
+    .send()
+    .await
+    .and_then(|x| x.error_for_status())
+    .map_err(|e| format!("{:?}", e))?
+    .text()
+    .await
+    .map_err(|e| format!("{:?}", e))?;
+
+  rocket::serde::json::from_str(&body).map_err(|e| format!("{:?}", e))
+}
+
+/// Hex SHA-256 hash of `value`
+fn sha256_hex(value: &[u8]) -> String {
+  Sha256::digest(value).iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// HMAC-SHA256 of `value` keyed by `key`
+fn hmac_sha256(key: &[u8], value: &str) -> Vec<u8> {
+  let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+  mac.update(value.as_bytes());
+  mac.finalize().into_bytes().to_vec()
+}
+
+/// Returns the `Authorization` header of a Secrets Manager request, signed with AWS Signature Version 4
+fn aws_authorization(
+  region: &str,
+  access_key_id: &str,
+  secret_access_key: &str,
+  host: &str,
+  amz_date: &str,
+  payload: &str,
+) -> String {
+  let date = &amz_date[..8];
+  let signed_headers = "content-type;host;x-amz-date;x-amz-target";
+  let canonical_request = format!(
+    "POST\n/\n\ncontent-type:application/x-amz-json-1.1\nhost:{}\nx-amz-date:{}\nx-amz-target:secretsmanager.GetSecretValue\n\n{}\n{}",
+    host,
+    amz_date,
+    signed_headers,
+    sha256_hex(payload.as_bytes())
+  );
+  let scope = format!("{}/{}/secretsmanager/aws4_request", date, region);
+  let string_to_sign = format!(
+    "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+    amz_date,
+    scope,
+    sha256_hex(canonical_request.as_bytes())
+  );
+
+  let key = hmac_sha256(format!("AWS4{}", secret_access_key).as_bytes(), date);
+  let key = hmac_sha256(&key, region);
+  let key = hmac_sha256(&key, "secretsmanager");
+  let key = hmac_sha256(&key, "aws4_request");
+  let signature: String = hmac_sha256(&key, &string_to_sign)
+    .iter()
+    .map(|x| format!("{:02x}", x))
+    .collect();
+
+  format!(
+    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+    access_key_id, scope, signed_headers, signature
+  )
+}
+
+/// Returns the secret `name`, from the secrets backend if it has it and the environment otherwise
+pub fn var(name: &str) -> Option<String> {
+  let loaded = match LOADED.read() {
+    Ok(value) => value,
+    Err(poisoned) => poisoned.into_inner(), // recover from poisoned lock
+  };
+
+  match loaded.as_ref().and_then(|x| x.get(name)) {
+    Some(value) => Some(value.clone()),
+    None => dotenv::var(name).ok(),
+  }
+}
+
+/// Loads the secrets from the backend of `SECRETS_BACKEND`, returning if they were loaded
+///
+/// Nothing is loaded for the environment. When loading fails the secrets loaded before are kept
+pub async fn load() -> bool {
+  let backend = match SecretsBackend::from_env() {
+    Ok(backend) => backend,
+    Err(e) => {
+      log!("Error configuring the secrets backend, {}", e);
+      return false;
+    }
+  };
+
+  match backend.fetch().await {
+    Ok(Some(secrets)) => {
+      let mut loaded = match LOADED.write() {
+        Ok(value) => value,
+        Err(poisoned) => poisoned.into_inner(), // recover from poisoned lock
+      };
+      *loaded = Some(secrets);
+      true
+    }
+    Ok(None) => false,
+    Err(e) => {
+      log!("Error loading secrets, keeping the ones loaded before. Error: {}", e);
+      false
+    }
+  }
+}
+
+/// Returns how often secrets are refreshed, read from `SECRETS_REFRESH_SECONDS`. `None` if they're never refreshed,
+/// when it's 0 or they come from the environment
+pub fn refresh_interval_from_env() -> Option<Duration> {
+  if matches!(SecretsBackend::from_env(), Ok(SecretsBackend::Env) | Err(_)) {
+    return None;
+  }
+
+  match dotenv::var("SECRETS_REFRESH_SECONDS").map(|x| x.trim().parse::<u64>()) {
+    Ok(Ok(0)) => None,
+    Ok(Ok(seconds)) => Some(Duration::from_secs(seconds)),
+    _ => Some(Duration::from_secs(DEFAULT_REFRESH_SECONDS)),
+  }
+}
+
+/// Refreshes the secrets every `interval`, so rotated secrets are picked up without a restart
+pub async fn refresh(interval: Duration) {
+  let mut ticker = rocket::tokio::time::interval(interval);
+  ticker.tick().await;
+
+  loop {
+    ticker.tick().await;
+    load().await;
+  }
+}
@@ -0,0 +1,135 @@
+//! Service accounts, authorizing CI pipelines and automation without a human's session
+//!
+//! Tokens are sent as `Authorization: Bearer ffs_sa_<secret>` and only give access to the products of their account,
+//! for what its scopes allow: `read` exports products, `evaluate` checks flags, and `write` changes products and their
+//! flags. Changes are audited with `service_account:<id>` as their actor
+
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::serde::json::json;
+
+use crate::controller::api_keys;
+use crate::controller::database::ConnectionManager;
+use crate::controller::error::ApiError;
+use crate::model::service_account::ServiceScope;
+use crate::model::user::Permission;
+
+/// Prefix of service account tokens, telling them apart from the bearer tokens of user sessions
+pub const TOKEN_PREFIX: &str = "ffs_sa_";
+
+/// Prefix of the actor of changes made by service accounts in the audit log
+const ACTOR_PREFIX: &str = "service_account:";
+
+/// Length of the start of a token stored alongside its hash, prefix included
+const TOKEN_PREFIX_LENGTH: usize = 16;
+
+/// Generates a new random token, returning the token and the start of it that's stored
+pub fn generate() -> (String, String) {
+  let secret: String = rand::random::<[u8; 32]>()
+    .iter()
+    .map(|x| format!("{:02x}", x))
+    .collect();
+  let token = format!("{}{}", TOKEN_PREFIX, secret);
+  let token_prefix = token[..TOKEN_PREFIX_LENGTH].to_string();
+
+  (token, token_prefix)
+}
+
+/// Returns the actor of changes made by the service account of ID `service_account_id`
+pub fn actor(service_account_id: &str) -> String {
+  format!("{}{}", ACTOR_PREFIX, service_account_id)
+}
+
+#[derive(Debug)]
+pub enum ServiceAccountError {
+  Invalid,
+  Unavailable,
+}
+
+/// Service account a request was authorized by
+pub struct ServiceAccountAuth {
+  /// Unique ID of the service account
+  pub service_account_id: String,
+  /// Unique IDs of the products the token gives access to
+  pub product_ids: Vec<String>,
+  /// What the token can do with the products
+  pub scopes: Vec<ServiceScope>,
+}
+
+impl ServiceAccountAuth {
+  /// Checks the token gives access to the product of ID `product_id`, with 403 otherwise
+  pub fn authorize(&self, product_id: &str) -> Result<(), ApiError> {
+    match self.product_ids.iter().any(|x| x == product_id) {
+      true => Ok(()),
+      false => Err(ApiError::new(
+        Status::Forbidden,
+        format!(
+          "Error. The service account doesn't give access to product '{}'.",
+          product_id
+        ),
+      )),
+    }
+  }
+
+  /// Checks the token has the scope `scope`, with 403 naming the missing scope otherwise
+  pub fn require_scope(&self, scope: ServiceScope) -> Result<(), ApiError> {
+    match self.scopes.contains(&scope) {
+      true => Ok(()),
+      false => Err(
+        ApiError::new(
+          Status::Forbidden,
+          format!("Error. Missing the '{}' scope of service accounts.", scope.name()),
+        )
+        .with_details(json!({ "scope": scope.name() })),
+      ),
+    }
+  }
+
+  /// Checks a scope of the token grants the role permission `permission`, with 403 naming the missing permission
+  /// otherwise
+  pub fn require_permission(&self, permission: Permission) -> Result<(), ApiError> {
+    match self.scopes.iter().any(|x| x.grants(permission)) {
+      true => Ok(()),
+      false => Err(
+        ApiError::new(
+          Status::Forbidden,
+          format!(
+            "Error. Missing permission '{}', which the scopes of the service account don't grant.",
+            permission.name()
+          ),
+        )
+        .with_details(json!({ "permission": permission.name() })),
+      ),
+    }
+  }
+
+  /// Looks up the service account of the request, if it sent a service account token
+  pub async fn from_header(request: &Request<'_>) -> Option<Result<ServiceAccountAuth, ServiceAccountError>> {
+    let authorization = request.headers().get_one("Authorization")?.trim();
+    let (scheme, token) = authorization.split_once(' ')?;
+    let token = token.trim();
+    if !scheme.eq_ignore_ascii_case("bearer") || !token.starts_with(TOKEN_PREFIX) {
+      return None;
+    }
+
+    let database_connection = match request.rocket().state::<ConnectionManager>() {
+      Some(value) => value,
+      None => return Some(Err(ServiceAccountError::Unavailable)),
+    };
+
+    Some(
+      match database_connection
+        .get_service_account_by_hash(&api_keys::hash(token))
+        .await
+      {
+        Ok(Some(service_account)) if service_account.is_active() => Ok(ServiceAccountAuth {
+          service_account_id: service_account.oid.map(|x| x.to_hex()).unwrap_or_default(),
+          product_ids: service_account.product_ids,
+          scopes: service_account.scopes,
+        }),
+        Ok(_) => Err(ServiceAccountError::Invalid),
+        Err(_) => Err(ServiceAccountError::Unavailable),
+      },
+    )
+  }
+}
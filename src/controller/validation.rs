@@ -0,0 +1,295 @@
+//! Validation of request bodies
+//!
+//! Bodies taken as `Valid<T>` are parsed from JSON and checked before the route runs. Invalid bodies are answered 422,
+//! listing every violation in the error's details:
+//!
+//! ```json
+//! {"code": "unprocessable_entity", "message": "Error. The request body is invalid.",
+//!  "details": {"violations": [{"field": "release_type", "message": "Percentage must be between 0 and 100, got 120."}]}}
+//! ```
+
+use std::ops::Deref;
+
+use evaluation::{EvaluationContext, ReleaseType, Rule};
+use rocket::data::{self, Data, FromData};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::Request;
+use rocket::serde::json::{self, json, Json};
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::RequestBody;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use rocket_okapi::request::OpenApiFromData;
+
+use crate::controller::error::ApiError;
+use crate::model::freeze::FreezeWindow;
+use crate::model::schedule::FlagChange;
+use crate::model::settings::SettingsOverrides;
+
+/// Longest name of a flag or product
+pub const MAX_NAME_LENGTH: usize = 100;
+
+/// Single problem with a request
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct Violation {
+  /// Path of the invalid field (e.g. `rules[2].condition`), empty if the problem is with the whole body
+  pub field: String,
+  /// Human readable description of the problem
+  pub message: String,
+}
+
+/// Problems found while validating a request
+#[derive(Debug, Default)]
+pub struct Violations {
+  /// Path of the value being validated, prefixed to the fields of its violations
+  prefix: String,
+  list: Vec<Violation>,
+}
+
+impl Violations {
+  /// Records a problem with `field` of the value being validated
+  pub fn add(&mut self, field: &str, message: impl Into<String>) {
+    self.list.push(Violation {
+      field: self.path(field),
+      message: message.into(),
+    });
+  }
+
+  /// Records a problem with `field` unless `valid`
+  pub fn check(&mut self, valid: bool, field: &str, message: impl Into<String>) {
+    if !valid {
+      self.add(field, message);
+    }
+  }
+
+  /// Records the problem of a `Result` returned by an existing check (e.g. `Condition::validate`), if any
+  pub fn check_result(&mut self, result: Result<(), String>, field: &str) {
+    if let Err(message) = result {
+      self.add(field, message);
+    }
+  }
+
+  /// Validates `value`, a field (or element, with a `[index]` field) of the value being validated
+  pub fn nested<T: Validate + ?Sized>(&mut self, field: &str, value: &T) {
+    let prefix = self.path(field);
+    let prefix = std::mem::replace(&mut self.prefix, prefix);
+    value.validate(self);
+    self.prefix = prefix;
+  }
+
+  /// Returns true if no problem was found
+  pub fn is_empty(&self) -> bool {
+    self.list.is_empty()
+  }
+
+  /// Problems found joined into one description, for responses that report a single message per item
+  pub fn summary(&self) -> String {
+    self
+      .list
+      .iter()
+      .map(|x| match x.field.as_str() {
+        "" => x.message.clone(),
+        field => format!("{}: {}", field, x.message),
+      })
+      .collect::<Vec<String>>()
+      .join(" ")
+  }
+
+  /// Returns a 422 error listing the problems found, if any
+  pub fn into_result(self) -> Result<(), ApiError> {
+    match self.is_empty() {
+      true => Ok(()),
+      false => Err(invalid(self.list)),
+    }
+  }
+
+  fn path(&self, field: &str) -> String {
+    match (self.prefix.is_empty(), field.is_empty() || field.starts_with('[')) {
+      (true, _) => field.to_string(),
+      (false, true) => format!("{}{}", self.prefix, field),
+      (false, false) => format!("{}.{}", self.prefix, field),
+    }
+  }
+}
+
+/// Something a request can be checked for problems with before it's acted on
+pub trait Validate {
+  /// Records every problem with the value in `violations`
+  fn validate(&self, violations: &mut Violations);
+
+  /// Returns every problem with the value
+  fn violations(&self) -> Violations {
+    let mut violations = Violations::default();
+    self.validate(&mut violations);
+    violations
+  }
+}
+
+impl<T: Validate> Validate for Vec<T> {
+  fn validate(&self, violations: &mut Violations) {
+    for (index, value) in self.iter().enumerate() {
+      violations.nested(&format!("[{}]", index), value);
+    }
+  }
+}
+
+/// Returns the 422 error reporting `violations`
+pub fn invalid(violations: Vec<Violation>) -> ApiError {
+  ApiError::new(Status::UnprocessableEntity, "Error. The request body is invalid.")
+    .with_details(json!({ "violations": violations }))
+}
+
+/// Violations of a body that failed validation, stored in the request local cache for the 422 catcher
+pub struct Invalid(pub Vec<Violation>);
+
+/// Request body parsed from JSON and validated, failing the request with 422 if it has any violation
+pub struct Valid<T>(pub T);
+
+impl<T> Valid<T> {
+  /// Consumes the wrapper, returning the validated body
+  pub fn into_inner(self) -> T {
+    self.0
+  }
+}
+
+impl<T> Deref for Valid<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.0
+  }
+}
+
+/// Why a body couldn't be taken as `Valid<T>`
+#[derive(Debug)]
+pub enum ValidationError<'r> {
+  /// The body isn't JSON of the expected shape, logged by Rocket when the guard fails
+  Parse(#[allow(dead_code)] json::Error<'r>),
+  /// The body was parsed but has violations
+  Invalid,
+}
+
+#[rocket::async_trait]
+impl<'r, T: Deserialize<'r> + Validate> FromData<'r> for Valid<T> {
+  type Error = ValidationError<'r>;
+
+  async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+    let body = match Json::<T>::from_data(request, data).await {
+      Outcome::Success(body) => body.into_inner(),
+      Outcome::Failure((status, e)) => return Outcome::Failure((status, ValidationError::Parse(e))),
+      Outcome::Forward(data) => return Outcome::Forward(data),
+    };
+
+    let violations = body.violations();
+    if violations.is_empty() {
+      return Outcome::Success(Valid(body));
+    }
+
+    request.local_cache(|| Invalid(violations.list));
+    Outcome::Failure((Status::UnprocessableEntity, ValidationError::Invalid))
+  }
+}
+
+impl<'r, T: Deserialize<'r> + Validate + JsonSchema> OpenApiFromData<'r> for Valid<T> {
+  fn request_body(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<RequestBody> {
+    Json::<T>::request_body(gen)
+  }
+}
+
+/// Checks a flag name is present, not too long, and only made of letters, digits, `-`, `_` and `.` so it can be used in
+/// paths
+pub fn check_flag_name(violations: &mut Violations, field: &str, name: &str) {
+  if name.is_empty() {
+    violations.add(field, "Flag names can't be empty.");
+    return;
+  }
+
+  violations.check(
+    name.chars().count() <= MAX_NAME_LENGTH,
+    field,
+    format!("Flag names can't be longer than {} characters.", MAX_NAME_LENGTH),
+  );
+  violations.check(
+    name
+      .chars()
+      .all(|x| x.is_ascii_alphanumeric() || matches!(x, '-' | '_' | '.')),
+    field,
+    "Flag names can only contain letters, digits, '-', '_' and '.'.",
+  );
+}
+
+/// Checks a product name isn't blank or too long
+pub fn check_product_name(violations: &mut Violations, field: &str, name: &str) {
+  violations.check(!name.trim().is_empty(), field, "Product names can't be empty.");
+  violations.check(
+    name.chars().count() <= MAX_NAME_LENGTH,
+    field,
+    format!("Product names can't be longer than {} characters.", MAX_NAME_LENGTH),
+  );
+}
+
+/// Checks a percentage is between 0 and 100
+pub fn check_percentage(violations: &mut Violations, field: &str, percentage: f32) {
+  violations.check(
+    (0.0..=100.0).contains(&percentage),
+    field,
+    format!("Percentage must be between 0 and 100, got {}.", percentage),
+  );
+}
+
+impl Validate for ReleaseType {
+  fn validate(&self, violations: &mut Violations) {
+    if let ReleaseType::Percentage(percentage, _, _) = self {
+      check_percentage(violations, "", *percentage);
+    }
+  }
+}
+
+impl Validate for Rule {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check_result(self.condition.validate(), "condition");
+  }
+}
+
+impl Validate for EvaluationContext {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check(
+      self.user.as_deref().is_none_or(|x| !x.trim().is_empty()),
+      "user",
+      "Users can't be empty.",
+    );
+    violations.check(
+      self.key.as_deref().is_none_or(|x| !x.trim().is_empty()),
+      "key",
+      "Keys can't be empty.",
+    );
+  }
+}
+
+impl Validate for FlagChange {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check_result(FlagChange::validate(self), "");
+  }
+}
+
+impl Validate for FreezeWindow {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check_result(FreezeWindow::validate(self), "");
+  }
+}
+
+impl Validate for SettingsOverrides {
+  fn validate(&self, violations: &mut Violations) {
+    if let Some(step) = self.max_rollout_step {
+      check_percentage(violations, "max_rollout_step", step);
+    }
+  }
+}
+
+/// Lists of IDs (e.g. users or flag names), which can't have blank entries
+impl Validate for String {
+  fn validate(&self, violations: &mut Violations) {
+    violations.check(!self.trim().is_empty(), "", "Can't be empty.");
+  }
+}
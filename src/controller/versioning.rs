@@ -0,0 +1,112 @@
+//! Versioning of the API
+//!
+//! Routes are served under `/api/v1`, so breaking changes to request and response shapes can ship as `/api/v2`. They
+//! are also still served at their original unversioned paths as deprecated aliases, so existing clients keep working
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response, Route};
+use rocket_okapi::okapi::openapi3::OpenApi;
+
+/// Prefix of version 1 of the API
+pub const API_V1: &str = "/api/v1";
+
+/// Prefix of the names of routes mounted as deprecated aliases
+const LEGACY_PREFIX: &str = "legacy:";
+
+/// Returns the path of the route replacing a deprecated route (under `API_V1`) from the path of a request to it
+type Successor = fn(&str) -> String;
+
+/// Names of deprecated routes, and how the paths of the routes replacing them are found
+const DEPRECATED_ROUTES: &[(&str, Successor)] = &[
+  ("login_with_path", |_| "/login".to_string()),
+  ("hoist_with_email", parent_path),
+  ("lower_with_email", parent_path),
+];
+
+/// Path without its last segment (e.g. `/hoist/<product_id>/<feature>` for `/hoist/<product_id>/<feature>/<email>`)
+fn parent_path(path: &str) -> String {
+  match path.rsplit_once('/') {
+    Some((parent, _)) => parent.to_string(),
+    None => path.to_string(),
+  }
+}
+
+/// Marks the operations of deprecated routes in the OpenAPI spec as deprecated
+pub fn mark_deprecated(spec: &mut OpenApi) {
+  for operation in spec.paths.values_mut().flat_map(|x| {
+    [&mut x.get, &mut x.put, &mut x.post, &mut x.delete, &mut x.patch]
+      .into_iter()
+      .flatten()
+  }) {
+    if DEPRECATED_ROUTES
+      .iter()
+      .any(|(name, _)| operation.operation_id.as_deref() == Some(name))
+    {
+      operation.deprecated = true;
+    }
+  }
+}
+
+/// Returns copies of `routes` to mount at their deprecated unversioned paths
+pub fn legacy_aliases(routes: &[Route]) -> Vec<Route> {
+  routes
+    .iter()
+    .cloned()
+    .map(|mut route| {
+      route.name = Some(format!("{}{}", LEGACY_PREFIX, route.name.as_deref().unwrap_or_default()).into());
+      route
+    })
+    .collect()
+}
+
+/// Fairing marking responses to deprecated unversioned paths with a `Deprecation` header and a `Link` to the
+/// versioned path. Responses of deprecated routes get the same headers, linking to the route replacing them instead
+pub struct LegacyPaths;
+
+#[rocket::async_trait]
+impl Fairing for LegacyPaths {
+  fn info(&self) -> Info {
+    Info {
+      name: "Legacy Paths",
+      kind: Kind::Response,
+    }
+  }
+
+  async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+    let route = match request.route() {
+      Some(route) => route,
+      None => return,
+    };
+
+    // Path under where the route is mounted. Aliases are mounted at the parent of what their versioned paths are
+    // mounted at (e.g. `/api/info` for `/info`)
+    let path = request.uri().path().as_str();
+    let path = match route.uri.base() {
+      "/" => path,
+      base => path.strip_prefix(base).unwrap_or(path),
+    };
+
+    let name = route.name.as_deref().unwrap_or_default();
+    let legacy = name.starts_with(LEGACY_PREFIX);
+    let name = name.strip_prefix(LEGACY_PREFIX).unwrap_or(name);
+    if let Some((_, successor)) = DEPRECATED_ROUTES.iter().find(|(deprecated, _)| *deprecated == name) {
+      response.set_header(Header::new("Deprecation", "true"));
+      response.set_header(Header::new(
+        "Link",
+        format!("<{}{}>; rel=\"successor-version\"", API_V1, successor(path)),
+      ));
+      return;
+    }
+
+    if !legacy {
+      return;
+    }
+
+    response.set_header(Header::new("Deprecation", "true"));
+    response.set_header(Header::new(
+      "Link",
+      format!("<{}{}>; rel=\"successor-version\"", API_V1, path),
+    ));
+  }
+}
@@ -0,0 +1,310 @@
+//! Delivery of changes to flags and products to webhooks
+//!
+//! Every change is POSTed as JSON to the webhooks accepting it, with the headers:
+//! * `X-Webhook-Event`     - type of the event (e.g. `flag.updated`, `webhook.test` for test events)
+//! * `X-Webhook-Delivery`  - unique ID of the delivery, the same for every attempt
+//! * `X-Webhook-Timestamp` - Unix time of the attempt, in seconds
+//! * `X-Webhook-Signature` - `sha256=` followed by the hex HMAC-SHA256 of `<timestamp>.<body>`, keyed by the secret
+//!
+//! Deliveries the destination doesn't answer with a 2xx status are retried with exponential backoff. Deliveries still
+//! failing after `MAX_ATTEMPTS` are kept as dead letters until they're retried through the API
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use mongodb::bson::oid::ObjectId;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::redirect::Policy;
+use reqwest::StatusCode;
+use rocket::serde::json::{json, Value};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::controller::case::{self, JsonCase};
+use crate::controller::database::ConnectionManager;
+use crate::controller::events::ChangeEvent;
+use crate::controller::info::BuildInfo;
+use crate::log;
+use crate::model::webhook::{DeliveryStatus, Webhook, WebhookDelivery};
+
+/// Attempts made to deliver an event before it's a dead letter
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// Type of the synthetic events sent to test webhooks
+pub const TEST_EVENT: &str = "webhook.test";
+
+/// Wait before the first retry, doubled for every retry after it
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Longest a destination may take to respond before the attempt fails
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bytes of the destination's response body kept with the delivery
+const MAX_RESPONSE_BODY: usize = 1024;
+
+/// Body POSTed to webhooks
+#[derive(Serialize)]
+struct Payload<'a> {
+  /// Unique ID of the delivery
+  id: String,
+  /// Type of the event (e.g. `flag.updated`)
+  #[serde(rename = "type")]
+  event: &'a str,
+  /// If the event is a synthetic one sent to test the webhook, rather than an actual change
+  test: bool,
+  /// When the event was sent
+  timestamp: DateTime<Utc>,
+  /// Unique ID of the changed product, or of the product of the changed flag
+  product_id: Option<&'a str>,
+  /// The changed entity, or the ID of the deleted one
+  data: Value,
+  /// Build of the service that sent the event, to correlate changes in behavior to deploys
+  service: BuildInfo,
+}
+
+/// Sends changes to the webhooks accepting them
+#[derive(Clone)]
+pub struct Dispatcher {
+  client: reqwest::Client,
+  database_connection: ConnectionManager,
+}
+
+impl Dispatcher {
+  /// Constructs a `Dispatcher` delivering the changes written through `database_connection`
+  pub fn new(database_connection: ConnectionManager) -> Dispatcher {
+    let client = reqwest::Client::builder()
+      .timeout(REQUEST_TIMEOUT)
+      .redirect(Policy::none())
+      .build()
+      .unwrap_or_default();
+
+    Dispatcher {
+      client,
+      database_connection,
+    }
+  }
+
+  /// Delivers changes as they're published, forever
+  pub async fn run(self) {
+    let mut events = self.database_connection.subscribe();
+
+    loop {
+      match events.recv().await {
+        Ok(event) => self.dispatch(&event).await,
+        Err(RecvError::Lagged(missed)) => log!("Error. Webhooks fell behind and missed {} changes", missed),
+        Err(RecvError::Closed) => return,
+      }
+    }
+  }
+
+  /// Starts delivering `event` to every webhook accepting it, in the background
+  async fn dispatch(&self, event: &ChangeEvent) {
+    let product_id = event.product_id();
+    let kind = event.kind();
+
+    // The error is logged, and the event isn't delivered
+    let webhooks = self.database_connection.get_webhooks(None).await.unwrap_or_default();
+
+    for webhook in webhooks {
+      if !webhook.accepts(product_id, kind) {
+        continue;
+      }
+
+      let data = match event {
+        ChangeEvent::FlagUpdated(flag) => json!({ "flag": webhook.user_identifiers.apply(flag) }),
+        ChangeEvent::FlagDeleted { flag_id, .. } => json!({ "flag_id": flag_id }),
+        ChangeEvent::ProductUpdated(product) => {
+          json!({ "product": webhook.user_identifiers.apply_to_product(product) })
+        }
+        ChangeEvent::ProductDeleted { .. } => json!({}),
+      };
+
+      if let Some(delivery) = self
+        .create_delivery(&webhook, kind.name(), Some(product_id), data)
+        .await
+      {
+        tokio::spawn(self.clone().deliver(webhook, delivery));
+      }
+    }
+  }
+
+  /// Sends a synthetic test event to `webhook` through the same signing, retries, and logging as changes
+  ///
+  /// Returns the delivery after its first attempt, so the destination's response can be shown. Failed attempts are
+  /// retried in the background
+  pub async fn test(&self, webhook: Webhook) -> Option<WebhookDelivery> {
+    let data = json!({ "message": "Test event, sent to check the webhook receives deliveries. Nothing changed." });
+    let delivery = self
+      .create_delivery(&webhook, TEST_EVENT, webhook.product_id.as_deref(), data)
+      .await?;
+
+    Some(self.send_now(webhook, delivery).await)
+  }
+
+  /// Delivers `delivery` (e.g. a dead letter) again, with a fresh set of attempts
+  ///
+  /// Returns the delivery after its first attempt, later attempts are made in the background
+  pub async fn retry(&self, webhook: Webhook, mut delivery: WebhookDelivery) -> WebhookDelivery {
+    delivery.status = DeliveryStatus::Pending;
+    delivery.attempts = 0;
+
+    self.send_now(webhook, delivery).await
+  }
+
+  /// Makes the first attempt of `delivery`, leaving the retries to the background
+  async fn send_now(&self, webhook: Webhook, delivery: WebhookDelivery) -> WebhookDelivery {
+    let delivery = self.attempt(&webhook, delivery).await;
+    if delivery.status == DeliveryStatus::Pending {
+      tokio::spawn(self.clone().deliver(webhook, delivery.clone()));
+    }
+
+    delivery
+  }
+
+  /// Records a pending delivery of an event to `webhook`
+  ///
+  /// Returns `None` if the payload couldn't be serialized
+  async fn create_delivery(
+    &self,
+    webhook: &Webhook,
+    event: &str,
+    product_id: Option<&str>,
+    data: Value,
+  ) -> Option<WebhookDelivery> {
+    let webhook_id = webhook.oid?.to_hex();
+    let oid = ObjectId::new();
+
+    let payload = Payload {
+      id: oid.to_hex(),
+      event,
+      test: event == TEST_EVENT,
+      timestamp: Utc::now(),
+      product_id,
+      data,
+      service: BuildInfo::current(),
+    };
+
+    let payload = match case::to_json(&payload, JsonCase::configured()) {
+      Ok(payload) => payload,
+      Err(e) => {
+        log!("Error serializing webhook payload. Error: {:?}", e);
+        return None;
+      }
+    };
+
+    // Delivered even if it couldn't be recorded, only its history is lost
+    let delivery = WebhookDelivery::new(oid, &webhook_id, event, payload);
+    let _ = self.database_connection.create_webhook_delivery(&delivery).await;
+
+    Some(delivery)
+  }
+
+  /// Attempts `delivery` until it's delivered or dead, waiting longer between every attempt
+  async fn deliver(self, webhook: Webhook, mut delivery: WebhookDelivery) {
+    while delivery.status == DeliveryStatus::Pending {
+      if delivery.attempts > 0 {
+        tokio::time::sleep(FIRST_RETRY_DELAY * 2u32.pow(delivery.attempts - 1)).await;
+      }
+
+      delivery = self.attempt(&webhook, delivery).await;
+    }
+  }
+
+  /// Makes one attempt of `delivery` and records its outcome
+  async fn attempt(&self, webhook: &Webhook, mut delivery: WebhookDelivery) -> WebhookDelivery {
+    delivery.attempts += 1;
+    delivery.attempted_at = Some(mongodb::bson::DateTime::now());
+
+    match self.post(webhook, &delivery).await {
+      Ok((status, body)) => {
+        delivery.response_status = Some(status.as_u16());
+        delivery.response_body = Some(body);
+        delivery.error = match status.is_success() {
+          true => None,
+          false => Some(format!("Error. Destination responded with {}.", status)),
+        };
+      }
+      Err(e) => {
+        delivery.response_status = None;
+        delivery.response_body = None;
+        delivery.error = Some(format!("Error. {}", e));
+      }
+    }
+
+    delivery.status = match delivery.error {
+      None => DeliveryStatus::Delivered,
+      Some(_) if delivery.attempts >= MAX_ATTEMPTS => DeliveryStatus::Dead,
+      Some(_) => DeliveryStatus::Pending,
+    };
+
+    if delivery.status == DeliveryStatus::Dead {
+      log!(
+        "Error. Delivery '{}' to webhook '{}' failed {} times and is a dead letter",
+        delivery.oid,
+        delivery.webhook_id,
+        delivery.attempts
+      );
+    }
+
+    let _ = self.database_connection.update_webhook_delivery(&delivery).await;
+
+    delivery
+  }
+
+  /// POSTs the signed payload of `delivery` to `webhook`
+  ///
+  /// Returns the status and start of the body of the response
+  async fn post(&self, webhook: &Webhook, delivery: &WebhookDelivery) -> reqwest::Result<(StatusCode, String)> {
+    let timestamp = Utc::now().timestamp();
+
+    let mut response = self
+      .client
+      .post(&webhook.url)
+      .header(CONTENT_TYPE, "application/json")
+      .header("X-Webhook-Event", &delivery.event)
+      .header("X-Webhook-Delivery", delivery.oid.to_hex())
+      .header("X-Webhook-Timestamp", timestamp.to_string())
+      .header(
+        "X-Webhook-Signature",
+        signature(&webhook.secret, timestamp, &delivery.payload),
+      )
+      .body(delivery.payload.clone())
+      .send()
+      .await?;
+
+    let status = response.status();
+
+    let mut body = vec![];
+    while body.len() < MAX_RESPONSE_BODY {
+      match response.chunk().await? {
+        Some(chunk) => body.extend_from_slice(&chunk),
+        None => break,
+      }
+    }
+    body.truncate(MAX_RESPONSE_BODY);
+
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+  }
+}
+
+/// Signature of a payload sent at `timestamp`, as sent in `X-Webhook-Signature`
+///
+/// The timestamp is signed too so receivers can reject old deliveries replayed at them
+fn signature(secret: &str, timestamp: i64, payload: &str) -> String {
+  let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+    Ok(mac) => mac,
+    Err(_) => return String::new(),
+  };
+  mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+
+  let hash: String = mac
+    .finalize()
+    .into_bytes()
+    .iter()
+    .map(|x| format!("{:02x}", x))
+    .collect();
+
+  format!("sha256={}", hash)
+}
@@ -5,506 +5,5405 @@ extern crate rocket;
 
 mod controller;
 mod model;
+#[cfg(test)]
+mod tests;
 
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 
-use rocket::http::{Cookie, CookieJar};
-use rocket::response::status;
-use rocket::serde::json::Json;
-use rocket::State;
+use chrono::{DateTime, Duration, Utc};
+use evaluation::{release, rule, Evaluable, EvaluationContext, Rule};
+use rocket::data::{Data, ToByteUnit};
+use rocket::fairing::AdHoc;
+use rocket::form::Form;
 use rocket::fs::NamedFile;
+use rocket::futures::stream::{self, BoxStream, StreamExt};
+use rocket::http::{ContentType, Cookie, CookieJar, SameSite, Status};
+use rocket::response::stream::TextStream;
+use rocket::response::{status, Redirect};
+use rocket::serde::json::{json, Json, Value};
+use rocket::State;
+use rocket_okapi::okapi::openapi3::Server;
+use rocket_okapi::settings::OpenApiSettings;
 use rocket_okapi::swagger_ui::{self, SwaggerUIConfig};
-use rocket_okapi::{openapi, openapi_get_routes};
+use rocket_okapi::{openapi, openapi_get_routes_spec};
 
-use controller::authentication::{AuthTokens, UserAuth};
-use controller::database::ConnectionManager;
-use controller::response::{Created, FlagCheck};
+use controller::allowlist::AdminAllowlist;
+use controller::api_keys::{self, ApiKeyAuth, CheckAuth};
+use controller::authentication::{self, Credentials, UserAgent, UserAuth, AUTH_TOKEN, USER_ID};
+use controller::backup;
+use controller::cache::{CachePolicy, Cached};
+use controller::case::CasedJson;
+use controller::cors::CheckCors;
+use controller::csrf::{self, CsrfGuard};
+use controller::database::error::StorageResult;
+use controller::database::{AuditFilter, ConnectionManager, FlagFilter, FlagProjection, FlagSort, Page, ReleaseKind};
+use controller::docs::{DocsAccess, DocsGuard};
+use controller::encoding::Negotiated;
+use controller::error::{self, ApiError};
+use controller::evaluations::{self, EvaluationCounter};
+use controller::export::{ExportFormat, ImportReport, ProductExport, MAX_IMPORT_SIZE};
+use controller::info::BuildInfo;
+use controller::live;
+use controller::load_shedding::LoadShedder;
+use controller::login_throttle::LoginThrottle;
+use controller::membership::{self, ProductMember};
+use controller::oidc::{self, OidcConfig};
+use controller::password_policy::PasswordPolicy;
+use controller::plugin::{LoadedPlugin, PluginHost, MAX_PLUGIN_SIZE};
+use controller::rate_limit::RateLimiter;
+use controller::request::{
+  ApiKeyRequest, CheckRequest, CommentRequest, FlagUpdateRequest, LoginRequest, OverridesRequest,
+  PasswordChangeRequest, PreviewRequest, ProductUpdateRequest, SamlPost, ScheduleChangeRequest, ServiceAccountRequest,
+  SetupRequest, UserStatusRequest, UserUpdateRequest, WebhookRequest,
+};
+use controller::request_id::{self, RequestIds};
+use controller::response::{
+  AuditPage, BatchFlagCheck, BucketPreview, BulkCreatedFlag, Created, CreatedApiKey, CreatedServiceAccount,
+  DependencyHealth, FlagCheck, Health, HealthStatus, LoggedIn, PreviewResult, ProductDeletion, SdkSnapshot, SearchKind,
+  SearchResult, SessionsRevoked, SnapshotFlag, StaleFlag,
+};
+use controller::saml::{self, SamlConfig};
+use controller::scheduler;
+use controller::scopes::{self, ActorAuth, Scoped};
+use controller::secrets;
+use controller::service_accounts;
+use controller::validation::{self, Valid, Validate, Violations};
+use controller::versioning::{self, LegacyPaths, API_V1};
+use controller::webhooks::Dispatcher;
+use model::api_key::{ApiKey, SpecSafeApiKey};
+use model::audit::{AuditAction, AuditEntry, EntityType};
+use model::comment::{Comment, SpecSafeComment, MAX_COMMENT_LENGTH};
+use model::dependency::{self, DependencyReport};
 use model::flag::{FeatureFlag, ReleaseType, SpecSafeFeatureFlag};
+use model::freeze::FreezeWindow;
+use model::plugin::{Plugin, SpecSafePlugin};
 use model::product::{Product, SpecSafeProduct};
-use model::user::{AccountType, SpecSafeUser, User};
-
-const USER_ID: &str = "user_id";
-const AUTH_TOKEN: &str = "auth_token";
+use model::schedule::{ScheduledChange, SpecSafeScheduledChange};
+use model::service_account::{ServiceAccount, ServiceScope, SpecSafeServiceAccount};
+use model::settings::{EffectiveSettings, SettingsOverrides};
+use model::stats::Stats;
+use model::user::{AccountType, Permission, Role, SpecSafeUser, User};
+use model::webhook::{DeliveryStatus, SpecSafeWebhook, SpecSafeWebhookDelivery, Webhook};
 
 #[openapi(skip)]
 #[get("/<file..>", rank = 10)]
-async fn index(file: PathBuf) -> Result<NamedFile, std::io::Error> {
-  NamedFile::open(Path::new("../hoist-the-colors/build").join(file)).await
+async fn index(file: PathBuf) -> Option<NamedFile> {
+  NamedFile::open(Path::new("../hoist-the-colors/build").join(file))
+    .await
+    .ok()
+}
+
+/// Target of requests shed by the `LoadShedder` while the service is overloaded
+#[openapi(skip)]
+#[get("/__load_shed")]
+async fn load_shed() -> Status {
+  Status::ServiceUnavailable
+}
+
+/// Target of requests limited by the `RateLimiter`
+#[openapi(skip)]
+#[get("/__rate_limited")]
+async fn rate_limited() -> Status {
+  Status::TooManyRequests
+}
+
+/// Target of administrative requests refused by the `AdminAllowlist`, made from outside the allowed ranges
+#[openapi(skip)]
+#[get("/__network_forbidden")]
+async fn network_forbidden() -> ApiError {
+  ApiError::new(Status::Forbidden, "Error. Changes aren't allowed from this network.")
+}
+
+/// Target of requests changing something with the login cookies but without their CSRF token, refused by the
+/// `CsrfGuard`
+#[openapi(skip)]
+#[get("/__csrf_forbidden")]
+async fn csrf_forbidden() -> ApiError {
+  ApiError::new(
+    Status::Forbidden,
+    format!(
+      "Error. Missing or incorrect CSRF token, send the '{}' cookie in the '{}' header.",
+      csrf::CSRF_COOKIE,
+      csrf::CSRF_HEADER
+    ),
+  )
+}
+
+/// Target of requests for the API docs made without logging in, while they're restricted to developers
+#[openapi(skip)]
+#[get("/__docs_unauthorized")]
+async fn docs_unauthorized() -> Status {
+  Status::Unauthorized
+}
+
+/// Target of requests for the API docs made by users who aren't developers, while they're restricted to developers
+#[openapi(skip)]
+#[get("/__docs_forbidden")]
+async fn docs_forbidden() -> Status {
+  Status::Forbidden
+}
+
+/// Reports if the service and its dependencies are healthy, for load balancers and orchestrators
+///
+/// Returns 503 if a dependency (e.g. the database) is unreachable
+#[openapi(skip)]
+#[get("/healthz")]
+async fn healthz(database_connection: &State<ConnectionManager>) -> status::Custom<CasedJson<Health>> {
+  health_report(database_connection).await
+}
+
+/// Reports if the instance is ready to serve requests, for readiness probes taking it out of rotation while its
+/// dependencies are unreachable
+///
+/// Returns 503 if a dependency (e.g. the database) is unreachable
+#[openapi(skip)]
+#[get("/readyz")]
+async fn readyz(database_connection: &State<ConnectionManager>) -> status::Custom<CasedJson<Health>> {
+  health_report(database_connection).await
+}
+
+/// Checks the dependencies of the service, 200 if they're all up and 503 otherwise
+async fn health_report(database_connection: &ConnectionManager) -> status::Custom<CasedJson<Health>> {
+  let health = database_connection.health().await;
+
+  let database = DependencyHealth {
+    name: "database".to_string(),
+    status: if health.up {
+      HealthStatus::Up
+    } else {
+      HealthStatus::Down
+    },
+    latency_ms: health.latency.as_millis() as u64,
+  };
+
+  let (code, status) = match database.status {
+    HealthStatus::Up => (Status::Ok, HealthStatus::Up),
+    HealthStatus::Down => (Status::ServiceUnavailable, HealthStatus::Down),
+  };
+
+  status::Custom(
+    code,
+    CasedJson(Health {
+      status,
+      dependencies: vec![database],
+    }),
+  )
 }
 
 /// Checks a product's flag to see if it is enabled
 ///
-/// Optionally can provide a user for flags that use limited/percentage release
+/// Optionally can provide a user for flags that use limited/percentage release, and attributes (e.g.
+/// `attributes.app_version=1.2.0`) for flags with targeting rules. Subjects without a user (e.g. logged out visitors)
+/// can provide an anonymous key instead, percentage releases are bucketed on it but it never has to exist as a user
+///
+/// If the product is in strict mode and the user doesn't exist (or isn't a member of the product), the flag is
+/// reported as disabled with the `UNKNOWN_USER` reason instead of being evaluated
+///
+/// An API key of the product can be sent in `X-Api-Key`, and must be when `REQUIRE_API_KEYS` is set. Returns 401 if the
+/// key is invalid and 403 if it's a key of another product, like every `/check` route
+///
+/// Results can be cached for the flag's effective `cache_ttl_seconds`, see the `Cache-Control` header. Send
+/// `Accept: application/msgpack` or `Accept: application/protobuf` for a MessagePack or Protobuf body
 ///
 /// # Parameters
 /// * **product_id** - Unique ID of the product that the feature flag belongs to
 /// * **feature**    - Name of the feature flag
 /// * **user**       - *(optional)* unique ID of the user to evaluate the flag with
+/// * **key**        - *(optional)* anonymous key (e.g. a device or session ID) used when there's no user
+/// * **attributes** - *(optional)* attributes of the subject used by targeting rules
 #[openapi(tag = "Flags")]
-#[get("/check/<product_id>/<feature>/with?<user>")]
+#[get("/check/<product_id>/<feature>/with?<user>&<key>&<attributes>")]
+#[allow(clippy::too_many_arguments)]
 async fn check(
   product_id: &str,
   feature: &str,
   user: Option<&str>,
+  key: Option<&str>,
+  attributes: HashMap<String, String>,
   database_connection: &State<ConnectionManager>,
-) -> Option<Json<FlagCheck>> {
-  match database_connection.get_feature_flag(product_id, feature).await {
-    Some(response) => {
-      if response.evaluate(user) {
-        return FlagCheck::get_enabled().await;
-      }
+  plugin_host: &State<PluginHost>,
+  evaluation_counter: &State<EvaluationCounter>,
+  check_auth: CheckAuth,
+) -> Result<Option<Cached<Negotiated<FlagCheck>>>, ApiError> {
+  check_auth.authorize(product_id)?;
+
+  let product = database_connection.get_product_by_id(product_id).await?;
+  let product_settings = product.as_ref().map(|x| x.settings.clone()).unwrap_or_default();
+
+  if let Some(user_id) = user {
+    if !is_known_user(product_id, user_id, database_connection).await? {
+      let settings = EffectiveSettings::resolve(&product_settings, None, &SettingsOverrides::default());
+      return Ok(Some(Cached::new(
+        Negotiated(FlagCheck::unknown_user()),
+        CachePolicy::from_settings(&settings),
+      )));
     }
-    None => return None,
   }
 
-  FlagCheck::get_disabled().await
-}
-
-/// Hoist a flag!
-///
-/// If the user is a `AccountType::Developer` then the flag is **enabled** globally
-///
-/// If the user is a `AccountType::Client` then the flag is **enabled** for that user.
-/// The user will still need to have access to the flag
-///
-/// Returns 400 if something goes wrong, 202 otherwise
-///
-/// # Parameters
-/// * **product_id** - Unique ID of the product
-/// * **feature**    - Name of the feature
-/// * **user_email** - email of the user hoisting the flag
-#[openapi(tag = "Flags")]
-#[patch("/hoist/<product_id>/<feature>/<user_email>")]
-async fn hoist(
-  product_id: &str,
-  feature: &str,
-  user_email: &str,
-  database_connection: &State<ConnectionManager>,
-) -> Result<status::Accepted<()>, status::BadRequest<()>> {
-  let mut flag = match database_connection.get_feature_flag(product_id, feature).await {
+  let flag = match database_connection
+    .get_feature_flag(product_id, feature, FlagProjection::Evaluation)
+    .await?
+  {
     Some(flag) => flag,
-    None => return Err(status::BadRequest(None)),
+    None => return Ok(None),
   };
 
-  let flag_id = match flag.oid {
-    Some(oid) => oid.to_hex(),
-    None => return Err(status::BadRequest(None)),
-  };
+  let mut context = EvaluationContext::new(user, attributes).with_key(key);
+  if flag.targets_account_type() {
+    context.account_type = get_account_type(user, database_connection).await?;
+  }
 
-  let user_id: Option<String> = match database_connection.get_user(Some(user_email), None).await {
-    Some(user) => match user.account_type {
-      AccountType::Developer => None,
-      AccountType::Client => match user.oid {
-        Some(oid) => Some(oid.to_hex()),
-        None => return Err(status::BadRequest(None)),
-      },
-    },
-    None => return Err(status::BadRequest(None)),
+  let plugin = match &product {
+    Some(product) => plugin_host.load(product, database_connection).await,
+    None => None,
   };
 
-  flag.hoist(user_id);
-
-  if database_connection.update_feature_flag(&flag_id, flag).await {
-    return Ok(status::Accepted(None));
-  }
+  let settings = EffectiveSettings::resolve(&product_settings, None, &flag.settings);
+  evaluation_counter.record(product_id, 1);
 
-  Err(status::BadRequest(None))
+  Ok(Some(Cached::new(
+    Negotiated(FlagCheck::evaluate(&flag, &context, plugin.as_ref()).await),
+    CachePolicy::from_settings(&settings),
+  )))
 }
 
-/// Lower a flag
+/// Checks every flag of a product in one request
 ///
-/// If the user is a `AccountType::Developer` then the flag is **disabled** globally
+/// Returns a map of flag name to the flag's check result. Optionally can provide a user for flags that use
+/// limited/percentage release (or an anonymous key when there's no user), and attributes for flags with targeting rules
 ///
-/// If the user is a `AccountType::Client` then the flag is **disabled** for that user.
-/// The user will still need to have access to the flag
-///
-/// Returns 400 if something goes wrong, 202 otherwise
+/// Results can be cached for the shortest effective `cache_ttl_seconds` of the product's flags, see the `Cache-Control`
+/// header. The body can be MessagePack or Protobuf, like `/check`'s
 ///
 /// # Parameters
-/// * **product_id** - Unique ID of the product
-/// * **feature**    - Name of the feature
-/// * **user_email** - email of the user lowering the flag
+/// * **product_id** - Unique ID of the product to evaluate the flags of
+/// * **user**       - *(optional)* unique ID of the user to evaluate the flags with
+/// * **key**        - *(optional)* anonymous key (e.g. a device or session ID) used when there's no user
+/// * **attributes** - *(optional)* attributes of the subject used by targeting rules
 #[openapi(tag = "Flags")]
-#[patch("/lower/<product_id>/<feature>/<user_email>")]
-async fn lower(
+#[get("/check-all/<product_id>/with?<user>&<key>&<attributes>")]
+#[allow(clippy::too_many_arguments)]
+async fn check_all(
   product_id: &str,
-  feature: &str,
-  user_email: &str,
+  user: Option<&str>,
+  key: Option<&str>,
+  attributes: HashMap<String, String>,
   database_connection: &State<ConnectionManager>,
-) -> Result<status::Accepted<()>, status::BadRequest<String>> {
-  let mut flag = match database_connection.get_feature_flag(product_id, feature).await {
-    Some(flag) => flag,
-    None => return Err(status::BadRequest(Some(format!("Error. Unable to get flag: '{}'.", feature)))),
-  };
+  plugin_host: &State<PluginHost>,
+  evaluation_counter: &State<EvaluationCounter>,
+  check_auth: CheckAuth,
+) -> Result<Cached<Negotiated<HashMap<String, FlagCheck>>>, ApiError> {
+  check_auth.authorize(product_id)?;
 
-  let flag_id = match flag.oid {
-    Some(oid) => oid.to_hex(),
-    None => return Err(status::BadRequest(Some("Error. Bad object ID.".to_string()))),
+  let mut context = EvaluationContext::new(user, attributes).with_key(key);
+  let known_user = match user {
+    Some(user_id) => is_known_user(product_id, user_id, database_connection).await?,
+    None => true,
   };
 
-  let user_id: Option<String> = match database_connection.get_user(Some(user_email), None).await {
-    Some(user) => match user.account_type {
-      AccountType::Developer => None,
-      AccountType::Client => match user.oid {
-        Some(oid) => Some(oid.to_hex()),
-        None => return Err(status::BadRequest(Some("Error. Bad user object ID.".to_string()))),
+  let flags = database_connection
+    .get_feature_flags(
+      product_id,
+      FlagFilter {
+        include_archived: true,
+        ..Default::default()
       },
-    },
-    None => return Err(status::BadRequest(Some(format!("Error. Unable to get user '{}'", user_email)))),
+      FlagProjection::Evaluation,
+    )
+    .await?;
+
+  if flags.iter().any(|x| x.targets_account_type()) {
+    context.account_type = get_account_type(user, database_connection).await?;
+  }
+
+  let product = database_connection.get_product_by_id(product_id).await?;
+  let product_settings = product.as_ref().map(|x| x.settings.clone()).unwrap_or_default();
+
+  let plugin = match &product {
+    Some(product) => plugin_host.load(product, database_connection).await,
+    None => None,
   };
 
-  flag.lower(user_id);
+  let policy = flags
+    .iter()
+    .map(|x| CachePolicy::from_settings(&EffectiveSettings::resolve(&product_settings, None, &x.settings)))
+    .reduce(CachePolicy::shortest)
+    .unwrap_or_else(|| {
+      let settings = EffectiveSettings::resolve(&product_settings, None, &SettingsOverrides::default());
+      CachePolicy::from_settings(&settings)
+    });
+
+  if known_user {
+    evaluation_counter.record(product_id, flags.len() as u64);
+  }
 
-  if database_connection.update_feature_flag(&flag_id, flag).await {
-    return Ok(status::Accepted(None));
+  let mut checks: HashMap<String, FlagCheck> = HashMap::new();
+  for flag in flags {
+    let check = if known_user {
+      FlagCheck::evaluate(&flag, &context, plugin.as_ref()).await
+    } else {
+      FlagCheck::unknown_user()
+    };
+    checks.insert(flag.name, check);
   }
 
-  Err(status::BadRequest(None))
+  Ok(Cached::new(Negotiated(checks), policy))
 }
 
-/// Gets a product given a name
+/// Evaluates a proposed definition of a flag against sample contexts without saving anything
 ///
-/// Will return 404 if no product with the given name is found
+/// Each context is evaluated with both the stored flag and the proposed one, so the effect of a change can be checked
+/// before it's made. Returns 404 if the flag isn't found and 422 listing the problems if a rule is invalid
 ///
 /// # Parameters
-/// * **name** - Name of the product
-#[openapi(tag = "Products")]
-#[get("/get/product/<name>")]
-async fn get_product(
-  name: &str,
+/// * **flag_id** - Unique ID of the flag
+/// * **preview** - Proposed flag definition and the sample contexts to evaluate it with
+#[openapi(tag = "Flags")]
+#[post("/flags/<flag_id>/preview", data = "<preview>")]
+async fn preview_flag(
+  flag_id: &str,
+  preview: Valid<PreviewRequest>,
   database_connection: &State<ConnectionManager>,
-) -> Result<Json<SpecSafeProduct>, status::NotFound<()>> {
-  let product = match database_connection.get_product(name).await {
-    Some(product) => product,
-    None => return Err(status::NotFound(())),
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<CasedJson<Vec<PreviewResult>>, ApiError> {
+  let PreviewRequest { mut flag, contexts } = preview.into_inner();
+  rule::prioritize(&mut flag.rules);
+
+  let current = match database_connection
+    .get_feature_flag_by_id(flag_id, FlagProjection::Evaluation)
+    .await?
+  {
+    Some(flag) => flag,
+    None => return Err(ApiError::not_found(format!("Error. Unable to get flag '{}'.", flag_id))),
   };
 
-  Ok(Json(product.get_spec_safe_product()))
+  flag.name = current.name.clone();
+  flag.bucket_salt = Some(current.bucket_salt().to_string());
+
+  Ok(CasedJson(
+    contexts
+      .into_iter()
+      .map(|context| {
+        let current = current.evaluate(&context).value;
+        let proposed = evaluation::evaluate(&flag, &context).value;
+        PreviewResult {
+          context,
+          current,
+          proposed,
+          changed: current != proposed,
+        }
+      })
+      .collect(),
+  ))
 }
 
-/// Gets all products that a user consumes
+/// Previews which users fall inside the current bucket of a percentage release
 ///
-/// If no products are found - this will return an empty list
+/// Only the percentage bucketing and the always include and exclude lists are checked, other reasons a user may not
+/// see a feature (e.g. targeting rules or the flag being disabled) are ignored. For flags bucketed on an attribute,
+/// **users** are values of that attribute
 ///
 /// # Parameters
-/// * **user_email** - email of a given user
-#[openapi(tag = "Products")]
-#[get("/get/products/<user_email>")]
-async fn get_products(user_email: &str, database_connection: &State<ConnectionManager>) -> Json<Vec<SpecSafeProduct>> {
-  let user = match database_connection.get_user(Some(user_email), None).await {
-    Some(value) => value,
-    None => return Json(vec![]),
+/// * **flag_id** - Unique ID of the flag
+/// * **users**   - Comma separated list of user IDs (or anonymous keys) to check
+#[openapi(tag = "Flags")]
+#[get("/flags/<flag_id>/bucket-preview?<users>")]
+async fn bucket_preview(
+  flag_id: &str,
+  users: &str,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<CasedJson<Vec<BucketPreview>>, ApiError> {
+  let flag = match database_connection
+    .get_feature_flag_by_id(flag_id, FlagProjection::Evaluation)
+    .await?
+  {
+    Some(flag) => flag,
+    None => return Err(ApiError::not_found(format!("Error. Unable to get flag '{}'.", flag_id))),
   };
 
-  let user_id = match user.account_type {
-    AccountType::Client => match user.oid {
-      Some(oid) => Some(oid.to_hex()),
-      None => return Json(vec![]),
-    },
-    AccountType::Developer => None,
+  let (percentage, allowlist) = match flag.release_type() {
+    ReleaseType::Percentage(percentage, allowlist, _) => (*percentage, allowlist),
+    _ => {
+      return Err(ApiError::bad_request(format!(
+        "Flag '{}' is not a percentage release.",
+        flag_id
+      )))
+    }
   };
 
-  Json(
-    database_connection
-      .get_products(user_id)
-      .await
-      .iter()
-      .map(|x| x.get_spec_safe_product())
-      .collect::<Vec<SpecSafeProduct>>(),
-  )
+  Ok(CasedJson(
+    users
+      .split(',')
+      .map(|x| x.trim())
+      .filter(|x| !x.is_empty())
+      .map(|user| {
+        let bucket = release::bucket(flag.bucket_salt(), user);
+        BucketPreview {
+          user: user.to_string(),
+          bucket,
+          in_bucket: bucket < percentage,
+          allowlisted: allowlist.iter().any(|x| x == user),
+          forced: if flag.always_exclude.iter().any(|x| x == user) {
+            Some(false)
+          } else if flag.always_include.iter().any(|x| x == user) {
+            Some(true)
+          } else {
+            None
+          },
+        }
+      })
+      .collect(),
+  ))
 }
 
-/// Gets a feature flag given a flag name and product ID
+/// Checks many flags, possibly across products and users, in one request
 ///
-/// Will return 404 if no flag is found matching the search
+/// The flags are fetched with a single query. Results are returned in the same order as the request, with a `null`
+/// check for flags that weren't found
 ///
 /// # Parameters
-/// * **name**       - name of the feature flag
-/// * **product_id** - unique ID of the flag's product
+/// * **checks** - List of `product_id`, `feature`, and optional `user` or anonymous `key` to check
 #[openapi(tag = "Flags")]
-#[get("/get/flag/<name>/<product_id>")]
-async fn get_flag(
-  name: &str,
-  product_id: &str,
+#[post("/check/batch", data = "<checks>")]
+async fn check_batch(
+  checks: Valid<Vec<CheckRequest>>,
   database_connection: &State<ConnectionManager>,
-) -> Result<Json<SpecSafeFeatureFlag>, status::NotFound<()>> {
-  let flag = match database_connection.get_feature_flag(product_id, name).await {
-    Some(flag) => flag,
-    None => return Err(status::NotFound(())),
-  };
+  plugin_host: &State<PluginHost>,
+  evaluation_counter: &State<EvaluationCounter>,
+  check_auth: CheckAuth,
+) -> Result<CasedJson<Vec<BatchFlagCheck>>, ApiError> {
+  let checks = checks.into_inner();
+  for request in &checks {
+    check_auth.authorize(&request.product_id)?;
+  }
 
-  Ok(Json(flag.get_spec_safe_feature_flag()))
-}
+  let keys: Vec<(String, String)> = checks
+    .iter()
+    .map(|x| (x.product_id.clone(), x.feature.clone()))
+    .collect();
 
-/// Gets all feature flags belonging to a product specified by product ID
-///
-/// Will return an empty list if no flags are found
-///
-/// # Paramaters
-/// * **product_id** - unique ID of the product
-#[openapi(tag = "Flags")]
-#[get("/get/flags/<product_id>")]
-async fn get_flags(product_id: &str, database_connection: &State<ConnectionManager>) -> Json<Vec<SpecSafeFeatureFlag>> {
-  Json(
-    database_connection
-      .get_feature_flags(product_id)
-      .await
-      .iter()
-      .map(|x| x.get_spec_safe_feature_flag())
-      .collect::<Vec<SpecSafeFeatureFlag>>(),
-  )
-}
+  let flags: HashMap<(String, String), FeatureFlag> = database_connection
+    .get_feature_flags_by_keys(&keys, FlagProjection::Evaluation)
+    .await?
+    .into_iter()
+    .map(|x| ((x.product_id.clone(), x.name.clone()), x))
+    .collect();
 
-#[openapi(tag = "Users")]
-#[get("/get/user/<user_id>")]
-async fn get_user(
-  user_id: &str,
-  database_connection: &State<ConnectionManager>,
-) -> Result<Json<SpecSafeUser>, status::NotFound<()>> {
-  let user = match database_connection.get_user(None, Some(user_id)).await {
-    Some(user) => user,
-    None => return Err(status::NotFound(())),
-  };
+  // Strict mode, account type, and plugin lookups are shared between checks of the same product and user
+  let mut known_users: HashMap<(String, String), bool> = HashMap::new();
+  let mut account_types: HashMap<String, Option<String>> = HashMap::new();
+  let mut plugins: HashMap<String, Option<LoadedPlugin>> = HashMap::new();
+  let mut evaluated: HashMap<String, u64> = HashMap::new();
+  let mut results: Vec<BatchFlagCheck> = vec![];
 
-  Ok(Json(user.get_spec_safe_user()))
-}
+  for request in checks {
+    let flag = flags.get(&(request.product_id.clone(), request.feature.clone()));
+    let mut context =
+      EvaluationContext::new(request.user.as_deref(), request.attributes).with_key(request.key.as_deref());
 
-#[openapi(tag = "Users")]
-#[get("/get/users/<account_type>")]
-async fn get_users(
-  account_type: Option<String>,
-  database_connection: &State<ConnectionManager>,
-) -> Json<Vec<SpecSafeUser>> {
-  let users = match account_type {
-    Some(account_type) => {
-      database_connection
-        .get_users(Some(AccountType::from(account_type)))
-        .await
+    if let (Some(flag), Some(user_id)) = (flag, &request.user) {
+      if flag.targets_account_type() {
+        context.account_type = match account_types.get(user_id) {
+          Some(account_type) => account_type.clone(),
+          None => {
+            let account_type = get_account_type(Some(user_id), database_connection).await?;
+            account_types.insert(user_id.clone(), account_type.clone());
+            account_type
+          }
+        };
+      }
     }
-    None => database_connection.get_users(None).await,
-  };
 
-  return Json(
-    users
-      .iter()
-      .map(|x| x.get_spec_safe_user())
-      .collect::<Vec<SpecSafeUser>>(),
-  );
+    if flag.is_some() && !plugins.contains_key(&request.product_id) {
+      let plugin = get_plugin(&request.product_id, database_connection, plugin_host).await?;
+      plugins.insert(request.product_id.clone(), plugin);
+    }
+    let plugin = plugins.get(&request.product_id).and_then(|x| x.as_ref());
+
+    let check = match (flag, &request.user) {
+      (None, _) => None,
+      (Some(flag), None) => {
+        *evaluated.entry(request.product_id.clone()).or_default() += 1;
+        Some(FlagCheck::evaluate(flag, &context, plugin).await)
+      }
+      (Some(flag), Some(user_id)) => {
+        let key = (request.product_id.clone(), user_id.clone());
+        let known_user = match known_users.get(&key) {
+          Some(known_user) => *known_user,
+          None => {
+            let known_user = is_known_user(&request.product_id, user_id, database_connection).await?;
+            known_users.insert(key, known_user);
+            known_user
+          }
+        };
+
+        if known_user {
+          *evaluated.entry(request.product_id.clone()).or_default() += 1;
+          Some(FlagCheck::evaluate(flag, &context, plugin).await)
+        } else {
+          Some(FlagCheck::unknown_user())
+        }
+      }
+    };
+
+    results.push(BatchFlagCheck {
+      product_id: request.product_id,
+      feature: request.feature,
+      user: request.user,
+      check,
+    });
+  }
+
+  for (product_id, count) in evaluated {
+    evaluation_counter.record(&product_id, count);
+  }
+
+  Ok(CasedJson(results))
 }
 
-/// Create a product with a given name
+/// Most contexts a `/check/<product_id>/<feature>/batch` request can evaluate a flag with
+const MAX_USER_BATCH: usize = 10_000;
+
+/// Checks a product's flag for many users at once, e.g. for backfilling jobs or email segmentation
 ///
-/// Can provide a list of initial users (by user ID) for the product
+/// Returns a map of user ID (or anonymous key, for contexts without a user) to the user's check result. Users are
+/// looked up with a single query. Returns 400 if a context has neither a user nor a key, or there are more than 10,000
+/// contexts, 422 if a user or key is empty or used by more than one context, and 404 if the flag isn't found
 ///
 /// # Parameters
-/// * **name**  - Name of the new product
-/// * **users** - List of initial users (send empty list if none are desired)
-#[openapi(tag = "Products")]
-#[post("/create/product/<name>", data = "<users>")]
-async fn create_product(
-  name: &str,
-  users: Json<Vec<String>>,
+/// * **product_id** - Unique ID of the product that the feature flag belongs to
+/// * **feature**    - Name of the feature flag
+/// * **contexts**   - Contexts (user or anonymous key, and attributes) to evaluate the flag with
+#[openapi(tag = "Flags")]
+#[post("/check/<product_id>/<feature>/batch", data = "<contexts>")]
+async fn check_users(
+  product_id: &str,
+  feature: &str,
+  contexts: Valid<Vec<EvaluationContext>>,
   database_connection: &State<ConnectionManager>,
-  _token_auth: UserAuth,
-) -> Result<status::Created<Json<Created>>, status::BadRequest<()>> {
-  let product_builder = Product::builder().with_name(name).with_users(users.into_inner());
+  plugin_host: &State<PluginHost>,
+  evaluation_counter: &State<EvaluationCounter>,
+  check_auth: CheckAuth,
+) -> Result<CasedJson<HashMap<String, FlagCheck>>, ApiError> {
+  check_auth.authorize(product_id)?;
 
-  let product = match database_connection.create_product(product_builder).await {
-    Some(value) => value,
-    None => return Err(status::BadRequest(None)),
+  let contexts = contexts.into_inner();
+  if contexts.len() > MAX_USER_BATCH {
+    return Err(ApiError::bad_request(format!(
+      "Error. Batches can't have more than {} contexts.",
+      MAX_USER_BATCH
+    )));
+  }
+  if contexts.iter().any(|x| x.user.is_none() && x.key.is_none()) {
+    return Err(ApiError::bad_request("Error. Every context needs a user or a key."));
+  }
+
+  // Results are keyed by user or key, contexts sharing one would overwrite each other's
+  let mut violations = Violations::default();
+  let mut seen = HashSet::new();
+  for (index, context) in contexts.iter().enumerate() {
+    let (field, id) = match (&context.user, &context.key) {
+      (Some(user_id), _) => ("user", user_id),
+      (None, Some(key)) => ("key", key),
+      (None, None) => continue,
+    };
+    violations.check(
+      seen.insert(id.as_str()),
+      &format!("[{}].{}", index, field),
+      format!("'{}' is the user or key of another context.", id),
+    );
+  }
+  violations.into_result()?;
+
+  let flag = match database_connection
+    .get_feature_flag(product_id, feature, FlagProjection::Evaluation)
+    .await?
+  {
+    Some(flag) => flag,
+    None => return Err(ApiError::not_found("Error. Flag not found.")),
   };
 
-  let product_id = match product.oid {
-    Some(oid) => oid,
-    None => return Err(status::BadRequest(None)),
+  let product = database_connection.get_product_by_id(product_id).await?;
+  let plugin = match &product {
+    Some(product) => plugin_host.load(product, database_connection).await,
+    None => None,
   };
 
-  Ok(status::Created::new(format!("/get/product/{}", product.name)).body(Json(Created::new(&product_id.to_hex()))))
+  let user_ids: Vec<String> = contexts.iter().filter_map(|x| x.user.clone()).collect();
+  let users: HashMap<String, User> = database_connection
+    .get_users_by_ids(&user_ids)
+    .await?
+    .into_iter()
+    .filter_map(|x| Some((x.oid?.to_hex(), x)))
+    .collect();
+
+  let mut evaluated = 0;
+  let mut checks: HashMap<String, FlagCheck> = HashMap::new();
+
+  for mut context in contexts {
+    let (id, check) = match context.user.clone() {
+      Some(user_id) => {
+        let user = users.get(&user_id);
+        if !is_known_to_product(product.as_ref(), &user_id, user) {
+          (user_id, FlagCheck::unknown_user())
+        } else {
+          if flag.targets_account_type() {
+            context.account_type = user.map(|x| x.account_type.name().to_string());
+          }
+          evaluated += 1;
+          (user_id, FlagCheck::evaluate(&flag, &context, plugin.as_ref()).await)
+        }
+      }
+      None => {
+        evaluated += 1;
+        let check = FlagCheck::evaluate(&flag, &context, plugin.as_ref()).await;
+        (context.key.unwrap_or_default(), check)
+      }
+    };
+
+    checks.insert(id, check);
+  }
+
+  evaluation_counter.record(product_id, evaluated);
+
+  Ok(CasedJson(checks))
 }
 
-/// Create a flag with a given name, status, the `client_toggle` enum, and release type
+/// Gets the evaluation ready definitions of every flag of a product, so server-side SDKs can evaluate flags locally
 ///
-/// The `client_toggle` enum determines if the flag can be toggled by clients
-///
-/// Leaving release type undefined will have it default to `Global`
+/// Definitions include the rules, percentages, and allowlists of the flags, archived flags included. Pass the
+/// `generated_at` of the last snapshot as `since` to only get the flags changed after it. Returns 400 if `since` isn't
+/// an RFC 3339 time and 404 if the product isn't found. Requires a server API key of the product in `X-Api-Key`. Send `Accept: application/msgpack` or
+/// `Accept: application/protobuf` for a MessagePack or Protobuf body
 ///
 /// # Parameters
-/// * **name**          - Name of the new feature flag
-/// * **product_id**    - Unique ID of product the flag belongs to
-/// * **enabled**       - If the flag is enabled (true) or not (false)
-/// * **client_toggle** - If clients can toggle flags on/off for themselves
-/// * **release_type**  - Release type enum containing relevant data to the release type
-#[openapi(tag = "Flags")]
-#[post(
-  "/create/flag/<name>/<product_id>/<enabled>/<client_toggle>",
-  data = "<release_type>"
-)]
-async fn create_flag(
-  name: &str,
+/// * **product_id** - Unique ID of the product
+/// * **since**      - *(optional)* only include flags changed after this RFC 3339 time
+#[openapi(tag = "SDK")]
+#[get("/sdk/snapshot/<product_id>?<since>")]
+async fn sdk_snapshot(
   product_id: &str,
-  enabled: bool,
-  client_toggle: bool,
-  release_type: Json<ReleaseType>,
+  since: Option<&str>,
   database_connection: &State<ConnectionManager>,
-  _token_auth: UserAuth,
-) -> Result<status::Created<Json<Created>>, status::BadRequest<()>> {
-  let flag_builder = FeatureFlag::builder()
-    .with_name(name)
-    .with_product_id(product_id)
-    .with_enabled(enabled)
-    .with_client_toggle(client_toggle)
-    .with_release_type(release_type.into_inner());
+  api_key: ApiKeyAuth,
+) -> Result<Negotiated<SdkSnapshot>, ApiError> {
+  api_key.require_server()?;
+  api_key.authorize(product_id)?;
 
-  let flag = match database_connection.create_flag(flag_builder).await {
-    Some(value) => value,
-    None => return Err(status::BadRequest(None)),
+  let since = match since.map(DateTime::parse_from_rfc3339) {
+    Some(Ok(since)) => Some(since.with_timezone(&Utc)),
+    Some(Err(_)) => return Err(ApiError::bad_request("Error. `since` must be an RFC 3339 time.")),
+    None => None,
   };
 
-  let flag_id = match flag.oid {
-    Some(oid) => oid,
-    None => return Err(status::BadRequest(None)),
+  let product = match database_connection.get_product_by_id(product_id).await? {
+    Some(product) => product,
+    None => return Err(ApiError::not_found("Error. Product not found.")),
   };
 
-  Ok(
-    status::Created::new(format!("/get/flag/{}/{}", flag.name, flag.product_id))
-      .body(Json(Created::new(&flag_id.to_hex()))),
-  )
+  let generated_at = Utc::now();
+  let flags = database_connection
+    .get_feature_flags(
+      product_id,
+      FlagFilter {
+        include_archived: true,
+        ..Default::default()
+      },
+      FlagProjection::Full,
+    )
+    .await?;
+
+  // Flags without a timestamp predate timestamps being recorded, so they're always included
+  let changed = |flag: &FeatureFlag| match (since, flag.updated_at) {
+    (Some(since), Some(updated_at)) => updated_at > since,
+    _ => true,
+  };
+
+  Ok(Negotiated(SdkSnapshot {
+    product_id: product_id.to_string(),
+    generated_at,
+    plugin: product.plugin_id.is_some(),
+    flag_names: flags.iter().map(|x| x.name.clone()).collect(),
+    flags: flags
+      .iter()
+      .filter(|x| changed(x))
+      .map(|x| SnapshotFlag {
+        definition: x.definition(),
+        payload: x.payload.clone(),
+        updated_at: x.updated_at,
+      })
+      .collect(),
+  }))
 }
 
-/// Create a user with a given name, email, and password hash
+/// Returns the name of the account type of a user (e.g. `Developer`), `None` if there's no user or it doesn't exist
 ///
-/// # Parameters
-/// * **account_type** - type of account
+/// Used to fill in the evaluation context of flags with rules targeting account types
+async fn get_account_type(
+  user_id: Option<&str>,
+  database_connection: &ConnectionManager,
+) -> StorageResult<Option<String>> {
+  let user = match user_id {
+    Some(user_id) => database_connection.get_user(None, Some(user_id)).await?,
+    None => None,
+  };
+
+  Ok(user.map(|x| x.account_type.name().to_string()))
+}
+
+/// Returns the compiled plugin of a product, `None` if the product doesn't exist or has no plugin
+async fn get_plugin(
+  product_id: &str,
+  database_connection: &ConnectionManager,
+  plugin_host: &PluginHost,
+) -> StorageResult<Option<LoadedPlugin>> {
+  match database_connection.get_product_by_id(product_id).await? {
+    Some(product) => Ok(plugin_host.load(&product, database_connection).await),
+    None => Ok(None),
+  }
+}
+
+/// Checks that a user can be evaluated against a product
+///
+/// Always `true` unless the product is in strict mode, in which case the user must exist and (for clients) be listed
+/// as a user of the product
+async fn is_known_user(
+  product_id: &str,
+  user_id: &str,
+  database_connection: &ConnectionManager,
+) -> StorageResult<bool> {
+  let product = match database_connection.get_product_by_id(product_id).await? {
+    Some(product) => product,
+    None => return Ok(true),
+  };
+
+  if !product.strict_mode {
+    return Ok(true);
+  }
+
+  let user = database_connection.get_user(None, Some(user_id)).await?;
+  Ok(is_known_to_product(Some(&product), user_id, user.as_ref()))
+}
+
+/// Checks that an already looked up user can be evaluated against a product, see `is_known_user`
+///
+/// `user` is `None` if the user doesn't exist
+fn is_known_to_product(product: Option<&Product>, user_id: &str, user: Option<&User>) -> bool {
+  let product = match product {
+    Some(product) if product.strict_mode => product,
+    _ => return true,
+  };
+
+  match user {
+    Some(user) => match user.account_type {
+      AccountType::Developer => true,
+      AccountType::Client => product.users.contains(&user_id.to_string()),
+    },
+    None => false,
+  }
+}
+
+/// Returns who a hoist or lower by `actor` applies to, `None` (everyone) for developers, service accounts and API keys,
+/// and the client's own ID for clients
+///
+/// Returns 401 if the user doesn't exist
+async fn change_target(database_connection: &ConnectionManager, actor: &ActorAuth) -> Result<Option<String>, ApiError> {
+  if !actor.is_user() {
+    return Ok(None);
+  }
+
+  match database_connection.get_user(None, Some(&actor.user_id)).await? {
+    Some(user) => match user.account_type {
+      AccountType::Developer => Ok(None),
+      AccountType::Client => Ok(Some(actor.user_id.clone())),
+    },
+    None => Err(ApiError::new(
+      Status::Unauthorized,
+      format!("Error. Unable to get user '{}'", actor.user_id),
+    )),
+  }
+}
+
+/// Rejects a request to a deprecated route naming its caller by email with 403, unless `user_email` is the email of the
+/// user making it
+async fn require_own_email(
+  database_connection: &ConnectionManager,
+  actor: &ActorAuth,
+  user_email: &str,
+) -> Result<(), ApiError> {
+  let user = match actor.is_user() {
+    true => database_connection.get_user(None, Some(&actor.user_id)).await?,
+    false => None,
+  };
+
+  match user {
+    Some(user) if user.email.eq_ignore_ascii_case(user_email.trim()) => Ok(()),
+    _ => Err(ApiError::new(
+      Status::Forbidden,
+      format!("Error. '{}' isn't the email of the logged in user.", user_email),
+    )),
+  }
+}
+
+/// Hoists or lowers a flag as `actor`, see `hoist` and `lower`
+async fn change_flag(
+  database_connection: &ConnectionManager,
+  actor: &ActorAuth,
+  product_id: &str,
+  feature: &str,
+  comment: Option<&str>,
+  override_freeze: Option<bool>,
+  action: AuditAction,
+) -> Result<status::Accepted<()>, ApiError> {
+  check_freeze(database_connection, product_id, override_freeze, &actor.user_id).await?;
+
+  if let Err(e) = validate_comment(comment) {
+    return Err(ApiError::bad_request(e));
+  }
+
+  let flag = match database_connection
+    .get_feature_flag(product_id, feature, FlagProjection::Summary)
+    .await?
+  {
+    Some(flag) => flag,
+    None => {
+      return Err(ApiError::bad_request(format!(
+        "Error. Unable to get flag: '{}'.",
+        feature
+      )))
+    }
+  };
+
+  let flag_id = match flag.oid {
+    Some(oid) => oid.to_hex(),
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  let user_id = change_target(database_connection, actor).await?;
+
+  match action {
+    AuditAction::Hoist => {
+      database_connection
+        .hoist_feature_flag(&flag_id, user_id.as_deref())
+        .await?
+    }
+    _ => {
+      database_connection
+        .lower_feature_flag(&flag_id, user_id.as_deref())
+        .await?
+    }
+  }
+
+  record_audit(
+    database_connection,
+    &actor.user_id,
+    EntityType::Flag,
+    &flag_id,
+    action,
+    change_details(product_id, feature, comment),
+  )
+  .await;
+  record_comment(database_connection, &flag_id, &actor.user_id, comment, action).await;
+  Ok(status::Accepted(None))
+}
+
+/// Hoist a flag!
+///
+/// If the caller is a `AccountType::Developer` (or a service account or API key) then the flag is **enabled** globally
+///
+/// If the caller is a `AccountType::Client` then the flag is **enabled** for them
+///
+/// Optionally can provide a comment justifying the change, it's added to the flag's comments and the audit log
+///
+/// Returns 423 if the product is in a freeze window, 403 if the caller's role doesn't grant `flags:write` or they
+/// aren't a user of the product, 400 if something goes wrong, 202 otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **feature**         - Name of the feature
+/// * **comment**         - *(optional)* why the flag is being hoisted
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[patch("/hoist/<product_id>/<feature>?<comment>&<override_freeze>", rank = 2)]
+async fn hoist(
+  product_id: &str,
+  feature: &str,
+  comment: Option<&str>,
+  override_freeze: Option<bool>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  change_flag(
+    database_connection,
+    &token_auth,
+    product_id,
+    feature,
+    comment,
+    override_freeze,
+    AuditAction::Hoist,
+  )
+  .await
+}
+
+/// Hoist a flag
+///
+/// Deprecated, use `PATCH /hoist/<product_id>/<feature>` instead. Behaves the same, **user_email** must be the email of
+/// the logged in user (403 otherwise)
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **feature**         - Name of the feature
+/// * **user_email**      - email of the logged in user
+/// * **comment**         - *(optional)* why the flag is being hoisted
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[patch("/hoist/<product_id>/<feature>/<user_email>?<comment>&<override_freeze>")]
+#[allow(clippy::too_many_arguments)]
+async fn hoist_with_email(
+  product_id: &str,
+  feature: &str,
+  user_email: &str,
+  comment: Option<&str>,
+  override_freeze: Option<bool>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  require_own_email(database_connection, &token_auth, user_email).await?;
+  change_flag(
+    database_connection,
+    &token_auth,
+    product_id,
+    feature,
+    comment,
+    override_freeze,
+    AuditAction::Hoist,
+  )
+  .await
+}
+
+/// Hoist several flags of a product, respecting their prerequisites
+///
+/// Like `hoist`, the flags are **enabled** globally if the caller is a `AccountType::Developer` (or a service account or
+/// API key), and only for the caller if they're a `AccountType::Client`
+///
+/// Flags are enabled in dependency order so a flag is never on while one of its prerequisites is off. Nothing is
+/// changed if a flag is missing, a prerequisite outside of the request is disabled, or the flags form a cycle; a 409
+/// with a report of the problems is returned instead
+///
+/// Returns 423 with every flag as failed if the product is in a freeze window, 202 with the order the flags were
+/// enabled in
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **features**        - Names of the features to hoist
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[patch("/hoist/bulk/<product_id>?<override_freeze>", data = "<features>")]
+async fn hoist_bulk(
+  product_id: &str,
+  override_freeze: Option<bool>,
+  features: Valid<Vec<String>>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<CasedJson<Vec<String>>>, ApiError> {
+  if let Err(e) = check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await {
+    let report = DependencyReport {
+      failed: features.into_inner(),
+      ..Default::default()
+    };
+    return Err(e.with_details(json!(report)));
+  }
+
+  let flags = database_connection
+    .get_feature_flags(
+      product_id,
+      FlagFilter {
+        include_archived: true,
+        ..Default::default()
+      },
+      FlagProjection::Full,
+    )
+    .await?;
+
+  let order = match dependency::enable_order(&flags, &features.into_inner()) {
+    Ok(order) => order,
+    Err(report) => {
+      let message = "Error. Flags can't be enabled in an order satisfying their prerequisites.";
+      return Err(ApiError::new(Status::Conflict, message).with_details(json!(report)));
+    }
+  };
+
+  let flags: HashMap<String, FeatureFlag> = flags.into_iter().map(|x| (x.name.clone(), x)).collect();
+  let user_id = change_target(database_connection, &token_auth).await?;
+
+  for name in &order {
+    let flag_id = match flags.get(name).and_then(|x| x.oid) {
+      Some(oid) => oid.to_hex(),
+      None => continue,
+    };
+
+    if let Err(e) = database_connection
+      .hoist_feature_flag(&flag_id, user_id.as_deref())
+      .await
+    {
+      let report = DependencyReport {
+        failed: vec![name.clone()],
+        ..Default::default()
+      };
+      return Err(ApiError::from(e).with_details(json!(report)));
+    }
+
+    let details = format!("{}/{}", product_id, name);
+    record_audit(
+      database_connection,
+      &token_auth.user_id,
+      EntityType::Flag,
+      &flag_id,
+      AuditAction::Hoist,
+      details,
+    )
+    .await;
+  }
+
+  Ok(status::Accepted(Some(CasedJson(order))))
+}
+
+/// Lower a flag
+///
+/// If the caller is a `AccountType::Developer` (or a service account or API key) then the flag is **disabled** globally
+///
+/// If the caller is a `AccountType::Client` then the flag is **disabled** for them
+///
+/// Optionally can provide a comment justifying the change, it's added to the flag's comments and the audit log
+///
+/// Returns 423 if the product is in a freeze window, 403 if the caller's role doesn't grant `flags:write` or they
+/// aren't a user of the product, 400 if something goes wrong, 202 otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **feature**         - Name of the feature
+/// * **comment**         - *(optional)* why the flag is being lowered
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[patch("/lower/<product_id>/<feature>?<comment>&<override_freeze>", rank = 2)]
+async fn lower(
+  product_id: &str,
+  feature: &str,
+  comment: Option<&str>,
+  override_freeze: Option<bool>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  change_flag(
+    database_connection,
+    &token_auth,
+    product_id,
+    feature,
+    comment,
+    override_freeze,
+    AuditAction::Lower,
+  )
+  .await
+}
+
+/// Lower a flag
+///
+/// Deprecated, use `PATCH /lower/<product_id>/<feature>` instead. Behaves the same, **user_email** must be the email of
+/// the logged in user (403 otherwise)
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **feature**         - Name of the feature
+/// * **user_email**      - email of the logged in user
+/// * **comment**         - *(optional)* why the flag is being lowered
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[patch("/lower/<product_id>/<feature>/<user_email>?<comment>&<override_freeze>")]
+#[allow(clippy::too_many_arguments)]
+async fn lower_with_email(
+  product_id: &str,
+  feature: &str,
+  user_email: &str,
+  comment: Option<&str>,
+  override_freeze: Option<bool>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  require_own_email(database_connection, &token_auth, user_email).await?;
+  change_flag(
+    database_connection,
+    &token_auth,
+    product_id,
+    feature,
+    comment,
+    override_freeze,
+    AuditAction::Lower,
+  )
+  .await
+}
+
+/// Replaces the targeting rules of a flag
+///
+/// Rules are checked by priority before the flag's release type, the first rule whose condition matches decides the
+/// result of the flag. Rules with equal priorities keep their order in the list, and priorities are renumbered from 0
+/// when saved. Returns 422 listing the problems if any rule is invalid, 202 otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **feature**         - Name of the feature
+/// * **rules**           - List of targeting rules (send an empty list to remove all rules)
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[put("/update/rules/<product_id>/<feature>?<override_freeze>", data = "<rules>")]
+async fn update_rules(
+  product_id: &str,
+  feature: &str,
+  override_freeze: Option<bool>,
+  rules: Valid<Vec<Rule>>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let mut rules = rules.into_inner();
+  rule::prioritize(&mut rules);
+
+  let mut flag = match database_connection
+    .get_feature_flag(product_id, feature, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => {
+      return Err(ApiError::bad_request(format!(
+        "Error. Unable to get flag: '{}'.",
+        feature
+      )))
+    }
+  };
+
+  let flag_id = match flag.oid {
+    Some(oid) => oid.to_hex(),
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  flag.rules = rules;
+
+  database_connection.update_feature_flag(&flag_id, flag).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Flag,
+    &flag_id,
+    AuditAction::UpdateRules,
+    format!("{}/{}", product_id, feature),
+  )
+  .await;
+  Ok(status::Accepted(None))
+}
+
+/// Replaces the always include and always exclude lists of a flag
+///
+/// Users on the lists (e.g. QA accounts) are forced into or out of the rollout, regardless of targeting rules and
+/// percentage bucketing. Returns 423 if the product is in a freeze window, 422 if a user is on both lists, 400 if
+/// something goes wrong, 202 otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **feature**         - Name of the feature
+/// * **overrides**       - User IDs to always include and always exclude (send empty lists to remove all overrides)
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[put("/update/overrides/<product_id>/<feature>?<override_freeze>", data = "<overrides>")]
+async fn update_overrides(
+  product_id: &str,
+  feature: &str,
+  override_freeze: Option<bool>,
+  overrides: Valid<OverridesRequest>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let OverridesRequest {
+    always_include,
+    always_exclude,
+  } = overrides.into_inner();
+
+  let mut flag = match database_connection
+    .get_feature_flag(product_id, feature, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => {
+      return Err(ApiError::bad_request(format!(
+        "Error. Unable to get flag: '{}'.",
+        feature
+      )))
+    }
+  };
+
+  let flag_id = match flag.oid {
+    Some(oid) => oid.to_hex(),
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  let details = format!(
+    "{}/{} include={:?} exclude={:?}",
+    product_id, feature, always_include, always_exclude
+  );
+  flag.always_include = always_include;
+  flag.always_exclude = always_exclude;
+
+  database_connection.update_feature_flag(&flag_id, flag).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Flag,
+    &flag_id,
+    AuditAction::UpdateOverrides,
+    details,
+  )
+  .await;
+  Ok(status::Accepted(None))
+}
+
+/// Replaces the definition of a flag (e.g. its name, release type, or client toggle)
+///
+/// The body is the full definition, fields left out are reset to their defaults. Users who disabled the flag for
+/// themselves, scheduled changes, the archived status, and the buckets of its percentage release are kept
+///
+/// Returns 404 if the flag isn't found, 409 if renaming it would clash with another flag or break a flag listing it as a
+/// prerequisite, 423 if the product is in a freeze window, 422 listing the problems if the definition is invalid,
+/// and 202 with the updated flag otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **name**            - Name of the feature
+/// * **update**          - New definition of the flag
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[put("/flags/<product_id>/<name>?<override_freeze>", data = "<update>")]
+async fn update_flag(
+  product_id: &str,
+  name: &str,
+  override_freeze: Option<bool>,
+  update: Valid<FlagUpdateRequest>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<CasedJson<SpecSafeFeatureFlag>>, ApiError> {
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let flag = match database_connection
+    .get_feature_flag(product_id, name, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => return Err(ApiError::not_found(format!("Error. Unable to get flag: '{}'.", name))),
+  };
+
+  let flag = save_flag_update(database_connection, flag, update.into_inner(), &token_auth.user_id).await?;
+
+  Ok(status::Accepted(Some(CasedJson(flag))))
+}
+
+/// Updates part of the definition of a flag with a JSON Merge Patch (RFC 7396), e.g. `{"enabled": false}`
+///
+/// Fields left out are kept, fields set to `null` are reset to their defaults. Otherwise behaves like
+/// `PUT /flags/<product_id>/<name>`, returning 400 for unknown fields as well
+///
+/// # Parameters
+/// * **flag_id**         - Unique ID of the flag
+/// * **patch**           - Merge patch of the definition of the flag
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[patch("/flags/<flag_id>?<override_freeze>", data = "<patch>")]
+async fn patch_flag(
+  flag_id: &str,
+  override_freeze: Option<bool>,
+  patch: Json<Value>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<status::Accepted<CasedJson<SpecSafeFeatureFlag>>, ApiError> {
+  let flag = match database_connection
+    .get_feature_flag_by_id(flag_id, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => {
+      return Err(ApiError::not_found(format!(
+        "Error. Unable to get flag: '{}'.",
+        flag_id
+      )))
+    }
+  };
+
+  membership::require_product_member(database_connection, &token_auth, &flag.product_id).await?;
+  check_freeze(
+    database_connection,
+    &flag.product_id,
+    override_freeze,
+    &token_auth.user_id,
+  )
+  .await?;
+
+  let update = match FlagUpdateRequest::merge_patch(&flag, patch.into_inner()) {
+    Ok(update) => update,
+    Err(e) => return Err(ApiError::bad_request(e)),
+  };
+
+  let flag = save_flag_update(database_connection, flag, update, &token_auth.user_id).await?;
+
+  Ok(status::Accepted(Some(CasedJson(flag))))
+}
+
+/// Deletes a flag and its comments, developers only
+///
+/// Entries about the flag in the audit log are kept. Returns 403 if the caller isn't a developer, 404 if the flag isn't
+/// found, 409 if another flag lists it as a prerequisite, 423 if the product is in a freeze window, and 204 otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **name**            - Name of the feature
+/// * **override_freeze** - *(optional)* delete the flag during a freeze window (423 otherwise)
+#[openapi(tag = "Flags")]
+#[delete("/flags/<product_id>/<name>?<override_freeze>")]
+async fn delete_flag(
+  product_id: &str,
+  name: &str,
+  override_freeze: Option<bool>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::NoContent, ApiError> {
+  match database_connection.get_user(None, Some(&token_auth.user_id)).await? {
+    Some(user) if matches!(user.account_type, AccountType::Developer) => (),
+    _ => {
+      return Err(ApiError::new(
+        Status::Forbidden,
+        "Error. Only developers can delete flags.",
+      ))
+    }
+  }
+
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let flags = database_connection
+    .get_feature_flags(
+      product_id,
+      FlagFilter {
+        include_archived: true,
+        ..Default::default()
+      },
+      FlagProjection::Full,
+    )
+    .await?;
+
+  let flag_id = match flags.iter().find(|x| x.name == name).and_then(|x| x.oid) {
+    Some(oid) => oid.to_hex(),
+    None => return Err(ApiError::not_found(format!("Error. Unable to get flag: '{}'.", name))),
+  };
+
+  if let Some(dependent) = flags.iter().find(|x| x.prerequisites.iter().any(|x| x == name)) {
+    return Err(ApiError::new(
+      Status::Conflict,
+      format!("Error. Flag '{}' is a prerequisite of '{}'.", name, dependent.name),
+    ));
+  }
+
+  database_connection.delete_feature_flag(&flag_id).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Flag,
+    &flag_id,
+    AuditAction::Delete,
+    format!("{}/{}", product_id, name),
+  )
+  .await;
+
+  Ok(status::NoContent)
+}
+
+/// Validates and saves a new definition of a flag, recording the change in the audit log
+async fn save_flag_update(
+  database_connection: &ConnectionManager,
+  mut flag: FeatureFlag,
+  update: FlagUpdateRequest,
+  actor: &str,
+) -> Result<SpecSafeFeatureFlag, ApiError> {
+  update.violations().into_result()?;
+
+  if let Some(maintainer_id) = &update.maintainer_id {
+    if database_connection.get_user(None, Some(maintainer_id)).await?.is_none() {
+      return Err(ApiError::bad_request(format!(
+        "Error. Unable to get user '{}'",
+        maintainer_id
+      )));
+    }
+  }
+
+  let flag_id = match flag.oid {
+    Some(oid) => oid.to_hex(),
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  let details = if update.name != flag.name {
+    let flags = database_connection
+      .get_feature_flags(
+        &flag.product_id,
+        FlagFilter {
+          include_archived: true,
+          ..Default::default()
+        },
+        FlagProjection::Full,
+      )
+      .await?;
+
+    if flags.iter().any(|x| x.name == update.name) {
+      return Err(ApiError::new(
+        Status::Conflict,
+        format!("Error. A flag named '{}' already exists.", update.name),
+      ));
+    }
+    if let Some(dependent) = flags.iter().find(|x| x.prerequisites.contains(&flag.name)) {
+      return Err(ApiError::new(
+        Status::Conflict,
+        format!("Error. Flag '{}' is a prerequisite of '{}'.", flag.name, dependent.name),
+      ));
+    }
+
+    format!("{}/{} (renamed from '{}')", flag.product_id, update.name, flag.name)
+  } else {
+    format!("{}/{}", flag.product_id, flag.name)
+  };
+
+  update.apply(&mut flag);
+  rule::prioritize(&mut flag.rules);
+  flag.updated_at = Some(Utc::now());
+  let spec_safe_flag = flag.get_spec_safe_feature_flag();
+
+  database_connection.update_feature_flag(&flag_id, flag).await?;
+
+  record_audit(
+    database_connection,
+    actor,
+    EntityType::Flag,
+    &flag_id,
+    AuditAction::Update,
+    details,
+  )
+  .await;
+  Ok(spec_safe_flag)
+}
+
+/// Reorders the targeting rules of a flag
+///
+/// **order** lists the current priorities of the flag's rules in their new order, e.g. `[2, 0, 1]` moves the third rule
+/// first. The rules are replaced in a single write, so checks never see a partially reordered list. Returns 400 if
+/// **order** doesn't list every rule exactly once, 202 otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **feature**         - Name of the feature
+/// * **order**           - Current priorities of the rules, in their new order
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[put("/update/rules/<product_id>/<feature>/order?<override_freeze>", data = "<order>")]
+async fn reorder_rules(
+  product_id: &str,
+  feature: &str,
+  override_freeze: Option<bool>,
+  order: Json<Vec<u32>>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let mut flag = match database_connection
+    .get_feature_flag(product_id, feature, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => {
+      return Err(ApiError::bad_request(format!(
+        "Error. Unable to get flag: '{}'.",
+        feature
+      )))
+    }
+  };
+
+  let flag_id = match flag.oid {
+    Some(oid) => oid.to_hex(),
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  // Flags saved before rules had priorities have every priority at 0
+  rule::prioritize(&mut flag.rules);
+  flag.rules = match rule::reorder(&flag.rules, &order) {
+    Ok(rules) => rules,
+    Err(e) => return Err(ApiError::bad_request(e)),
+  };
+
+  database_connection.update_feature_flag(&flag_id, flag).await?;
+
+  let details = format!("{}/{} order={:?}", product_id, feature, order.into_inner());
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Flag,
+    &flag_id,
+    AuditAction::UpdateRules,
+    details,
+  )
+  .await;
+  Ok(status::Accepted(None))
+}
+
+/// Schedules a change to be applied to a flag later (e.g. setting its percentage to 50 at `2024-06-01T09:00:00Z`)
+///
+/// Due changes are applied by a background task, checked every `SCHEDULER_INTERVAL_SECONDS` (60 by default). Returns
+/// 404 if the flag isn't found, 403 if the caller isn't a user of its product, 422 if the change is invalid or due in the
+/// past, and 201 with the change's ID otherwise
+///
+/// # Parameters
+/// * **flag_id**  - Unique ID of the flag
+/// * **schedule** - Change to apply, and when to apply it
+#[openapi(tag = "Flags")]
+#[post("/flags/<flag_id>/scheduled-changes", data = "<schedule>")]
+async fn schedule_change(
+  flag_id: &str,
+  schedule: Valid<ScheduleChangeRequest>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<status::Created<CasedJson<Created>>, ApiError> {
+  let ScheduleChangeRequest { change, at } = schedule.into_inner();
+
+  let mut flag = match database_connection
+    .get_feature_flag_by_id(flag_id, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => return Err(ApiError::not_found(format!("Error. Unable to get flag '{}'.", flag_id))),
+  };
+  membership::require_product_member(database_connection, &token_auth, &flag.product_id).await?;
+
+  let scheduled = ScheduledChange::new(change, at, &token_auth.user_id);
+  let change_id = scheduled.id.to_hex();
+  let details = format!(
+    "{}/{} {:?} at {}",
+    flag.product_id,
+    flag.name,
+    scheduled.change,
+    at.to_rfc3339()
+  );
+  flag.scheduled_changes.push(scheduled);
+
+  database_connection.update_feature_flag(flag_id, flag).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Flag,
+    flag_id,
+    AuditAction::ScheduleChange,
+    details,
+  )
+  .await;
+
+  Ok(status::Created::new(format!("/flags/{}/scheduled-changes", flag_id)).body(CasedJson(Created::new(&change_id))))
+}
+
+/// Lists the pending scheduled changes of a flag, in the order they're due
+///
+/// Returns 404 if the flag isn't found
+///
+/// # Parameters
+/// * **flag_id** - Unique ID of the flag
+#[openapi(tag = "Flags")]
+#[get("/flags/<flag_id>/scheduled-changes")]
+async fn get_scheduled_changes(
+  flag_id: &str,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<Option<CasedJson<Vec<SpecSafeScheduledChange>>>, ApiError> {
+  let flag = match database_connection
+    .get_feature_flag_by_id(flag_id, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => return Ok(None),
+  };
+
+  let mut changes: Vec<&ScheduledChange> = flag.scheduled_changes.iter().collect();
+  changes.sort_by_key(|x| x.at);
+
+  Ok(Some(CasedJson(
+    changes
+      .into_iter()
+      .map(|x| x.get_spec_safe_scheduled_change())
+      .collect(),
+  )))
+}
+
+/// Cancels a pending scheduled change of a flag
+///
+/// Returns 404 if the flag or change isn't found (changes that were already applied can't be cancelled), 403 if the
+/// caller isn't a user of the flag's product, 204 otherwise
+///
+/// # Parameters
+/// * **flag_id**   - Unique ID of the flag
+/// * **change_id** - Unique ID of the scheduled change
+#[openapi(tag = "Flags")]
+#[delete("/flags/<flag_id>/scheduled-changes/<change_id>")]
+async fn cancel_scheduled_change(
+  flag_id: &str,
+  change_id: &str,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<status::NoContent, ApiError> {
+  let not_found = || ApiError::not_found(format!("Error. Unable to get scheduled change '{}'.", change_id));
+
+  let mut flag = match database_connection
+    .get_feature_flag_by_id(flag_id, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => return Err(not_found()),
+  };
+  membership::require_product_member(database_connection, &token_auth, &flag.product_id).await?;
+
+  let position = match flag.scheduled_changes.iter().position(|x| x.id.to_hex() == change_id) {
+    Some(position) => position,
+    None => return Err(not_found()),
+  };
+  let cancelled = flag.scheduled_changes.remove(position);
+  let details = format!("{}/{} {:?}", flag.product_id, flag.name, cancelled.change);
+
+  database_connection.update_feature_flag(flag_id, flag).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Flag,
+    flag_id,
+    AuditAction::CancelScheduledChange,
+    details,
+  )
+  .await;
+
+  Ok(status::NoContent)
+}
+
+/// Comments on a flag, e.g. to explain the state it's in
+///
+/// Returns 404 if the flag isn't found, 403 if the caller isn't a user of its product, 422 if the comment is empty or
+/// longer than 4096 characters, and 201 with the comment's ID otherwise
+///
+/// # Parameters
+/// * **flag_id** - Unique ID of the flag
+/// * **comment** - Text of the comment
+#[openapi(tag = "Flags")]
+#[post("/flags/<flag_id>/comments", data = "<comment>")]
+async fn create_comment(
+  flag_id: &str,
+  comment: Valid<CommentRequest>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<status::Created<CasedJson<Created>>, ApiError> {
+  let body = comment.into_inner().body;
+
+  let flag = match database_connection
+    .get_feature_flag_by_id(flag_id, FlagProjection::Summary)
+    .await?
+  {
+    Some(flag) => flag,
+    None => return Err(ApiError::not_found(format!("Error. Unable to get flag '{}'.", flag_id))),
+  };
+  membership::require_product_member(database_connection, &token_auth, &flag.product_id).await?;
+
+  let comment_builder = Comment::builder()
+    .with_flag_id(flag_id)
+    .with_author(&token_auth.user_id)
+    .with_body(body.trim());
+
+  let comment_id = match database_connection.create_comment(comment_builder).await? {
+    Comment { oid: Some(oid), .. } => oid.to_hex(),
+    _ => return Err(ApiError::bad_request("Error. Unable to create comment.")),
+  };
+
+  Ok(status::Created::new(format!("/flags/{}/comments", flag_id)).body(CasedJson(Created::new(&comment_id))))
+}
+
+/// Lists the comments on a flag, oldest first
+///
+/// Includes the comments left with hoists and lowers of the flag. Returns 404 if the flag isn't found
+///
+/// # Parameters
+/// * **flag_id** - Unique ID of the flag
+#[openapi(tag = "Flags")]
+#[get("/flags/<flag_id>/comments")]
+async fn get_comments(
+  flag_id: &str,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<Option<CasedJson<Vec<SpecSafeComment>>>, ApiError> {
+  if database_connection
+    .get_feature_flag_by_id(flag_id, FlagProjection::Evaluation)
+    .await?
+    .is_none()
+  {
+    return Ok(None);
+  }
+
+  Ok(Some(CasedJson(
+    database_connection
+      .get_comments(flag_id)
+      .await?
+      .iter()
+      .map(|x| x.get_spec_safe_comment())
+      .collect(),
+  )))
+}
+
+/// Archives a flag
+///
+/// Archived flags always evaluate as disabled and are left out of flag listings unless asked for, they can be
+/// restored with `/unarchive/flag/<product_id>/<name>`. Returns 202 if the flag was archived
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **name**            - Name of the feature
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[patch("/archive/flag/<product_id>/<name>?<override_freeze>")]
+async fn archive_flag(
+  product_id: &str,
+  name: &str,
+  override_freeze: Option<bool>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  set_archived(
+    product_id,
+    name,
+    true,
+    override_freeze,
+    database_connection,
+    &token_auth,
+  )
+  .await
+}
+
+/// Restores an archived flag
+///
+/// The flag evaluates as it did before it was archived. Returns 202 if the flag was restored
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **name**            - Name of the feature
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[patch("/unarchive/flag/<product_id>/<name>?<override_freeze>")]
+async fn unarchive_flag(
+  product_id: &str,
+  name: &str,
+  override_freeze: Option<bool>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  set_archived(
+    product_id,
+    name,
+    false,
+    override_freeze,
+    database_connection,
+    &token_auth,
+  )
+  .await
+}
+
+async fn set_archived(
+  product_id: &str,
+  name: &str,
+  archived: bool,
+  override_freeze: Option<bool>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: &ActorAuth,
+) -> Result<status::Accepted<()>, ApiError> {
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let mut flag = match database_connection
+    .get_feature_flag(product_id, name, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => return Err(ApiError::bad_request(format!("Error. Unable to get flag: '{}'.", name))),
+  };
+
+  let flag_id = match flag.oid {
+    Some(oid) => oid.to_hex(),
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  flag.archived = archived;
+
+  database_connection.update_feature_flag(&flag_id, flag).await?;
+
+  let action = if archived {
+    AuditAction::Archive
+  } else {
+    AuditAction::Unarchive
+  };
+  let details = format!("{}/{}", product_id, name);
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Flag,
+    &flag_id,
+    action,
+    details,
+  )
+  .await;
+  Ok(status::Accepted(None))
+}
+
+/// Marks a flag as permanent or temporary
+///
+/// Permanent flags (e.g. operational kill switches) are never reported as stale. Returns 423 if the product is in a
+/// freeze window, 202 if the flag was updated
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **feature**         - Name of the feature
+/// * **permanent**       - If the flag is meant to stay rather than be removed once rolled out
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[patch("/update/permanent/<product_id>/<feature>/<permanent>?<override_freeze>")]
+async fn update_permanent(
+  product_id: &str,
+  feature: &str,
+  permanent: bool,
+  override_freeze: Option<bool>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let mut flag = match database_connection
+    .get_feature_flag(product_id, feature, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => {
+      return Err(ApiError::bad_request(format!(
+        "Error. Unable to get flag: '{}'.",
+        feature
+      )))
+    }
+  };
+
+  let flag_id = match flag.oid {
+    Some(oid) => oid.to_hex(),
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  flag.permanent = permanent;
+
+  database_connection.update_feature_flag(&flag_id, flag).await?;
+
+  let details = format!("{}/{} permanent={}", product_id, feature, permanent);
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Flag,
+    &flag_id,
+    AuditAction::UpdatePermanent,
+    details,
+  )
+  .await;
+  Ok(status::Accepted(None))
+}
+
+/// Sets the configuration payload of a flag
+///
+/// The payload is any JSON value (e.g. `{"button_color": "red", "limit": 10}`) returned by checks alongside the
+/// enabled status while the flag is enabled, so products can be configured rather than only gated. Send `null` to
+/// remove the payload. Returns 202 if the flag was updated
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **feature**         - Name of the feature
+/// * **payload**         - JSON payload of the flag
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Flags")]
+#[put("/update/payload/<product_id>/<feature>?<override_freeze>", data = "<payload>")]
+async fn update_payload(
+  product_id: &str,
+  feature: &str,
+  override_freeze: Option<bool>,
+  payload: Json<Value>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let mut flag = match database_connection
+    .get_feature_flag(product_id, feature, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => {
+      return Err(ApiError::bad_request(format!(
+        "Error. Unable to get flag: '{}'.",
+        feature
+      )))
+    }
+  };
+
+  let flag_id = match flag.oid {
+    Some(oid) => oid.to_hex(),
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  flag.payload = match payload.into_inner() {
+    Value::Null => None,
+    payload => Some(payload),
+  };
+
+  database_connection.update_feature_flag(&flag_id, flag).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Flag,
+    &flag_id,
+    AuditAction::UpdatePayload,
+    format!("{}/{}", product_id, feature),
+  )
+  .await;
+  Ok(status::Accepted(None))
+}
+
+/// Lists temporary flags of a product that have been fully rolled out for a while and can likely be removed
+///
+/// A flag is fully rolled out when it's enabled, released globally or to 100%, and no targeting rule disables it.
+/// The time since the flag was last changed is used as the time it has been fully rolled out
+///
+/// # Parameters
+/// * **product_id** - Unique ID of the product
+/// * **days**       - *(optional)* minimum number of days the flag has been unchanged, defaults to 30
+#[openapi(tag = "Flags")]
+#[get("/report/stale/<product_id>?<days>")]
+async fn stale_flags(
+  product_id: &str,
+  days: Option<u32>,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<CasedJson<Vec<StaleFlag>>, ApiError> {
+  let now = Utc::now();
+  let min_age = Duration::days(days.unwrap_or(30) as i64);
+
+  Ok(CasedJson(
+    database_connection
+      .get_feature_flags(product_id, FlagFilter::default(), FlagProjection::Full)
+      .await?
+      .into_iter()
+      .filter(|x| x.is_stale(now, min_age))
+      .map(|x| StaleFlag {
+        days_unchanged: x.updated_at.map(|updated_at| (now - updated_at).num_days()),
+        name: x.name,
+        owner: x.owner,
+        maintainer_id: x.maintainer_id,
+        updated_at: x.updated_at,
+      })
+      .collect(),
+  ))
+}
+
+/// Gets the effective settings of a flag and the level each setting was inherited from
+///
+/// Settings are resolved from the organization defaults, then the product, then the optional environment, then the
+/// flag itself, with each level overriding its parent. Returns 404 if the flag or its product isn't found
+///
+/// # Parameters
+/// * **product_id**  - Unique ID of the product
+/// * **feature**     - Name of the feature
+/// * **environment** - *(optional)* name of the environment to resolve the settings for
+#[openapi(tag = "Settings")]
+#[get("/get/settings/<product_id>/<feature>?<environment>")]
+async fn get_settings(
+  product_id: &str,
+  feature: &str,
+  environment: Option<&str>,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<CasedJson<EffectiveSettings>, ApiError> {
+  let flag = match database_connection
+    .get_feature_flag(product_id, feature, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => return Err(ApiError::not_found("Error. Flag not found.")),
+  };
+
+  let product = match database_connection.get_product_by_id(product_id).await? {
+    Some(product) => product,
+    None => return Err(ApiError::not_found("Error. Product not found.")),
+  };
+
+  let environment = environment.and_then(|x| product.environments.get(x));
+
+  Ok(CasedJson(EffectiveSettings::resolve(
+    &product.settings,
+    environment,
+    &flag.settings,
+  )))
+}
+
+/// Replaces the settings overridden by a product, or by one of its environments
+///
+/// Returns 400 if something goes wrong, 202 otherwise
+///
+/// # Parameters
+/// * **product_id**  - Unique ID of the product
+/// * **environment** - *(optional)* name of the environment to set the overrides of, the product itself if left out
+/// * **settings**    - Settings to override (leave a setting out to inherit it)
+#[openapi(tag = "Settings")]
+#[put("/update/settings/<product_id>?<environment>", data = "<settings>")]
+async fn update_product_settings(
+  product_id: &str,
+  environment: Option<&str>,
+  settings: Valid<SettingsOverrides>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  let mut product = member.product;
+
+  match environment {
+    Some(environment) => {
+      product
+        .environments
+        .insert(environment.to_string(), settings.into_inner());
+    }
+    None => product.settings = settings.into_inner(),
+  }
+
+  database_connection.update_product(product_id, product).await?;
+
+  let details = match environment {
+    Some(environment) => format!("environment '{}'", environment),
+    None => String::new(),
+  };
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Product,
+    product_id,
+    AuditAction::UpdateSettings,
+    details,
+  )
+  .await;
+  Ok(status::Accepted(None))
+}
+
+/// Replaces the settings overridden by a flag
+///
+/// Returns 423 if the product is in a freeze window, 400 if something goes wrong, 202 otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **feature**         - Name of the feature
+/// * **settings**        - Settings to override (leave a setting out to inherit it)
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Settings")]
+#[put("/update/settings/<product_id>/<feature>?<override_freeze>", data = "<settings>")]
+async fn update_flag_settings(
+  product_id: &str,
+  feature: &str,
+  override_freeze: Option<bool>,
+  settings: Valid<SettingsOverrides>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let mut flag = match database_connection
+    .get_feature_flag(product_id, feature, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => {
+      return Err(ApiError::bad_request(format!(
+        "Error. Unable to get flag: '{}'.",
+        feature
+      )))
+    }
+  };
+
+  let flag_id = match flag.oid {
+    Some(oid) => oid.to_hex(),
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  flag.settings = settings.into_inner();
+
+  database_connection.update_feature_flag(&flag_id, flag).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Flag,
+    &flag_id,
+    AuditAction::UpdateSettings,
+    format!("{}/{}", product_id, feature),
+  )
+  .await;
+  Ok(status::Accepted(None))
+}
+
+/// Replaces the freeze windows of a product
+///
+/// While a product is in a freeze window (e.g. Friday 18:00 to Monday 06:00 UTC) hoisting, lowering, and updating its
+/// flags is rejected with 423, unless a developer sets `override_freeze`. Scheduled changes wait for the window to
+/// end. Send an empty list to remove all windows. The windows can't be changed during one of them either, so a freeze
+/// can't be lifted to make changes anyway. Returns 423 if the product is in a freeze window, 422 if a window is
+/// malformed, 202 otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **freeze_windows**  - Weekly windows during which flags of the product can't be changed
+/// * **override_freeze** - *(optional)* change the windows during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Products")]
+#[put("/update/freeze-windows/<product_id>?<override_freeze>", data = "<freeze_windows>")]
+async fn update_freeze_windows(
+  product_id: &str,
+  override_freeze: Option<bool>,
+  freeze_windows: Valid<Vec<FreezeWindow>>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let freeze_windows = freeze_windows.into_inner();
+
+  let mut product = member.product;
+
+  let details = format!("{} window(s)", freeze_windows.len());
+  product.freeze_windows = freeze_windows;
+
+  database_connection.update_product(product_id, product).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Product,
+    product_id,
+    AuditAction::UpdateFreezeWindows,
+    details,
+  )
+  .await;
+  Ok(status::Accepted(None))
+}
+
+/// Uploads a WASM plugin implementing custom evaluation logic for the flags of a product
+///
+/// The body is the raw WASM module (at most 1 MiB). Plugins can't import anything and must export `memory`,
+/// `alloc(len: i32) -> i32`, and `evaluate(ptr: i32, len: i32) -> i32`. For every check of the product's flags (after
+/// the always include and exclude lists, before targeting rules) `evaluate` is called with the UTF-8 JSON
+/// `{"flag": ..., "context": ...}` written to memory reserved with `alloc`. It returns `1` to enable the flag, `0` to
+/// disable it, and anything else to leave the flag to targeting rules and its release type. Evaluations run in a
+/// fresh sandbox with limited fuel and memory, a plugin that fails is ignored
+///
+/// Developers only, plugins run for every check of the product's flags. Replaces any previous plugin of the product.
+/// Returns 403 if the user isn't a developer, 423 if the product is in a freeze window, 400 if the module is invalid,
+/// 202 with the uploaded plugin otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **module**          - WASM module of the plugin
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Products")]
+#[put("/update/plugin/<product_id>?<override_freeze>", data = "<module>")]
+async fn update_plugin(
+  product_id: &str,
+  override_freeze: Option<bool>,
+  module: Data<'_>,
+  database_connection: &State<ConnectionManager>,
+  plugin_host: &State<PluginHost>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Accepted<CasedJson<SpecSafePlugin>>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage plugins").await?;
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let module = match module.open(MAX_PLUGIN_SIZE.bytes()).into_bytes().await {
+    Ok(module) if module.is_complete() => module.into_inner(),
+    Ok(_) => {
+      return Err(ApiError::new(
+        Status::PayloadTooLarge,
+        format!("Error. Plugins can't be larger than {} bytes.", MAX_PLUGIN_SIZE),
+      ))
+    }
+    Err(e) => return Err(ApiError::bad_request(e.to_string())),
+  };
+
+  if let Err(e) = plugin_host.compile(&module) {
+    return Err(ApiError::bad_request(e));
+  }
+
+  let mut product = match database_connection.get_product_by_id(product_id).await? {
+    Some(product) => product,
+    None => {
+      return Err(ApiError::bad_request(format!(
+        "Error. Unable to get product '{}'",
+        product_id
+      )))
+    }
+  };
+
+  let plugin_builder = Plugin::builder()
+    .with_product_id(product_id)
+    .with_module(module)
+    .with_created_by(&token_auth.user_id);
+
+  let plugin = database_connection.create_plugin(plugin_builder).await?;
+
+  let spec_safe_plugin = plugin.get_spec_safe_plugin();
+  product.plugin_id = Some(spec_safe_plugin.oid.clone());
+
+  database_connection.update_product(product_id, product).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Product,
+    product_id,
+    AuditAction::UpdatePlugin,
+    format!("plugin '{}' ({} bytes)", spec_safe_plugin.oid, spec_safe_plugin.size),
+  )
+  .await;
+  Ok(status::Accepted(Some(CasedJson(spec_safe_plugin))))
+}
+
+/// Removes the WASM plugin of a product, its flags are evaluated without custom logic again
+///
+/// Developers only. Returns 403 if the user isn't a developer, 423 if the product is in a freeze window, 400 if something
+/// goes wrong, 202 otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **override_freeze** - *(optional)* make the change during a freeze window (423 otherwise), developers only
+#[openapi(tag = "Products")]
+#[delete("/update/plugin/<product_id>?<override_freeze>")]
+async fn remove_plugin(
+  product_id: &str,
+  override_freeze: Option<bool>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  member: ProductMember,
+) -> Result<status::Accepted<()>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage plugins").await?;
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let mut product = member.product;
+
+  let details = match product.plugin_id.take() {
+    Some(plugin_id) => format!("plugin '{}'", plugin_id),
+    None => return Ok(status::Accepted(None)),
+  };
+
+  database_connection.update_product(product_id, product).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Product,
+    product_id,
+    AuditAction::RemovePlugin,
+    details,
+  )
+  .await;
+  Ok(status::Accepted(None))
+}
+
+/// Renames a product and sets its strict mode
+///
+/// Flags refer to their product by ID, so they're left as they are. Returns 404 if the product isn't found, 409 if
+/// another product has the name, 422 if the name is empty or too long, and 202 with the updated product otherwise
+///
+/// # Parameters
+/// * **product_id** - Unique ID of the product
+/// * **update**     - New name and strict mode of the product
+#[openapi(tag = "Products")]
+#[put("/products/<product_id>", data = "<update>")]
+async fn update_product(
+  product_id: &str,
+  update: Valid<ProductUpdateRequest>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  member: ProductMember,
+) -> Result<status::Accepted<CasedJson<SpecSafeProduct>>, ApiError> {
+  let update = update.into_inner();
+
+  let mut product = member.product;
+
+  if update.name != product.name && database_connection.get_product(&update.name).await?.is_some() {
+    return Err(ApiError::new(
+      Status::Conflict,
+      format!("Error. A product named '{}' already exists.", update.name),
+    ));
+  }
+
+  let details = if update.name != product.name {
+    format!("{} (renamed from '{}')", update.name, product.name)
+  } else {
+    update.name.clone()
+  };
+
+  product.name = update.name;
+  product.strict_mode = update.strict_mode;
+  let spec_safe_product = product.get_spec_safe_product();
+
+  database_connection.update_product(product_id, product).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Product,
+    product_id,
+    AuditAction::Update,
+    details,
+  )
+  .await;
+
+  Ok(status::Accepted(Some(CasedJson(spec_safe_product))))
+}
+
+/// Adds a user to a product, doing nothing if it's already one of its users
+///
+/// Returns 400 if the user doesn't exist, 404 if the product isn't found, and 204 otherwise
+///
+/// # Parameters
+/// * **product_id** - Unique ID of the product
+/// * **user_id**    - Unique ID of the user to add
+#[openapi(tag = "Products")]
+#[patch("/products/<product_id>/users/<user_id>/add")]
+async fn add_product_user(
+  product_id: &str,
+  user_id: &str,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<status::NoContent, ApiError> {
+  if database_connection.get_user(None, Some(user_id)).await?.is_none() {
+    return Err(ApiError::bad_request(format!(
+      "Error. Unable to get user '{}'",
+      user_id
+    )));
+  }
+
+  database_connection.add_product_user(product_id, user_id).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Product,
+    product_id,
+    AuditAction::AddUser,
+    user_id.to_string(),
+  )
+  .await;
+
+  Ok(status::NoContent)
+}
+
+/// Removes a user from a product, doing nothing if it isn't one of its users
+///
+/// Returns 404 if the product isn't found and 204 otherwise
+///
+/// # Parameters
+/// * **product_id** - Unique ID of the product
+/// * **user_id**    - Unique ID of the user to remove
+#[openapi(tag = "Products")]
+#[patch("/products/<product_id>/users/<user_id>/remove")]
+async fn remove_product_user(
+  product_id: &str,
+  user_id: &str,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<status::NoContent, ApiError> {
+  database_connection.remove_product_user(product_id, user_id).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Product,
+    product_id,
+    AuditAction::RemoveUser,
+    user_id.to_string(),
+  )
+  .await;
+
+  Ok(status::NoContent)
+}
+
+/// Deletes a product along with its flags, their comments, and its plugin, developers with `flags:write` only
+///
+/// With `dry_run` nothing is deleted, the response lists what would be. Entries in the audit log are kept. Returns 403 if
+/// the caller isn't a developer, 404 if the product isn't found, 423 if it's in a freeze window, and the deleted
+/// product, flags, and plugin otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **dry_run**         - *(optional)* only list what would be deleted
+/// * **override_freeze** - *(optional)* delete the product during a freeze window (423 otherwise)
+#[openapi(tag = "Products")]
+#[delete("/products/<product_id>?<dry_run>&<override_freeze>")]
+async fn delete_product(
+  product_id: &str,
+  dry_run: Option<bool>,
+  override_freeze: Option<bool>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<CasedJson<ProductDeletion>, ApiError> {
+  match database_connection.get_user(None, Some(&token_auth.user_id)).await? {
+    Some(user) if matches!(user.account_type, AccountType::Developer) => (),
+    _ => {
+      return Err(ApiError::new(
+        Status::Forbidden,
+        "Error. Only developers can delete products.",
+      ))
+    }
+  }
+
+  let product = match database_connection.get_product_by_id(product_id).await? {
+    Some(product) => product,
+    None => {
+      return Err(ApiError::not_found(format!(
+        "Error. Unable to get product: '{}'.",
+        product_id
+      )))
+    }
+  };
+
+  let dry_run = dry_run.unwrap_or(false);
+  if !dry_run {
+    check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+  }
+
+  let flags = database_connection
+    .get_feature_flags(
+      product_id,
+      FlagFilter {
+        include_archived: true,
+        ..Default::default()
+      },
+      FlagProjection::Summary,
+    )
+    .await?;
+
+  let deletion = ProductDeletion {
+    product_id: product_id.to_string(),
+    name: product.name,
+    flags: flags.iter().map(|x| x.name.clone()).collect(),
+    plugin_id: product.plugin_id,
+    dry_run,
+  };
+
+  if dry_run {
+    return Ok(CasedJson(deletion));
+  }
+
+  let flag_ids: Vec<String> = flags.iter().filter_map(|x| x.oid).map(|x| x.to_hex()).collect();
+  database_connection.delete_product(product_id, &flag_ids).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Product,
+    product_id,
+    AuditAction::Delete,
+    format!("{} ({} flags)", deletion.name, deletion.flags.len()),
+  )
+  .await;
+
+  Ok(CasedJson(deletion))
+}
+
+/// Results of each kind returned by `/search` by default
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+
+/// Most results of each kind returned by `/search`
+const MAX_SEARCH_LIMIT: i64 = 100;
+
+/// Searches flags, products, and users by the start of their name (email for users), ignoring case, developers only
+///
+/// Results are grouped by kind, flags first, with up to `limit` results of each kind. Returns 403 if the caller isn't a
+/// developer, 400 if the term is empty
+///
+/// # Parameters
+/// * **q**     - Start of the names to search for (e.g. `checkout`)
+/// * **limit** - *(optional)* most results of each kind, defaults to 20, at most 100
+#[openapi(tag = "Search")]
+#[get("/search?<q>&<limit>")]
+async fn search(
+  q: &str,
+  limit: Option<i64>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: UserAuth,
+) -> Result<CasedJson<Vec<SearchResult>>, ApiError> {
+  match database_connection.get_user(None, Some(&token_auth.user_id)).await? {
+    Some(user) if matches!(user.account_type, AccountType::Developer) => (),
+    _ => return Err(ApiError::new(Status::Forbidden, "Error. Only developers can search.")),
+  }
+
+  let term = q.trim();
+  if term.is_empty() {
+    return Err(ApiError::bad_request("Error. Search terms can't be empty."));
+  }
+  let limit = limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT);
+
+  let (flags, products, users) = rocket::futures::join!(
+    database_connection.search_feature_flags(term, limit),
+    database_connection.search_products(term, limit),
+    database_connection.search_users(term, limit),
+  );
+  let (flags, products, users) = (flags?, products?, users?);
+
+  let flags = flags.into_iter().filter_map(|x| {
+    Some(SearchResult {
+      kind: SearchKind::Flag,
+      id: x.oid?.to_hex(),
+      name: x.name,
+      product_id: Some(x.product_id),
+    })
+  });
+  let products = products.into_iter().filter_map(|x| {
+    Some(SearchResult {
+      kind: SearchKind::Product,
+      id: x.oid?.to_hex(),
+      name: x.name,
+      product_id: None,
+    })
+  });
+  let users = users.into_iter().filter_map(|x| {
+    Some(SearchResult {
+      kind: SearchKind::User,
+      id: x.oid?.to_hex(),
+      name: x.email,
+      product_id: None,
+    })
+  });
+
+  Ok(CasedJson(flags.chain(products).chain(users).collect()))
+}
+
+/// Gets a product given a name
+///
+/// Will return 404 if no product with the given name is found
+///
+/// # Parameters
+/// * **name** - Name of the product
+#[openapi(tag = "Products")]
+#[get("/get/product/<name>")]
+async fn get_product(
+  name: &str,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<CasedJson<SpecSafeProduct>, ApiError> {
+  let product = match database_connection.get_product(name).await? {
+    Some(product) => product,
+    None => return Err(ApiError::not_found("Error. Product not found.")),
+  };
+
+  Ok(CasedJson(product.get_spec_safe_product()))
+}
+
+/// Gets all products that a user consumes
+///
+/// If no products are found - this will return an empty list. Returns 400 if `limit` is out of range
+///
+/// # Parameters
+/// * **user_email** - email of a given user
+/// * **skip**       - *(optional)* number of products to skip, for paging
+/// * **limit**      - *(optional)* most products to return, from 1 to 1000, every product by default
+#[openapi(tag = "Products")]
+#[get("/get/products/<user_email>?<skip>&<limit>")]
+async fn get_products(
+  user_email: &str,
+  skip: Option<u64>,
+  limit: Option<i64>,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<CasedJson<Vec<SpecSafeProduct>>, ApiError> {
+  let page = list_page(skip, limit)?;
+
+  let user = match database_connection.get_user(Some(user_email), None).await? {
+    Some(value) => value,
+    None => return Ok(CasedJson(vec![])),
+  };
+
+  let user_id = match user.account_type {
+    AccountType::Client => match user.oid {
+      Some(oid) => Some(oid.to_hex()),
+      None => return Ok(CasedJson(vec![])),
+    },
+    AccountType::Developer => None,
+  };
+
+  Ok(CasedJson(
+    database_connection
+      .get_products(user_id, page)
+      .await?
+      .iter()
+      .map(|x| x.get_spec_safe_product())
+      .collect::<Vec<SpecSafeProduct>>(),
+  ))
+}
+
+/// Most items a page of a listing can have
+const MAX_PAGE_LIMIT: i64 = 1000;
+
+/// Parses the page of a listing, the whole listing by default, with 400 if `limit` is out of range
+fn list_page(skip: Option<u64>, limit: Option<i64>) -> Result<Page, ApiError> {
+  match limit {
+    Some(limit) if !(1..=MAX_PAGE_LIMIT).contains(&limit) => Err(ApiError::bad_request(format!(
+      "Error. The limit must be between 1 and {}.",
+      MAX_PAGE_LIMIT
+    ))),
+    _ => Ok(Page {
+      skip: skip.unwrap_or(0),
+      limit: limit.unwrap_or(0),
+    }),
+  }
+}
+
+/// Gets a feature flag given a flag name and product ID
+///
+/// Will return 404 if no flag is found matching the search
+///
+/// # Parameters
+/// * **name**       - name of the feature flag
+/// * **product_id** - unique ID of the flag's product
+#[openapi(tag = "Flags")]
+#[get("/get/flag/<name>/<product_id>")]
+async fn get_flag(
+  name: &str,
+  product_id: &str,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<CasedJson<SpecSafeFeatureFlag>, ApiError> {
+  let flag = match database_connection
+    .get_feature_flag(product_id, name, FlagProjection::Full)
+    .await?
+  {
+    Some(flag) => flag,
+    None => return Err(ApiError::not_found("Error. Flag not found.")),
+  };
+
+  Ok(CasedJson(flag.get_spec_safe_feature_flag()))
+}
+
+/// Gets all feature flags belonging to a product specified by product ID
+///
+/// Will return an empty list if no flags are found, and 400 if `sort` or `release_type` isn't recognized or `limit` is
+/// out of range
+///
+/// # Paramaters
+/// * **product_id**   - unique ID of the product
+/// * **tag**          - *(optional)* only return flags carrying this tag
+/// * **archived**     - *(optional)* also return archived flags, defaults to false
+/// * **enabled**      - *(optional)* only return flags with this global enabled status
+/// * **release_type** - *(optional)* only return flags with this kind of release (`Global`, `Limited`, `Percentage`)
+/// * **sort**         - *(optional)* field to sort by (`name`, `created_at`, `updated_at`), prefix with `-` to reverse
+/// * **skip**         - *(optional)* number of flags to skip, for paging
+/// * **limit**        - *(optional)* most flags to return, from 1 to 1000, every flag by default
+#[openapi(tag = "Flags")]
+#[get("/get/flags/<product_id>?<tag>&<archived>&<enabled>&<release_type>&<sort>&<skip>&<limit>")]
+#[allow(clippy::too_many_arguments)]
+async fn get_flags(
+  product_id: &str,
+  tag: Option<&str>,
+  archived: Option<bool>,
+  enabled: Option<bool>,
+  release_type: Option<&str>,
+  sort: Option<&str>,
+  skip: Option<u64>,
+  limit: Option<i64>,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<CasedJson<Vec<SpecSafeFeatureFlag>>, ApiError> {
+  let release_type = match release_type {
+    Some(name) => match ReleaseKind::from_name(name) {
+      Some(kind) => Some(kind),
+      None => {
+        return Err(ApiError::bad_request(format!(
+          "Error. Unknown release type '{}'.",
+          name
+        )))
+      }
+    },
+    None => None,
+  };
+
+  let sort = match sort {
+    Some(value) => match FlagSort::parse(value) {
+      Some(sort) => Some(sort),
+      None => {
+        return Err(ApiError::bad_request(format!(
+          "Error. Can't sort flags by '{}'.",
+          value
+        )))
+      }
+    },
+    None => None,
+  };
+
+  let filter = FlagFilter {
+    tag,
+    include_archived: archived.unwrap_or(false),
+    enabled,
+    release_type,
+    sort,
+    page: list_page(skip, limit)?,
+  };
+
+  Ok(CasedJson(
+    database_connection
+      .get_feature_flags(product_id, filter, FlagProjection::Full)
+      .await?
+      .iter()
+      .map(|x| x.get_spec_safe_feature_flag())
+      .collect::<Vec<SpecSafeFeatureFlag>>(),
+  ))
+}
+
+/// Gets all feature flags, across every product, maintained by a user
+///
+/// Lets maintainers see which flags they're responsible for retiring. Will return an empty list if no flags are found
+///
+/// # Paramaters
+/// * **user_id**  - unique ID of the maintainer
+/// * **archived** - *(optional)* also return archived flags, defaults to false
+#[openapi(tag = "Flags")]
+#[get("/get/flags/by-maintainer/<user_id>?<archived>")]
+async fn get_flags_by_maintainer(
+  user_id: &str,
+  archived: Option<bool>,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<CasedJson<Vec<SpecSafeFeatureFlag>>, ApiError> {
+  let filter = FlagFilter {
+    include_archived: archived.unwrap_or(false),
+    ..Default::default()
+  };
+
+  Ok(CasedJson(
+    database_connection
+      .get_feature_flags_by_maintainer(user_id, filter)
+      .await?
+      .iter()
+      .map(|x| x.get_spec_safe_feature_flag())
+      .collect::<Vec<SpecSafeFeatureFlag>>(),
+  ))
+}
+
+/// Changes the name, email, account type, and role of a user
+///
+/// Users can update themselves, developers can update anyone. Only developers can change account types, and only users
+/// with the `users:admin` permission roles. Returns 403 if
+/// the caller isn't allowed to make the change, 404 if the user isn't found, 409 if another user has the email, 422 if
+/// the name or email is invalid, and 202 with the updated user otherwise
+///
+/// # Parameters
+/// * **user_id** - Unique ID of the user
+/// * **update**  - New name, email, and optionally account type and role of the user
+#[openapi(tag = "Users")]
+#[put("/users/<user_id>", data = "<update>")]
+async fn update_user(
+  user_id: &str,
+  update: Valid<UserUpdateRequest>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: UserAuth,
+) -> Result<status::Accepted<CasedJson<SpecSafeUser>>, ApiError> {
+  let update = update.into_inner();
+
+  let is_developer = matches!(
+    database_connection.get_user(None, Some(&token_auth.user_id)).await?,
+    Some(User {
+      account_type: AccountType::Developer,
+      ..
+    })
+  );
+  if token_auth.user_id != user_id && !is_developer {
+    return Err(ApiError::new(
+      Status::Forbidden,
+      "Error. Only developers can update other users.",
+    ));
+  }
+
+  let mut user = match database_connection.get_user(None, Some(user_id)).await? {
+    Some(user) => user,
+    None => return Err(ApiError::not_found(format!("Error. Unable to get user '{}'", user_id))),
+  };
+
+  if let Some(account_type) = update.account_type {
+    if account_type.name() != user.account_type.name() && !is_developer {
+      return Err(ApiError::new(
+        Status::Forbidden,
+        "Error. Only developers can change account types.",
+      ));
+    }
+    user.account_type = account_type;
+  }
+
+  if let Some(role) = update.role {
+    if role != user.role() {
+      scopes::require_user(
+        database_connection,
+        &token_auth.user_id,
+        token_auth.scopes.as_deref(),
+        Permission::UsersAdmin,
+      )
+      .await?;
+    }
+    user.role = Some(role);
+  }
+
+  if update.email != user.email && database_connection.get_user(Some(&update.email), None).await?.is_some() {
+    return Err(ApiError::new(
+      Status::Conflict,
+      format!("Error. A user with the email '{}' already exists.", update.email),
+    ));
+  }
+
+  user.name = update.name;
+  user.email = update.email;
+  let spec_safe_user = user.get_spec_safe_user();
+
+  database_connection.update_user(user_id, user).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::User,
+    user_id,
+    AuditAction::Update,
+    format!(
+      "{} ({}, {})",
+      spec_safe_user.email,
+      spec_safe_user.account_type.name(),
+      spec_safe_user.role.name()
+    ),
+  )
+  .await;
+
+  Ok(status::Accepted(Some(CasedJson(spec_safe_user))))
+}
+
+/// Gets the user logged in with the request's cookies, so frontends can restore their session after a reload
+///
+/// Returns 401 if not logged in, and 404 if the user was deleted since logging in
+#[openapi(tag = "Users")]
+#[get("/me")]
+async fn me(
+  database_connection: &State<ConnectionManager>,
+  token_auth: UserAuth,
+) -> Result<CasedJson<SpecSafeUser>, ApiError> {
+  match database_connection.get_user(None, Some(&token_auth.user_id)).await? {
+    Some(user) => Ok(CasedJson(user.get_spec_safe_user())),
+    None => Err(ApiError::not_found("Error. User not found.")),
+  }
+}
+
+/// Changes the password of the logged in user
+///
+/// Every session of the user is logged out, the current one included, so they have to log in again with the new
+/// password. Returns 401 if the current password is wrong, and 422 if the new one breaks the password policy, naming the
+/// rule in `details.rule`
+///
+/// # Parameters
+/// * **change** - Current and new hashed passwords of the user
+#[openapi(tag = "Users")]
+#[post("/users/password", data = "<change>")]
+async fn change_password(
+  change: Valid<PasswordChangeRequest>,
+  database_connection: &State<ConnectionManager>,
+  password_policy: &State<PasswordPolicy>,
+  jar: &CookieJar<'_>,
+  token_auth: UserAuth,
+) -> Result<status::Accepted<CasedJson<SessionsRevoked>>, ApiError> {
+  let change = change.into_inner();
+  password_policy.check(&change.new_hash).await?;
+
+  let mut user = match database_connection.get_user(None, Some(&token_auth.user_id)).await? {
+    Some(user) => user,
+    None => return Err(ApiError::not_found("Error. User not found.")),
+  };
+
+  if user.password_hash != change.current_hash {
+    return Err(ApiError::new(Status::Unauthorized, "Error. Incorrect password."));
+  }
+
+  user.password_hash = change.new_hash;
+  let email = user.email.clone();
+
+  database_connection.update_user(&token_auth.user_id, user).await?;
+
+  jar.remove_private(Cookie::named(USER_ID));
+  jar.remove_private(Cookie::named(AUTH_TOKEN));
+  csrf::remove_token(jar);
+
+  let revoked = database_connection.delete_sessions(&token_auth.user_id).await? as usize;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::User,
+    &token_auth.user_id,
+    AuditAction::ChangePassword,
+    email,
+  )
+  .await;
+
+  Ok(status::Accepted(Some(CasedJson(SessionsRevoked { revoked }))))
+}
+
+#[openapi(tag = "Users")]
+#[get("/get/user/<user_id>")]
+async fn get_user(
+  user_id: &str,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<CasedJson<SpecSafeUser>, ApiError> {
+  let user = match database_connection.get_user(None, Some(user_id)).await? {
+    Some(user) => user,
+    None => return Err(ApiError::not_found("Error. User not found.")),
+  };
+
+  Ok(CasedJson(user.get_spec_safe_user()))
+}
+
+/// Gets the users of an account type
+///
+/// Returns 400 if `limit` is out of range
+///
+/// # Parameters
+/// * **account_type** - `developer` or `client`
+/// * **skip**         - *(optional)* number of users to skip, for paging
+/// * **limit**        - *(optional)* most users to return, from 1 to 1000, every user by default
+#[openapi(tag = "Users")]
+#[get("/get/users/<account_type>?<skip>&<limit>")]
+async fn get_users(
+  account_type: Option<String>,
+  skip: Option<u64>,
+  limit: Option<i64>,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<CasedJson<Vec<SpecSafeUser>>, ApiError> {
+  let page = list_page(skip, limit)?;
+
+  let users = match account_type {
+    Some(account_type) => {
+      database_connection
+        .get_users(Some(AccountType::from(account_type)), page)
+        .await?
+    }
+    None => database_connection.get_users(None, page).await?,
+  };
+
+  Ok(CasedJson(
+    users
+      .iter()
+      .map(|x| x.get_spec_safe_user())
+      .collect::<Vec<SpecSafeUser>>(),
+  ))
+}
+
+/// Create a product with a given name
+///
+/// Can provide a list of initial users (by user ID) for the product
+///
+/// # Parameters
+/// * **name**        - Name of the new product
+/// * **strict_mode** - *(optional)* reject evaluations with users unknown to the product
+/// * **users**       - List of initial users (send empty list if none are desired)
+#[openapi(tag = "Products")]
+#[post("/create/product/<name>?<strict_mode>", data = "<users>")]
+async fn create_product(
+  name: &str,
+  strict_mode: Option<bool>,
+  users: Valid<Vec<String>>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<status::Created<CasedJson<Created>>, ApiError> {
+  let mut violations = Violations::default();
+  validation::check_product_name(&mut violations, "name", name);
+  violations.into_result()?;
+
+  let product_builder = Product::builder()
+    .with_name(name)
+    .with_users(users.into_inner())
+    .with_strict_mode(strict_mode.unwrap_or_default());
+
+  let product = database_connection.create_product(product_builder).await?;
+
+  let product_id = match product.oid {
+    Some(oid) => oid,
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Product,
+    &product_id.to_hex(),
+    AuditAction::Create,
+    product.name.clone(),
+  )
+  .await;
+
+  Ok(status::Created::new(format!("/get/product/{}", product.name)).body(CasedJson(Created::new(&product_id.to_hex()))))
+}
+
+/// Create a flag with a given name, status, the `client_toggle` enum, and release type
+///
+/// The `client_toggle` enum determines if the flag can be toggled by clients
+///
+/// Leaving release type undefined will have it default to `Global`
+///
+/// # Parameters
+/// * **name**          - Name of the new feature flag
+/// * **product_id**    - Unique ID of product the flag belongs to
+/// * **enabled**       - If the flag is enabled (true) or not (false)
+/// * **client_toggle** - If clients can toggle flags on/off for themselves
+/// * **tags**          - *(optional)* tags to group the flag by, repeat the parameter for multiple tags
+/// * **description**   - *(optional)* description of what the flag gates
+/// * **owner**         - *(optional)* team or person that owns the flag
+/// * **maintainer_id** - *(optional)* unique ID of the user maintaining the flag, returns 400 if the user doesn't exist
+/// * **prerequisites** - *(optional)* names of flags that must be enabled before this flag, repeat for multiple
+/// * **permanent**     - *(optional)* if the flag is meant to stay rather than be removed once rolled out
+/// * **release_type**  - Release type enum containing relevant data to the release type
+#[openapi(tag = "Flags")]
+#[post(
+  "/create/flag/<name>/<product_id>/<enabled>/<client_toggle>?<tags>&<description>&<owner>&<maintainer_id>&<prerequisites>&<permanent>",
+  data = "<release_type>"
+)]
+#[allow(clippy::too_many_arguments)]
+async fn create_flag(
+  name: &str,
+  product_id: &str,
+  enabled: bool,
+  client_toggle: bool,
+  tags: Vec<String>,
+  description: Option<&str>,
+  owner: Option<&str>,
+  maintainer_id: Option<&str>,
+  prerequisites: Vec<String>,
+  permanent: Option<bool>,
+  release_type: Valid<ReleaseType>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<status::Created<CasedJson<Created>>, ApiError> {
+  let mut violations = Violations::default();
+  validation::check_flag_name(&mut violations, "name", name);
+  violations.check(
+    !prerequisites.iter().any(|x| x == name),
+    "prerequisites",
+    "A flag can't be its own prerequisite.",
+  );
+  violations.into_result()?;
+
+  if let Some(maintainer_id) = maintainer_id {
+    if database_connection.get_user(None, Some(maintainer_id)).await?.is_none() {
+      return Err(ApiError::bad_request(format!(
+        "Error. Maintainer '{}' doesn't exist.",
+        maintainer_id
+      )));
+    }
+  }
+
+  let flag_builder = FeatureFlag::builder()
+    .with_name(name)
+    .with_product_id(product_id)
+    .with_enabled(enabled)
+    .with_client_toggle(client_toggle)
+    .with_tags(tags)
+    .with_description(description.unwrap_or_default())
+    .with_owner(owner.unwrap_or_default())
+    .with_maintainer_id(maintainer_id)
+    .with_prerequisites(prerequisites)
+    .with_permanent(permanent.unwrap_or_default())
+    .with_release_type(release_type.into_inner());
+
+  let flag = database_connection.create_flag(flag_builder).await?;
+
+  let flag_id = match flag.oid {
+    Some(oid) => oid,
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  let details = format!("{}/{}", flag.product_id, flag.name);
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Flag,
+    &flag_id.to_hex(),
+    AuditAction::Create,
+    details,
+  )
+  .await;
+
+  Ok(
+    status::Created::new(format!("/get/flag/{}/{}", flag.name, flag.product_id))
+      .body(CasedJson(Created::new(&flag_id.to_hex()))),
+  )
+}
+
+/// Largest number of flags created by a single `/create/flags/<product_id>` request
+const MAX_BULK_FLAGS: usize = 500;
+
+/// Creates multiple flags of a product at once, e.g. for the initial setup of a project
+///
+/// Every definition is checked on its own and the valid ones are inserted together, a definition failing doesn't stop
+/// the others, except the ones that have it as a prerequisite. Returns 400 if more than 500 flags are given, 404 if the product isn't found, and the outcome of every
+/// definition otherwise, in the order they were given
+///
+/// # Parameters
+/// * **product_id** - Unique ID of the product the flags belong to
+/// * **flags**      - Definitions of the new flags
+#[openapi(tag = "Flags")]
+#[post("/create/flags/<product_id>", data = "<flags>")]
+async fn create_flags(
+  product_id: &str,
+  flags: Json<Vec<FlagUpdateRequest>>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<CasedJson<Vec<BulkCreatedFlag>>, ApiError> {
+  let definitions = flags.into_inner();
+  if definitions.len() > MAX_BULK_FLAGS {
+    return Err(ApiError::bad_request(format!(
+      "Error. At most {} flags can be created at once.",
+      MAX_BULK_FLAGS
+    )));
+  }
+
+  if database_connection.get_product_by_id(product_id).await?.is_none() {
+    return Err(ApiError::not_found(format!(
+      "Error. Unable to get product: '{}'.",
+      product_id
+    )));
+  }
+
+  let existing: Vec<String> = database_connection
+    .get_feature_flags(
+      product_id,
+      FlagFilter {
+        include_archived: true,
+        ..Default::default()
+      },
+      FlagProjection::Summary,
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.name)
+    .collect();
+
+  let mut results: Vec<BulkCreatedFlag> = definitions
+    .iter()
+    .map(|x| BulkCreatedFlag {
+      name: x.name.clone(),
+      id: None,
+      error: None,
+    })
+    .collect();
+
+  let mut maintainers: HashMap<String, bool> = HashMap::new();
+  let mut flag_builders = vec![];
+  let mut indexes = vec![];
+
+  for (index, definition) in definitions.iter().enumerate() {
+    let violations = definition.violations();
+    let error = if !violations.is_empty() {
+      Some(format!("Error. {}", violations.summary()))
+    } else if existing.contains(&definition.name) {
+      Some(format!("Error. A flag named '{}' already exists.", definition.name))
+    } else if results[..index].iter().any(|x| x.name == definition.name) {
+      Some(format!("Error. Flag '{}' is defined more than once.", definition.name))
+    } else {
+      None
+    };
+
+    let error = match (error, &definition.maintainer_id) {
+      (None, Some(maintainer_id)) => {
+        let found = match maintainers.get(maintainer_id) {
+          Some(found) => *found,
+          None => {
+            let found = database_connection.get_user(None, Some(maintainer_id)).await?.is_some();
+            maintainers.insert(maintainer_id.clone(), found);
+            found
+          }
+        };
+        match found {
+          true => None,
+          false => Some(format!("Error. Unable to get user '{}'", maintainer_id)),
+        }
+      }
+      (error, _) => error,
+    };
+
+    results[index].error = error;
+  }
+
+  // Prerequisites must exist or be created by the request, so definitions failing fail the ones depending on them
+  loop {
+    let accepted: Vec<&str> = results
+      .iter()
+      .filter(|x| x.error.is_none())
+      .map(|x| x.name.as_str())
+      .collect();
+    let unsatisfied: Vec<(usize, String)> = definitions
+      .iter()
+      .enumerate()
+      .filter(|(index, _)| results[*index].error.is_none())
+      .filter_map(|(index, definition)| {
+        definition
+          .prerequisites
+          .iter()
+          .find(|x| !existing.contains(x) && !accepted.contains(&x.as_str()))
+          .map(|x| (index, x.clone()))
+      })
+      .collect();
+    if unsatisfied.is_empty() {
+      break;
+    }
+
+    for (index, prerequisite) in unsatisfied {
+      results[index].error = match results.iter().any(|x| x.name == prerequisite) {
+        true => Some(format!("Error. Prerequisite '{}' failed to be created.", prerequisite)),
+        false => Some(format!("Error. Prerequisite '{}' doesn't exist.", prerequisite)),
+      };
+    }
+  }
+
+  for (index, definition) in definitions.into_iter().enumerate() {
+    if results[index].error.is_none() {
+      flag_builders.push(definition.builder(product_id));
+      indexes.push(index);
+    }
+  }
+
+  let created = database_connection.create_flags(flag_builders).await?;
+  for (index, flag) in indexes.into_iter().zip(created) {
+    match flag.and_then(|x| x.oid) {
+      Some(oid) => {
+        let flag_id = oid.to_hex();
+        record_audit(
+          database_connection,
+          &token_auth.user_id,
+          EntityType::Flag,
+          &flag_id,
+          AuditAction::Create,
+          format!("{}/{}", product_id, results[index].name),
+        )
+        .await;
+        results[index].id = Some(flag_id);
+      }
+      None => results[index].error = Some("Error. Unable to create flag.".to_string()),
+    }
+  }
+
+  Ok(CasedJson(results))
+}
+
+/// Create a user with a given name, email, and password hash
+///
+/// Requires the `users:admin` permission. Returns 422 if the role isn't one of `admin`, `editor` or `viewer`, or the
+/// password breaks the password policy, naming the rule in `details.rule`
+///
+/// # Parameters
+/// * **account_type** - type of account
 /// * **name**         - Name of the new user
 /// * **email**        - Email address for the new user
 /// * **hash**         - Hashed password of the new user
+/// * **role**         - *(optional)* role of the new user, `admin` for developers and `editor` for clients by default
 #[openapi(tag = "Users")]
-#[post("/create/user/<name>/<email>/<hash>/<account_type>")]
+#[post("/create/user/<name>/<email>/<hash>/<account_type>?<role>")]
+#[allow(clippy::too_many_arguments)]
 async fn create_user(
   account_type: String,
   name: &str,
   email: &str,
   hash: &str,
+  role: Option<&str>,
+  database_connection: &State<ConnectionManager>,
+  password_policy: &State<PasswordPolicy>,
+  token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<status::Created<CasedJson<Created>>, ApiError> {
+  password_policy.check(hash).await?;
+
+  let mut user_builder = User::builder()
+    .with_name(name)
+    .with_account_type(AccountType::from(account_type))
+    .with_email(email)
+    .with_password_hash(hash);
+
+  if let Some(role) = role {
+    let mut violations = Violations::default();
+    match Role::from_name(role) {
+      Some(role) => user_builder = user_builder.with_role(role),
+      None => violations.add("role", "Roles must be 'admin', 'editor' or 'viewer'."),
+    }
+    violations.into_result()?;
+  }
+
+  let user = database_connection.create_user(user_builder).await?;
+
+  let user_id = match user.oid {
+    Some(oid) => oid,
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::User,
+    &user_id.to_hex(),
+    AuditAction::Create,
+    user.email.clone(),
+  )
+  .await;
+
+  Ok(status::Created::new(format!("/get/user/{}", &user_id.to_hex())).body(CasedJson(Created::new(&user_id.to_hex()))))
+}
+
+/// Actor of the first user's creation in the audit log, made before anyone could log in
+const BOOTSTRAP_ACTOR: &str = "bootstrap";
+
+/// Creates the first user of a fresh deployment, a developer with the `admin` role
+///
+/// Open without logging in, but only while there are no users: returns 409 once the first user exists. When
+/// `BOOTSTRAP_TOKEN` is set the request must send it as `bootstrap_token`, 401 otherwise. Log in as the new user
+/// afterwards to create the others
+///
+/// # Parameters
+/// * **setup** - Name, email and hashed password of the first user
+#[openapi(tag = "Users")]
+#[post("/setup", data = "<setup>")]
+async fn setup(
+  setup: Valid<SetupRequest>,
+  database_connection: &State<ConnectionManager>,
+  password_policy: &State<PasswordPolicy>,
+) -> Result<status::Created<CasedJson<Created>>, ApiError> {
+  if let Some(bootstrap_token) = authentication::bootstrap_token_from_env() {
+    // Compares hashes so the time taken doesn't hint at how much of the token matched
+    let sent = setup.bootstrap_token.as_deref().map(api_keys::hash);
+    if sent != Some(api_keys::hash(&bootstrap_token)) {
+      return Err(ApiError::new(
+        Status::Unauthorized,
+        "Error. Missing or incorrect bootstrap token.",
+      ));
+    }
+  }
+
+  if database_connection.count_users().await? > 0 {
+    return Err(ApiError::new(
+      Status::Conflict,
+      "Error. Setup is done, users already exist.",
+    ));
+  }
+
+  let setup = setup.into_inner();
+  password_policy.check(&setup.hash).await?;
+  let user_builder = User::builder()
+    .with_name(setup.name.trim())
+    .with_account_type(AccountType::Developer)
+    .with_email(setup.email.trim())
+    .with_password_hash(&setup.hash)
+    .with_role(Role::Admin);
+
+  let user_id = match database_connection.create_user(user_builder).await? {
+    User { oid: Some(oid), .. } => oid,
+    _ => return Err(ApiError::bad_request("Error. Unable to create user.")),
+  };
+
+  log!("Created the first user '{}' through setup", user_id.to_hex());
+  record_audit(
+    database_connection,
+    BOOTSTRAP_ACTOR,
+    EntityType::User,
+    &user_id.to_hex(),
+    AuditAction::Create,
+    setup.email.trim().to_string(),
+  )
+  .await;
+
+  Ok(status::Created::new(format!("/get/user/{}", &user_id.to_hex())).body(CasedJson(Created::new(&user_id.to_hex()))))
+}
+
+/// Login as a user
+///
+/// Sets the login cookies and returns the logged in user, with a `token` API clients can send as
+/// `Authorization: Bearer <token>` instead of the cookies. The token can be limited to `scopes` (e.g. `["flags:read"]`
+/// for a read-only script), on top of what the user's role grants. Returns 401 if the user isn't found or the password
+/// is wrong, and after failed logins 429 until the email and IP may retry, or 423 once they're locked out
+///
+/// # Parameters
+/// * **login** - Email and hashed password of the user being logged in, and optionally the scopes of the token
+#[openapi(tag = "Users")]
+#[post("/login", data = "<login>")]
+async fn login(
+  login: Valid<LoginRequest>,
+  database_connection: &State<ConnectionManager>,
+  login_throttle: &State<LoginThrottle>,
+  client_ip: Option<IpAddr>,
+  user_agent: UserAgent,
+  jar: &CookieJar<'_>,
+) -> Result<status::Accepted<CasedJson<LoggedIn>>, ApiError> {
+  login_throttle.check(&login.email, client_ip)?;
+  let logged_in = log_in(
+    &login.email,
+    &login.hash,
+    login.scopes.clone(),
+    database_connection,
+    user_agent.0.as_deref(),
+    jar,
+  )
+  .await;
+  throttle_login(login_throttle, &login.email, client_ip, logged_in)
+}
+
+/// Login as a user
+///
+/// Deprecated, the credentials end up in access logs and browser history. Use `POST /login` instead
+///
+/// # Parameters
+/// * **email** - email of the user being logged in
+/// * **hash**  - Hashed password of the user being logged in
+#[openapi(tag = "Users")]
+#[get("/login/<email>/<hash>")]
+async fn login_with_path(
+  email: &str,
+  hash: &str,
+  database_connection: &State<ConnectionManager>,
+  login_throttle: &State<LoginThrottle>,
+  client_ip: Option<IpAddr>,
+  user_agent: UserAgent,
+  jar: &CookieJar<'_>,
+) -> Result<status::Accepted<CasedJson<LoggedIn>>, ApiError> {
+  login_throttle.check(email, client_ip)?;
+  let logged_in = log_in(email, hash, None, database_connection, user_agent.0.as_deref(), jar).await;
+  throttle_login(login_throttle, email, client_ip, logged_in)
+}
+
+/// Records the outcome of a login in `login_throttle`, failed logins (401) count against the email and client IP
+fn throttle_login<T>(
+  login_throttle: &LoginThrottle,
+  email: &str,
+  client_ip: Option<IpAddr>,
+  logged_in: Result<T, ApiError>,
+) -> Result<T, ApiError> {
+  match &logged_in {
+    Ok(_) => {
+      login_throttle.clear(email);
+    }
+    Err(e) if e.status == Status::Unauthorized => login_throttle.record_failure(email, client_ip),
+    Err(_) => (),
+  }
+
+  logged_in
+}
+
+/// Checks a user's credentials and sets the login cookies, returning the logged in user. The session's token is limited
+/// to `scopes` if given
+async fn log_in(
+  email: &str,
+  hash: &str,
+  scopes: Option<Vec<Permission>>,
+  database_connection: &ConnectionManager,
+  user_agent: Option<&str>,
+  jar: &CookieJar<'_>,
+) -> Result<status::Accepted<CasedJson<LoggedIn>>, ApiError> {
+  let user = match database_connection.get_user(Some(email), None).await? {
+    Some(value) => value,
+    None => {
+      return Err(ApiError::new(
+        Status::Unauthorized,
+        format!("Error. User {} not found.", email),
+      ))
+    }
+  };
+
+  if user.password_hash == hash {
+    let logged_in = start_session(&user, scopes, database_connection, user_agent, jar).await?;
+    return Ok(status::Accepted(Some(CasedJson(logged_in))));
+  }
+
+  Err(ApiError::new(Status::Unauthorized, "Error. Incorrect password."))
+}
+
+/// Starts a session of an authenticated user limited to `scopes` if given, setting the login cookies
+///
+/// Returns 403 if the user is suspended or deactivated
+async fn start_session(
+  user: &User,
+  scopes: Option<Vec<Permission>>,
+  database_connection: &ConnectionManager,
+  user_agent: Option<&str>,
+  jar: &CookieJar<'_>,
+) -> Result<LoggedIn, ApiError> {
+  if !user.is_active() {
+    return Err(ApiError::new(
+      Status::Forbidden,
+      "Error. The account is suspended or deactivated.",
+    ));
+  }
+
+  let user_id = match user.oid {
+    Some(oid) => oid,
+    None => return Err(ApiError::bad_request("Error. Bad object ID.")),
+  };
+
+  let auth_token =
+    authentication::start_session(database_connection, &user_id.to_hex(), user_agent, scopes.clone()).await?;
+
+  // Add cookies for user id and authentication token to request
+  jar.add_private(Cookie::new(USER_ID, user_id.to_hex()));
+  jar.add_private(Cookie::new(AUTH_TOKEN, auth_token.clone()));
+  csrf::set_token(jar);
+
+  Ok(LoggedIn {
+    user: user.get_spec_safe_user(),
+    token: authentication::bearer_token(&user_id.to_hex(), &auth_token),
+    scopes,
+  })
+}
+
+/// Returns the user of a login through the identity provider `provider`, looked up by the `email` it verified
+///
+/// Users are linked to their identity at the provider (`external_id`) on their first login through it, and created with
+/// the account type and role of `access` if they don't exist. Existing users are given them too if `sync_access`, when
+/// the provider decides what users can do. Returns 403 if the user is linked to another identity
+#[allow(clippy::too_many_arguments)]
+async fn external_user(
+  database_connection: &ConnectionManager,
+  external_id: &str,
+  email: &str,
+  name: Option<&str>,
+  access: (AccountType, Role),
+  sync_access: bool,
+  provider: &str,
+) -> Result<User, ApiError> {
+  let (account_type, role) = access;
+
+  let mut user = match database_connection.get_user(Some(email), None).await? {
+    Some(user) => user,
+    None => {
+      let user_builder = User::builder()
+        .with_name(name.unwrap_or(email))
+        .with_account_type(account_type)
+        .with_role(role)
+        .with_email(email)
+        // Users created through a provider have no password, the hash of a random one can't be logged in with
+        .with_password_hash(&api_keys::hash(&oidc::new_state()))
+        .with_external_id(external_id);
+
+      let user = database_connection.create_user(user_builder).await?;
+      let user_id = user.oid.map(|x| x.to_hex()).unwrap_or_default();
+      record_audit(
+        database_connection,
+        &user_id,
+        EntityType::User,
+        &user_id,
+        AuditAction::Create,
+        format!("{} (through {})", email, provider),
+      )
+      .await;
+      return Ok(user);
+    }
+  };
+
+  let mut changes = vec![];
+  match &user.external_id {
+    Some(linked) if linked != external_id => {
+      return Err(ApiError::new(
+        Status::Forbidden,
+        format!("Error. User '{}' is linked to another identity.", email),
+      ))
+    }
+    Some(_) => (),
+    None => {
+      user.external_id = Some(external_id.to_string());
+      changes.push(format!("linked to {}", provider));
+    }
+  }
+
+  if sync_access && (user.account_type.name() != account_type.name() || user.role() != role) {
+    changes.push(format!("{}, {} through {}", account_type.name(), role.name(), provider));
+    user.account_type = account_type;
+    user.role = Some(role);
+  }
+
+  if !changes.is_empty() {
+    let user_id = user.oid.map(|x| x.to_hex()).unwrap_or_default();
+    database_connection.update_user(&user_id, user.clone()).await?;
+    record_audit(
+      database_connection,
+      &user_id,
+      EntityType::User,
+      &user_id,
+      AuditAction::Update,
+      format!("{} ({})", email, changes.join(", ")),
+    )
+    .await;
+  }
+
+  Ok(user)
+}
+
+/// Starts a login through the OpenID Connect provider, redirecting to it
+///
+/// Returns 404 if OIDC login isn't configured, 502 if the provider can't be reached, and a redirect otherwise
+#[openapi(tag = "Users")]
+#[get("/oidc/login")]
+async fn oidc_login(jar: &CookieJar<'_>) -> Result<Redirect, ApiError> {
+  let config = OidcConfig::required()?;
+  let state = oidc::new_state();
+  let url = oidc::authorization_url(&config, &state).await?;
+
+  // The provider redirects back cross-site, which strict cookies wouldn't be sent with
+  jar.add_private(Cookie::build(oidc::OIDC_STATE, state).same_site(SameSite::Lax).finish());
+
+  Ok(Redirect::to(url))
+}
+
+/// Completes a login through the OpenID Connect provider, which redirects here
+///
+/// The user is looked up by their verified email, linked to their identity at the provider on their first OIDC login,
+/// and created if they don't exist. Sets the login cookies and redirects to `OIDC_POST_LOGIN_REDIRECT`. Returns 401 if
+/// the provider denied the login or the state doesn't match, 403 if the email isn't verified or the user is linked to
+/// another identity, 404 if OIDC login isn't configured, and 502 if the provider can't be reached
+///
+/// # Parameters
+/// * **code**  - *(optional)* authorization code given by the provider
+/// * **state** - *(optional)* state given when the login was started
+/// * **error** - *(optional)* why the provider denied the login
+#[openapi(tag = "Users")]
+#[get("/oidc/callback?<code>&<state>&<error>")]
+async fn oidc_callback(
+  code: Option<&str>,
+  state: Option<&str>,
+  error: Option<&str>,
+  database_connection: &State<ConnectionManager>,
+  user_agent: UserAgent,
+  jar: &CookieJar<'_>,
+) -> Result<Redirect, ApiError> {
+  let config = OidcConfig::required()?;
+
+  let expected_state = jar.get_private(oidc::OIDC_STATE).map(|x| x.value().to_string());
+  jar.remove_private(Cookie::named(oidc::OIDC_STATE));
+
+  if let Some(error) = error {
+    return Err(ApiError::new(
+      Status::Unauthorized,
+      format!("Error. The OIDC provider denied the login: {}.", error),
+    ));
+  }
+
+  let code = match (code, state, expected_state) {
+    (Some(code), Some(state), Some(expected_state)) if state == expected_state => code,
+    _ => return Err(ApiError::new(Status::Unauthorized, "Error. Invalid OIDC login state.")),
+  };
+
+  let user_info = oidc::user_info(&config, code).await?;
+  let email = user_info.verified_email()?;
+  let external_id = format!("{}|{}", config.issuer, user_info.sub);
+
+  let user = external_user(
+    database_connection,
+    &external_id,
+    email,
+    user_info.name.as_deref(),
+    (AccountType::Client, config.default_role),
+    false,
+    &config.issuer,
+  )
+  .await?;
+
+  start_session(&user, None, database_connection, user_agent.0.as_deref(), jar).await?;
+  Ok(Redirect::to(config.post_login_redirect))
+}
+
+/// Gets the SAML service provider metadata, for registering the service at the IdP
+///
+/// Returns 404 if SAML login isn't configured
+#[openapi(tag = "Users")]
+#[get("/saml/metadata")]
+async fn saml_metadata() -> Result<(ContentType, String), ApiError> {
+  let config = SamlConfig::required()?;
+  Ok((ContentType::XML, config.metadata()))
+}
+
+/// Starts a login through the SAML IdP, redirecting to it with an authentication request
+///
+/// Returns 404 if SAML login isn't configured, and a redirect otherwise
+///
+/// # Parameters
+/// * **redirect** - *(optional)* path to redirect to once logged in, `SAML_POST_LOGIN_REDIRECT` by default
+#[openapi(tag = "Users")]
+#[get("/saml/login?<redirect>")]
+async fn saml_login(redirect: Option<&str>) -> Result<Redirect, ApiError> {
+  let config = SamlConfig::required()?;
+  Ok(Redirect::to(config.login_url(redirect)?))
+}
+
+/// Completes a login through the SAML IdP, which POSTs its response here (the assertion consumer service)
+///
+/// The user is looked up by their email, linked to their identity at the IdP on their first SAML login, and created if
+/// they don't exist. Their account type and role are set from their IdP groups on every login. Sets the login cookies
+/// and redirects to the relay state if it's a path, `SAML_POST_LOGIN_REDIRECT` otherwise. Returns 400 if the response
+/// can't be parsed, 401 if it isn't valid, 403 if the user is linked to another identity, and 404 if SAML login isn't
+/// configured
+///
+/// # Parameters
+/// * **response** - Form with the base64 encoded `SAMLResponse` and optional `RelayState`
+#[openapi(tag = "Users")]
+#[post("/saml/acs", data = "<response>")]
+async fn saml_acs(
+  response: Form<SamlPost>,
+  database_connection: &State<ConnectionManager>,
+  user_agent: UserAgent,
+  jar: &CookieJar<'_>,
+) -> Result<Redirect, ApiError> {
+  let config = SamlConfig::required()?;
+  let saml_user = saml::verify_response(&config, &response.saml_response).await?;
+
+  let provider = config.idp_entity_id.clone().unwrap_or(saml_user.issuer);
+  let user = external_user(
+    database_connection,
+    &format!("{}|{}", provider, saml_user.name_id),
+    &saml_user.email,
+    saml_user.name.as_deref(),
+    config.access(&saml_user.groups),
+    true,
+    &provider,
+  )
+  .await?;
+
+  start_session(&user, None, database_connection, user_agent.0.as_deref(), jar).await?;
+
+  // Only paths of the service are followed, so the relay state can't redirect elsewhere
+  let redirect = match response.relay_state.as_deref().and_then(saml::local_path) {
+    Some(path) => path.to_string(),
+    None => config.post_login_redirect,
+  };
+  Ok(Redirect::to(redirect))
+}
+
+/// Logs out of the session the request was sent with, the user's other sessions stay logged in
+///
+/// Returns 401 if the request has no credentials, or they aren't of a logged in session
+#[openapi(tag = "Users")]
+#[post("/logout")]
+async fn logout(
+  credentials: Option<Credentials>,
+  database_connection: &State<ConnectionManager>,
+  jar: &CookieJar<'_>,
+) -> Result<status::Accepted<()>, ApiError> {
+  // Get user ID and auth token from request cookies or bearer token
+  let Credentials { user_id, auth_token } = match credentials {
+    Some(credentials) => credentials,
+    None => return Err(ApiError::new(Status::Unauthorized, "Error. Not logged in.")),
+  };
+
+  // Remove login cookies
+  jar.remove_private(Cookie::named(USER_ID));
+  jar.remove_private(Cookie::named(AUTH_TOKEN));
+  csrf::remove_token(jar);
+
+  if database_connection
+    .delete_session(&user_id, &api_keys::hash(&auth_token))
+    .await?
+  {
+    Ok(status::Accepted(None))
+  } else {
+    Err(ApiError::new(Status::Unauthorized, "Error. Not logged into server."))
+  }
+}
+
+/// Logs the current user out of every session, on every device
+///
+/// Returns how many sessions were logged out, the current one included
+#[openapi(tag = "Users")]
+#[post("/logout/all")]
+async fn logout_all(
+  database_connection: &State<ConnectionManager>,
+  jar: &CookieJar<'_>,
+  token_auth: UserAuth,
+) -> Result<status::Accepted<CasedJson<SessionsRevoked>>, ApiError> {
+  jar.remove_private(Cookie::named(USER_ID));
+  jar.remove_private(Cookie::named(AUTH_TOKEN));
+  csrf::remove_token(jar);
+
+  Ok(status::Accepted(Some(CasedJson(SessionsRevoked {
+    revoked: database_connection.delete_sessions(&token_auth.user_id).await? as usize,
+  }))))
+}
+
+/// Lifts the login lockout of a user, and the wait after their recent failed logins, developers with `users:admin` only
+///
+/// Returns 403 if the caller isn't a developer or their role or token doesn't grant `users:admin`, 404 if the user
+/// isn't found, and 204 otherwise
+///
+/// # Parameters
+/// * **user_id** - Unique ID of the user to unlock
+#[openapi(tag = "Users")]
+#[post("/users/<user_id>/unlock")]
+async fn unlock_user(
+  user_id: &str,
+  database_connection: &State<ConnectionManager>,
+  login_throttle: &State<LoginThrottle>,
+  token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<status::NoContent, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "unlock users").await?;
+
+  let user = match database_connection.get_user(None, Some(user_id)).await? {
+    Some(user) => user,
+    None => return Err(ApiError::not_found(format!("Error. Unable to get user '{}'", user_id))),
+  };
+
+  if login_throttle.clear(&user.email) {
+    record_audit(
+      database_connection,
+      &token_auth.user_id,
+      EntityType::User,
+      user_id,
+      AuditAction::Unlock,
+      user.email,
+    )
+    .await;
+  }
+
+  Ok(status::NoContent)
+}
+
+/// Suspends, deactivates or reactivates a user
+///
+/// Requires the `users:admin` permission. Suspended and deactivated users can't log in, their sessions are ended, and
+/// they can no longer hoist or lower flags. Returns 403 for the caller's own account, 404 if the user isn't found, and
+/// 202 with the updated user otherwise
+///
+/// # Parameters
+/// * **user_id** - Unique ID of the user
+/// * **update**  - New status of the user
+#[openapi(tag = "Users")]
+#[put("/users/<user_id>/status", data = "<update>")]
+async fn update_user_status(
+  user_id: &str,
+  update: Valid<UserStatusRequest>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<status::Accepted<CasedJson<SpecSafeUser>>, ApiError> {
+  if token_auth.user_id == user_id {
+    return Err(ApiError::new(
+      Status::Forbidden,
+      "Error. Users can't change their own status.",
+    ));
+  }
+
+  let mut user = match database_connection.get_user(None, Some(user_id)).await? {
+    Some(user) => user,
+    None => return Err(ApiError::not_found(format!("Error. Unable to get user '{}'", user_id))),
+  };
+
+  user.status = update.status;
+  let spec_safe_user = user.get_spec_safe_user();
+  let is_active = user.is_active();
+
+  database_connection.update_user(user_id, user).await?;
+
+  if !is_active {
+    database_connection.delete_sessions(user_id).await?;
+  }
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::User,
+    user_id,
+    AuditAction::UpdateStatus,
+    format!("{} ({})", spec_safe_user.email, spec_safe_user.status.name()),
+  )
+  .await;
+
+  Ok(status::Accepted(Some(CasedJson(spec_safe_user))))
+}
+
+/// Gets a page of the audit log, newest entries first
+///
+/// Every given filter must match. Returns 400 if a filter or the cursor is invalid. Needs `users:admin`, returns 403
+/// otherwise
+///
+/// # Parameters
+/// * **actor**       - *(optional)* only list changes made by this user ID (or email, for hoists and lowers)
+/// * **entity_type** - *(optional)* only list changes to `product`, `flag`, `user` or `webhook` entities
+/// * **action**      - *(optional)* only list this action (e.g. `hoist`, `update_rules`)
+/// * **from**        - *(optional)* only list changes made at or after this RFC 3339 time
+/// * **to**          - *(optional)* only list changes made at or before this RFC 3339 time
+/// * **after**       - *(optional)* cursor returned with the previous page
+/// * **limit**       - *(optional)* number of entries per page, defaults to 50 and is capped at 500
+#[openapi(tag = "Audit")]
+#[get("/get/audit?<actor>&<entity_type>&<action>&<from>&<to>&<after>&<limit>")]
+#[allow(clippy::too_many_arguments)]
+async fn get_audit(
+  actor: Option<&str>,
+  entity_type: Option<&str>,
+  action: Option<&str>,
+  from: Option<&str>,
+  to: Option<&str>,
+  after: Option<&str>,
+  limit: Option<i64>,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<CasedJson<AuditPage>, ApiError> {
+  let filter = audit_filter(actor, entity_type, action, from, to).map_err(ApiError::bad_request)?;
+  let limit = limit.unwrap_or(50).clamp(1, AUDIT_PAGE_LIMIT);
+
+  let entries = match database_connection.get_audit_entries(&filter, after, limit).await? {
+    Some(entries) => entries,
+    None => return Err(ApiError::bad_request("Error. Unable to get audit entries.")),
+  };
+
+  let next_cursor = match entries.len() as i64 == limit {
+    true => entries.last().and_then(|x| x.oid).map(|x| x.to_hex()),
+    false => None,
+  };
+
+  Ok(CasedJson(AuditPage {
+    entries: entries.iter().map(|x| x.get_spec_safe_audit_entry()).collect(),
+    next_cursor,
+  }))
+}
+
+/// Exports a product's flags and settings as a portable JSON or YAML document, e.g. for backups or code review
+///
+/// IDs are left out and flags are ordered by name so exports can be diffed, archived flags are included. Service
+/// accounts and API keys need access to the product and a scope granting `flags:read`. Returns 400 if the format is unknown and 404 if the
+/// product isn't found
+///
+/// # Parameters
+/// * **product_id** - Unique ID of the product
+/// * **format**     - *(optional)* `json` (default) or `yaml`
+#[openapi(tag = "Products")]
+#[get("/export/product/<product_id>?<format>")]
+async fn export_product(
+  product_id: &str,
+  format: Option<&str>,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::FlagsRead>,
+) -> Result<(ContentType, String), ApiError> {
+  let format = export_format(format)?;
+
+  let product = match database_connection.get_product_by_id(product_id).await? {
+    Some(product) => product,
+    None => {
+      return Err(ApiError::not_found(format!(
+        "Error. Unable to get product: '{}'.",
+        product_id
+      )))
+    }
+  };
+
+  let flags = database_connection
+    .get_feature_flags(
+      product_id,
+      FlagFilter {
+        include_archived: true,
+        ..Default::default()
+      },
+      FlagProjection::Full,
+    )
+    .await?;
+
+  match format.render(&ProductExport::new(&product, &flags)) {
+    Ok(document) => Ok((format.content_type(), document)),
+    Err(e) => Err(ApiError::new(
+      Status::InternalServerError,
+      format!("Error. Unable to export product: {}.", e),
+    )),
+  }
+}
+
+/// Imports a document in the format of `/export/product/<product_id>` into a product, e.g. to promote flags between
+/// environments or restore a backup
+///
+/// The document is validated and compared to the product first. With `dry_run` the differences are only reported,
+/// otherwise the flags of the document are created or replaced and the product's settings are replaced, all in one
+/// transaction. Flags missing from the document are left as they are. Returns 400 if the document is invalid, 404 if the
+/// product isn't found, 413 if the document is larger than 5 MiB, and the differences otherwise
+///
+/// # Parameters
+/// * **product_id**      - Unique ID of the product
+/// * **format**          - *(optional)* `json` (default) or `yaml`
+/// * **dry_run**         - *(optional)* only report the differences
+/// * **override_freeze** - *(optional)* import during a freeze window (423 otherwise), developers only
+/// * **document**        - Exported document
+#[openapi(tag = "Products")]
+#[post(
+  "/import/product/<product_id>?<format>&<dry_run>&<override_freeze>",
+  data = "<document>"
+)]
+#[allow(clippy::too_many_arguments)]
+async fn import_product(
+  product_id: &str,
+  format: Option<&str>,
+  dry_run: Option<bool>,
+  override_freeze: Option<bool>,
+  document: Data<'_>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+  _member: ProductMember,
+) -> Result<CasedJson<ImportReport>, ApiError> {
+  let format = export_format(format)?;
+
+  let document = match document.open(MAX_IMPORT_SIZE.bytes()).into_string().await {
+    Ok(document) if document.is_complete() => document.into_inner(),
+    Ok(_) => {
+      return Err(ApiError::new(
+        Status::PayloadTooLarge,
+        format!("Error. Imports can't be larger than {} bytes.", MAX_IMPORT_SIZE),
+      ))
+    }
+    Err(e) => return Err(ApiError::bad_request(e.to_string())),
+  };
+
+  let document = match format.parse(&document) {
+    Ok(document) => document,
+    Err(e) => return Err(ApiError::bad_request(format!("Error. Invalid document: {}.", e))),
+  };
+
+  let mut product = match database_connection.get_product_by_id(product_id).await? {
+    Some(product) => product,
+    None => {
+      return Err(ApiError::not_found(format!(
+        "Error. Unable to get product: '{}'.",
+        product_id
+      )))
+    }
+  };
+
+  let flags = database_connection
+    .get_feature_flags(
+      product_id,
+      FlagFilter {
+        include_archived: true,
+        ..Default::default()
+      },
+      FlagProjection::Full,
+    )
+    .await?;
+
+  if let Err(e) = document.validate(&flags) {
+    return Err(ApiError::bad_request(e));
+  }
+
+  for maintainer_id in document
+    .flags
+    .iter()
+    .filter_map(|x| x.definition.maintainer_id.as_ref())
+  {
+    if database_connection.get_user(None, Some(maintainer_id)).await?.is_none() {
+      return Err(ApiError::bad_request(format!(
+        "Error. Unable to get user '{}'",
+        maintainer_id
+      )));
+    }
+  }
+
+  let mut report = document.diff(&product, &flags);
+  report.dry_run = dry_run.unwrap_or(false);
+  if report.dry_run {
+    return Ok(CasedJson(report));
+  }
+
+  check_freeze(database_connection, product_id, override_freeze, &token_auth.user_id).await?;
+
+  let product_changed = !report.product_changes.is_empty();
+  if product_changed {
+    product.strict_mode = document.product.strict_mode;
+    product.settings = document.product.settings;
+    product.environments = document.product.environments.into_iter().collect();
+    product.freeze_windows = document.product.freeze_windows;
+  }
+
+  let mut existing: HashMap<String, FeatureFlag> = flags.into_iter().map(|x| (x.name.clone(), x)).collect();
+  let mut updated = vec![];
+  let mut flag_builders = vec![];
+  for imported in document.flags {
+    let name = imported.definition.name.clone();
+
+    let mut flag = match existing.remove(&name) {
+      Some(flag) => flag,
+      None => {
+        flag_builders.push(imported.definition.builder(product_id).with_archived(imported.archived));
+        continue;
+      }
+    };
+    if !report.updated.iter().any(|x| x.name == name) {
+      continue;
+    }
+
+    imported.definition.apply(&mut flag);
+    flag.archived = imported.archived;
+    rule::prioritize(&mut flag.rules);
+    updated.push(flag);
+  }
+
+  // Written in one transaction, so a failure doesn't leave the product half imported
+  let audited: Vec<(String, String)> = updated
+    .iter()
+    .map(|x| (x.oid.map(|x| x.to_hex()).unwrap_or_default(), x.name.clone()))
+    .collect();
+  let created = database_connection
+    .import_product(product_id, product_changed.then_some(product), updated, flag_builders)
+    .await?;
+
+  if product_changed {
+    record_audit(
+      database_connection,
+      &token_auth.user_id,
+      EntityType::Product,
+      product_id,
+      AuditAction::Update,
+      format!("imported {}", report.product_changes.join(", ")),
+    )
+    .await;
+  }
+  for (flag_id, name) in audited {
+    record_audit(
+      database_connection,
+      &token_auth.user_id,
+      EntityType::Flag,
+      &flag_id,
+      AuditAction::Update,
+      format!("{}/{} (imported)", product_id, name),
+    )
+    .await;
+  }
+  for flag in created {
+    record_audit(
+      database_connection,
+      &token_auth.user_id,
+      EntityType::Flag,
+      &flag.oid.map(|x| x.to_hex()).unwrap_or_default(),
+      AuditAction::Create,
+      format!("{}/{} (imported)", product_id, flag.name),
+    )
+    .await;
+  }
+
+  Ok(CasedJson(report))
+}
+
+/// Parses the format of an export or import, JSON by default, with 400 if it's unknown
+fn export_format(format: Option<&str>) -> Result<ExportFormat, ApiError> {
+  match format {
+    Some(name) => match ExportFormat::from_name(name) {
+      Some(format) => Ok(format),
+      None => Err(ApiError::bad_request(format!(
+        "Error. Unknown export format '{}', expected json or yaml.",
+        name
+      ))),
+    },
+    None => Ok(ExportFormat::Json),
+  }
+}
+
+/// Exports the audit log as CSV, newest entries first
+///
+/// The export is streamed page by page, so it can cover the whole log. Returns 400 if a filter is invalid. Needs
+/// `users:admin`, returns 403 otherwise
+///
+/// # Parameters
+/// * **actor**       - *(optional)* only export changes made by this user ID (or email, for hoists and lowers)
+/// * **entity_type** - *(optional)* only export changes to `product`, `flag`, `user` or `webhook` entities
+/// * **action**      - *(optional)* only export this action (e.g. `hoist`, `update_rules`)
+/// * **from**        - *(optional)* only export changes made at or after this RFC 3339 time
+/// * **to**          - *(optional)* only export changes made at or before this RFC 3339 time
+#[openapi(tag = "Audit")]
+#[get("/export/audit?<actor>&<entity_type>&<action>&<from>&<to>")]
+async fn export_audit(
+  actor: Option<&str>,
+  entity_type: Option<&str>,
+  action: Option<&str>,
+  from: Option<&str>,
+  to: Option<&str>,
+  database_connection: &State<ConnectionManager>,
+  _token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<(ContentType, TextStream<BoxStream<'static, String>>), ApiError> {
+  let filter = audit_filter(actor, entity_type, action, from, to).map_err(ApiError::bad_request)?;
+  let database_connection = database_connection.inner().clone();
+
+  // Each page is streamed as one chunk, the page after it is only read once the chunk has been sent
+  let pages = stream::unfold(Some((database_connection, None)), move |state| {
+    let filter = filter.clone();
+    async move {
+      let (database_connection, after): (ConnectionManager, Option<String>) = state?;
+      let entries = database_connection
+        .get_audit_entries(&filter, after.as_deref(), AUDIT_PAGE_LIMIT)
+        .await
+        .ok()
+        .flatten()?;
+
+      let next = match entries.len() as i64 == AUDIT_PAGE_LIMIT {
+        true => entries
+          .last()
+          .and_then(|x| x.oid)
+          .map(|x| (database_connection, Some(x.to_hex()))),
+        false => None,
+      };
+
+      Some((entries.iter().map(|x| x.csv_row()).collect::<String>(), next))
+    }
+  });
+
+  let csv = stream::once(async { AuditEntry::csv_header().to_string() }).chain(pages);
+
+  Ok((ContentType::CSV, TextStream::from(csv.boxed())))
+}
+
+/// Largest page of audit entries read at once
+const AUDIT_PAGE_LIMIT: i64 = 500;
+
+/// Builds an `AuditFilter` from query parameters, returning a description of the first invalid parameter on error
+fn audit_filter(
+  actor: Option<&str>,
+  entity_type: Option<&str>,
+  action: Option<&str>,
+  from: Option<&str>,
+  to: Option<&str>,
+) -> Result<AuditFilter, String> {
+  let entity_type = match entity_type {
+    Some(value) => match EntityType::from_name(value) {
+      Some(entity_type) => Some(entity_type),
+      None => return Err(format!("Error. Unknown entity type '{}'.", value)),
+    },
+    None => None,
+  };
+
+  let action = match action {
+    Some(value) => match AuditAction::from_name(value) {
+      Some(action) => Some(action),
+      None => return Err(format!("Error. Unknown action '{}'.", value)),
+    },
+    None => None,
+  };
+
+  let parse_time = |value: &str| match DateTime::parse_from_rfc3339(value) {
+    Ok(time) => Ok(time.with_timezone(&Utc)),
+    Err(_) => Err(format!("Error. '{}' isn't an RFC 3339 time.", value)),
+  };
+
+  Ok(AuditFilter {
+    actor: actor.map(|x| x.to_string()),
+    entity_type,
+    action,
+    from: from.map(parse_time).transpose()?,
+    to: to.map(parse_time).transpose()?,
+  })
+}
+
+/// Rejects callers that aren't developers with 403, `action` says what they can't do (e.g. `manage webhooks`)
+async fn require_developer(
+  database_connection: &ConnectionManager,
+  user_id: &str,
+  action: &str,
+) -> Result<(), ApiError> {
+  match database_connection.get_user(None, Some(user_id)).await? {
+    Some(user) if matches!(user.account_type, AccountType::Developer) => Ok(()),
+    _ => Err(ApiError::new(
+      Status::Forbidden,
+      format!("Error. Only developers can {}.", action),
+    )),
+  }
+}
+
+/// Records a change in the audit log
+async fn record_audit(
+  database_connection: &ConnectionManager,
+  actor: &str,
+  entity_type: EntityType,
+  entity_id: &str,
+  action: AuditAction,
+  details: String,
+) {
+  // Failing to record is logged, and doesn't fail the change
+  let _ = database_connection
+    .create_audit_entry(
+      AuditEntry::builder()
+        .with_actor(actor)
+        .with_entity(entity_type, entity_id)
+        .with_action(action)
+        .with_details(&details),
+    )
+    .await;
+}
+
+/// Rejects a change to the flags of a product with 423 while the product is in a freeze window
+///
+/// Developers can make the change anyway by setting `override_freeze`, `user_id` is the ID of the caller
+async fn check_freeze(
+  database_connection: &ConnectionManager,
+  product_id: &str,
+  override_freeze: Option<bool>,
+  user_id: &str,
+) -> Result<(), ApiError> {
+  let product = match database_connection.get_product_by_id(product_id).await? {
+    Some(product) => product,
+    None => return Ok(()),
+  };
+
+  if !product.is_frozen(Utc::now()) {
+    return Ok(());
+  }
+
+  if override_freeze.unwrap_or(false) {
+    if let Some(user) = database_connection.get_user(None, Some(user_id)).await? {
+      if matches!(user.account_type, AccountType::Developer) {
+        return Ok(());
+      }
+    }
+  }
+
+  Err(ApiError::new(
+    Status::Locked,
+    format!("Error. Product '{}' is in a freeze window.", product.name),
+  ))
+}
+
+/// Checks an optional comment isn't empty or longer than `MAX_COMMENT_LENGTH`, returning a description of the problem
+fn validate_comment(comment: Option<&str>) -> Result<(), String> {
+  match comment.map(|x| x.trim()) {
+    Some("") => Err("Error. Comments can't be empty.".to_string()),
+    Some(comment) if comment.chars().count() > MAX_COMMENT_LENGTH => Err(format!(
+      "Error. Comments can't be longer than {} characters.",
+      MAX_COMMENT_LENGTH
+    )),
+    _ => Ok(()),
+  }
+}
+
+/// Audit details of a change to a flag, with the comment justifying it if there is one
+fn change_details(product_id: &str, feature: &str, comment: Option<&str>) -> String {
+  match comment {
+    Some(comment) => format!("{}/{}: {}", product_id, feature, comment.trim()),
+    None => format!("{}/{}", product_id, feature),
+  }
+}
+
+/// Adds the comment justifying a change to the flag's comments, does nothing without a comment
+async fn record_comment(
+  database_connection: &ConnectionManager,
+  flag_id: &str,
+  author: &str,
+  comment: Option<&str>,
+  action: AuditAction,
+) {
+  if let Some(comment) = comment {
+    let comment_builder = Comment::builder()
+      .with_flag_id(flag_id)
+      .with_author(author)
+      .with_body(comment.trim())
+      .with_action(action);
+
+    let _ = database_connection.create_comment(comment_builder).await;
+  }
+}
+
+/// Creates a webhook, notified of changes to flags and products with signed POSTs
+///
+/// Developers whose role grants `flags:write` only. Returns 422 if the webhook is malformed or has no secret, 404 if
+/// the product isn't found, and 201 with the webhook's ID otherwise
+///
+/// # Parameters
+/// * **webhook** - URL, secret, and which changes to deliver
+#[openapi(tag = "Webhooks")]
+#[post("/webhooks", data = "<webhook>")]
+async fn create_webhook(
+  webhook: Valid<WebhookRequest>,
   database_connection: &State<ConnectionManager>,
-  _token_auth: UserAuth,
-) -> Result<status::Created<Json<Created>>, status::BadRequest<()>> {
-  let user_builder = User::builder()
-    .with_name(name)
-    .with_account_type(AccountType::from(account_type))
-    .with_email(email)
-    .with_password_hash(hash);
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<status::Created<CasedJson<Created>>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage webhooks").await?;
 
-  let user = match database_connection.create_user(user_builder).await {
-    Some(value) => value,
-    None => return Err(status::BadRequest(None)),
+  let webhook = webhook.into_inner();
+  validate_webhook(database_connection, &webhook).await?;
+
+  let secret = match &webhook.secret {
+    Some(secret) => secret.clone(),
+    None => {
+      return Err(ApiError::bad_request(
+        "Error. Webhooks need a secret to sign deliveries with.",
+      ))
+    }
   };
 
-  let user_id = match user.oid {
-    Some(oid) => oid,
-    None => return Err(status::BadRequest(None)),
+  let webhook_builder = Webhook::builder()
+    .with_url(&webhook.url)
+    .with_secret(&secret)
+    .with_product_id(webhook.product_id)
+    .with_events(webhook.events)
+    .with_user_identifiers(webhook.user_identifiers)
+    .with_enabled(webhook.enabled.unwrap_or(true))
+    .with_created_by(&token_auth.user_id);
+
+  let webhook_id = match database_connection.create_webhook(webhook_builder).await? {
+    Webhook { oid: Some(oid), .. } => oid.to_hex(),
+    _ => return Err(ApiError::bad_request("Error. Unable to create webhook.")),
   };
 
-  Ok(status::Created::new(format!("/get/user/{}", &user_id.to_hex())).body(Json(Created::new(&user_id.to_hex()))))
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Webhook,
+    &webhook_id,
+    AuditAction::Create,
+    webhook.url,
+  )
+  .await;
+
+  Ok(status::Created::new(format!("/webhooks/{}", webhook_id)).body(CasedJson(Created::new(&webhook_id))))
 }
 
-/// Login as a user
+/// Lists webhooks, secrets left out
+///
+/// Developers whose role grants `flags:write` only
 ///
 /// # Parameters
-/// * **email** - email of the user being logged in
-/// * **hash**  - Hashed password of the user being logged in
-#[openapi(tag = "Users")]
-#[get("/login/<email>/<hash>")]
-async fn login(
-  email: &str,
-  hash: &str,
+/// * **product_id** - *(optional)* only list the webhooks of this product
+#[openapi(tag = "Webhooks")]
+#[get("/webhooks?<product_id>")]
+async fn get_webhooks(
+  product_id: Option<&str>,
   database_connection: &State<ConnectionManager>,
-  auth_tokens_mut: &State<Arc<Mutex<AuthTokens>>>,
-  jar: &CookieJar<'_>,
-) -> Result<status::Accepted<Json<SpecSafeUser>>, status::BadRequest<String>> {
-  let user = match database_connection.get_user(Some(email), None).await {
-    Some(value) => value,
-    None => return Err(status::BadRequest(Some(format!("User {} not found", email)))),
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<CasedJson<Vec<SpecSafeWebhook>>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage webhooks").await?;
+
+  Ok(CasedJson(
+    database_connection
+      .get_webhooks(product_id)
+      .await?
+      .iter()
+      .map(|x| x.get_spec_safe_webhook())
+      .collect(),
+  ))
+}
+
+/// Gets a webhook, its secret left out
+///
+/// Developers whose role grants `flags:write` only. Returns 404 if the webhook isn't found
+///
+/// # Parameters
+/// * **webhook_id** - Unique ID of the webhook
+#[openapi(tag = "Webhooks")]
+#[get("/webhooks/<webhook_id>")]
+async fn get_webhook(
+  webhook_id: &str,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<CasedJson<SpecSafeWebhook>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage webhooks").await?;
+
+  let webhook = find_webhook(database_connection, webhook_id).await?;
+
+  Ok(CasedJson(webhook.get_spec_safe_webhook()))
+}
+
+/// Replaces the definition of a webhook, keeping its secret if none is given
+///
+/// Developers whose role grants `flags:write` only. Returns 422 if the webhook is malformed, 404 if the webhook or
+/// product isn't found, and 202 with the updated webhook otherwise
+///
+/// # Parameters
+/// * **webhook_id** - Unique ID of the webhook
+/// * **update**     - New URL, secret, and which changes to deliver
+#[openapi(tag = "Webhooks")]
+#[put("/webhooks/<webhook_id>", data = "<update>")]
+async fn update_webhook(
+  webhook_id: &str,
+  update: Valid<WebhookRequest>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<status::Accepted<CasedJson<SpecSafeWebhook>>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage webhooks").await?;
+
+  let update = update.into_inner();
+  validate_webhook(database_connection, &update).await?;
+
+  let mut webhook = find_webhook(database_connection, webhook_id).await?;
+  webhook.url = update.url;
+  if let Some(secret) = update.secret {
+    webhook.secret = secret;
+  }
+  webhook.product_id = update.product_id;
+  webhook.events = update.events;
+  webhook.user_identifiers = update.user_identifiers;
+  webhook.enabled = update.enabled.unwrap_or(true);
+
+  let spec_safe_webhook = webhook.get_spec_safe_webhook();
+  let details = webhook.url.clone();
+
+  database_connection.update_webhook(webhook_id, webhook).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Webhook,
+    webhook_id,
+    AuditAction::Update,
+    details,
+  )
+  .await;
+
+  Ok(status::Accepted(Some(CasedJson(spec_safe_webhook))))
+}
+
+/// Deletes a webhook and the history of its deliveries
+///
+/// Developers whose role grants `flags:write` only. Returns 404 if the webhook isn't found, and 204 otherwise
+///
+/// # Parameters
+/// * **webhook_id** - Unique ID of the webhook
+#[openapi(tag = "Webhooks")]
+#[delete("/webhooks/<webhook_id>")]
+async fn delete_webhook(
+  webhook_id: &str,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<status::NoContent, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage webhooks").await?;
+
+  let webhook = find_webhook(database_connection, webhook_id).await?;
+
+  database_connection.delete_webhook(webhook_id).await?;
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::Webhook,
+    webhook_id,
+    AuditAction::Delete,
+    webhook.url,
+  )
+  .await;
+
+  Ok(status::NoContent)
+}
+
+/// Default number of deliveries listed when no `limit` is given
+const DEFAULT_DELIVERY_LIMIT: i64 = 50;
+/// Most deliveries listed at once
+const MAX_DELIVERY_LIMIT: i64 = 500;
+
+/// Lists the deliveries to a webhook, newest first, e.g. `status=dead` to list its dead letters
+///
+/// Developers whose role grants `flags:write` only. Returns 400 if the status is unknown, and 404 if the webhook isn't
+/// found
+///
+/// # Parameters
+/// * **webhook_id** - Unique ID of the webhook
+/// * **status**     - *(optional)* only list `pending`, `delivered`, or `dead` deliveries
+/// * **limit**      - *(optional)* number of deliveries to list, defaults to 50 and is capped at 500
+#[openapi(tag = "Webhooks")]
+#[get("/webhooks/<webhook_id>/deliveries?<status>&<limit>")]
+async fn get_webhook_deliveries(
+  webhook_id: &str,
+  status: Option<&str>,
+  limit: Option<i64>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<CasedJson<Vec<SpecSafeWebhookDelivery>>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage webhooks").await?;
+
+  let status = match status.map(|x| (x, DeliveryStatus::from_name(x))) {
+    Some((_, Some(status))) => Some(status),
+    Some((name, None)) => {
+      return Err(ApiError::bad_request(format!(
+        "Error. Unknown delivery status '{}'.",
+        name
+      )))
+    }
+    None => None,
   };
 
-  if user.password_hash == hash {
-    let mut auth_tokens = match auth_tokens_mut.lock() {
-      Ok(value) => value,
-      Err(poisoned) => poisoned.into_inner(), // recover from poisoned mutex
-    };
+  find_webhook(database_connection, webhook_id).await?;
 
-    let user_id = match user.oid {
-      Some(oid) => oid,
-      None => return Err(status::BadRequest(None)),
-    };
+  let limit = limit.unwrap_or(DEFAULT_DELIVERY_LIMIT).clamp(1, MAX_DELIVERY_LIMIT);
+
+  Ok(CasedJson(
+    database_connection
+      .get_webhook_deliveries(webhook_id, status, limit)
+      .await?
+      .iter()
+      .map(|x| x.get_spec_safe_webhook_delivery())
+      .collect(),
+  ))
+}
+
+/// Delivers an earlier event to a webhook again, e.g. a dead letter once the destination is fixed
+///
+/// The same payload is sent with a new signature and a fresh set of attempts. Developers whose role grants
+/// `flags:write` only. Returns 404 if the webhook or delivery isn't found, and the delivery after its first attempt
+/// otherwise
+///
+/// # Parameters
+/// * **webhook_id**  - Unique ID of the webhook
+/// * **delivery_id** - Unique ID of the delivery
+#[openapi(tag = "Webhooks")]
+#[post("/webhooks/<webhook_id>/deliveries/<delivery_id>/retry")]
+async fn retry_webhook_delivery(
+  webhook_id: &str,
+  delivery_id: &str,
+  database_connection: &State<ConnectionManager>,
+  dispatcher: &State<Dispatcher>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<CasedJson<SpecSafeWebhookDelivery>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage webhooks").await?;
+
+  let webhook = find_webhook(database_connection, webhook_id).await?;
+
+  let delivery = match database_connection.get_webhook_delivery(delivery_id).await? {
+    Some(delivery) if delivery.webhook_id == webhook_id => delivery,
+    _ => {
+      return Err(ApiError::not_found(format!(
+        "Error. Unable to get delivery '{}' of webhook '{}'.",
+        delivery_id, webhook_id
+      )))
+    }
+  };
 
-    // Add cookies for user id and authentication token to request
-    jar.add_private(Cookie::new(USER_ID, user_id.to_hex()));
-    jar.add_private(Cookie::new(AUTH_TOKEN, auth_tokens.add_token(&user_id.to_hex())));
+  let delivery = dispatcher.retry(webhook, delivery).await;
+
+  Ok(CasedJson(delivery.get_spec_safe_webhook_delivery()))
+}
 
+/// Sends a test event to a webhook, to check it receives, verifies, and accepts deliveries
+///
+/// The event has type `webhook.test` and `test` set in its payload, and goes through the same signing, retries, and
+/// delivery log as changes do. Developers whose role grants `flags:write` only. Returns 404 if the webhook isn't found,
+/// and the delivery after its first attempt otherwise, with the status and body the destination responded with
+///
+/// # Parameters
+/// * **webhook_id** - Unique ID of the webhook
+#[openapi(tag = "Webhooks")]
+#[post("/webhooks/<webhook_id>/test")]
+async fn test_webhook(
+  webhook_id: &str,
+  database_connection: &State<ConnectionManager>,
+  dispatcher: &State<Dispatcher>,
+  token_auth: Scoped<scopes::FlagsWrite>,
+) -> Result<CasedJson<SpecSafeWebhookDelivery>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage webhooks").await?;
 
+  let webhook = find_webhook(database_connection, webhook_id).await?;
 
-    return Ok(status::Accepted(Some(Json(user.get_spec_safe_user()))));
+  match dispatcher.test(webhook).await {
+    Some(delivery) => Ok(CasedJson(delivery.get_spec_safe_webhook_delivery())),
+    None => Err(ApiError::new(
+      Status::InternalServerError,
+      "Error. Unable to create the test event.",
+    )),
   }
+}
 
-  Err(status::BadRequest(Some("Incorrect password".to_string())))
+/// Gets a webhook, with 404 if it isn't found
+async fn find_webhook(database_connection: &ConnectionManager, webhook_id: &str) -> Result<Webhook, ApiError> {
+  match database_connection.get_webhook(webhook_id).await? {
+    Some(webhook) => Ok(webhook),
+    None => Err(ApiError::not_found(format!(
+      "Error. Unable to get webhook '{}'.",
+      webhook_id
+    ))),
+  }
 }
 
-#[openapi(tag = "Users")]
-#[post("/logout")]
-async fn logout(auth_tokens_mut: &State<Arc<Mutex<AuthTokens>>>, jar: &CookieJar<'_>,) -> Result<status::Accepted<()>, status::BadRequest<String>> {
-    // Get user ID from request cookies
-    let user_id = match jar.get_private(USER_ID) {
-        Some(user_id) => user_id.value().to_string(),
-        None => return Err(status::BadRequest(Some("Not logged in".to_string()))),
-    };
-    
-    // Remove login cookies
-    jar.remove_private(Cookie::named(USER_ID));
-    jar.remove_private(Cookie::named(AUTH_TOKEN));
-
-    let mut auth_tokens = match auth_tokens_mut.lock() {
-        Ok(auth_tokens) => auth_tokens,
-        Err(poisoned) => poisoned.into_inner(),
-    };
+/// Checks the product of a webhook exists, with 404 otherwise
+async fn validate_webhook(database_connection: &ConnectionManager, webhook: &WebhookRequest) -> Result<(), ApiError> {
+  if let Some(product_id) = &webhook.product_id {
+    if database_connection.get_product_by_id(product_id).await?.is_none() {
+      return Err(ApiError::not_found(format!(
+        "Error. Unable to get product: '{}'.",
+        product_id
+      )));
+    }
+  }
 
-    if auth_tokens.remove_token(&user_id) {
-        return Ok(status::Accepted(None))
-    } else {
-        return Err(status::BadRequest(Some("Not logged into server".to_string())))
+  Ok(())
+}
+
+/// Creates an API key of a product, authorizing SDKs to check its flags by sending it in the `X-Api-Key` header
+///
+/// Developers whose role grants `users:admin` only. The key is only shown in this response, just its hash is stored.
+/// Returns 404 if the product isn't found, and 201 with the key otherwise
+///
+/// # Parameters
+/// * **product_id** - Unique ID of the product the key gives access to
+/// * **api_key**    - Name of the key, whether it's a `server` or `client` key, and optionally its scopes
+#[openapi(tag = "API Keys")]
+#[post("/products/<product_id>/api-keys", data = "<api_key>")]
+async fn create_api_key(
+  product_id: &str,
+  api_key: Valid<ApiKeyRequest>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<status::Created<CasedJson<CreatedApiKey>>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage API keys").await?;
+
+  if database_connection.get_product_by_id(product_id).await?.is_none() {
+    return Err(ApiError::not_found(format!(
+      "Error. Unable to get product: '{}'.",
+      product_id
+    )));
+  }
+
+  let api_key = api_key.into_inner();
+  let (key, key_prefix) = api_keys::generate(api_key.kind);
+
+  let api_key_builder = ApiKey::builder()
+    .with_product_id(product_id)
+    .with_name(api_key.name.trim())
+    .with_kind(api_key.kind)
+    .with_scopes(api_key.scopes.clone())
+    .with_key(&api_keys::hash(&key), &key_prefix)
+    .with_created_by(&token_auth.user_id);
+
+  let created = match database_connection.create_api_key(api_key_builder).await? {
+    created @ ApiKey { oid: Some(_), .. } => created.get_spec_safe_api_key(),
+    _ => {
+      return Err(ApiError::new(
+        Status::InternalServerError,
+        "Error. Unable to create API key.",
+      ))
+    }
+  };
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::ApiKey,
+    &created.oid,
+    AuditAction::Create,
+    format!(
+      "{} {} key '{}' ({})",
+      product_id,
+      api_key.kind.name(),
+      created.name,
+      key_prefix
+    ),
+  )
+  .await;
+
+  Ok(
+    status::Created::new(format!("/products/{}/api-keys/{}", product_id, created.oid))
+      .body(CasedJson(CreatedApiKey { key, api_key: created })),
+  )
+}
+
+/// Lists the API keys of a product, revoked ones included, their keys left out
+///
+/// Developers whose role grants `users:admin` only
+///
+/// # Parameters
+/// * **product_id** - Unique ID of the product
+#[openapi(tag = "API Keys")]
+#[get("/products/<product_id>/api-keys")]
+async fn get_api_keys(
+  product_id: &str,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<CasedJson<Vec<SpecSafeApiKey>>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage API keys").await?;
+
+  Ok(CasedJson(
+    database_connection
+      .get_api_keys(product_id)
+      .await?
+      .iter()
+      .map(|x| x.get_spec_safe_api_key())
+      .collect(),
+  ))
+}
+
+/// Revokes an API key, it's kept for the record but no longer authorizes anything
+///
+/// Developers whose role grants `users:admin` only. Returns 404 if the key isn't a key of the product, 409 if it's
+/// already revoked, and 204 otherwise
+///
+/// # Parameters
+/// * **product_id** - Unique ID of the product
+/// * **api_key_id** - Unique ID of the API key
+#[openapi(tag = "API Keys")]
+#[delete("/products/<product_id>/api-keys/<api_key_id>")]
+async fn revoke_api_key(
+  product_id: &str,
+  api_key_id: &str,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<status::NoContent, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage API keys").await?;
+
+  let api_key = match database_connection.get_api_key(api_key_id).await? {
+    Some(api_key) if api_key.product_id == product_id => api_key,
+    _ => {
+      return Err(ApiError::not_found(format!(
+        "Error. Unable to get API key '{}'.",
+        api_key_id
+      )))
+    }
+  };
+
+  if !api_key.is_active() || !database_connection.revoke_api_key(api_key_id).await? {
+    return Err(ApiError::new(
+      Status::Conflict,
+      format!("Error. API key '{}' is already revoked.", api_key_id),
+    ));
+  }
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::ApiKey,
+    api_key_id,
+    AuditAction::Revoke,
+    format!(
+      "{} {} key '{}' ({})",
+      product_id,
+      api_key.kind.name(),
+      api_key.name,
+      api_key.key_prefix
+    ),
+  )
+  .await;
+
+  Ok(status::NoContent)
+}
+
+/// Creates a service account, authorizing CI pipelines and automation by sending its token as
+/// `Authorization: Bearer <token>`
+///
+/// Developers whose role grants `users:admin` only. The token only gives access to the given products, for what its
+/// scopes allow: `read` exports them, `evaluate` checks their flags, and `write` changes them and their flags. It's
+/// only shown in this response, just its hash is stored. Returns 422 if the request is invalid, 404 if a product isn't
+/// found, and 201 with the token otherwise
+///
+/// # Parameters
+/// * **service_account** - Name of the account, the products it gives access to and its scopes
+#[openapi(tag = "Service Accounts")]
+#[post("/service-accounts", data = "<service_account>")]
+async fn create_service_account(
+  service_account: Valid<ServiceAccountRequest>,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<status::Created<CasedJson<CreatedServiceAccount>>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage service accounts").await?;
+
+  let service_account = service_account.into_inner();
+  let mut product_ids: Vec<String> = vec![];
+  for product_id in service_account.product_ids.iter().map(|x| x.trim()) {
+    if database_connection.get_product_by_id(product_id).await?.is_none() {
+      return Err(ApiError::not_found(format!(
+        "Error. Unable to get product: '{}'.",
+        product_id
+      )));
+    }
+    if !product_ids.iter().any(|x| x == product_id) {
+      product_ids.push(product_id.to_string());
+    }
+  }
+
+  let mut scopes: Vec<ServiceScope> = vec![];
+  for scope in service_account.scopes {
+    if !scopes.contains(&scope) {
+      scopes.push(scope);
+    }
+  }
+
+  let (token, token_prefix) = service_accounts::generate();
+
+  let service_account_builder = ServiceAccount::builder()
+    .with_name(service_account.name.trim())
+    .with_product_ids(product_ids)
+    .with_scopes(scopes)
+    .with_token(&api_keys::hash(&token), &token_prefix)
+    .with_created_by(&token_auth.user_id);
+
+  let created = match database_connection
+    .create_service_account(service_account_builder)
+    .await?
+  {
+    created @ ServiceAccount { oid: Some(_), .. } => created.get_spec_safe_service_account(),
+    _ => {
+      return Err(ApiError::new(
+        Status::InternalServerError,
+        "Error. Unable to create service account.",
+      ))
+    }
+  };
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::ServiceAccount,
+    &created.oid,
+    AuditAction::Create,
+    format!(
+      "'{}' ({}) products={} scopes={}",
+      created.name,
+      token_prefix,
+      created.product_ids.join(","),
+      created.scopes.iter().map(|x| x.name()).collect::<Vec<_>>().join(",")
+    ),
+  )
+  .await;
+
+  Ok(
+    status::Created::new(format!("/service-accounts/{}", created.oid)).body(CasedJson(CreatedServiceAccount {
+      token,
+      service_account: created,
+    })),
+  )
+}
+
+/// Lists the service accounts, revoked ones included, their tokens left out
+///
+/// Developers whose role grants `users:admin` only
+#[openapi(tag = "Service Accounts")]
+#[get("/service-accounts")]
+async fn get_service_accounts(
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<CasedJson<Vec<SpecSafeServiceAccount>>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage service accounts").await?;
+
+  Ok(CasedJson(
+    database_connection
+      .get_service_accounts()
+      .await?
+      .iter()
+      .map(|x| x.get_spec_safe_service_account())
+      .collect(),
+  ))
+}
+
+/// Revokes the token of a service account, the account is kept for the record but no longer authorizes anything
+///
+/// Developers whose role grants `users:admin` only. Returns 404 if the service account isn't found, 409 if it's already
+/// revoked, and 204 otherwise
+///
+/// # Parameters
+/// * **service_account_id** - Unique ID of the service account
+#[openapi(tag = "Service Accounts")]
+#[delete("/service-accounts/<service_account_id>")]
+async fn revoke_service_account(
+  service_account_id: &str,
+  database_connection: &State<ConnectionManager>,
+  token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<status::NoContent, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "manage service accounts").await?;
+
+  let service_account = match database_connection.get_service_account(service_account_id).await? {
+    Some(service_account) => service_account,
+    None => {
+      return Err(ApiError::not_found(format!(
+        "Error. Unable to get service account '{}'.",
+        service_account_id
+      )))
+    }
+  };
+
+  if !service_account.is_active() || !database_connection.revoke_service_account(service_account_id).await? {
+    return Err(ApiError::new(
+      Status::Conflict,
+      format!("Error. Service account '{}' is already revoked.", service_account_id),
+    ));
+  }
+
+  record_audit(
+    database_connection,
+    &token_auth.user_id,
+    EntityType::ServiceAccount,
+    service_account_id,
+    AuditAction::Revoke,
+    format!("'{}' ({})", service_account.name, service_account.token_prefix),
+  )
+  .await;
+
+  Ok(status::NoContent)
+}
+
+/// Gets a summary of everything the service manages, for administrators
+///
+/// Counts products, flags (by state, release type and product), users (by account type), logged in sessions, and the
+/// flag evaluations made over the last 24 hours. Only developers whose role grants `users:admin` can get the summary,
+/// returns 403 for anyone else
+#[openapi(tag = "Service")]
+#[get("/stats")]
+async fn stats(
+  database_connection: &State<ConnectionManager>,
+  evaluation_counter: &State<EvaluationCounter>,
+  token_auth: Scoped<scopes::UsersAdmin>,
+) -> Result<CasedJson<Stats>, ApiError> {
+  require_developer(database_connection, &token_auth.user_id, "get stats").await?;
+
+  // Write the evaluations counted in memory first, so they're part of the summary
+  evaluation_counter.flush(database_connection).await;
+
+  Ok(CasedJson(
+    database_connection
+      .aggregate_counts(Utc::now() - Duration::hours(24))
+      .await?,
+  ))
+}
+
+/// Gets build information about the running service
+///
+/// Includes the crate version, git SHA, build timestamp, and enabled cargo features so behavior changes can be
+/// correlated to deploys
+#[openapi(tag = "Service")]
+#[get("/info")]
+async fn info() -> CasedJson<BuildInfo> {
+  CasedJson(BuildInfo::current())
+}
+
+/// Runs the maintenance command given by `args`, returning its exit code, `None` if `args` don't give one
+///
+/// - `--revert-migration`: reverts the last data migration applied, e.g. before rolling back to an older version
+/// - `--export-backup <path>`: writes a backup of everything stored to the file at `path`
+/// - `--restore-backup <path>`: restores the backup in the file at `path`
+async fn run_command(args: &[String]) -> Option<i32> {
+  let command = args.first()?.as_str();
+  let path = args.get(1).map(Path::new);
+
+  let result = match (command, path) {
+    ("--revert-migration", _) => match ConnectionManager::new().revert_last_migration().await {
+      Ok(Some(id)) => Ok(format!("Reverted migration '{}'", id)),
+      Ok(None) => Ok("No migration to revert".to_string()),
+      Err(e) => Err(format!("Error reverting the last migration: {}", e)),
+    },
+    ("--export-backup", Some(path)) => backup::export(&ConnectionManager::new(), path)
+      .await
+      .map(|count| format!("Backed up {} documents to '{}'", count, path.display())),
+    ("--restore-backup", Some(path)) => backup::restore(&ConnectionManager::new(), path)
+      .await
+      .map(|count| format!("Restored {} documents from '{}'", count, path.display())),
+    ("--export-backup" | "--restore-backup", None) => Err(format!("Usage: {} <path>", command)),
+    _ => return None,
+  };
+
+  match result {
+    Ok(message) => {
+      println!("{}", message);
+      Some(0)
+    }
+    Err(message) => {
+      eprintln!("{}", message);
+      Some(1)
     }
+  }
 }
 
 #[launch]
-fn rocket() -> _ {
-  rocket::build()
-    .manage(ConnectionManager::new())
-    .manage(Arc::new(Mutex::new(AuthTokens::new()))) // Wrap in Arc<Mutex<T>> for thread safe mutability
-    .mount(
+async fn rocket() -> _ {
+  if secrets::load().await {
+    log!("Loaded secrets from the secrets backend");
+  }
+
+  // Maintenance commands are run instead of serving
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  if let Some(code) = run_command(&args).await {
+    std::process::exit(code);
+  }
+
+  let (routes, mut spec) = openapi_get_routes_spec![
+    check,
+    check_all,
+    check_batch,
+    check_users,
+    sdk_snapshot,
+    preview_flag,
+    bucket_preview,
+    hoist,
+    hoist_with_email,
+    hoist_bulk,
+    lower,
+    lower_with_email,
+    update_rules,
+    reorder_rules,
+    update_overrides,
+    update_flag,
+    patch_flag,
+    delete_flag,
+    delete_product,
+    update_product,
+    add_product_user,
+    remove_product_user,
+    update_user,
+    search,
+    archive_flag,
+    unarchive_flag,
+    update_permanent,
+    update_payload,
+    schedule_change,
+    get_scheduled_changes,
+    cancel_scheduled_change,
+    create_comment,
+    get_comments,
+    stale_flags,
+    get_settings,
+    update_product_settings,
+    update_flag_settings,
+    update_freeze_windows,
+    update_plugin,
+    remove_plugin,
+    get_product,
+    get_products,
+    get_flag,
+    get_flags,
+    get_flags_by_maintainer,
+    me,
+    change_password,
+    get_user,
+    get_users,
+    get_audit,
+    export_audit,
+    export_product,
+    import_product,
+    create_product,
+    create_flag,
+    create_flags,
+    create_user,
+    setup,
+    login,
+    login_with_path,
+    logout,
+    logout_all,
+    unlock_user,
+    update_user_status,
+    oidc_login,
+    oidc_callback,
+    saml_metadata,
+    saml_login,
+    saml_acs,
+    create_webhook,
+    get_webhooks,
+    get_webhook,
+    update_webhook,
+    delete_webhook,
+    create_api_key,
+    get_api_keys,
+    revoke_api_key,
+    create_service_account,
+    get_service_accounts,
+    revoke_service_account,
+    get_webhook_deliveries,
+    retry_webhook_delivery,
+    test_webhook,
+    stats,
+    info,
+  ];
+  let routes = request_id::traced(routes);
+  versioning::mark_deprecated(&mut spec);
+  spec.servers = vec![Server {
+    url: API_V1.to_string(),
+    ..Default::default()
+  }];
+
+  let settings = OpenApiSettings::new();
+  let legacy_routes = versioning::legacy_aliases(&routes);
+  let legacy_spec_routes = request_id::traced(versioning::legacy_aliases(&[rocket_okapi::get_openapi_route(
+    spec.clone(),
+    &settings,
+  )]));
+
+  // Shared with the webhook dispatcher, which subscribes to the changes written through it
+  let database_connection = ConnectionManager::new();
+  let docs_access = DocsAccess::from_env();
+
+  // Rocket only reads its secret key at launch, so a rotated key needs a restart
+  let mut figment = rocket::Config::figment();
+  if let Some(secret_key) = secrets::var("ROCKET_SECRET_KEY") {
+    figment = figment.merge(("secret_key", secret_key));
+  }
+
+  let rocket = rocket::custom(figment)
+    .attach(RequestIds)
+    .attach(LoadShedder::from_env())
+    .attach(RateLimiter::from_env())
+    .attach(AdminAllowlist::from_env())
+    .attach(CheckCors::from_env())
+    .attach(CsrfGuard)
+    .attach(LegacyPaths)
+    .attach(DocsGuard { access: docs_access })
+    .attach(AdHoc::on_liftoff("Startup Banner", |_| {
+      Box::pin(async move {
+        println!("{}", BuildInfo::current().banner());
+      })
+    }))
+    .attach(AdHoc::try_on_ignite("Migrations", |rocket| async move {
+      // Applied before serving, so requests never see documents older than the service expects. Failures stop the
+      // launch, the migrations left are applied on the next start
+      let database_connection = match rocket.state::<ConnectionManager>() {
+        Some(database_connection) => database_connection.clone(),
+        None => return Ok(rocket),
+      };
+
+      match database_connection.run_migrations().await {
+        Ok(applied) => {
+          if !applied.is_empty() {
+            log!("Applied migrations {}", applied.join(", "));
+          }
+          Ok(rocket)
+        }
+        Err(_) => Err(rocket),
+      }
+    }))
+    .attach(AdHoc::on_liftoff("Indexes", |rocket| {
+      Box::pin(async move {
+        // Created in the background so an unreachable database doesn't hold up the launch
+        if let Some(database_connection) = rocket.state::<ConnectionManager>().cloned() {
+          // Failures are logged, queries work without the indexes, only slower
+          rocket::tokio::spawn(async move {
+            let _ = database_connection.create_audit_indexes().await;
+            let _ = database_connection.create_search_indexes().await;
+            let _ = database_connection.create_api_key_indexes().await;
+            let _ = database_connection.create_service_account_indexes().await;
+            let _ = database_connection.create_session_indexes().await;
+            let _ = database_connection
+              .create_evaluation_count_indexes(evaluations::RETENTION)
+              .await;
+          });
+        }
+      })
+    }))
+    .attach(AdHoc::on_liftoff("Change Streams", |rocket| {
+      Box::pin(async move {
+        if let Some(database_connection) = rocket.state::<ConnectionManager>().cloned() {
+          rocket::tokio::spawn(async move { database_connection.watch_changes().await });
+        }
+      })
+    }))
+    .attach(AdHoc::on_liftoff("Live Flag Stream", |rocket| {
+      Box::pin(async move {
+        let address = live::address_from_env(rocket.config().address, rocket.config().port);
+        if let Some(database_connection) = rocket.state::<ConnectionManager>().cloned() {
+          rocket::tokio::spawn(live::serve(address, database_connection));
+        }
+      })
+    }))
+    .attach(AdHoc::on_liftoff("Webhooks", |rocket| {
+      Box::pin(async move {
+        if let Some(dispatcher) = rocket.state::<Dispatcher>().cloned() {
+          rocket::tokio::spawn(dispatcher.run());
+        }
+      })
+    }))
+    .attach(AdHoc::on_liftoff("Evaluation Counts", |rocket| {
+      Box::pin(async move {
+        let database_connection = rocket.state::<ConnectionManager>().cloned();
+        let evaluation_counter = rocket.state::<EvaluationCounter>().cloned();
+
+        if let (Some(database_connection), Some(evaluation_counter)) = (database_connection, evaluation_counter) {
+          rocket::tokio::spawn(evaluation_counter.run(database_connection));
+        }
+      })
+    }))
+    .attach(AdHoc::on_liftoff("Secrets Refresh", |_| {
+      Box::pin(async move {
+        if let Some(interval) = secrets::refresh_interval_from_env() {
+          rocket::tokio::spawn(secrets::refresh(interval));
+        }
+      })
+    }))
+    .attach(AdHoc::on_liftoff("Scheduled Changes", |rocket| {
+      Box::pin(async move {
+        if let Some(database_connection) = rocket.state::<ConnectionManager>().cloned() {
+          rocket::tokio::spawn(scheduler::run(database_connection, scheduler::interval_from_env()));
+        }
+      })
+    }))
+    .manage(Dispatcher::new(database_connection.clone()))
+    .manage(database_connection)
+    .manage(PluginHost::new())
+    .manage(EvaluationCounter::new())
+    .manage(LoginThrottle::from_env())
+    .manage(PasswordPolicy::from_env())
+    .register(
       "/",
-      openapi_get_routes![
-        index,
-        check,
-        hoist,
-        lower,
-        get_product,
-        get_products,
-        get_flag,
-        get_flags,
-        get_user,
-        get_users,
-        create_product,
-        create_flag,
-        create_user,
-        login,
-        logout,
+      catchers![
+        error::not_found,
+        error::unprocessable_entity,
+        error::internal_server_error,
+        error::default
       ],
     )
     .mount(
-      "/swagger-ui/",
-      swagger_ui::make_swagger_ui(&SwaggerUIConfig {
-        url: "../openapi.json".to_string(),
-        ..Default::default()
-      }),
+      "/",
+      request_id::traced(routes![
+        index,
+        load_shed,
+        rate_limited,
+        network_forbidden,
+        csrf_forbidden,
+        docs_unauthorized,
+        docs_forbidden,
+        healthz,
+        readyz
+      ]),
     )
+    .mount(API_V1, routes)
+    .mount("/", legacy_routes)
+    .mount("/api", versioning::legacy_aliases(&request_id::traced(routes![info])));
+
+  // The docs describe the full admin surface, so production deployments can leave them out
+  if docs_access.is_enabled() {
+    rocket
+      .mount(
+        API_V1,
+        request_id::traced(vec![rocket_okapi::get_openapi_route(spec, &settings)]),
+      )
+      .mount("/", legacy_spec_routes)
+      .mount(
+        "/swagger-ui/",
+        swagger_ui::make_swagger_ui(&SwaggerUIConfig {
+          url: "../api/v1/openapi.json".to_string(),
+          ..Default::default()
+        }),
+      )
+  } else {
+    rocket
+  }
 }
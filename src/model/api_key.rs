@@ -0,0 +1,195 @@
+//! Data model of API keys, authorizing SDKs to check the flags of a product without a user session
+
+use chrono::{DateTime, TimeZone, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::model::user::Permission;
+
+/// Where an API key is used, which decides what it can access
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyKind {
+  /// Kept on servers, can check flags and get the full definitions of flags from `/sdk` routes
+  Server,
+  /// Shipped in apps (e.g. mobile or web), can only check flags
+  Client,
+}
+
+impl ApiKeyKind {
+  /// Serialized name of the kind
+  pub fn name(&self) -> &'static str {
+    match self {
+      ApiKeyKind::Server => "server",
+      ApiKeyKind::Client => "client",
+    }
+  }
+
+  /// Prefix of keys of the kind, so leaked keys can be recognized
+  pub fn prefix(&self) -> &'static str {
+    match self {
+      ApiKeyKind::Server => "ffs_server_",
+      ApiKeyKind::Client => "ffs_client_",
+    }
+  }
+}
+
+/// Data Object for an API key
+///
+/// Only the SHA-256 hash of the key is stored, the key itself is shown once when it's created
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiKey {
+  /// Unique ID of the API key
+  #[serde(alias = "_id", rename(serialize = "_id"), skip_serializing_if = "Option::is_none")]
+  pub oid: Option<ObjectId>,
+  /// Unique ID of the product the key gives access to
+  pub product_id: String,
+  /// Human readable name of the key (e.g. `ios-app`)
+  pub name: String,
+  /// Where the key is used
+  pub kind: ApiKeyKind,
+  /// What the key can do with its product, `flags:read` for checking flags and `flags:write` for changing them
+  #[serde(default = "default_scopes")]
+  pub scopes: Vec<Permission>,
+  /// Hex SHA-256 hash of the key
+  pub key_hash: String,
+  /// Start of the key, so it can be told apart from other keys without being stored
+  pub key_prefix: String,
+  /// User ID of who created the key
+  pub created_by: String,
+  /// When the key was created
+  pub created_at: mongodb::bson::DateTime,
+  /// When the key was revoked, revoked keys are kept for the record but authorize nothing
+  #[serde(default)]
+  pub revoked_at: Option<mongodb::bson::DateTime>,
+}
+
+/// Scopes of keys created before keys had scopes, and of keys created without any
+pub fn default_scopes() -> Vec<Permission> {
+  vec![Permission::FlagsRead]
+}
+
+impl ApiKey {
+  /// Returns an `ApiKeyBuilder` to eventually construct an `ApiKey`
+  pub fn builder() -> ApiKeyBuilder {
+    ApiKeyBuilder::default()
+  }
+
+  /// If the key hasn't been revoked
+  pub fn is_active(&self) -> bool {
+    self.revoked_at.is_none()
+  }
+
+  pub fn get_spec_safe_api_key(&self) -> SpecSafeApiKey {
+    SpecSafeApiKey {
+      oid: match self.oid {
+        Some(oid) => oid.to_hex(),
+        None => ObjectId::default().to_hex(),
+      },
+      product_id: self.product_id.clone(),
+      name: self.name.clone(),
+      kind: self.kind,
+      scopes: self.scopes.clone(),
+      key_prefix: self.key_prefix.clone(),
+      created_by: self.created_by.clone(),
+      created_at: Utc.timestamp_millis(self.created_at.timestamp_millis()),
+      revoked_at: self.revoked_at.map(|x| Utc.timestamp_millis(x.timestamp_millis())),
+    }
+  }
+}
+
+/// API key as shown through the API, its hash left out
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SpecSafeApiKey {
+  /// Unique ID of the API key
+  pub oid: String,
+  /// Unique ID of the product the key gives access to
+  pub product_id: String,
+  /// Human readable name of the key
+  pub name: String,
+  /// Where the key is used
+  pub kind: ApiKeyKind,
+  /// What the key can do with its product
+  pub scopes: Vec<Permission>,
+  /// Start of the key, so it can be told apart from other keys
+  pub key_prefix: String,
+  /// User ID of who created the key
+  pub created_by: String,
+  /// When the key was created
+  pub created_at: DateTime<Utc>,
+  /// When the key was revoked, `None` if it's active
+  pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+pub struct ApiKeyBuilder {
+  /// Unique ID of the product the key gives access to
+  pub product_id: String,
+  /// Human readable name of the key
+  pub name: String,
+  /// Where the key is used
+  pub kind: Option<ApiKeyKind>,
+  /// What the key can do with its product
+  pub scopes: Vec<Permission>,
+  /// Hex SHA-256 hash of the key
+  pub key_hash: String,
+  /// Start of the key
+  pub key_prefix: String,
+  /// User ID of who created the key
+  pub created_by: String,
+}
+
+impl ApiKeyBuilder {
+  pub fn with_product_id(mut self, product_id: &str) -> ApiKeyBuilder {
+    self.product_id = product_id.to_string();
+    self
+  }
+
+  pub fn with_name(mut self, name: &str) -> ApiKeyBuilder {
+    self.name = name.to_string();
+    self
+  }
+
+  pub fn with_kind(mut self, kind: ApiKeyKind) -> ApiKeyBuilder {
+    self.kind = Some(kind);
+    self
+  }
+
+  pub fn with_scopes(mut self, scopes: Vec<Permission>) -> ApiKeyBuilder {
+    self.scopes = scopes;
+    self
+  }
+
+  /// Sets the hash and prefix of the key, the key itself isn't kept
+  pub fn with_key(mut self, key_hash: &str, key_prefix: &str) -> ApiKeyBuilder {
+    self.key_hash = key_hash.to_string();
+    self.key_prefix = key_prefix.to_string();
+    self
+  }
+
+  pub fn with_created_by(mut self, created_by: &str) -> ApiKeyBuilder {
+    self.created_by = created_by.to_string();
+    self
+  }
+
+  /// Builds the API key, timestamped with the current time. Keys are server keys unless a kind is given, and can only
+  /// read unless scopes are given
+  pub fn build(self) -> ApiKey {
+    ApiKey {
+      oid: None,
+      product_id: self.product_id,
+      name: self.name,
+      kind: self.kind.unwrap_or(ApiKeyKind::Server),
+      scopes: match self.scopes.is_empty() {
+        true => default_scopes(),
+        false => self.scopes,
+      },
+      key_hash: self.key_hash,
+      key_prefix: self.key_prefix,
+      created_by: self.created_by,
+      created_at: mongodb::bson::DateTime::now(),
+      revoked_at: None,
+    }
+  }
+}
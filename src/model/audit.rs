@@ -0,0 +1,299 @@
+//! Data model of the audit log
+
+use chrono::{DateTime, TimeZone, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+/// Type of entity an audit entry is about
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EntityType {
+  Product,
+  Flag,
+  User,
+  Webhook,
+  ApiKey,
+  ServiceAccount,
+}
+
+impl EntityType {
+  /// Parses an entity type from its serialized name (e.g. `flag`)
+  pub fn from_name(name: &str) -> Option<EntityType> {
+    match name.trim().to_lowercase().as_str() {
+      "product" => Some(EntityType::Product),
+      "flag" => Some(EntityType::Flag),
+      "user" => Some(EntityType::User),
+      "webhook" => Some(EntityType::Webhook),
+      "api_key" => Some(EntityType::ApiKey),
+      "service_account" => Some(EntityType::ServiceAccount),
+      _ => None,
+    }
+  }
+
+  /// Serialized name of the entity type
+  pub fn name(&self) -> &'static str {
+    match self {
+      EntityType::Product => "product",
+      EntityType::Flag => "flag",
+      EntityType::User => "user",
+      EntityType::Webhook => "webhook",
+      EntityType::ApiKey => "api_key",
+      EntityType::ServiceAccount => "service_account",
+    }
+  }
+}
+
+/// Action recorded by an audit entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+  Create,
+  Update,
+  Delete,
+  Hoist,
+  Lower,
+  UpdateRules,
+  UpdateSettings,
+  UpdatePermanent,
+  UpdatePayload,
+  ScheduleChange,
+  CancelScheduledChange,
+  ApplyScheduledChange,
+  Archive,
+  Unarchive,
+  UpdateFreezeWindows,
+  UpdateOverrides,
+  UpdatePlugin,
+  RemovePlugin,
+  AddUser,
+  RemoveUser,
+  ChangePassword,
+  Revoke,
+  Unlock,
+  UpdateStatus,
+}
+
+impl AuditAction {
+  /// Parses an action from its serialized name (e.g. `update_rules`)
+  pub fn from_name(name: &str) -> Option<AuditAction> {
+    match name.trim().to_lowercase().as_str() {
+      "create" => Some(AuditAction::Create),
+      "update" => Some(AuditAction::Update),
+      "delete" => Some(AuditAction::Delete),
+      "hoist" => Some(AuditAction::Hoist),
+      "lower" => Some(AuditAction::Lower),
+      "update_rules" => Some(AuditAction::UpdateRules),
+      "update_settings" => Some(AuditAction::UpdateSettings),
+      "update_permanent" => Some(AuditAction::UpdatePermanent),
+      "update_payload" => Some(AuditAction::UpdatePayload),
+      "schedule_change" => Some(AuditAction::ScheduleChange),
+      "cancel_scheduled_change" => Some(AuditAction::CancelScheduledChange),
+      "apply_scheduled_change" => Some(AuditAction::ApplyScheduledChange),
+      "archive" => Some(AuditAction::Archive),
+      "unarchive" => Some(AuditAction::Unarchive),
+      "update_freeze_windows" => Some(AuditAction::UpdateFreezeWindows),
+      "update_overrides" => Some(AuditAction::UpdateOverrides),
+      "update_plugin" => Some(AuditAction::UpdatePlugin),
+      "remove_plugin" => Some(AuditAction::RemovePlugin),
+      "add_user" => Some(AuditAction::AddUser),
+      "remove_user" => Some(AuditAction::RemoveUser),
+      "change_password" => Some(AuditAction::ChangePassword),
+      "revoke" => Some(AuditAction::Revoke),
+      "unlock" => Some(AuditAction::Unlock),
+      "update_status" => Some(AuditAction::UpdateStatus),
+      _ => None,
+    }
+  }
+
+  /// Serialized name of the action
+  pub fn name(&self) -> &'static str {
+    match self {
+      AuditAction::Create => "create",
+      AuditAction::Update => "update",
+      AuditAction::Delete => "delete",
+      AuditAction::Hoist => "hoist",
+      AuditAction::Lower => "lower",
+      AuditAction::UpdateRules => "update_rules",
+      AuditAction::UpdateSettings => "update_settings",
+      AuditAction::UpdatePermanent => "update_permanent",
+      AuditAction::UpdatePayload => "update_payload",
+      AuditAction::ScheduleChange => "schedule_change",
+      AuditAction::CancelScheduledChange => "cancel_scheduled_change",
+      AuditAction::ApplyScheduledChange => "apply_scheduled_change",
+      AuditAction::Archive => "archive",
+      AuditAction::Unarchive => "unarchive",
+      AuditAction::UpdateFreezeWindows => "update_freeze_windows",
+      AuditAction::UpdateOverrides => "update_overrides",
+      AuditAction::UpdatePlugin => "update_plugin",
+      AuditAction::RemovePlugin => "remove_plugin",
+      AuditAction::AddUser => "add_user",
+      AuditAction::RemoveUser => "remove_user",
+      AuditAction::ChangePassword => "change_password",
+      AuditAction::Revoke => "revoke",
+      AuditAction::Unlock => "unlock",
+      AuditAction::UpdateStatus => "update_status",
+    }
+  }
+}
+
+/// Data Object for an entry of the audit log
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+  /// Unique ID of the audit entry
+  #[serde(alias = "_id", rename(serialize = "_id"), skip_serializing_if = "Option::is_none")]
+  pub oid: Option<ObjectId>,
+  /// User ID (or email, for routes without a session) of who made the change
+  pub actor: String,
+  /// Type of the changed entity
+  pub entity_type: EntityType,
+  /// Unique ID of the changed entity
+  pub entity_id: String,
+  /// What was done to the entity
+  pub action: AuditAction,
+  /// Human readable details of the change
+  #[serde(default)]
+  pub details: String,
+  /// When the change was made, stored as a BSON date so it can be range queried
+  pub timestamp: mongodb::bson::DateTime,
+}
+
+impl AuditEntry {
+  /// Returns an `AuditEntryBuilder` to eventually construct an `AuditEntry`
+  pub fn builder() -> AuditEntryBuilder {
+    AuditEntryBuilder::new()
+  }
+
+  /// When the change was made
+  pub fn timestamp(&self) -> DateTime<Utc> {
+    Utc.timestamp_millis(self.timestamp.timestamp_millis())
+  }
+
+  pub fn get_spec_safe_audit_entry(&self) -> SpecSafeAuditEntry {
+    SpecSafeAuditEntry {
+      oid: match self.oid {
+        Some(oid) => oid.to_hex(),
+        None => ObjectId::default().to_hex(),
+      },
+      actor: self.actor.clone(),
+      entity_type: self.entity_type,
+      entity_id: self.entity_id.clone(),
+      action: self.action,
+      details: self.details.clone(),
+      timestamp: self.timestamp(),
+    }
+  }
+
+  /// Header of the CSV export, matching the columns of `AuditEntry::csv_row`
+  pub fn csv_header() -> &'static str {
+    "id,timestamp,actor,entity_type,entity_id,action,details\n"
+  }
+
+  /// Returns the entry as a line of CSV
+  pub fn csv_row(&self) -> String {
+    let oid = self.oid.map(|x| x.to_hex()).unwrap_or_default();
+
+    format!(
+      "{},{},{},{},{},{},{}\n",
+      oid,
+      self.timestamp().to_rfc3339(),
+      csv_field(&self.actor),
+      self.entity_type.name(),
+      csv_field(&self.entity_id),
+      self.action.name(),
+      csv_field(&self.details),
+    )
+  }
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+  if value.contains([',', '"', '\n', '\r']) {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  } else {
+    value.to_string()
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SpecSafeAuditEntry {
+  /// Unique ID of the audit entry
+  pub oid: String,
+  /// User ID (or email, for routes without a session) of who made the change
+  pub actor: String,
+  /// Type of the changed entity
+  pub entity_type: EntityType,
+  /// Unique ID of the changed entity
+  pub entity_id: String,
+  /// What was done to the entity
+  pub action: AuditAction,
+  /// Human readable details of the change
+  pub details: String,
+  /// When the change was made
+  pub timestamp: DateTime<Utc>,
+}
+
+pub struct AuditEntryBuilder {
+  /// User ID (or email) of who made the change
+  pub actor: String,
+  /// Type of the changed entity
+  pub entity_type: EntityType,
+  /// Unique ID of the changed entity
+  pub entity_id: String,
+  /// What was done to the entity
+  pub action: AuditAction,
+  /// Human readable details of the change
+  pub details: String,
+}
+
+impl Default for AuditEntryBuilder {
+  fn default() -> AuditEntryBuilder {
+    AuditEntryBuilder {
+      actor: String::new(),
+      entity_type: EntityType::Flag,
+      entity_id: String::new(),
+      action: AuditAction::Create,
+      details: String::new(),
+    }
+  }
+}
+
+impl AuditEntryBuilder {
+  fn new() -> AuditEntryBuilder {
+    AuditEntryBuilder::default()
+  }
+
+  pub fn with_actor(mut self, actor: &str) -> AuditEntryBuilder {
+    self.actor = actor.to_string();
+    self
+  }
+
+  pub fn with_entity(mut self, entity_type: EntityType, entity_id: &str) -> AuditEntryBuilder {
+    self.entity_type = entity_type;
+    self.entity_id = entity_id.to_string();
+    self
+  }
+
+  pub fn with_action(mut self, action: AuditAction) -> AuditEntryBuilder {
+    self.action = action;
+    self
+  }
+
+  pub fn with_details(mut self, details: &str) -> AuditEntryBuilder {
+    self.details = details.to_string();
+    self
+  }
+
+  /// Builds the entry, timestamped with the current time
+  pub fn build(self) -> AuditEntry {
+    AuditEntry {
+      oid: None,
+      actor: self.actor,
+      entity_type: self.entity_type,
+      entity_id: self.entity_id,
+      action: self.action,
+      details: self.details,
+      timestamp: mongodb::bson::DateTime::now(),
+    }
+  }
+}
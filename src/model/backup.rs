@@ -0,0 +1,65 @@
+//! Data model of the backups of everything the service stores, see `--export-backup` and `--restore-backup`
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::model::api_key::ApiKey;
+use crate::model::audit::AuditEntry;
+use crate::model::comment::Comment;
+use crate::model::flag::FeatureFlag;
+use crate::model::plugin::Plugin;
+use crate::model::product::Product;
+use crate::model::service_account::ServiceAccount;
+use crate::model::user::User;
+use crate::model::webhook::{Webhook, WebhookDelivery};
+
+/// Version of the backup format, bumped on changes older services couldn't restore
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Every stored document, with their IDs so they're restored as they were
+///
+/// Logged in sessions and hourly evaluation counts expire, and are left out
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+  /// Version of the format of the backup, see `BACKUP_VERSION`
+  pub version: u32,
+  /// When the backup was made
+  pub created_at: DateTime<Utc>,
+  #[serde(default)]
+  pub products: Vec<Product>,
+  #[serde(default)]
+  pub flags: Vec<FeatureFlag>,
+  /// Users, with their password hash
+  #[serde(default)]
+  pub users: Vec<User>,
+  #[serde(default)]
+  pub comments: Vec<Comment>,
+  #[serde(default)]
+  pub plugins: Vec<Plugin>,
+  #[serde(default)]
+  pub webhooks: Vec<Webhook>,
+  #[serde(default)]
+  pub deliveries: Vec<WebhookDelivery>,
+  #[serde(default)]
+  pub api_keys: Vec<ApiKey>,
+  #[serde(default)]
+  pub service_accounts: Vec<ServiceAccount>,
+  #[serde(default)]
+  pub audit: Vec<AuditEntry>,
+}
+
+impl Backup {
+  /// Number of documents in the backup
+  pub fn document_count(&self) -> usize {
+    self.products.len()
+      + self.flags.len()
+      + self.users.len()
+      + self.comments.len()
+      + self.plugins.len()
+      + self.webhooks.len()
+      + self.deliveries.len()
+      + self.api_keys.len()
+      + self.service_accounts.len()
+      + self.audit.len()
+  }
+}
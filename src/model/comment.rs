@@ -0,0 +1,122 @@
+//! Data model of comments on Feature Flags
+
+use chrono::{DateTime, TimeZone, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::model::audit::AuditAction;
+
+/// Longest accepted comment, in characters
+pub const MAX_COMMENT_LENGTH: usize = 4096;
+
+/// Data Object for a comment on a Feature Flag
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Comment {
+  /// Unique ID of the comment
+  #[serde(alias = "_id", rename(serialize = "_id"), skip_serializing_if = "Option::is_none")]
+  pub oid: Option<ObjectId>,
+  /// Unique ID of the flag the comment is on
+  pub flag_id: String,
+  /// User ID (or email, for routes without a session) of who wrote the comment
+  pub author: String,
+  /// Text of the comment
+  pub body: String,
+  /// Change the comment justifies (e.g. `hoist`), if it was left with one
+  #[serde(default)]
+  pub action: Option<AuditAction>,
+  /// When the comment was written, stored as a BSON date so comments can be sorted
+  pub created_at: mongodb::bson::DateTime,
+}
+
+impl Comment {
+  /// Returns a `CommentBuilder` to eventually construct a `Comment`
+  pub fn builder() -> CommentBuilder {
+    CommentBuilder::new()
+  }
+
+  /// When the comment was written
+  pub fn created_at(&self) -> DateTime<Utc> {
+    Utc.timestamp_millis(self.created_at.timestamp_millis())
+  }
+
+  pub fn get_spec_safe_comment(&self) -> SpecSafeComment {
+    SpecSafeComment {
+      oid: match self.oid {
+        Some(oid) => oid.to_hex(),
+        None => ObjectId::default().to_hex(),
+      },
+      flag_id: self.flag_id.clone(),
+      author: self.author.clone(),
+      body: self.body.clone(),
+      action: self.action,
+      created_at: self.created_at(),
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SpecSafeComment {
+  /// Unique ID of the comment
+  pub oid: String,
+  /// Unique ID of the flag the comment is on
+  pub flag_id: String,
+  /// User ID (or email, for routes without a session) of who wrote the comment
+  pub author: String,
+  /// Text of the comment
+  pub body: String,
+  /// Change the comment justifies (e.g. `hoist`), if it was left with one
+  pub action: Option<AuditAction>,
+  /// When the comment was written
+  pub created_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct CommentBuilder {
+  /// Unique ID of the flag the comment is on
+  pub flag_id: String,
+  /// User ID (or email) of who wrote the comment
+  pub author: String,
+  /// Text of the comment
+  pub body: String,
+  /// Change the comment justifies, if any
+  pub action: Option<AuditAction>,
+}
+
+impl CommentBuilder {
+  fn new() -> CommentBuilder {
+    CommentBuilder::default()
+  }
+
+  pub fn with_flag_id(mut self, flag_id: &str) -> CommentBuilder {
+    self.flag_id = flag_id.to_string();
+    self
+  }
+
+  pub fn with_author(mut self, author: &str) -> CommentBuilder {
+    self.author = author.to_string();
+    self
+  }
+
+  pub fn with_body(mut self, body: &str) -> CommentBuilder {
+    self.body = body.to_string();
+    self
+  }
+
+  pub fn with_action(mut self, action: AuditAction) -> CommentBuilder {
+    self.action = Some(action);
+    self
+  }
+
+  /// Builds the comment, timestamped with the current time
+  pub fn build(self) -> Comment {
+    Comment {
+      oid: None,
+      flag_id: self.flag_id,
+      author: self.author,
+      body: self.body,
+      action: self.action,
+      created_at: mongodb::bson::DateTime::now(),
+    }
+  }
+}
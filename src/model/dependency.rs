@@ -0,0 +1,205 @@
+//! Prerequisite graph resolution for applying flag changes in bulk
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::model::flag::FeatureFlag;
+
+/// Report of why a set of flags can't be enabled together
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DependencyReport {
+  /// Requested flags that don't exist in the product
+  pub missing: Vec<String>,
+  /// Prerequisites that are neither enabled nor part of the request
+  pub unsatisfied: Vec<UnsatisfiedPrerequisite>,
+  /// Requested flags that depend on each other in a cycle
+  pub cycle: Vec<String>,
+  /// Flags that failed to update while applying the change, if any
+  pub failed: Vec<String>,
+}
+
+impl DependencyReport {
+  /// Returns `true` if nothing in the report prevents the change
+  pub fn is_empty(&self) -> bool {
+    self.missing.is_empty() && self.unsatisfied.is_empty() && self.cycle.is_empty() && self.failed.is_empty()
+  }
+}
+
+/// A flag whose prerequisite isn't satisfied
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UnsatisfiedPrerequisite {
+  /// Name of the flag being enabled
+  pub flag: String,
+  /// Name of the prerequisite that is off (or doesn't exist)
+  pub prerequisite: String,
+}
+
+/// Computes the order to enable the `requested` flags in so every flag is enabled after its prerequisites
+///
+/// `flags` should contain every flag of the product. Prerequisites outside of `requested` must already be enabled.
+/// Returns a `DependencyReport` if any flag is missing, any prerequisite is unsatisfied, or the requested flags form a
+/// cycle
+///
+/// # Parameters
+/// * **flags**     - All flags of the product
+/// * **requested** - Names of the flags to enable
+pub fn enable_order(flags: &[FeatureFlag], requested: &[String]) -> Result<Vec<String>, DependencyReport> {
+  let by_name: HashMap<&str, &FeatureFlag> = flags.iter().map(|x| (x.name.as_str(), x)).collect();
+  let requested_set: HashSet<&str> = requested.iter().map(|x| x.as_str()).collect();
+  let mut report = DependencyReport::default();
+
+  // Number of requested prerequisites still to be enabled before each requested flag
+  let mut pending: HashMap<&str, usize> = HashMap::new();
+  // Requested flags waiting on each requested prerequisite
+  let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+
+  for name in &requested_set {
+    let flag = match by_name.get(name) {
+      Some(flag) => flag,
+      None => {
+        report.missing.push(name.to_string());
+        continue;
+      }
+    };
+
+    let mut count = 0;
+    for prerequisite in &flag.prerequisites {
+      if requested_set.contains(prerequisite.as_str()) {
+        count += 1;
+        dependents.entry(prerequisite.as_str()).or_default().push(name);
+        continue;
+      }
+
+      match by_name.get(prerequisite.as_str()) {
+        Some(prerequisite_flag) if prerequisite_flag.enabled => (),
+        _ => report.unsatisfied.push(UnsatisfiedPrerequisite {
+          flag: name.to_string(),
+          prerequisite: prerequisite.clone(),
+        }),
+      }
+    }
+    pending.insert(name, count);
+  }
+
+  // Kahn's algorithm, sorting each ready set for a deterministic order
+  let mut ready: Vec<&str> = pending.iter().filter(|(_, x)| **x == 0).map(|(x, _)| *x).collect();
+  ready.sort_unstable();
+  let mut queue: VecDeque<&str> = ready.into_iter().collect();
+  let mut order: Vec<String> = vec![];
+
+  while let Some(name) = queue.pop_front() {
+    order.push(name.to_string());
+
+    let mut unblocked: Vec<&str> = vec![];
+    for dependent in dependents.get(name).cloned().unwrap_or_default() {
+      if let Some(count) = pending.get_mut(dependent) {
+        *count -= 1;
+        if *count == 0 {
+          unblocked.push(dependent);
+        }
+      }
+    }
+    unblocked.sort_unstable();
+    queue.extend(unblocked);
+  }
+
+  if order.len() < pending.len() {
+    // Flags left are in a cycle or wait on one, drop the ones no flag left waits on until only cycles remain
+    let mut left: HashSet<&str> = pending
+      .keys()
+      .copied()
+      .filter(|x| !order.iter().any(|y| y == x))
+      .collect();
+    loop {
+      let waited_on = |name: &&str| {
+        dependents
+          .get(name)
+          .is_some_and(|x| x.iter().any(|dependent| left.contains(dependent)))
+      };
+      let blocked: Vec<&str> = left.iter().copied().filter(|x| !waited_on(x)).collect();
+      if blocked.is_empty() {
+        break;
+      }
+      for name in blocked {
+        left.remove(name);
+      }
+    }
+
+    report.cycle = left.into_iter().map(|x| x.to_string()).collect();
+    report.cycle.sort();
+  }
+
+  if report.is_empty() {
+    Ok(order)
+  } else {
+    report.missing.sort();
+    Err(report)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn flag(name: &str, enabled: bool, prerequisites: &[&str]) -> FeatureFlag {
+    FeatureFlag::builder()
+      .with_name(name)
+      .with_enabled(enabled)
+      .with_prerequisites(prerequisites.iter().map(|x| x.to_string()).collect())
+      .build()
+  }
+
+  fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|x| x.to_string()).collect()
+  }
+
+  #[test]
+  fn flags_are_enabled_after_their_prerequisites() {
+    let flags = vec![
+      flag("checkout", false, &["payments", "cart"]),
+      flag("payments", false, &["accounts"]),
+      flag("cart", false, &[]),
+      flag("accounts", true, &[]),
+    ];
+
+    let order = enable_order(&flags, &names(&["checkout", "payments", "cart"])).unwrap();
+
+    assert_eq!(order, names(&["cart", "payments", "checkout"]));
+  }
+
+  #[test]
+  fn missing_flags_and_unsatisfied_prerequisites_are_reported() {
+    let flags = vec![
+      flag("checkout", false, &["payments", "gone"]),
+      flag("payments", false, &[]),
+    ];
+
+    let report = enable_order(&flags, &names(&["checkout", "unknown"])).unwrap_err();
+
+    assert_eq!(report.missing, names(&["unknown"]));
+    let mut unsatisfied: Vec<&str> = report.unsatisfied.iter().map(|x| x.prerequisite.as_str()).collect();
+    unsatisfied.sort_unstable();
+    assert_eq!(unsatisfied, vec!["gone", "payments"]);
+    assert!(report.unsatisfied.iter().all(|x| x.flag == "checkout"));
+    assert!(report.cycle.is_empty());
+  }
+
+  #[test]
+  fn cycles_are_reported_without_the_flags_waiting_on_them() {
+    let flags = vec![
+      flag("a", false, &["b"]),
+      flag("b", false, &["c"]),
+      flag("c", false, &["a"]),
+      flag("after", false, &["a"]),
+      flag("self", false, &["self"]),
+      flag("free", false, &[]),
+    ];
+
+    let report = enable_order(&flags, &names(&["a", "b", "c", "after", "self", "free"])).unwrap_err();
+
+    assert_eq!(report.cycle, names(&["a", "b", "c", "self"]));
+    assert!(report.missing.is_empty() && report.unsatisfied.is_empty());
+  }
+}
@@ -1,11 +1,19 @@
 //! Data model structures of the Feature Flag
 
+use chrono::{DateTime, Duration, Utc};
+use evaluation::{Evaluable, EvaluationContext, EvaluationResult, FlagDefinition, Rule, Verdict};
 use mongodb::bson::oid::ObjectId;
+use rocket::serde::json::Value;
 use rocket_okapi::okapi::schemars::{self, JsonSchema};
 use serde::{Deserialize, Serialize};
 
+use crate::model::schedule::{ChangedField, FlagChange, ScheduledChange};
+use crate::model::settings::SettingsOverrides;
+
+pub use evaluation::{BucketBy, ReleaseType};
+
 /// Data Object for a Feature Flag
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FeatureFlag {
   /// Unique ID of the feature flag
   #[serde(alias = "_id", rename(serialize = "_id"), skip_serializing_if = "Option::is_none")]
@@ -20,8 +28,57 @@ pub struct FeatureFlag {
   pub client_toggle: bool,
   /// List of all users who've disabled the feature
   pub disabled_for: Vec<String>,
+  /// Users (e.g. QA accounts) always evaluating as enabled, overriding targeting rules and the release type
+  #[serde(default)]
+  pub always_include: Vec<String>,
+  /// Users always evaluating as disabled, overriding targeting rules, the release type, and `always_include`
+  #[serde(default)]
+  pub always_exclude: Vec<String>,
   /// Type of release and relevant data
   pub release_type: ReleaseType,
+  /// Tags used to group flags (e.g. by squad or initiative)
+  #[serde(default)]
+  pub tags: Vec<String>,
+  /// Human readable description of what the flag gates
+  #[serde(default)]
+  pub description: String,
+  /// Owner of the flag (team or person responsible for it)
+  #[serde(default)]
+  pub owner: String,
+  /// Unique ID of the user responsible for maintaining (and eventually retiring) the flag
+  #[serde(default)]
+  pub maintainer_id: Option<String>,
+  /// When the flag was created
+  #[serde(default)]
+  pub created_at: Option<DateTime<Utc>>,
+  /// When the flag was last changed
+  #[serde(default)]
+  pub updated_at: Option<DateTime<Utc>>,
+  /// Names of flags (in the same product) that must be enabled before this flag
+  #[serde(default)]
+  pub prerequisites: Vec<String>,
+  /// Targeting rules, checked by priority before the release type
+  #[serde(default)]
+  pub rules: Vec<Rule>,
+  /// Settings overriding the product and environment settings for the flag
+  #[serde(default)]
+  pub settings: SettingsOverrides,
+  /// If the flag is archived (hidden from default listings and always evaluating as disabled)
+  #[serde(default)]
+  pub archived: bool,
+  /// If the flag is meant to stay (e.g. an operational kill switch) rather than be removed once rolled out
+  #[serde(default)]
+  pub permanent: bool,
+  /// JSON configuration (e.g. a button color or limits) returned by checks alongside an enabled status
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub payload: Option<Value>,
+  /// Changes waiting to be applied to the flag
+  #[serde(default)]
+  pub scheduled_changes: Vec<ScheduledChange>,
+  /// Salt of the percentage release buckets, generated when the flag is created so buckets survive renames and differ
+  /// between flags of the same name in other products. Flags created before salts were stored bucket on their name
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub bucket_salt: Option<String>,
 }
 
 impl Default for FeatureFlag {
@@ -33,7 +90,23 @@ impl Default for FeatureFlag {
       enabled: false,
       client_toggle: false,
       disabled_for: vec![],
+      always_include: vec![],
+      always_exclude: vec![],
       release_type: ReleaseType::Global,
+      tags: vec![],
+      description: String::new(),
+      owner: String::new(),
+      maintainer_id: None,
+      created_at: None,
+      updated_at: None,
+      prerequisites: vec![],
+      rules: vec![],
+      settings: SettingsOverrides::default(),
+      archived: false,
+      permanent: false,
+      payload: None,
+      scheduled_changes: vec![],
+      bucket_salt: None,
     }
   }
 }
@@ -53,58 +126,108 @@ impl FeatureFlag {
 
   pub fn lower(&mut self, user_id: Option<String>) {
     match user_id {
+      Some(user_id) if self.disabled_for.contains(&user_id) => (),
       Some(user_id) => self.disabled_for.push(user_id),
       None => self.enabled = false,
     }
   }
 
-  /// Evaluates the flag returning true if it is enabled and false otherwise
+  /// Evaluates the flag, returning if it is enabled and why
   ///
-  /// Can optionally be provided a user to evaluate with
+  /// Users on the always exclude or always include lists are decided first, then targeting rules are checked by
+  /// priority before the release type, the first matching rule decides the result
   ///
   /// # Parameters
-  /// * **user_id** - *(optional)* User used to evaluate the flag with
-  pub fn evaluate(&self, user_id: Option<&str>) -> bool {
-    if !self.enabled {
-      return false;
+  /// * **context** - Context (optional user and attributes) used to evaluate the flag with
+  pub fn evaluate(&self, context: &EvaluationContext) -> EvaluationResult {
+    evaluation::evaluate(self, context)
+  }
+
+  /// Evaluates the flag like `evaluate`, letting custom logic (e.g. the product's plugin) decide the result before
+  /// targeting rules are checked
+  ///
+  /// # Parameters
+  /// * **context** - Context (optional user and attributes) used to evaluate the flag with
+  /// * **verdict** - Custom logic deciding the result, it can't enable a disabled or archived flag
+  pub fn evaluate_with(&self, context: &EvaluationContext, verdict: &dyn Verdict) -> EvaluationResult {
+    evaluation::evaluate_with(self, context, Some(verdict))
+  }
+
+  /// Returns the standalone definition of the flag, everything needed to evaluate it outside of the service
+  pub fn definition(&self) -> FlagDefinition {
+    FlagDefinition {
+      name: self.name.clone(),
+      enabled: self.enabled,
+      archived: self.archived,
+      disabled_for: self.disabled_for.clone(),
+      always_include: self.always_include.clone(),
+      always_exclude: self.always_exclude.clone(),
+      release_type: self.release_type.clone(),
+      rules: self.rules.clone(),
+      bucket_salt: Some(self.bucket_salt().to_string()),
     }
+  }
 
-    match &self.release_type {
-      ReleaseType::Global => match user_id {
-        Some(user_id) => {
-          if self.disabled_for.contains(&user_id.to_string()) {
-            return false;
-          } else {
-            return self.enabled;
-          }
-        }
-        None => return self.enabled,
-      },
-      ReleaseType::Limited(allowlist) => match user_id {
-        Some(user_id) => {
-          if self.disabled_for.contains(&user_id.to_string()) {
-            return false;
-          }
-          if allowlist.contains(&user_id.to_string()) {
-            return self.enabled;
-          }
-        }
-        None => return false,
-      },
-      ReleaseType::Percentage(_, allowlist) => match user_id {
-        Some(user_id) => {
-          if self.disabled_for.contains(&user_id.to_string()) {
-            return false;
-          }
-          if allowlist.contains(&user_id.to_string()) {
-            return self.enabled;
-          }
+  /// Returns the field a change sets on the flag, and the value it sets it to
+  pub fn resolve_change(&self, change: &FlagChange) -> ChangedField {
+    match change {
+      FlagChange::Enable => ChangedField::Enabled(true),
+      FlagChange::Disable => ChangedField::Enabled(false),
+      FlagChange::SetPercentage(percentage) => ChangedField::ReleaseType(match &self.release_type {
+        ReleaseType::Global => ReleaseType::Percentage(*percentage, vec![], BucketBy::User),
+        ReleaseType::Limited(allowlist) => ReleaseType::Percentage(*percentage, allowlist.clone(), BucketBy::User),
+        ReleaseType::Percentage(_, allowlist, bucket_by) => {
+          ReleaseType::Percentage(*percentage, allowlist.clone(), bucket_by.clone())
         }
-        None => return false,
-      },
+      }),
+      FlagChange::SetReleaseType(release_type) => ChangedField::ReleaseType(release_type.clone()),
     }
+  }
 
-    false
+  /// Sets a field resolved by `resolve_change`
+  pub fn set_field(&mut self, field: &ChangedField) {
+    match field {
+      ChangedField::Enabled(enabled) => self.enabled = *enabled,
+      ChangedField::ReleaseType(release_type) => self.release_type = release_type.clone(),
+    }
+  }
+
+  /// Removes the scheduled changes due at `now`, returning them in the order they were due
+  pub fn take_due_changes(&mut self, now: DateTime<Utc>) -> Vec<ScheduledChange> {
+    let (mut due, pending): (Vec<ScheduledChange>, Vec<ScheduledChange>) =
+      self.scheduled_changes.drain(..).partition(|x| x.at() <= now);
+
+    self.scheduled_changes = pending;
+    due.sort_by_key(|x| x.at);
+    due
+  }
+
+  /// Returns true if any of the flag's rules target account types, checks then need the account type of their user
+  pub fn targets_account_type(&self) -> bool {
+    self.rules.iter().any(|x| x.condition.uses_account_type())
+  }
+
+  /// Returns true if the flag is on for everyone: enabled, globally or at 100%, with no rule or user excluded
+  pub fn is_fully_rolled_out(&self) -> bool {
+    let released = match &self.release_type {
+      ReleaseType::Global => true,
+      ReleaseType::Limited(_) => false,
+      ReleaseType::Percentage(percentage, _, _) => *percentage >= 100.0,
+    };
+
+    self.enabled && !self.archived && released && self.always_exclude.is_empty() && self.rules.iter().all(|x| x.enabled)
+  }
+
+  /// Returns true if the flag is temporary and has been fully rolled out, unchanged, for at least `min_age`
+  ///
+  /// Flags without an `updated_at` timestamp predate timestamps being recorded and are always old enough
+  pub fn is_stale(&self, now: DateTime<Utc>, min_age: Duration) -> bool {
+    let old_enough = match self.updated_at {
+      Some(updated_at) => now - updated_at >= min_age,
+      None => true,
+    };
+
+    !self.permanent && self.is_fully_rolled_out() && old_enough
   }
 
   pub fn get_spec_safe_feature_flag(&self) -> SpecSafeFeatureFlag {
@@ -117,12 +240,26 @@ impl FeatureFlag {
       product_id: self.product_id.clone(),
       enabled: self.enabled,
       client_toggle: self.client_toggle,
+      always_include: self.always_include.clone(),
+      always_exclude: self.always_exclude.clone(),
       release_type: self.release_type.clone(),
+      tags: self.tags.clone(),
+      description: self.description.clone(),
+      owner: self.owner.clone(),
+      maintainer_id: self.maintainer_id.clone(),
+      created_at: self.created_at,
+      updated_at: self.updated_at,
+      prerequisites: self.prerequisites.clone(),
+      rules: self.rules.clone(),
+      settings: self.settings.clone(),
+      archived: self.archived,
+      permanent: self.permanent,
+      payload: self.payload.clone(),
     }
   }
 }
 
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SpecSafeFeatureFlag {
   // Unique ID of the feature flag
   pub oid: String,
@@ -134,8 +271,36 @@ pub struct SpecSafeFeatureFlag {
   pub enabled: bool,
   /// If client toggles are enabled
   pub client_toggle: bool,
+  /// Users (e.g. QA accounts) always evaluating as enabled, overriding targeting rules and the release type
+  pub always_include: Vec<String>,
+  /// Users always evaluating as disabled, overriding targeting rules, the release type, and `always_include`
+  pub always_exclude: Vec<String>,
   /// Type of release and relevant data
   pub release_type: ReleaseType,
+  /// Tags used to group flags (e.g. by squad or initiative)
+  pub tags: Vec<String>,
+  /// Human readable description of what the flag gates
+  pub description: String,
+  /// Owner of the flag (team or person responsible for it)
+  pub owner: String,
+  /// Unique ID of the user responsible for maintaining (and eventually retiring) the flag
+  pub maintainer_id: Option<String>,
+  /// When the flag was created
+  pub created_at: Option<DateTime<Utc>>,
+  /// When the flag was last changed
+  pub updated_at: Option<DateTime<Utc>>,
+  /// Names of flags (in the same product) that must be enabled before this flag
+  pub prerequisites: Vec<String>,
+  /// Targeting rules, checked by priority before the release type
+  pub rules: Vec<Rule>,
+  /// Settings overriding the product and environment settings for the flag
+  pub settings: SettingsOverrides,
+  /// If the flag is archived (hidden from default listings and always evaluating as disabled)
+  pub archived: bool,
+  /// If the flag is meant to stay (e.g. an operational kill switch) rather than be removed once rolled out
+  pub permanent: bool,
+  /// JSON configuration (e.g. a button color or limits) returned by checks alongside an enabled status
+  pub payload: Option<Value>,
 }
 
 #[derive(Clone)]
@@ -152,8 +317,40 @@ pub struct FeatureFlagBuilder {
   pub client_toggle: bool,
   /// List of all users who've disabled the feature
   pub disabled_for: Vec<String>,
+  /// Users always evaluating as enabled
+  pub always_include: Vec<String>,
+  /// Users always evaluating as disabled
+  pub always_exclude: Vec<String>,
   /// Type of release and relevant data
   pub release_type: ReleaseType,
+  /// Tags used to group flags (e.g. by squad or initiative)
+  pub tags: Vec<String>,
+  /// Human readable description of what the flag gates
+  pub description: String,
+  /// Owner of the flag (team or person responsible for it)
+  pub owner: String,
+  /// Unique ID of the user responsible for maintaining (and eventually retiring) the flag
+  pub maintainer_id: Option<String>,
+  /// When the flag was created
+  pub created_at: Option<DateTime<Utc>>,
+  /// When the flag was last changed
+  pub updated_at: Option<DateTime<Utc>>,
+  /// Names of flags (in the same product) that must be enabled before this flag
+  pub prerequisites: Vec<String>,
+  /// Targeting rules, checked by priority before the release type
+  pub rules: Vec<Rule>,
+  /// Settings overriding the product and environment settings for the flag
+  pub settings: SettingsOverrides,
+  /// If the flag is archived (hidden from default listings and always evaluating as disabled)
+  pub archived: bool,
+  /// If the flag is meant to stay (e.g. an operational kill switch) rather than be removed once rolled out
+  pub permanent: bool,
+  /// JSON configuration (e.g. a button color or limits) returned by checks alongside an enabled status
+  pub payload: Option<Value>,
+  /// Changes waiting to be applied to the flag
+  pub scheduled_changes: Vec<ScheduledChange>,
+  /// Salt of the percentage release buckets, random by default
+  pub bucket_salt: Option<String>,
 }
 
 impl Default for FeatureFlagBuilder {
@@ -167,7 +364,23 @@ impl Default for FeatureFlagBuilder {
       enabled: default_flag.enabled,
       client_toggle: default_flag.client_toggle,
       disabled_for: default_flag.disabled_for,
+      always_include: default_flag.always_include,
+      always_exclude: default_flag.always_exclude,
       release_type: default_flag.release_type,
+      tags: default_flag.tags,
+      description: default_flag.description,
+      owner: default_flag.owner,
+      maintainer_id: default_flag.maintainer_id,
+      created_at: default_flag.created_at,
+      updated_at: default_flag.updated_at,
+      prerequisites: default_flag.prerequisites,
+      rules: default_flag.rules,
+      settings: default_flag.settings,
+      archived: default_flag.archived,
+      permanent: default_flag.permanent,
+      payload: default_flag.payload,
+      scheduled_changes: default_flag.scheduled_changes,
+      bucket_salt: Some(generate_salt()),
     }
   }
 }
@@ -202,6 +415,7 @@ impl FeatureFlagBuilder {
     self
   }
 
+  #[allow(dead_code)]
   pub fn with_disabled_for(mut self, disabled_for: Vec<String>) -> FeatureFlagBuilder {
     self.disabled_for = disabled_for;
     self
@@ -212,6 +426,76 @@ impl FeatureFlagBuilder {
     self
   }
 
+  pub fn with_tags(mut self, tags: Vec<String>) -> FeatureFlagBuilder {
+    self.tags = tags;
+    self
+  }
+
+  pub fn with_description(mut self, description: &str) -> FeatureFlagBuilder {
+    self.description = description.to_string();
+    self
+  }
+
+  pub fn with_owner(mut self, owner: &str) -> FeatureFlagBuilder {
+    self.owner = owner.to_string();
+    self
+  }
+
+  pub fn with_maintainer_id(mut self, maintainer_id: Option<&str>) -> FeatureFlagBuilder {
+    self.maintainer_id = maintainer_id.map(|x| x.to_string());
+    self
+  }
+
+  pub fn with_created_at(mut self, created_at: DateTime<Utc>) -> FeatureFlagBuilder {
+    self.created_at = Some(created_at);
+    self
+  }
+
+  pub fn with_updated_at(mut self, updated_at: DateTime<Utc>) -> FeatureFlagBuilder {
+    self.updated_at = Some(updated_at);
+    self
+  }
+
+  pub fn with_prerequisites(mut self, prerequisites: Vec<String>) -> FeatureFlagBuilder {
+    self.prerequisites = prerequisites;
+    self
+  }
+
+  pub fn with_permanent(mut self, permanent: bool) -> FeatureFlagBuilder {
+    self.permanent = permanent;
+    self
+  }
+
+  pub fn with_always_include(mut self, always_include: Vec<String>) -> FeatureFlagBuilder {
+    self.always_include = always_include;
+    self
+  }
+
+  pub fn with_always_exclude(mut self, always_exclude: Vec<String>) -> FeatureFlagBuilder {
+    self.always_exclude = always_exclude;
+    self
+  }
+
+  pub fn with_rules(mut self, rules: Vec<Rule>) -> FeatureFlagBuilder {
+    self.rules = rules;
+    self
+  }
+
+  pub fn with_settings(mut self, settings: SettingsOverrides) -> FeatureFlagBuilder {
+    self.settings = settings;
+    self
+  }
+
+  pub fn with_payload(mut self, payload: Option<Value>) -> FeatureFlagBuilder {
+    self.payload = payload;
+    self
+  }
+
+  pub fn with_archived(mut self, archived: bool) -> FeatureFlagBuilder {
+    self.archived = archived;
+    self
+  }
+
   pub fn build(self) -> FeatureFlag {
     FeatureFlag {
       oid: self.oid,
@@ -220,20 +504,97 @@ impl FeatureFlagBuilder {
       enabled: self.enabled,
       client_toggle: self.client_toggle,
       disabled_for: self.disabled_for,
+      always_include: self.always_include,
+      always_exclude: self.always_exclude,
       release_type: self.release_type,
+      tags: self.tags,
+      description: self.description,
+      owner: self.owner,
+      maintainer_id: self.maintainer_id,
+      created_at: self.created_at,
+      updated_at: self.updated_at,
+      prerequisites: self.prerequisites,
+      rules: self.rules,
+      settings: self.settings,
+      archived: self.archived,
+      permanent: self.permanent,
+      payload: self.payload,
+      scheduled_changes: self.scheduled_changes,
+      bucket_salt: self.bucket_salt,
     }
   }
 }
 
-/// Data object for a Feature Flag Release Type
-///
-/// Release types contain relevant information to the type of release
-#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
-pub enum ReleaseType {
-  /// Release is global
-  Global,
-  /// Release is limited, contains an allowlist of users
-  Limited(Vec<String>),
-  /// Release is percentage, contains a percentage and allowlist
-  Percentage(f32, Vec<String>),
+/// Generates a random bucket salt for a new flag
+fn generate_salt() -> String {
+  rand::random::<[u8; 16]>()
+    .iter()
+    .map(|x| format!("{:02x}", x))
+    .collect()
+}
+
+impl Evaluable for FeatureFlag {
+  fn bucket_salt(&self) -> &str {
+    self.bucket_salt.as_deref().unwrap_or(&self.name)
+  }
+
+  fn enabled(&self) -> bool {
+    self.enabled
+  }
+
+  fn archived(&self) -> bool {
+    self.archived
+  }
+
+  fn disabled_for(&self) -> &[String] {
+    &self.disabled_for
+  }
+
+  fn always_include(&self) -> &[String] {
+    &self.always_include
+  }
+
+  fn always_exclude(&self) -> &[String] {
+    &self.always_exclude
+  }
+
+  fn release_type(&self) -> &ReleaseType {
+    &self.release_type
+  }
+
+  fn rules(&self) -> &[Rule] {
+    &self.rules
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bucket_salts_are_random_per_flag_and_kept_by_clones() {
+    let builder = FeatureFlag::builder().with_name("new-checkout");
+    let first = builder.clone().build();
+    let second = builder.build();
+    let other = FeatureFlag::builder().with_name("new-checkout").build();
+
+    assert_eq!(first.bucket_salt, second.bucket_salt);
+    assert_ne!(first.bucket_salt, other.bucket_salt);
+    assert_eq!(first.definition().bucket_salt, first.bucket_salt);
+  }
+
+  #[test]
+  fn flags_without_a_salt_bucket_on_their_name() {
+    let flag = FeatureFlag {
+      name: "new-checkout".to_string(),
+      ..Default::default()
+    };
+
+    assert_eq!(flag.bucket_salt(), "new-checkout");
+    assert_eq!(flag.definition().bucket_salt.as_deref(), Some("new-checkout"));
+
+    let mut renamed = flag.definition();
+    renamed.name = "checkout-v2".to_string();
+    assert_eq!(renamed.bucket_salt(), "new-checkout");
+  }
 }
@@ -0,0 +1,95 @@
+//! Data model of rollout freeze windows, recurring weekly periods during which flags of a product can't be changed
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+/// Day of the week
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Weekday {
+  Monday,
+  Tuesday,
+  Wednesday,
+  Thursday,
+  Friday,
+  Saturday,
+  Sunday,
+}
+
+impl Weekday {
+  /// Days since Monday
+  fn index(&self) -> u32 {
+    match self {
+      Weekday::Monday => 0,
+      Weekday::Tuesday => 1,
+      Weekday::Wednesday => 2,
+      Weekday::Thursday => 3,
+      Weekday::Friday => 4,
+      Weekday::Saturday => 5,
+      Weekday::Sunday => 6,
+    }
+  }
+}
+
+/// Weekly period during which flags of a product can't be changed (e.g. Friday 18:00 to Monday 06:00)
+///
+/// Times are `HH:MM` in UTC. A window ending before it starts (like the example) wraps around the end of the week
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FreezeWindow {
+  /// Day the window starts on
+  pub start_day: Weekday,
+  /// Time the window starts at, `HH:MM` in UTC
+  pub start_time: String,
+  /// Day the window ends on
+  pub end_day: Weekday,
+  /// Time the window ends at (exclusive), `HH:MM` in UTC
+  pub end_time: String,
+}
+
+impl FreezeWindow {
+  /// Checks the window is well formed, returning a description of the problem if not
+  pub fn validate(&self) -> Result<(), String> {
+    let start = minute_of_week(self.start_day, &self.start_time)?;
+    let end = minute_of_week(self.end_day, &self.end_time)?;
+
+    if start == end {
+      return Err("Freeze windows can't start and end at the same time".to_string());
+    }
+
+    Ok(())
+  }
+
+  /// Returns `true` if `now` is inside the window, malformed windows never contain anything
+  pub fn contains(&self, now: DateTime<Utc>) -> bool {
+    let (start, end) = match (
+      minute_of_week(self.start_day, &self.start_time),
+      minute_of_week(self.end_day, &self.end_time),
+    ) {
+      (Ok(start), Ok(end)) => (start, end),
+      _ => return false,
+    };
+
+    let now = now.weekday().num_days_from_monday() * 24 * 60 + now.hour() * 60 + now.minute();
+
+    if start < end {
+      start <= now && now < end
+    } else {
+      now >= start || now < end
+    }
+  }
+}
+
+/// Minutes since the start of the week (Monday 00:00) of a day and `HH:MM` time
+fn minute_of_week(day: Weekday, time: &str) -> Result<u32, String> {
+  let invalid = || format!("Invalid time '{}', expected HH:MM", time);
+
+  let (hours, minutes) = time.trim().split_once(':').ok_or_else(invalid)?;
+  let hours: u32 = hours.parse().map_err(|_| invalid())?;
+  let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+
+  if hours > 23 || minutes > 59 {
+    return Err(invalid());
+  }
+
+  Ok(day.index() * 24 * 60 + hours * 60 + minutes)
+}
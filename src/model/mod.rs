@@ -1,5 +1,18 @@
 //! Data model for the Feature Flagging Service
 
+pub mod api_key;
+pub mod audit;
+pub mod backup;
+pub mod comment;
+pub mod dependency;
 pub mod flag;
+pub mod freeze;
+pub mod plugin;
 pub mod product;
+pub mod schedule;
+pub mod service_account;
+pub mod session;
+pub mod settings;
+pub mod stats;
 pub mod user;
+pub mod webhook;
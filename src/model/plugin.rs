@@ -0,0 +1,110 @@
+//! Data model of WASM plugins, modules implementing custom evaluation logic for the flags of a product
+
+use chrono::{DateTime, TimeZone, Utc};
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::spec::BinarySubtype;
+use mongodb::bson::Binary;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+/// Data Object for an uploaded WASM plugin
+///
+/// Plugins are never changed once uploaded, a new upload creates a new plugin the product then points to
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Plugin {
+  /// Unique ID of the plugin
+  #[serde(alias = "_id", rename(serialize = "_id"), skip_serializing_if = "Option::is_none")]
+  pub oid: Option<ObjectId>,
+  /// Unique ID of the product the plugin was uploaded for
+  pub product_id: String,
+  /// Compiled WASM module
+  pub module: Binary,
+  /// User ID of who uploaded the plugin
+  pub created_by: String,
+  /// When the plugin was uploaded
+  pub created_at: mongodb::bson::DateTime,
+}
+
+impl Plugin {
+  /// Returns a `PluginBuilder` to eventually construct a `Plugin`
+  pub fn builder() -> PluginBuilder {
+    PluginBuilder::new()
+  }
+
+  /// When the plugin was uploaded
+  pub fn created_at(&self) -> DateTime<Utc> {
+    Utc.timestamp_millis(self.created_at.timestamp_millis())
+  }
+
+  pub fn get_spec_safe_plugin(&self) -> SpecSafePlugin {
+    SpecSafePlugin {
+      oid: match self.oid {
+        Some(oid) => oid.to_hex(),
+        None => ObjectId::default().to_hex(),
+      },
+      product_id: self.product_id.clone(),
+      size: self.module.bytes.len(),
+      created_by: self.created_by.clone(),
+      created_at: self.created_at(),
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SpecSafePlugin {
+  /// Unique ID of the plugin
+  pub oid: String,
+  /// Unique ID of the product the plugin was uploaded for
+  pub product_id: String,
+  /// Size of the WASM module, in bytes
+  pub size: usize,
+  /// User ID of who uploaded the plugin
+  pub created_by: String,
+  /// When the plugin was uploaded
+  pub created_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct PluginBuilder {
+  /// Unique ID of the product the plugin is uploaded for
+  pub product_id: String,
+  /// Compiled WASM module
+  pub module: Vec<u8>,
+  /// User ID of who uploaded the plugin
+  pub created_by: String,
+}
+
+impl PluginBuilder {
+  fn new() -> PluginBuilder {
+    PluginBuilder::default()
+  }
+
+  pub fn with_product_id(mut self, product_id: &str) -> PluginBuilder {
+    self.product_id = product_id.to_string();
+    self
+  }
+
+  pub fn with_module(mut self, module: Vec<u8>) -> PluginBuilder {
+    self.module = module;
+    self
+  }
+
+  pub fn with_created_by(mut self, created_by: &str) -> PluginBuilder {
+    self.created_by = created_by.to_string();
+    self
+  }
+
+  /// Builds the plugin, timestamped with the current time
+  pub fn build(self) -> Plugin {
+    Plugin {
+      oid: None,
+      product_id: self.product_id,
+      module: Binary {
+        subtype: BinarySubtype::Generic,
+        bytes: self.module,
+      },
+      created_by: self.created_by,
+      created_at: mongodb::bson::DateTime::now(),
+    }
+  }
+}
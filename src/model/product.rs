@@ -1,12 +1,17 @@
 //! Data model for Products
 
+use chrono::{DateTime, Utc};
 use mongodb::bson::oid::ObjectId;
 use rocket_okapi::okapi::schemars::{self, JsonSchema};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::vec::Vec;
 
+use crate::model::freeze::FreezeWindow;
+use crate::model::settings::SettingsOverrides;
+
 /// Data object for products
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Product {
   /// String generated my MongoDB
   #[serde(alias = "_id", skip_serializing_if = "Option::is_none")]
@@ -15,6 +20,21 @@ pub struct Product {
   pub name: String,
   /// List of product user ids
   pub users: Vec<String>,
+  /// If evaluations with users unknown to the product should be rejected
+  #[serde(default)]
+  pub strict_mode: bool,
+  /// Settings overriding the organization defaults for the product's flags
+  #[serde(default)]
+  pub settings: SettingsOverrides,
+  /// Settings overriding the product settings, by environment name
+  #[serde(default)]
+  pub environments: HashMap<String, SettingsOverrides>,
+  /// Weekly windows during which the product's flags can't be changed
+  #[serde(default)]
+  pub freeze_windows: Vec<FreezeWindow>,
+  /// Unique ID of the WASM plugin implementing custom evaluation logic for the product's flags, if any
+  #[serde(default)]
+  pub plugin_id: Option<String>,
 }
 
 impl Default for Product {
@@ -23,6 +43,11 @@ impl Default for Product {
       oid: Default::default(),
       name: "default_product".to_string(),
       users: Vec::new(),
+      strict_mode: false,
+      settings: SettingsOverrides::default(),
+      environments: HashMap::new(),
+      freeze_windows: vec![],
+      plugin_id: None,
     }
   }
 }
@@ -32,6 +57,11 @@ impl Product {
     ProductBuilder::new()
   }
 
+  /// Returns true if `now` is inside one of the product's freeze windows
+  pub fn is_frozen(&self, now: DateTime<Utc>) -> bool {
+    self.freeze_windows.iter().any(|x| x.contains(now))
+  }
+
   pub fn get_spec_safe_product(&self) -> SpecSafeProduct {
     SpecSafeProduct {
       oid: match self.oid {
@@ -40,17 +70,32 @@ impl Product {
       },
       name: self.name.clone(),
       users: self.users.clone(),
+      strict_mode: self.strict_mode,
+      settings: self.settings.clone(),
+      environments: self.environments.clone(),
+      freeze_windows: self.freeze_windows.clone(),
+      plugin_id: self.plugin_id.clone(),
     }
   }
 }
 
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SpecSafeProduct {
   pub oid: String,
   /// Product Name
   pub name: String,
   /// List of product user ids
   pub users: Vec<String>,
+  /// If evaluations with users unknown to the product should be rejected
+  pub strict_mode: bool,
+  /// Settings overriding the organization defaults for the product's flags
+  pub settings: SettingsOverrides,
+  /// Settings overriding the product settings, by environment name
+  pub environments: HashMap<String, SettingsOverrides>,
+  /// Weekly windows during which the product's flags can't be changed
+  pub freeze_windows: Vec<FreezeWindow>,
+  /// Unique ID of the WASM plugin implementing custom evaluation logic for the product's flags, if any
+  pub plugin_id: Option<String>,
 }
 
 #[derive(Clone)]
@@ -61,6 +106,16 @@ pub struct ProductBuilder {
   pub name: String,
   /// List of product user IDs
   pub users: Vec<String>,
+  /// If evaluations with users unknown to the product should be rejected
+  pub strict_mode: bool,
+  /// Settings overriding the organization defaults for the product's flags
+  pub settings: SettingsOverrides,
+  /// Settings overriding the product settings, by environment name
+  pub environments: HashMap<String, SettingsOverrides>,
+  /// Weekly windows during which the product's flags can't be changed
+  pub freeze_windows: Vec<FreezeWindow>,
+  /// Unique ID of the WASM plugin implementing custom evaluation logic for the product's flags, if any
+  pub plugin_id: Option<String>,
 }
 
 impl Default for ProductBuilder {
@@ -70,6 +125,11 @@ impl Default for ProductBuilder {
       oid: default_product.oid,
       name: default_product.name,
       users: default_product.users,
+      strict_mode: default_product.strict_mode,
+      settings: default_product.settings,
+      environments: default_product.environments,
+      freeze_windows: default_product.freeze_windows,
+      plugin_id: default_product.plugin_id,
     }
   }
 }
@@ -94,11 +154,21 @@ impl ProductBuilder {
     self
   }
 
+  pub fn with_strict_mode(mut self, strict_mode: bool) -> ProductBuilder {
+    self.strict_mode = strict_mode;
+    self
+  }
+
   pub fn build(self) -> Product {
     Product {
       oid: self.oid,
       name: self.name,
       users: self.users,
+      strict_mode: self.strict_mode,
+      settings: self.settings,
+      environments: self.environments,
+      freeze_windows: self.freeze_windows,
+      plugin_id: self.plugin_id,
     }
   }
 }
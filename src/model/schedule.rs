@@ -0,0 +1,102 @@
+//! Data model of changes scheduled to be applied to a Feature Flag later
+
+use chrono::{DateTime, TimeZone, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::model::flag::ReleaseType;
+
+/// Change that can be scheduled on a flag
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub enum FlagChange {
+  /// Globally enables the flag
+  Enable,
+  /// Globally disables the flag
+  Disable,
+  /// Sets the percentage of a percentage release, other release types become a percentage release keeping their
+  /// allowlist
+  SetPercentage(f32),
+  /// Replaces the release type of the flag
+  SetReleaseType(ReleaseType),
+}
+
+impl FlagChange {
+  /// Checks the change is well formed, returning a description of the problem if not
+  pub fn validate(&self) -> Result<(), String> {
+    let percentage = match self {
+      FlagChange::SetPercentage(percentage) => *percentage,
+      FlagChange::SetReleaseType(ReleaseType::Percentage(percentage, _, _)) => *percentage,
+      _ => return Ok(()),
+    };
+
+    if !(0.0..=100.0).contains(&percentage) {
+      return Err(format!("Percentage must be between 0 and 100, got {}", percentage));
+    }
+
+    Ok(())
+  }
+}
+
+/// Field of a flag a change sets, resolved against the flag it's applied to so it can be written on its own
+#[derive(Clone, Debug)]
+pub enum ChangedField {
+  Enabled(bool),
+  ReleaseType(ReleaseType),
+}
+
+/// Change of a flag waiting to be applied
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduledChange {
+  /// Unique ID of the scheduled change
+  pub id: ObjectId,
+  /// Change to apply
+  pub change: FlagChange,
+  /// When the change is due, stored as a BSON date so due changes can be queried
+  pub at: mongodb::bson::DateTime,
+  /// User ID of who scheduled the change
+  pub created_by: String,
+}
+
+impl ScheduledChange {
+  /// Creates a new `ScheduledChange` with a freshly generated ID
+  ///
+  /// # Parameters
+  /// * **change**     - Change to apply
+  /// * **at**         - When the change is due
+  /// * **created_by** - User ID of who scheduled the change
+  pub fn new(change: FlagChange, at: DateTime<Utc>, created_by: &str) -> ScheduledChange {
+    ScheduledChange {
+      id: ObjectId::new(),
+      change,
+      at: mongodb::bson::DateTime::from_millis(at.timestamp_millis()),
+      created_by: created_by.to_string(),
+    }
+  }
+
+  /// When the change is due
+  pub fn at(&self) -> DateTime<Utc> {
+    Utc.timestamp_millis(self.at.timestamp_millis())
+  }
+
+  pub fn get_spec_safe_scheduled_change(&self) -> SpecSafeScheduledChange {
+    SpecSafeScheduledChange {
+      id: self.id.to_hex(),
+      change: self.change.clone(),
+      at: self.at(),
+      created_by: self.created_by.clone(),
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SpecSafeScheduledChange {
+  /// Unique ID of the scheduled change
+  pub id: String,
+  /// Change to apply
+  pub change: FlagChange,
+  /// When the change is due
+  pub at: DateTime<Utc>,
+  /// User ID of who scheduled the change
+  pub created_by: String,
+}
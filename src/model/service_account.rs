@@ -0,0 +1,175 @@
+//! Data model of service accounts, authorizing CI pipelines and automation without a user session
+
+use chrono::{DateTime, TimeZone, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::model::user::Permission;
+
+/// What the token of a service account can do with its products
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceScope {
+  /// Export the flags and settings of its products
+  Read,
+  /// Check flags of its products with the `/check` routes
+  Evaluate,
+  /// Change its products and their flags
+  Write,
+}
+
+impl ServiceScope {
+  /// Serialized name of the scope
+  pub fn name(&self) -> &'static str {
+    match self {
+      ServiceScope::Read => "read",
+      ServiceScope::Evaluate => "evaluate",
+      ServiceScope::Write => "write",
+    }
+  }
+
+  /// If the scope grants the role permission `permission`. Service accounts are never granted `users:admin`
+  pub fn grants(&self, permission: Permission) -> bool {
+    matches!(
+      (self, permission),
+      (ServiceScope::Read, Permission::FlagsRead) | (ServiceScope::Write, Permission::FlagsWrite)
+    )
+  }
+}
+
+/// Data Object for a service account
+///
+/// Only the SHA-256 hash of its token is stored, the token itself is shown once when the account is created
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ServiceAccount {
+  /// Unique ID of the service account
+  #[serde(alias = "_id", rename(serialize = "_id"), skip_serializing_if = "Option::is_none")]
+  pub oid: Option<ObjectId>,
+  /// Human readable name of the account (e.g. `release-pipeline`)
+  pub name: String,
+  /// Unique IDs of the products the token gives access to
+  pub product_ids: Vec<String>,
+  /// What the token can do with the products
+  pub scopes: Vec<ServiceScope>,
+  /// Hex SHA-256 hash of the token
+  pub token_hash: String,
+  /// Start of the token, so it can be told apart from other tokens without being stored
+  pub token_prefix: String,
+  /// User ID of who created the account
+  pub created_by: String,
+  /// When the account was created
+  pub created_at: mongodb::bson::DateTime,
+  /// When the token was revoked, revoked accounts are kept for the record but authorize nothing
+  #[serde(default)]
+  pub revoked_at: Option<mongodb::bson::DateTime>,
+}
+
+impl ServiceAccount {
+  /// Returns a `ServiceAccountBuilder` to eventually construct a `ServiceAccount`
+  pub fn builder() -> ServiceAccountBuilder {
+    ServiceAccountBuilder::default()
+  }
+
+  /// If the token hasn't been revoked
+  pub fn is_active(&self) -> bool {
+    self.revoked_at.is_none()
+  }
+
+  pub fn get_spec_safe_service_account(&self) -> SpecSafeServiceAccount {
+    SpecSafeServiceAccount {
+      oid: match self.oid {
+        Some(oid) => oid.to_hex(),
+        None => ObjectId::default().to_hex(),
+      },
+      name: self.name.clone(),
+      product_ids: self.product_ids.clone(),
+      scopes: self.scopes.clone(),
+      token_prefix: self.token_prefix.clone(),
+      created_by: self.created_by.clone(),
+      created_at: Utc.timestamp_millis(self.created_at.timestamp_millis()),
+      revoked_at: self.revoked_at.map(|x| Utc.timestamp_millis(x.timestamp_millis())),
+    }
+  }
+}
+
+/// Service account as shown through the API, the hash of its token left out
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SpecSafeServiceAccount {
+  /// Unique ID of the service account
+  pub oid: String,
+  /// Human readable name of the account
+  pub name: String,
+  /// Unique IDs of the products the token gives access to
+  pub product_ids: Vec<String>,
+  /// What the token can do with the products
+  pub scopes: Vec<ServiceScope>,
+  /// Start of the token, so it can be told apart from other tokens
+  pub token_prefix: String,
+  /// User ID of who created the account
+  pub created_by: String,
+  /// When the account was created
+  pub created_at: DateTime<Utc>,
+  /// When the token was revoked, `None` if it's active
+  pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+pub struct ServiceAccountBuilder {
+  /// Human readable name of the account
+  pub name: String,
+  /// Unique IDs of the products the token gives access to
+  pub product_ids: Vec<String>,
+  /// What the token can do with the products
+  pub scopes: Vec<ServiceScope>,
+  /// Hex SHA-256 hash of the token
+  pub token_hash: String,
+  /// Start of the token
+  pub token_prefix: String,
+  /// User ID of who created the account
+  pub created_by: String,
+}
+
+impl ServiceAccountBuilder {
+  pub fn with_name(mut self, name: &str) -> ServiceAccountBuilder {
+    self.name = name.to_string();
+    self
+  }
+
+  pub fn with_product_ids(mut self, product_ids: Vec<String>) -> ServiceAccountBuilder {
+    self.product_ids = product_ids;
+    self
+  }
+
+  pub fn with_scopes(mut self, scopes: Vec<ServiceScope>) -> ServiceAccountBuilder {
+    self.scopes = scopes;
+    self
+  }
+
+  /// Sets the hash and prefix of the token, the token itself isn't kept
+  pub fn with_token(mut self, token_hash: &str, token_prefix: &str) -> ServiceAccountBuilder {
+    self.token_hash = token_hash.to_string();
+    self.token_prefix = token_prefix.to_string();
+    self
+  }
+
+  pub fn with_created_by(mut self, created_by: &str) -> ServiceAccountBuilder {
+    self.created_by = created_by.to_string();
+    self
+  }
+
+  /// Builds the service account, timestamped with the current time
+  pub fn build(self) -> ServiceAccount {
+    ServiceAccount {
+      oid: None,
+      name: self.name,
+      product_ids: self.product_ids,
+      scopes: self.scopes,
+      token_hash: self.token_hash,
+      token_prefix: self.token_prefix,
+      created_by: self.created_by,
+      created_at: mongodb::bson::DateTime::now(),
+      revoked_at: None,
+    }
+  }
+}
@@ -0,0 +1,96 @@
+//! Data model of login sessions, stored so they survive restarts and are shared by every instance
+
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+use crate::model::user::Permission;
+
+/// Data Object for a login session
+///
+/// Only the SHA-256 hash of the session's auth token is stored, the token itself is only known by the client
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+  /// Unique ID of the session
+  #[serde(alias = "_id", rename(serialize = "_id"), skip_serializing_if = "Option::is_none")]
+  pub oid: Option<ObjectId>,
+  /// Unique ID of the logged in user
+  pub user_id: String,
+  /// Hex SHA-256 hash of the auth token
+  pub token_hash: String,
+  /// `User-Agent` header of the login, to tell sessions apart
+  #[serde(default)]
+  pub user_agent: Option<String>,
+  /// Permissions the token is limited to, on top of the user's role. `None` if it isn't limited
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub scopes: Option<Vec<Permission>>,
+  /// When the user logged in
+  pub created_at: mongodb::bson::DateTime,
+  /// When the session expires, pushed back by every authenticated request
+  pub expires_at: mongodb::bson::DateTime,
+}
+
+impl Session {
+  /// Returns a `SessionBuilder` to eventually construct a `Session`
+  pub fn builder() -> SessionBuilder {
+    SessionBuilder::default()
+  }
+}
+
+#[derive(Default)]
+pub struct SessionBuilder {
+  /// Unique ID of the logged in user
+  pub user_id: String,
+  /// Hex SHA-256 hash of the auth token
+  pub token_hash: String,
+  /// `User-Agent` header of the login
+  pub user_agent: Option<String>,
+  /// Permissions the token is limited to
+  pub scopes: Option<Vec<Permission>>,
+  /// How long the session lasts without activity
+  pub ttl: Option<chrono::Duration>,
+}
+
+impl SessionBuilder {
+  pub fn with_user_id(mut self, user_id: &str) -> SessionBuilder {
+    self.user_id = user_id.to_string();
+    self
+  }
+
+  /// Sets the hash of the auth token, the token itself isn't kept
+  pub fn with_token_hash(mut self, token_hash: &str) -> SessionBuilder {
+    self.token_hash = token_hash.to_string();
+    self
+  }
+
+  pub fn with_user_agent(mut self, user_agent: Option<&str>) -> SessionBuilder {
+    self.user_agent = user_agent.map(|x| x.to_string());
+    self
+  }
+
+  /// Limits the token to `scopes`, `None` to only limit it by the user's role
+  pub fn with_scopes(mut self, scopes: Option<Vec<Permission>>) -> SessionBuilder {
+    self.scopes = scopes;
+    self
+  }
+
+  pub fn with_ttl(mut self, ttl: chrono::Duration) -> SessionBuilder {
+    self.ttl = Some(ttl);
+    self
+  }
+
+  /// Builds the session, starting now and expiring after its TTL. Sessions without a TTL are expired already
+  pub fn build(self) -> Session {
+    let now: DateTime<Utc> = Utc::now();
+    let ttl = self.ttl.unwrap_or_else(chrono::Duration::zero);
+    Session {
+      oid: None,
+      user_id: self.user_id,
+      token_hash: self.token_hash,
+      user_agent: self.user_agent,
+      scopes: self.scopes,
+      created_at: mongodb::bson::DateTime::from_millis(now.timestamp_millis()),
+      expires_at: mongodb::bson::DateTime::from_millis((now + ttl).timestamp_millis()),
+    }
+  }
+}
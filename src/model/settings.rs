@@ -0,0 +1,144 @@
+//! Data model for layered settings (organization → product → environment → flag)
+
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+/// Settings overridden at one level of the hierarchy
+///
+/// Every field is optional, a level only overrides the settings it sets and inherits the rest from its parent
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SettingsOverrides {
+  /// If flags should evaluate as enabled when they can't be evaluated (e.g. the database is unreachable)
+  #[serde(default, skip_serializing_if = "Option::is_none", alias = "failOpen")]
+  pub fail_open: Option<bool>,
+  /// How long evaluation results may be cached, in seconds
+  #[serde(default, skip_serializing_if = "Option::is_none", alias = "cacheTtlSeconds")]
+  pub cache_ttl_seconds: Option<u64>,
+  /// How long stale evaluation results may be served while they're revalidated, in seconds
+  #[serde(
+    default,
+    skip_serializing_if = "Option::is_none",
+    alias = "staleWhileRevalidateSeconds"
+  )]
+  pub stale_while_revalidate_seconds: Option<u64>,
+  /// Largest increase of a percentage release allowed in a single change
+  #[serde(default, skip_serializing_if = "Option::is_none", alias = "maxRolloutStep")]
+  pub max_rollout_step: Option<f32>,
+  /// Where notifications about flag changes are sent (e.g. webhook URLs or email addresses)
+  #[serde(default, skip_serializing_if = "Option::is_none", alias = "notificationTargets")]
+  pub notification_targets: Option<Vec<String>>,
+}
+
+/// Level of the settings hierarchy a setting was resolved from
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, JsonSchema)]
+pub enum SettingsLevel {
+  /// Organization defaults, from the service configuration
+  Organization,
+  /// Product the flag belongs to
+  Product,
+  /// Environment of the product
+  Environment,
+  /// The flag itself
+  Flag,
+}
+
+/// A resolved setting and the level it came from
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Resolved<T> {
+  /// Effective value of the setting
+  pub value: T,
+  /// Level the value was inherited from
+  pub source: SettingsLevel,
+}
+
+/// Fully resolved settings of a flag, with the provenance of each setting
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EffectiveSettings {
+  /// If flags should evaluate as enabled when they can't be evaluated
+  pub fail_open: Resolved<bool>,
+  /// How long evaluation results may be cached, in seconds
+  pub cache_ttl_seconds: Resolved<u64>,
+  /// How long stale evaluation results may be served while they're revalidated, in seconds
+  pub stale_while_revalidate_seconds: Resolved<u64>,
+  /// Largest increase of a percentage release allowed in a single change
+  pub max_rollout_step: Resolved<f32>,
+  /// Where notifications about flag changes are sent
+  pub notification_targets: Resolved<Vec<String>>,
+}
+
+impl EffectiveSettings {
+  /// Returns the organization defaults
+  ///
+  /// Read from `SETTINGS_FAIL_OPEN`, `SETTINGS_CACHE_TTL_SECONDS`, `SETTINGS_STALE_WHILE_REVALIDATE_SECONDS`,
+  /// `SETTINGS_MAX_ROLLOUT_STEP` and `SETTINGS_NOTIFICATION_TARGETS` (comma separated), falling back to fail closed, a
+  /// 30 second TTL, no stale results, no rollout step limit, and no notification targets
+  pub fn organization_defaults() -> EffectiveSettings {
+    let organization = SettingsLevel::Organization;
+
+    EffectiveSettings {
+      fail_open: organization.resolved(env_setting("SETTINGS_FAIL_OPEN").unwrap_or(false)),
+      cache_ttl_seconds: organization.resolved(env_setting("SETTINGS_CACHE_TTL_SECONDS").unwrap_or(30)),
+      stale_while_revalidate_seconds: organization
+        .resolved(env_setting("SETTINGS_STALE_WHILE_REVALIDATE_SECONDS").unwrap_or(0)),
+      max_rollout_step: organization.resolved(env_setting("SETTINGS_MAX_ROLLOUT_STEP").unwrap_or(100.0)),
+      notification_targets: organization.resolved(match dotenv::var("SETTINGS_NOTIFICATION_TARGETS") {
+        Ok(value) => value
+          .split(',')
+          .map(|x| x.trim().to_string())
+          .filter(|x| !x.is_empty())
+          .collect(),
+        Err(_) => vec![],
+      }),
+    }
+  }
+
+  /// Resolves the settings of a flag by applying each level's overrides on top of the organization defaults
+  ///
+  /// # Parameters
+  /// * **product**     - Overrides of the flag's product
+  /// * **environment** - *(optional)* overrides of the environment being resolved
+  /// * **flag**        - Overrides of the flag
+  pub fn resolve(
+    product: &SettingsOverrides,
+    environment: Option<&SettingsOverrides>,
+    flag: &SettingsOverrides,
+  ) -> EffectiveSettings {
+    let mut settings = EffectiveSettings::organization_defaults();
+
+    settings.apply(product, SettingsLevel::Product);
+    if let Some(environment) = environment {
+      settings.apply(environment, SettingsLevel::Environment);
+    }
+    settings.apply(flag, SettingsLevel::Flag);
+
+    settings
+  }
+
+  fn apply(&mut self, overrides: &SettingsOverrides, level: SettingsLevel) {
+    if let Some(value) = overrides.fail_open {
+      self.fail_open = level.resolved(value);
+    }
+    if let Some(value) = overrides.cache_ttl_seconds {
+      self.cache_ttl_seconds = level.resolved(value);
+    }
+    if let Some(value) = overrides.stale_while_revalidate_seconds {
+      self.stale_while_revalidate_seconds = level.resolved(value);
+    }
+    if let Some(value) = overrides.max_rollout_step {
+      self.max_rollout_step = level.resolved(value);
+    }
+    if let Some(value) = &overrides.notification_targets {
+      self.notification_targets = level.resolved(value.clone());
+    }
+  }
+}
+
+impl SettingsLevel {
+  fn resolved<T>(self, value: T) -> Resolved<T> {
+    Resolved { value, source: self }
+  }
+}
+
+fn env_setting<T: std::str::FromStr>(key: &str) -> Option<T> {
+  dotenv::var(key).ok().and_then(|x| x.parse().ok())
+}
@@ -0,0 +1,47 @@
+//! Data model of the statistics summary of the service
+
+use std::collections::BTreeMap;
+
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+/// Counts of everything the service manages
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Stats {
+  /// Number of products
+  pub products: u64,
+  /// Counts of flags
+  pub flags: FlagStats,
+  /// Counts of users
+  pub users: UserStats,
+  /// Number of logged in sessions
+  pub active_sessions: u64,
+  /// Number of flag evaluations over the last 24 hours, rounded out to whole hours
+  pub evaluations_24h: u64,
+}
+
+/// Counts of flags, by state, by release type and by product
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct FlagStats {
+  /// Number of flags
+  pub total: u64,
+  /// Flags that are enabled and not archived
+  pub enabled: u64,
+  /// Flags that are disabled and not archived
+  pub disabled: u64,
+  /// Flags that are archived, enabled or not
+  pub archived: u64,
+  /// Number of flags of each kind of release type (e.g. `Percentage`)
+  pub by_release_type: BTreeMap<String, u64>,
+  /// Number of flags of each product, by product ID. Products without flags are left out
+  pub by_product: BTreeMap<String, u64>,
+}
+
+/// Counts of users, by account type
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct UserStats {
+  /// Number of users
+  pub total: u64,
+  /// Number of users of each account type (e.g. `Developer`)
+  pub by_account_type: BTreeMap<String, u64>,
+}
@@ -5,7 +5,7 @@ use rocket_okapi::okapi::schemars::{self, JsonSchema};
 use serde::{Deserialize, Serialize};
 
 /// Data object for users
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct User {
   /// Unique ID of user
   #[serde(alias = "_id", skip_serializing_if = "Option::is_none")]
@@ -14,10 +14,20 @@ pub struct User {
   pub name: String,
   /// Type of user account
   pub account_type: AccountType,
+  /// Role of the user, deciding what they can change. `None` for users created before roles, see `User::role`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub role: Option<Role>,
   /// User email
   pub email: String,
-  /// User password hash
+  /// User password hash, empty for users fetched for listings, which leave it out
+  #[serde(default)]
   pub password_hash: String,
+  /// ID of the user at the identity provider they log in with (`<issuer>|<subject>`), `None` if they never did
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub external_id: Option<String>,
+  /// If the user can log in, users created before statuses are active
+  #[serde(default)]
+  pub status: UserStatus,
 }
 
 impl Default for User {
@@ -26,8 +36,11 @@ impl Default for User {
       oid: Default::default(),
       name: "default_user".to_string(),
       account_type: AccountType::Client,
+      role: None,
       email: "default_user_email".to_string(),
       password_hash: "default_password_hash".to_string(),
+      external_id: None,
+      status: UserStatus::Active,
     }
   }
 }
@@ -37,6 +50,20 @@ impl User {
     UserBuilder::new()
   }
 
+  /// Role of the user, users without one are admins if they're developers and editors otherwise
+  pub fn role(&self) -> Role {
+    match (self.role, &self.account_type) {
+      (Some(role), _) => role,
+      (None, AccountType::Developer) => Role::Admin,
+      (None, AccountType::Client) => Role::Editor,
+    }
+  }
+
+  /// If the user can log in and use the API
+  pub fn is_active(&self) -> bool {
+    self.status == UserStatus::Active
+  }
+
   pub fn get_spec_safe_user(&self) -> SpecSafeUser {
     SpecSafeUser {
       oid: match self.oid {
@@ -45,7 +72,9 @@ impl User {
       },
       name: self.name.clone(),
       account_type: self.account_type.clone(),
+      role: self.role(),
       email: self.email.clone(),
+      status: self.status,
     }
   }
 }
@@ -58,8 +87,12 @@ pub struct SpecSafeUser {
   pub name: String,
   /// Type of user account
   pub account_type: AccountType,
+  /// Role of the user
+  pub role: Role,
   /// User email
   pub email: String,
+  /// If the user can log in
+  pub status: UserStatus,
 }
 
 #[derive(Clone)]
@@ -70,10 +103,16 @@ pub struct UserBuilder {
   name: String,
   /// Type of user account
   account_type: AccountType,
+  /// Role of the user
+  role: Option<Role>,
   /// User email
   email: String,
   /// User password hash
   password_hash: String,
+  /// ID of the user at their identity provider
+  external_id: Option<String>,
+  /// If the user can log in
+  status: UserStatus,
 }
 
 impl Default for UserBuilder {
@@ -84,8 +123,11 @@ impl Default for UserBuilder {
       oid: default_user.oid,
       name: default_user.name,
       account_type: default_user.account_type,
+      role: default_user.role,
       email: default_user.email,
       password_hash: default_user.password_hash,
+      external_id: default_user.external_id,
+      status: default_user.status,
     }
   }
 }
@@ -111,6 +153,11 @@ impl UserBuilder {
     self
   }
 
+  pub fn with_role(mut self, role: Role) -> UserBuilder {
+    self.role = Some(role);
+    self
+  }
+
   pub fn with_email(mut self, email: &str) -> UserBuilder {
     self.email = email.to_string();
     self
@@ -121,6 +168,11 @@ impl UserBuilder {
     self
   }
 
+  pub fn with_external_id(mut self, external_id: &str) -> UserBuilder {
+    self.external_id = Some(external_id.to_string());
+    self
+  }
+
   /// Builds itself into and returns a `User` consuming the `UserBuilder`
   ///
   /// # Examples
@@ -140,8 +192,11 @@ impl UserBuilder {
       oid: self.oid,
       name: self.name,
       account_type: self.account_type,
+      role: self.role,
       email: self.email,
       password_hash: self.password_hash,
+      external_id: self.external_id,
+      status: self.status,
     }
   }
 }
@@ -155,21 +210,127 @@ pub enum AccountType {
 const CLIENT: &str = "Client";
 const DEVELOPER: &str = "Developer";
 
+impl AccountType {
+  /// Name of the account type (e.g. `Developer`)
+  pub fn name(&self) -> &'static str {
+    match self {
+      AccountType::Client => CLIENT,
+      AccountType::Developer => DEVELOPER,
+    }
+  }
+}
+
 impl std::convert::From<String> for AccountType {
   fn from(other: String) -> Self {
     match other.as_str() {
-      CLIENT => return Self::Client,
-      DEVELOPER => return Self::Developer,
-      &_ => return Self::Client,
+      CLIENT => Self::Client,
+      DEVELOPER => Self::Developer,
+      &_ => Self::Client,
+    }
+  }
+}
+
+impl std::convert::From<AccountType> for mongodb::bson::Bson {
+  fn from(account_type: AccountType) -> Self {
+    match account_type {
+      AccountType::Client => mongodb::bson::Bson::String(CLIENT.to_string()),
+      AccountType::Developer => mongodb::bson::Bson::String(DEVELOPER.to_string()),
+    }
+  }
+}
+
+/// If a user can log in, only active users can
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UserStatus {
+  /// Can log in and use the API
+  #[default]
+  Active,
+  /// Temporarily locked out by an admin, e.g. while an incident is investigated
+  Suspended,
+  /// Permanently locked out, e.g. after leaving the organization. Kept for the audit log
+  Deactivated,
+}
+
+impl UserStatus {
+  /// Serialized name of the status
+  pub fn name(&self) -> &'static str {
+    match self {
+      UserStatus::Active => "active",
+      UserStatus::Suspended => "suspended",
+      UserStatus::Deactivated => "deactivated",
+    }
+  }
+}
+
+/// Role of a user, deciding which permissions they have
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+  /// Can do everything, including managing users
+  Admin,
+  /// Can create and change flags
+  Editor,
+  /// Can only read flags
+  Viewer,
+}
+
+impl Role {
+  /// Serialized name of the role
+  pub fn name(&self) -> &'static str {
+    match self {
+      Role::Admin => "admin",
+      Role::Editor => "editor",
+      Role::Viewer => "viewer",
     }
   }
+
+  /// Returns the role of a serialized name (e.g. `viewer`), `None` if there's no such role
+  pub fn from_name(name: &str) -> Option<Role> {
+    match name {
+      "admin" => Some(Role::Admin),
+      "editor" => Some(Role::Editor),
+      "viewer" => Some(Role::Viewer),
+      _ => None,
+    }
+  }
+
+  /// Permissions the role grants
+  pub fn permissions(&self) -> &'static [Permission] {
+    match self {
+      Role::Admin => &[Permission::FlagsRead, Permission::FlagsWrite, Permission::UsersAdmin],
+      Role::Editor => &[Permission::FlagsRead, Permission::FlagsWrite],
+      Role::Viewer => &[Permission::FlagsRead],
+    }
+  }
+
+  /// If the role grants `permission`
+  pub fn has(&self, permission: Permission) -> bool {
+    self.permissions().contains(&permission)
+  }
+}
+
+/// Something a user can be allowed to do, and a scope tokens and API keys can be limited to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Permission {
+  /// Read products and flags
+  #[serde(rename = "flags:read")]
+  FlagsRead,
+  /// Create, change and delete products and flags
+  #[serde(rename = "flags:write")]
+  FlagsWrite,
+  /// Create users, change their roles, and manage who uses products
+  #[serde(rename = "users:admin")]
+  UsersAdmin,
 }
 
-impl std::convert::Into<mongodb::bson::Bson> for AccountType {
-  fn into(self) -> mongodb::bson::Bson {
+impl Permission {
+  /// Name of the permission, as named in errors (e.g. `flags:write`)
+  pub fn name(&self) -> &'static str {
     match self {
-      Self::Client => return mongodb::bson::Bson::String(CLIENT.to_string()),
-      Self::Developer => return mongodb::bson::Bson::String(DEVELOPER.to_string()),
+      Permission::FlagsRead => "flags:read",
+      Permission::FlagsWrite => "flags:write",
+      Permission::UsersAdmin => "users:admin",
     }
   }
 }
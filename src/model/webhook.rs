@@ -0,0 +1,291 @@
+//! Data model of webhooks, URLs notified of changes to flags and products, and of their deliveries
+
+use chrono::{DateTime, TimeZone, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::controller::events::{EventKind, UserIdentifiers};
+
+/// Data Object for a webhook
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Webhook {
+  /// Unique ID of the webhook
+  #[serde(alias = "_id", rename(serialize = "_id"), skip_serializing_if = "Option::is_none")]
+  pub oid: Option<ObjectId>,
+  /// URL changes are POSTed to
+  pub url: String,
+  /// Secret deliveries are signed with, never shown once set
+  pub secret: String,
+  /// Unique ID of the product whose changes are delivered, every product's if `None`
+  pub product_id: Option<String>,
+  /// Kinds of changes delivered, every kind if empty
+  pub events: Vec<EventKind>,
+  /// How user identifiers appear in deliveries
+  #[serde(default)]
+  pub user_identifiers: UserIdentifiers,
+  /// If changes are delivered, disabled webhooks are kept but skipped
+  pub enabled: bool,
+  /// User ID of who created the webhook
+  pub created_by: String,
+  /// When the webhook was created
+  pub created_at: mongodb::bson::DateTime,
+}
+
+impl Webhook {
+  /// Returns a `WebhookBuilder` to eventually construct a `Webhook`
+  pub fn builder() -> WebhookBuilder {
+    WebhookBuilder::new()
+  }
+
+  /// When the webhook was created
+  pub fn created_at(&self) -> DateTime<Utc> {
+    Utc.timestamp_millis(self.created_at.timestamp_millis())
+  }
+
+  /// If changes of `kind` to the product of ID `product_id` are delivered to the webhook
+  pub fn accepts(&self, product_id: &str, kind: EventKind) -> bool {
+    let product_matches = match &self.product_id {
+      Some(webhook_product_id) => webhook_product_id == product_id,
+      None => true,
+    };
+
+    self.enabled && product_matches && (self.events.is_empty() || self.events.contains(&kind))
+  }
+
+  pub fn get_spec_safe_webhook(&self) -> SpecSafeWebhook {
+    SpecSafeWebhook {
+      oid: match self.oid {
+        Some(oid) => oid.to_hex(),
+        None => ObjectId::default().to_hex(),
+      },
+      url: self.url.clone(),
+      product_id: self.product_id.clone(),
+      events: self.events.clone(),
+      user_identifiers: self.user_identifiers,
+      enabled: self.enabled,
+      created_by: self.created_by.clone(),
+      created_at: self.created_at(),
+    }
+  }
+}
+
+/// Webhook as shown through the API, its secret left out
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SpecSafeWebhook {
+  /// Unique ID of the webhook
+  pub oid: String,
+  /// URL changes are POSTed to
+  pub url: String,
+  /// Unique ID of the product whose changes are delivered, every product's if `None`
+  pub product_id: Option<String>,
+  /// Kinds of changes delivered, every kind if empty
+  pub events: Vec<EventKind>,
+  /// How user identifiers appear in deliveries
+  pub user_identifiers: UserIdentifiers,
+  /// If changes are delivered
+  pub enabled: bool,
+  /// User ID of who created the webhook
+  pub created_by: String,
+  /// When the webhook was created
+  pub created_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct WebhookBuilder {
+  /// URL changes are POSTed to
+  pub url: String,
+  /// Secret deliveries are signed with
+  pub secret: String,
+  /// Unique ID of the product whose changes are delivered, every product's if `None`
+  pub product_id: Option<String>,
+  /// Kinds of changes delivered, every kind if empty
+  pub events: Vec<EventKind>,
+  /// How user identifiers appear in deliveries
+  pub user_identifiers: UserIdentifiers,
+  /// If changes are delivered
+  pub enabled: bool,
+  /// User ID of who created the webhook
+  pub created_by: String,
+}
+
+impl WebhookBuilder {
+  fn new() -> WebhookBuilder {
+    WebhookBuilder {
+      enabled: true,
+      ..Default::default()
+    }
+  }
+
+  pub fn with_url(mut self, url: &str) -> WebhookBuilder {
+    self.url = url.to_string();
+    self
+  }
+
+  pub fn with_secret(mut self, secret: &str) -> WebhookBuilder {
+    self.secret = secret.to_string();
+    self
+  }
+
+  pub fn with_product_id(mut self, product_id: Option<String>) -> WebhookBuilder {
+    self.product_id = product_id;
+    self
+  }
+
+  pub fn with_events(mut self, events: Vec<EventKind>) -> WebhookBuilder {
+    self.events = events;
+    self
+  }
+
+  pub fn with_user_identifiers(mut self, user_identifiers: UserIdentifiers) -> WebhookBuilder {
+    self.user_identifiers = user_identifiers;
+    self
+  }
+
+  pub fn with_enabled(mut self, enabled: bool) -> WebhookBuilder {
+    self.enabled = enabled;
+    self
+  }
+
+  pub fn with_created_by(mut self, created_by: &str) -> WebhookBuilder {
+    self.created_by = created_by.to_string();
+    self
+  }
+
+  /// Builds the webhook, timestamped with the current time
+  pub fn build(self) -> Webhook {
+    Webhook {
+      oid: None,
+      url: self.url,
+      secret: self.secret,
+      product_id: self.product_id,
+      events: self.events,
+      user_identifiers: self.user_identifiers,
+      enabled: self.enabled,
+      created_by: self.created_by,
+      created_at: mongodb::bson::DateTime::now(),
+    }
+  }
+}
+
+/// State of a delivery
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+  /// Not delivered yet, attempts are left
+  Pending,
+  /// The destination accepted the delivery
+  Delivered,
+  /// Every attempt failed, the delivery is a dead letter until it's retried
+  Dead,
+}
+
+impl DeliveryStatus {
+  /// Parses a delivery status from its serialized name (e.g. `dead`)
+  pub fn from_name(name: &str) -> Option<DeliveryStatus> {
+    match name.trim().to_lowercase().as_str() {
+      "pending" => Some(DeliveryStatus::Pending),
+      "delivered" => Some(DeliveryStatus::Delivered),
+      "dead" => Some(DeliveryStatus::Dead),
+      _ => None,
+    }
+  }
+
+  /// Serialized name of the delivery status
+  pub fn name(&self) -> &'static str {
+    match self {
+      DeliveryStatus::Pending => "pending",
+      DeliveryStatus::Delivered => "delivered",
+      DeliveryStatus::Dead => "dead",
+    }
+  }
+}
+
+/// Data Object for a delivery of an event to a webhook, updated after every attempt
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+  /// Unique ID of the delivery, also sent in its payload
+  #[serde(alias = "_id", rename(serialize = "_id"))]
+  pub oid: ObjectId,
+  /// Unique ID of the webhook the event is delivered to
+  pub webhook_id: String,
+  /// Type of the delivered event (e.g. `flag.updated`)
+  pub event: String,
+  /// Exact body POSTed to the webhook
+  pub payload: String,
+  /// State of the delivery
+  pub status: DeliveryStatus,
+  /// Attempts made since the delivery was created or last retried
+  pub attempts: u32,
+  /// HTTP status the destination last responded with, if it responded
+  pub response_status: Option<u16>,
+  /// Start of the body the destination last responded with, if it responded
+  pub response_body: Option<String>,
+  /// Why the last attempt failed, if it did
+  pub error: Option<String>,
+  /// When the delivery was created
+  pub created_at: mongodb::bson::DateTime,
+  /// When the delivery was last attempted
+  pub attempted_at: Option<mongodb::bson::DateTime>,
+}
+
+impl WebhookDelivery {
+  /// Constructs a pending delivery of ID `oid`, delivering `payload` to the webhook of ID `webhook_id`
+  pub fn new(oid: ObjectId, webhook_id: &str, event: &str, payload: String) -> WebhookDelivery {
+    WebhookDelivery {
+      oid,
+      webhook_id: webhook_id.to_string(),
+      event: event.to_string(),
+      payload,
+      status: DeliveryStatus::Pending,
+      attempts: 0,
+      response_status: None,
+      response_body: None,
+      error: None,
+      created_at: mongodb::bson::DateTime::now(),
+      attempted_at: None,
+    }
+  }
+
+  pub fn get_spec_safe_webhook_delivery(&self) -> SpecSafeWebhookDelivery {
+    SpecSafeWebhookDelivery {
+      oid: self.oid.to_hex(),
+      webhook_id: self.webhook_id.clone(),
+      event: self.event.clone(),
+      payload: self.payload.clone(),
+      status: self.status,
+      attempts: self.attempts,
+      response_status: self.response_status,
+      response_body: self.response_body.clone(),
+      error: self.error.clone(),
+      created_at: Utc.timestamp_millis(self.created_at.timestamp_millis()),
+      attempted_at: self.attempted_at.map(|x| Utc.timestamp_millis(x.timestamp_millis())),
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SpecSafeWebhookDelivery {
+  /// Unique ID of the delivery, also sent in its payload
+  pub oid: String,
+  /// Unique ID of the webhook the event is delivered to
+  pub webhook_id: String,
+  /// Type of the delivered event (e.g. `flag.updated`)
+  pub event: String,
+  /// Exact body POSTed to the webhook
+  pub payload: String,
+  /// State of the delivery
+  pub status: DeliveryStatus,
+  /// Attempts made since the delivery was created or last retried
+  pub attempts: u32,
+  /// HTTP status the destination last responded with, if it responded
+  pub response_status: Option<u16>,
+  /// Start of the body the destination last responded with, if it responded
+  pub response_body: Option<String>,
+  /// Why the last attempt failed, if it did
+  pub error: Option<String>,
+  /// When the delivery was created
+  pub created_at: DateTime<Utc>,
+  /// When the delivery was last attempted
+  pub attempted_at: Option<DateTime<Utc>>,
+}
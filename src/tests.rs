@@ -0,0 +1,270 @@
+//! Integration tests of the API, served by a local Rocket instance storing everything in memory
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::serde::json::{json, serde_json, Value};
+
+/// Password of the users created by the tests, strong enough for the default password policy
+const PASSWORD: &str = "Correct-Horse-Battery-9";
+
+/// Returns a client of the service on the memory driver
+///
+/// Untracked, so the login cookies aren't sent back and requests authenticate with bearer tokens only
+async fn client() -> Client {
+  std::env::set_var("DATABASE_CONNECTION_TYPE", "memory");
+  std::env::remove_var("REQUIRE_API_KEYS");
+
+  Client::untracked(super::rocket().await)
+    .await
+    .expect("valid rocket instance")
+}
+
+async fn json_body(response: LocalResponse<'_>) -> Value {
+  let body = response.into_string().await.expect("response body");
+  serde_json::from_str(&body).expect("JSON body")
+}
+
+fn bearer(token: &str) -> Header<'static> {
+  Header::new("Authorization", format!("Bearer {}", token))
+}
+
+/// Logs in the user of `email`, limiting the token to `scopes` if given, returning the token
+async fn login(client: &Client, email: &str, scopes: Option<&[&str]>) -> String {
+  let response = client
+    .post("/api/v1/login")
+    .header(ContentType::JSON)
+    .body(json!({ "email": email, "hash": PASSWORD, "scopes": scopes }).to_string())
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Accepted);
+
+  json_body(response).await["token"]
+    .as_str()
+    .expect("token in the login response")
+    .to_string()
+}
+
+/// Sets up the first developer and creates a product, returning the developer's token and the product's ID
+async fn setup_product(client: &Client, name: &str) -> (String, String) {
+  let response = client
+    .post("/api/v1/setup")
+    .header(ContentType::JSON)
+    .body(json!({ "name": "Admin", "email": "admin@example.com", "hash": PASSWORD }).to_string())
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Created);
+  let token = login(client, "admin@example.com", None).await;
+
+  let response = client
+    .post(format!("/api/v1/create/product/{}", name))
+    .header(ContentType::JSON)
+    .header(bearer(&token))
+    .body("[]")
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Created);
+  let product_id = json_body(response).await["id"].as_str().unwrap().to_string();
+
+  (token, product_id)
+}
+
+#[rocket::async_test]
+async fn login_scoped_auth_and_check_on_memory_driver() {
+  let client = client().await;
+
+  let response = client
+    .post("/api/v1/setup")
+    .header(ContentType::JSON)
+    .body(json!({ "name": "Admin", "email": "admin@example.com", "hash": PASSWORD }).to_string())
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Created);
+
+  let token = login(&client, "admin@example.com", None).await;
+  let read_only = login(&client, "admin@example.com", Some(&["flags:read"])).await;
+
+  // Routes need credentials, and the token's scopes must grant their permission
+  let response = client.get("/api/v1/get/product/checkout").dispatch().await;
+  assert_eq!(response.status(), Status::Unauthorized);
+
+  let response = client
+    .post("/api/v1/create/product/checkout")
+    .header(ContentType::JSON)
+    .header(bearer(&read_only))
+    .body("[]")
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Forbidden);
+  assert_eq!(json_body(response).await["details"]["permission"], "flags:write");
+
+  let response = client
+    .post("/api/v1/create/product/checkout")
+    .header(ContentType::JSON)
+    .header(bearer(&token))
+    .body("[]")
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Created);
+  let product_id = json_body(response).await["id"].as_str().unwrap().to_string();
+
+  let response = client
+    .get("/api/v1/get/product/checkout")
+    .header(bearer(&read_only))
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Ok);
+
+  let response = client
+    .post(format!("/api/v1/create/flags/{}", product_id))
+    .header(ContentType::JSON)
+    .header(bearer(&token))
+    .body(
+      json!([
+        { "name": "new-cart", "enabled": true, "client_toggle": false, "release_type": "Global" },
+        { "name": "old-cart", "enabled": false, "client_toggle": false, "release_type": "Global" },
+      ])
+      .to_string(),
+    )
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Ok);
+
+  // Checks don't need a login while API keys aren't required
+  let response = client
+    .get(format!("/api/v1/check/{}/new-cart/with", product_id))
+    .header(Header::new("Accept", "application/json"))
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Ok);
+  assert_eq!(json_body(response).await["enabled"], true);
+
+  let response = client
+    .get(format!("/api/v1/check/{}/old-cart/with", product_id))
+    .header(Header::new("Accept", "application/json"))
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Ok);
+  assert_eq!(json_body(response).await["enabled"], false);
+
+  let response = client
+    .get(format!("/api/v1/check/{}/missing/with", product_id))
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::NotFound);
+
+  // Left for last, failed logins make the next ones wait
+  let response = client
+    .post("/api/v1/login")
+    .header(ContentType::JSON)
+    .body(json!({ "email": "admin@example.com", "hash": "wrong" }).to_string())
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn bulk_created_flags_need_their_prerequisites_created() {
+  let client = client().await;
+  let (token, product_id) = setup_product(&client, "checkout").await;
+
+  let flag = |name: &str, prerequisites: &[&str]| {
+    json!({
+      "name": name,
+      "enabled": false,
+      "client_toggle": false,
+      "release_type": "Global",
+      "prerequisites": prerequisites,
+    })
+  };
+  // Invalid, a user can't be both always included and excluded
+  let mut invalid = flag("risk-checks", &[]);
+  invalid["always_include"] = json!(["u1"]);
+  invalid["always_exclude"] = json!(["u1"]);
+
+  let response = client
+    .post(format!("/api/v1/create/flags/{}", product_id))
+    .header(ContentType::JSON)
+    .header(bearer(&token))
+    .body(
+      json!([
+        flag("payments", &[]),
+        flag("checkout", &["payments", "risk-checks"]),
+        invalid,
+        flag("express-checkout", &["checkout"]),
+        flag("cart", &["payments"]),
+      ])
+      .to_string(),
+    )
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Ok);
+
+  let results = json_body(response).await;
+  let created: Vec<bool> = results
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|x| x["error"].is_null() && x["id"].is_string())
+    .collect();
+  assert_eq!(created, vec![true, false, false, false, true]);
+  assert_eq!(
+    results[1]["error"],
+    "Error. Prerequisite 'risk-checks' failed to be created."
+  );
+  assert_eq!(
+    results[3]["error"],
+    "Error. Prerequisite 'checkout' failed to be created."
+  );
+
+  let response = client
+    .get(format!("/api/v1/check/{}/checkout/with", product_id))
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn batch_checks_refuse_contexts_sharing_a_result() {
+  let client = client().await;
+  let (token, product_id) = setup_product(&client, "checkout").await;
+
+  let response = client
+    .post(format!("/api/v1/create/flags/{}", product_id))
+    .header(ContentType::JSON)
+    .header(bearer(&token))
+    .body(
+      json!([{ "name": "new-cart", "enabled": true, "client_toggle": false, "release_type": "Global" }]).to_string(),
+    )
+    .dispatch()
+    .await;
+  assert_eq!(response.status(), Status::Ok);
+
+  let batch = |contexts: Value| {
+    client
+      .post(format!("/api/v1/check/{}/new-cart/batch", product_id))
+      .header(ContentType::JSON)
+      .header(Header::new("Accept", "application/json"))
+      .body(contexts.to_string())
+      .dispatch()
+  };
+
+  let response = batch(json!([{ "key": "device-1" }, { "key": "device-2" }])).await;
+  assert_eq!(response.status(), Status::Ok);
+  let results = json_body(response).await;
+  assert_eq!(results["device-1"]["enabled"], true);
+  assert_eq!(results["device-2"]["enabled"], true);
+
+  let response = batch(json!([{ "key": "device-1" }, { "key": "device-2" }, { "key": "device-1" }])).await;
+  assert_eq!(response.status(), Status::UnprocessableEntity);
+  assert_eq!(
+    json_body(response).await["details"]["violations"][0]["field"],
+    "[2].key"
+  );
+
+  let response = batch(json!([{ "key": "device-1" }, { "key": " " }])).await;
+  assert_eq!(response.status(), Status::UnprocessableEntity);
+  assert_eq!(
+    json_body(response).await["details"]["violations"][0]["field"],
+    "[1].key"
+  );
+}